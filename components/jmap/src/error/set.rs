@@ -80,6 +80,16 @@ pub enum SetErrorType {
     ForbiddenToSend,
     #[serde(rename = "cannotUnsend")]
     CannotUnsend,
+    #[serde(rename = "alreadyExists")]
+    AlreadyExists,
+    #[serde(rename = "invalidSieve")]
+    InvalidSieve,
+    #[serde(rename = "sieveIsActive")]
+    SieveIsActive,
+    #[serde(rename = "addressBookHasContents")]
+    AddressBookHasContents,
+    #[serde(rename = "calendarHasEvent")]
+    CalendarHasEvent,
 }
 
 impl SetErrorType {
@@ -107,6 +117,11 @@ impl SetErrorType {
             SetErrorType::ForbiddenMailFrom => "forbiddenMailFrom",
             SetErrorType::ForbiddenToSend => "forbiddenToSend",
             SetErrorType::CannotUnsend => "cannotUnsend",
+            SetErrorType::AlreadyExists => "alreadyExists",
+            SetErrorType::InvalidSieve => "invalidSieve",
+            SetErrorType::SieveIsActive => "sieveIsActive",
+            SetErrorType::AddressBookHasContents => "addressBookHasContents",
+            SetErrorType::CalendarHasEvent => "calendarHasEvent",
         }
     }
 }
@@ -123,6 +123,7 @@ where
                 next_call: None,
                 change_id: None,
                 state_changes: None,
+                account_state_changes: Vec::new(),
             },
             will_destroy,
             request,
@@ -50,6 +50,12 @@ pub enum URI {
     Calendars,
     #[serde(rename(serialize = "urn:ietf:params:jmap:websocket"))]
     WebSocket,
+    #[serde(rename(serialize = "urn:ietf:params:jmap:quota"))]
+    Quota,
+    #[serde(rename(serialize = "urn:ietf:params:jmap:sieve"))]
+    Sieve,
+    #[serde(rename(serialize = "urn:ietf:params:jmap:blob"))]
+    Blob,
 }
 
 pub type Result<T> = std::result::Result<T, MethodError>;
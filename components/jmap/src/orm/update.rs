@@ -155,7 +155,7 @@ where
             self.serialize().ok_or_else(|| {
                 StoreError::SerializeError("Failed to serialize ORM object.".to_string())
             })?,
-            IndexOptions::new().store(),
+            IndexOptions::new().store().encrypt(),
         );
         Ok(())
     }
@@ -50,7 +50,9 @@ impl Display for Property {
             Property::Members => f.write_str("members"),
             Property::Aliases => f.write_str("aliases"),
             Property::ACL => f.write_str("acl"),
-            Property::Invalid => Ok(()),
+            Property::OAuthGrants | Property::AppPasswords | Property::TOTP | Property::Invalid => {
+                Ok(())
+            }
         }
     }
 }
@@ -78,6 +80,9 @@ impl From<FieldId> for Property {
             11 => Property::Picture,
             12 => Property::Members,
             13 => Property::ACL,
+            14 => Property::OAuthGrants,
+            15 => Property::AppPasswords,
+            16 => Property::TOTP,
             _ => Property::Invalid,
         }
     }
@@ -33,7 +33,9 @@ use crate::{
     types::{blob::JMAPBlob, jmap::JMAPId},
 };
 
-use super::schema::{Comparator, Filter, Patch, Principal, Property, Type, Value};
+use super::schema::{
+    AppPassword, Comparator, Filter, OAuthGrant, Patch, Principal, Property, Type, Value, TOTP,
+};
 
 impl orm::Value for Value {
     fn index_as(&self) -> orm::Index {
@@ -89,9 +91,24 @@ impl orm::Value for Value {
             Value::Type { .. } => std::mem::size_of::<Type>(),
             Value::DKIM { value } => {
                 value.dkim_selector.as_ref().map(|s| s.len()).unwrap_or(0)
+                    + value.dkim_record.as_ref().map(|s| s.len()).unwrap_or(0)
+                    + value.dkim_rotation.as_ref().map_or(0, |r| {
+                        r.selector.len()
+                            + r.record.as_ref().map(|s| s.len()).unwrap_or(0)
+                            + r.secret.as_ref().map(|s| s.len()).unwrap_or(0)
+                    })
                     + std::mem::size_of::<i64>()
             }
             Value::Members { value } => value.len() * std::mem::size_of::<JMAPId>(),
+            Value::OAuthGrants { value } => value.iter().fold(0, |acc, grant| {
+                acc + grant.client_id.len() + std::mem::size_of::<OAuthGrant>()
+            }),
+            Value::AppPasswords { value } => value.iter().fold(0, |acc, password| {
+                acc + password.label.len()
+                    + password.hash.len()
+                    + std::mem::size_of::<AppPassword>()
+            }),
+            Value::TOTP { value } => value.secret.len() + std::mem::size_of::<TOTP>(),
             Value::ACL(value) => value.iter().fold(0, |acc, (k, v)| {
                 acc + k.len() + v.len() * std::mem::size_of::<ACL>()
             }),
@@ -168,7 +185,7 @@ impl Object for Principal {
             (Property::Description, 512),
             (Property::Timezone, 100),
             (Property::Secret, 2048),
-            (Property::DKIM, 100),
+            (Property::DKIM, 4096),
         ]
     }
 }
@@ -51,7 +51,10 @@ pub enum Property {
     Picture = 11,
     Members = 12,
     ACL = 13,
-    Invalid = 14,
+    OAuthGrants = 14,
+    AppPasswords = 15,
+    TOTP = 16,
+    Invalid = 17,
 }
 
 pub const ACCOUNTS_TO_DELETE: u8 = u8::MAX;
@@ -74,12 +77,101 @@ pub enum Type {
     Other,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct DKIM {
     #[serde(rename = "dkimSelector")]
     pub dkim_selector: Option<String>,
     #[serde(rename = "dkimExpiration")]
     pub dkim_expiration: Option<i64>,
+    #[serde(rename = "dkimGenerate", default)]
+    pub dkim_generate: Option<DKIMAlgorithm>,
+    #[serde(rename = "dkimAlgorithm", default)]
+    pub dkim_algorithm: Option<DKIMAlgorithm>,
+    #[serde(rename = "dkimRecord", default)]
+    pub dkim_record: Option<String>,
+    #[serde(rename = "dkimRotation", default)]
+    pub dkim_rotation: Option<DKIMRotation>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DKIMRotation {
+    #[serde(rename = "selector")]
+    pub selector: String,
+    #[serde(rename = "algorithm", default)]
+    pub algorithm: Option<DKIMAlgorithm>,
+    #[serde(rename = "rotateAt")]
+    pub rotate_at: i64,
+    #[serde(rename = "overlap", default)]
+    pub overlap: Option<i64>,
+    #[serde(rename = "record", default)]
+    pub record: Option<String>,
+    #[serde(rename = "secret", default)]
+    pub secret: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DKIMAlgorithm {
+    #[serde(rename = "rsa")]
+    Rsa,
+    #[serde(rename = "ed25519")]
+    Ed25519,
+}
+
+impl DKIM {
+    /// Completes a scheduled selector rotation once its overlap window has
+    /// elapsed, returning the private key that replaces the current one.
+    pub fn promote(&mut self, now: i64) -> Option<String> {
+        match &self.dkim_rotation {
+            Some(rotation) if rotation.rotate_at + rotation.overlap.unwrap_or(0) <= now => {
+                let rotation = self.dkim_rotation.take().unwrap();
+                self.dkim_selector = rotation.selector.into();
+                self.dkim_algorithm = rotation.algorithm;
+                self.dkim_record = rotation.record;
+                rotation.secret
+            }
+            _ => None,
+        }
+    }
+}
+
+/// OAuth grant issued to a client, kept as a hidden property of the Principal
+/// so that revocations are replicated along with the rest of the account.
+/// The last time a grant was used is recorded separately by each node.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OAuthGrant {
+    pub id: u64,
+    pub client_id: String,
+    pub created: i64,
+}
+
+/// Application-specific password, only its SHA-256 digest is stored.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AppPassword {
+    pub id: u64,
+    pub label: String,
+    pub hash: String,
+    pub created: i64,
+    pub scope: Option<AppPasswordScope>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AppPasswordScope {
+    #[serde(rename = "jmap")]
+    Jmap,
+    #[serde(rename = "smtp")]
+    Smtp,
+}
+
+/// TOTP second factor. The secret is pending until the first code is verified,
+/// and the last accepted time step is kept to prevent codes from being replayed.
+/// Consecutive failed codes are counted to lock out guessing attempts.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TOTP {
+    pub secret: String,
+    pub enabled: bool,
+    pub last_step: u64,
+    pub failed_attempts: u32,
+    pub locked_until: i64,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -92,6 +184,9 @@ pub enum Value {
     Type { value: Type },
     DKIM { value: DKIM },
     Members { value: Vec<JMAPId> },
+    OAuthGrants { value: Vec<OAuthGrant> },
+    AppPasswords { value: Vec<AppPassword> },
+    TOTP { value: TOTP },
     ACL(VecMap<String, Vec<ACL>>),
     Patch(Patch),
     Null,
@@ -54,7 +54,10 @@ impl Serialize for Principal {
                 Value::Blob { value } => map.serialize_entry(name, value)?,
                 Value::DKIM { value } => map.serialize_entry(name, value)?,
                 Value::ACL(value) => map.serialize_entry(name, value)?,
-                Value::Patch(_) => (),
+                Value::Patch(_)
+                | Value::OAuthGrants { .. }
+                | Value::AppPasswords { .. }
+                | Value::TOTP { .. } => (),
             }
         }
 
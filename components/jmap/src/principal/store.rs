@@ -44,6 +44,9 @@ where
 {
     fn principal_to_email(&self, id: AccountId) -> crate::Result<Option<String>>;
     fn principal_to_id<U>(&self, email: &str) -> crate::error::set::Result<AccountId, U>;
+    fn principal_quota(&self, id: AccountId) -> store::Result<Option<u64>>;
+    fn principal_has_quota(&self, id: AccountId, size: usize) -> store::Result<bool>;
+    fn principal_has_upload_quota(&self, id: AccountId, size: usize) -> store::Result<bool>;
 }
 
 impl<T> JMAPPrincipals<T> for JMAPStore<T>
@@ -91,4 +94,34 @@ where
             )
         })
     }
+
+    fn principal_quota(&self, id: AccountId) -> store::Result<Option<u64>> {
+        Ok(self
+            .get_orm::<Principal>(SUPERUSER_ID, id)?
+            .and_then(|mut p| p.remove(&Property::Quota))
+            .and_then(|p| match p {
+                Value::Number { value } if value > 0 => Some(value as u64),
+                _ => None,
+            }))
+    }
+
+    fn principal_has_quota(&self, id: AccountId, size: usize) -> store::Result<bool> {
+        Ok(if let Some(quota) = self.principal_quota(id)? {
+            self.get_used_quota(id)?.saturating_add(size as i64) <= quota as i64
+        } else {
+            true
+        })
+    }
+
+    fn principal_has_upload_quota(&self, id: AccountId, size: usize) -> store::Result<bool> {
+        // Uploads not yet linked to a document also count towards the quota
+        Ok(if let Some(quota) = self.principal_quota(id)? {
+            self.get_used_quota(id)?
+                .saturating_add(self.get_pending_quota(id)?)
+                .saturating_add(size as i64)
+                <= quota as i64
+        } else {
+            true
+        })
+    }
 }
@@ -62,3 +62,196 @@ pub struct CopyBlobResponse {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub not_copied: Option<VecMap<JMAPBlob, SetError<()>>>,
 }
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct UploadBlobRequest {
+    #[serde(skip)]
+    pub acl: Option<Arc<ACLToken>>,
+
+    #[serde(rename = "accountId")]
+    pub account_id: JMAPId,
+
+    #[serde(rename = "create")]
+    pub create: VecMap<String, UploadObject>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct UploadObject {
+    #[serde(rename = "data")]
+    pub data: Vec<DataSourceObject>,
+
+    #[serde(rename = "type")]
+    pub type_: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum DataSourceObject {
+    Text {
+        #[serde(rename = "data:asText")]
+        value: String,
+    },
+    Base64 {
+        #[serde(rename = "data:asBase64")]
+        value: String,
+    },
+    Blob {
+        #[serde(rename = "blobId")]
+        blob_id: JMAPBlob,
+        #[serde(rename = "offset")]
+        offset: Option<usize>,
+        #[serde(rename = "length")]
+        length: Option<usize>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UploadBlobResponse {
+    #[serde(rename = "accountId")]
+    pub account_id: JMAPId,
+
+    #[serde(rename = "created")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created: Option<VecMap<String, BlobCreatedObject>>,
+
+    #[serde(rename = "notCreated")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub not_created: Option<VecMap<String, SetError<()>>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BlobCreatedObject {
+    #[serde(rename = "id")]
+    pub id: JMAPBlob,
+
+    #[serde(rename = "type")]
+    pub type_: Option<String>,
+
+    #[serde(rename = "size")]
+    pub size: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum BlobProperty {
+    #[serde(rename = "id")]
+    Id,
+    #[serde(rename = "data")]
+    Data,
+    #[serde(rename = "data:asText")]
+    DataAsText,
+    #[serde(rename = "data:asBase64")]
+    DataAsBase64,
+    #[serde(rename = "size")]
+    Size,
+    #[serde(rename = "digest:sha-256")]
+    DigestSha256,
+    #[serde(rename = "digest:sha-512")]
+    DigestSha512,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GetBlobRequest {
+    #[serde(skip)]
+    pub acl: Option<Arc<ACLToken>>,
+
+    #[serde(rename = "accountId")]
+    pub account_id: JMAPId,
+
+    #[serde(rename = "ids")]
+    pub ids: Vec<JMAPBlob>,
+
+    #[serde(rename = "properties")]
+    pub properties: Option<Vec<BlobProperty>>,
+
+    #[serde(rename = "offset")]
+    pub offset: Option<usize>,
+
+    #[serde(rename = "length")]
+    pub length: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GetBlobResponse {
+    #[serde(rename = "accountId")]
+    pub account_id: JMAPId,
+
+    #[serde(rename = "list")]
+    pub list: Vec<BlobObject>,
+
+    #[serde(rename = "notFound")]
+    pub not_found: Vec<JMAPBlob>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BlobObject {
+    #[serde(rename = "id")]
+    pub id: JMAPBlob,
+
+    #[serde(rename = "data:asText")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data_as_text: Option<Option<String>>,
+
+    #[serde(rename = "data:asBase64")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data_as_base64: Option<String>,
+
+    #[serde(rename = "digest:sha-256")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub digest_sha256: Option<String>,
+
+    #[serde(rename = "digest:sha-512")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub digest_sha512: Option<String>,
+
+    #[serde(rename = "size")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<usize>,
+
+    #[serde(rename = "isEncodingProblem")]
+    #[serde(skip_serializing_if = "is_false")]
+    pub is_encoding_problem: bool,
+
+    #[serde(rename = "isTruncated")]
+    #[serde(skip_serializing_if = "is_false")]
+    pub is_truncated: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LookupBlobRequest {
+    #[serde(skip)]
+    pub acl: Option<Arc<ACLToken>>,
+
+    #[serde(rename = "accountId")]
+    pub account_id: JMAPId,
+
+    #[serde(rename = "typeNames")]
+    pub type_names: Vec<String>,
+
+    #[serde(rename = "ids")]
+    pub ids: Vec<JMAPBlob>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LookupBlobResponse {
+    #[serde(rename = "accountId")]
+    pub account_id: JMAPId,
+
+    #[serde(rename = "list")]
+    pub list: Vec<BlobLookupObject>,
+
+    #[serde(rename = "notFound")]
+    pub not_found: Vec<JMAPBlob>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BlobLookupObject {
+    #[serde(rename = "id")]
+    pub id: JMAPBlob,
+
+    #[serde(rename = "matchedIds")]
+    pub matched_ids: VecMap<String, Vec<JMAPId>>,
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
@@ -168,6 +168,9 @@ impl MaybeIdReference {
 pub enum Method {
     Echo,
     CopyBlob,
+    UploadBlob,
+    GetBlob,
+    LookupBlob,
     GetPushSubscription,
     SetPushSubscription,
     GetMailbox,
@@ -185,6 +188,7 @@ pub enum Method {
     CopyEmail,
     ImportEmail,
     ParseEmail,
+    InvitationReplyEmail,
     GetSearchSnippet,
     GetIdentity,
     ChangesIdentity,
@@ -199,6 +203,35 @@ pub enum Method {
     GetPrincipal,
     SetPrincipal,
     QueryPrincipal,
+    GetQuota,
+    ChangesQuota,
+    QueryQuota,
+    QueryChangesQuota,
+    GetSieveScript,
+    ChangesSieveScript,
+    QuerySieveScript,
+    QueryChangesSieveScript,
+    SetSieveScript,
+    GetAddressBook,
+    ChangesAddressBook,
+    QueryAddressBook,
+    QueryChangesAddressBook,
+    SetAddressBook,
+    GetContactCard,
+    ChangesContactCard,
+    QueryContactCard,
+    QueryChangesContactCard,
+    SetContactCard,
+    GetCalendar,
+    ChangesCalendar,
+    QueryCalendar,
+    QueryChangesCalendar,
+    SetCalendar,
+    GetCalendarEvent,
+    ChangesCalendarEvent,
+    QueryCalendarEvent,
+    QueryChangesCalendarEvent,
+    SetCalendarEvent,
     Error,
 }
 
@@ -211,6 +244,9 @@ impl serde::Serialize for Method {
         serializer.serialize_str(match self {
             Method::Echo => "Core/echo",
             Method::CopyBlob => "Blob/copy",
+            Method::UploadBlob => "Blob/upload",
+            Method::GetBlob => "Blob/get",
+            Method::LookupBlob => "Blob/lookup",
             Method::GetPushSubscription => "PushSubscription/get",
             Method::SetPushSubscription => "PushSubscription/set",
             Method::GetMailbox => "Mailbox/get",
@@ -228,6 +264,7 @@ impl serde::Serialize for Method {
             Method::CopyEmail => "Email/copy",
             Method::ImportEmail => "Email/import",
             Method::ParseEmail => "Email/parse",
+            Method::InvitationReplyEmail => "Email/invitationReply",
             Method::GetSearchSnippet => "SearchSnippet/get",
             Method::GetIdentity => "Identity/get",
             Method::ChangesIdentity => "Identity/changes",
@@ -242,6 +279,35 @@ impl serde::Serialize for Method {
             Method::GetPrincipal => "Principal/get",
             Method::SetPrincipal => "Principal/set",
             Method::QueryPrincipal => "Principal/query",
+            Method::GetQuota => "Quota/get",
+            Method::ChangesQuota => "Quota/changes",
+            Method::QueryQuota => "Quota/query",
+            Method::QueryChangesQuota => "Quota/queryChanges",
+            Method::GetSieveScript => "SieveScript/get",
+            Method::ChangesSieveScript => "SieveScript/changes",
+            Method::QuerySieveScript => "SieveScript/query",
+            Method::QueryChangesSieveScript => "SieveScript/queryChanges",
+            Method::SetSieveScript => "SieveScript/set",
+            Method::GetAddressBook => "AddressBook/get",
+            Method::ChangesAddressBook => "AddressBook/changes",
+            Method::QueryAddressBook => "AddressBook/query",
+            Method::QueryChangesAddressBook => "AddressBook/queryChanges",
+            Method::SetAddressBook => "AddressBook/set",
+            Method::GetContactCard => "ContactCard/get",
+            Method::ChangesContactCard => "ContactCard/changes",
+            Method::QueryContactCard => "ContactCard/query",
+            Method::QueryChangesContactCard => "ContactCard/queryChanges",
+            Method::SetContactCard => "ContactCard/set",
+            Method::GetCalendar => "Calendar/get",
+            Method::ChangesCalendar => "Calendar/changes",
+            Method::QueryCalendar => "Calendar/query",
+            Method::QueryChangesCalendar => "Calendar/queryChanges",
+            Method::SetCalendar => "Calendar/set",
+            Method::GetCalendarEvent => "CalendarEvent/get",
+            Method::ChangesCalendarEvent => "CalendarEvent/changes",
+            Method::QueryCalendarEvent => "CalendarEvent/query",
+            Method::QueryChangesCalendarEvent => "CalendarEvent/queryChanges",
+            Method::SetCalendarEvent => "CalendarEvent/set",
             Method::Error => "error",
         })
     }
@@ -263,6 +329,9 @@ impl<'de> serde::de::Visitor<'de> for MethodVisitor {
         Ok(match v {
             "Core/echo" => Method::Echo,
             "Blob/copy" => Method::CopyBlob,
+            "Blob/upload" => Method::UploadBlob,
+            "Blob/get" => Method::GetBlob,
+            "Blob/lookup" => Method::LookupBlob,
             "PushSubscription/get" => Method::GetPushSubscription,
             "PushSubscription/set" => Method::SetPushSubscription,
             "Mailbox/get" => Method::GetMailbox,
@@ -280,6 +349,7 @@ impl<'de> serde::de::Visitor<'de> for MethodVisitor {
             "Email/copy" => Method::CopyEmail,
             "Email/import" => Method::ImportEmail,
             "Email/parse" => Method::ParseEmail,
+            "Email/invitationReply" => Method::InvitationReplyEmail,
             "SearchSnippet/get" => Method::GetSearchSnippet,
             "Identity/get" => Method::GetIdentity,
             "Identity/changes" => Method::ChangesIdentity,
@@ -294,6 +364,35 @@ impl<'de> serde::de::Visitor<'de> for MethodVisitor {
             "Principal/get" => Method::GetPrincipal,
             "Principal/set" => Method::SetPrincipal,
             "Principal/query" => Method::QueryPrincipal,
+            "Quota/get" => Method::GetQuota,
+            "Quota/changes" => Method::ChangesQuota,
+            "Quota/query" => Method::QueryQuota,
+            "Quota/queryChanges" => Method::QueryChangesQuota,
+            "SieveScript/get" => Method::GetSieveScript,
+            "SieveScript/changes" => Method::ChangesSieveScript,
+            "SieveScript/query" => Method::QuerySieveScript,
+            "SieveScript/queryChanges" => Method::QueryChangesSieveScript,
+            "SieveScript/set" => Method::SetSieveScript,
+            "AddressBook/get" => Method::GetAddressBook,
+            "AddressBook/changes" => Method::ChangesAddressBook,
+            "AddressBook/query" => Method::QueryAddressBook,
+            "AddressBook/queryChanges" => Method::QueryChangesAddressBook,
+            "AddressBook/set" => Method::SetAddressBook,
+            "ContactCard/get" => Method::GetContactCard,
+            "ContactCard/changes" => Method::ChangesContactCard,
+            "ContactCard/query" => Method::QueryContactCard,
+            "ContactCard/queryChanges" => Method::QueryChangesContactCard,
+            "ContactCard/set" => Method::SetContactCard,
+            "Calendar/get" => Method::GetCalendar,
+            "Calendar/changes" => Method::ChangesCalendar,
+            "Calendar/query" => Method::QueryCalendar,
+            "Calendar/queryChanges" => Method::QueryChangesCalendar,
+            "Calendar/set" => Method::SetCalendar,
+            "CalendarEvent/get" => Method::GetCalendarEvent,
+            "CalendarEvent/changes" => Method::ChangesCalendarEvent,
+            "CalendarEvent/query" => Method::QueryCalendarEvent,
+            "CalendarEvent/queryChanges" => Method::QueryChangesCalendarEvent,
+            "CalendarEvent/set" => Method::SetCalendarEvent,
             _ => Method::Error,
         })
     }
@@ -96,6 +96,9 @@ pub struct SetResponse<O: SetObject> {
     #[serde(skip)]
     pub state_changes: Option<Vec<(TypeState, ChangeId)>>,
 
+    #[serde(skip)]
+    pub account_state_changes: Vec<(AccountId, Vec<(TypeState, ChangeId)>)>,
+
     #[serde(skip)]
     pub next_call: Option<O::NextCall>,
 }
@@ -219,6 +222,11 @@ impl<O: SetObject> SetResponse<O> {
         self.state_changes.take()
     }
 
+    // State changes of other accounts affected by this request
+    pub fn account_state_changes(&mut self) -> Vec<(AccountId, Vec<(TypeState, ChangeId)>)> {
+        std::mem::take(&mut self.account_state_changes)
+    }
+
     pub fn next_call(&mut self) -> Option<O::NextCall> {
         self.next_call.take()
     }
@@ -35,7 +35,13 @@ pub enum TypeState {
     Mailbox = 3,
     Thread = 4,
     Identity = 5,
-    None = 6,
+    Quota = 6,
+    SieveScript = 7,
+    AddressBook = 8,
+    ContactCard = 9,
+    Calendar = 10,
+    CalendarEvent = 11,
+    None = 12,
 }
 
 impl From<u64> for TypeState {
@@ -47,6 +53,12 @@ impl From<u64> for TypeState {
             3 => TypeState::Mailbox,
             4 => TypeState::Thread,
             5 => TypeState::Identity,
+            6 => TypeState::Quota,
+            7 => TypeState::SieveScript,
+            8 => TypeState::AddressBook,
+            9 => TypeState::ContactCard,
+            10 => TypeState::Calendar,
+            11 => TypeState::CalendarEvent,
             _ => {
                 debug_assert!(false, "Invalid type_state value: {}", value);
                 TypeState::None
@@ -81,6 +93,12 @@ impl TryFrom<Collection> for TypeState {
             Collection::Thread => Ok(TypeState::Thread),
             Collection::Identity => Ok(TypeState::Identity),
             Collection::EmailSubmission => Ok(TypeState::EmailSubmission),
+            Collection::Quota => Ok(TypeState::Quota),
+            Collection::SieveScript => Ok(TypeState::SieveScript),
+            Collection::AddressBook => Ok(TypeState::AddressBook),
+            Collection::ContactCard => Ok(TypeState::ContactCard),
+            Collection::Calendar => Ok(TypeState::Calendar),
+            Collection::CalendarEvent => Ok(TypeState::CalendarEvent),
             _ => Err(()),
         }
     }
@@ -95,6 +113,12 @@ impl TypeState {
             "Mailbox" => TypeState::Mailbox,
             "Thread" => TypeState::Thread,
             "Identity" => TypeState::Identity,
+            "Quota" => TypeState::Quota,
+            "SieveScript" => TypeState::SieveScript,
+            "AddressBook" => TypeState::AddressBook,
+            "ContactCard" => TypeState::ContactCard,
+            "Calendar" => TypeState::Calendar,
+            "CalendarEvent" => TypeState::CalendarEvent,
             _ => TypeState::None,
         }
     }
@@ -109,6 +133,12 @@ impl Display for TypeState {
             TypeState::Mailbox => write!(f, "Mailbox"),
             TypeState::Thread => write!(f, "Thread"),
             TypeState::Identity => write!(f, "Identity"),
+            TypeState::Quota => write!(f, "Quota"),
+            TypeState::SieveScript => write!(f, "SieveScript"),
+            TypeState::AddressBook => write!(f, "AddressBook"),
+            TypeState::ContactCard => write!(f, "ContactCard"),
+            TypeState::Calendar => write!(f, "Calendar"),
+            TypeState::CalendarEvent => write!(f, "CalendarEvent"),
             TypeState::None => Ok(()),
         }
     }
@@ -0,0 +1,193 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use super::schema::{CalendarEvent, Property, Value};
+use crate::calendar::sharing::JMAPShareCalendars;
+use jmap::jmap_store::get::{default_mapper, GetHelper, GetObject};
+use jmap::orm::serialize::JMAPOrm;
+use jmap::request::get::{GetRequest, GetResponse};
+use jmap::request::MaybeIdReference;
+use jmap::types::jmap::JMAPId;
+use store::core::acl::ACL;
+use store::core::error::StoreError;
+use store::core::vec_map::VecMap;
+use store::Store;
+use store::{AccountId, JMAPStore};
+
+impl GetObject for CalendarEvent {
+    type GetArguments = ();
+
+    fn default_properties() -> Vec<Self::Property> {
+        vec![
+            Property::Id,
+            Property::CalendarIds,
+            Property::Type,
+            Property::Uid,
+            Property::ProdId,
+            Property::Created,
+            Property::Updated,
+            Property::Sequence,
+            Property::Method,
+            Property::Title,
+            Property::Description,
+            Property::DescriptionContentType,
+            Property::Locations,
+            Property::VirtualLocations,
+            Property::Links,
+            Property::Locale,
+            Property::Keywords,
+            Property::Categories,
+            Property::Color,
+            Property::RecurrenceId,
+            Property::RecurrenceIdTimeZone,
+            Property::RecurrenceRules,
+            Property::ExcludedRecurrenceRules,
+            Property::RecurrenceOverrides,
+            Property::Excluded,
+            Property::Priority,
+            Property::FreeBusyStatus,
+            Property::Privacy,
+            Property::ReplyTo,
+            Property::SentBy,
+            Property::Participants,
+            Property::RequestStatus,
+            Property::UseDefaultAlerts,
+            Property::Alerts,
+            Property::Localizations,
+            Property::TimeZone,
+            Property::TimeZones,
+            Property::Start,
+            Property::Duration,
+            Property::ShowWithoutTime,
+            Property::Status,
+            Property::RelatedTo,
+            Property::IsDraft,
+        ]
+    }
+
+    fn get_as_id(&self, property: &Self::Property) -> Option<Vec<JMAPId>> {
+        match self.properties.get(property)? {
+            Value::Id { value } => Some(vec![*value]),
+            Value::CalendarIds { value, .. } => Some(
+                value
+                    .iter()
+                    .filter_map(|(id, v)| match id {
+                        MaybeIdReference::Value(id) if *v => Some(*id),
+                        _ => None,
+                    })
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+}
+
+pub trait JMAPGetCalendarEvent<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn calendar_event_get(
+        &self,
+        request: GetRequest<CalendarEvent>,
+    ) -> jmap::Result<GetResponse<CalendarEvent>>;
+}
+
+impl<T> JMAPGetCalendarEvent<T> for JMAPStore<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn calendar_event_get(
+        &self,
+        request: GetRequest<CalendarEvent>,
+    ) -> jmap::Result<GetResponse<CalendarEvent>> {
+        let mut helper = GetHelper::new(
+            self,
+            request,
+            default_mapper.into(),
+            (|account_id: AccountId, member_of: &[AccountId]| {
+                self.calendars_shared_events(account_id, member_of, ACL::ReadItems)
+            })
+            .into(),
+        )?;
+        let account_id = helper.account_id;
+
+        // Add Id Property
+        if !helper.properties.contains(&Property::Id) {
+            helper.properties.push(Property::Id);
+        }
+
+        helper.get(|id, properties| {
+            let mut fields = self
+                .get_orm::<CalendarEvent>(account_id, id.get_document_id())?
+                .ok_or_else(|| StoreError::NotFound("CalendarEvent data not found".to_string()))?;
+            let mut calendar_event = VecMap::with_capacity(properties.len());
+
+            for property in properties {
+                let value = match property {
+                    Property::Id => Value::Id { value: id },
+                    Property::CalendarIds => fields
+                        .get_tags(&Property::CalendarIds)
+                        .map(|tags| Value::CalendarIds {
+                            value: tags
+                                .iter()
+                                .map(|tag| (MaybeIdReference::Value(tag.as_id().into()), true))
+                                .collect(),
+                            set: true,
+                        })
+                        .unwrap_or_default(),
+                    Property::Type => Value::Text {
+                        value: "Event".to_string(),
+                    },
+                    Property::Sequence => fields
+                        .remove(property)
+                        .unwrap_or(Value::Number { value: 0 }),
+                    Property::Excluded
+                    | Property::ShowWithoutTime
+                    | Property::IsDraft
+                    | Property::UseDefaultAlerts => fields
+                        .remove(property)
+                        .unwrap_or(Value::Bool { value: false }),
+                    Property::Duration => fields.remove(property).unwrap_or(Value::Text {
+                        value: "PT0S".to_string(),
+                    }),
+                    Property::Status => fields.remove(property).unwrap_or(Value::Text {
+                        value: "confirmed".to_string(),
+                    }),
+                    Property::FreeBusyStatus => fields.remove(property).unwrap_or(Value::Text {
+                        value: "busy".to_string(),
+                    }),
+                    Property::Privacy => fields.remove(property).unwrap_or(Value::Text {
+                        value: "public".to_string(),
+                    }),
+                    Property::UtcStart | Property::UtcEnd | Property::Invalid => continue,
+                    _ => fields.remove(property).unwrap_or_default(),
+                };
+
+                calendar_event.append(*property, value);
+            }
+            Ok(Some(CalendarEvent {
+                properties: calendar_event,
+            }))
+        })
+    }
+}
@@ -0,0 +1,242 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use super::recurrence::EventTimes;
+use super::schema::{CalendarEvent, Comparator, Filter, Property, Value};
+use crate::calendar::sharing::JMAPShareCalendars;
+use jmap::error::method::MethodError;
+use jmap::jmap_store::query::{QueryHelper, QueryObject};
+use jmap::orm::serialize::JMAPOrm;
+use jmap::request::query::{self, QueryRequest, QueryResponse};
+use jmap::types::jmap::JMAPId;
+use store::core::acl::ACL;
+use store::core::tag::Tag;
+use store::read::comparator::{self, FieldComparator};
+use store::read::default_filter_mapper;
+use store::read::filter::{self, Query};
+use store::Store;
+use store::{AccountId, JMAPStore};
+
+impl QueryObject for CalendarEvent {
+    type QueryArguments = ();
+
+    type Filter = Filter;
+
+    type Comparator = Comparator;
+}
+
+pub trait JMAPCalendarEventQuery<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn calendar_event_query(
+        &self,
+        request: QueryRequest<CalendarEvent>,
+    ) -> jmap::Result<QueryResponse>;
+}
+
+impl<T> JMAPCalendarEventQuery<T> for JMAPStore<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn calendar_event_query(
+        &self,
+        request: QueryRequest<CalendarEvent>,
+    ) -> jmap::Result<QueryResponse> {
+        let mut helper = QueryHelper::new(
+            self,
+            request,
+            (|account_id: AccountId, member_of: &[AccountId]| {
+                self.calendars_shared_events(account_id, member_of, ACL::ReadItems)
+            })
+            .into(),
+        )?;
+        let account_id = helper.account_id;
+        let mut after = None;
+        let mut before = None;
+
+        // Recurring events are matched against the time range after querying the
+        // index, which is only correct when the range applies to every result.
+        if let Some(filter) = &helper.request.filter {
+            if match filter {
+                query::Filter::FilterOperator(op) if op.operator == query::Operator::And => {
+                    op.conditions.iter().any(|filter| {
+                        matches!(filter, query::Filter::FilterOperator(_)) && has_time_range(filter)
+                    })
+                }
+                _ => matches!(filter, query::Filter::FilterOperator(_)) && has_time_range(filter),
+            } {
+                return Err(MethodError::UnsupportedFilter(
+                    "The after and before conditions are only supported at the top level."
+                        .to_string(),
+                ));
+            }
+        }
+
+        helper.parse_filter(|filter| {
+            Ok(match filter {
+                Filter::InCalendars { value } => filter::Filter::or(
+                    value
+                        .into_iter()
+                        .map(|id| {
+                            filter::Filter::eq(
+                                Property::CalendarIds.into(),
+                                Query::Tag(Tag::Id(id.get_document_id())),
+                            )
+                        })
+                        .collect(),
+                ),
+                // The index only holds the first start and last end of each event,
+                // recurring events are expanded once the results are known.
+                Filter::After { value } => {
+                    after = Some(value.timestamp());
+                    filter::Filter::gt(
+                        Property::UtcEnd.into(),
+                        Query::LongInteger(std::cmp::max(value.timestamp(), 0) as u64),
+                    )
+                }
+                Filter::Before { value } => {
+                    before = Some(value.timestamp());
+                    filter::Filter::lt(
+                        Property::UtcStart.into(),
+                        Query::LongInteger(std::cmp::max(value.timestamp(), 0) as u64),
+                    )
+                }
+                Filter::Text { value } => filter::Filter::or(
+                    [
+                        Property::Title,
+                        Property::Description,
+                        Property::Locations,
+                        Property::VirtualLocations,
+                        Property::Participants,
+                    ]
+                    .into_iter()
+                    .map(|property| {
+                        filter::Filter::eq(property.into(), Query::Tokenize(value.clone()))
+                    })
+                    .collect(),
+                ),
+                Filter::Title { value } => {
+                    filter::Filter::eq(Property::Title.into(), Query::Tokenize(value))
+                }
+                Filter::Description { value } => {
+                    filter::Filter::eq(Property::Description.into(), Query::Tokenize(value))
+                }
+                Filter::Location { value } => filter::Filter::or(vec![
+                    filter::Filter::eq(Property::Locations.into(), Query::Tokenize(value.clone())),
+                    filter::Filter::eq(Property::VirtualLocations.into(), Query::Tokenize(value)),
+                ]),
+                Filter::Owner { value } => filter::Filter::or(vec![
+                    filter::Filter::eq(Property::ReplyTo.into(), Query::Tokenize(value.clone())),
+                    filter::Filter::eq(Property::Participants.into(), Query::Tokenize(value)),
+                ]),
+                Filter::Attendee { value } => {
+                    filter::Filter::eq(Property::Participants.into(), Query::Tokenize(value))
+                }
+                Filter::Uid { value } => {
+                    filter::Filter::eq(Property::Uid.into(), Query::Keyword(value))
+                }
+                Filter::Unsupported { value } => {
+                    return Err(MethodError::UnsupportedFilter(value));
+                }
+            })
+        })?;
+
+        helper.parse_comparator(|comparator| {
+            Ok(comparator::Comparator::Field(FieldComparator {
+                field: {
+                    match comparator.property {
+                        Comparator::Start => Property::UtcStart,
+                        Comparator::Created => Property::Created,
+                        Comparator::Updated => Property::Updated,
+                    }
+                }
+                .into(),
+                ascending: comparator.is_ascending,
+            }))
+        })?;
+
+        if after.is_some() || before.is_some() {
+            // Discard recurring events without occurrences in the requested range
+            helper.query(
+                default_filter_mapper,
+                Some(|ids: Vec<JMAPId>| {
+                    let mut results = Vec::with_capacity(ids.len());
+                    for id in ids {
+                        let fields = if let Some(fields) =
+                            self.get_orm::<CalendarEvent>(account_id, id.get_document_id())?
+                        {
+                            fields
+                        } else {
+                            continue;
+                        };
+                        let get_text = |property: Property| {
+                            fields.get(&property).and_then(|value| match value {
+                                Value::Text { value } | Value::JSCalendar { value } => {
+                                    Some(value.as_str())
+                                }
+                                _ => None,
+                            })
+                        };
+
+                        if get_text(Property::RecurrenceRules).is_none()
+                            && get_text(Property::RecurrenceOverrides).is_none()
+                        {
+                            // Non-recurring events are fully matched by the index
+                            results.push(id);
+                        } else if let Some(times) = get_text(Property::Start).and_then(|start| {
+                            EventTimes::parse(
+                                start,
+                                get_text(Property::Duration),
+                                get_text(Property::TimeZone),
+                                get_text(Property::RecurrenceRules),
+                                get_text(Property::ExcludedRecurrenceRules),
+                                get_text(Property::RecurrenceOverrides),
+                            )
+                        }) {
+                            if times.has_occurrence_between(after, before) {
+                                results.push(id);
+                            }
+                        }
+                    }
+                    Ok(results)
+                }),
+            )
+        } else {
+            helper.query(
+                default_filter_mapper,
+                None::<jmap::jmap_store::query::ExtraFilterFnc>,
+            )
+        }
+    }
+}
+
+fn has_time_range(filter: &query::Filter<Filter>) -> bool {
+    match filter {
+        query::Filter::FilterOperator(op) => op.conditions.iter().any(has_time_range),
+        query::Filter::FilterCondition(condition) => {
+            matches!(condition, Filter::After { .. } | Filter::Before { .. })
+        }
+        query::Filter::Empty => false,
+    }
+}
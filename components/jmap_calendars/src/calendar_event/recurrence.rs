@@ -0,0 +1,918 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use chrono_tz::Tz;
+use serde::Deserialize;
+use store::chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, TimeZone, Timelike, Weekday};
+
+// Upper bounds used to stop the expansion of rules that never (or rarely) match
+const MAX_PERIODS: usize = 100_000;
+const MAX_INSTANCES: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum Frequency {
+    #[serde(rename = "yearly")]
+    Yearly,
+    #[serde(rename = "monthly")]
+    Monthly,
+    #[serde(rename = "weekly")]
+    Weekly,
+    #[serde(rename = "daily")]
+    Daily,
+    #[serde(rename = "hourly")]
+    Hourly,
+    #[serde(rename = "minutely")]
+    Minutely,
+    #[serde(rename = "secondly")]
+    Secondly,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum Day {
+    #[serde(rename = "mo")]
+    Monday,
+    #[serde(rename = "tu")]
+    Tuesday,
+    #[serde(rename = "we")]
+    Wednesday,
+    #[serde(rename = "th")]
+    Thursday,
+    #[serde(rename = "fr")]
+    Friday,
+    #[serde(rename = "sa")]
+    Saturday,
+    #[serde(rename = "su")]
+    Sunday,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct NDay {
+    pub day: Day,
+    #[serde(rename = "nthOfPeriod")]
+    pub nth_of_period: Option<i32>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RecurrenceRule {
+    pub frequency: Frequency,
+    #[serde(default = "default_interval")]
+    pub interval: u32,
+    #[serde(rename = "firstDayOfWeek")]
+    pub first_day_of_week: Option<Day>,
+    #[serde(rename = "byDay", default)]
+    pub by_day: Vec<NDay>,
+    #[serde(rename = "byMonthDay", default)]
+    pub by_month_day: Vec<i32>,
+    #[serde(rename = "byMonth", default)]
+    pub by_month: Vec<String>,
+    #[serde(rename = "byYearDay", default)]
+    pub by_year_day: Vec<i32>,
+    #[serde(rename = "byHour", default)]
+    pub by_hour: Vec<u32>,
+    #[serde(rename = "byMinute", default)]
+    pub by_minute: Vec<u32>,
+    #[serde(rename = "bySecond", default)]
+    pub by_second: Vec<u32>,
+    #[serde(rename = "bySetPosition", default)]
+    pub by_set_position: Vec<i32>,
+    pub count: Option<u32>,
+    pub until: Option<String>,
+}
+
+fn default_interval() -> u32 {
+    1
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct OverridePatch {
+    #[serde(default)]
+    excluded: bool,
+    start: Option<String>,
+    duration: Option<String>,
+}
+
+/// The time properties of a JSCalendar event, used to expand its occurrences.
+#[derive(Debug, Clone)]
+pub struct EventTimes {
+    start: NaiveDateTime,
+    duration: Duration,
+    time_zone: Option<Tz>,
+    rules: Vec<RecurrenceRule>,
+    excluded_rules: Vec<RecurrenceRule>,
+    overrides: Vec<(NaiveDateTime, Option<(NaiveDateTime, Duration)>)>,
+}
+
+impl EventTimes {
+    pub fn parse(
+        start: &str,
+        duration: Option<&str>,
+        time_zone: Option<&str>,
+        recurrence_rules: Option<&str>,
+        excluded_recurrence_rules: Option<&str>,
+        recurrence_overrides: Option<&str>,
+    ) -> Option<Self> {
+        let start = parse_local_date_time(start)?;
+        let duration = match duration {
+            Some(duration) => parse_duration(duration)?,
+            None => Duration::zero(),
+        };
+        let time_zone = match time_zone {
+            Some(time_zone) => Some(time_zone.parse::<Tz>().ok()?),
+            None => None,
+        };
+        let rules = match recurrence_rules {
+            Some(rules) => serde_json::from_str::<Vec<RecurrenceRule>>(rules).ok()?,
+            None => Vec::new(),
+        };
+        let excluded_rules = match excluded_recurrence_rules {
+            Some(rules) => serde_json::from_str::<Vec<RecurrenceRule>>(rules).ok()?,
+            None => Vec::new(),
+        };
+        let mut overrides = Vec::new();
+        if let Some(recurrence_overrides) = recurrence_overrides {
+            for (recurrence_id, patch) in serde_json::from_str::<
+                std::collections::BTreeMap<String, OverridePatch>,
+            >(recurrence_overrides)
+            .ok()?
+            {
+                let recurrence_id = parse_local_date_time(&recurrence_id)?;
+                overrides.push((
+                    recurrence_id,
+                    if !patch.excluded {
+                        Some((
+                            match patch.start {
+                                Some(start) => parse_local_date_time(&start)?,
+                                None => recurrence_id,
+                            },
+                            match patch.duration {
+                                Some(duration) => parse_duration(&duration)?,
+                                None => duration,
+                            },
+                        ))
+                    } else {
+                        None
+                    },
+                ));
+            }
+        }
+
+        for rule in rules.iter().chain(excluded_rules.iter()) {
+            if rule.interval == 0 {
+                return None;
+            }
+            if let Some(until) = &rule.until {
+                parse_local_date_time(until)?;
+            }
+        }
+
+        Some(EventTimes {
+            start,
+            duration,
+            time_zone,
+            rules,
+            excluded_rules,
+            overrides,
+        })
+    }
+
+    /// Returns the UTC timestamp of the earliest occurrence start and of the
+    /// latest occurrence end, or None for events that recur forever.
+    pub fn utc_range(&self) -> (i64, Option<i64>) {
+        let mut first_start = i64::MAX;
+        let mut last_end = Some(i64::MIN);
+
+        if self.rules.iter().any(|rule| rule.is_infinite()) {
+            last_end = None;
+        }
+
+        let mut instances = 0;
+        self.for_each_occurrence(None, |start, end| {
+            first_start = std::cmp::min(first_start, start);
+            if let Some(last_end) = &mut last_end {
+                *last_end = std::cmp::max(*last_end, end);
+                instances += 1;
+                instances < MAX_INSTANCES
+            } else {
+                false
+            }
+        });
+
+        // The expansion stopped before reaching the end of the recurrence set
+        if instances >= MAX_INSTANCES {
+            last_end = None;
+        }
+
+        if first_start == i64::MAX {
+            // All occurrences were excluded, fall back to the event start.
+            let start = self.to_utc(self.start);
+            (start, Some(start + self.duration.num_seconds()))
+        } else {
+            (first_start, last_end)
+        }
+    }
+
+    /// Returns true if any occurrence overlaps the specified UTC time range.
+    pub fn has_occurrence_between(&self, after: Option<i64>, before: Option<i64>) -> bool {
+        let mut found = false;
+        let mut instances = 0;
+
+        self.for_each_occurrence(after, |start, end| {
+            instances += 1;
+            if before.map_or(true, |before| start < before)
+                && after.map_or(true, |after| {
+                    end > after || (start == end && start >= after)
+                })
+            {
+                found = true;
+                false
+            } else {
+                instances < MAX_INSTANCES
+                    && before.map_or(true, |before| {
+                        // Stop once the occurrences start after the range, except
+                        // for overrides which are not sorted.
+                        start < before
+                    })
+            }
+        });
+
+        found
+    }
+
+    /// Calls the closure with the UTC start and end of each occurrence, overrides
+    /// first and then the recurrence set in chronological order. The closure returns
+    /// false to stop the expansion. When a UTC timestamp is provided, occurrences
+    /// ending before it may be skipped.
+    fn for_each_occurrence(&self, from: Option<i64>, mut fnc: impl FnMut(i64, i64) -> bool) {
+        // Overrides either modify or add occurrences
+        for (_, patch) in &self.overrides {
+            if let Some((start, duration)) = patch {
+                let start = self.to_utc(*start);
+                if !fnc(start, start + duration.num_seconds()) {
+                    return;
+                }
+            }
+        }
+
+        let is_overridden = |dt: &NaiveDateTime| self.overrides.iter().any(|(id, _)| id == dt);
+
+        if self.rules.is_empty() {
+            if !is_overridden(&self.start) && !self.is_excluded(&self.start) {
+                let start = self.to_utc(self.start);
+                fnc(start, start + self.duration.num_seconds());
+            }
+            return;
+        }
+
+        // Local time from which the expansion can start, with enough margin to
+        // account for the duration and any time zone offset.
+        let from = from
+            .and_then(|from| NaiveDateTime::from_timestamp_opt(from, 0))
+            .and_then(|from| from.checked_sub_signed(self.duration + Duration::days(2)));
+
+        // Merge the occurrences produced by all rules in chronological order
+        let mut iters = self
+            .rules
+            .iter()
+            .map(|rule| {
+                RuleIter::new(rule, self.start, true)
+                    .skip_to(from)
+                    .peekable()
+            })
+            .collect::<Vec<_>>();
+        let mut excluded_iters = self
+            .excluded_rules
+            .iter()
+            .map(|rule| {
+                RuleIter::new(rule, self.start, false)
+                    .skip_to(from)
+                    .peekable()
+            })
+            .collect::<Vec<_>>();
+        let mut last = None;
+
+        loop {
+            let next = iters.iter_mut().filter_map(|it| it.peek().copied()).min();
+            let next = if let Some(next) = next {
+                for it in iters.iter_mut() {
+                    if it.peek() == Some(&next) {
+                        it.next();
+                    }
+                }
+                next
+            } else {
+                return;
+            };
+            if last == Some(next) {
+                continue;
+            }
+            last = Some(next);
+
+            // Skip occurrences matched by an exclusion rule
+            let mut is_excluded = false;
+            for it in excluded_iters.iter_mut() {
+                while matches!(it.peek(), Some(dt) if dt < &next) {
+                    it.next();
+                }
+                if it.peek() == Some(&next) {
+                    is_excluded = true;
+                }
+            }
+
+            if !is_excluded && !is_overridden(&next) {
+                let start = self.to_utc(next);
+                if !fnc(start, start + self.duration.num_seconds()) {
+                    return;
+                }
+            }
+        }
+    }
+
+    fn is_excluded(&self, dt: &NaiveDateTime) -> bool {
+        self.excluded_rules.iter().any(|rule| {
+            RuleIter::new(rule, self.start, false)
+                .take_while(|item| item <= dt)
+                .any(|item| &item == dt)
+        })
+    }
+
+    fn to_utc(&self, dt: NaiveDateTime) -> i64 {
+        if let Some(time_zone) = &self.time_zone {
+            time_zone
+                .from_local_datetime(&dt)
+                .earliest()
+                .or_else(|| {
+                    // Local time falls in a DST gap
+                    time_zone
+                        .from_local_datetime(&(dt + Duration::hours(1)))
+                        .earliest()
+                })
+                .map(|dt| dt.timestamp())
+                .unwrap_or_else(|| dt.timestamp())
+        } else {
+            dt.timestamp()
+        }
+    }
+}
+
+impl RecurrenceRule {
+    pub fn is_infinite(&self) -> bool {
+        self.count.is_none() && self.until.is_none()
+    }
+
+    fn months(&self) -> Vec<u32> {
+        self.by_month
+            .iter()
+            .filter_map(|month| month.trim_end_matches('L').parse::<u32>().ok())
+            .filter(|month| (1..=12).contains(month))
+            .collect()
+    }
+
+    fn has_weekday(&self, weekday: Weekday) -> bool {
+        self.by_day
+            .iter()
+            .any(|nday| Weekday::from(nday.day) == weekday)
+    }
+
+    fn matches_month_day(&self, date: NaiveDate) -> bool {
+        let days_in_month = days_in_month(date.year(), date.month()) as i32;
+        self.by_month_day.iter().any(|&day| {
+            if day > 0 {
+                date.day() as i32 == day
+            } else {
+                date.day() as i32 == days_in_month + day + 1
+            }
+        })
+    }
+
+    /// Returns the period of the rule that contains the specified date.
+    fn period_of(&self, start: &NaiveDateTime, dt: &NaiveDateTime) -> i64 {
+        let elapsed = match self.frequency {
+            Frequency::Yearly => (dt.year() - start.year()) as i64,
+            Frequency::Monthly => {
+                (dt.year() as i64 * 12 + dt.month0() as i64)
+                    - (start.year() as i64 * 12 + start.month0() as i64)
+            }
+            Frequency::Weekly => (*dt - *start).num_weeks(),
+            Frequency::Daily => (*dt - *start).num_days(),
+            Frequency::Hourly => (*dt - *start).num_hours(),
+            Frequency::Minutely => (*dt - *start).num_minutes(),
+            Frequency::Secondly => (*dt - *start).num_seconds(),
+        };
+        elapsed / self.interval as i64
+    }
+
+    fn matches_date(&self, date: NaiveDate, months: &[u32]) -> bool {
+        (months.is_empty() || months.contains(&date.month()))
+            && (self.by_month_day.is_empty() || self.matches_month_day(date))
+            && (self.by_day.is_empty() || self.has_weekday(date.weekday()))
+    }
+
+    /// Expands byDay within the range of dates [first, last].
+    fn expand_by_day(&self, first: NaiveDate, last: NaiveDate) -> Vec<NaiveDate> {
+        let mut dates = Vec::new();
+        for nday in &self.by_day {
+            let weekday = Weekday::from(nday.day);
+            let mut matches = Vec::new();
+            let mut date = first;
+            while date <= last {
+                if date.weekday() == weekday {
+                    matches.push(date);
+                }
+                date = date.succ();
+            }
+            match nday.nth_of_period {
+                Some(nth) if nth > 0 => {
+                    if let Some(date) = matches.get(nth as usize - 1) {
+                        dates.push(*date);
+                    }
+                }
+                Some(nth) if nth < 0 => {
+                    if let Some(pos) = matches.len().checked_sub((-nth) as usize) {
+                        dates.push(matches[pos]);
+                    }
+                }
+                _ => dates.extend(matches),
+            }
+        }
+        dates
+    }
+
+    fn expand_month(&self, year: i32, month: u32, start: &NaiveDateTime) -> Vec<NaiveDate> {
+        let first = NaiveDate::from_ymd(year, month, 1);
+        let last = NaiveDate::from_ymd(year, month, days_in_month(year, month));
+
+        if !self.by_month_day.is_empty() {
+            let mut dates = Vec::new();
+            let mut date = first;
+            while date <= last {
+                if self.matches_month_day(date)
+                    && (self.by_day.is_empty() || self.has_weekday(date.weekday()))
+                {
+                    dates.push(date);
+                }
+                date = date.succ();
+            }
+            dates
+        } else if !self.by_day.is_empty() {
+            self.expand_by_day(first, last)
+        } else {
+            NaiveDate::from_ymd_opt(year, month, start.day())
+                .into_iter()
+                .collect()
+        }
+    }
+
+    /// Returns the candidate occurrences within the n-th period of the rule.
+    fn expand_period(&self, start: &NaiveDateTime, period: i64) -> Vec<NaiveDateTime> {
+        let months = self.months();
+        let step = period * self.interval as i64;
+
+        let dates = match self.frequency {
+            Frequency::Yearly => {
+                let year = start.year() + step as i32;
+                if !self.by_year_day.is_empty() {
+                    let first = NaiveDate::from_ymd(year, 1, 1);
+                    let last = NaiveDate::from_ymd(year, 12, 31);
+                    self.by_year_day
+                        .iter()
+                        .filter_map(|&day| {
+                            if day > 0 {
+                                first.checked_add_signed(Duration::days(day as i64 - 1))
+                            } else {
+                                last.checked_add_signed(Duration::days(day as i64 + 1))
+                            }
+                        })
+                        .filter(|date| date.year() == year && self.matches_date(*date, &months))
+                        .collect()
+                } else if !months.is_empty() || !self.by_month_day.is_empty() {
+                    let months = if !months.is_empty() {
+                        months
+                    } else {
+                        (1..=12).collect()
+                    };
+                    months
+                        .into_iter()
+                        .flat_map(|month| self.expand_month(year, month, start))
+                        .collect()
+                } else if !self.by_day.is_empty() {
+                    self.expand_by_day(
+                        NaiveDate::from_ymd(year, 1, 1),
+                        NaiveDate::from_ymd(year, 12, 31),
+                    )
+                } else {
+                    NaiveDate::from_ymd_opt(year, start.month(), start.day())
+                        .into_iter()
+                        .collect()
+                }
+            }
+            Frequency::Monthly => {
+                let month = (start.year() as i64 * 12 + start.month0() as i64) + step;
+                let (year, month) = ((month / 12) as i32, (month % 12) as u32 + 1);
+                if months.is_empty() || months.contains(&month) {
+                    self.expand_month(year, month, start)
+                } else {
+                    Vec::new()
+                }
+            }
+            Frequency::Weekly => {
+                let week_start = Weekday::from(self.first_day_of_week.unwrap_or(Day::Monday));
+                let offset = (7 + start.weekday().num_days_from_monday()
+                    - week_start.num_days_from_monday())
+                    % 7;
+                let first = start.date() - Duration::days(offset as i64) + Duration::weeks(step);
+                (0..7)
+                    .map(|day| first + Duration::days(day))
+                    .filter(|date| {
+                        (if self.by_day.is_empty() {
+                            date.weekday() == start.weekday()
+                        } else {
+                            self.has_weekday(date.weekday())
+                        }) && (months.is_empty() || months.contains(&date.month()))
+                    })
+                    .collect()
+            }
+            Frequency::Daily => {
+                let date = start.date() + Duration::days(step);
+                if self.matches_date(date, &months) {
+                    vec![date]
+                } else {
+                    Vec::new()
+                }
+            }
+            Frequency::Hourly | Frequency::Minutely | Frequency::Secondly => {
+                let dt = *start
+                    + match self.frequency {
+                        Frequency::Hourly => Duration::hours(step),
+                        Frequency::Minutely => Duration::minutes(step),
+                        _ => Duration::seconds(step),
+                    };
+                return if self.matches_date(dt.date(), &months)
+                    && (self.by_hour.is_empty() || self.by_hour.contains(&dt.hour()))
+                    && (self.by_minute.is_empty() || self.by_minute.contains(&dt.minute()))
+                    && (self.by_second.is_empty() || self.by_second.contains(&dt.second()))
+                {
+                    vec![dt]
+                } else {
+                    Vec::new()
+                };
+            }
+        };
+
+        // Add times
+        let hours = if !self.by_hour.is_empty() {
+            self.by_hour.clone()
+        } else {
+            vec![start.hour()]
+        };
+        let minutes = if !self.by_minute.is_empty() {
+            self.by_minute.clone()
+        } else {
+            vec![start.minute()]
+        };
+        let seconds = if !self.by_second.is_empty() {
+            self.by_second.clone()
+        } else {
+            vec![start.second()]
+        };
+        let mut candidates = Vec::with_capacity(dates.len() * hours.len());
+        for date in dates {
+            for hour in &hours {
+                for minute in &minutes {
+                    for second in &seconds {
+                        if let Some(dt) = date.and_hms_opt(*hour, *minute, *second) {
+                            candidates.push(dt);
+                        }
+                    }
+                }
+            }
+        }
+        candidates.sort_unstable();
+        candidates.dedup();
+
+        // Apply bySetPosition
+        if !self.by_set_position.is_empty() && !candidates.is_empty() {
+            let mut selected = self
+                .by_set_position
+                .iter()
+                .filter_map(|&pos| {
+                    if pos > 0 {
+                        candidates.get(pos as usize - 1).copied()
+                    } else {
+                        candidates
+                            .len()
+                            .checked_sub((-pos) as usize)
+                            .map(|pos| candidates[pos])
+                    }
+                })
+                .collect::<Vec<_>>();
+            selected.sort_unstable();
+            selected.dedup();
+            selected
+        } else {
+            candidates
+        }
+    }
+}
+
+struct RuleIter<'x> {
+    rule: &'x RecurrenceRule,
+    start: NaiveDateTime,
+    until: Option<NaiveDateTime>,
+    include_start: bool,
+    buffer: std::vec::IntoIter<NaiveDateTime>,
+    period: i64,
+    first_period: i64,
+    emitted: u32,
+    done: bool,
+}
+
+impl<'x> RuleIter<'x> {
+    fn new(rule: &'x RecurrenceRule, start: NaiveDateTime, include_start: bool) -> Self {
+        RuleIter {
+            rule,
+            start,
+            until: rule
+                .until
+                .as_ref()
+                .and_then(|until| parse_local_date_time(until)),
+            include_start,
+            buffer: Vec::new().into_iter(),
+            period: 0,
+            first_period: 0,
+            emitted: 0,
+            done: false,
+        }
+    }
+
+    /// Starts the expansion at the period preceding the specified date. Rules
+    /// limited by count are always expanded from the start.
+    fn skip_to(mut self, from: Option<NaiveDateTime>) -> Self {
+        if let Some(from) = from {
+            if self.rule.count.is_none() && from > self.start {
+                self.include_start = false;
+                self.period = std::cmp::max(self.rule.period_of(&self.start, &from) - 1, 0);
+                self.first_period = self.period;
+            }
+        }
+        self
+    }
+}
+
+impl<'x> Iterator for RuleIter<'x> {
+    type Item = NaiveDateTime;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // The event start is always the first occurrence of the recurrence set
+        if self.include_start {
+            self.include_start = false;
+            self.emitted += 1;
+            return Some(self.start);
+        }
+
+        loop {
+            if self.done || matches!(self.rule.count, Some(count) if self.emitted >= count) {
+                self.done = true;
+                return None;
+            }
+
+            if let Some(dt) = self.buffer.next() {
+                if dt < self.start || (dt == self.start && self.emitted > 0) {
+                    continue;
+                } else if matches!(self.until, Some(until) if dt > until) {
+                    self.done = true;
+                    return None;
+                }
+                self.emitted += 1;
+                return Some(dt);
+            } else if (self.period - self.first_period) as usize >= MAX_PERIODS {
+                self.done = true;
+                return None;
+            }
+
+            self.buffer = self
+                .rule
+                .expand_period(&self.start, self.period)
+                .into_iter();
+            self.period += 1;
+        }
+    }
+}
+
+impl From<Day> for Weekday {
+    fn from(day: Day) -> Self {
+        match day {
+            Day::Monday => Weekday::Mon,
+            Day::Tuesday => Weekday::Tue,
+            Day::Wednesday => Weekday::Wed,
+            Day::Thursday => Weekday::Thu,
+            Day::Friday => Weekday::Fri,
+            Day::Saturday => Weekday::Sat,
+            Day::Sunday => Weekday::Sun,
+        }
+    }
+}
+
+fn days_in_month(year: i32, month: u32) -> u32 {
+    if month == 12 {
+        31
+    } else {
+        (NaiveDate::from_ymd(year, month + 1, 1) - NaiveDate::from_ymd(year, month, 1)).num_days()
+            as u32
+    }
+}
+
+pub fn parse_local_date_time(value: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S").ok()
+}
+
+/// Parses an ISO 8601 duration such as "PT1H30M" or "P1DT12H".
+pub fn parse_duration(value: &str) -> Option<Duration> {
+    let (is_negative, value) = match value.strip_prefix('-') {
+        Some(value) => (true, value),
+        None => (false, value.strip_prefix('+').unwrap_or(value)),
+    };
+    let mut chars = value.strip_prefix('P')?.chars();
+    let mut seconds = 0;
+    let mut number = None;
+    let mut is_time = false;
+
+    for ch in &mut chars {
+        match ch {
+            '0'..='9' => {
+                number = Some(number.unwrap_or(0) * 10 + (ch as i64 - '0' as i64));
+            }
+            'T' if !is_time && number.is_none() => {
+                is_time = true;
+            }
+            _ => {
+                let multiplier = match (ch, is_time) {
+                    ('W', false) => 7 * 86400,
+                    ('D', false) => 86400,
+                    ('H', true) => 3600,
+                    ('M', true) => 60,
+                    ('S', true) => 1,
+                    _ => return None,
+                };
+                seconds += number.take()? * multiplier;
+            }
+        }
+    }
+
+    if number.is_none() {
+        Some(Duration::seconds(if is_negative {
+            -seconds
+        } else {
+            seconds
+        }))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use store::chrono::NaiveDateTime;
+
+    use super::{parse_duration, parse_local_date_time, EventTimes, RuleIter};
+
+    fn expand(start: &str, rule: &str, limit: usize) -> Vec<NaiveDateTime> {
+        let rules = serde_json::from_str::<Vec<super::RecurrenceRule>>(rule).unwrap();
+        RuleIter::new(&rules[0], parse_local_date_time(start).unwrap(), true)
+            .take(limit)
+            .collect()
+    }
+
+    fn dates(values: &[&str]) -> Vec<NaiveDateTime> {
+        values
+            .iter()
+            .map(|value| parse_local_date_time(value).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn expand_rules() {
+        for (start, rule, limit, expected) in [
+            (
+                "2022-01-03T09:00:00",
+                r#"[{"frequency": "weekly", "byDay": [{"day": "mo"}, {"day": "we"}], "count": 4}]"#,
+                10,
+                vec![
+                    "2022-01-03T09:00:00",
+                    "2022-01-05T09:00:00",
+                    "2022-01-10T09:00:00",
+                    "2022-01-12T09:00:00",
+                ],
+            ),
+            (
+                "2022-01-31T10:00:00",
+                r#"[{"frequency": "monthly", "count": 3}]"#,
+                10,
+                vec![
+                    "2022-01-31T10:00:00",
+                    "2022-03-31T10:00:00",
+                    "2022-05-31T10:00:00",
+                ],
+            ),
+            (
+                "2022-01-01T08:00:00",
+                r#"[{"frequency": "monthly", "byDay": [{"day": "fr", "nthOfPeriod": -1}]}]"#,
+                3,
+                vec![
+                    "2022-01-01T08:00:00",
+                    "2022-01-28T08:00:00",
+                    "2022-02-25T08:00:00",
+                ],
+            ),
+            (
+                "2022-01-01T00:00:00",
+                r#"[{"frequency": "daily", "interval": 10, "until": "2022-01-25T00:00:00"}]"#,
+                10,
+                vec![
+                    "2022-01-01T00:00:00",
+                    "2022-01-11T00:00:00",
+                    "2022-01-21T00:00:00",
+                ],
+            ),
+            (
+                "2020-02-29T12:00:00",
+                r#"[{"frequency": "yearly", "count": 2}]"#,
+                10,
+                vec!["2020-02-29T12:00:00", "2024-02-29T12:00:00"],
+            ),
+            (
+                "2022-11-01T09:00:00",
+                r#"[{"frequency": "yearly", "byMonth": ["11"], "byDay": [{"day": "th", "nthOfPeriod": 4}], "count": 2}]"#,
+                10,
+                vec!["2022-11-01T09:00:00", "2022-11-24T09:00:00"],
+            ),
+        ] {
+            assert_eq!(expand(start, rule, limit), dates(&expected), "{}", rule);
+        }
+    }
+
+    #[test]
+    fn occurrences_between() {
+        let event = EventTimes::parse(
+            "2022-01-03T09:00:00",
+            Some("PT1H"),
+            Some("Europe/Madrid"),
+            Some(r#"[{"frequency": "weekly"}]"#),
+            None,
+            Some(r#"{"2022-01-10T09:00:00": {"excluded": true}}"#),
+        )
+        .unwrap();
+
+        // 2022-01-03T08:00:00Z
+        assert_eq!(event.utc_range(), (1641196800, None));
+        // First week
+        assert!(event.has_occurrence_between(Some(1641196800), Some(1641200400)));
+        // Excluded second week
+        assert!(!event.has_occurrence_between(Some(1641801600), Some(1641805200)));
+        // Third week, far in the future
+        assert!(event.has_occurrence_between(Some(1642406400), Some(1642410000)));
+        assert!(event.has_occurrence_between(Some(1956566400), None));
+        // Before the first occurrence
+        assert!(!event.has_occurrence_between(None, Some(1641196800)));
+
+        // Far-future ranges of long open-ended series
+        let event = EventTimes::parse(
+            "2000-01-01T00:00:00",
+            Some("PT30M"),
+            None,
+            Some(r#"[{"frequency": "hourly", "byMinute": [0]}]"#),
+            Some(r#"[{"frequency": "daily", "byHour": [12], "byMinute": [0]}]"#),
+            None,
+        )
+        .unwrap();
+        // 2030-01-01T00:10:00Z to 2030-01-01T00:20:00Z
+        assert!(event.has_occurrence_between(Some(1893456600), Some(1893457200)));
+        // 2030-01-01T00:40:00Z to 2030-01-01T00:50:00Z
+        assert!(!event.has_occurrence_between(Some(1893458400), Some(1893459000)));
+        // Excluded 2030-01-01T12:00:00Z occurrence
+        assert!(!event.has_occurrence_between(Some(1893499200), Some(1893501000)));
+
+        assert_eq!(parse_duration("P1DT2H30M").unwrap().num_seconds(), 95400);
+        assert_eq!(parse_duration("PT15M").unwrap().num_seconds(), 900);
+        assert!(parse_duration("P1H").is_none());
+    }
+}
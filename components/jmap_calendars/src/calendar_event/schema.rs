@@ -0,0 +1,495 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::fmt::Display;
+
+use jmap::{
+    orm,
+    request::MaybeIdReference,
+    types::{date::JMAPDate, jmap::JMAPId},
+};
+use serde::{Deserialize, Serialize};
+use store::{core::vec_map::VecMap, FieldId};
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct CalendarEvent {
+    pub properties: VecMap<Property, Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Value {
+    Id {
+        value: JMAPId,
+    },
+    Text {
+        value: String,
+    },
+    Number {
+        value: i64,
+    },
+    Bool {
+        value: bool,
+    },
+    Date {
+        value: JMAPDate,
+    },
+    Timestamp {
+        value: u64,
+    },
+    CalendarIds {
+        value: VecMap<MaybeIdReference, bool>,
+        set: bool,
+    },
+    TextMap {
+        value: VecMap<String, bool>,
+    },
+    JSCalendar {
+        value: String,
+    },
+    Null,
+}
+
+impl Default for Value {
+    fn default() -> Self {
+        Value::Null
+    }
+}
+
+impl orm::Value for Value {
+    fn index_as(&self) -> orm::Index {
+        match self {
+            Value::Text { value } => value.to_string().into(),
+            Value::Date { value } => (value.timestamp() as u64).into(),
+            Value::Timestamp { value } => (*value).into(),
+            Value::TextMap { value } => {
+                if !value.is_empty() {
+                    value
+                        .iter()
+                        .filter(|(_, v)| **v)
+                        .map(|(k, _)| k.to_string())
+                        .collect::<Vec<_>>()
+                        .into()
+                } else {
+                    orm::Index::Null
+                }
+            }
+            Value::JSCalendar { value } => {
+                // Index all the string values contained in the JSCalendar object
+                let mut text = String::new();
+                if let Ok(value) = serde_json::from_str::<serde_json::Value>(value) {
+                    collect_text(&value, &mut text);
+                }
+                if !text.is_empty() {
+                    text.into()
+                } else {
+                    orm::Index::Null
+                }
+            }
+            _ => orm::Index::Null,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        match self {
+            Value::Text { value } => value.is_empty(),
+            Value::TextMap { value } => value.is_empty(),
+            Value::JSCalendar { value } => value.is_empty(),
+            Value::Null => true,
+            _ => false,
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Value::Id { .. } => std::mem::size_of::<JMAPId>(),
+            Value::Text { value } => value.len(),
+            Value::Number { .. } => std::mem::size_of::<i64>(),
+            Value::Bool { .. } => std::mem::size_of::<bool>(),
+            Value::Date { .. } => std::mem::size_of::<JMAPDate>(),
+            Value::Timestamp { .. } => std::mem::size_of::<u64>(),
+            Value::CalendarIds { value, .. } => {
+                value.len() * std::mem::size_of::<MaybeIdReference>()
+            }
+            Value::TextMap { value } => value.keys().fold(0, |acc, k| acc + k.len()),
+            Value::JSCalendar { value } => value.len(),
+            Value::Null => 0,
+        }
+    }
+}
+
+fn collect_text(value: &serde_json::Value, text: &mut String) {
+    match value {
+        serde_json::Value::String(value) => {
+            if !text.is_empty() {
+                text.push(' ');
+            }
+            text.push_str(value);
+        }
+        serde_json::Value::Array(list) => {
+            for value in list {
+                collect_text(value, text);
+            }
+        }
+        serde_json::Value::Object(map) => {
+            for (key, value) in map {
+                if key != "@type" {
+                    collect_text(value, text);
+                }
+            }
+        }
+        _ => (),
+    }
+}
+
+impl Value {
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            Value::Text { value } => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_jscalendar(&self) -> Option<&str> {
+        match self {
+            Value::JSCalendar { value } => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_timestamp(&self) -> Option<u64> {
+        match self {
+            Value::Timestamp { value } => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn get_calendar_ids(&mut self) -> Option<&mut VecMap<MaybeIdReference, bool>> {
+        match self {
+            Value::CalendarIds { value, .. } => Some(value),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Copy)]
+#[repr(u8)]
+pub enum Property {
+    Id = 0,
+    CalendarIds = 1,
+    Type = 2,
+    Uid = 3,
+    ProdId = 4,
+    Created = 5,
+    Updated = 6,
+    Sequence = 7,
+    Method = 8,
+    Title = 9,
+    Description = 10,
+    DescriptionContentType = 11,
+    Locations = 12,
+    VirtualLocations = 13,
+    Links = 14,
+    Locale = 15,
+    Keywords = 16,
+    Categories = 17,
+    Color = 18,
+    RecurrenceId = 19,
+    RecurrenceIdTimeZone = 20,
+    RecurrenceRules = 21,
+    ExcludedRecurrenceRules = 22,
+    RecurrenceOverrides = 23,
+    Excluded = 24,
+    Priority = 25,
+    FreeBusyStatus = 26,
+    Privacy = 27,
+    ReplyTo = 28,
+    SentBy = 29,
+    Participants = 30,
+    RequestStatus = 31,
+    UseDefaultAlerts = 32,
+    Alerts = 33,
+    Localizations = 34,
+    TimeZone = 35,
+    TimeZones = 36,
+    Start = 37,
+    Duration = 38,
+    ShowWithoutTime = 39,
+    Status = 40,
+    RelatedTo = 41,
+    IsDraft = 42,
+    UtcStart = 43,
+    UtcEnd = 44,
+    Invalid = 45,
+}
+
+impl Display for Property {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Property::Id => write!(f, "id"),
+            Property::CalendarIds => write!(f, "calendarIds"),
+            Property::Type => write!(f, "@type"),
+            Property::Uid => write!(f, "uid"),
+            Property::ProdId => write!(f, "prodId"),
+            Property::Created => write!(f, "created"),
+            Property::Updated => write!(f, "updated"),
+            Property::Sequence => write!(f, "sequence"),
+            Property::Method => write!(f, "method"),
+            Property::Title => write!(f, "title"),
+            Property::Description => write!(f, "description"),
+            Property::DescriptionContentType => write!(f, "descriptionContentType"),
+            Property::Locations => write!(f, "locations"),
+            Property::VirtualLocations => write!(f, "virtualLocations"),
+            Property::Links => write!(f, "links"),
+            Property::Locale => write!(f, "locale"),
+            Property::Keywords => write!(f, "keywords"),
+            Property::Categories => write!(f, "categories"),
+            Property::Color => write!(f, "color"),
+            Property::RecurrenceId => write!(f, "recurrenceId"),
+            Property::RecurrenceIdTimeZone => write!(f, "recurrenceIdTimeZone"),
+            Property::RecurrenceRules => write!(f, "recurrenceRules"),
+            Property::ExcludedRecurrenceRules => write!(f, "excludedRecurrenceRules"),
+            Property::RecurrenceOverrides => write!(f, "recurrenceOverrides"),
+            Property::Excluded => write!(f, "excluded"),
+            Property::Priority => write!(f, "priority"),
+            Property::FreeBusyStatus => write!(f, "freeBusyStatus"),
+            Property::Privacy => write!(f, "privacy"),
+            Property::ReplyTo => write!(f, "replyTo"),
+            Property::SentBy => write!(f, "sentBy"),
+            Property::Participants => write!(f, "participants"),
+            Property::RequestStatus => write!(f, "requestStatus"),
+            Property::UseDefaultAlerts => write!(f, "useDefaultAlerts"),
+            Property::Alerts => write!(f, "alerts"),
+            Property::Localizations => write!(f, "localizations"),
+            Property::TimeZone => write!(f, "timeZone"),
+            Property::TimeZones => write!(f, "timeZones"),
+            Property::Start => write!(f, "start"),
+            Property::Duration => write!(f, "duration"),
+            Property::ShowWithoutTime => write!(f, "showWithoutTime"),
+            Property::Status => write!(f, "status"),
+            Property::RelatedTo => write!(f, "relatedTo"),
+            Property::IsDraft => write!(f, "isDraft"),
+            Property::UtcStart | Property::UtcEnd | Property::Invalid => Ok(()),
+        }
+    }
+}
+
+impl Property {
+    pub fn parse(value: &str) -> Self {
+        match value {
+            "id" => Property::Id,
+            "calendarIds" => Property::CalendarIds,
+            "@type" => Property::Type,
+            "uid" => Property::Uid,
+            "prodId" => Property::ProdId,
+            "created" => Property::Created,
+            "updated" => Property::Updated,
+            "sequence" => Property::Sequence,
+            "method" => Property::Method,
+            "title" => Property::Title,
+            "description" => Property::Description,
+            "descriptionContentType" => Property::DescriptionContentType,
+            "locations" => Property::Locations,
+            "virtualLocations" => Property::VirtualLocations,
+            "links" => Property::Links,
+            "locale" => Property::Locale,
+            "keywords" => Property::Keywords,
+            "categories" => Property::Categories,
+            "color" => Property::Color,
+            "recurrenceId" => Property::RecurrenceId,
+            "recurrenceIdTimeZone" => Property::RecurrenceIdTimeZone,
+            "recurrenceRules" => Property::RecurrenceRules,
+            "excludedRecurrenceRules" => Property::ExcludedRecurrenceRules,
+            "recurrenceOverrides" => Property::RecurrenceOverrides,
+            "excluded" => Property::Excluded,
+            "priority" => Property::Priority,
+            "freeBusyStatus" => Property::FreeBusyStatus,
+            "privacy" => Property::Privacy,
+            "replyTo" => Property::ReplyTo,
+            "sentBy" => Property::SentBy,
+            "participants" => Property::Participants,
+            "requestStatus" => Property::RequestStatus,
+            "useDefaultAlerts" => Property::UseDefaultAlerts,
+            "alerts" => Property::Alerts,
+            "localizations" => Property::Localizations,
+            "timeZone" => Property::TimeZone,
+            "timeZones" => Property::TimeZones,
+            "start" => Property::Start,
+            "duration" => Property::Duration,
+            "showWithoutTime" => Property::ShowWithoutTime,
+            "status" => Property::Status,
+            "relatedTo" => Property::RelatedTo,
+            "isDraft" => Property::IsDraft,
+            _ => Property::Invalid,
+        }
+    }
+
+    pub fn is_text(&self) -> bool {
+        matches!(
+            self,
+            Property::ProdId
+                | Property::Method
+                | Property::Title
+                | Property::Description
+                | Property::DescriptionContentType
+                | Property::Locale
+                | Property::Color
+                | Property::RecurrenceId
+                | Property::RecurrenceIdTimeZone
+                | Property::FreeBusyStatus
+                | Property::Privacy
+                | Property::SentBy
+                | Property::RequestStatus
+                | Property::TimeZone
+                | Property::Start
+                | Property::Duration
+                | Property::Status
+        )
+    }
+
+    pub fn is_jscalendar(&self) -> bool {
+        matches!(
+            self,
+            Property::Locations
+                | Property::VirtualLocations
+                | Property::Links
+                | Property::RecurrenceRules
+                | Property::ExcludedRecurrenceRules
+                | Property::RecurrenceOverrides
+                | Property::ReplyTo
+                | Property::Participants
+                | Property::Alerts
+                | Property::Localizations
+                | Property::TimeZones
+                | Property::RelatedTo
+        )
+    }
+
+    /// Returns true if the property affects the time span of the event.
+    pub fn is_time_related(&self) -> bool {
+        matches!(
+            self,
+            Property::Start
+                | Property::Duration
+                | Property::TimeZone
+                | Property::RecurrenceRules
+                | Property::ExcludedRecurrenceRules
+                | Property::RecurrenceOverrides
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Filter {
+    InCalendars { value: Vec<JMAPId> },
+    After { value: JMAPDate },
+    Before { value: JMAPDate },
+    Text { value: String },
+    Title { value: String },
+    Description { value: String },
+    Location { value: String },
+    Owner { value: String },
+    Attendee { value: String },
+    Uid { value: String },
+    Unsupported { value: String },
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "property")]
+pub enum Comparator {
+    #[serde(rename = "start")]
+    Start,
+    #[serde(rename = "created")]
+    Created,
+    #[serde(rename = "updated")]
+    Updated,
+}
+
+impl From<Property> for FieldId {
+    fn from(field: Property) -> Self {
+        field as FieldId
+    }
+}
+
+impl From<FieldId> for Property {
+    fn from(field: FieldId) -> Self {
+        match field {
+            0 => Property::Id,
+            1 => Property::CalendarIds,
+            2 => Property::Type,
+            3 => Property::Uid,
+            4 => Property::ProdId,
+            5 => Property::Created,
+            6 => Property::Updated,
+            7 => Property::Sequence,
+            8 => Property::Method,
+            9 => Property::Title,
+            10 => Property::Description,
+            11 => Property::DescriptionContentType,
+            12 => Property::Locations,
+            13 => Property::VirtualLocations,
+            14 => Property::Links,
+            15 => Property::Locale,
+            16 => Property::Keywords,
+            17 => Property::Categories,
+            18 => Property::Color,
+            19 => Property::RecurrenceId,
+            20 => Property::RecurrenceIdTimeZone,
+            21 => Property::RecurrenceRules,
+            22 => Property::ExcludedRecurrenceRules,
+            23 => Property::RecurrenceOverrides,
+            24 => Property::Excluded,
+            25 => Property::Priority,
+            26 => Property::FreeBusyStatus,
+            27 => Property::Privacy,
+            28 => Property::ReplyTo,
+            29 => Property::SentBy,
+            30 => Property::Participants,
+            31 => Property::RequestStatus,
+            32 => Property::UseDefaultAlerts,
+            33 => Property::Alerts,
+            34 => Property::Localizations,
+            35 => Property::TimeZone,
+            36 => Property::TimeZones,
+            37 => Property::Start,
+            38 => Property::Duration,
+            39 => Property::ShowWithoutTime,
+            40 => Property::Status,
+            41 => Property::RelatedTo,
+            42 => Property::IsDraft,
+            43 => Property::UtcStart,
+            44 => Property::UtcEnd,
+            _ => Property::Invalid,
+        }
+    }
+}
+
+impl TryFrom<&str> for Property {
+    type Error = ();
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match Property::parse(value) {
+            Property::Invalid | Property::UtcStart | Property::UtcEnd => Err(()),
+            property => Ok(property),
+        }
+    }
+}
@@ -0,0 +1,293 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{borrow::Cow, fmt};
+
+use jmap::{
+    request::{query::FilterDeserializer, MaybeIdReference},
+    types::{date::JMAPDate, jmap::JMAPId, json_pointer::JSONPointer},
+};
+use serde::{de::IgnoredAny, ser::SerializeMap, Deserialize, Serialize};
+use store::core::vec_map::VecMap;
+
+use super::schema::{CalendarEvent, Filter, Property, Value};
+
+// Property de/serialization
+impl Serialize for Property {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+struct PropertyVisitor;
+
+impl<'de> serde::de::Visitor<'de> for PropertyVisitor {
+    type Value = Property;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a valid JMAP CalendarEvent property")
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        Ok(Property::parse(v))
+    }
+}
+
+impl<'de> Deserialize<'de> for Property {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_str(PropertyVisitor)
+    }
+}
+
+// CalendarEvent de/serialization
+impl Serialize for CalendarEvent {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut map = serializer.serialize_map(self.properties.len().into())?;
+
+        for (name, value) in &self.properties {
+            match value {
+                Value::Id { value } => map.serialize_entry(name, value)?,
+                Value::Text { value } => map.serialize_entry(name, value)?,
+                Value::Number { value } => map.serialize_entry(name, value)?,
+                Value::Bool { value } => map.serialize_entry(name, value)?,
+                Value::Date { value } => map.serialize_entry(name, value)?,
+                Value::CalendarIds { value, .. } => map.serialize_entry(name, value)?,
+                Value::TextMap { value } => map.serialize_entry(name, value)?,
+                Value::JSCalendar { value } => map.serialize_entry(
+                    name,
+                    &serde_json::from_str::<serde_json::Value>(value).unwrap_or_default(),
+                )?,
+                Value::Timestamp { .. } => (),
+                Value::Null => map.serialize_entry(name, &None::<&str>)?,
+            }
+        }
+
+        map.end()
+    }
+}
+
+struct CalendarEventVisitor;
+
+impl<'de> serde::de::Visitor<'de> for CalendarEventVisitor {
+    type Value = CalendarEvent;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a valid JMAP CalendarEvent object")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: serde::de::MapAccess<'de>,
+    {
+        let mut properties: VecMap<Property, Value> = VecMap::new();
+
+        while let Some(key) = map.next_key::<Cow<str>>()? {
+            match Property::parse(key.as_ref()) {
+                Property::CalendarIds => {
+                    if let Some(value) =
+                        map.next_value::<Option<VecMap<MaybeIdReference, bool>>>()?
+                    {
+                        properties.append(
+                            Property::CalendarIds,
+                            Value::CalendarIds { value, set: true },
+                        );
+                    }
+                }
+                property @ (Property::Type | Property::Uid) => {
+                    properties.append(
+                        property,
+                        if let Some(value) = map.next_value::<Option<String>>()? {
+                            Value::Text { value }
+                        } else {
+                            Value::Null
+                        },
+                    );
+                }
+                property if property.is_text() => {
+                    properties.append(
+                        property,
+                        if let Some(value) = map.next_value::<Option<String>>()? {
+                            Value::Text { value }
+                        } else {
+                            Value::Null
+                        },
+                    );
+                }
+                property @ (Property::Sequence | Property::Priority) => {
+                    properties.append(
+                        property,
+                        if let Some(value) = map.next_value::<Option<i64>>()? {
+                            Value::Number { value }
+                        } else {
+                            Value::Null
+                        },
+                    );
+                }
+                property @ (Property::Excluded
+                | Property::UseDefaultAlerts
+                | Property::ShowWithoutTime
+                | Property::IsDraft) => {
+                    properties.append(
+                        property,
+                        if let Some(value) = map.next_value::<Option<bool>>()? {
+                            Value::Bool { value }
+                        } else {
+                            Value::Null
+                        },
+                    );
+                }
+                property @ (Property::Created | Property::Updated) => {
+                    properties.append(
+                        property,
+                        if let Some(value) = map.next_value::<Option<JMAPDate>>()? {
+                            Value::Date { value }
+                        } else {
+                            Value::Null
+                        },
+                    );
+                }
+                property @ (Property::Keywords | Property::Categories) => {
+                    properties.append(
+                        property,
+                        if let Some(value) = map.next_value::<Option<VecMap<String, bool>>>()? {
+                            Value::TextMap { value }
+                        } else {
+                            Value::Null
+                        },
+                    );
+                }
+                property if property.is_jscalendar() => {
+                    properties.append(
+                        property,
+                        match map.next_value::<serde_json::Value>()? {
+                            serde_json::Value::Null => Value::Null,
+                            value => Value::JSCalendar {
+                                value: value.to_string(),
+                            },
+                        },
+                    );
+                }
+                _ => match JSONPointer::parse(key.as_ref()) {
+                    Some(JSONPointer::Path(path)) if path.len() == 2 => {
+                        if let (
+                            Some(JSONPointer::String(property)),
+                            Some(JSONPointer::String(id)),
+                        ) = (path.get(0), path.get(1))
+                        {
+                            match (Property::parse(property), JMAPId::parse(id)) {
+                                (Property::CalendarIds, Some(id)) => {
+                                    let value = map.next_value::<Option<bool>>()?.unwrap_or(false);
+                                    properties
+                                        .get_mut_or_insert_with(Property::CalendarIds, || {
+                                            Value::CalendarIds {
+                                                value: VecMap::new(),
+                                                set: false,
+                                            }
+                                        })
+                                        .get_calendar_ids()
+                                        .unwrap()
+                                        .append(MaybeIdReference::Value(id), value);
+                                }
+                                _ => {
+                                    map.next_value::<IgnoredAny>()?;
+                                }
+                            }
+                        } else {
+                            map.next_value::<IgnoredAny>()?;
+                        }
+                    }
+                    _ => {
+                        map.next_value::<IgnoredAny>()?;
+                    }
+                },
+            }
+        }
+
+        Ok(CalendarEvent { properties })
+    }
+}
+
+impl<'de> Deserialize<'de> for CalendarEvent {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_map(CalendarEventVisitor)
+    }
+}
+
+// Filter deserializer
+impl FilterDeserializer for Filter {
+    fn deserialize<'x>(property: &str, map: &mut impl serde::de::MapAccess<'x>) -> Option<Self> {
+        match property {
+            "inCalendars" => Filter::InCalendars {
+                value: map.next_value().ok()?,
+            },
+            "after" => Filter::After {
+                value: map.next_value().ok()?,
+            },
+            "before" => Filter::Before {
+                value: map.next_value().ok()?,
+            },
+            "text" => Filter::Text {
+                value: map.next_value().ok()?,
+            },
+            "title" => Filter::Title {
+                value: map.next_value().ok()?,
+            },
+            "description" => Filter::Description {
+                value: map.next_value().ok()?,
+            },
+            "location" => Filter::Location {
+                value: map.next_value().ok()?,
+            },
+            "owner" => Filter::Owner {
+                value: map.next_value().ok()?,
+            },
+            "attendee" => Filter::Attendee {
+                value: map.next_value().ok()?,
+            },
+            "uid" => Filter::Uid {
+                value: map.next_value().ok()?,
+            },
+            unsupported => {
+                map.next_value::<IgnoredAny>().ok()?;
+                Filter::Unsupported {
+                    value: unsupported.to_string(),
+                }
+            }
+        }
+        .into()
+    }
+}
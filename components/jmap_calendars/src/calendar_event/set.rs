@@ -0,0 +1,497 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::SystemTime;
+
+use super::recurrence::{parse_duration, parse_local_date_time, EventTimes};
+use super::schema::{CalendarEvent, Property, Value};
+use crate::calendar::sharing::JMAPShareCalendars;
+use chrono_tz::Tz;
+use jmap::error::set::{SetError, SetErrorType};
+use jmap::jmap_store::set::{SetHelper, SetObject};
+use jmap::jmap_store::Object;
+use jmap::orm::{serialize::JMAPOrm, TinyORM};
+use jmap::request::set::{SetRequest, SetResponse};
+use jmap::request::{ACLEnforce, MaybeIdReference, ResultReference};
+use jmap::types::date::JMAPDate;
+use jmap::types::jmap::JMAPId;
+use store::core::acl::ACL;
+use store::core::collection::Collection;
+use store::core::document::Document;
+use store::core::error::StoreError;
+use store::core::tag::Tag;
+use store::core::vec_map::VecMap;
+use store::rand::{self, Rng};
+use store::roaring::RoaringBitmap;
+use store::{AccountId, JMAPStore};
+use store::{SharedBitmap, Store};
+
+impl SetObject for CalendarEvent {
+    type SetArguments = ();
+
+    type NextCall = ();
+
+    fn eval_id_references(&mut self, mut fnc: impl FnMut(&str) -> Option<JMAPId>) {
+        if let Some(Value::CalendarIds { value, .. }) =
+            self.properties.get_mut(&Property::CalendarIds)
+        {
+            if value
+                .keys()
+                .any(|k| matches!(k, MaybeIdReference::Reference(_)))
+            {
+                let mut new_values = VecMap::with_capacity(value.len());
+
+                for (id, value) in std::mem::take(value).into_iter() {
+                    if let MaybeIdReference::Reference(id) = &id {
+                        if let Some(id) = fnc(id) {
+                            new_values.append(MaybeIdReference::Value(id), value);
+                            continue;
+                        }
+                    }
+                    new_values.append(id, value);
+                }
+
+                *value = new_values;
+            }
+        }
+    }
+
+    fn eval_result_references(&mut self, _fnc: impl FnMut(&ResultReference) -> Option<Vec<u64>>) {}
+}
+
+pub trait JMAPSetCalendarEvent<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn calendar_event_set(
+        &self,
+        request: SetRequest<CalendarEvent>,
+    ) -> jmap::Result<SetResponse<CalendarEvent>>;
+    fn calendar_event_delete(
+        &self,
+        account_id: AccountId,
+        document: &mut Document,
+    ) -> store::Result<()>;
+}
+
+impl<T> JMAPSetCalendarEvent<T> for JMAPStore<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn calendar_event_set(
+        &self,
+        request: SetRequest<CalendarEvent>,
+    ) -> jmap::Result<SetResponse<CalendarEvent>> {
+        let mut helper = SetHelper::new(self, request)?;
+        let calendar_ids = self
+            .get_document_ids(helper.account_id, Collection::Calendar)?
+            .unwrap_or_default();
+
+        helper.create(|_create_id, item, helper, document| {
+            let fields = TinyORM::<CalendarEvent>::new().calendar_event_set(
+                helper,
+                item,
+                &calendar_ids,
+                None,
+            )?;
+
+            // Check ACLs
+            if helper.acl.is_shared(helper.account_id) {
+                let allowed_calendars = helper.store.calendars_shared(
+                    helper.account_id,
+                    &helper.acl.member_of,
+                    ACL::AddItems,
+                )?;
+                for calendar in fields.get_tags(&Property::CalendarIds).unwrap() {
+                    let calendar_id = calendar.as_id();
+                    if !allowed_calendars.has_access(calendar_id) {
+                        return Err(SetError::forbidden(format!(
+                            "You are not allowed to add events to calendar {}.",
+                            JMAPId::from(calendar_id)
+                        )));
+                    }
+                }
+            }
+
+            // Return the server-set properties
+            let mut calendar_event = CalendarEvent::new(document.document_id.into());
+            for property in [Property::Uid, Property::Created, Property::Updated] {
+                if let Some(value) = fields.get(&property) {
+                    calendar_event.properties.append(property, value.clone());
+                }
+            }
+
+            fields.insert_validate(document)?;
+
+            Ok(calendar_event)
+        })?;
+
+        helper.update(|id, item, helper, document| {
+            let document_id = id.get_document_id();
+            let current_fields = self
+                .get_orm::<CalendarEvent>(helper.account_id, document_id)?
+                .ok_or_else(|| SetError::new_err(SetErrorType::NotFound))?;
+            let modifies_event = item
+                .properties
+                .keys()
+                .any(|property| property != &Property::CalendarIds);
+
+            let fields = TinyORM::track_changes(&current_fields).calendar_event_set(
+                helper,
+                item,
+                &calendar_ids,
+                Some(&current_fields),
+            )?;
+
+            // Check ACLs
+            if helper.acl.is_shared(helper.account_id) {
+                for (acl, calendars, message) in [
+                    (
+                        ACL::AddItems,
+                        current_fields.get_added_tags(&fields, &Property::CalendarIds),
+                        "You are not allowed to add events to calendar",
+                    ),
+                    (
+                        ACL::RemoveItems,
+                        current_fields.get_removed_tags(&fields, &Property::CalendarIds),
+                        "You are not allowed to remove events from calendar",
+                    ),
+                ] {
+                    if !calendars.is_empty() {
+                        let allowed_calendars = helper.store.calendars_shared(
+                            helper.account_id,
+                            &helper.acl.member_of,
+                            acl,
+                        )?;
+                        for calendar in calendars {
+                            let calendar_id = calendar.as_id();
+                            if !allowed_calendars.has_access(calendar_id) {
+                                return Err(SetError::forbidden(format!(
+                                    "{} {}.",
+                                    message,
+                                    JMAPId::from(calendar_id)
+                                )));
+                            }
+                        }
+                    }
+                }
+
+                if modifies_event
+                    && !helper
+                        .store
+                        .calendars_shared_events(
+                            helper.account_id,
+                            &helper.acl.member_of,
+                            ACL::ModifyItems,
+                        )?
+                        .has_access(document_id)
+                {
+                    return Err(SetError::forbidden(
+                        "You are not allowed to modify this event.",
+                    ));
+                }
+            }
+
+            // Merge changes
+            current_fields.merge_validate(document, fields)?;
+
+            Ok(None)
+        })?;
+
+        helper.destroy(|_id, helper, document| {
+            // Check ACLs
+            if helper.acl.is_shared(helper.account_id)
+                && !helper
+                    .store
+                    .calendars_shared_events(
+                        helper.account_id,
+                        &helper.acl.member_of,
+                        ACL::RemoveItems,
+                    )?
+                    .has_access(document.document_id)
+            {
+                return Err(SetError::forbidden(
+                    "You are not allowed to delete this event.",
+                ));
+            }
+
+            self.calendar_event_delete(helper.account_id, document)?;
+            Ok(())
+        })?;
+
+        helper.into_response()
+    }
+
+    fn calendar_event_delete(
+        &self,
+        account_id: AccountId,
+        document: &mut Document,
+    ) -> store::Result<()> {
+        // Delete ORM
+        self.get_orm::<CalendarEvent>(account_id, document.document_id)?
+            .ok_or_else(|| {
+                StoreError::NotFound(format!(
+                    "Failed to fetch CalendarEvent ORM for {}:{}.",
+                    account_id, document.document_id
+                ))
+            })?
+            .delete(document);
+
+        Ok(())
+    }
+}
+
+trait CalendarEventSet<T>: Sized
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn calendar_event_set(
+        self,
+        helper: &mut SetHelper<CalendarEvent, T>,
+        calendar_event: CalendarEvent,
+        calendar_ids: &RoaringBitmap,
+        fields: Option<&TinyORM<CalendarEvent>>,
+    ) -> jmap::error::set::Result<Self, Property>;
+}
+
+impl<T> CalendarEventSet<T> for TinyORM<CalendarEvent>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn calendar_event_set(
+        mut self,
+        helper: &mut SetHelper<CalendarEvent, T>,
+        calendar_event: CalendarEvent,
+        calendar_ids: &RoaringBitmap,
+        current_fields: Option<&TinyORM<CalendarEvent>>,
+    ) -> jmap::error::set::Result<Self, Property> {
+        let mut set_updated = true;
+        let mut update_times = current_fields.is_none();
+
+        for (property, value) in calendar_event.properties {
+            let value = match (property, value) {
+                (Property::CalendarIds, Value::CalendarIds { value, set }) => {
+                    if set {
+                        self.untag_all(&Property::CalendarIds);
+                    }
+
+                    for (calendar_id, is_set) in value {
+                        let calendar_id = helper
+                            .unwrap_id_reference(Property::CalendarIds, &calendar_id)?
+                            .get_document_id();
+
+                        if !calendar_ids.contains(calendar_id) {
+                            return Err(SetError::invalid_property(
+                                Property::CalendarIds,
+                                format!("calendarId {} does not exist.", JMAPId::from(calendar_id)),
+                            ));
+                        } else if is_set {
+                            self.tag(Property::CalendarIds, Tag::Id(calendar_id));
+                        } else {
+                            self.untag(&Property::CalendarIds, &Tag::Id(calendar_id));
+                        }
+                    }
+                    continue;
+                }
+                (Property::Type, Value::Text { value }) if value == "Event" => continue,
+                (Property::Uid, value @ Value::Text { .. }) => {
+                    if let Some(current_uid) = current_fields.and_then(|f| f.get(&Property::Uid)) {
+                        if current_uid != &value {
+                            return Err(SetError::invalid_property(
+                                property,
+                                "The uid of an event cannot be changed.".to_string(),
+                            ));
+                        }
+                        continue;
+                    }
+                    value
+                }
+                (Property::Start, Value::Text { value }) => {
+                    if parse_local_date_time(&value).is_none() {
+                        return Err(SetError::invalid_property(
+                            property,
+                            "Expected a LocalDateTime.".to_string(),
+                        ));
+                    }
+                    update_times = true;
+                    Value::Text { value }
+                }
+                (Property::Duration, Value::Text { value }) => {
+                    if parse_duration(&value).is_none() {
+                        return Err(SetError::invalid_property(
+                            property,
+                            "Expected a Duration.".to_string(),
+                        ));
+                    }
+                    update_times = true;
+                    Value::Text { value }
+                }
+                (Property::TimeZone | Property::RecurrenceIdTimeZone, Value::Text { value }) => {
+                    if value.parse::<Tz>().is_err() {
+                        return Err(SetError::invalid_property(
+                            property,
+                            format!("Unknown time zone {:?}.", value),
+                        ));
+                    }
+                    update_times = true;
+                    Value::Text { value }
+                }
+                (property, value @ (Value::Text { .. } | Value::Null)) if property.is_text() => {
+                    if property.is_time_related() {
+                        update_times = true;
+                    }
+                    value
+                }
+                (
+                    Property::Sequence | Property::Priority,
+                    value @ (Value::Number { .. } | Value::Null),
+                ) => value,
+                (
+                    Property::Excluded
+                    | Property::UseDefaultAlerts
+                    | Property::ShowWithoutTime
+                    | Property::IsDraft,
+                    value @ (Value::Bool { .. } | Value::Null),
+                ) => value,
+                (Property::Created, value @ (Value::Date { .. } | Value::Null)) => value,
+                (Property::Updated, value @ Value::Date { .. }) => {
+                    set_updated = false;
+                    value
+                }
+                (
+                    Property::Keywords | Property::Categories,
+                    value @ (Value::TextMap { .. } | Value::Null),
+                ) => value,
+                (property, value @ (Value::JSCalendar { .. } | Value::Null))
+                    if property.is_jscalendar() =>
+                {
+                    if property.is_time_related() {
+                        update_times = true;
+                    }
+                    value
+                }
+                (_, _) => {
+                    return Err(SetError::invalid_property(
+                        property,
+                        "Unexpected value.".to_string(),
+                    ));
+                }
+            };
+
+            self.set(property, value);
+        }
+
+        // Events have to belong to at least one calendar
+        if !self.has_tags(&Property::CalendarIds) {
+            return Err(SetError::invalid_property(
+                Property::CalendarIds,
+                "Events have to belong to at least one calendar.",
+            ));
+        }
+
+        // Expand the recurrence rules to obtain the time span of the event
+        if update_times {
+            let get_text = |property: Property| {
+                self.get(&property)
+                    .or_else(|| current_fields.and_then(|f| f.get(&property)))
+                    .and_then(|value| match value {
+                        Value::Text { value } | Value::JSCalendar { value } => Some(value.as_str()),
+                        _ => None,
+                    })
+            };
+
+            if let Some(start) = get_text(Property::Start) {
+                let (utc_start, utc_end) = EventTimes::parse(
+                    start,
+                    get_text(Property::Duration),
+                    get_text(Property::TimeZone),
+                    get_text(Property::RecurrenceRules),
+                    get_text(Property::ExcludedRecurrenceRules),
+                    get_text(Property::RecurrenceOverrides),
+                )
+                .ok_or_else(|| {
+                    SetError::invalid_property(
+                        Property::RecurrenceRules,
+                        "Failed to parse the recurrence of the event.",
+                    )
+                })?
+                .utc_range();
+
+                self.set(
+                    Property::UtcStart,
+                    Value::Timestamp {
+                        value: std::cmp::max(utc_start, 0) as u64,
+                    },
+                );
+                self.set(
+                    Property::UtcEnd,
+                    Value::Timestamp {
+                        value: utc_end.map_or(u64::MAX, |end| std::cmp::max(end, 0) as u64),
+                    },
+                );
+            }
+        }
+
+        let now = JMAPDate::from_timestamp(
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0) as i64,
+        );
+        if current_fields.is_none() {
+            if !self.has_property(&Property::Uid) {
+                self.set(
+                    Property::Uid,
+                    Value::Text {
+                        value: generate_uid(),
+                    },
+                );
+            }
+            if !self.has_property(&Property::Created) {
+                self.set(Property::Created, Value::Date { value: now.clone() });
+            }
+        }
+        if set_updated {
+            self.set(Property::Updated, Value::Date { value: now });
+        }
+
+        Ok(self)
+    }
+}
+
+fn generate_uid() -> String {
+    let mut bytes = rand::thread_rng().gen::<[u8; 16]>();
+
+    // RFC 4122 version 4 UUID
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let mut uid = String::with_capacity(36);
+    for (pos, byte) in bytes.iter().enumerate() {
+        if [4, 6, 8, 10].contains(&pos) {
+            uid.push('-');
+        }
+        uid.push_str(&format!("{:02x}", byte));
+    }
+    uid
+}
@@ -0,0 +1,241 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::fmt::Display;
+
+use jmap::{
+    orm::{self, acl::ACLUpdate},
+    types::jmap::JMAPId,
+};
+use serde::{Deserialize, Serialize};
+use store::{
+    core::{acl::ACL, bitmap::Bitmap, vec_map::VecMap},
+    AccountId, FieldId,
+};
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct AddressBook {
+    pub properties: VecMap<Property, Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Value {
+    Id { value: JMAPId },
+    Text { value: String },
+    Bool { value: bool },
+    Number { value: u32 },
+    Subscriptions { value: Vec<AccountId> },
+    AddressBookRights { value: AddressBookRights },
+    ACLSet(Vec<ACLUpdate>),
+    ACLGet(VecMap<String, Vec<ACL>>),
+    Null,
+}
+
+impl Default for Value {
+    fn default() -> Self {
+        Value::Null
+    }
+}
+
+impl orm::Value for Value {
+    fn index_as(&self) -> orm::Index {
+        match self {
+            Value::Text { value } => value.to_string().into(),
+            Value::Number { value } => (*value).into(),
+            Value::Subscriptions { value } => {
+                if !value.is_empty() {
+                    value.to_vec().into()
+                } else {
+                    orm::Index::Null
+                }
+            }
+            _ => orm::Index::Null,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        match self {
+            Value::Text { value } => value.is_empty(),
+            Value::Null => true,
+            _ => false,
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Value::Id { .. } => std::mem::size_of::<JMAPId>(),
+            Value::Text { value } => value.len(),
+            Value::Bool { .. } => std::mem::size_of::<bool>(),
+            Value::Number { .. } => std::mem::size_of::<u32>(),
+            Value::Subscriptions { value } => value.len() * std::mem::size_of::<u32>(),
+            Value::AddressBookRights { .. } => std::mem::size_of::<AddressBookRights>(),
+            Value::ACLSet(value) => value.len() * std::mem::size_of::<ACLUpdate>(),
+            Value::ACLGet(value) => value.iter().fold(0, |acc, (k, v)| {
+                acc + k.len() + v.len() * std::mem::size_of::<ACL>()
+            }),
+            Value::Null => 0,
+        }
+    }
+}
+
+impl Value {
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            Value::Text { value } => Some(value),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AddressBookRights {
+    #[serde(rename = "mayRead")]
+    may_read: bool,
+
+    #[serde(rename = "mayWrite")]
+    may_write: bool,
+
+    #[serde(rename = "mayShare")]
+    may_share: bool,
+
+    #[serde(rename = "mayDelete")]
+    may_delete: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Copy)]
+#[repr(u8)]
+pub enum Property {
+    Id = 0,
+    Name = 1,
+    Description = 2,
+    SortOrder = 3,
+    IsDefault = 4,
+    IsSubscribed = 5,
+    MyRights = 6,
+    ACL = 7,
+    Invalid = 8,
+}
+
+impl Display for Property {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Property::Id => write!(f, "id"),
+            Property::Name => write!(f, "name"),
+            Property::Description => write!(f, "description"),
+            Property::SortOrder => write!(f, "sortOrder"),
+            Property::IsDefault => write!(f, "isDefault"),
+            Property::IsSubscribed => write!(f, "isSubscribed"),
+            Property::MyRights => write!(f, "myRights"),
+            Property::ACL => write!(f, "acl"),
+            Property::Invalid => Ok(()),
+        }
+    }
+}
+
+impl Property {
+    pub fn parse(value: &str) -> Self {
+        match value {
+            "id" => Property::Id,
+            "name" => Property::Name,
+            "description" => Property::Description,
+            "sortOrder" => Property::SortOrder,
+            "isDefault" => Property::IsDefault,
+            "isSubscribed" => Property::IsSubscribed,
+            "myRights" => Property::MyRights,
+            "acl" => Property::ACL,
+            _ => Property::Invalid,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Filter {
+    Name { value: String },
+    IsDefault { value: bool },
+    IsSubscribed { value: bool },
+    Unsupported { value: String },
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "property")]
+pub enum Comparator {
+    #[serde(rename = "name")]
+    Name,
+    #[serde(rename = "sortOrder")]
+    SortOrder,
+}
+
+impl From<Property> for FieldId {
+    fn from(field: Property) -> Self {
+        field as FieldId
+    }
+}
+
+impl From<FieldId> for Property {
+    fn from(field: FieldId) -> Self {
+        match field {
+            0 => Property::Id,
+            1 => Property::Name,
+            2 => Property::Description,
+            3 => Property::SortOrder,
+            4 => Property::IsDefault,
+            5 => Property::IsSubscribed,
+            6 => Property::MyRights,
+            7 => Property::ACL,
+            _ => Property::Invalid,
+        }
+    }
+}
+
+impl TryFrom<&str> for Property {
+    type Error = ();
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match Property::parse(value) {
+            Property::Invalid => Err(()),
+            property => Ok(property),
+        }
+    }
+}
+
+impl AddressBookRights {
+    pub fn owner() -> Self {
+        AddressBookRights {
+            may_read: true,
+            may_write: true,
+            may_share: true,
+            may_delete: true,
+        }
+    }
+
+    pub fn shared(acl: Bitmap<ACL>) -> Self {
+        AddressBookRights {
+            may_read: acl.contains(ACL::ReadItems),
+            may_write: acl.contains(ACL::AddItems)
+                && acl.contains(ACL::ModifyItems)
+                && acl.contains(ACL::RemoveItems),
+            may_share: acl.contains(ACL::Administer),
+            may_delete: acl.contains(ACL::Delete),
+        }
+    }
+}
@@ -0,0 +1,114 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::sync::Arc;
+
+use store::{
+    core::{acl::ACL, collection::Collection, error::StoreError, tag::Tag},
+    roaring::RoaringBitmap,
+    AccountId, JMAPStore, SharedResource, Store,
+};
+
+use crate::contact_card::schema::Property;
+
+pub trait JMAPShareContacts<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn contacts_shared_books(
+        &self,
+        owner_id: AccountId,
+        shared_to: &[AccountId],
+        acl: ACL,
+    ) -> store::Result<Arc<Option<RoaringBitmap>>>;
+    fn contacts_shared_cards(
+        &self,
+        owner_id: AccountId,
+        shared_to: &[AccountId],
+        acl: ACL,
+    ) -> store::Result<Arc<Option<RoaringBitmap>>>;
+}
+
+impl<T> JMAPShareContacts<T> for JMAPStore<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn contacts_shared_books(
+        &self,
+        owner_id: AccountId,
+        shared_to: &[AccountId],
+        acl: ACL,
+    ) -> store::Result<Arc<Option<RoaringBitmap>>> {
+        self.shared_documents
+            .try_get_with::<_, StoreError>(
+                SharedResource::new(
+                    owner_id,
+                    shared_to.first().copied().unwrap(),
+                    Collection::ContactCard,
+                    acl,
+                ),
+                || {
+                    Ok(Arc::new(self.get_shared_documents(
+                        shared_to,
+                        owner_id,
+                        Collection::AddressBook,
+                        acl.into(),
+                    )?))
+                },
+            )
+            .map_err(|e| e.as_ref().clone())
+    }
+
+    fn contacts_shared_cards(
+        &self,
+        owner_id: AccountId,
+        shared_to: &[AccountId],
+        acl: ACL,
+    ) -> store::Result<Arc<Option<RoaringBitmap>>> {
+        Ok(Arc::new(
+            if let Some(shared_books) = self
+                .contacts_shared_books(owner_id, shared_to, acl)?
+                .as_ref()
+            {
+                let mut shared_cards = RoaringBitmap::new();
+                for address_book_id in shared_books {
+                    if let Some(card_ids) = self.get_tag(
+                        owner_id,
+                        Collection::ContactCard,
+                        Property::AddressBookIds.into(),
+                        Tag::Id(address_book_id),
+                    )? {
+                        shared_cards |= card_ids;
+                    }
+                }
+                if !shared_cards.is_empty() {
+                    shared_cards.into()
+                } else {
+                    None
+                }
+            } else {
+                None
+            },
+        ))
+    }
+}
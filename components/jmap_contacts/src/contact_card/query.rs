@@ -0,0 +1,179 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use super::schema::{Comparator, ContactCard, Filter, Property};
+use crate::address_book::sharing::JMAPShareContacts;
+use jmap::error::method::MethodError;
+use jmap::jmap_store::query::{ExtraFilterFnc, QueryHelper, QueryObject};
+use jmap::request::query::{QueryRequest, QueryResponse};
+use store::core::acl::ACL;
+use store::core::tag::Tag;
+use store::read::comparator::{self, FieldComparator};
+use store::read::default_filter_mapper;
+use store::read::filter::{self, Query};
+use store::Store;
+use store::{AccountId, JMAPStore};
+
+impl QueryObject for ContactCard {
+    type QueryArguments = ();
+
+    type Filter = Filter;
+
+    type Comparator = Comparator;
+}
+
+pub trait JMAPContactCardQuery<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn contact_card_query(&self, request: QueryRequest<ContactCard>)
+        -> jmap::Result<QueryResponse>;
+}
+
+impl<T> JMAPContactCardQuery<T> for JMAPStore<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn contact_card_query(
+        &self,
+        request: QueryRequest<ContactCard>,
+    ) -> jmap::Result<QueryResponse> {
+        let mut helper = QueryHelper::new(
+            self,
+            request,
+            (|account_id: AccountId, member_of: &[AccountId]| {
+                self.contacts_shared_cards(account_id, member_of, ACL::ReadItems)
+            })
+            .into(),
+        )?;
+
+        helper.parse_filter(|filter| {
+            Ok(match filter {
+                Filter::InAddressBook { value } => filter::Filter::eq(
+                    Property::AddressBookIds.into(),
+                    Query::Tag(Tag::Id(value.get_document_id())),
+                ),
+                Filter::InAddressBookOtherThan { value } => filter::Filter::and(
+                    value
+                        .into_iter()
+                        .map(|id| {
+                            filter::Filter::not(vec![filter::Filter::eq(
+                                Property::AddressBookIds.into(),
+                                Query::Tag(Tag::Id(id.get_document_id())),
+                            )])
+                        })
+                        .collect(),
+                ),
+                Filter::Uid { value } => {
+                    filter::Filter::eq(Property::Uid.into(), Query::Keyword(value))
+                }
+                Filter::HasMember { value } => {
+                    filter::Filter::eq(Property::Members.into(), Query::Keyword(value))
+                }
+                Filter::Kind { value } => {
+                    filter::Filter::eq(Property::Kind.into(), Query::Keyword(value))
+                }
+                Filter::CreatedBefore { value } => filter::Filter::lt(
+                    Property::Created.into(),
+                    Query::LongInteger(value.timestamp() as u64),
+                ),
+                Filter::CreatedAfter { value } => filter::Filter::gt(
+                    Property::Created.into(),
+                    Query::LongInteger(value.timestamp() as u64),
+                ),
+                Filter::UpdatedBefore { value } => filter::Filter::lt(
+                    Property::Updated.into(),
+                    Query::LongInteger(value.timestamp() as u64),
+                ),
+                Filter::UpdatedAfter { value } => filter::Filter::gt(
+                    Property::Updated.into(),
+                    Query::LongInteger(value.timestamp() as u64),
+                ),
+                Filter::Text { value } => filter::Filter::or(
+                    [
+                        Property::Name,
+                        Property::Nicknames,
+                        Property::Organizations,
+                        Property::Titles,
+                        Property::Emails,
+                        Property::Phones,
+                        Property::OnlineServices,
+                        Property::Addresses,
+                        Property::Notes,
+                    ]
+                    .into_iter()
+                    .map(|property| {
+                        filter::Filter::eq(property.into(), Query::Tokenize(value.clone()))
+                    })
+                    .collect(),
+                ),
+                Filter::Name { value } => {
+                    filter::Filter::eq(Property::Name.into(), Query::Tokenize(value))
+                }
+                Filter::Nickname { value } => {
+                    filter::Filter::eq(Property::Nicknames.into(), Query::Tokenize(value))
+                }
+                Filter::Organization { value } => filter::Filter::or(vec![
+                    filter::Filter::eq(
+                        Property::Organizations.into(),
+                        Query::Tokenize(value.clone()),
+                    ),
+                    filter::Filter::eq(Property::Titles.into(), Query::Tokenize(value)),
+                ]),
+                Filter::Email { value } => {
+                    filter::Filter::eq(Property::Emails.into(), Query::Tokenize(value))
+                }
+                Filter::Phone { value } => {
+                    filter::Filter::eq(Property::Phones.into(), Query::Tokenize(value))
+                }
+                Filter::OnlineService { value } => {
+                    filter::Filter::eq(Property::OnlineServices.into(), Query::Tokenize(value))
+                }
+                Filter::Address { value } => {
+                    filter::Filter::eq(Property::Addresses.into(), Query::Tokenize(value))
+                }
+                Filter::Note { value } => {
+                    filter::Filter::eq(Property::Notes.into(), Query::Tokenize(value))
+                }
+                Filter::Unsupported { value } => {
+                    return Err(MethodError::UnsupportedFilter(value));
+                }
+            })
+        })?;
+
+        helper.parse_comparator(|comparator| {
+            Ok(comparator::Comparator::Field(FieldComparator {
+                field: {
+                    match comparator.property {
+                        Comparator::Created => Property::Created,
+                        Comparator::Updated => Property::Updated,
+                    }
+                }
+                .into(),
+                ascending: comparator.is_ascending,
+            }))
+        })?;
+
+        helper.query(default_filter_mapper, None::<ExtraFilterFnc>)
+    }
+}
@@ -0,0 +1,403 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::fmt::Display;
+
+use jmap::{
+    orm,
+    request::MaybeIdReference,
+    types::{date::JMAPDate, jmap::JMAPId},
+};
+use serde::{Deserialize, Serialize};
+use store::{core::vec_map::VecMap, FieldId};
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ContactCard {
+    pub properties: VecMap<Property, Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Value {
+    Id {
+        value: JMAPId,
+    },
+    Text {
+        value: String,
+    },
+    Date {
+        value: JMAPDate,
+    },
+    AddressBookIds {
+        value: VecMap<MaybeIdReference, bool>,
+        set: bool,
+    },
+    TextMap {
+        value: VecMap<String, bool>,
+    },
+    JSContact {
+        value: String,
+    },
+    Null,
+}
+
+impl Default for Value {
+    fn default() -> Self {
+        Value::Null
+    }
+}
+
+impl orm::Value for Value {
+    fn index_as(&self) -> orm::Index {
+        match self {
+            Value::Text { value } => value.to_string().into(),
+            Value::Date { value } => (value.timestamp() as u64).into(),
+            Value::TextMap { value } => {
+                if !value.is_empty() {
+                    value
+                        .iter()
+                        .filter(|(_, v)| **v)
+                        .map(|(k, _)| k.to_string())
+                        .collect::<Vec<_>>()
+                        .into()
+                } else {
+                    orm::Index::Null
+                }
+            }
+            Value::JSContact { value } => {
+                // Index all the string values contained in the JSContact object
+                let mut text = String::new();
+                if let Ok(value) = serde_json::from_str::<serde_json::Value>(value) {
+                    collect_text(&value, &mut text);
+                }
+                if !text.is_empty() {
+                    text.into()
+                } else {
+                    orm::Index::Null
+                }
+            }
+            _ => orm::Index::Null,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        match self {
+            Value::Text { value } => value.is_empty(),
+            Value::TextMap { value } => value.is_empty(),
+            Value::JSContact { value } => value.is_empty(),
+            Value::Null => true,
+            _ => false,
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Value::Id { .. } => std::mem::size_of::<JMAPId>(),
+            Value::Text { value } => value.len(),
+            Value::Date { .. } => std::mem::size_of::<JMAPDate>(),
+            Value::AddressBookIds { value, .. } => {
+                value.len() * std::mem::size_of::<MaybeIdReference>()
+            }
+            Value::TextMap { value } => value.keys().fold(0, |acc, k| acc + k.len()),
+            Value::JSContact { value } => value.len(),
+            Value::Null => 0,
+        }
+    }
+}
+
+fn collect_text(value: &serde_json::Value, text: &mut String) {
+    match value {
+        serde_json::Value::String(value) => {
+            if !text.is_empty() {
+                text.push(' ');
+            }
+            text.push_str(value);
+        }
+        serde_json::Value::Array(list) => {
+            for value in list {
+                collect_text(value, text);
+            }
+        }
+        serde_json::Value::Object(map) => {
+            for (key, value) in map {
+                if key != "@type" {
+                    collect_text(value, text);
+                }
+            }
+        }
+        _ => (),
+    }
+}
+
+impl Value {
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            Value::Text { value } => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn get_address_book_ids(&mut self) -> Option<&mut VecMap<MaybeIdReference, bool>> {
+        match self {
+            Value::AddressBookIds { value, .. } => Some(value),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Copy)]
+#[repr(u8)]
+pub enum Property {
+    Id = 0,
+    AddressBookIds = 1,
+    Type = 2,
+    Version = 3,
+    Uid = 4,
+    Kind = 5,
+    Created = 6,
+    Updated = 7,
+    Language = 8,
+    ProdId = 9,
+    Members = 10,
+    RelatedTo = 11,
+    Name = 12,
+    Nicknames = 13,
+    Organizations = 14,
+    SpeakToAs = 15,
+    Titles = 16,
+    Emails = 17,
+    OnlineServices = 18,
+    Phones = 19,
+    PreferredLanguages = 20,
+    Calendars = 21,
+    SchedulingAddresses = 22,
+    Addresses = 23,
+    CryptoKeys = 24,
+    Directories = 25,
+    Links = 26,
+    Media = 27,
+    Localizations = 28,
+    Anniversaries = 29,
+    Keywords = 30,
+    Notes = 31,
+    PersonalInfo = 32,
+    Invalid = 33,
+}
+
+impl Display for Property {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Property::Id => write!(f, "id"),
+            Property::AddressBookIds => write!(f, "addressBookIds"),
+            Property::Type => write!(f, "@type"),
+            Property::Version => write!(f, "version"),
+            Property::Uid => write!(f, "uid"),
+            Property::Kind => write!(f, "kind"),
+            Property::Created => write!(f, "created"),
+            Property::Updated => write!(f, "updated"),
+            Property::Language => write!(f, "language"),
+            Property::ProdId => write!(f, "prodId"),
+            Property::Members => write!(f, "members"),
+            Property::RelatedTo => write!(f, "relatedTo"),
+            Property::Name => write!(f, "name"),
+            Property::Nicknames => write!(f, "nicknames"),
+            Property::Organizations => write!(f, "organizations"),
+            Property::SpeakToAs => write!(f, "speakToAs"),
+            Property::Titles => write!(f, "titles"),
+            Property::Emails => write!(f, "emails"),
+            Property::OnlineServices => write!(f, "onlineServices"),
+            Property::Phones => write!(f, "phones"),
+            Property::PreferredLanguages => write!(f, "preferredLanguages"),
+            Property::Calendars => write!(f, "calendars"),
+            Property::SchedulingAddresses => write!(f, "schedulingAddresses"),
+            Property::Addresses => write!(f, "addresses"),
+            Property::CryptoKeys => write!(f, "cryptoKeys"),
+            Property::Directories => write!(f, "directories"),
+            Property::Links => write!(f, "links"),
+            Property::Media => write!(f, "media"),
+            Property::Localizations => write!(f, "localizations"),
+            Property::Anniversaries => write!(f, "anniversaries"),
+            Property::Keywords => write!(f, "keywords"),
+            Property::Notes => write!(f, "notes"),
+            Property::PersonalInfo => write!(f, "personalInfo"),
+            Property::Invalid => Ok(()),
+        }
+    }
+}
+
+impl Property {
+    pub fn parse(value: &str) -> Self {
+        match value {
+            "id" => Property::Id,
+            "addressBookIds" => Property::AddressBookIds,
+            "@type" => Property::Type,
+            "version" => Property::Version,
+            "uid" => Property::Uid,
+            "kind" => Property::Kind,
+            "created" => Property::Created,
+            "updated" => Property::Updated,
+            "language" => Property::Language,
+            "prodId" => Property::ProdId,
+            "members" => Property::Members,
+            "relatedTo" => Property::RelatedTo,
+            "name" => Property::Name,
+            "nicknames" => Property::Nicknames,
+            "organizations" => Property::Organizations,
+            "speakToAs" => Property::SpeakToAs,
+            "titles" => Property::Titles,
+            "emails" => Property::Emails,
+            "onlineServices" => Property::OnlineServices,
+            "phones" => Property::Phones,
+            "preferredLanguages" => Property::PreferredLanguages,
+            "calendars" => Property::Calendars,
+            "schedulingAddresses" => Property::SchedulingAddresses,
+            "addresses" => Property::Addresses,
+            "cryptoKeys" => Property::CryptoKeys,
+            "directories" => Property::Directories,
+            "links" => Property::Links,
+            "media" => Property::Media,
+            "localizations" => Property::Localizations,
+            "anniversaries" => Property::Anniversaries,
+            "keywords" => Property::Keywords,
+            "notes" => Property::Notes,
+            "personalInfo" => Property::PersonalInfo,
+            _ => Property::Invalid,
+        }
+    }
+
+    pub fn is_jscontact(&self) -> bool {
+        matches!(
+            self,
+            Property::RelatedTo
+                | Property::Name
+                | Property::Nicknames
+                | Property::Organizations
+                | Property::SpeakToAs
+                | Property::Titles
+                | Property::Emails
+                | Property::OnlineServices
+                | Property::Phones
+                | Property::PreferredLanguages
+                | Property::Calendars
+                | Property::SchedulingAddresses
+                | Property::Addresses
+                | Property::CryptoKeys
+                | Property::Directories
+                | Property::Links
+                | Property::Media
+                | Property::Localizations
+                | Property::Anniversaries
+                | Property::Notes
+                | Property::PersonalInfo
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Filter {
+    InAddressBook { value: JMAPId },
+    InAddressBookOtherThan { value: Vec<JMAPId> },
+    Uid { value: String },
+    HasMember { value: String },
+    Kind { value: String },
+    CreatedBefore { value: JMAPDate },
+    CreatedAfter { value: JMAPDate },
+    UpdatedBefore { value: JMAPDate },
+    UpdatedAfter { value: JMAPDate },
+    Text { value: String },
+    Name { value: String },
+    Nickname { value: String },
+    Organization { value: String },
+    Email { value: String },
+    Phone { value: String },
+    OnlineService { value: String },
+    Address { value: String },
+    Note { value: String },
+    Unsupported { value: String },
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "property")]
+pub enum Comparator {
+    #[serde(rename = "created")]
+    Created,
+    #[serde(rename = "updated")]
+    Updated,
+}
+
+impl From<Property> for FieldId {
+    fn from(field: Property) -> Self {
+        field as FieldId
+    }
+}
+
+impl From<FieldId> for Property {
+    fn from(field: FieldId) -> Self {
+        match field {
+            0 => Property::Id,
+            1 => Property::AddressBookIds,
+            2 => Property::Type,
+            3 => Property::Version,
+            4 => Property::Uid,
+            5 => Property::Kind,
+            6 => Property::Created,
+            7 => Property::Updated,
+            8 => Property::Language,
+            9 => Property::ProdId,
+            10 => Property::Members,
+            11 => Property::RelatedTo,
+            12 => Property::Name,
+            13 => Property::Nicknames,
+            14 => Property::Organizations,
+            15 => Property::SpeakToAs,
+            16 => Property::Titles,
+            17 => Property::Emails,
+            18 => Property::OnlineServices,
+            19 => Property::Phones,
+            20 => Property::PreferredLanguages,
+            21 => Property::Calendars,
+            22 => Property::SchedulingAddresses,
+            23 => Property::Addresses,
+            24 => Property::CryptoKeys,
+            25 => Property::Directories,
+            26 => Property::Links,
+            27 => Property::Media,
+            28 => Property::Localizations,
+            29 => Property::Anniversaries,
+            30 => Property::Keywords,
+            31 => Property::Notes,
+            32 => Property::PersonalInfo,
+            _ => Property::Invalid,
+        }
+    }
+}
+
+impl TryFrom<&str> for Property {
+    type Error = ();
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match Property::parse(value) {
+            Property::Invalid => Err(()),
+            property => Ok(property),
+        }
+    }
+}
@@ -0,0 +1,409 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::SystemTime;
+
+use super::schema::{ContactCard, Property, Value};
+use crate::address_book::sharing::JMAPShareContacts;
+use jmap::error::set::{SetError, SetErrorType};
+use jmap::jmap_store::set::{SetHelper, SetObject};
+use jmap::jmap_store::Object;
+use jmap::orm::{serialize::JMAPOrm, TinyORM};
+use jmap::request::set::{SetRequest, SetResponse};
+use jmap::request::{ACLEnforce, MaybeIdReference, ResultReference};
+use jmap::types::date::JMAPDate;
+use jmap::types::jmap::JMAPId;
+use store::core::acl::ACL;
+use store::core::collection::Collection;
+use store::core::document::Document;
+use store::core::error::StoreError;
+use store::core::tag::Tag;
+use store::core::vec_map::VecMap;
+use store::rand::{self, Rng};
+use store::roaring::RoaringBitmap;
+use store::{AccountId, JMAPStore};
+use store::{SharedBitmap, Store};
+
+impl SetObject for ContactCard {
+    type SetArguments = ();
+
+    type NextCall = ();
+
+    fn eval_id_references(&mut self, mut fnc: impl FnMut(&str) -> Option<JMAPId>) {
+        if let Some(Value::AddressBookIds { value, .. }) =
+            self.properties.get_mut(&Property::AddressBookIds)
+        {
+            if value
+                .keys()
+                .any(|k| matches!(k, MaybeIdReference::Reference(_)))
+            {
+                let mut new_values = VecMap::with_capacity(value.len());
+
+                for (id, value) in std::mem::take(value).into_iter() {
+                    if let MaybeIdReference::Reference(id) = &id {
+                        if let Some(id) = fnc(id) {
+                            new_values.append(MaybeIdReference::Value(id), value);
+                            continue;
+                        }
+                    }
+                    new_values.append(id, value);
+                }
+
+                *value = new_values;
+            }
+        }
+    }
+
+    fn eval_result_references(&mut self, _fnc: impl FnMut(&ResultReference) -> Option<Vec<u64>>) {}
+}
+
+pub trait JMAPSetContactCard<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn contact_card_set(
+        &self,
+        request: SetRequest<ContactCard>,
+    ) -> jmap::Result<SetResponse<ContactCard>>;
+    fn contact_card_delete(
+        &self,
+        account_id: AccountId,
+        document: &mut Document,
+    ) -> store::Result<()>;
+}
+
+impl<T> JMAPSetContactCard<T> for JMAPStore<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn contact_card_set(
+        &self,
+        request: SetRequest<ContactCard>,
+    ) -> jmap::Result<SetResponse<ContactCard>> {
+        let mut helper = SetHelper::new(self, request)?;
+        let address_book_ids = self
+            .get_document_ids(helper.account_id, Collection::AddressBook)?
+            .unwrap_or_default();
+
+        helper.create(|_create_id, item, helper, document| {
+            let fields = TinyORM::<ContactCard>::new().contact_card_set(
+                helper,
+                item,
+                &address_book_ids,
+                None,
+            )?;
+
+            // Check ACLs
+            if helper.acl.is_shared(helper.account_id) {
+                let allowed_books = helper.store.contacts_shared_books(
+                    helper.account_id,
+                    &helper.acl.member_of,
+                    ACL::AddItems,
+                )?;
+                for address_book in fields.get_tags(&Property::AddressBookIds).unwrap() {
+                    let address_book_id = address_book.as_id();
+                    if !allowed_books.has_access(address_book_id) {
+                        return Err(SetError::forbidden(format!(
+                            "You are not allowed to add contacts to address book {}.",
+                            JMAPId::from(address_book_id)
+                        )));
+                    }
+                }
+            }
+
+            // Return the server-set properties
+            let mut contact_card = ContactCard::new(document.document_id.into());
+            for property in [Property::Uid, Property::Created, Property::Updated] {
+                if let Some(value) = fields.get(&property) {
+                    contact_card.properties.append(property, value.clone());
+                }
+            }
+
+            fields.insert_validate(document)?;
+
+            Ok(contact_card)
+        })?;
+
+        helper.update(|id, item, helper, document| {
+            let document_id = id.get_document_id();
+            let current_fields = self
+                .get_orm::<ContactCard>(helper.account_id, document_id)?
+                .ok_or_else(|| SetError::new_err(SetErrorType::NotFound))?;
+            let modifies_card = item
+                .properties
+                .keys()
+                .any(|property| property != &Property::AddressBookIds);
+
+            let fields = TinyORM::track_changes(&current_fields).contact_card_set(
+                helper,
+                item,
+                &address_book_ids,
+                Some(&current_fields),
+            )?;
+
+            // Check ACLs
+            if helper.acl.is_shared(helper.account_id) {
+                for (acl, address_books, message) in [
+                    (
+                        ACL::AddItems,
+                        current_fields.get_added_tags(&fields, &Property::AddressBookIds),
+                        "You are not allowed to add contacts to address book",
+                    ),
+                    (
+                        ACL::RemoveItems,
+                        current_fields.get_removed_tags(&fields, &Property::AddressBookIds),
+                        "You are not allowed to remove contacts from address book",
+                    ),
+                ] {
+                    if !address_books.is_empty() {
+                        let allowed_books = helper.store.contacts_shared_books(
+                            helper.account_id,
+                            &helper.acl.member_of,
+                            acl,
+                        )?;
+                        for address_book in address_books {
+                            let address_book_id = address_book.as_id();
+                            if !allowed_books.has_access(address_book_id) {
+                                return Err(SetError::forbidden(format!(
+                                    "{} {}.",
+                                    message,
+                                    JMAPId::from(address_book_id)
+                                )));
+                            }
+                        }
+                    }
+                }
+
+                if modifies_card
+                    && !helper
+                        .store
+                        .contacts_shared_cards(
+                            helper.account_id,
+                            &helper.acl.member_of,
+                            ACL::ModifyItems,
+                        )?
+                        .has_access(document_id)
+                {
+                    return Err(SetError::forbidden(
+                        "You are not allowed to modify this contact.",
+                    ));
+                }
+            }
+
+            // Merge changes
+            current_fields.merge_validate(document, fields)?;
+
+            Ok(None)
+        })?;
+
+        helper.destroy(|_id, helper, document| {
+            // Check ACLs
+            if helper.acl.is_shared(helper.account_id)
+                && !helper
+                    .store
+                    .contacts_shared_cards(
+                        helper.account_id,
+                        &helper.acl.member_of,
+                        ACL::RemoveItems,
+                    )?
+                    .has_access(document.document_id)
+            {
+                return Err(SetError::forbidden(
+                    "You are not allowed to delete this contact.",
+                ));
+            }
+
+            self.contact_card_delete(helper.account_id, document)?;
+            Ok(())
+        })?;
+
+        helper.into_response()
+    }
+
+    fn contact_card_delete(
+        &self,
+        account_id: AccountId,
+        document: &mut Document,
+    ) -> store::Result<()> {
+        // Delete ORM
+        self.get_orm::<ContactCard>(account_id, document.document_id)?
+            .ok_or_else(|| {
+                StoreError::NotFound(format!(
+                    "Failed to fetch ContactCard ORM for {}:{}.",
+                    account_id, document.document_id
+                ))
+            })?
+            .delete(document);
+
+        Ok(())
+    }
+}
+
+trait ContactCardSet<T>: Sized
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn contact_card_set(
+        self,
+        helper: &mut SetHelper<ContactCard, T>,
+        contact_card: ContactCard,
+        address_book_ids: &RoaringBitmap,
+        fields: Option<&TinyORM<ContactCard>>,
+    ) -> jmap::error::set::Result<Self, Property>;
+}
+
+impl<T> ContactCardSet<T> for TinyORM<ContactCard>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn contact_card_set(
+        mut self,
+        helper: &mut SetHelper<ContactCard, T>,
+        contact_card: ContactCard,
+        address_book_ids: &RoaringBitmap,
+        current_fields: Option<&TinyORM<ContactCard>>,
+    ) -> jmap::error::set::Result<Self, Property> {
+        let mut set_updated = true;
+
+        for (property, value) in contact_card.properties {
+            let value = match (property, value) {
+                (Property::AddressBookIds, Value::AddressBookIds { value, set }) => {
+                    if set {
+                        self.untag_all(&Property::AddressBookIds);
+                    }
+
+                    for (address_book_id, is_set) in value {
+                        let address_book_id = helper
+                            .unwrap_id_reference(Property::AddressBookIds, &address_book_id)?
+                            .get_document_id();
+
+                        if !address_book_ids.contains(address_book_id) {
+                            return Err(SetError::invalid_property(
+                                Property::AddressBookIds,
+                                format!(
+                                    "addressBookId {} does not exist.",
+                                    JMAPId::from(address_book_id)
+                                ),
+                            ));
+                        } else if is_set {
+                            self.tag(Property::AddressBookIds, Tag::Id(address_book_id));
+                        } else {
+                            self.untag(&Property::AddressBookIds, &Tag::Id(address_book_id));
+                        }
+                    }
+                    continue;
+                }
+                (Property::Type, Value::Text { value }) if value == "Card" => continue,
+                (Property::Uid, value @ Value::Text { .. }) => {
+                    if let Some(current_uid) = current_fields.and_then(|f| f.get(&Property::Uid)) {
+                        if current_uid != &value {
+                            return Err(SetError::invalid_property(
+                                property,
+                                "The uid of a contact cannot be changed.".to_string(),
+                            ));
+                        }
+                        continue;
+                    }
+                    value
+                }
+                (
+                    Property::Version | Property::Kind | Property::Language | Property::ProdId,
+                    value @ (Value::Text { .. } | Value::Null),
+                ) => value,
+                (Property::Created, value @ (Value::Date { .. } | Value::Null)) => value,
+                (Property::Updated, value @ Value::Date { .. }) => {
+                    set_updated = false;
+                    value
+                }
+                (
+                    Property::Members | Property::Keywords,
+                    value @ (Value::TextMap { .. } | Value::Null),
+                ) => value,
+                (property, value @ (Value::JSContact { .. } | Value::Null))
+                    if property.is_jscontact() =>
+                {
+                    value
+                }
+                (_, _) => {
+                    return Err(SetError::invalid_property(
+                        property,
+                        "Unexpected value.".to_string(),
+                    ));
+                }
+            };
+
+            self.set(property, value);
+        }
+
+        // Contact cards have to belong to at least one address book
+        if !self.has_tags(&Property::AddressBookIds) {
+            return Err(SetError::invalid_property(
+                Property::AddressBookIds,
+                "Contact cards have to belong to at least one address book.",
+            ));
+        }
+
+        let now = JMAPDate::from_timestamp(
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0) as i64,
+        );
+        if current_fields.is_none() {
+            if !self.has_property(&Property::Uid) {
+                self.set(
+                    Property::Uid,
+                    Value::Text {
+                        value: generate_uid(),
+                    },
+                );
+            }
+            if !self.has_property(&Property::Created) {
+                self.set(Property::Created, Value::Date { value: now.clone() });
+            }
+        }
+        if set_updated {
+            self.set(Property::Updated, Value::Date { value: now });
+        }
+
+        Ok(self)
+    }
+}
+
+fn generate_uid() -> String {
+    let mut bytes = rand::thread_rng().gen::<[u8; 16]>();
+
+    // RFC 4122 version 4 UUID
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let mut uid = String::with_capacity(45);
+    uid.push_str("urn:uuid:");
+    for (pos, byte) in bytes.iter().enumerate() {
+        if [4, 6, 8, 10].contains(&pos) {
+            uid.push('-');
+        }
+        uid.push_str(&format!("{:02x}", byte));
+    }
+    uid
+}
@@ -0,0 +1,124 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::fmt::Write;
+
+use mail_builder::{
+    headers::{address::Address, content_type::ContentType},
+    mime::MimePart,
+    MessageBuilder,
+};
+
+pub struct DeliveryFailure {
+    pub recipient: String,
+    pub reply: String,
+}
+
+impl DeliveryFailure {
+    pub fn new(recipient: String, reply: String) -> Self {
+        DeliveryFailure { recipient, reply }
+    }
+
+    // Use the enhanced status code (RFC 3463) included in the reply, if any.
+    pub fn status(&self) -> &str {
+        match self.reply.split(' ').nth(1) {
+            Some(code) if is_enhanced_status_code(code) => code,
+            _ if self.reply.starts_with('5') => "5.0.0",
+            // Temporary failure that exceeded the maximum queue time
+            _ => "4.4.7",
+        }
+    }
+}
+
+fn is_enhanced_status_code(code: &str) -> bool {
+    let parts = code.split('.').collect::<Vec<_>>();
+    parts.len() == 3
+        && matches!(parts[0], "4" | "5")
+        && parts[1..]
+            .iter()
+            .all(|p| (1..=3).contains(&p.len()) && p.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// Builds an RFC 3464 delivery status notification addressed to the
+/// sender of the message.
+pub fn build_dsn(
+    reporting_mta: &str,
+    sender: &str,
+    failures: &[DeliveryFailure],
+    message: &[u8],
+) -> Vec<u8> {
+    // Human readable part
+    let mut text = String::from(concat!(
+        "This is an automatically generated Delivery Status Notification.\r\n\r\n",
+        "Delivery to the following recipients failed permanently:\r\n\r\n"
+    ));
+    for failure in failures {
+        let _ = write!(
+            text,
+            "    {}\r\n        {}\r\n\r\n",
+            failure.recipient, failure.reply
+        );
+    }
+
+    // Machine readable part
+    let mut status = format!("Reporting-MTA: dns; {}\r\n", reporting_mta);
+    for failure in failures {
+        let _ = write!(
+            status,
+            concat!(
+                "\r\nFinal-Recipient: rfc822; {}\r\n",
+                "Action: failed\r\n",
+                "Status: {}\r\n",
+                "Diagnostic-Code: smtp; {}\r\n"
+            ),
+            failure.recipient,
+            failure.status(),
+            failure.reply
+        );
+    }
+
+    // Headers of the original message
+    let headers = message
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .map(|pos| &message[..pos + 2])
+        .unwrap_or(message);
+
+    MessageBuilder::new()
+        .from(Address::from((
+            "Mail Delivery Subsystem",
+            format!("MAILER-DAEMON@{}", reporting_mta),
+        )))
+        .to(sender)
+        .subject("Undelivered Mail Returned to Sender")
+        .body(MimePart::new_multipart(
+            ContentType::new("multipart/report").attribute("report-type", "delivery-status"),
+            vec![
+                MimePart::new_text(text),
+                MimePart::new_binary("message/delivery-status", status.into_bytes()),
+                MimePart::new_binary("text/rfc822-headers", headers),
+            ],
+        ))
+        .write_to_vec()
+        .unwrap_or_default()
+}
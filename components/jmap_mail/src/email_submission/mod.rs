@@ -22,8 +22,10 @@
 */
 
 pub mod changes;
+pub mod dsn;
 pub mod get;
 pub mod query;
+pub mod queue;
 pub mod raft;
 pub mod schema;
 pub mod serialize;
@@ -64,6 +66,7 @@ impl Object for EmailSubmission {
             (Property::IdentityId, <u64 as Options>::F_INDEX),
             (Property::ThreadId, <u64 as Options>::F_INDEX),
             (Property::SendAt, <u64 as Options>::F_INDEX),
+            (Property::DeliveryQueue, <u64 as Options>::F_INDEX),
         ]
     }
 
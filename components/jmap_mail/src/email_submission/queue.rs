@@ -0,0 +1,121 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap::{orm::serialize::JMAPOrm, SUPERUSER_ID};
+use store::{
+    core::{collection::Collection, JMAPIdPrefix},
+    read::{
+        comparator::Comparator,
+        filter::{Filter, Query},
+        FilterMapper,
+    },
+    AccountId, DocumentId, JMAPStore, Store,
+};
+
+use super::schema::{EmailSubmission, Property, UndoStatus, Value};
+
+pub trait JMAPEmailSubmissionQueue<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn email_submission_send_at(
+        &self,
+        account_id: AccountId,
+        document_id: DocumentId,
+    ) -> store::Result<Option<i64>>;
+
+    fn email_submission_pending(&self) -> store::Result<Vec<(AccountId, DocumentId, i64)>>;
+}
+
+impl<T> JMAPEmailSubmissionQueue<T> for JMAPStore<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn email_submission_send_at(
+        &self,
+        account_id: AccountId,
+        document_id: DocumentId,
+    ) -> store::Result<Option<i64>> {
+        let email_submission = if let Some(email_submission) =
+            self.get_orm::<EmailSubmission>(account_id, document_id)?
+        {
+            email_submission
+        } else {
+            return Ok(None);
+        };
+
+        // Canceled submissions are never sent
+        let undo_status = match email_submission.get(&Property::UndoStatus) {
+            Some(Value::UndoStatus { value }) => value,
+            _ => return Ok(None),
+        };
+        if undo_status == &UndoStatus::Canceled {
+            return Ok(None);
+        }
+
+        // Recipients waiting for a retry are sent on their next attempt
+        if let Some(Value::DeliveryQueue { value }) = email_submission.get(&Property::DeliveryQueue)
+        {
+            return Ok(value.next_attempt());
+        }
+
+        // Otherwise, only pending submissions can be sent
+        if undo_status != &UndoStatus::Pending {
+            return Ok(None);
+        }
+
+        Ok(Some(
+            if let Some(Value::DateTime { value }) = email_submission.get(&Property::SendAt) {
+                value.timestamp()
+            } else {
+                0
+            },
+        ))
+    }
+
+    fn email_submission_pending(&self) -> store::Result<Vec<(AccountId, DocumentId, i64)>> {
+        let mut pending = Vec::new();
+
+        for account_id in self
+            .get_document_ids(SUPERUSER_ID, Collection::Principal)?
+            .unwrap_or_default()
+        {
+            for jmap_id in self.query_store::<FilterMapper>(
+                account_id,
+                Collection::EmailSubmission,
+                Filter::or(vec![
+                    Filter::eq(Property::UndoStatus.into(), Query::Keyword("p".to_string())),
+                    Filter::gt(Property::DeliveryQueue.into(), Query::LongInteger(0)),
+                ]),
+                Comparator::None,
+            )? {
+                let document_id = jmap_id.get_document_id();
+                if let Some(send_at) = self.email_submission_send_at(account_id, document_id)? {
+                    pending.push((account_id, document_id, send_at));
+                }
+            }
+        }
+
+        Ok(pending)
+    }
+}
@@ -66,6 +66,9 @@ pub enum Value {
         value: ResultReference,
     },
     Null,
+    DeliveryQueue {
+        value: DeliveryQueue,
+    },
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -143,6 +146,31 @@ impl DeliveryStatus {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeliveryQueue {
+    pub created: i64,
+    pub recipients: AHashMap<String, QueuedRecipient>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueuedRecipient {
+    pub attempts: u32,
+    pub next_attempt: i64,
+}
+
+impl DeliveryQueue {
+    pub fn new(created: i64) -> Self {
+        DeliveryQueue {
+            created,
+            recipients: AHashMap::new(),
+        }
+    }
+
+    pub fn next_attempt(&self) -> Option<i64> {
+        self.recipients.values().map(|r| r.next_attempt).min()
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Delivered {
     #[serde(rename = "queued")]
@@ -176,7 +204,8 @@ pub enum Property {
     DeliveryStatus = 7,
     DsnBlobIds = 8,
     MdnBlobIds = 9,
-    Invalid = 10,
+    DeliveryQueue = 10,
+    Invalid = 11,
 }
 
 impl Property {
@@ -210,7 +239,7 @@ impl Display for Property {
             Property::DeliveryStatus => write!(f, "deliveryStatus"),
             Property::DsnBlobIds => write!(f, "dsnBlobIds"),
             Property::MdnBlobIds => write!(f, "mdnBlobIds"),
-            Property::Invalid => Ok(()),
+            Property::DeliveryQueue | Property::Invalid => Ok(()),
         }
     }
 }
@@ -234,6 +263,7 @@ impl From<FieldId> for Property {
             7 => Property::DeliveryStatus,
             8 => Property::DsnBlobIds,
             9 => Property::MdnBlobIds,
+            10 => Property::DeliveryQueue,
             _ => Property::Invalid,
         }
     }
@@ -282,6 +312,10 @@ impl orm::Value for Value {
                 UndoStatus::Final => "f".to_string().into(),
                 UndoStatus::Canceled => "c".to_string().into(),
             },
+            Value::DeliveryQueue { value } => value
+                .next_attempt()
+                .map(|next_attempt| (next_attempt as u64).into())
+                .unwrap_or(orm::Index::Null),
             _ => orm::Index::Null,
         }
     }
@@ -308,6 +342,9 @@ impl orm::Value for Value {
             Value::IdReference { value } => value.len(),
             Value::ResultReference { .. } => std::mem::size_of::<ResultReference>(),
             Value::Null => 0,
+            Value::DeliveryQueue { value } => value.recipients.keys().fold(0, |acc, x| {
+                acc + x.len() + std::mem::size_of::<QueuedRecipient>()
+            }),
         }
     }
 }
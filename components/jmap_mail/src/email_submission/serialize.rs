@@ -79,7 +79,9 @@ impl Serialize for EmailSubmission {
             match value {
                 Value::Id { value } => map.serialize_entry(name, value)?,
                 Value::Text { value } => map.serialize_entry(name, value)?,
-                Value::Null => map.serialize_entry(name, &None::<&str>)?,
+                Value::Null | Value::DeliveryQueue { .. } => {
+                    map.serialize_entry(name, &None::<&str>)?
+                }
                 Value::ResultReference { value } => map.serialize_entry(name, value)?,
                 Value::IdReference { value } => {
                     map.serialize_entry(name, &format!("#{}", value))?
@@ -21,7 +21,7 @@
  * for more details.
 */
 
-use super::schema::{Address, EmailSubmission, Envelope, Property, Value};
+use super::schema::{Address, EmailSubmission, Envelope, Property, UndoStatus, Value};
 use crate::identity;
 use crate::identity::schema::Identity;
 use crate::mail::schema::Email;
@@ -235,6 +235,16 @@ where
                 ));
             }
 
+            // New submissions are pending until they are sent
+            if fields.get(&Property::UndoStatus).is_none() {
+                fields.set(
+                    Property::UndoStatus,
+                    Value::UndoStatus {
+                        value: UndoStatus::Pending,
+                    },
+                );
+            }
+
             // Set the sentAt property
             fields.set(
                 Property::SendAt,
@@ -364,10 +374,33 @@ where
                 let current_fields = self
                     .get_orm::<EmailSubmission>(helper.account_id, id.get_document_id())?
                     .ok_or_else(|| SetError::new_err(SetErrorType::NotFound))?;
-                let mut fields = TinyORM::track_changes(&current_fields);
 
+                // Only pending submissions can be canceled
+                if value != UndoStatus::Canceled {
+                    return Err(SetError::invalid_property(
+                        Property::UndoStatus,
+                        "undoStatus can only be set to canceled.",
+                    ));
+                } else if !matches!(
+                    current_fields.get(&Property::UndoStatus),
+                    Some(Value::UndoStatus {
+                        value: UndoStatus::Pending
+                    })
+                ) {
+                    return Err(SetError::new(
+                        SetErrorType::CannotUnsend,
+                        "This submission can no longer be canceled.",
+                    ));
+                }
+
+                let mut fields = TinyORM::track_changes(&current_fields);
                 fields.set(Property::UndoStatus, Value::UndoStatus { value });
 
+                // Remove any pending retries from the delivery queue
+                if current_fields.has_property(&Property::DeliveryQueue) {
+                    fields.set(Property::DeliveryQueue, Value::Null);
+                }
+
                 // Merge changes
                 current_fields.merge_validate(document, fields)?;
             }
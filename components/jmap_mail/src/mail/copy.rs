@@ -31,6 +31,7 @@ use jmap::{
     error::set::{SetError, SetErrorType},
     jmap_store::copy::CopyHelper,
     orm::TinyORM,
+    principal::store::JMAPPrincipals,
     request::{
         copy::{CopyRequest, CopyResponse},
         set::SetRequest,
@@ -223,6 +224,14 @@ where
                 ))
             })?;
 
+            // Check quota
+            if !self.principal_has_quota(helper.account_id, message_data.size)? {
+                return Err(SetError::new(
+                    SetErrorType::OverQuota,
+                    "You have exceeded your storage quota.",
+                ));
+            }
+
             // Set receivedAt
             if let Some(received_at) = received_at {
                 // Serialize message data and outline
@@ -250,6 +259,21 @@ where
             );
             document.blob(metadata_blob_id, IndexOptions::new());
 
+            // Link calendar invitation
+            if let Some(invitation_blob_id) = self.get_document_value::<BlobId>(
+                helper.from_account_id,
+                Collection::Mail,
+                document_id,
+                MessageField::Invitation.into(),
+            )? {
+                document.binary(
+                    MessageField::Invitation,
+                    invitation_blob_id.serialize().unwrap(),
+                    IndexOptions::new(),
+                );
+                document.blob(invitation_blob_id, IndexOptions::new());
+            }
+
             // Add fields
             fields.insert(document)?;
 
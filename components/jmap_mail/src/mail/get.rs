@@ -23,6 +23,7 @@
 
 use super::{
     conv::IntoForm,
+    itip::Invitation,
     schema::{
         BodyProperty, Email, EmailBodyPart, EmailBodyValue, EmailHeader, HeaderForm,
         HeaderProperty, Property, Value,
@@ -154,6 +155,12 @@ where
         acl: &Arc<ACLToken>,
         blob: &JMAPBlob,
     ) -> store::Result<BlobResult>;
+    fn mail_blob_has_access(
+        &self,
+        account_id: AccountId,
+        acl: &Arc<ACLToken>,
+        blob_id: &BlobId,
+    ) -> store::Result<bool>;
 }
 
 impl<T> JMAPGetMail<T> for JMAPStore<T>
@@ -556,6 +563,34 @@ where
                         .mime_parts
                         .as_body_structure(&body_properties, raw_message.as_deref(), &blob_id)
                         .map(|b| b.into()),
+                    Property::CalendarInvitation => {
+                        if let Some(invitation_blob_id) = self.get_document_value::<BlobId>(
+                            account_id,
+                            Collection::Mail,
+                            document_id,
+                            MessageField::Invitation.into(),
+                        )? {
+                            Value::Invitation {
+                                value: Invitation::deserialize(
+                                    &self.blob_get(&invitation_blob_id)?.ok_or_else(|| {
+                                        StoreError::NotFound(format!(
+                                            "Invitation blob for {}/{} not found.",
+                                            account_id, document_id
+                                        ))
+                                    })?,
+                                )
+                                .ok_or_else(|| {
+                                    StoreError::DataCorruption(format!(
+                                        "Failed to deserialize invitation for {}/{}.",
+                                        account_id, document_id
+                                    ))
+                                })?,
+                            }
+                            .into()
+                        } else {
+                            None
+                        }
+                    }
                     Property::Invalid(property) => {
                         return Err(MethodError::InvalidArguments(format!(
                             "Unknown property {:?}",
@@ -577,23 +612,8 @@ where
         acl: &Arc<ACLToken>,
         blob: &JMAPBlob,
     ) -> store::Result<BlobResult> {
-        if !self.blob_account_has_access(&blob.id, &acl.member_of)? && !acl.is_member(SUPERUSER_ID)
-        {
-            if let Some(shared_ids) = self
-                .mail_shared_messages(account_id, &acl.member_of, ACL::ReadItems)?
-                .as_ref()
-            {
-                if !self.blob_document_has_access(
-                    &blob.id,
-                    account_id,
-                    Collection::Mail,
-                    shared_ids,
-                )? {
-                    return Ok(BlobResult::Unauthorized);
-                }
-            } else {
-                return Ok(BlobResult::Unauthorized);
-            }
+        if !self.mail_blob_has_access(account_id, acl, &blob.id)? {
+            return Ok(BlobResult::Unauthorized);
         }
 
         Ok(if let Some(section) = &blob.section {
@@ -621,6 +641,24 @@ where
         .map(BlobResult::Blob)
         .unwrap_or(BlobResult::NotFound))
     }
+
+    fn mail_blob_has_access(
+        &self,
+        account_id: AccountId,
+        acl: &Arc<ACLToken>,
+        blob_id: &BlobId,
+    ) -> store::Result<bool> {
+        if self.blob_account_has_access(blob_id, &acl.member_of)? || acl.is_member(SUPERUSER_ID) {
+            Ok(true)
+        } else if let Some(shared_ids) = self
+            .mail_shared_messages(account_id, &acl.member_of, ACL::ReadItems)?
+            .as_ref()
+        {
+            self.blob_document_has_access(blob_id, account_id, Collection::Mail, shared_ids)
+        } else {
+            Ok(false)
+        }
+    }
 }
 
 impl MimePart {
@@ -30,6 +30,7 @@ use jmap::jmap_store::changes::JMAPChanges;
 use jmap::jmap_store::Object;
 use jmap::orm::serialize::JMAPOrm;
 use jmap::orm::TinyORM;
+use jmap::principal::store::JMAPPrincipals;
 use jmap::request::{ACLEnforce, MaybeIdReference, MaybeResultReference, ResultReference};
 use jmap::types::blob::JMAPBlob;
 use jmap::types::date::JMAPDate;
@@ -267,6 +268,17 @@ where
                 }
 
                 match self.mail_blob_get(account_id, &acl, &item.blob_id)? {
+                    BlobResult::Blob(blob)
+                        if !self.principal_has_quota(account_id, blob.len())? =>
+                    {
+                        not_created.append(
+                            id,
+                            SetError::new(
+                                SetErrorType::OverQuota,
+                                "You have exceeded your storage quota.",
+                            ),
+                        );
+                    }
                     BlobResult::Blob(blob) => {
                         created.append(
                             id,
@@ -909,6 +921,7 @@ impl MessageData {
             self.size as Integer,
             IndexOptions::new().index() | options,
         );
+        document.quota(self.size as u64, options);
 
         document.number(
             MessageField::ReceivedAt,
@@ -0,0 +1,908 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{fmt::Write, sync::Arc, time::SystemTime};
+
+use jmap::{
+    error::method::MethodError, principal, request::ACLEnforce, sanitize_email,
+    types::jmap::JMAPId, SUPERUSER_ID,
+};
+use mail_builder::{
+    headers::{address::Address, content_type::ContentType},
+    mime::MimePart,
+    MessageBuilder,
+};
+use mail_parser::{HeaderName, HeaderValue, Message, PartType, RfcHeader};
+use serde::{Deserialize, Serialize};
+use store::{
+    bincode,
+    blob::BlobId,
+    chrono::{DateTime, Utc},
+    core::{acl::ACLToken, collection::Collection, document::Document, error::StoreError},
+    read::{
+        comparator::Comparator,
+        filter::{Filter, Query},
+        FilterMapper,
+    },
+    serialize::{StoreDeserialize, StoreSerialize},
+    write::options::{IndexOptions, Options},
+    JMAPStore, Store,
+};
+
+use super::MessageField;
+
+const PROD_ID: &str = "-//Stalwart Labs Ltd.//Stalwart JMAP Server//EN";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum InvitationMethod {
+    #[serde(rename = "request")]
+    Request,
+    #[serde(rename = "reply")]
+    Reply,
+    #[serde(rename = "cancel")]
+    Cancel,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ParticipationStatus {
+    #[serde(rename = "needs-action")]
+    NeedsAction,
+    #[serde(rename = "accepted")]
+    Accepted,
+    #[serde(rename = "declined")]
+    Declined,
+    #[serde(rename = "tentative")]
+    Tentative,
+    #[serde(rename = "delegated")]
+    Delegated,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InvitationOrganizer {
+    #[serde(rename = "email")]
+    pub email: String,
+
+    #[serde(rename = "name")]
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InvitationAttendee {
+    #[serde(rename = "email")]
+    pub email: String,
+
+    #[serde(rename = "name")]
+    pub name: Option<String>,
+
+    #[serde(rename = "participationStatus")]
+    pub participation_status: ParticipationStatus,
+}
+
+/// iTIP scheduling message (RFC 5546) found in a `text/calendar` part
+/// of an e-mail delivered via iMIP (RFC 6047).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Invitation {
+    #[serde(rename = "method")]
+    pub method: InvitationMethod,
+
+    #[serde(rename = "uid")]
+    pub uid: String,
+
+    #[serde(rename = "sequence")]
+    pub sequence: u32,
+
+    #[serde(rename = "summary")]
+    pub summary: Option<String>,
+
+    #[serde(rename = "start")]
+    pub start: Option<String>,
+
+    #[serde(rename = "end")]
+    pub end: Option<String>,
+
+    #[serde(rename = "timeZone")]
+    pub time_zone: Option<String>,
+
+    #[serde(rename = "organizer")]
+    pub organizer: Option<InvitationOrganizer>,
+
+    #[serde(rename = "attendees")]
+    pub attendees: Vec<InvitationAttendee>,
+}
+
+impl StoreSerialize for Invitation {
+    fn serialize(&self) -> Option<Vec<u8>> {
+        bincode::serialize(self).ok()
+    }
+}
+
+impl StoreDeserialize for Invitation {
+    fn deserialize(bytes: &[u8]) -> Option<Self> {
+        bincode::deserialize_from(bytes).ok()
+    }
+}
+
+impl InvitationMethod {
+    pub fn parse(value: &str) -> Option<Self> {
+        if value.eq_ignore_ascii_case("REQUEST") {
+            Some(InvitationMethod::Request)
+        } else if value.eq_ignore_ascii_case("REPLY") {
+            Some(InvitationMethod::Reply)
+        } else if value.eq_ignore_ascii_case("CANCEL") {
+            Some(InvitationMethod::Cancel)
+        } else {
+            None
+        }
+    }
+}
+
+impl ParticipationStatus {
+    pub fn parse(value: &str) -> Self {
+        if value.eq_ignore_ascii_case("ACCEPTED") {
+            ParticipationStatus::Accepted
+        } else if value.eq_ignore_ascii_case("DECLINED") {
+            ParticipationStatus::Declined
+        } else if value.eq_ignore_ascii_case("TENTATIVE") {
+            ParticipationStatus::Tentative
+        } else if value.eq_ignore_ascii_case("DELEGATED") {
+            ParticipationStatus::Delegated
+        } else {
+            ParticipationStatus::NeedsAction
+        }
+    }
+
+    pub fn as_ical(&self) -> &'static str {
+        match self {
+            ParticipationStatus::NeedsAction => "NEEDS-ACTION",
+            ParticipationStatus::Accepted => "ACCEPTED",
+            ParticipationStatus::Declined => "DECLINED",
+            ParticipationStatus::Tentative => "TENTATIVE",
+            ParticipationStatus::Delegated => "DELEGATED",
+        }
+    }
+
+    pub fn as_reply_subject(&self) -> &'static str {
+        match self {
+            ParticipationStatus::Accepted => "Accepted",
+            ParticipationStatus::Declined => "Declined",
+            ParticipationStatus::Tentative => "Tentatively accepted",
+            ParticipationStatus::Delegated => "Delegated",
+            ParticipationStatus::NeedsAction => "Pending",
+        }
+    }
+}
+
+struct ContentLine {
+    name: String,
+    params: Vec<(String, String)>,
+    value: String,
+}
+
+impl ContentLine {
+    fn parse(line: &str) -> Option<Self> {
+        let mut name = String::new();
+        let mut params = Vec::new();
+        let mut param_name = String::new();
+        let mut param_value = String::new();
+        let mut in_params = false;
+        let mut in_value = false;
+        let mut in_quotes = false;
+
+        for (pos, ch) in line.char_indices() {
+            match ch {
+                '"' if in_value => {
+                    in_quotes = !in_quotes;
+                }
+                ';' | ':' if !in_quotes => {
+                    if in_params {
+                        params.push((
+                            std::mem::take(&mut param_name).to_ascii_uppercase(),
+                            std::mem::take(&mut param_value),
+                        ));
+                    }
+                    if ch == ':' {
+                        return ContentLine {
+                            name: name.to_ascii_uppercase(),
+                            params,
+                            value: line[pos + 1..].to_string(),
+                        }
+                        .into();
+                    }
+                    in_params = true;
+                    in_value = false;
+                }
+                '=' if in_params && !in_value => {
+                    in_value = true;
+                }
+                _ if in_value => {
+                    param_value.push(ch);
+                }
+                _ if in_params => {
+                    param_name.push(ch);
+                }
+                _ => {
+                    name.push(ch);
+                }
+            }
+        }
+
+        None
+    }
+
+    fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(param_name, _)| param_name == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn mailto(&self) -> Option<String> {
+        let value = self.value.trim();
+        sanitize_email(
+            if value
+                .get(..7)
+                .map_or(false, |scheme| scheme.eq_ignore_ascii_case("mailto:"))
+            {
+                &value[7..]
+            } else {
+                value
+            },
+        )
+    }
+
+    fn text(&self) -> String {
+        let mut text = String::with_capacity(self.value.len());
+        let mut is_escaped = false;
+        for ch in self.value.chars() {
+            if is_escaped {
+                text.push(match ch {
+                    'n' | 'N' => '\n',
+                    ch => ch,
+                });
+                is_escaped = false;
+            } else if ch == '\\' {
+                is_escaped = true;
+            } else {
+                text.push(ch);
+            }
+        }
+        text
+    }
+
+    fn date_time(&self) -> Option<String> {
+        let value = self.value.trim().as_bytes();
+        if value.len() < 8 || !value[..8].iter().all(|ch| ch.is_ascii_digit()) {
+            return None;
+        }
+        let mut date_time = format!(
+            "{}-{}-{}",
+            std::str::from_utf8(&value[0..4]).ok()?,
+            std::str::from_utf8(&value[4..6]).ok()?,
+            std::str::from_utf8(&value[6..8]).ok()?
+        );
+        if value.len() >= 15
+            && value[8] == b'T'
+            && value[9..15].iter().all(|ch| ch.is_ascii_digit())
+        {
+            write!(
+                date_time,
+                "T{}:{}:{}",
+                std::str::from_utf8(&value[9..11]).ok()?,
+                std::str::from_utf8(&value[11..13]).ok()?,
+                std::str::from_utf8(&value[13..15]).ok()?
+            )
+            .ok()?;
+            if value.get(15) == Some(&b'Z') {
+                date_time.push('Z');
+            }
+        }
+        date_time.into()
+    }
+}
+
+impl Invitation {
+    /// Looks for an iTIP REQUEST, REPLY or CANCEL in the `text/calendar` parts of a message.
+    pub fn parse(message: &Message) -> Option<Invitation> {
+        for part in &message.parts {
+            let content_type = part.headers.iter().find_map(|header| {
+                if let (
+                    HeaderName::Rfc(RfcHeader::ContentType),
+                    HeaderValue::ContentType(content_type),
+                ) = (&header.name, &header.value)
+                {
+                    Some(content_type)
+                } else {
+                    None
+                }
+            });
+            if !matches!(content_type, Some(content_type)
+                if content_type.c_type.eq_ignore_ascii_case("text")
+                    && content_type
+                        .c_subtype
+                        .as_ref()
+                        .map_or(false, |s| s.eq_ignore_ascii_case("calendar")))
+            {
+                continue;
+            }
+
+            let ical = match &part.body {
+                PartType::Text(text) => text.as_ref(),
+                _ => continue,
+            };
+            if let Some(invitation) = Invitation::parse_icalendar(ical) {
+                return Some(invitation);
+            }
+        }
+
+        None
+    }
+
+    /// Parses the first VEVENT of an iCalendar (RFC 5545) object.
+    pub fn parse_icalendar(ical: &str) -> Option<Invitation> {
+        let mut method = None;
+        let mut uid = None;
+        let mut sequence = 0;
+        let mut summary = None;
+        let mut start = None;
+        let mut end = None;
+        let mut time_zone = None;
+        let mut organizer = None;
+        let mut attendees = Vec::new();
+        let mut components = Vec::new();
+        let mut has_event = false;
+
+        for line in unfold(ical) {
+            let line = if let Some(line) = ContentLine::parse(&line) {
+                line
+            } else {
+                continue;
+            };
+
+            match line.name.as_str() {
+                "BEGIN" => {
+                    let component = line.value.trim().to_ascii_uppercase();
+                    if component == "VEVENT" {
+                        if has_event {
+                            break;
+                        }
+                        has_event = true;
+                    }
+                    components.push(component);
+                }
+                "END" => {
+                    components.pop();
+                }
+                "METHOD" if components.len() == 1 => {
+                    method = InvitationMethod::parse(line.value.trim());
+                }
+                _ if components.last().map_or(false, |c| c == "VEVENT") => {
+                    match line.name.as_str() {
+                        "UID" => {
+                            uid = line.value.trim().to_string().into();
+                        }
+                        "SEQUENCE" => {
+                            sequence = line.value.trim().parse().unwrap_or(0);
+                        }
+                        "SUMMARY" => {
+                            summary = line.text().into();
+                        }
+                        "DTSTART" => {
+                            start = line.date_time();
+                            time_zone = line.param("TZID").map(|tz| tz.to_string());
+                        }
+                        "DTEND" => {
+                            end = line.date_time();
+                        }
+                        "ORGANIZER" => {
+                            organizer = line.mailto().map(|email| InvitationOrganizer {
+                                email,
+                                name: line.param("CN").map(|cn| cn.to_string()),
+                            });
+                        }
+                        "ATTENDEE" => {
+                            if let Some(email) = line.mailto() {
+                                attendees.push(InvitationAttendee {
+                                    email,
+                                    name: line.param("CN").map(|cn| cn.to_string()),
+                                    participation_status: line
+                                        .param("PARTSTAT")
+                                        .map(ParticipationStatus::parse)
+                                        .unwrap_or(ParticipationStatus::NeedsAction),
+                                });
+                            }
+                        }
+                        _ => (),
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        Invitation {
+            method: method?,
+            uid: uid.filter(|uid| !uid.is_empty())?,
+            sequence,
+            summary,
+            start,
+            end,
+            time_zone,
+            organizer,
+            attendees,
+        }
+        .into()
+    }
+
+    /// Requests and cancellations have to be sent by the organizer,
+    /// replies by one of the attendees.
+    pub fn is_valid_sender(&self, mail_from: &str) -> bool {
+        let mail_from = if let Some(mail_from) = sanitize_email(mail_from) {
+            mail_from
+        } else {
+            return false;
+        };
+
+        match self.method {
+            InvitationMethod::Request | InvitationMethod::Cancel => self
+                .organizer
+                .as_ref()
+                .map_or(false, |organizer| organizer.email == mail_from),
+            InvitationMethod::Reply => self
+                .attendees
+                .iter()
+                .any(|attendee| attendee.email == mail_from),
+        }
+    }
+
+    pub fn build_reply(
+        &self,
+        attendee: &InvitationAttendee,
+        participation_status: ParticipationStatus,
+    ) -> Option<Vec<u8>> {
+        let organizer = self.organizer.as_ref()?;
+        let dt_stamp = DateTime::<Utc>::from(SystemTime::now())
+            .format("%Y%m%dT%H%M%SZ")
+            .to_string();
+
+        let mut ical = String::with_capacity(512);
+        ical.push_str("BEGIN:VCALENDAR\r\n");
+        ical.push_str("VERSION:2.0\r\n");
+        write_line(&mut ical, "PRODID", PROD_ID);
+        ical.push_str("METHOD:REPLY\r\n");
+        ical.push_str("BEGIN:VEVENT\r\n");
+        write_line(&mut ical, "UID", &self.uid);
+        write_line(&mut ical, "SEQUENCE", &self.sequence.to_string());
+        write_line(&mut ical, "DTSTAMP", &dt_stamp);
+        if let Some(summary) = &self.summary {
+            write_line(&mut ical, "SUMMARY", &escape_text(summary));
+        }
+        write_line(
+            &mut ical,
+            &participant_name("ORGANIZER", organizer.name.as_deref(), None),
+            &format!("mailto:{}", organizer.email),
+        );
+        write_line(
+            &mut ical,
+            &participant_name(
+                "ATTENDEE",
+                attendee.name.as_deref(),
+                participation_status.as_ical().into(),
+            ),
+            &format!("mailto:{}", attendee.email),
+        );
+        ical.push_str("END:VEVENT\r\n");
+        ical.push_str("END:VCALENDAR\r\n");
+
+        let subject = format!(
+            "{}: {}",
+            participation_status.as_reply_subject(),
+            self.summary.as_deref().unwrap_or("Invitation")
+        );
+        let text = format!(
+            "{} has {} the invitation.",
+            attendee.name.as_deref().unwrap_or(&attendee.email),
+            participation_status.as_reply_subject().to_lowercase()
+        );
+
+        MessageBuilder::new()
+            .from(
+                attendee
+                    .name
+                    .as_deref()
+                    .map(|name| Address::from((name, attendee.email.as_str())))
+                    .unwrap_or_else(|| Address::from(attendee.email.as_str())),
+            )
+            .to(organizer
+                .name
+                .as_deref()
+                .map(|name| Address::from((name, organizer.email.as_str())))
+                .unwrap_or_else(|| Address::from(organizer.email.as_str())))
+            .subject(subject)
+            .body(MimePart::new_multipart(
+                "multipart/alternative",
+                vec![
+                    MimePart::new_text(text),
+                    MimePart::new_binary(
+                        ContentType::new("text/calendar")
+                            .attribute("method", "REPLY")
+                            .attribute("charset", "utf-8"),
+                        ical.into_bytes(),
+                    ),
+                ],
+            ))
+            .write_to_vec()
+            .ok()
+    }
+}
+
+fn unfold(ical: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in ical.split('\n') {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        if let (Some(folded), Some(last_line)) = (
+            line.strip_prefix(' ').or_else(|| line.strip_prefix('\t')),
+            lines.last_mut(),
+        ) {
+            last_line.push_str(folded);
+        } else if !line.is_empty() {
+            lines.push(line.to_string());
+        }
+    }
+    lines
+}
+
+fn escape_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '\\' | ';' | ',' => {
+                escaped.push('\\');
+                escaped.push(ch);
+            }
+            '\n' => escaped.push_str("\\n"),
+            '\r' => (),
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+fn participant_name(name: &str, cn: Option<&str>, partstat: Option<&str>) -> String {
+    let mut result = name.to_string();
+    if let Some(partstat) = partstat {
+        result.push_str(";PARTSTAT=");
+        result.push_str(partstat);
+    }
+    if let Some(cn) = cn {
+        result.push_str(";CN=\"");
+        result.extend(cn.chars().filter(|ch| *ch != '"'));
+        result.push('"');
+    }
+    result
+}
+
+fn write_line(ical: &mut String, name: &str, value: &str) {
+    // Fold lines longer than 75 octets
+    let line = format!("{}:{}", name, value);
+    let mut line_len = 0;
+    for ch in line.chars() {
+        if line_len + ch.len_utf8() > 75 {
+            ical.push_str("\r\n ");
+            line_len = 1;
+        }
+        ical.push(ch);
+        line_len += ch.len_utf8();
+    }
+    ical.push_str("\r\n");
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct EmailInvitationReplyRequest {
+    #[serde(skip)]
+    pub acl: Option<Arc<ACLToken>>,
+
+    #[serde(rename = "accountId")]
+    pub account_id: JMAPId,
+
+    #[serde(rename = "emailId")]
+    pub email_id: JMAPId,
+
+    #[serde(rename = "participationStatus")]
+    pub participation_status: ParticipationStatus,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct EmailInvitationReplyResponse {
+    #[serde(rename = "accountId")]
+    pub account_id: JMAPId,
+
+    #[serde(rename = "emailId")]
+    pub email_id: JMAPId,
+
+    #[serde(rename = "participationStatus")]
+    pub participation_status: ParticipationStatus,
+
+    #[serde(skip)]
+    pub reply: Option<InvitationReply>,
+}
+
+#[derive(Debug, Clone)]
+pub struct InvitationReply {
+    pub from: String,
+    pub to: String,
+    pub message: Vec<u8>,
+}
+
+pub trait JMAPMailInvitation<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn mail_link_invitation(
+        &self,
+        document: &mut Document,
+        invitation: &Invitation,
+    ) -> store::Result<()>;
+    fn mail_invitation_reply(
+        &self,
+        request: EmailInvitationReplyRequest,
+    ) -> jmap::Result<EmailInvitationReplyResponse>;
+}
+
+impl<T> JMAPMailInvitation<T> for JMAPStore<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn mail_link_invitation(
+        &self,
+        document: &mut Document,
+        invitation: &Invitation,
+    ) -> store::Result<()> {
+        let invitation_bytes = invitation
+            .serialize()
+            .ok_or_else(|| StoreError::SerializeError("Failed to serialize invitation".into()))?;
+        let invitation_blob_id = BlobId::new_local(&invitation_bytes);
+
+        self.blob_store(&invitation_blob_id, invitation_bytes)?;
+        document.binary(
+            MessageField::Invitation,
+            invitation_blob_id.serialize().unwrap(),
+            IndexOptions::new(),
+        );
+        document.blob(invitation_blob_id, IndexOptions::new());
+        Ok(())
+    }
+
+    fn mail_invitation_reply(
+        &self,
+        request: EmailInvitationReplyRequest,
+    ) -> jmap::Result<EmailInvitationReplyResponse> {
+        let account_id = request.account_id.get_document_id();
+        let document_id = request.email_id.get_document_id();
+
+        // Replies are sent on behalf of the account owner
+        if !request.acl.unwrap().is_member(account_id) {
+            return Err(MethodError::Forbidden(
+                "You are not allowed to reply to invitations on behalf of this account."
+                    .to_string(),
+            ));
+        }
+        if !matches!(
+            request.participation_status,
+            ParticipationStatus::Accepted
+                | ParticipationStatus::Declined
+                | ParticipationStatus::Tentative
+        ) {
+            return Err(MethodError::InvalidArguments(
+                "Participation status has to be accepted, declined or tentative.".to_string(),
+            ));
+        }
+
+        // Fetch invitation
+        let invitation = if let Some(invitation_blob_id) = self
+            .get_document_ids(account_id, Collection::Mail)?
+            .filter(|document_ids| document_ids.contains(document_id))
+            .map(|_| {
+                self.get_document_value::<BlobId>(
+                    account_id,
+                    Collection::Mail,
+                    document_id,
+                    MessageField::Invitation.into(),
+                )
+            })
+            .transpose()?
+            .flatten()
+        {
+            Invitation::deserialize(&self.blob_get(&invitation_blob_id)?.ok_or_else(|| {
+                StoreError::NotFound(format!(
+                    "Invitation blob for {}:{} not found.",
+                    account_id, document_id
+                ))
+            })?)
+            .ok_or_else(|| {
+                StoreError::DataCorruption(format!(
+                    "Failed to deserialize invitation for {}:{}.",
+                    account_id, document_id
+                ))
+            })?
+        } else {
+            return Err(MethodError::InvalidArguments(format!(
+                "Email {} does not contain a calendar invitation.",
+                request.email_id
+            )));
+        };
+        if invitation.method != InvitationMethod::Request {
+            return Err(MethodError::InvalidArguments(
+                "Only invitation requests can be replied to.".to_string(),
+            ));
+        }
+
+        // Find the attendee matching one of the account's addresses
+        let mut attendee = None;
+        for invitation_attendee in &invitation.attendees {
+            if self
+                .query_store::<FilterMapper>(
+                    SUPERUSER_ID,
+                    Collection::Principal,
+                    Filter::or(vec![
+                        Filter::eq(
+                            principal::schema::Property::Email.into(),
+                            Query::Index(invitation_attendee.email.clone()),
+                        ),
+                        Filter::eq(
+                            principal::schema::Property::Aliases.into(),
+                            Query::Index(invitation_attendee.email.clone()),
+                        ),
+                    ]),
+                    Comparator::None,
+                )?
+                .into_iter()
+                .any(|id| id.get_document_id() == account_id)
+            {
+                attendee = invitation_attendee.into();
+                break;
+            }
+        }
+        let attendee = attendee.ok_or_else(|| {
+            MethodError::InvalidArguments(
+                "This account is not an attendee of the invitation.".to_string(),
+            )
+        })?;
+
+        let message = invitation
+            .build_reply(attendee, request.participation_status)
+            .ok_or_else(|| {
+                MethodError::InvalidArguments(
+                    "Invitation does not have a valid organizer.".to_string(),
+                )
+            })?;
+
+        Ok(EmailInvitationReplyResponse {
+            account_id: request.account_id,
+            email_id: request.email_id,
+            participation_status: request.participation_status,
+            reply: InvitationReply {
+                from: attendee.email.clone(),
+                to: invitation.organizer.as_ref().unwrap().email.clone(),
+                message,
+            }
+            .into(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Invitation, InvitationMethod, ParticipationStatus};
+
+    const INVITATION: &str = concat!(
+        "BEGIN:VCALENDAR\r\n",
+        "VERSION:2.0\r\n",
+        "PRODID:-//Example//Calendar//EN\r\n",
+        "METHOD:REQUEST\r\n",
+        "BEGIN:VEVENT\r\n",
+        "UID:4f6b6b1e-0000-4000-8000-000000000001\r\n",
+        "SEQUENCE:2\r\n",
+        "DTSTAMP:20221001T080000Z\r\n",
+        "DTSTART;TZID=Europe/Berlin:20221010T100000\r\n",
+        "DTEND;TZID=Europe/Berlin:20221010T110000\r\n",
+        "SUMMARY:Quarterly planning\\, part 1\r\n",
+        "ORGANIZER;CN=\"Jane Doe\":mailto:jane@example.org\r\n",
+        "ATTENDEE;CN=\"John Doe\";PARTSTAT=NEEDS-ACTION;RSVP=TRUE:\r\n",
+        " mailto:john@example.org\r\n",
+        "ATTENDEE;PARTSTAT=ACCEPTED:mailto:bill@example.org\r\n",
+        "BEGIN:VALARM\r\n",
+        "ACTION:DISPLAY\r\n",
+        "SUMMARY:Reminder\r\n",
+        "END:VALARM\r\n",
+        "END:VEVENT\r\n",
+        "END:VCALENDAR\r\n"
+    );
+
+    #[test]
+    fn parse_invitation() {
+        let invitation = Invitation::parse_icalendar(INVITATION).unwrap();
+
+        assert_eq!(invitation.method, InvitationMethod::Request);
+        assert_eq!(invitation.uid, "4f6b6b1e-0000-4000-8000-000000000001");
+        assert_eq!(invitation.sequence, 2);
+        assert_eq!(
+            invitation.summary.as_deref(),
+            Some("Quarterly planning, part 1")
+        );
+        assert_eq!(invitation.start.as_deref(), Some("2022-10-10T10:00:00"));
+        assert_eq!(invitation.end.as_deref(), Some("2022-10-10T11:00:00"));
+        assert_eq!(invitation.time_zone.as_deref(), Some("Europe/Berlin"));
+        assert_eq!(
+            invitation.organizer.as_ref().unwrap().email,
+            "jane@example.org"
+        );
+        assert_eq!(
+            invitation.organizer.as_ref().unwrap().name.as_deref(),
+            Some("Jane Doe")
+        );
+        assert_eq!(invitation.attendees.len(), 2);
+        assert_eq!(invitation.attendees[0].email, "john@example.org");
+        assert_eq!(invitation.attendees[0].name.as_deref(), Some("John Doe"));
+        assert_eq!(
+            invitation.attendees[0].participation_status,
+            ParticipationStatus::NeedsAction
+        );
+        assert_eq!(
+            invitation.attendees[1].participation_status,
+            ParticipationStatus::Accepted
+        );
+
+        assert!(invitation.is_valid_sender("Jane@Example.org"));
+        assert!(!invitation.is_valid_sender("john@example.org"));
+        assert!(!invitation.is_valid_sender(""));
+
+        assert!(Invitation::parse_icalendar(
+            &INVITATION.replace("METHOD:REQUEST", "METHOD:PUBLISH")
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn build_reply() {
+        let invitation = Invitation::parse_icalendar(INVITATION).unwrap();
+        let message = String::from_utf8(
+            invitation
+                .build_reply(&invitation.attendees[0], ParticipationStatus::Accepted)
+                .unwrap(),
+        )
+        .unwrap();
+        let message = mail_parser::Message::parse(message.as_bytes()).unwrap();
+        let reply = Invitation::parse(&message).unwrap();
+
+        assert_eq!(reply.method, InvitationMethod::Reply);
+        assert_eq!(reply.uid, invitation.uid);
+        assert_eq!(reply.sequence, invitation.sequence);
+        assert_eq!(reply.attendees.len(), 1);
+        assert_eq!(reply.attendees[0].email, "john@example.org");
+        assert_eq!(
+            reply.attendees[0].participation_status,
+            ParticipationStatus::Accepted
+        );
+        assert!(reply.is_valid_sender("john@example.org"));
+        assert!(!reply.is_valid_sender("jane@example.org"));
+    }
+}
@@ -26,6 +26,7 @@ pub mod conv;
 pub mod copy;
 pub mod get;
 pub mod import;
+pub mod itip;
 pub mod parse;
 pub mod query;
 pub mod raft;
@@ -34,6 +35,7 @@ pub mod search_snippet;
 pub mod serialize;
 pub mod set;
 pub mod sharing;
+pub mod spam;
 
 use jmap::{jmap_store::Object, types::jmap::JMAPId};
 use serde::{Deserialize, Serialize};
@@ -327,6 +329,8 @@ pub enum MessageField {
     ThreadId = 136,
     Mailbox = 137,
     HasHeader = 138,
+    Invitation = 139,
+    SpamClass = 140,
 }
 
 impl From<MessageField> for FieldId {
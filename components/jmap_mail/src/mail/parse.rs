@@ -437,6 +437,7 @@ impl IntoParsedEmail for Message<'_> {
                 | Property::MailboxIds
                 | Property::Keywords
                 | Property::ReceivedAt
+                | Property::CalendarInvitation
                 | Property::Invalid(_) => None,
             };
 
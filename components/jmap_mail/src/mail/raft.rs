@@ -35,6 +35,7 @@ use store::{
 };
 
 use super::schema::Email;
+use super::spam::JMAPMailSpam;
 use super::MessageData;
 use super::MessageField;
 
@@ -51,7 +52,8 @@ where
     ) -> store::Result<()> {
         if let Some(blobs) = as_insert {
             // First blobId contains the message metadata
-            let metadata_blob_id = blobs.into_iter().next().ok_or_else(|| {
+            let mut blobs = blobs.into_iter();
+            let metadata_blob_id = blobs.next().ok_or_else(|| {
                 StoreError::InternalError(format!(
                     "Failed to get message metadata blob for {}.",
                     document.document_id
@@ -93,6 +95,16 @@ where
                 IndexOptions::new(),
             );
             document.blob(metadata_blob_id, IndexOptions::new());
+
+            // Link calendar invitation blob, if any
+            if let Some(invitation_blob_id) = blobs.nth(1) {
+                document.binary(
+                    MessageField::Invitation,
+                    invitation_blob_id.serialize().unwrap(),
+                    IndexOptions::new(),
+                );
+                document.blob(invitation_blob_id, IndexOptions::new());
+            }
         } else {
             let thread_id = jmap_id.get_prefix_id();
             let current_thread_id = store
@@ -126,6 +138,9 @@ where
                     IndexOptions::new().store(),
                 );
             }
+
+            // Replay spam filter training
+            store.mail_spam_train(write_batch.account_id, document)?;
         }
         Ok(())
     }
@@ -177,6 +192,14 @@ where
                 ))
             })?;
         blobs.push(message_data.raw_message);
+        if let Some(invitation_blob_id) = store.get_document_value(
+            account_id,
+            Collection::Mail,
+            document_id,
+            MessageField::Invitation.into(),
+        )? {
+            blobs.push(invitation_blob_id);
+        }
         Ok(blobs)
     }
 }
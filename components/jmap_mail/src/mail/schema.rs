@@ -38,7 +38,7 @@ use store::{
     FieldId,
 };
 
-use super::{HeaderName, MessageField};
+use super::{itip::Invitation, HeaderName, MessageField};
 
 #[derive(Debug, Clone, Default, Eq, PartialEq)]
 pub struct Email {
@@ -236,6 +236,7 @@ pub enum Property {
     Attachments,
     BodyStructure,
     Headers,
+    CalendarInvitation,
     Header(HeaderProperty),
     Invalid(String),
 }
@@ -269,6 +270,7 @@ impl Property {
             "attachments" => Property::Attachments,
             "bodyStructure" => Property::BodyStructure,
             "headers" => Property::Headers,
+            "calendarInvitation" => Property::CalendarInvitation,
             _ if value.starts_with("header:") => {
                 if let Some(header) = HeaderProperty::parse(value) {
                     Property::Header(header)
@@ -331,6 +333,7 @@ impl Display for Property {
             Property::Attachments => write!(f, "attachments"),
             Property::BodyStructure => write!(f, "bodyStructure"),
             Property::Headers => write!(f, "headers"),
+            Property::CalendarInvitation => write!(f, "calendarInvitation"),
             Property::Header(header) => header.fmt(f),
             Property::Invalid(value) => write!(f, "{}", value),
         }
@@ -577,6 +580,9 @@ pub enum Value {
     Headers {
         value: Vec<EmailHeader>,
     },
+    Invitation {
+        value: Invitation,
+    },
     Null,
 }
 
@@ -695,6 +701,7 @@ impl From<Property> for FieldId {
             Property::Attachments => 20,
             Property::BodyStructure => 21,
             Property::Headers => 22,
+            Property::CalendarInvitation => 25,
             Property::Header(_) => 23,
             Property::Invalid(_) => 24,
         }
@@ -727,6 +734,7 @@ impl From<FieldId> for Property {
             20 => Property::Attachments,
             21 => Property::BodyStructure,
             22 => Property::Headers,
+            25 => Property::CalendarInvitation,
             136 => Property::ThreadId,
             137 => Property::MailboxIds,
             132 => Property::Keywords,
@@ -73,6 +73,7 @@ impl Serialize for Email {
                 Value::GroupedAddresses { value } => map.serialize_entry(name, value)?,
                 Value::GroupedAddressesList { value } => map.serialize_entry(name, value)?,
                 Value::Headers { value } => map.serialize_entry(name, value)?,
+                Value::Invitation { value } => map.serialize_entry(name, value)?,
                 Value::Null => map.serialize_entry(name, &None::<&str>)?,
             }
         }
@@ -419,6 +420,7 @@ impl Serialize for EmailBodyPart {
                 Value::GroupedAddresses { value } => map.serialize_entry(name, value)?,
                 Value::GroupedAddressesList { value } => map.serialize_entry(name, value)?,
                 Value::Headers { value } => map.serialize_entry(name, value)?,
+                Value::Invitation { value } => map.serialize_entry(name, value)?,
                 Value::Null => map.serialize_entry(name, &None::<&str>)?,
             }
         }
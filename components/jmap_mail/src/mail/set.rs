@@ -26,11 +26,13 @@ use super::schema::{
     BodyProperty, Email, EmailBodyPart, EmailBodyValue, HeaderForm, Keyword, Property, Value,
 };
 use super::sharing::JMAPShareMail;
+use super::spam::JMAPMailSpam;
 use super::{HeaderName, MessageData, MessageField};
 use crate::mail::import::JMAPMailImport;
 use jmap::error::set::{SetError, SetErrorType};
 use jmap::jmap_store::set::{SetHelper, SetObject};
 use jmap::orm::{serialize::JMAPOrm, TinyORM};
+use jmap::principal::store::JMAPPrincipals;
 use jmap::request::set::{SetRequest, SetResponse};
 use jmap::request::{ACLEnforce, MaybeIdReference, ResultReference};
 use jmap::types::blob::JMAPBlob;
@@ -530,6 +532,12 @@ where
             builder.write_to(&mut blob).map_err(|_| {
                 StoreError::SerializeError("Failed to write to memory.".to_string())
             })?;
+            if !self.principal_has_quota(account_id, blob.len())? {
+                return Err(SetError::new(
+                    SetErrorType::OverQuota,
+                    "You have exceeded your storage quota.",
+                ));
+            }
             let blob_id = BlobId::new_external(&blob);
             let raw_blob: JMAPBlob = (&blob_id).into();
 
@@ -743,6 +751,9 @@ where
             // Merge changes
             current_fields.merge_validate(document, fields)?;
 
+            // Train the spam filter when messages are marked as junk or not junk
+            self.mail_spam_train(account_id, document)?;
+
             Ok(None)
         })?;
 
@@ -836,6 +847,38 @@ where
             IndexOptions::new().clear(),
         );
 
+        // Unlink calendar invitation
+        if let Some(invitation_blob_id) = self.get_document_value::<BlobId>(
+            account_id,
+            Collection::Mail,
+            document_id,
+            MessageField::Invitation.into(),
+        )? {
+            document.blob(invitation_blob_id, IndexOptions::new().clear());
+            document.binary(
+                MessageField::Invitation,
+                Vec::with_capacity(0),
+                IndexOptions::new().clear(),
+            );
+        }
+
+        // Remove the class the message was learned as by the spam filter
+        if self
+            .get_document_value::<Vec<u8>>(
+                account_id,
+                Collection::Mail,
+                document_id,
+                MessageField::SpamClass.into(),
+            )?
+            .is_some()
+        {
+            document.binary(
+                MessageField::SpamClass,
+                Vec::with_capacity(0),
+                IndexOptions::new().clear(),
+            );
+        }
+
         // Fetch ORM
         let fields = self
             .get_orm::<Email>(account_id, document_id)?
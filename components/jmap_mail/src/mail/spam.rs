@@ -0,0 +1,189 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use mail_parser::{decoders::html::html_to_text, Message, PartType};
+use store::{
+    ahash::AHashSet,
+    blob::BlobId,
+    core::{collection::Collection, document::Document, error::StoreError, tag::Tag},
+    nlp::bayes::bayes_tokenize,
+    serialize::StoreDeserialize,
+    write::options::{IndexOptions, Options},
+    AccountId, FieldId, JMAPStore, Store,
+};
+
+use crate::sieve_script::runtime::MessageHeaders;
+
+use super::{schema::Keyword, MessageData, MessageField};
+
+pub trait JMAPMailSpam<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn mail_spam_tokens(&self, message: &Message) -> AHashSet<String>;
+    fn mail_spam_headers(&self, headers: &MessageHeaders) -> bool;
+    fn mail_is_spam(&self, account_id: AccountId, tokens: &AHashSet<String>)
+        -> store::Result<bool>;
+    fn mail_spam_train(&self, account_id: AccountId, document: &mut Document) -> store::Result<()>;
+}
+
+impl<T> JMAPMailSpam<T> for JMAPStore<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn mail_spam_tokens(&self, message: &Message) -> AHashSet<String> {
+        let mut tokens = AHashSet::new();
+        if !self.config.spam_bayes {
+            return tokens;
+        }
+
+        if let Some(subject) = message.get_subject() {
+            bayes_tokenize(subject, &mut tokens);
+        }
+        for part_id in message.text_body.iter().chain(message.html_body.iter()) {
+            match message.parts.get(*part_id).map(|part| &part.body) {
+                Some(PartType::Text(text)) => bayes_tokenize(text, &mut tokens),
+                Some(PartType::Html(html)) => bayes_tokenize(&html_to_text(html), &mut tokens),
+                _ => (),
+            }
+        }
+        tokens
+    }
+
+    fn mail_spam_headers(&self, headers: &MessageHeaders) -> bool {
+        self.config.spam_headers.iter().any(|(name, value)| {
+            headers.get(name).map_or(false, |header| {
+                header.trim().to_lowercase().starts_with(value.as_str())
+            })
+        })
+    }
+
+    fn mail_is_spam(
+        &self,
+        account_id: AccountId,
+        tokens: &AHashSet<String>,
+    ) -> store::Result<bool> {
+        if !self.config.spam_bayes || tokens.is_empty() {
+            return Ok(false);
+        }
+
+        Ok(self
+            .bayes_classify(account_id, tokens)?
+            .map_or(false, |probability| {
+                probability >= self.config.spam_bayes_threshold
+            }))
+    }
+
+    fn mail_spam_train(&self, account_id: AccountId, document: &mut Document) -> store::Result<()> {
+        if !self.config.spam_bayes {
+            return Ok(());
+        }
+
+        // Training is derived from the keyword changes in the document, which allows
+        // followers replaying the same update to learn the same way.
+        let mut junk = None;
+        let mut not_junk = None;
+        for field in &document.tag_fields {
+            if field.field == FieldId::from(MessageField::Keyword) {
+                match field.value {
+                    Tag::Static(Keyword::JUNK) => junk = Some(!field.options.is_clear()),
+                    Tag::Static(Keyword::NOTJUNK) => not_junk = Some(!field.options.is_clear()),
+                    _ => (),
+                }
+            }
+        }
+
+        let is_spam = match (junk, not_junk) {
+            (Some(true), _) => true,
+            (_, Some(true)) => false,
+            _ => return Ok(()),
+        };
+
+        // The class a message was learned as is stored with it, so that flipping
+        // its keywords unlearns the previous class instead of counting it twice.
+        let document_id = document.document_id;
+        let learned_as = self
+            .get_document_value::<Vec<u8>>(
+                account_id,
+                Collection::Mail,
+                document_id,
+                MessageField::SpamClass.into(),
+            )?
+            .and_then(|class| class.first().map(|class| *class == 1));
+        if learned_as == Some(is_spam) {
+            return Ok(());
+        }
+
+        // Fetch raw message
+        let metadata_blob_id = if let Some(metadata_blob_id) = self.get_document_value::<BlobId>(
+            account_id,
+            Collection::Mail,
+            document_id,
+            MessageField::Metadata.into(),
+        )? {
+            metadata_blob_id
+        } else {
+            return Ok(());
+        };
+        let message_data =
+            MessageData::deserialize(&self.blob_get(&metadata_blob_id)?.ok_or_else(|| {
+                StoreError::NotFound(format!(
+                    "Message data blob for {}:{} not found.",
+                    account_id, document_id
+                ))
+            })?)
+            .ok_or_else(|| {
+                StoreError::DataCorruption(format!(
+                    "Failed to deserialize message data for {}:{}.",
+                    account_id, document_id
+                ))
+            })?;
+        let raw_message = self.blob_get(&message_data.raw_message)?.ok_or_else(|| {
+            StoreError::NotFound(format!(
+                "Raw email message not found for {}:{}.",
+                account_id, document_id
+            ))
+        })?;
+
+        // Train classifier
+        if let Some(message) = Message::parse(&raw_message) {
+            let tokens = self
+                .mail_spam_tokens(&message)
+                .into_iter()
+                .collect::<Vec<_>>();
+            if !tokens.is_empty() {
+                if let Some(was_spam) = learned_as {
+                    document.bayes(tokens.clone(), was_spam, IndexOptions::new().clear());
+                }
+                document.bayes(tokens, is_spam, IndexOptions::new());
+                document.binary(
+                    MessageField::SpamClass,
+                    vec![is_spam as u8],
+                    IndexOptions::new(),
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
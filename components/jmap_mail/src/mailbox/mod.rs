@@ -28,6 +28,7 @@ pub mod raft;
 pub mod schema;
 pub mod serialize;
 pub mod set;
+pub mod uids;
 
 use jmap::types::jmap::JMAPId;
 use jmap::{jmap_store::Object, orm::TinyORM};
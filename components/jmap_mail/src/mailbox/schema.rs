@@ -34,6 +34,8 @@ use store::{
     AccountId, FieldId,
 };
 
+use super::uids::MailboxUidState;
+
 #[derive(Debug, Clone, Default, Eq, PartialEq)]
 pub struct Mailbox {
     pub properties: VecMap<Property, Value>,
@@ -51,6 +53,7 @@ pub enum Value {
     IdReference { value: String },
     ACLSet(Vec<ACLUpdate>),
     ACLGet(VecMap<String, Vec<ACL>>),
+    Uids { value: MailboxUidState },
     Null,
 }
 
@@ -99,6 +102,7 @@ impl orm::Value for Value {
             Value::ACLGet(value) => value.iter().fold(0, |acc, (k, v)| {
                 acc + k.len() + v.len() * std::mem::size_of::<ACL>()
             }),
+            Value::Uids { .. } => std::mem::size_of::<MailboxUidState>(),
             Value::Null => 0,
         }
     }
@@ -193,7 +197,8 @@ pub enum Property {
     MyRights = 9,
     IsSubscribed = 10,
     ACL = 11,
-    Invalid = 12,
+    Uids = 12,
+    Invalid = 13,
 }
 
 impl Display for Property {
@@ -211,7 +216,7 @@ impl Display for Property {
             Property::MyRights => write!(f, "myRights"),
             Property::IsSubscribed => write!(f, "isSubscribed"),
             Property::ACL => write!(f, "acl"),
-            Property::Invalid => Ok(()),
+            Property::Uids | Property::Invalid => Ok(()),
         }
     }
 }
@@ -278,6 +283,7 @@ impl From<FieldId> for Property {
             9 => Property::MyRights,
             10 => Property::IsSubscribed,
             11 => Property::ACL,
+            12 => Property::Uids,
             _ => Property::Invalid,
         }
     }
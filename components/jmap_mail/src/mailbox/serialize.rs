@@ -92,7 +92,7 @@ impl Serialize for Mailbox {
                     map.serialize_entry(name, &format!("#{}", value))?
                 }
                 Value::ACLGet(value) => map.serialize_entry(name, value)?,
-                Value::Subscriptions { .. } | Value::ACLSet(_) => (),
+                Value::Subscriptions { .. } | Value::ACLSet(_) | Value::Uids { .. } => (),
             }
         }
 
@@ -24,6 +24,7 @@
 use std::time::Duration;
 
 use super::schema::{Mailbox, Property, Value};
+use super::uids::JMAPMailboxUids;
 use crate::mail::schema::Email;
 use crate::mail::set::JMAPSetMail;
 use crate::mail::sharing::JMAPShareMail;
@@ -365,6 +366,9 @@ where
     }
 
     fn mailbox_delete(&self, account_id: AccountId, document: &mut Document) -> store::Result<()> {
+        // Delete IMAP UID map
+        self.mailbox_uids_delete(account_id, document.document_id)?;
+
         // Delete ORM
         self.get_orm::<Mailbox>(account_id, document.document_id)?
             .ok_or_else(|| {
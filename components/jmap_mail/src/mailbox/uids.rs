@@ -0,0 +1,479 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::SystemTime;
+
+use jmap::orm::{serialize::JMAPOrm, TinyORM};
+use serde::{Deserialize, Serialize};
+use store::ahash::{AHashMap, AHashSet};
+use store::core::collection::Collection;
+use store::core::document::Document;
+use store::core::error::StoreError;
+use store::core::tag::Tag;
+use store::core::JMAPIdPrefix;
+use store::log::changes::{Change, ChangeId, Query};
+use store::serialize::key::ValueKey;
+use store::write::batch::WriteBatch;
+use store::write::operation::WriteOperation;
+use store::{bincode, AccountId, ColumnFamily, Direction, DocumentId, JMAPId, JMAPStore, Store};
+
+use crate::mail::MessageField;
+
+use super::schema::{Mailbox, Property, Value};
+
+const MAX_EXPUNGED: usize = 1024;
+
+const UIDS_STATE: u8 = 0;
+const UIDS_EXPUNGED: u8 = 1;
+const UIDS_ITEM: u8 = 2;
+
+/// IMAP UID state of a mailbox, kept as a hidden property of the Mailbox object
+/// so that UIDs keep increasing after a leader change. The UID map itself is
+/// stored under a dedicated per-mailbox key.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct MailboxUidState {
+    pub uid_validity: u32,
+    pub uid_next: u32,
+    pub highest_modseq: u64,
+}
+
+/// IMAP UID map of a mailbox.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct MailboxUids {
+    pub uid_validity: u32,
+    pub uid_next: u32,
+    pub highest_modseq: u64,
+    pub items: Vec<UidItem>,
+    pub expunged: Vec<(u32, u64)>,
+    pub expunged_modseq: u64,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct UidItem {
+    pub uid: u32,
+    pub id: JMAPId,
+    pub modseq: u64,
+}
+
+impl MailboxUidState {
+    // Mod-sequences are derived from the last change id, see `mailbox_uids_sync`.
+    pub fn change_id(&self) -> Option<ChangeId> {
+        self.highest_modseq.checked_sub(2)
+    }
+}
+
+impl MailboxUids {
+    pub fn new(uid_validity: u32) -> Self {
+        MailboxUids {
+            uid_validity,
+            uid_next: 1,
+            highest_modseq: 1,
+            items: Vec::new(),
+            expunged: Vec::new(),
+            expunged_modseq: 0,
+        }
+    }
+
+    pub fn state(&self) -> MailboxUidState {
+        MailboxUidState {
+            uid_validity: self.uid_validity,
+            uid_next: self.uid_next,
+            highest_modseq: self.highest_modseq,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn uid_to_seqnum(&self, uid: u32) -> Option<u32> {
+        self.items
+            .binary_search_by(|item| item.uid.cmp(&uid))
+            .ok()
+            .map(|pos| pos as u32 + 1)
+    }
+
+    pub fn get_by_uid(&self, uid: u32) -> Option<&UidItem> {
+        self.items
+            .binary_search_by(|item| item.uid.cmp(&uid))
+            .ok()
+            .map(|pos| &self.items[pos])
+    }
+
+    pub fn get_by_id(&self, id: JMAPId) -> Option<&UidItem> {
+        self.items.iter().find(|item| item.id == id)
+    }
+
+    /// Returns the UIDs expunged after the given mod-sequence, or None if
+    /// the tombstones for that mod-sequence have already been discarded.
+    pub fn expunged_since(&self, modseq: u64) -> Option<Vec<u32>> {
+        if modseq >= self.expunged_modseq {
+            Some(
+                self.expunged
+                    .iter()
+                    .filter_map(|(uid, expunged_modseq)| {
+                        if *expunged_modseq > modseq {
+                            Some(*uid)
+                        } else {
+                            None
+                        }
+                    })
+                    .collect(),
+            )
+        } else {
+            None
+        }
+    }
+}
+
+pub trait JMAPMailboxUids<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn mailbox_uids_sync(
+        &self,
+        account_id: AccountId,
+        document_id: DocumentId,
+    ) -> store::Result<Option<(MailboxUids, Option<ChangeId>)>>;
+    fn mailbox_uids_get(
+        &self,
+        account_id: AccountId,
+        document_id: DocumentId,
+        state: &MailboxUidState,
+    ) -> store::Result<Option<MailboxUids>>;
+    fn mailbox_uids_delete(
+        &self,
+        account_id: AccountId,
+        document_id: DocumentId,
+    ) -> store::Result<()>;
+}
+
+impl<T> JMAPMailboxUids<T> for JMAPStore<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn mailbox_uids_sync(
+        &self,
+        account_id: AccountId,
+        document_id: DocumentId,
+    ) -> store::Result<Option<(MailboxUids, Option<ChangeId>)>> {
+        let _lock = self.lock_collection(account_id, Collection::Mailbox);
+
+        let mailbox = if let Some(mailbox) = self.get_orm::<Mailbox>(account_id, document_id)? {
+            mailbox
+        } else {
+            return Ok(None);
+        };
+        let last_change_id = self.get_last_change_id(account_id, Collection::Mail)?;
+        let state = match mailbox.get(&Property::Uids) {
+            Some(Value::Uids { value }) => *value,
+            _ => MailboxUidState::default(),
+        };
+
+        // The local UID map is discarded when it does not match the mailbox state,
+        // which happens when this node did not perform the last synchronization.
+        let mut uids = if state.uid_validity != 0 {
+            self.mailbox_uids_get(account_id, document_id, &state)?
+                .unwrap_or_else(|| MailboxUids::new(0))
+        } else {
+            MailboxUids::new(0)
+        };
+
+        // Nothing to do if the mailbox was synchronized after the last change.
+        if uids.uid_validity != 0 && state.change_id() == last_change_id {
+            return Ok(Some((uids, None)));
+        }
+
+        let mut document_ids = self
+            .get_tag(
+                account_id,
+                Collection::Mail,
+                MessageField::Mailbox.into(),
+                Tag::Id(document_id),
+            )?
+            .unwrap_or_default();
+        let modseq = last_change_id.map(|id| id + 2).unwrap_or(1);
+        let mut ops = Vec::new();
+
+        // Obtain the messages that were modified since the last synchronization,
+        // deleted ids flag document ids that might have been reused.
+        let mut updated_ids = AHashSet::default();
+        let mut deleted_ids = AHashSet::default();
+        if uids.uid_validity != 0 {
+            if let Some(change_id) = state.change_id() {
+                if let Some(changes) =
+                    self.get_changes(account_id, Collection::Mail, Query::Since(change_id))?
+                {
+                    for change in changes.changes {
+                        match change {
+                            Change::Insert(id) | Change::Update(id) | Change::ChildUpdate(id) => {
+                                updated_ids.insert(id.get_document_id());
+                            }
+                            Change::Delete(id) => {
+                                deleted_ids.insert(id.get_document_id());
+                            }
+                        }
+                    }
+                } else {
+                    uids.uid_validity = 0;
+                }
+            }
+        }
+
+        if uids.uid_validity == 0 {
+            // Generate a new UIDVALIDITY, making sure it differs from the previous one.
+            let mut uid_validity = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|d| d.as_secs() as u32)
+                .unwrap_or(1);
+            if uid_validity <= state.uid_validity {
+                uid_validity = state.uid_validity + 1;
+            }
+            uids = MailboxUids::new(uid_validity);
+
+            // Remove any stale UID map
+            for key in uid_keys(self, account_id, document_id)? {
+                ops.push(WriteOperation::delete(ColumnFamily::Values, key));
+            }
+        }
+
+        // Remove expunged messages
+        let mut expunged = Vec::new();
+        uids.items.retain(|item| {
+            let item_document_id = item.id.get_document_id();
+            if document_ids.contains(item_document_id) && !deleted_ids.contains(&item_document_id) {
+                document_ids.remove(item_document_id);
+                true
+            } else {
+                expunged.push(item.uid);
+                false
+            }
+        });
+        if !expunged.is_empty() {
+            for uid in expunged {
+                ops.push(WriteOperation::delete(
+                    ColumnFamily::Values,
+                    uid_item_key(account_id, document_id, uid),
+                ));
+                uids.expunged.push((uid, modseq));
+            }
+            if uids.expunged.len() > MAX_EXPUNGED {
+                let remove_count = uids.expunged.len() - MAX_EXPUNGED;
+                uids.expunged_modseq = uids.expunged[remove_count - 1].1;
+                uids.expunged.drain(0..remove_count);
+            }
+            ops.push(WriteOperation::set(
+                ColumnFamily::Values,
+                uid_key(account_id, document_id, UIDS_EXPUNGED),
+                serialize(&(uids.expunged_modseq, &uids.expunged))?,
+            ));
+        }
+
+        // Update the mod-sequence of modified messages
+        for item in &mut uids.items {
+            if updated_ids.contains(&item.id.get_document_id()) {
+                item.modseq = modseq;
+                ops.push(WriteOperation::set(
+                    ColumnFamily::Values,
+                    uid_item_key(account_id, document_id, item.uid),
+                    serialize(&(item.id, item.modseq))?,
+                ));
+            }
+        }
+
+        // Assign UIDs to new messages
+        if !document_ids.is_empty() {
+            let thread_ids = self
+                .get_multi_document_value(
+                    account_id,
+                    Collection::Mail,
+                    document_ids.iter(),
+                    MessageField::ThreadId.into(),
+                )?
+                .into_iter()
+                .zip(document_ids.iter())
+                .filter_map(
+                    |(thread_id, document_id): (Option<DocumentId>, DocumentId)| {
+                        (document_id, thread_id?).into()
+                    },
+                )
+                .collect::<AHashMap<DocumentId, DocumentId>>();
+
+            for message_id in document_ids {
+                if let Some(thread_id) = thread_ids.get(&message_id) {
+                    let item = UidItem {
+                        uid: uids.uid_next,
+                        id: JMAPId::from_parts(*thread_id, message_id),
+                        modseq,
+                    };
+                    ops.push(WriteOperation::set(
+                        ColumnFamily::Values,
+                        uid_item_key(account_id, document_id, item.uid),
+                        serialize(&(item.id, item.modseq))?,
+                    ));
+                    uids.items.push(item);
+                    uids.uid_next += 1;
+                }
+            }
+        }
+
+        if ops.is_empty() && uids.uid_validity == state.uid_validity {
+            return Ok(Some((uids, None)));
+        }
+        uids.highest_modseq = modseq;
+
+        // Save the UID map, which is only valid for the new mailbox state
+        let new_state = uids.state();
+        ops.push(WriteOperation::set(
+            ColumnFamily::Values,
+            uid_key(account_id, document_id, UIDS_STATE),
+            serialize(&new_state)?,
+        ));
+        self.db.write(ops)?;
+
+        // Update the mailbox state
+        let mut batch = WriteBatch::new(account_id);
+        let mut document = Document::new(Collection::Mailbox, document_id);
+        let mut fields = TinyORM::track_changes(&mailbox);
+        fields.set(Property::Uids, Value::Uids { value: new_state });
+        mailbox.merge(&mut document, fields)?;
+        batch.update_document(document);
+        batch.log_child_update(Collection::Mailbox, document_id);
+
+        Ok(Some((
+            uids,
+            self.write(batch)?.map(|changes| changes.change_id),
+        )))
+    }
+
+    fn mailbox_uids_get(
+        &self,
+        account_id: AccountId,
+        document_id: DocumentId,
+        state: &MailboxUidState,
+    ) -> store::Result<Option<MailboxUids>> {
+        match self.db.get::<Vec<u8>>(
+            ColumnFamily::Values,
+            &uid_key(account_id, document_id, UIDS_STATE),
+        )? {
+            Some(bytes) if deserialize::<MailboxUidState>(&bytes)? == *state => (),
+            _ => return Ok(None),
+        }
+
+        let mut uids = MailboxUids {
+            uid_validity: state.uid_validity,
+            uid_next: state.uid_next,
+            highest_modseq: state.highest_modseq,
+            ..Default::default()
+        };
+        if let Some(bytes) = self.db.get::<Vec<u8>>(
+            ColumnFamily::Values,
+            &uid_key(account_id, document_id, UIDS_EXPUNGED),
+        )? {
+            let (expunged_modseq, expunged) = deserialize(&bytes)?;
+            uids.expunged_modseq = expunged_modseq;
+            uids.expunged = expunged;
+        }
+
+        // Keys are sorted by UID
+        let prefix = uid_key(account_id, document_id, UIDS_ITEM);
+        for (key, value) in self
+            .db
+            .iterator(ColumnFamily::Values, &prefix, Direction::Forward)?
+        {
+            if !key.starts_with(&prefix) {
+                break;
+            }
+            let uid = key
+                .get(prefix.len()..)
+                .and_then(|bytes| bytes.try_into().ok())
+                .map(u32::from_be_bytes)
+                .ok_or_else(|| {
+                    StoreError::DataCorruption(format!("Corrupted UID key [{:?}]", key))
+                })?;
+            let (id, modseq) = deserialize(&value)?;
+            uids.items.push(UidItem { uid, id, modseq });
+        }
+
+        Ok(Some(uids))
+    }
+
+    fn mailbox_uids_delete(
+        &self,
+        account_id: AccountId,
+        document_id: DocumentId,
+    ) -> store::Result<()> {
+        let ops = uid_keys(self, account_id, document_id)?
+            .into_iter()
+            .map(|key| WriteOperation::delete(ColumnFamily::Values, key))
+            .collect::<Vec<_>>();
+        if !ops.is_empty() {
+            self.db.write(ops)?;
+        }
+        Ok(())
+    }
+}
+
+fn uid_keys<T>(
+    store: &JMAPStore<T>,
+    account_id: AccountId,
+    document_id: DocumentId,
+) -> store::Result<Vec<Vec<u8>>>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    let prefix = ValueKey::serialize_uids(account_id, document_id);
+    let mut keys = Vec::new();
+    for (key, _) in store
+        .db
+        .iterator(ColumnFamily::Values, &prefix, Direction::Forward)?
+    {
+        if !key.starts_with(&prefix) {
+            break;
+        }
+        keys.push(key.to_vec());
+    }
+    Ok(keys)
+}
+
+fn uid_key(account_id: AccountId, document_id: DocumentId, key_type: u8) -> Vec<u8> {
+    let mut key = ValueKey::serialize_uids(account_id, document_id);
+    key.push(key_type);
+    key
+}
+
+fn uid_item_key(account_id: AccountId, document_id: DocumentId, uid: u32) -> Vec<u8> {
+    let mut key = uid_key(account_id, document_id, UIDS_ITEM);
+    key.extend_from_slice(&uid.to_be_bytes());
+    key
+}
+
+fn serialize<U: Serialize>(value: &U) -> store::Result<Vec<u8>> {
+    bincode::serialize(value)
+        .map_err(|_| StoreError::SerializeError("Failed to serialize UID map.".to_string()))
+}
+
+fn deserialize<U: for<'de> Deserialize<'de>>(bytes: &[u8]) -> store::Result<U> {
+    bincode::deserialize(bytes)
+        .map_err(|_| StoreError::DeserializeError("Failed to deserialize UID map.".to_string()))
+}
@@ -0,0 +1,1290 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::fmt::Display;
+
+// Extensions that can be declared using the "require" command.
+pub const SIEVE_EXTENSIONS: &[&str] = &[
+    "fileinto",
+    "reject",
+    "ereject",
+    "envelope",
+    "vacation",
+    "imap4flags",
+    "copy",
+    "comparator-i;octet",
+    "comparator-i;ascii-casemap",
+];
+
+const MAX_NESTING_DEPTH: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sieve {
+    pub commands: Vec<Command>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    If {
+        conditions: Vec<(Test, Vec<Command>)>,
+        otherwise: Option<Vec<Command>>,
+    },
+    Stop,
+    Keep {
+        flags: Option<Vec<String>>,
+    },
+    Discard,
+    FileInto {
+        mailbox: String,
+        flags: Option<Vec<String>>,
+        copy: bool,
+    },
+    Redirect {
+        address: String,
+        copy: bool,
+    },
+    Reject {
+        reason: String,
+    },
+    Vacation(Box<Vacation>),
+    SetFlag {
+        flags: Vec<String>,
+    },
+    AddFlag {
+        flags: Vec<String>,
+    },
+    RemoveFlag {
+        flags: Vec<String>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Vacation {
+    pub days: u64,
+    pub subject: Option<String>,
+    pub from: Option<String>,
+    pub addresses: Vec<String>,
+    pub mime: bool,
+    pub handle: Option<String>,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Test {
+    True,
+    False,
+    Not(Box<Test>),
+    AllOf(Vec<Test>),
+    AnyOf(Vec<Test>),
+    Exists {
+        headers: Vec<String>,
+    },
+    Size {
+        over: bool,
+        limit: u64,
+    },
+    Header {
+        headers: Vec<String>,
+        keys: Vec<String>,
+        match_type: MatchType,
+        comparator: Comparator,
+    },
+    Address {
+        headers: Vec<String>,
+        keys: Vec<String>,
+        part: AddressPart,
+        match_type: MatchType,
+        comparator: Comparator,
+    },
+    Envelope {
+        parts: Vec<EnvelopePart>,
+        keys: Vec<String>,
+        part: AddressPart,
+        match_type: MatchType,
+        comparator: Comparator,
+    },
+    HasFlag {
+        keys: Vec<String>,
+        match_type: MatchType,
+        comparator: Comparator,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchType {
+    Is,
+    Contains,
+    Matches,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparator {
+    Octet,
+    AsciiCaseMap,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressPart {
+    All,
+    LocalPart,
+    Domain,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnvelopePart {
+    From,
+    To,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompileError {
+    pub line: usize,
+    pub message: String,
+}
+
+pub type Result<T> = std::result::Result<T, CompileError>;
+
+impl Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Sieve {
+    pub fn compile(script: &[u8]) -> Result<Sieve> {
+        Parser::new(std::str::from_utf8(script).map_err(|_| CompileError {
+            line: 1,
+            message: "Script is not valid UTF-8.".to_string(),
+        })?)
+        .parse()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Identifier(String),
+    Tag(String),
+    Number(u64),
+    String(String),
+    BracketOpen,
+    BracketClose,
+    ParenOpen,
+    ParenClose,
+    CurlyOpen,
+    CurlyClose,
+    Comma,
+    Semicolon,
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Identifier(value) => write!(f, "identifier {:?}", value),
+            Token::Tag(value) => write!(f, "tag \":{}\"", value),
+            Token::Number(value) => write!(f, "number {}", value),
+            Token::String(_) => write!(f, "string"),
+            Token::BracketOpen => write!(f, "\"[\""),
+            Token::BracketClose => write!(f, "\"]\""),
+            Token::ParenOpen => write!(f, "\"(\""),
+            Token::ParenClose => write!(f, "\")\""),
+            Token::CurlyOpen => write!(f, "\"{{\""),
+            Token::CurlyClose => write!(f, "\"}}\""),
+            Token::Comma => write!(f, "\",\""),
+            Token::Semicolon => write!(f, "\";\""),
+        }
+    }
+}
+
+struct Tokenizer<'x> {
+    bytes: &'x [u8],
+    pos: usize,
+    line: usize,
+    peeked: Option<Option<(Token, usize)>>,
+}
+
+impl<'x> Tokenizer<'x> {
+    fn new(script: &'x str) -> Self {
+        Tokenizer {
+            bytes: script.as_bytes(),
+            pos: 0,
+            line: 1,
+            peeked: None,
+        }
+    }
+
+    fn error(&self, line: usize, message: impl Into<String>) -> CompileError {
+        CompileError {
+            line,
+            message: message.into(),
+        }
+    }
+
+    fn peek(&mut self) -> Result<Option<&(Token, usize)>> {
+        if self.peeked.is_none() {
+            self.peeked = Some(self.read_token()?);
+        }
+        Ok(self.peeked.as_ref().unwrap().as_ref())
+    }
+
+    fn next(&mut self) -> Result<Option<(Token, usize)>> {
+        if let Some(token) = self.peeked.take() {
+            Ok(token)
+        } else {
+            self.read_token()
+        }
+    }
+
+    fn skip_whitespace(&mut self) -> Result<()> {
+        while let Some(&ch) = self.bytes.get(self.pos) {
+            match ch {
+                b'\n' => {
+                    self.line += 1;
+                    self.pos += 1;
+                }
+                b' ' | b'\t' | b'\r' => {
+                    self.pos += 1;
+                }
+                b'#' => {
+                    while let Some(&ch) = self.bytes.get(self.pos) {
+                        if ch == b'\n' {
+                            break;
+                        }
+                        self.pos += 1;
+                    }
+                }
+                b'/' if self.bytes.get(self.pos + 1) == Some(&b'*') => {
+                    let line = self.line;
+                    self.pos += 2;
+                    loop {
+                        match self.bytes.get(self.pos) {
+                            Some(b'*') if self.bytes.get(self.pos + 1) == Some(&b'/') => {
+                                self.pos += 2;
+                                break;
+                            }
+                            Some(ch) => {
+                                if *ch == b'\n' {
+                                    self.line += 1;
+                                }
+                                self.pos += 1;
+                            }
+                            None => {
+                                return Err(self.error(line, "Unterminated comment."));
+                            }
+                        }
+                    }
+                }
+                _ => break,
+            }
+        }
+        Ok(())
+    }
+
+    fn read_token(&mut self) -> Result<Option<(Token, usize)>> {
+        self.skip_whitespace()?;
+        let line = self.line;
+        let ch = if let Some(&ch) = self.bytes.get(self.pos) {
+            ch
+        } else {
+            return Ok(None);
+        };
+        self.pos += 1;
+
+        let token = match ch {
+            b'[' => Token::BracketOpen,
+            b']' => Token::BracketClose,
+            b'(' => Token::ParenOpen,
+            b')' => Token::ParenClose,
+            b'{' => Token::CurlyOpen,
+            b'}' => Token::CurlyClose,
+            b',' => Token::Comma,
+            b';' => Token::Semicolon,
+            b'"' => Token::String(self.read_quoted_string(line)?),
+            b':' => {
+                let tag = self.read_identifier();
+                if tag.is_empty() {
+                    return Err(self.error(line, "Expected tag name after \":\"."));
+                }
+                Token::Tag(tag)
+            }
+            b'0'..=b'9' => {
+                self.pos -= 1;
+                Token::Number(self.read_number(line)?)
+            }
+            b'a'..=b'z' | b'A'..=b'Z' | b'_' => {
+                self.pos -= 1;
+                let identifier = self.read_identifier();
+                if identifier == "text" && self.bytes.get(self.pos) == Some(&b':') {
+                    self.pos += 1;
+                    Token::String(self.read_multiline_string(line)?)
+                } else {
+                    Token::Identifier(identifier)
+                }
+            }
+            _ => {
+                return Err(self.error(line, format!("Unexpected character {:?}.", char::from(ch))));
+            }
+        };
+
+        Ok(Some((token, line)))
+    }
+
+    fn read_identifier(&mut self) -> String {
+        let start = self.pos;
+        while let Some(ch) = self.bytes.get(self.pos) {
+            if ch.is_ascii_alphanumeric() || *ch == b'_' {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        String::from_utf8_lossy(&self.bytes[start..self.pos]).to_ascii_lowercase()
+    }
+
+    fn read_number(&mut self, line: usize) -> Result<u64> {
+        let mut number: u64 = 0;
+        while let Some(ch) = self.bytes.get(self.pos) {
+            if ch.is_ascii_digit() {
+                number = number
+                    .checked_mul(10)
+                    .and_then(|n| n.checked_add((*ch - b'0') as u64))
+                    .ok_or_else(|| self.error(line, "Number is too large."))?;
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+
+        let multiplier = match self.bytes.get(self.pos) {
+            Some(b'k' | b'K') => 1024,
+            Some(b'm' | b'M') => 1024 * 1024,
+            Some(b'g' | b'G') => 1024 * 1024 * 1024,
+            _ => 1,
+        };
+        if multiplier != 1 {
+            self.pos += 1;
+        }
+
+        number
+            .checked_mul(multiplier)
+            .ok_or_else(|| self.error(line, "Number is too large."))
+    }
+
+    fn read_quoted_string(&mut self, line: usize) -> Result<String> {
+        let mut value = Vec::new();
+        loop {
+            match self.bytes.get(self.pos) {
+                Some(b'"') => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(b'\\') => {
+                    // Undefined escape sequences drop the backslash (RFC 5228, section 2.4.2)
+                    if let Some(&ch) = self.bytes.get(self.pos + 1) {
+                        if ch == b'\n' {
+                            self.line += 1;
+                        }
+                        value.push(ch);
+                        self.pos += 2;
+                    } else {
+                        self.pos += 1;
+                    }
+                }
+                Some(&ch) => {
+                    if ch == b'\n' {
+                        self.line += 1;
+                    }
+                    value.push(ch);
+                    self.pos += 1;
+                }
+                None => {
+                    return Err(self.error(line, "Unterminated string."));
+                }
+            }
+        }
+        Ok(String::from_utf8(value).unwrap_or_default())
+    }
+
+    fn read_multiline_string(&mut self, line: usize) -> Result<String> {
+        // Skip whitespace and an optional comment up to the end of the line
+        while let Some(&ch) = self.bytes.get(self.pos) {
+            match ch {
+                b' ' | b'\t' | b'\r' => self.pos += 1,
+                b'#' => {
+                    while !matches!(self.bytes.get(self.pos), Some(b'\n') | None) {
+                        self.pos += 1;
+                    }
+                }
+                b'\n' => break,
+                _ => {
+                    return Err(self.error(line, "Expected new line after \"text:\"."));
+                }
+            }
+        }
+        if self.bytes.get(self.pos).is_none() {
+            return Err(self.error(line, "Unterminated multi-line string."));
+        }
+        self.pos += 1;
+        self.line += 1;
+
+        let mut value = String::new();
+        loop {
+            let start = self.pos;
+            while !matches!(self.bytes.get(self.pos), Some(b'\n') | None) {
+                self.pos += 1;
+            }
+            if self.pos == self.bytes.len() {
+                return Err(self.error(line, "Unterminated multi-line string."));
+            }
+            let mut text_line = &self.bytes[start..self.pos];
+            if let Some((b'\r', rest)) = text_line.split_last() {
+                text_line = rest;
+            }
+            self.pos += 1;
+            self.line += 1;
+
+            if text_line == b"." {
+                break;
+            } else if text_line.starts_with(b"..") {
+                text_line = &text_line[1..];
+            }
+            value.push_str(&String::from_utf8_lossy(text_line));
+            value.push_str("\r\n");
+        }
+
+        Ok(value)
+    }
+}
+
+#[derive(Debug)]
+enum Argument {
+    Tag(String),
+    Number(u64),
+    String(String),
+    StringList(Vec<String>),
+}
+
+impl Argument {
+    fn into_string_list(self) -> Option<Vec<String>> {
+        match self {
+            Argument::String(value) => Some(vec![value]),
+            Argument::StringList(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    fn into_string(self) -> Option<String> {
+        match self {
+            Argument::String(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+struct Arguments {
+    line: usize,
+    items: std::vec::IntoIter<Argument>,
+}
+
+impl Arguments {
+    fn next(&mut self) -> Option<Argument> {
+        self.items.next()
+    }
+
+    fn error(&self, message: impl Into<String>) -> CompileError {
+        CompileError {
+            line: self.line,
+            message: message.into(),
+        }
+    }
+
+    fn string(&mut self, name: &str) -> Result<String> {
+        self.next()
+            .and_then(|arg| arg.into_string())
+            .ok_or_else(|| self.error(format!("Expected string argument for {}.", name)))
+    }
+
+    fn string_list(&mut self, name: &str) -> Result<Vec<String>> {
+        self.next()
+            .and_then(|arg| arg.into_string_list())
+            .ok_or_else(|| self.error(format!("Expected string list argument for {}.", name)))
+    }
+
+    fn number(&mut self, name: &str) -> Result<u64> {
+        match self.next() {
+            Some(Argument::Number(value)) => Ok(value),
+            _ => Err(self.error(format!("Expected number argument for {}.", name))),
+        }
+    }
+}
+
+struct Parser<'x> {
+    tokenizer: Tokenizer<'x>,
+    extensions: Vec<String>,
+    has_commands: bool,
+    depth: usize,
+}
+
+impl<'x> Parser<'x> {
+    fn new(script: &'x str) -> Self {
+        Parser {
+            tokenizer: Tokenizer::new(script),
+            extensions: Vec::new(),
+            has_commands: false,
+            depth: 0,
+        }
+    }
+
+    fn parse(mut self) -> Result<Sieve> {
+        let commands = self.parse_commands(false)?;
+        Ok(Sieve { commands })
+    }
+
+    fn error(&self, line: usize, message: impl Into<String>) -> CompileError {
+        CompileError {
+            line,
+            message: message.into(),
+        }
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<()> {
+        match self.tokenizer.next()? {
+            Some((token, _)) if token == expected => Ok(()),
+            Some((token, line)) => {
+                Err(self.error(line, format!("Expected {} but found {}.", expected, token)))
+            }
+            None => Err(self.error(
+                self.tokenizer.line,
+                format!("Expected {} but reached end of script.", expected),
+            )),
+        }
+    }
+
+    fn require(&self, extension: &str, line: usize) -> Result<()> {
+        if self.extensions.iter().any(|e| e == extension) {
+            Ok(())
+        } else {
+            Err(self.error(
+                line,
+                format!(
+                    "Extension {:?} must be declared with \"require\".",
+                    extension
+                ),
+            ))
+        }
+    }
+
+    fn parse_commands(&mut self, is_block: bool) -> Result<Vec<Command>> {
+        let mut commands = Vec::new();
+
+        loop {
+            let (name, line) = match self.tokenizer.next()? {
+                Some((Token::Identifier(name), line)) => (name, line),
+                Some((Token::CurlyClose, _)) if is_block => break,
+                Some((token, line)) => {
+                    return Err(self.error(line, format!("Expected command but found {}.", token)));
+                }
+                None if is_block => {
+                    return Err(self.error(self.tokenizer.line, "Missing \"}\" in block."));
+                }
+                None => break,
+            };
+
+            if name == "require" {
+                if self.has_commands || is_block {
+                    return Err(
+                        self.error(line, "\"require\" must appear before any other command.")
+                    );
+                }
+                let mut arguments = self.parse_arguments(line)?;
+                for extension in arguments.string_list("require")? {
+                    let extension = extension.to_ascii_lowercase();
+                    if !SIEVE_EXTENSIONS.contains(&extension.as_str()) {
+                        return Err(
+                            self.error(line, format!("Unsupported extension {:?}.", extension))
+                        );
+                    }
+                    self.extensions.push(extension);
+                }
+                self.expect_end_of_command(arguments)?;
+                continue;
+            }
+            self.has_commands = true;
+
+            let command = match name.as_str() {
+                "if" => self.parse_if(line)?,
+                "elsif" | "else" => {
+                    return Err(self.error(line, format!("\"{}\" without \"if\".", name)));
+                }
+                "stop" => {
+                    let arguments = self.parse_arguments(line)?;
+                    self.expect_end_of_command(arguments)?;
+                    Command::Stop
+                }
+                "discard" => {
+                    let arguments = self.parse_arguments(line)?;
+                    self.expect_end_of_command(arguments)?;
+                    Command::Discard
+                }
+                "keep" => {
+                    let mut arguments = self.parse_arguments(line)?;
+                    let mut flags = None;
+                    let mut positional = Vec::new();
+                    while let Some(argument) = arguments.next() {
+                        match argument {
+                            Argument::Tag(tag) if tag == "flags" => {
+                                self.require("imap4flags", line)?;
+                                flags = parse_flags(arguments.string_list(":flags")?).into();
+                            }
+                            argument => positional.push(argument),
+                        }
+                    }
+                    self.expect_end_of_command(Arguments {
+                        line,
+                        items: positional.into_iter(),
+                    })?;
+                    Command::Keep { flags }
+                }
+                "fileinto" => {
+                    self.require("fileinto", line)?;
+                    let mut arguments = self.parse_arguments(line)?;
+                    let mut flags = None;
+                    let mut copy = false;
+                    let mut mailbox = None;
+                    while let Some(argument) = arguments.next() {
+                        match argument {
+                            Argument::Tag(tag) if tag == "flags" => {
+                                self.require("imap4flags", line)?;
+                                flags = parse_flags(arguments.string_list(":flags")?).into();
+                            }
+                            Argument::Tag(tag) if tag == "copy" => {
+                                self.require("copy", line)?;
+                                copy = true;
+                            }
+                            Argument::String(value) if mailbox.is_none() => {
+                                mailbox = value.into();
+                            }
+                            argument => {
+                                return Err(self.unexpected_argument(line, &argument));
+                            }
+                        }
+                    }
+                    self.expect_semicolon()?;
+                    Command::FileInto {
+                        mailbox: mailbox
+                            .ok_or_else(|| self.error(line, "Missing mailbox for \"fileinto\"."))?,
+                        flags,
+                        copy,
+                    }
+                }
+                "redirect" => {
+                    let mut arguments = self.parse_arguments(line)?;
+                    let mut copy = false;
+                    let mut address = None;
+                    while let Some(argument) = arguments.next() {
+                        match argument {
+                            Argument::Tag(tag) if tag == "copy" => {
+                                self.require("copy", line)?;
+                                copy = true;
+                            }
+                            Argument::String(value) if address.is_none() => {
+                                if !value.contains('@') {
+                                    return Err(self.error(
+                                        line,
+                                        format!("Invalid redirect address {:?}.", value),
+                                    ));
+                                }
+                                address = value.trim().to_string().into();
+                            }
+                            argument => {
+                                return Err(self.unexpected_argument(line, &argument));
+                            }
+                        }
+                    }
+                    self.expect_semicolon()?;
+                    Command::Redirect {
+                        address: address
+                            .ok_or_else(|| self.error(line, "Missing address for \"redirect\"."))?,
+                        copy,
+                    }
+                }
+                "reject" | "ereject" => {
+                    self.require(&name, line)?;
+                    let mut arguments = self.parse_arguments(line)?;
+                    let reason = arguments.string(&name)?;
+                    self.expect_end_of_command(arguments)?;
+                    Command::Reject { reason }
+                }
+                "vacation" => {
+                    self.require("vacation", line)?;
+                    Command::Vacation(Box::new(self.parse_vacation(line)?))
+                }
+                "setflag" | "addflag" | "removeflag" => {
+                    self.require("imap4flags", line)?;
+                    let mut arguments = self.parse_arguments(line)?;
+                    let flags = parse_flags(arguments.string_list(&name)?);
+                    self.expect_end_of_command(arguments)?;
+                    match name.as_str() {
+                        "setflag" => Command::SetFlag { flags },
+                        "addflag" => Command::AddFlag { flags },
+                        _ => Command::RemoveFlag { flags },
+                    }
+                }
+                _ => {
+                    return Err(self.error(line, format!("Unknown command {:?}.", name)));
+                }
+            };
+
+            commands.push(command);
+        }
+
+        Ok(commands)
+    }
+
+    fn parse_if(&mut self, line: usize) -> Result<Command> {
+        let mut conditions = vec![(self.parse_test()?, self.parse_block(line)?)];
+        let mut otherwise = None;
+
+        loop {
+            match self.tokenizer.peek()? {
+                Some((Token::Identifier(name), _)) if name == "elsif" => {
+                    let (_, line) = self.tokenizer.next()?.unwrap();
+                    conditions.push((self.parse_test()?, self.parse_block(line)?));
+                }
+                Some((Token::Identifier(name), _)) if name == "else" => {
+                    let (_, line) = self.tokenizer.next()?.unwrap();
+                    otherwise = self.parse_block(line)?.into();
+                    break;
+                }
+                _ => break,
+            }
+        }
+
+        Ok(Command::If {
+            conditions,
+            otherwise,
+        })
+    }
+
+    fn parse_block(&mut self, line: usize) -> Result<Vec<Command>> {
+        self.expect(Token::CurlyOpen)?;
+        self.depth += 1;
+        if self.depth > MAX_NESTING_DEPTH {
+            return Err(self.error(line, "Too many nested blocks."));
+        }
+        let commands = self.parse_commands(true)?;
+        self.depth -= 1;
+        Ok(commands)
+    }
+
+    fn parse_vacation(&mut self, line: usize) -> Result<Vacation> {
+        let mut arguments = self.parse_arguments(line)?;
+        let mut vacation = Vacation {
+            days: 7,
+            subject: None,
+            from: None,
+            addresses: Vec::new(),
+            mime: false,
+            handle: None,
+            reason: String::new(),
+        };
+        let mut reason = None;
+
+        while let Some(argument) = arguments.next() {
+            match argument {
+                Argument::Tag(tag) => match tag.as_str() {
+                    "days" => {
+                        vacation.days = arguments.number(":days")?.clamp(1, 30);
+                    }
+                    "subject" => {
+                        vacation.subject = arguments.string(":subject")?.into();
+                    }
+                    "from" => {
+                        vacation.from = arguments.string(":from")?.into();
+                    }
+                    "addresses" => {
+                        vacation.addresses = arguments.string_list(":addresses")?;
+                    }
+                    "mime" => {
+                        vacation.mime = true;
+                    }
+                    "handle" => {
+                        vacation.handle = arguments.string(":handle")?.into();
+                    }
+                    _ => {
+                        return Err(self.unexpected_argument(line, &Argument::Tag(tag)));
+                    }
+                },
+                Argument::String(value) if reason.is_none() => {
+                    reason = value.into();
+                }
+                argument => {
+                    return Err(self.unexpected_argument(line, &argument));
+                }
+            }
+        }
+        self.expect_semicolon()?;
+
+        vacation.reason =
+            reason.ok_or_else(|| self.error(line, "Missing reason for \"vacation\"."))?;
+        Ok(vacation)
+    }
+
+    fn parse_test(&mut self) -> Result<Test> {
+        let (name, line) = match self.tokenizer.next()? {
+            Some((Token::Identifier(name), line)) => (name, line),
+            Some((token, line)) => {
+                return Err(self.error(line, format!("Expected test but found {}.", token)));
+            }
+            None => {
+                return Err(self.error(
+                    self.tokenizer.line,
+                    "Expected test but reached end of script.",
+                ));
+            }
+        };
+
+        Ok(match name.as_str() {
+            "true" => Test::True,
+            "false" => Test::False,
+            "not" => {
+                self.depth += 1;
+                if self.depth > MAX_NESTING_DEPTH {
+                    return Err(self.error(line, "Too many nested tests."));
+                }
+                let test = self.parse_test()?;
+                self.depth -= 1;
+                Test::Not(Box::new(test))
+            }
+            "allof" | "anyof" => {
+                self.expect(Token::ParenOpen)?;
+                self.depth += 1;
+                if self.depth > MAX_NESTING_DEPTH {
+                    return Err(self.error(line, "Too many nested tests."));
+                }
+                let mut tests = vec![self.parse_test()?];
+                loop {
+                    match self.tokenizer.next()? {
+                        Some((Token::Comma, _)) => {
+                            tests.push(self.parse_test()?);
+                        }
+                        Some((Token::ParenClose, _)) => break,
+                        Some((token, line)) => {
+                            return Err(self.error(
+                                line,
+                                format!("Expected \",\" or \")\" but found {}.", token),
+                            ));
+                        }
+                        None => {
+                            return Err(self.error(self.tokenizer.line, "Unterminated test list."));
+                        }
+                    }
+                }
+                self.depth -= 1;
+                if name == "allof" {
+                    Test::AllOf(tests)
+                } else {
+                    Test::AnyOf(tests)
+                }
+            }
+            "exists" => {
+                let mut arguments = self.parse_arguments(line)?;
+                let headers = arguments.string_list("exists")?;
+                self.expect_no_arguments(arguments)?;
+                Test::Exists { headers }
+            }
+            "size" => {
+                let mut arguments = self.parse_arguments(line)?;
+                let over = match arguments.next() {
+                    Some(Argument::Tag(tag)) if tag == "over" => true,
+                    Some(Argument::Tag(tag)) if tag == "under" => false,
+                    _ => {
+                        return Err(self.error(line, "Expected \":over\" or \":under\"."));
+                    }
+                };
+                let limit = arguments.number("size")?;
+                self.expect_no_arguments(arguments)?;
+                Test::Size { over, limit }
+            }
+            "header" | "address" | "envelope" | "hasflag" => {
+                let arguments = self.parse_arguments(line)?;
+                self.parse_match_test(&name, line, arguments)?
+            }
+            _ => {
+                return Err(self.error(line, format!("Unknown test {:?}.", name)));
+            }
+        })
+    }
+
+    fn parse_match_test(&self, name: &str, line: usize, mut arguments: Arguments) -> Result<Test> {
+        let mut match_type = None;
+        let mut comparator = None;
+        let mut address_part = None;
+        let mut positional = Vec::new();
+        let has_address_part = matches!(name, "address" | "envelope");
+
+        match name {
+            "envelope" => self.require("envelope", line)?,
+            "hasflag" => self.require("imap4flags", line)?,
+            _ => (),
+        }
+
+        while let Some(argument) = arguments.next() {
+            match argument {
+                Argument::Tag(tag) => match tag.as_str() {
+                    "is" | "contains" | "matches" if match_type.is_none() => {
+                        match_type = match tag.as_str() {
+                            "is" => MatchType::Is,
+                            "contains" => MatchType::Contains,
+                            _ => MatchType::Matches,
+                        }
+                        .into();
+                    }
+                    "comparator" if comparator.is_none() => {
+                        comparator = match arguments.string(":comparator")?.as_str() {
+                            "i;octet" => Comparator::Octet,
+                            "i;ascii-casemap" => Comparator::AsciiCaseMap,
+                            other => {
+                                return Err(self
+                                    .error(line, format!("Unsupported comparator {:?}.", other)));
+                            }
+                        }
+                        .into();
+                    }
+                    "all" | "localpart" | "domain"
+                        if has_address_part && address_part.is_none() =>
+                    {
+                        address_part = match tag.as_str() {
+                            "all" => AddressPart::All,
+                            "localpart" => AddressPart::LocalPart,
+                            _ => AddressPart::Domain,
+                        }
+                        .into();
+                    }
+                    _ => {
+                        return Err(self.unexpected_argument(line, &Argument::Tag(tag)));
+                    }
+                },
+                argument => {
+                    positional.push(
+                        argument
+                            .into_string_list()
+                            .ok_or_else(|| self.error(line, "Expected string list."))?,
+                    );
+                }
+            }
+        }
+
+        let match_type = match_type.unwrap_or(MatchType::Is);
+        let comparator = comparator.unwrap_or(Comparator::AsciiCaseMap);
+        let part = address_part.unwrap_or(AddressPart::All);
+
+        if name == "hasflag" {
+            return if positional.len() == 1 {
+                Ok(Test::HasFlag {
+                    keys: parse_flags(positional.pop().unwrap()),
+                    match_type,
+                    comparator,
+                })
+            } else {
+                Err(self.error(line, "Expected a list of flags for \"hasflag\"."))
+            };
+        } else if positional.len() != 2 {
+            return Err(self.error(
+                line,
+                format!("Expected header list and key list for {:?}.", name),
+            ));
+        }
+
+        let keys = positional.pop().unwrap();
+        let headers = positional.pop().unwrap();
+
+        Ok(match name {
+            "header" => Test::Header {
+                headers,
+                keys,
+                match_type,
+                comparator,
+            },
+            "address" => Test::Address {
+                headers,
+                keys,
+                part,
+                match_type,
+                comparator,
+            },
+            _ => Test::Envelope {
+                parts: headers
+                    .into_iter()
+                    .map(|part| match part.to_ascii_lowercase().as_str() {
+                        "from" => Ok(EnvelopePart::From),
+                        "to" => Ok(EnvelopePart::To),
+                        _ => {
+                            Err(self.error(line, format!("Unsupported envelope part {:?}.", part)))
+                        }
+                    })
+                    .collect::<Result<Vec<_>>>()?,
+                keys,
+                part,
+                match_type,
+                comparator,
+            },
+        })
+    }
+
+    fn parse_arguments(&mut self, line: usize) -> Result<Arguments> {
+        let mut items = Vec::new();
+
+        loop {
+            match self.tokenizer.peek()? {
+                Some((Token::Tag(_) | Token::Number(_) | Token::String(_), _)) => {
+                    items.push(match self.tokenizer.next()?.unwrap().0 {
+                        Token::Tag(tag) => Argument::Tag(tag),
+                        Token::Number(number) => Argument::Number(number),
+                        Token::String(string) => Argument::String(string),
+                        _ => unreachable!(),
+                    });
+                }
+                Some((Token::BracketOpen, _)) => {
+                    self.tokenizer.next()?;
+                    let mut list = Vec::new();
+                    loop {
+                        match self.tokenizer.next()? {
+                            Some((Token::String(string), _)) => list.push(string),
+                            Some((token, line)) => {
+                                return Err(self
+                                    .error(line, format!("Expected string but found {}.", token)));
+                            }
+                            None => {
+                                return Err(
+                                    self.error(self.tokenizer.line, "Unterminated string list.")
+                                );
+                            }
+                        }
+                        match self.tokenizer.next()? {
+                            Some((Token::Comma, _)) => (),
+                            Some((Token::BracketClose, _)) => break,
+                            Some((token, line)) => {
+                                return Err(self.error(
+                                    line,
+                                    format!("Expected \",\" or \"]\" but found {}.", token),
+                                ));
+                            }
+                            None => {
+                                return Err(
+                                    self.error(self.tokenizer.line, "Unterminated string list.")
+                                );
+                            }
+                        }
+                    }
+                    items.push(Argument::StringList(list));
+                }
+                _ => break,
+            }
+        }
+
+        Ok(Arguments {
+            line,
+            items: items.into_iter(),
+        })
+    }
+
+    fn expect_no_arguments(&self, mut arguments: Arguments) -> Result<()> {
+        if let Some(argument) = arguments.next() {
+            Err(self.unexpected_argument(arguments.line, &argument))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn expect_end_of_command(&mut self, arguments: Arguments) -> Result<()> {
+        self.expect_no_arguments(arguments)?;
+        self.expect_semicolon()
+    }
+
+    fn expect_semicolon(&mut self) -> Result<()> {
+        self.expect(Token::Semicolon)
+    }
+
+    fn unexpected_argument(&self, line: usize, argument: &Argument) -> CompileError {
+        self.error(
+            line,
+            match argument {
+                Argument::Tag(tag) => format!("Unexpected tag \":{}\".", tag),
+                Argument::Number(number) => format!("Unexpected number {}.", number),
+                Argument::String(_) | Argument::StringList(_) => "Unexpected string.".to_string(),
+            },
+        )
+    }
+}
+
+// Flags are separated by spaces and compared case-insensitively (RFC 5232, section 3)
+pub fn parse_flags(flags: Vec<String>) -> Vec<String> {
+    let mut result: Vec<String> = Vec::with_capacity(flags.len());
+    for flag in flags.iter().flat_map(|flags| flags.split_whitespace()) {
+        if !result.iter().any(|f| f.eq_ignore_ascii_case(flag)) {
+            result.push(flag.to_string());
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AddressPart, Command, Comparator, EnvelopePart, MatchType, Sieve, Test};
+
+    #[test]
+    fn compile_sieve() {
+        let sieve = Sieve::compile(
+            concat!(
+                "require [\"fileinto\", \"imap4flags\", \"envelope\", \"vacation\"];\r\n",
+                "# Comment\r\n",
+                "if allof (header :contains \"Subject\" \"TPS\",\r\n",
+                "          not exists \"X-Spam\") {\r\n",
+                "    fileinto :flags \"\\\\Flagged \\\\Seen\" \"Reports\";\r\n",
+                "    stop;\r\n",
+                "} elsif envelope :domain :is \"from\" \"example.org\" {\r\n",
+                "    discard;\r\n",
+                "} else {\r\n",
+                "    /* Multi-line\r\n comment */\r\n",
+                "    vacation :days 3 :subject \"Away\" text:\r\n",
+                "I'm away.\r\n",
+                "..\r\n",
+                ".\r\n",
+                ";\r\n",
+                "}\r\n",
+                "if size :over 1M { addflag \"$Big\"; }\r\n",
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            sieve.commands,
+            vec![
+                Command::If {
+                    conditions: vec![
+                        (
+                            Test::AllOf(vec![
+                                Test::Header {
+                                    headers: vec!["Subject".to_string()],
+                                    keys: vec!["TPS".to_string()],
+                                    match_type: MatchType::Contains,
+                                    comparator: Comparator::AsciiCaseMap,
+                                },
+                                Test::Not(Box::new(Test::Exists {
+                                    headers: vec!["X-Spam".to_string()]
+                                }))
+                            ]),
+                            vec![
+                                Command::FileInto {
+                                    mailbox: "Reports".to_string(),
+                                    flags: Some(vec![
+                                        "\\Flagged".to_string(),
+                                        "\\Seen".to_string()
+                                    ]),
+                                    copy: false
+                                },
+                                Command::Stop
+                            ]
+                        ),
+                        (
+                            Test::Envelope {
+                                parts: vec![EnvelopePart::From],
+                                keys: vec!["example.org".to_string()],
+                                part: AddressPart::Domain,
+                                match_type: MatchType::Is,
+                                comparator: Comparator::AsciiCaseMap,
+                            },
+                            vec![Command::Discard]
+                        )
+                    ],
+                    otherwise: Some(vec![Command::Vacation(Box::new(super::Vacation {
+                        days: 3,
+                        subject: Some("Away".to_string()),
+                        from: None,
+                        addresses: vec![],
+                        mime: false,
+                        handle: None,
+                        reason: "I'm away.\r\n.\r\n".to_string()
+                    }))])
+                },
+                Command::If {
+                    conditions: vec![(
+                        Test::Size {
+                            over: true,
+                            limit: 1024 * 1024
+                        },
+                        vec![Command::AddFlag {
+                            flags: vec!["$Big".to_string()]
+                        }]
+                    )],
+                    otherwise: None
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn compile_sieve_errors() {
+        for (script, expected_error) in [
+            (
+                "fileinto \"Junk\";",
+                "line 1: Extension \"fileinto\" must be declared",
+            ),
+            (
+                "require \"foobar\";",
+                "line 1: Unsupported extension \"foobar\".",
+            ),
+            (
+                "keep;\nrequire \"fileinto\";",
+                "line 2: \"require\" must appear",
+            ),
+            ("if true {\n  keep;\n", "line 3: Missing \"}\" in block."),
+            (
+                "if header :is \"Subject\" { keep; }",
+                "line 1: Expected header list",
+            ),
+            ("frobnicate;", "line 1: Unknown command \"frobnicate\"."),
+            ("keep", "Expected \";\" but reached end of script."),
+            (
+                "if \"true\" { keep; }",
+                "line 1: Expected test but found string.",
+            ),
+            (
+                "redirect \"nobody\";",
+                "line 1: Invalid redirect address \"nobody\".",
+            ),
+            ("discard \"x\";", "line 1: Unexpected string."),
+            ("elsif true { keep; }", "line 1: \"elsif\" without \"if\"."),
+            ("keep; \"unterminated", "line 1: Unterminated string."),
+        ] {
+            let error = Sieve::compile(script.as_bytes()).unwrap_err().to_string();
+            assert!(
+                error.starts_with(expected_error) || error.ends_with(expected_error),
+                "{:?} -> {:?}",
+                script,
+                error
+            );
+        }
+    }
+}
@@ -0,0 +1,300 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::time::SystemTime;
+
+use jmap::jmap_store::get::{default_mapper, GetHelper, GetObject, SharedDocsFnc};
+use jmap::orm::{serialize::JMAPOrm, TinyORM};
+use jmap::request::get::{GetRequest, GetResponse};
+use jmap::types::jmap::JMAPId;
+
+use mail_builder::headers::address::Address;
+use mail_builder::headers::raw::Raw;
+use mail_builder::mime::MimePart;
+use mail_builder::MessageBuilder;
+use store::ahash::AHashMap;
+use store::blob::BlobId;
+use store::core::collection::Collection;
+use store::core::document::Document;
+use store::core::error::StoreError;
+use store::core::vec_map::VecMap;
+use store::read::comparator::Comparator;
+use store::read::filter::{Filter, Query};
+use store::read::FilterMapper;
+use store::tracing::error;
+use store::write::batch::WriteBatch;
+use store::{AccountId, DocumentId, JMAPStore, Store};
+
+use crate::vacation_response::get::VacationMessage;
+
+use super::compiler::Sieve;
+use super::runtime::{MessageHeaders, VacationAction};
+use super::schema::{Property, SieveScript, Value};
+
+impl GetObject for SieveScript {
+    type GetArguments = ();
+
+    fn default_properties() -> Vec<Self::Property> {
+        vec![
+            Property::Id,
+            Property::Name,
+            Property::BlobId,
+            Property::IsActive,
+        ]
+    }
+
+    fn get_as_id(&self, property: &Self::Property) -> Option<Vec<JMAPId>> {
+        match self.properties.get(property)? {
+            Value::Id { value } => Some(vec![*value]),
+            _ => None,
+        }
+    }
+}
+
+pub trait JMAPGetSieveScript<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn sieve_script_get(
+        &self,
+        request: GetRequest<SieveScript>,
+    ) -> jmap::Result<GetResponse<SieveScript>>;
+
+    fn sieve_script_get_active(
+        &self,
+        account_id: AccountId,
+    ) -> store::Result<Option<(DocumentId, Sieve)>>;
+
+    fn build_sieve_vacation_response(
+        &self,
+        account_id: AccountId,
+        document_id: DocumentId,
+        vacation: VacationAction,
+        from: (Option<&str>, &str),
+        to: &str,
+        message: &MessageHeaders,
+    ) -> store::Result<Option<VacationMessage>>;
+}
+
+impl<T> JMAPGetSieveScript<T> for JMAPStore<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn sieve_script_get(
+        &self,
+        request: GetRequest<SieveScript>,
+    ) -> jmap::Result<GetResponse<SieveScript>> {
+        let mut helper =
+            GetHelper::new(self, request, default_mapper.into(), None::<SharedDocsFnc>)?;
+        let account_id = helper.account_id;
+
+        // Add Id Property
+        if !helper.properties.contains(&Property::Id) {
+            helper.properties.push(Property::Id);
+        }
+
+        helper.get(|id, properties| {
+            let mut fields = self
+                .get_orm::<SieveScript>(account_id, id.get_document_id())?
+                .ok_or_else(|| StoreError::NotFound("SieveScript data not found".to_string()))?;
+            let mut sieve_script = VecMap::with_capacity(properties.len());
+
+            for property in properties {
+                sieve_script.append(
+                    *property,
+                    match property {
+                        Property::Id => Value::Id { value: id },
+                        Property::IsActive => Value::Bool {
+                            value: matches!(
+                                fields.get(property),
+                                Some(Value::Bool { value: true })
+                            ),
+                        },
+                        _ => fields.remove(property).unwrap_or_default(),
+                    },
+                );
+            }
+            Ok(Some(SieveScript {
+                properties: sieve_script,
+            }))
+        })
+    }
+
+    fn sieve_script_get_active(
+        &self,
+        account_id: AccountId,
+    ) -> store::Result<Option<(DocumentId, Sieve)>> {
+        let document_id = if let Some(document_id) = self
+            .query_store::<FilterMapper>(
+                account_id,
+                Collection::SieveScript,
+                Filter::eq(Property::IsActive.into(), Query::LongInteger(1)),
+                Comparator::None,
+            )?
+            .get_min()
+        {
+            document_id
+        } else {
+            return Ok(None);
+        };
+
+        let script = self
+            .get_document_value::<BlobId>(
+                account_id,
+                Collection::SieveScript,
+                document_id,
+                Property::BlobId.into(),
+            )?
+            .and_then(|blob_id| self.blob_get(&blob_id).transpose())
+            .transpose()?
+            .ok_or_else(|| {
+                StoreError::NotFound(format!(
+                    "SieveScript blob for {}:{} not found.",
+                    account_id, document_id
+                ))
+            })?;
+
+        // Scripts are validated before being stored, this should not happen.
+        match Sieve::compile(&script) {
+            Ok(sieve) => Ok(Some((document_id, sieve))),
+            Err(err) => {
+                error!(
+                    "Failed to compile active SieveScript {}:{}: {}",
+                    account_id, document_id, err
+                );
+                Ok(None)
+            }
+        }
+    }
+
+    fn build_sieve_vacation_response(
+        &self,
+        account_id: AccountId,
+        document_id: DocumentId,
+        vacation: VacationAction,
+        (from_name, from_addr): (Option<&str>, &str),
+        to: &str,
+        message: &MessageHeaders,
+    ) -> store::Result<Option<VacationMessage>> {
+        let sieve_script =
+            if let Some(sieve_script) = self.get_orm::<SieveScript>(account_id, document_id)? {
+                sieve_script
+            } else {
+                return Ok(None);
+            };
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0) as i64;
+
+        // Make sure we havent emailed this address within the requested period
+        let mut hasher = DefaultHasher::new();
+        vacation.handle.hash(&mut hasher);
+        to.to_lowercase().hash(&mut hasher);
+        let key = format!("{:x}", hasher.finish());
+
+        let mut responses = if let Some(Value::VacationResponses { value }) =
+            sieve_script.get(&Property::VacationResponses_)
+        {
+            value
+                .iter()
+                .filter(|(_, expires)| **expires > now)
+                .map(|(k, v)| (k.to_string(), *v))
+                .collect::<AHashMap<_, _>>()
+        } else {
+            AHashMap::new()
+        };
+        if responses.contains_key(&key) {
+            return Ok(None);
+        }
+        responses.insert(key, now + (vacation.days as i64 * 86400));
+
+        // Update the sieve script object with the new addresses
+        let mut new_sieve_script = TinyORM::track_changes(&sieve_script);
+        new_sieve_script.set(
+            Property::VacationResponses_,
+            Value::VacationResponses { value: responses },
+        );
+
+        // Build vacation response
+        let from_addr = vacation.from.as_deref().unwrap_or(from_addr);
+        let mut builder = MessageBuilder::new()
+            .from(
+                from_name
+                    .filter(|_| vacation.from.is_none())
+                    .map(|from_name| Address::from((from_name, from_addr)))
+                    .unwrap_or_else(|| Address::from(from_addr)),
+            )
+            .to(to)
+            .subject(vacation.subject.unwrap_or_else(|| {
+                format!("Auto: {}", message.get("subject").unwrap_or("Your message"))
+            }))
+            .header("Auto-Submitted", Raw::from("auto-replied".to_string()));
+        if let Some(message_id) = message.get("message-id") {
+            builder = builder
+                .header("In-Reply-To", Raw::from(message_id.to_string()))
+                .header(
+                    "References",
+                    Raw::from(if let Some(references) = message.get("references") {
+                        format!("{} {}", references, message_id)
+                    } else {
+                        message_id.to_string()
+                    }),
+                );
+        }
+
+        let message = if vacation.mime {
+            // The reason is a MIME entity, use its Content-Type and body
+            let reason = MessageHeaders::parse(vacation.reason.as_bytes());
+            let body = vacation
+                .reason
+                .split_once("\r\n\r\n")
+                .map(|(_, body)| body)
+                .unwrap_or("");
+            builder.body(MimePart::new_binary(
+                reason.get("content-type").unwrap_or("text/plain"),
+                body.as_bytes(),
+            ))
+        } else {
+            builder.text_body(vacation.reason)
+        }
+        .write_to_vec()
+        .unwrap_or_default();
+
+        // Save changes
+        let mut batch = WriteBatch::new(account_id);
+        let mut document = Document::new(Collection::SieveScript, document_id);
+        sieve_script.merge(&mut document, new_sieve_script)?;
+        batch.update_document(document);
+        batch.log_update(Collection::SieveScript, document_id as u64);
+        self.write(batch)?;
+
+        Ok(Some(VacationMessage {
+            from: from_addr.to_string(),
+            to: to.to_string(),
+            message,
+        }))
+    }
+}
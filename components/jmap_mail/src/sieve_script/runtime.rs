@@ -0,0 +1,653 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use super::compiler::{
+    parse_flags, AddressPart, Command, Comparator, EnvelopePart, MatchType, Sieve, Test, Vacation,
+};
+
+pub struct Envelope<'x> {
+    pub from: &'x str,
+    pub to: &'x str,
+}
+
+pub struct MessageHeaders {
+    pub headers: Vec<(String, String)>,
+    pub size: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    Keep { flags: Vec<String> },
+    FileInto { mailbox: String, flags: Vec<String> },
+    Redirect { address: String },
+    Reject { reason: String },
+    Vacation(VacationAction),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VacationAction {
+    pub days: u64,
+    pub subject: Option<String>,
+    pub from: Option<String>,
+    pub mime: bool,
+    pub handle: String,
+    pub reason: String,
+}
+
+struct Runtime<'x> {
+    envelope: &'x Envelope<'x>,
+    message: &'x MessageHeaders,
+    flags: Vec<String>,
+    actions: Vec<Action>,
+    implicit_keep: bool,
+    max_redirects: usize,
+}
+
+impl Sieve {
+    pub fn run(
+        &self,
+        envelope: &Envelope,
+        message: &MessageHeaders,
+        max_redirects: usize,
+    ) -> Vec<Action> {
+        let mut runtime = Runtime {
+            envelope,
+            message,
+            flags: Vec::new(),
+            actions: Vec::new(),
+            implicit_keep: true,
+            max_redirects,
+        };
+        runtime.execute(&self.commands);
+
+        // A rejected message cannot be delivered nor redirected (RFC 5429 section 2.3),
+        // the sender is notified through the bounce instead.
+        if runtime
+            .actions
+            .iter()
+            .any(|action| matches!(action, Action::Reject { .. }))
+        {
+            runtime
+                .actions
+                .retain(|action| matches!(action, Action::Reject { .. }));
+        } else if runtime.implicit_keep {
+            runtime.actions.push(Action::Keep {
+                flags: runtime.flags,
+            });
+        }
+
+        runtime.actions
+    }
+}
+
+impl<'x> Runtime<'x> {
+    // Returns false when the script execution has to stop
+    fn execute(&mut self, commands: &[Command]) -> bool {
+        for command in commands {
+            match command {
+                Command::If {
+                    conditions,
+                    otherwise,
+                } => {
+                    let mut block = otherwise.as_ref();
+                    for (test, commands) in conditions {
+                        if self.eval(test) {
+                            block = Some(commands);
+                            break;
+                        }
+                    }
+                    if let Some(commands) = block {
+                        if !self.execute(commands) {
+                            return false;
+                        }
+                    }
+                }
+                Command::Stop => return false,
+                Command::Keep { flags } => {
+                    self.implicit_keep = false;
+                    self.actions.push(Action::Keep {
+                        flags: flags.as_ref().unwrap_or(&self.flags).clone(),
+                    });
+                }
+                Command::Discard => {
+                    self.implicit_keep = false;
+                }
+                Command::FileInto {
+                    mailbox,
+                    flags,
+                    copy,
+                } => {
+                    if !copy {
+                        self.implicit_keep = false;
+                    }
+                    self.actions.push(Action::FileInto {
+                        mailbox: mailbox.to_string(),
+                        flags: flags.as_ref().unwrap_or(&self.flags).clone(),
+                    });
+                }
+                Command::Redirect { address, copy } => {
+                    if self
+                        .actions
+                        .iter()
+                        .filter(|action| matches!(action, Action::Redirect { .. }))
+                        .count()
+                        < self.max_redirects
+                    {
+                        if !copy {
+                            self.implicit_keep = false;
+                        }
+                        if !self.actions.iter().any(|action| {
+                            matches!(action, Action::Redirect { address: a } if a.eq_ignore_ascii_case(address))
+                        }) {
+                            self.actions.push(Action::Redirect {
+                                address: address.to_string(),
+                            });
+                        }
+                    }
+                }
+                Command::Reject { reason } => {
+                    self.implicit_keep = false;
+                    self.actions.push(Action::Reject {
+                        reason: reason.to_string(),
+                    });
+                }
+                Command::Vacation(vacation) => {
+                    if self.should_reply(vacation)
+                        && !self
+                            .actions
+                            .iter()
+                            .any(|action| matches!(action, Action::Vacation(_)))
+                    {
+                        self.actions.push(Action::Vacation(VacationAction {
+                            days: vacation.days,
+                            subject: vacation.subject.clone(),
+                            from: vacation.from.clone(),
+                            mime: vacation.mime,
+                            handle: vacation.handle.clone().unwrap_or_else(|| {
+                                format!(
+                                    "{}\n{}",
+                                    vacation.subject.as_deref().unwrap_or(""),
+                                    vacation.reason
+                                )
+                            }),
+                            reason: vacation.reason.to_string(),
+                        }));
+                    }
+                }
+                Command::SetFlag { flags } => {
+                    self.flags = flags.clone();
+                }
+                Command::AddFlag { flags } => {
+                    self.flags = parse_flags(
+                        self.flags
+                            .iter()
+                            .chain(flags.iter())
+                            .cloned()
+                            .collect::<Vec<_>>(),
+                    );
+                }
+                Command::RemoveFlag { flags } => {
+                    self.flags
+                        .retain(|flag| !flags.iter().any(|f| f.eq_ignore_ascii_case(flag)));
+                }
+            }
+        }
+
+        true
+    }
+
+    fn eval(&self, test: &Test) -> bool {
+        match test {
+            Test::True => true,
+            Test::False => false,
+            Test::Not(test) => !self.eval(test),
+            Test::AllOf(tests) => tests.iter().all(|test| self.eval(test)),
+            Test::AnyOf(tests) => tests.iter().any(|test| self.eval(test)),
+            Test::Exists { headers } => headers
+                .iter()
+                .all(|header| self.header_values(header).next().is_some()),
+            Test::Size { over, limit } => {
+                if *over {
+                    self.message.size as u64 > *limit
+                } else {
+                    (self.message.size as u64) < *limit
+                }
+            }
+            Test::Header {
+                headers,
+                keys,
+                match_type,
+                comparator,
+            } => headers.iter().any(|header| {
+                self.header_values(header).any(|value| {
+                    keys.iter()
+                        .any(|key| matches(value, key, *match_type, *comparator))
+                })
+            }),
+            Test::Address {
+                headers,
+                keys,
+                part,
+                match_type,
+                comparator,
+            } => headers.iter().any(|header| {
+                self.header_values(header).any(|value| {
+                    parse_addresses(value).iter().any(|address| {
+                        let address = address_part(address, *part);
+                        keys.iter()
+                            .any(|key| matches(address, key, *match_type, *comparator))
+                    })
+                })
+            }),
+            Test::Envelope {
+                parts,
+                keys,
+                part,
+                match_type,
+                comparator,
+            } => parts.iter().any(|envelope_part| {
+                let address = address_part(
+                    match envelope_part {
+                        EnvelopePart::From => self.envelope.from,
+                        EnvelopePart::To => self.envelope.to,
+                    },
+                    *part,
+                );
+                keys.iter()
+                    .any(|key| matches(address, key, *match_type, *comparator))
+            }),
+            Test::HasFlag {
+                keys,
+                match_type,
+                comparator,
+            } => self.flags.iter().any(|flag| {
+                keys.iter()
+                    .any(|key| matches(flag, key, *match_type, *comparator))
+            }),
+        }
+    }
+
+    fn header_values<'y>(&'y self, name: &'y str) -> impl Iterator<Item = &'y str> + 'y {
+        self.message
+            .headers
+            .iter()
+            .filter(move |(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    // Implements the checks from RFC 5230, section 4.5
+    fn should_reply(&self, vacation: &Vacation) -> bool {
+        if self.envelope.from.is_empty()
+            || self.envelope.from.eq_ignore_ascii_case(self.envelope.to)
+            || self
+                .header_values("auto-submitted")
+                .any(|value| !value.trim().eq_ignore_ascii_case("no"))
+            || self.header_values("precedence").any(|value| {
+                matches!(
+                    value.trim().to_ascii_lowercase().as_str(),
+                    "bulk" | "list" | "junk"
+                )
+            })
+            || self.header_values("list-id").next().is_some()
+        {
+            return false;
+        }
+
+        ["to", "cc", "bcc", "resent-to", "resent-cc", "resent-bcc"]
+            .iter()
+            .any(|header| {
+                self.header_values(header).any(|value| {
+                    parse_addresses(value).iter().any(|address| {
+                        address.eq_ignore_ascii_case(self.envelope.to)
+                            || vacation
+                                .addresses
+                                .iter()
+                                .any(|a| a.trim().eq_ignore_ascii_case(address))
+                    })
+                })
+            })
+    }
+}
+
+impl MessageHeaders {
+    pub fn parse(raw_message: &[u8]) -> Self {
+        let mut headers: Vec<(String, String)> = Vec::new();
+
+        for line in raw_message.split(|&ch| ch == b'\n') {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            if line.is_empty() {
+                break;
+            } else if line[0] == b' ' || line[0] == b'\t' {
+                // Unfold header
+                if let Some((_, value)) = headers.last_mut() {
+                    value.push_str(&String::from_utf8_lossy(line));
+                }
+            } else if let Some(pos) = line.iter().position(|&ch| ch == b':') {
+                headers.push((
+                    String::from_utf8_lossy(&line[..pos]).trim().to_string(),
+                    String::from_utf8_lossy(&line[pos + 1..]).to_string(),
+                ));
+            }
+        }
+
+        for (_, value) in headers.iter_mut() {
+            *value = value.trim().to_string();
+        }
+
+        MessageHeaders {
+            headers,
+            size: raw_message.len(),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn get_all<'x>(&'x self, name: &'x str) -> impl Iterator<Item = &'x str> + 'x {
+        self.headers
+            .iter()
+            .filter(move |(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+fn matches(value: &str, key: &str, match_type: MatchType, comparator: Comparator) -> bool {
+    let (value, key) = match comparator {
+        Comparator::Octet => (value.to_string(), key.to_string()),
+        Comparator::AsciiCaseMap => (value.to_ascii_lowercase(), key.to_ascii_lowercase()),
+    };
+
+    match match_type {
+        MatchType::Is => value == key,
+        MatchType::Contains => value.contains(&key),
+        MatchType::Matches => {
+            let value = value.chars().collect::<Vec<_>>();
+            let mut pattern = Vec::new();
+            let mut chars = key.chars();
+            while let Some(ch) = chars.next() {
+                pattern.push(match ch {
+                    '*' => Wildcard::Any,
+                    '?' => Wildcard::One,
+                    '\\' => Wildcard::Char(chars.next().unwrap_or('\\')),
+                    ch => Wildcard::Char(ch),
+                });
+            }
+            glob_match(&value, &pattern)
+        }
+    }
+}
+
+enum Wildcard {
+    Any,
+    One,
+    Char(char),
+}
+
+fn glob_match(value: &[char], pattern: &[Wildcard]) -> bool {
+    let mut value_pos = 0;
+    let mut pattern_pos = 0;
+    let mut backtrack = None;
+
+    while value_pos < value.len() {
+        match pattern.get(pattern_pos) {
+            Some(Wildcard::Any) => {
+                pattern_pos += 1;
+                backtrack = Some((pattern_pos, value_pos));
+                continue;
+            }
+            Some(Wildcard::One) => {
+                value_pos += 1;
+                pattern_pos += 1;
+                continue;
+            }
+            Some(Wildcard::Char(ch)) if *ch == value[value_pos] => {
+                value_pos += 1;
+                pattern_pos += 1;
+                continue;
+            }
+            _ => (),
+        }
+
+        if let Some((backtrack_pattern, backtrack_value)) = backtrack {
+            pattern_pos = backtrack_pattern;
+            value_pos = backtrack_value + 1;
+            backtrack = Some((backtrack_pattern, value_pos));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[pattern_pos..]
+        .iter()
+        .all(|wildcard| matches!(wildcard, Wildcard::Any))
+}
+
+fn address_part(address: &str, part: AddressPart) -> &str {
+    match part {
+        AddressPart::All => address,
+        AddressPart::LocalPart => address
+            .rsplit_once('@')
+            .map(|(local, _)| local)
+            .unwrap_or(address),
+        AddressPart::Domain => address
+            .rsplit_once('@')
+            .map(|(_, domain)| domain)
+            .unwrap_or(""),
+    }
+}
+
+// Extracts the e-mail addresses from an address list header
+pub fn parse_addresses(value: &str) -> Vec<String> {
+    let mut addresses = Vec::new();
+    let mut address = String::new();
+    let mut in_quote = false;
+    let mut in_angle = false;
+    let mut angle_address = None;
+    let mut comment_depth = 0;
+    let mut chars = value.chars();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' if in_quote || comment_depth > 0 => {
+                chars.next();
+            }
+            '"' if comment_depth == 0 => {
+                in_quote = !in_quote;
+            }
+            '(' if !in_quote => {
+                comment_depth += 1;
+            }
+            ')' if !in_quote && comment_depth > 0 => {
+                comment_depth -= 1;
+            }
+            _ if in_quote || comment_depth > 0 => (),
+            '<' => {
+                in_angle = true;
+                address.clear();
+            }
+            '>' if in_angle => {
+                in_angle = false;
+                angle_address = std::mem::take(&mut address).into();
+            }
+            ':' if !in_angle => {
+                // Group name
+                address.clear();
+            }
+            ',' | ';' if !in_angle => {
+                let addr = angle_address.take().unwrap_or_else(|| address.clone());
+                if !addr.trim().is_empty() {
+                    addresses.push(addr.trim().to_string());
+                }
+                address.clear();
+            }
+            _ => {
+                address.push(ch);
+            }
+        }
+    }
+
+    let addr = angle_address.unwrap_or(address);
+    if !addr.trim().is_empty() {
+        addresses.push(addr.trim().to_string());
+    }
+
+    addresses
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_addresses, Action, Envelope, MessageHeaders, VacationAction};
+    use crate::sieve_script::compiler::Sieve;
+
+    const MESSAGE: &str = concat!(
+        "From: \"Bill Lumbergh\" <bill@example.com>\r\n",
+        "To: John Doe <jdoe@example.com>,\r\n",
+        " jane@example.com\r\n",
+        "Subject: TPS Report\r\n",
+        "X-Priority: 1\r\n",
+        "\r\n",
+        "I'm going to need those TPS reports ASAP.\r\n"
+    );
+
+    fn run(script: &str) -> Vec<Action> {
+        Sieve::compile(script.as_bytes()).unwrap().run(
+            &Envelope {
+                from: "bill@example.com",
+                to: "jdoe@example.com",
+            },
+            &MessageHeaders::parse(MESSAGE.as_bytes()),
+            1,
+        )
+    }
+
+    fn keep(flags: &[&str]) -> Action {
+        Action::Keep {
+            flags: flags.iter().map(|f| f.to_string()).collect(),
+        }
+    }
+
+    fn fileinto(mailbox: &str, flags: &[&str]) -> Action {
+        Action::FileInto {
+            mailbox: mailbox.to_string(),
+            flags: flags.iter().map(|f| f.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn run_sieve() {
+        for (script, expected_actions) in [
+            ("", vec![keep(&[])]),
+            ("discard;", vec![]),
+            (
+                "require \"fileinto\"; fileinto \"Reports\";",
+                vec![fileinto("Reports", &[])],
+            ),
+            (
+                concat!(
+                    "require [\"fileinto\", \"copy\", \"imap4flags\"];",
+                    "if header :matches \"subject\" \"tps*\" { addflag \"\\\\Seen\"; ",
+                    "fileinto :copy \"Reports\"; }"
+                ),
+                vec![fileinto("Reports", &["\\Seen"]), keep(&["\\Seen"])],
+            ),
+            (
+                "if address :domain :is \"to\" \"example.com\" { keep; } discard;",
+                vec![keep(&[])],
+            ),
+            (
+                "if address :localpart :is \"to\" \"nobody\" { keep; } else { discard; }",
+                vec![],
+            ),
+            (
+                concat!(
+                    "require [\"envelope\", \"reject\"];",
+                    "if envelope :all :contains \"from\" \"@example.com\" { ",
+                    "redirect \"bill@example.org\"; reject \"Go away.\"; }"
+                ),
+                vec![Action::Reject {
+                    reason: "Go away.".to_string(),
+                }],
+            ),
+            (
+                "redirect \"a@example.org\"; redirect \"b@example.org\";",
+                vec![Action::Redirect {
+                    address: "a@example.org".to_string(),
+                }],
+            ),
+            (
+                "if anyof (size :over 10K, not exists [\"X-Priority\"]) { discard; }",
+                vec![keep(&[])],
+            ),
+            (
+                "if allof (size :under 1K, header :is \"x-priority\" \"1\") { stop; } discard;",
+                vec![keep(&[])],
+            ),
+            (
+                concat!(
+                    "require \"imap4flags\"; setflag [\"\\\\Flagged\", \"$Work\"];",
+                    "removeflag \"$work\";",
+                    "if hasflag :is \"\\\\flagged\" { keep :flags \"$Urgent\"; }"
+                ),
+                vec![keep(&["$Urgent"])],
+            ),
+            (
+                "require \"vacation\"; vacation :days 5 :handle \"away\" \"I'm away.\";",
+                vec![
+                    Action::Vacation(VacationAction {
+                        days: 5,
+                        subject: None,
+                        from: None,
+                        mime: false,
+                        handle: "away".to_string(),
+                        reason: "I'm away.".to_string(),
+                    }),
+                    keep(&[]),
+                ],
+            ),
+        ] {
+            assert_eq!(run(script), expected_actions, "{}", script);
+        }
+    }
+
+    #[test]
+    fn parse_address_list() {
+        for (value, expected_addresses) in [
+            ("jdoe@example.com", vec!["jdoe@example.com"]),
+            (
+                "\"Doe, John\" <jdoe@example.com>, jane@example.com (Jane)",
+                vec!["jdoe@example.com", "jane@example.com"],
+            ),
+            (
+                "Team: bill@example.com, <ann@example.com>;",
+                vec!["bill@example.com", "ann@example.com"],
+            ),
+            ("undisclosed-recipients:;", vec![]),
+        ] {
+            assert_eq!(parse_addresses(value), expected_addresses, "{}", value);
+        }
+    }
+}
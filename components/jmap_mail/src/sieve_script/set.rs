@@ -0,0 +1,415 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap::error::set::{SetError, SetErrorType};
+use jmap::jmap_store::set::SetHelper;
+use jmap::jmap_store::Object;
+use jmap::orm::{serialize::JMAPOrm, TinyORM};
+use jmap::request::set::SetResponse;
+use jmap::request::{MaybeIdReference, ResultReference};
+use jmap::types::blob::JMAPBlob;
+use jmap::types::jmap::JMAPId;
+use jmap::{jmap_store::set::SetObject, request::set::SetRequest};
+use store::blob::BlobId;
+use store::core::collection::Collection;
+use store::core::document::Document;
+use store::core::error::StoreError;
+use store::core::vec_map::VecMap;
+use store::read::comparator::Comparator;
+use store::read::filter::{Filter, Query};
+use store::read::FilterMapper;
+use store::serialize::StoreSerialize;
+use store::write::options::IndexOptions;
+use store::{AccountId, DocumentId, JMAPStore, Store};
+
+use crate::mail::get::{BlobResult, JMAPGetMail};
+
+use super::compiler::Sieve;
+use super::schema::{Property, SieveScript, Value};
+
+#[derive(Debug, Clone, Default)]
+pub struct SetArguments {
+    pub on_success_activate_script: Option<MaybeIdReference>,
+    pub on_success_deactivate_script: bool,
+}
+
+impl SetObject for SieveScript {
+    type SetArguments = SetArguments;
+
+    type NextCall = ();
+
+    fn eval_id_references(&mut self, _fnc: impl FnMut(&str) -> Option<JMAPId>) {}
+    fn eval_result_references(&mut self, _fnc: impl FnMut(&ResultReference) -> Option<Vec<u64>>) {}
+}
+
+pub trait JMAPSetSieveScript<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn sieve_script_set(
+        &self,
+        request: SetRequest<SieveScript>,
+    ) -> jmap::Result<SetResponse<SieveScript>>;
+
+    fn sieve_script_delete(
+        &self,
+        account_id: AccountId,
+        document: &mut Document,
+    ) -> store::Result<()>;
+}
+
+impl<T> JMAPSetSieveScript<T> for JMAPStore<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn sieve_script_set(
+        &self,
+        request: SetRequest<SieveScript>,
+    ) -> jmap::Result<SetResponse<SieveScript>> {
+        let mut helper = SetHelper::new(self, request)?;
+        let activate_id = helper.request.arguments.on_success_activate_script.take();
+        let deactivate = helper.request.arguments.on_success_deactivate_script;
+        let mut activated_id = None;
+
+        helper.create(|create_id, item, helper, document| {
+            if helper.document_ids.len() as usize + helper.response.created.len()
+                >= helper.store.config.sieve_max_scripts
+            {
+                return Err(SetError::new(
+                    SetErrorType::OverQuota,
+                    "Maximum number of Sieve scripts reached.",
+                ));
+            }
+
+            let mut fields = TinyORM::<SieveScript>::new();
+            let mut blob_id = None;
+
+            for (property, value) in item.properties {
+                match (property, value) {
+                    (Property::Name, Value::Text { value }) => {
+                        helper.validate_sieve_script_name(&value, None)?;
+                        fields.set(Property::Name, Value::Text { value });
+                    }
+                    (Property::Name, Value::Null) => (),
+                    (Property::BlobId, Value::BlobId { value }) => {
+                        helper.validate_sieve_script_blob(&value)?;
+                        blob_id = value.into();
+                    }
+                    (Property::IsActive, _) => {
+                        return Err(SetError::invalid_property(
+                            property,
+                            "Use onSuccessActivateScript to activate scripts.",
+                        ));
+                    }
+                    (property, _) => {
+                        return Err(SetError::invalid_property(
+                            property,
+                            "Field could not be set.",
+                        ));
+                    }
+                }
+            }
+
+            // Link blob
+            let blob_id = blob_id
+                .ok_or_else(|| SetError::invalid_property(Property::BlobId, "Missing blobId."))?;
+            document.binary(
+                Property::BlobId,
+                blob_id.id.serialize().unwrap(),
+                IndexOptions::new(),
+            );
+            document.blob(blob_id.id.clone(), IndexOptions::new());
+            fields.set(Property::BlobId, Value::BlobId { value: blob_id });
+
+            // Activate the script if it was referenced by onSuccessActivateScript
+            let is_active =
+                matches!(&activate_id, Some(MaybeIdReference::Reference(id)) if id == create_id);
+            fields.set(Property::IsActive, Value::Bool { value: is_active });
+
+            // Validate fields
+            fields.insert_validate(document)?;
+
+            let id = JMAPId::from(document.document_id);
+            if is_active {
+                activated_id = id.into();
+            }
+            Ok(SieveScript::new(id))
+        })?;
+
+        helper.update(|id, item, helper, document| {
+            let current_fields = self
+                .get_orm::<SieveScript>(helper.account_id, id.get_document_id())?
+                .ok_or_else(|| SetError::new_err(SetErrorType::NotFound))?;
+            let mut fields = TinyORM::track_changes(&current_fields);
+
+            for (property, value) in item.properties {
+                match (property, value) {
+                    (Property::Name, Value::Text { value }) => {
+                        helper.validate_sieve_script_name(&value, id.get_document_id().into())?;
+                        fields.set(Property::Name, Value::Text { value });
+                    }
+                    (Property::Name, Value::Null) => {
+                        fields.set(Property::Name, Value::Null);
+                    }
+                    (Property::BlobId, Value::BlobId { value }) => {
+                        helper.validate_sieve_script_blob(&value)?;
+
+                        // Unlink previous blob
+                        if let Some(Value::BlobId { value }) = current_fields.get(&Property::BlobId)
+                        {
+                            document.blob(value.id.clone(), IndexOptions::new().clear());
+                        }
+
+                        // Link new blob
+                        document.binary(
+                            Property::BlobId,
+                            value.id.serialize().unwrap(),
+                            IndexOptions::new(),
+                        );
+                        document.blob(value.id.clone(), IndexOptions::new());
+                        fields.set(Property::BlobId, Value::BlobId { value });
+                    }
+                    (Property::IsActive, _) => {
+                        return Err(SetError::invalid_property(
+                            property,
+                            "Use onSuccessActivateScript to activate scripts.",
+                        ));
+                    }
+                    (property, _) => {
+                        return Err(SetError::invalid_property(
+                            property,
+                            "Field could not be set.",
+                        ));
+                    }
+                }
+            }
+
+            // Merge changes
+            current_fields.merge_validate(document, fields)?;
+            Ok(None)
+        })?;
+
+        helper.destroy(|_id, helper, document| {
+            if let Some(orm) =
+                self.get_orm::<SieveScript>(helper.account_id, document.document_id)?
+            {
+                if let Some(Value::Bool { value: true }) = orm.get(&Property::IsActive) {
+                    return Err(SetError::new(
+                        SetErrorType::SieveIsActive,
+                        "Deactivate this script before destroying it.",
+                    ));
+                }
+                self.sieve_script_delete(helper.account_id, document)?;
+            }
+            Ok(())
+        })?;
+
+        // Activate or deactivate scripts
+        if (activate_id.is_some() || deactivate)
+            && helper.response.not_created.is_empty()
+            && helper.response.not_updated.is_empty()
+            && helper.response.not_destroyed.is_empty()
+        {
+            let activate_id = if activated_id.is_none() {
+                match &activate_id {
+                    Some(MaybeIdReference::Value(id))
+                        if helper.document_ids.contains(id.get_document_id())
+                            && !helper.response.destroyed.contains(id) =>
+                    {
+                        Some(*id)
+                    }
+                    _ => None,
+                }
+            } else {
+                None
+            };
+
+            let mut changed_ids = Vec::with_capacity(2);
+            for document_id in self.query_store::<FilterMapper>(
+                helper.account_id,
+                Collection::SieveScript,
+                Filter::eq(Property::IsActive.into(), Query::LongInteger(1)),
+                Comparator::None,
+            )? {
+                let id = JMAPId::from(document_id);
+                if activate_id != Some(id) && activated_id != Some(id) {
+                    changed_ids.push((id, false));
+                }
+            }
+            if let Some(id) = activate_id {
+                changed_ids.push((id, true));
+            }
+
+            for (id, is_active) in changed_ids {
+                let current_fields = self
+                    .get_orm::<SieveScript>(helper.account_id, id.get_document_id())?
+                    .ok_or_else(|| {
+                        StoreError::NotFound(format!(
+                            "SieveScript ORM data for {}:{} not found.",
+                            helper.account_id,
+                            id.get_document_id()
+                        ))
+                    })?;
+                if matches!(current_fields.get(&Property::IsActive), Some(Value::Bool { value }) if *value == is_active)
+                {
+                    continue;
+                }
+                let mut fields = TinyORM::track_changes(&current_fields);
+                fields.set(Property::IsActive, Value::Bool { value: is_active });
+                let mut document = Document::new(Collection::SieveScript, id.get_document_id());
+                current_fields.merge(&mut document, fields)?;
+                helper.changes.update_document(document);
+                helper.changes.log_update(Collection::SieveScript, id);
+
+                let mut properties = VecMap::with_capacity(1);
+                properties.append(Property::IsActive, Value::Bool { value: is_active });
+                helper
+                    .response
+                    .updated
+                    .append(id, SieveScript { properties }.into());
+            }
+        }
+
+        helper.into_response()
+    }
+
+    fn sieve_script_delete(
+        &self,
+        account_id: AccountId,
+        document: &mut Document,
+    ) -> store::Result<()> {
+        let document_id = document.document_id;
+        let sieve_script = self
+            .get_orm::<SieveScript>(account_id, document_id)?
+            .ok_or_else(|| {
+                StoreError::NotFound(format!(
+                    "SieveScript ORM data for {}:{} not found.",
+                    account_id, document_id
+                ))
+            })?;
+
+        // Delete ORM
+        sieve_script.delete(document);
+
+        // Unlink blob
+        if let Some(blob_id) = self.get_document_value::<BlobId>(
+            account_id,
+            Collection::SieveScript,
+            document_id,
+            Property::BlobId.into(),
+        )? {
+            document.blob(blob_id, IndexOptions::new().clear());
+            document.binary(
+                Property::BlobId,
+                Vec::with_capacity(0),
+                IndexOptions::new().clear(),
+            );
+            Ok(())
+        } else {
+            Err(StoreError::NotFound(format!(
+                "SieveScript Blob for {}:{} not found.",
+                account_id, document_id
+            )))
+        }
+    }
+}
+
+trait SieveScriptValidator {
+    fn validate_sieve_script_name(
+        &self,
+        name: &str,
+        document_id: Option<DocumentId>,
+    ) -> jmap::error::set::Result<(), Property>;
+
+    fn validate_sieve_script_blob(
+        &self,
+        blob_id: &JMAPBlob,
+    ) -> jmap::error::set::Result<(), Property>;
+}
+
+impl<T> SieveScriptValidator for SetHelper<'_, SieveScript, T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn validate_sieve_script_name(
+        &self,
+        name: &str,
+        document_id: Option<DocumentId>,
+    ) -> jmap::error::set::Result<(), Property> {
+        if name.is_empty() || name.len() > self.store.config.sieve_max_script_name_len {
+            return Err(SetError::invalid_property(
+                Property::Name,
+                "Invalid script name length.",
+            ));
+        } else if name.contains(|ch: char| ch.is_control()) {
+            return Err(SetError::invalid_property(
+                Property::Name,
+                "Script name contains invalid characters.",
+            ));
+        }
+
+        if self
+            .store
+            .query_store::<FilterMapper>(
+                self.account_id,
+                Collection::SieveScript,
+                Filter::eq(Property::Name.into(), Query::Index(name.to_string())),
+                Comparator::None,
+            )?
+            .into_iter()
+            .any(|id| Some(id.get_document_id()) != document_id)
+        {
+            return Err(SetError::new(
+                SetErrorType::AlreadyExists,
+                format!("A script with name '{}' already exists.", name),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn validate_sieve_script_blob(
+        &self,
+        blob_id: &JMAPBlob,
+    ) -> jmap::error::set::Result<(), Property> {
+        match self
+            .store
+            .mail_blob_get(self.account_id, &self.acl, blob_id)?
+        {
+            BlobResult::Blob(script) if script.len() > self.store.config.sieve_max_script_size => {
+                Err(SetError::new(
+                    SetErrorType::TooLarge,
+                    "Script exceeds the maximum allowed size.",
+                ))
+            }
+            BlobResult::Blob(script) => match Sieve::compile(&script) {
+                Ok(_) => Ok(()),
+                Err(err) => Err(SetError::new(SetErrorType::InvalidSieve, err.to_string())),
+            },
+            BlobResult::NotFound | BlobResult::Unauthorized => Err(SetError::new(
+                SetErrorType::BlobNotFound,
+                format!("BlobId {} not found.", blob_id),
+            )),
+        }
+    }
+}
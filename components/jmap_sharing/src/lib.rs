@@ -22,4 +22,5 @@
 */
 
 pub mod principal;
+pub mod quota;
 pub use argon2;
@@ -25,30 +25,46 @@ use std::sync::Arc;
 
 use jmap::{
     orm::serialize::JMAPOrm,
-    principal::schema::{Principal, Property, Type, Value},
+    principal::schema::{AppPasswordScope, Principal, Property, Type, Value},
     types::jmap::JMAPId,
     SUPERUSER_ID,
 };
 use store::{
     core::{acl::ACLToken, collection::Collection, error::StoreError, JMAPIdPrefix},
+    log::changes::ChangeId,
     read::{
         comparator::Comparator,
         filter::{Filter, Query},
         FilterMapper,
     },
-    tracing::debug,
+    tracing::{debug, error},
     AccountId, JMAPStore, RecipientType, Store,
 };
 
+use super::{credentials::JMAPAccountCredentials, ldap::JMAPLdap};
+
 pub trait JMAPAccountStore {
     fn find_individual(&self, email: &str) -> store::Result<Option<AccountId>>;
-    fn authenticate(&self, login: &str, password: &str) -> store::Result<Option<AccountId>>;
+    fn authenticate(
+        &self,
+        login: &str,
+        password: &str,
+        sync_directory: bool,
+    ) -> store::Result<Option<(AccountId, Option<ChangeId>)>>;
+    fn authenticate_client(
+        &self,
+        login: &str,
+        password: &str,
+        scope: Option<AppPasswordScope>,
+        sync_directory: bool,
+    ) -> store::Result<(Option<(AccountId, Option<u64>)>, Option<ChangeId>)>;
     fn get_acl_token(&self, primary_id: AccountId) -> store::Result<Arc<ACLToken>>;
     fn get_account_details(
         &self,
         account_id: AccountId,
     ) -> store::Result<Option<(String, String, Type)>>;
     fn get_account_secret_hash(&self, account_id: AccountId) -> store::Result<Option<String>>;
+    fn get_account_addresses(&self, account_id: AccountId) -> store::Result<Vec<String>>;
     fn expand_rcpt(&self, email: String) -> store::Result<Arc<RecipientType>>;
 }
 
@@ -72,7 +88,26 @@ where
             .map(|id| id.get_document_id()))
     }
 
-    fn authenticate(&self, login: &str, password: &str) -> store::Result<Option<AccountId>> {
+    // Returns the change id of any directory changes synchronized at login,
+    // which is only done when `sync_directory` is set.
+    fn authenticate(
+        &self,
+        login: &str,
+        password: &str,
+        sync_directory: bool,
+    ) -> store::Result<Option<(AccountId, Option<ChangeId>)>> {
+        // Try the directory first, local accounts (such as the administrator) are
+        // used as a fallback.
+        if self.config.ldap.is_some() {
+            match self.ldap_authenticate(login, password, sync_directory) {
+                Ok(Some(result)) => return Ok(Some(result)),
+                Ok(None) => (),
+                Err(err) => {
+                    error!("LDAP authentication of '{}' failed: {}", login, err);
+                }
+            }
+        }
+
         if let Some(account_id) = self.find_individual(login)? {
             if let Some(mut fields) = self.get_orm::<Principal>(SUPERUSER_ID, account_id)? {
                 if !matches!(
@@ -106,7 +141,7 @@ where
                     if let Ok(matches) = argon2::verify_encoded(&password_hash, password.as_bytes())
                     {
                         if matches {
-                            Ok(Some(account_id))
+                            Ok(Some((account_id, None)))
                         } else {
                             debug!(
                                 "Login failed: Invalid password for account {}.",
@@ -141,6 +176,38 @@ where
         }
     }
 
+    // Authenticates a client using either the main password, which is refused
+    // when two-factor authentication is enabled, or an app password valid for
+    // the requested scope. Returns the account id and the app password id used,
+    // along with the change id of any directory changes synchronized at login.
+    fn authenticate_client(
+        &self,
+        login: &str,
+        password: &str,
+        scope: Option<AppPasswordScope>,
+        sync_directory: bool,
+    ) -> store::Result<(Option<(AccountId, Option<u64>)>, Option<ChangeId>)> {
+        if let Some((account_id, change_id)) = self.authenticate(login, password, sync_directory)? {
+            if !self.totp_is_enabled(account_id)? {
+                Ok((Some((account_id, None)), change_id))
+            } else {
+                debug!(
+                    "Login failed: Account {} has two-factor authentication enabled, an app password is required.",
+                    JMAPId::from(account_id)
+                );
+                Ok((None, change_id))
+            }
+        } else if let Some(account_id) = self.find_individual(login)? {
+            Ok((
+                self.app_password_verify(account_id, password, scope)?
+                    .map(|password_id| (account_id, Some(password_id))),
+                None,
+            ))
+        } else {
+            Ok((None, None))
+        }
+    }
+
     fn get_acl_token(&self, primary_id: AccountId) -> store::Result<Arc<ACLToken>> {
         self.acl_tokens
             .try_get_with::<_, StoreError>(primary_id, || {
@@ -262,9 +329,36 @@ where
         }
     }
 
+    // Returns the e-mail address and aliases of a principal
+    fn get_account_addresses(&self, account_id: AccountId) -> store::Result<Vec<String>> {
+        let mut addresses = Vec::new();
+        if let Some(mut fields) = self.get_orm::<Principal>(SUPERUSER_ID, account_id)? {
+            if let Some(Value::Text { value }) = fields.remove(&Property::Email) {
+                addresses.push(value);
+            }
+            if let Some(Value::TextList { value }) = fields.remove(&Property::Aliases) {
+                addresses.extend(value);
+            }
+        }
+        Ok(addresses)
+    }
+
     fn expand_rcpt(&self, email: String) -> store::Result<Arc<RecipientType>> {
         self.recipients
             .try_get_with::<_, StoreError>(email.clone(), || {
+                // Expand mailing lists from the directory's group membership
+                if self.config.ldap.is_some() {
+                    match self.ldap_expand_group(&email) {
+                        Ok(Some(list)) if !list.is_empty() => {
+                            return Ok(Arc::new(RecipientType::List(list)));
+                        }
+                        Ok(_) => (),
+                        Err(err) => {
+                            error!("LDAP expansion of '{}' failed: {}", email, err);
+                        }
+                    }
+                }
+
                 Ok(Arc::new(
                     if let Some(account_id) = self
                         .query_store::<FilterMapper>(
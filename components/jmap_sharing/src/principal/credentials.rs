@@ -0,0 +1,413 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use hmac::{Hmac, Mac};
+use jmap::{
+    orm::{serialize::JMAPOrm, TinyORM},
+    principal::schema::{AppPassword, AppPasswordScope, Principal, Property, Value, TOTP},
+    SUPERUSER_ID,
+};
+use sha1::Sha1;
+use store::{
+    core::{collection::Collection, document::Document},
+    log::changes::ChangeId,
+    rand::{self, thread_rng, Rng},
+    sha2::{Digest, Sha256},
+    write::batch::WriteBatch,
+    AccountId, JMAPStore, Store,
+};
+
+use super::unix_timestamp;
+
+const TOTP_STEP: i64 = 30;
+const TOTP_DIGITS: u32 = 6;
+const TOTP_MAX_FAILURES: u32 = 5;
+const TOTP_LOCKOUT: i64 = 15 * 60;
+const BASE32_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+pub trait JMAPAccountCredentials<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn app_password_create(
+        &self,
+        account_id: AccountId,
+        label: String,
+        scope: Option<AppPasswordScope>,
+    ) -> store::Result<Option<(AppPassword, String, ChangeId)>>;
+    fn app_password_list(&self, account_id: AccountId) -> store::Result<Vec<AppPassword>>;
+    fn app_password_revoke(
+        &self,
+        account_id: AccountId,
+        password_id: u64,
+    ) -> store::Result<Option<ChangeId>>;
+    fn app_password_verify(
+        &self,
+        account_id: AccountId,
+        password: &str,
+        scope: Option<AppPasswordScope>,
+    ) -> store::Result<Option<u64>>;
+    fn totp_enroll(&self, account_id: AccountId) -> store::Result<Option<(String, ChangeId)>>;
+    fn totp_confirm(&self, account_id: AccountId, code: &str) -> store::Result<Option<ChangeId>>;
+    fn totp_disable(&self, account_id: AccountId) -> store::Result<Option<ChangeId>>;
+    fn totp_is_enabled(&self, account_id: AccountId) -> store::Result<bool>;
+    fn totp_verify(
+        &self,
+        account_id: AccountId,
+        code: &str,
+    ) -> store::Result<(bool, Option<ChangeId>)>;
+    fn credentials_update<F>(&self, account_id: AccountId, f: F) -> store::Result<Option<ChangeId>>
+    where
+        F: FnOnce(&mut Vec<AppPassword>, &mut Option<TOTP>) -> bool;
+}
+
+impl<T> JMAPAccountCredentials<T> for JMAPStore<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn app_password_create(
+        &self,
+        account_id: AccountId,
+        label: String,
+        scope: Option<AppPasswordScope>,
+    ) -> store::Result<Option<(AppPassword, String, ChangeId)>> {
+        let password = thread_rng()
+            .sample_iter(rand::distributions::Alphanumeric)
+            .take(24)
+            .map(char::from)
+            .collect::<String>();
+        let mut app_password = None;
+
+        Ok(self
+            .credentials_update(account_id, |passwords, _| {
+                // Password ids are displayed as JMAP ids, where u64::MAX is reserved
+                let mut password_id;
+                loop {
+                    password_id = thread_rng().gen::<u64>();
+                    if password_id != u64::MAX && !passwords.iter().any(|p| p.id == password_id) {
+                        break;
+                    }
+                }
+                let new_password = AppPassword {
+                    id: password_id,
+                    label,
+                    hash: hash_app_password(&password),
+                    created: unix_timestamp(),
+                    scope,
+                };
+                passwords.push(new_password.clone());
+                app_password = new_password.into();
+                true
+            })?
+            .and_then(|change_id| {
+                app_password.map(|app_password| (app_password, password, change_id))
+            }))
+    }
+
+    fn app_password_list(&self, account_id: AccountId) -> store::Result<Vec<AppPassword>> {
+        Ok(
+            match self
+                .get_orm::<Principal>(SUPERUSER_ID, account_id)?
+                .and_then(|mut fields| fields.remove(&Property::AppPasswords))
+            {
+                Some(Value::AppPasswords { value }) => value,
+                _ => Vec::new(),
+            },
+        )
+    }
+
+    fn app_password_revoke(
+        &self,
+        account_id: AccountId,
+        password_id: u64,
+    ) -> store::Result<Option<ChangeId>> {
+        self.credentials_update(account_id, |passwords, _| {
+            let num_passwords = passwords.len();
+            passwords.retain(|password| password.id != password_id);
+            passwords.len() != num_passwords
+        })
+    }
+
+    fn app_password_verify(
+        &self,
+        account_id: AccountId,
+        password: &str,
+        scope: Option<AppPasswordScope>,
+    ) -> store::Result<Option<u64>> {
+        let hash = hash_app_password(password);
+        Ok(self
+            .app_password_list(account_id)?
+            .into_iter()
+            .find(|app_password| {
+                app_password.hash == hash
+                    && app_password
+                        .scope
+                        .map_or(true, |password_scope| Some(password_scope) == scope)
+            })
+            .map(|app_password| app_password.id))
+    }
+
+    fn totp_enroll(&self, account_id: AccountId) -> store::Result<Option<(String, ChangeId)>> {
+        let secret = base32_encode(&thread_rng().gen::<[u8; 20]>());
+
+        Ok(self
+            .credentials_update(account_id, |_, totp| {
+                // Enrolling again is not allowed once a second factor is active
+                if totp.as_ref().map_or(false, |totp| totp.enabled) {
+                    false
+                } else {
+                    *totp = TOTP {
+                        secret: secret.clone(),
+                        enabled: false,
+                        last_step: 0,
+                        failed_attempts: 0,
+                        locked_until: 0,
+                    }
+                    .into();
+                    true
+                }
+            })?
+            .map(|change_id| (secret, change_id)))
+    }
+
+    fn totp_confirm(&self, account_id: AccountId, code: &str) -> store::Result<Option<ChangeId>> {
+        self.credentials_update(account_id, |_, totp| match totp {
+            Some(totp) if !totp.enabled => {
+                if let Some(step) = totp_match(&totp.secret, code, totp.last_step) {
+                    totp.enabled = true;
+                    totp.last_step = step;
+                    true
+                } else {
+                    false
+                }
+            }
+            _ => false,
+        })
+    }
+
+    fn totp_disable(&self, account_id: AccountId) -> store::Result<Option<ChangeId>> {
+        self.credentials_update(account_id, |_, totp| totp.take().is_some())
+    }
+
+    fn totp_is_enabled(&self, account_id: AccountId) -> store::Result<bool> {
+        Ok(matches!(
+            self.get_orm::<Principal>(SUPERUSER_ID, account_id)?
+                .and_then(|mut fields| fields.remove(&Property::TOTP)),
+            Some(Value::TOTP {
+                value: TOTP { enabled: true, .. }
+            })
+        ))
+    }
+
+    fn totp_verify(
+        &self,
+        account_id: AccountId,
+        code: &str,
+    ) -> store::Result<(bool, Option<ChangeId>)> {
+        let mut is_valid = false;
+        let change_id = self.credentials_update(account_id, |_, totp| match totp {
+            Some(totp) if totp.enabled => {
+                // Codes are not checked while the account is locked out
+                let now = unix_timestamp();
+                if totp.locked_until > now {
+                    false
+                } else if let Some(step) = totp_match(&totp.secret, code, totp.last_step) {
+                    totp.last_step = step;
+                    totp.failed_attempts = 0;
+                    is_valid = true;
+                    true
+                } else {
+                    totp.failed_attempts += 1;
+                    if totp.failed_attempts >= TOTP_MAX_FAILURES {
+                        totp.failed_attempts = 0;
+                        totp.locked_until = now + TOTP_LOCKOUT;
+                    }
+                    true
+                }
+            }
+            _ => false,
+        })?;
+        Ok((is_valid, change_id))
+    }
+
+    fn credentials_update<F>(&self, account_id: AccountId, f: F) -> store::Result<Option<ChangeId>>
+    where
+        F: FnOnce(&mut Vec<AppPassword>, &mut Option<TOTP>) -> bool,
+    {
+        let _lock = self.lock_collection(SUPERUSER_ID, Collection::Principal);
+
+        let principal =
+            if let Some(principal) = self.get_orm::<Principal>(SUPERUSER_ID, account_id)? {
+                principal
+            } else {
+                return Ok(None);
+            };
+        let mut passwords = match principal.get(&Property::AppPasswords) {
+            Some(Value::AppPasswords { value }) => value.clone(),
+            _ => Vec::new(),
+        };
+        let mut totp = match principal.get(&Property::TOTP) {
+            Some(Value::TOTP { value }) => value.clone().into(),
+            _ => None,
+        };
+        if !f(&mut passwords, &mut totp) {
+            return Ok(None);
+        }
+
+        // Save changes
+        let mut batch = WriteBatch::new(SUPERUSER_ID);
+        let mut document = Document::new(Collection::Principal, account_id);
+        let mut fields = TinyORM::track_changes(&principal);
+        fields.set(
+            Property::AppPasswords,
+            if !passwords.is_empty() {
+                Value::AppPasswords { value: passwords }
+            } else {
+                Value::Null
+            },
+        );
+        fields.set(
+            Property::TOTP,
+            if let Some(totp) = totp {
+                Value::TOTP { value: totp }
+            } else {
+                Value::Null
+            },
+        );
+        principal.merge(&mut document, fields)?;
+        batch.update_document(document);
+        batch.log_update(Collection::Principal, account_id);
+
+        Ok(self.write(batch)?.map(|changes| changes.change_id))
+    }
+}
+
+// App passwords are random, so a fast digest is enough to protect them at rest.
+fn hash_app_password(password: &str) -> String {
+    Sha256::digest(password.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+// Returns the time step of a valid TOTP code (RFC 6238), allowing one step of
+// clock drift. Steps at or before the last accepted one are rejected.
+fn totp_match(secret: &str, code: &str, last_step: u64) -> Option<u64> {
+    let secret = base32_decode(secret)?;
+    let code = code.trim();
+    if code.len() != TOTP_DIGITS as usize || !code.chars().all(|ch| ch.is_ascii_digit()) {
+        return None;
+    }
+    let code = code.parse::<u32>().ok()?;
+    let step = (unix_timestamp() / TOTP_STEP) as u64;
+
+    [step.saturating_sub(1), step, step + 1]
+        .into_iter()
+        .find(|&step| step > last_step && hotp(&secret, step) == Some(code))
+}
+
+// HMAC-based one-time password (RFC 4226)
+pub fn hotp(secret: &[u8], counter: u64) -> Option<u32> {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).ok()?;
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let value = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    Some(value % 10u32.pow(TOTP_DIGITS))
+}
+
+pub fn base32_encode(bytes: &[u8]) -> String {
+    let mut result = String::with_capacity((bytes.len() * 8 + 4) / 5);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for &byte in bytes {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            result.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        result.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    result
+}
+
+pub fn base32_decode(value: &str) -> Option<Vec<u8>> {
+    let mut result = Vec::with_capacity(value.len() * 5 / 8);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for ch in value.bytes().filter(|&ch| ch != b'=' && ch != b' ') {
+        let pos = BASE32_ALPHABET
+            .iter()
+            .position(|&a| a == ch.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | pos as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            result.push((buffer >> bits) as u8);
+        }
+    }
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{base32_decode, base32_encode, hotp};
+
+    #[test]
+    fn hotp_rfc4226() {
+        // Test vectors from RFC 4226, Appendix D
+        let secret = b"12345678901234567890";
+        for (counter, expected) in [
+            755224, 287082, 359152, 969429, 338314, 254676, 287922, 162583, 399871, 520489,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            assert_eq!(hotp(secret, counter as u64), Some(expected));
+        }
+    }
+
+    #[test]
+    fn base32() {
+        for (bytes, encoded) in [
+            (&b""[..], ""),
+            (b"f", "MY"),
+            (b"fo", "MZXQ"),
+            (b"foo", "MZXW6"),
+            (b"foob", "MZXW6YQ"),
+            (b"fooba", "MZXW6YTB"),
+            (b"foobar", "MZXW6YTBOI"),
+        ] {
+            assert_eq!(base32_encode(bytes), encoded);
+            assert_eq!(base32_decode(encoded).unwrap(), bytes);
+        }
+    }
+}
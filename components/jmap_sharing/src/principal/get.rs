@@ -27,7 +27,6 @@ use jmap::principal::schema::{Principal, Property, Value};
 use jmap::principal::store::JMAPPrincipals;
 use jmap::request::get::{GetRequest, GetResponse};
 use jmap::SUPERUSER_ID;
-use jmap_mail::mail_send::dkim::DKIM;
 use store::core::collection::Collection;
 use store::core::error::StoreError;
 use store::core::tag::Tag;
@@ -39,13 +38,16 @@ use store::read::FilterMapper;
 use store::JMAPStore;
 use store::Store;
 
+use super::dkim::DkimSigner;
+use super::unix_timestamp;
+
 pub trait JMAPGetPrincipal<T>
 where
     T: for<'x> Store<'x> + 'static,
 {
     fn principal_get(&self, request: GetRequest<Principal>)
         -> jmap::Result<GetResponse<Principal>>;
-    fn dkim_get(&self, domain_name: String) -> store::Result<Option<DKIM<'_>>>;
+    fn dkim_get(&self, domain_name: String) -> store::Result<Option<DkimSigner>>;
 }
 
 impl<T> JMAPGetPrincipal<T> for JMAPStore<T>
@@ -82,6 +84,16 @@ where
                         }
 
                         Property::Secret => Value::Null,
+                        Property::DKIM => match fields.remove(property) {
+                            Some(Value::DKIM { mut value }) => {
+                                value.promote(unix_timestamp());
+                                if let Some(rotation) = &mut value.dkim_rotation {
+                                    rotation.secret = None;
+                                }
+                                Value::DKIM { value }
+                            }
+                            _ => Value::Null,
+                        },
                         _ => fields.remove(property).unwrap_or_default(),
                     },
                 );
@@ -92,7 +104,7 @@ where
         })
     }
 
-    fn dkim_get(&self, domain_name: String) -> store::Result<Option<DKIM<'_>>> {
+    fn dkim_get(&self, domain_name: String) -> store::Result<Option<DkimSigner>> {
         if let Some(domain_id) = self
             .query_store::<FilterMapper>(
                 SUPERUSER_ID,
@@ -105,7 +117,7 @@ where
             )?
             .next()
         {
-            if let Some((Value::Text { value: dkim }, dkim_settings)) = self
+            if let Some((Value::Text { value: secret }, dkim_settings)) = self
                 .get_orm::<Principal>(SUPERUSER_ID, domain_id.get_document_id())?
                 .map(|mut p| {
                     (
@@ -114,23 +126,16 @@ where
                     )
                 })
             {
-                let mut dkim = DKIM::from_pkcs1_pem(&dkim)
-                    .map_err(|err| {
-                        StoreError::InternalError(format!("Failed to DKIM sign: {}", err))
-                    })?
-                    .domain(domain_name)
-                    .selector("default");
-
-                if let Value::DKIM { value } = dkim_settings {
-                    if let Some(expiration) = value.dkim_expiration {
-                        dkim = dkim.expiration(expiration as u64);
-                    }
-                    if let Some(selector) = value.dkim_selector {
-                        dkim = dkim.selector(selector);
-                    }
-                }
-
-                return Ok(Some(dkim));
+                return DkimSigner::new(
+                    domain_name,
+                    match dkim_settings {
+                        Value::DKIM { value } => Some(value),
+                        _ => None,
+                    },
+                    &secret,
+                )
+                .map(Some)
+                .map_err(|err| StoreError::InternalError(format!("Failed to DKIM sign: {}", err)));
             }
         }
 
@@ -0,0 +1,312 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap::{
+    orm::{serialize::JMAPOrm, TinyORM},
+    principal::schema::{OAuthGrant, Principal, Property, Value},
+    SUPERUSER_ID,
+};
+use serde::{Deserialize, Serialize};
+use store::{
+    bincode,
+    core::{collection::Collection, document::Document},
+    log::changes::ChangeId,
+    rand::{thread_rng, Rng},
+    serialize::{key::ValueKey, StoreDeserialize, StoreSerialize},
+    write::{batch::WriteBatch, operation::WriteOperation},
+    AccountId, ColumnFamily, JMAPStore, Store,
+};
+
+use super::unix_timestamp;
+
+// Avoid writing to the store on every request made with the same grant.
+const LAST_USED_GRANULARITY: i64 = 60;
+
+/// Last time an OAuth grant was used. This is local bookkeeping stored under
+/// its own key, it is neither logged as a change nor replicated.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OAuthGrantUsage {
+    pub last_used: i64,
+    pub ip: Option<String>,
+}
+
+pub trait JMAPAccountGrants<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn oauth_grant_create(
+        &self,
+        account_id: AccountId,
+        client_id: String,
+        ip: Option<String>,
+        max_idle: i64,
+    ) -> store::Result<Option<(u64, ChangeId)>>;
+    fn oauth_grant_secret_hash(
+        &self,
+        account_id: AccountId,
+        grant_id: u64,
+    ) -> store::Result<Option<String>>;
+    fn oauth_grant_list(
+        &self,
+        account_id: AccountId,
+    ) -> store::Result<Vec<(OAuthGrant, OAuthGrantUsage)>>;
+    fn oauth_grant_usage(
+        &self,
+        account_id: AccountId,
+        grant: &OAuthGrant,
+    ) -> store::Result<OAuthGrantUsage>;
+    fn oauth_grant_touch(
+        &self,
+        account_id: AccountId,
+        grant_id: u64,
+        ip: Option<String>,
+    ) -> store::Result<()>;
+    fn oauth_grant_revoke(
+        &self,
+        account_id: AccountId,
+        grant_id: u64,
+    ) -> store::Result<Option<ChangeId>>;
+    fn oauth_grant_update<F>(&self, account_id: AccountId, f: F) -> store::Result<Option<ChangeId>>
+    where
+        F: FnOnce(&mut Vec<OAuthGrant>) -> bool;
+}
+
+impl<T> JMAPAccountGrants<T> for JMAPStore<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn oauth_grant_create(
+        &self,
+        account_id: AccountId,
+        client_id: String,
+        ip: Option<String>,
+        max_idle: i64,
+    ) -> store::Result<Option<(u64, ChangeId)>> {
+        let now = unix_timestamp();
+        let mut grant_id = 0;
+
+        // Discard grants whose refresh tokens have expired
+        let mut expired_ids = Vec::new();
+        for (grant, usage) in self.oauth_grant_list(account_id)? {
+            if usage.last_used + max_idle <= now {
+                expired_ids.push(grant.id);
+            }
+        }
+
+        let change_id = if let Some(change_id) = self.oauth_grant_update(account_id, |grants| {
+            grants.retain(|grant| !expired_ids.contains(&grant.id));
+
+            // Grant ids are displayed as JMAP ids, where u64::MAX is reserved
+            loop {
+                grant_id = thread_rng().gen::<u64>();
+                if grant_id != u64::MAX && !grants.iter().any(|grant| grant.id == grant_id) {
+                    break;
+                }
+            }
+            grants.push(OAuthGrant {
+                id: grant_id,
+                client_id,
+                created: now,
+            });
+            true
+        })? {
+            change_id
+        } else {
+            return Ok(None);
+        };
+
+        let mut ops = expired_ids
+            .into_iter()
+            .map(|grant_id| {
+                WriteOperation::delete(
+                    ColumnFamily::Values,
+                    ValueKey::serialize_grant(account_id, grant_id),
+                )
+            })
+            .collect::<Vec<_>>();
+        ops.push(WriteOperation::set(
+            ColumnFamily::Values,
+            ValueKey::serialize_grant(account_id, grant_id),
+            OAuthGrantUsage { last_used: now, ip }.serialize().unwrap(),
+        ));
+        self.db.write(ops)?;
+
+        Ok(Some((grant_id, change_id)))
+    }
+
+    // Returns the secret hash of the account, or None if the grant was revoked
+    // or the account no longer exists.
+    fn oauth_grant_secret_hash(
+        &self,
+        account_id: AccountId,
+        grant_id: u64,
+    ) -> store::Result<Option<String>> {
+        let mut fields =
+            if let Some(fields) = self.get_orm::<Principal>(SUPERUSER_ID, account_id)? {
+                fields
+            } else {
+                return Ok(None);
+            };
+        match fields.remove(&Property::OAuthGrants) {
+            Some(Value::OAuthGrants { value })
+                if value.iter().any(|grant| grant.id == grant_id) =>
+            {
+                Ok(Some(match fields.remove(&Property::Secret) {
+                    Some(Value::Text { value }) => value,
+                    _ => String::new(),
+                }))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn oauth_grant_list(
+        &self,
+        account_id: AccountId,
+    ) -> store::Result<Vec<(OAuthGrant, OAuthGrantUsage)>> {
+        match self
+            .get_orm::<Principal>(SUPERUSER_ID, account_id)?
+            .and_then(|mut fields| fields.remove(&Property::OAuthGrants))
+        {
+            Some(Value::OAuthGrants { value }) => value
+                .into_iter()
+                .map(|grant| {
+                    let usage = self.oauth_grant_usage(account_id, &grant)?;
+                    Ok((grant, usage))
+                })
+                .collect(),
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    // Grants that were not used on this node report their creation time.
+    fn oauth_grant_usage(
+        &self,
+        account_id: AccountId,
+        grant: &OAuthGrant,
+    ) -> store::Result<OAuthGrantUsage> {
+        Ok(self
+            .db
+            .get::<OAuthGrantUsage>(
+                ColumnFamily::Values,
+                &ValueKey::serialize_grant(account_id, grant.id),
+            )?
+            .unwrap_or(OAuthGrantUsage {
+                last_used: grant.created,
+                ip: None,
+            }))
+    }
+
+    fn oauth_grant_touch(
+        &self,
+        account_id: AccountId,
+        grant_id: u64,
+        ip: Option<String>,
+    ) -> store::Result<()> {
+        let now = unix_timestamp();
+        let key = ValueKey::serialize_grant(account_id, grant_id);
+
+        if !matches!(
+            self.db.get::<OAuthGrantUsage>(ColumnFamily::Values, &key)?,
+            Some(usage) if usage.last_used + LAST_USED_GRANULARITY > now && usage.ip == ip
+        ) {
+            self.db.set(
+                ColumnFamily::Values,
+                &key,
+                &OAuthGrantUsage { last_used: now, ip }.serialize().unwrap(),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn oauth_grant_revoke(
+        &self,
+        account_id: AccountId,
+        grant_id: u64,
+    ) -> store::Result<Option<ChangeId>> {
+        let change_id = self.oauth_grant_update(account_id, |grants| {
+            let num_grants = grants.len();
+            grants.retain(|grant| grant.id != grant_id);
+            grants.len() != num_grants
+        })?;
+        if change_id.is_some() {
+            self.db.delete(
+                ColumnFamily::Values,
+                &ValueKey::serialize_grant(account_id, grant_id),
+            )?;
+        }
+        Ok(change_id)
+    }
+
+    fn oauth_grant_update<F>(&self, account_id: AccountId, f: F) -> store::Result<Option<ChangeId>>
+    where
+        F: FnOnce(&mut Vec<OAuthGrant>) -> bool,
+    {
+        let _lock = self.lock_collection(SUPERUSER_ID, Collection::Principal);
+
+        let principal =
+            if let Some(principal) = self.get_orm::<Principal>(SUPERUSER_ID, account_id)? {
+                principal
+            } else {
+                return Ok(None);
+            };
+        let mut grants = match principal.get(&Property::OAuthGrants) {
+            Some(Value::OAuthGrants { value }) => value.clone(),
+            _ => Vec::new(),
+        };
+        if !f(&mut grants) {
+            return Ok(None);
+        }
+
+        // Save changes
+        let mut batch = WriteBatch::new(SUPERUSER_ID);
+        let mut document = Document::new(Collection::Principal, account_id);
+        let mut fields = TinyORM::track_changes(&principal);
+        fields.set(
+            Property::OAuthGrants,
+            if !grants.is_empty() {
+                Value::OAuthGrants { value: grants }
+            } else {
+                Value::Null
+            },
+        );
+        principal.merge(&mut document, fields)?;
+        batch.update_document(document);
+        batch.log_update(Collection::Principal, account_id);
+
+        Ok(self.write(batch)?.map(|changes| changes.change_id))
+    }
+}
+
+impl StoreSerialize for OAuthGrantUsage {
+    fn serialize(&self) -> Option<Vec<u8>> {
+        bincode::serialize(self).ok()
+    }
+}
+
+impl StoreDeserialize for OAuthGrantUsage {
+    fn deserialize(bytes: &[u8]) -> Option<Self> {
+        bincode::deserialize(bytes).ok()
+    }
+}
@@ -0,0 +1,544 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::Duration;
+
+use jmap::{
+    orm::serialize::JMAPOrm,
+    principal::schema::{Principal, Property, Type, Value},
+    request::set::SetRequest,
+    types::jmap::JMAPId,
+    SUPERUSER_ID,
+};
+use ldap3::{dn_escape, ldap_escape, LdapConn, LdapConnSettings, LdapError, Scope, SearchEntry};
+use store::{
+    ahash::AHashMap,
+    config::ldap::LdapConfig,
+    core::{collection::Collection, error::StoreError, vec_map::VecMap, JMAPIdPrefix},
+    log::changes::ChangeId,
+    parking_lot::{const_mutex, Mutex},
+    rand::{self, Rng},
+    read::{
+        comparator::Comparator,
+        filter::{Filter, Query},
+        FilterMapper,
+    },
+    tracing::{debug, error},
+    AccountId, JMAPStore, Store,
+};
+
+use super::{account::JMAPAccountStore, set::JMAPSetPrincipal};
+
+pub trait JMAPLdap {
+    fn ldap_authenticate(
+        &self,
+        login: &str,
+        password: &str,
+        sync: bool,
+    ) -> store::Result<Option<(AccountId, Option<ChangeId>)>>;
+    fn ldap_sync(&self) -> store::Result<Option<ChangeId>>;
+    fn ldap_expand_group(&self, email: &str) -> store::Result<Option<Vec<(AccountId, String)>>>;
+}
+
+// Keeps the filters used to resolve group members within directory limits
+const MEMBERS_PER_SEARCH: usize = 100;
+
+struct LdapEntry {
+    dn: String,
+    email: String,
+    name: String,
+    aliases: Vec<String>,
+    members: Vec<String>,
+}
+
+impl<T> JMAPLdap for JMAPStore<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    // Principals are only synchronized at login when `sync` is set, which callers
+    // restrict to the cluster leader. The returned change id has to be committed.
+    fn ldap_authenticate(
+        &self,
+        login: &str,
+        password: &str,
+        sync: bool,
+    ) -> store::Result<Option<(AccountId, Option<ChangeId>)>> {
+        let config = if let Some(config) = &self.config.ldap {
+            config
+        } else {
+            return Ok(None);
+        };
+
+        // Most directories accept an empty password as an anonymous bind
+        if password.is_empty() {
+            return Ok(None);
+        }
+
+        let filter = config.filter_user.replace('?', &ldap_escape(login));
+        let entry = if let Some(template) = &config.bind_template {
+            // Bind directly as the user, then read its own entry
+            let mut conn = ldap_connect(config)?;
+            let dn = template.replace('?', &dn_escape(login));
+            let entry = if ldap_bind(&mut conn, &dn, password)? {
+                ldap_search(&mut conn, config, &filter)?.into_iter().next()
+            } else {
+                debug!("LDAP login failed: Could not bind as '{}'.", dn);
+                None
+            };
+            conn.unbind().ok();
+            entry
+        } else {
+            // Search the user's entry with the service account, then bind as the user
+            ldap_with_conn(config, |conn| {
+                match ldap_search(conn, config, &filter)?.into_iter().next() {
+                    Some(entry) => {
+                        let is_valid = ldap_bind(conn, &entry.dn, password)?;
+                        // Bind as the service account again before the connection is pooled
+                        ldap_bind_service(conn, config)?;
+                        if is_valid {
+                            Ok(Some(entry))
+                        } else {
+                            debug!("LDAP login failed: Invalid password for '{}'.", entry.dn);
+                            Ok(None)
+                        }
+                    }
+                    None => {
+                        debug!("LDAP login failed: Login '{}' not found.", login);
+                        Ok(None)
+                    }
+                }
+            })?
+        };
+
+        match entry {
+            Some(entry) if config.sync_on_login && sync => self
+                .ldap_sync_principal(&entry, Type::Individual, None)
+                .map(Some),
+            Some(entry) => Ok(self
+                .find_individual(&entry.email)?
+                .map(|account_id| (account_id, None))),
+            None => Ok(None),
+        }
+    }
+
+    fn ldap_sync(&self) -> store::Result<Option<ChangeId>> {
+        let config = if let Some(config) = &self.config.ldap {
+            config
+        } else {
+            return Ok(None);
+        };
+
+        let (users, groups) = ldap_with_conn(config, |conn| {
+            Ok((
+                ldap_search(conn, config, &config.filter_all_users)?,
+                ldap_search(conn, config, &config.filter_all_groups)?,
+            ))
+        })?;
+
+        // Sync users first, so group members can be resolved by DN
+        let mut change_id = None;
+        let mut dn_to_id = AHashMap::with_capacity(users.len());
+        for user in users {
+            match self.ldap_sync_principal(&user, Type::Individual, None) {
+                Ok((account_id, changed)) => {
+                    dn_to_id.insert(user.dn.to_lowercase(), account_id);
+                    change_id = changed.or(change_id);
+                }
+                Err(err) => {
+                    error!("Failed to sync LDAP user '{}': {}", user.dn, err);
+                }
+            }
+        }
+
+        for group in groups {
+            let members = group
+                .members
+                .iter()
+                .filter_map(|dn| dn_to_id.get(&dn.to_lowercase()))
+                .map(|account_id| JMAPId::from(*account_id))
+                .collect::<Vec<_>>();
+            match self.ldap_sync_principal(&group, Type::Group, members.into()) {
+                Ok((_, changed)) => {
+                    change_id = changed.or(change_id);
+                }
+                Err(err) => {
+                    error!("Failed to sync LDAP group '{}': {}", group.dn, err);
+                }
+            }
+        }
+
+        Ok(change_id)
+    }
+
+    fn ldap_expand_group(&self, email: &str) -> store::Result<Option<Vec<(AccountId, String)>>> {
+        let config = if let Some(config) = &self.config.ldap {
+            config
+        } else {
+            return Ok(None);
+        };
+
+        let (member_dns, members) = if let Some(result) = ldap_with_conn(config, |conn| {
+            let group = if let Some(group) = ldap_search(
+                conn,
+                config,
+                &config.filter_group.replace('?', &ldap_escape(email)),
+            )?
+            .into_iter()
+            .next()
+            {
+                group
+            } else {
+                return Ok(None);
+            };
+
+            // Resolve the member DNs with one search per batch of members
+            let mut members = Vec::with_capacity(group.members.len());
+            for dns in group.members.chunks(MEMBERS_PER_SEARCH) {
+                let mut filter = String::from("(|");
+                for dn in dns {
+                    filter.push_str(&format!("({}={})", config.attr_dn, ldap_escape(dn)));
+                }
+                filter.push(')');
+                members.extend(ldap_search(conn, config, &filter)?);
+            }
+            Ok(Some((group.members, members)))
+        })? {
+            result
+        } else {
+            return Ok(None);
+        };
+
+        // Map members to local accounts, keeping the order of the group
+        let mut members = members
+            .into_iter()
+            .map(|member| (member.dn.to_lowercase(), member))
+            .collect::<AHashMap<_, _>>();
+        let mut list = Vec::with_capacity(members.len());
+        for dn in member_dns {
+            if let Some(member) = members.remove(&dn.to_lowercase()) {
+                if let Some(account_id) = self.find_individual(&member.email)? {
+                    list.push((account_id, member.email));
+                } else {
+                    debug!(
+                        "LDAP group '{}' member '{}' has no local account.",
+                        email, member.email
+                    );
+                }
+            }
+        }
+
+        Ok(Some(list))
+    }
+}
+
+trait LdapSync {
+    fn ldap_sync_principal(
+        &self,
+        entry: &LdapEntry,
+        ptype: Type,
+        members: Option<Vec<JMAPId>>,
+    ) -> store::Result<(AccountId, Option<ChangeId>)>;
+}
+
+impl<T> LdapSync for JMAPStore<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn ldap_sync_principal(
+        &self,
+        entry: &LdapEntry,
+        ptype: Type,
+        members: Option<Vec<JMAPId>>,
+    ) -> store::Result<(AccountId, Option<ChangeId>)> {
+        let account_id = if ptype == Type::Individual {
+            self.find_individual(&entry.email)?
+        } else {
+            self.query_store::<FilterMapper>(
+                SUPERUSER_ID,
+                Collection::Principal,
+                Filter::and(vec![
+                    Filter::eq(Property::Email.into(), Query::Index(entry.email.clone())),
+                    Filter::eq(Property::Type.into(), Query::Keyword("g".to_string())),
+                ]),
+                Comparator::None,
+            )?
+            .into_iter()
+            .next()
+            .map(|id| id.get_document_id())
+        };
+
+        // Only send the properties that changed in the directory
+        let mut principal = Principal::default();
+        let current_fields = if let Some(account_id) = account_id {
+            self.get_orm::<Principal>(SUPERUSER_ID, account_id)?
+        } else {
+            None
+        };
+        let mut values = vec![
+            (
+                Property::Name,
+                Value::Text {
+                    value: entry.name.clone(),
+                },
+            ),
+            (
+                Property::Aliases,
+                Value::TextList {
+                    value: entry.aliases.clone(),
+                },
+            ),
+        ];
+        if let Some(members) = members {
+            values.push((Property::Members, Value::Members { value: members }));
+        }
+        for (property, value) in values {
+            let current_value = current_fields
+                .as_ref()
+                .and_then(|fields| fields.get(&property));
+            let is_empty = match &value {
+                Value::TextList { value } => value.is_empty(),
+                Value::Members { value } => value.is_empty(),
+                _ => false,
+            };
+            if current_value.map_or(!is_empty, |current_value| current_value != &value) {
+                principal.properties.append(property, value);
+            }
+        }
+
+        let (create, update) = if let Some(account_id) = account_id {
+            if principal.properties.is_empty() {
+                return Ok((account_id, None));
+            }
+            (
+                None,
+                Some(VecMap::from_iter([(JMAPId::from(account_id), principal)])),
+            )
+        } else {
+            principal
+                .properties
+                .append(Property::Type, Value::Type { value: ptype });
+            principal.properties.append(
+                Property::Email,
+                Value::Text {
+                    value: entry.email.clone(),
+                },
+            );
+            if ptype == Type::Individual {
+                // Passwords are verified by the directory
+                principal.properties.append(
+                    Property::Secret,
+                    Value::Text {
+                        value: rand::thread_rng()
+                            .sample_iter(rand::distributions::Alphanumeric)
+                            .take(32)
+                            .map(char::from)
+                            .collect(),
+                    },
+                );
+            }
+            (
+                Some(VecMap::from_iter([("p".to_string(), principal)])),
+                None,
+            )
+        };
+
+        let mut response = self
+            .principal_set(SetRequest {
+                acl: self.get_acl_token(SUPERUSER_ID)?.into(),
+                account_id: JMAPId::from(SUPERUSER_ID),
+                if_in_state: None,
+                create,
+                update,
+                destroy: None,
+                arguments: (),
+            })
+            .map_err(|err| StoreError::InternalError(err.to_string()))?;
+
+        if let Some(account_id) = account_id {
+            if let Some(err) = response.not_updated.remove(&JMAPId::from(account_id)) {
+                Err(StoreError::InvalidArguments(format!(
+                    "Failed to update principal {}: {:?}",
+                    entry.email, err
+                )))
+            } else {
+                Ok((account_id, response.has_changes()))
+            }
+        } else if let Some(account_id) = response
+            .created
+            .get("p")
+            .and_then(|principal| principal.id())
+            .map(|id| id.get_document_id())
+        {
+            Ok((account_id, response.has_changes()))
+        } else {
+            Err(StoreError::InvalidArguments(format!(
+                "Failed to create principal {}: {:?}",
+                entry.email,
+                response.not_created.remove(&"p".to_string())
+            )))
+        }
+    }
+}
+
+impl LdapEntry {
+    fn new(mut entry: SearchEntry, config: &LdapConfig) -> Option<Self> {
+        // Attribute names are case insensitive
+        let mut take = |name: &str| {
+            entry
+                .attrs
+                .keys()
+                .find(|key| key.eq_ignore_ascii_case(name))
+                .cloned()
+                .and_then(|key| entry.attrs.remove(&key))
+                .unwrap_or_default()
+        };
+        let email = take(&config.attr_email).into_iter().next()?.to_lowercase();
+        let name = take(&config.attr_name)
+            .into_iter()
+            .next()
+            .unwrap_or_else(|| email.clone());
+        let aliases = take(&config.attr_aliases)
+            .into_iter()
+            .map(|alias| alias.to_lowercase())
+            .filter(|alias| alias != &email)
+            .collect();
+        let members = take(&config.attr_members);
+
+        Some(LdapEntry {
+            dn: entry.dn,
+            email,
+            name,
+            aliases,
+            members,
+        })
+    }
+}
+
+// Idle connections bound as the service account
+static LDAP_POOL: Mutex<Vec<LdapConn>> = const_mutex(Vec::new());
+
+// Runs a directory operation on a pooled connection bound as the service account.
+// Pooled connections might have been closed by the directory, so a failed operation
+// is retried once on a new connection.
+fn ldap_with_conn<R>(
+    config: &LdapConfig,
+    mut f: impl FnMut(&mut LdapConn) -> store::Result<R>,
+) -> store::Result<R> {
+    let conn = LDAP_POOL.lock().pop();
+    if let Some(mut conn) = conn {
+        match f(&mut conn) {
+            Ok(result) => {
+                ldap_release(config, conn);
+                return Ok(result);
+            }
+            Err(err) => {
+                debug!("Discarding pooled LDAP connection: {}", err);
+            }
+        }
+    }
+
+    let mut conn = ldap_connect(config)?;
+    ldap_bind_service(&mut conn, config)?;
+    let result = f(&mut conn)?;
+    ldap_release(config, conn);
+    Ok(result)
+}
+
+fn ldap_release(config: &LdapConfig, mut conn: LdapConn) {
+    let mut pool = LDAP_POOL.lock();
+    if pool.len() < config.pool_size {
+        pool.push(conn);
+    } else {
+        drop(pool);
+        conn.unbind().ok();
+    }
+}
+
+fn ldap_connect(config: &LdapConfig) -> store::Result<LdapConn> {
+    LdapConn::with_settings(
+        LdapConnSettings::new().set_conn_timeout(Duration::from_secs(config.timeout)),
+        &config.url,
+    )
+    .map_err(ldap_error)
+}
+
+fn ldap_bind(conn: &mut LdapConn, dn: &str, password: &str) -> store::Result<bool> {
+    // Result code 49 is 'invalidCredentials'
+    match conn.simple_bind(dn, password).map_err(ldap_error)?.rc {
+        0 => Ok(true),
+        49 => Ok(false),
+        rc => Err(StoreError::InternalError(format!(
+            "LDAP bind as '{}' failed with result code {}.",
+            dn, rc
+        ))),
+    }
+}
+
+fn ldap_bind_service(conn: &mut LdapConn, config: &LdapConfig) -> store::Result<()> {
+    if let Some(bind_dn) = &config.bind_dn {
+        if !ldap_bind(
+            conn,
+            bind_dn,
+            config.bind_secret.as_deref().unwrap_or_default(),
+        )? {
+            return Err(StoreError::InternalError(format!(
+                "Invalid LDAP credentials for '{}'.",
+                bind_dn
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn ldap_search(
+    conn: &mut LdapConn,
+    config: &LdapConfig,
+    filter: &str,
+) -> store::Result<Vec<LdapEntry>> {
+    Ok(conn
+        .search(
+            &config.base_dn,
+            Scope::Subtree,
+            filter,
+            ldap_attributes(config),
+        )
+        .and_then(|result| result.success())
+        .map_err(ldap_error)?
+        .0
+        .into_iter()
+        .filter_map(|entry| LdapEntry::new(SearchEntry::construct(entry), config))
+        .collect())
+}
+
+fn ldap_attributes(config: &LdapConfig) -> Vec<&str> {
+    vec![
+        config.attr_email.as_str(),
+        config.attr_name.as_str(),
+        config.attr_aliases.as_str(),
+        config.attr_members.as_str(),
+    ]
+}
+
+fn ldap_error(err: LdapError) -> StoreError {
+    StoreError::InternalError(format!("LDAP error: {}", err))
+}
@@ -21,6 +21,8 @@
  * for more details.
 */
 
+use std::time::SystemTime;
+
 use jmap::{
     orm::TinyORM,
     principal::schema::{Principal, Property, Type, Value},
@@ -28,7 +30,11 @@ use jmap::{
 use store::rand::{self, Rng};
 
 pub mod account;
+pub mod credentials;
+pub mod dkim;
 pub mod get;
+pub mod grants;
+pub mod ldap;
 pub mod query;
 pub mod set;
 
@@ -87,3 +93,10 @@ impl CreateAccount for TinyORM<Principal> {
         self
     }
 }
+
+pub(crate) fn unix_timestamp() -> i64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0) as i64
+}
@@ -26,13 +26,15 @@ use jmap::jmap_store::set::SetHelper;
 use jmap::jmap_store::Object;
 use jmap::orm::acl::ACLUpdate;
 use jmap::orm::{serialize::JMAPOrm, TinyORM};
-use jmap::principal::schema::{Patch, Principal, Property, Type, Value, ACCOUNTS_TO_DELETE};
+use jmap::principal::schema::{
+    DKIMAlgorithm, Patch, Principal, Property, Type, Value, ACCOUNTS_TO_DELETE, DKIM,
+};
 use jmap::principal::store::JMAPPrincipals;
 use jmap::request::set::SetRequest;
 use jmap::request::set::SetResponse;
 use jmap::types::jmap::JMAPId;
+use jmap::types::type_state::TypeState;
 use jmap::{sanitize_domain, sanitize_email, SUPERUSER_ID};
-use jmap_mail::mail_send::dkim::DKIM;
 use jmap_mail::mailbox::schema::Mailbox;
 use jmap_mail::mailbox::CreateMailbox;
 use store::ahash::AHashSet;
@@ -40,14 +42,20 @@ use store::core::collection::Collection;
 use store::core::document::Document;
 use store::core::error::StoreError;
 use store::core::tag::Tag;
+use store::core::vec_map::VecMap;
+use store::log::changes::ChangeId;
 use store::rand::Rng;
 use store::read::comparator::Comparator;
 use store::read::filter::{self, Filter, Query};
 use store::read::FilterMapper;
-use store::write::batch::WriteBatch;
+use store::write::batch::{WriteBatch, QUOTA_ID};
 use store::write::options::IndexOptions;
 use store::{rand, DocumentId, JMAPStore, Store};
 
+use super::account::JMAPAccountStore;
+use super::dkim::{dns_record, generate_key};
+use super::unix_timestamp;
+
 pub trait JMAPSetPrincipal<T>
 where
     T: for<'x> Store<'x> + 'static,
@@ -62,6 +70,12 @@ where
     ) -> store::Result<()>;
 
     fn principal_purge(&self) -> store::Result<()>;
+
+    fn principal_provision(
+        &self,
+        email: &str,
+        name: &str,
+    ) -> store::Result<(DocumentId, Option<ChangeId>)>;
 }
 
 impl<T> JMAPSetPrincipal<T> for JMAPStore<T>
@@ -99,6 +113,12 @@ where
                 document.document_id,
             )?;
 
+            // Return the DNS records of any generated DKIM keys
+            let mut principal = Principal::new(document.document_id.into());
+            if let Some(dkim) = dkim_response(&fields) {
+                principal.properties.append(Property::DKIM, dkim);
+            }
+
             // Invalidate cache
             if let Some(Value::Text { value: email }) = fields.get(&Property::Email) {
                 helper.store.recipients.invalidate(email);
@@ -111,9 +131,10 @@ where
 
             fields.insert_validate(document)?;
 
-            Ok(Principal::new(document.document_id.into()))
+            Ok(principal)
         })?;
 
+        let mut quota_changes = Vec::new();
         helper.update(|id, item, helper, document| {
             let document_id = id.get_document_id();
             let current_fields = self
@@ -161,10 +182,22 @@ where
                 helper.store.recipients.invalidate(email);
             }
 
+            let principal = dkim_response(&fields).map(|dkim| {
+                let mut principal = Principal::default();
+                principal.properties.append(Property::DKIM, dkim);
+                principal
+            });
+            let has_quota_change = fields.get(&Property::Quota).map_or(false, |quota| {
+                quota != current_fields.get(&Property::Quota).unwrap_or(&Value::Null)
+            });
+
             // Merge changes
             current_fields.merge_validate(document, fields)?;
+            if has_quota_change {
+                quota_changes.push(document_id);
+            }
 
-            Ok(None)
+            Ok(principal)
         })?;
 
         helper.destroy(|id, helper, document| {
@@ -237,7 +270,21 @@ where
             Ok(())
         })?;
 
-        helper.into_response()
+        let mut response = helper.into_response()?;
+
+        // Log quota limit changes in the accounts they apply to
+        for account_id in quota_changes {
+            let mut batch = WriteBatch::new(account_id);
+            batch.log_update(Collection::Quota, QUOTA_ID);
+            if let Some(changes) = self.write(batch)? {
+                response.change_id = changes.change_id.into();
+                response
+                    .account_state_changes
+                    .push((account_id, vec![(TypeState::Quota, changes.change_id)]));
+            }
+        }
+
+        Ok(response)
     }
 
     fn principal_delete(
@@ -286,6 +333,72 @@ where
 
         Ok(())
     }
+
+    // Creates an individual account for a user authenticated by an external
+    // identity provider. A random secret is assigned as these accounts
+    // are not meant to be accessed using a password.
+    fn principal_provision(
+        &self,
+        email: &str,
+        name: &str,
+    ) -> store::Result<(DocumentId, Option<ChangeId>)> {
+        let mut principal = Principal::default();
+        principal.properties.append(
+            Property::Type,
+            Value::Type {
+                value: Type::Individual,
+            },
+        );
+        principal.properties.append(
+            Property::Email,
+            Value::Text {
+                value: email.to_string(),
+            },
+        );
+        principal.properties.append(
+            Property::Name,
+            Value::Text {
+                value: name.to_string(),
+            },
+        );
+        principal.properties.append(
+            Property::Secret,
+            Value::Text {
+                value: rand::thread_rng()
+                    .sample_iter(rand::distributions::Alphanumeric)
+                    .take(32)
+                    .map(char::from)
+                    .collect(),
+            },
+        );
+
+        let mut response = self
+            .principal_set(SetRequest {
+                acl: self.get_acl_token(SUPERUSER_ID)?.into(),
+                account_id: JMAPId::from(SUPERUSER_ID),
+                if_in_state: None,
+                create: Some(VecMap::from_iter([("p".to_string(), principal)])),
+                update: None,
+                destroy: None,
+                arguments: (),
+            })
+            .map_err(|err| StoreError::InternalError(err.to_string()))?;
+
+        if let Some(document_id) = response
+            .created
+            .get("p")
+            .and_then(|principal| principal.id())
+            .map(|id| id.get_document_id())
+        {
+            Ok((document_id, response.has_changes()))
+        } else {
+            Err(StoreError::InvalidArguments(format!(
+                "Failed to provision account {}: {:?}",
+                email,
+                response.not_created.remove(&"p".to_string())
+            )))
+        }
+    }
 }
 
 trait PrincipalSet<T>: Sized
@@ -330,6 +443,8 @@ where
             }
         };
         let mut validate_emails = Vec::new();
+        let mut dkim_key = None;
+        let has_secret = principal.properties.get(&Property::Secret).is_some();
 
         // Process changes
         for (property, value) in principal.properties {
@@ -412,7 +527,7 @@ where
                     let mut aliases = Vec::with_capacity(value.len());
                     for email in value {
                         if let Some(email) = sanitize_email(&email) {
-                            if current_fields.map_or(true, |v| match v.get(&Property::Email) {
+                            if current_fields.map_or(true, |v| match v.get(&Property::Aliases) {
                                 Some(Value::TextList { value }) => !value.contains(&email),
                                 _ => true,
                             }) {
@@ -487,9 +602,10 @@ where
                     if !value.is_empty() && ptype == Type::Domain =>
                 {
                     // Validate DKIM key
-                    match DKIM::from_pkcs1_pem(&value) {
-                        Ok(_) => {
+                    match dns_record(&value) {
+                        Ok(key) => {
                             self.tag(Property::DKIM, Tag::Default);
+                            dkim_key = key.into();
                             Value::Text { value }
                         }
                         Err(err) => {
@@ -538,7 +654,81 @@ where
                     continue;
                 }
 
-                (Property::DKIM, value @ Value::DKIM { .. }) if ptype == Type::Domain => value,
+                (Property::DKIM, Value::DKIM { mut value }) if ptype == Type::Domain => {
+                    let mut current_dkim = match current_fields
+                        .and_then(|current_fields| current_fields.get(&Property::DKIM))
+                    {
+                        Some(Value::DKIM { value }) => value.clone(),
+                        _ => DKIM::default(),
+                    };
+                    let promoted_secret = current_dkim.promote(unix_timestamp());
+
+                    if let Some(algorithm) = value.dkim_generate.take() {
+                        if has_secret {
+                            return Err(SetError::invalid_property(
+                                property,
+                                "Cannot generate a DKIM key while setting a secret.".to_string(),
+                            ));
+                        }
+                        let (secret, record) = new_dkim_key(algorithm, property)?;
+                        self.set(Property::Secret, Value::Text { value: secret });
+                        self.tag(Property::DKIM, Tag::Default);
+                        value.dkim_algorithm = algorithm.into();
+                        value.dkim_record = record.into();
+                    } else if !has_secret {
+                        if let Some(secret) = promoted_secret {
+                            self.set(Property::Secret, Value::Text { value: secret });
+                        }
+                        value.dkim_algorithm = current_dkim.dkim_algorithm;
+                        value.dkim_record = current_dkim.dkim_record;
+                    }
+
+                    if let Some(rotation) = &mut value.dkim_rotation {
+                        if rotation.selector.is_empty()
+                            || value.dkim_selector.as_ref() == Some(&rotation.selector)
+                        {
+                            return Err(SetError::invalid_property(
+                                property,
+                                "The rotation selector must differ from the current selector."
+                                    .to_string(),
+                            ));
+                        }
+
+                        if let Some(secret) = &rotation.secret {
+                            let (algorithm, record) = dns_record(secret).map_err(|err| {
+                                SetError::invalid_property(
+                                    property,
+                                    format!("Invalid DKIM key: {}", err),
+                                )
+                            })?;
+                            rotation.algorithm = algorithm.into();
+                            rotation.record = record.into();
+                        } else if let Some(current_rotation) =
+                            current_dkim.dkim_rotation.filter(|current_rotation| {
+                                current_rotation.selector == rotation.selector
+                                    && rotation.algorithm.map_or(true, |algorithm| {
+                                        current_rotation.algorithm == Some(algorithm)
+                                    })
+                            })
+                        {
+                            // Keep the key whose record might already be published
+                            rotation.algorithm = current_rotation.algorithm;
+                            rotation.record = current_rotation.record;
+                            rotation.secret = current_rotation.secret;
+                        } else {
+                            let algorithm = rotation
+                                .algorithm
+                                .or(value.dkim_algorithm)
+                                .unwrap_or(DKIMAlgorithm::Rsa);
+                            let (secret, record) = new_dkim_key(algorithm, property)?;
+                            rotation.algorithm = algorithm.into();
+                            rotation.record = record.into();
+                            rotation.secret = secret.into();
+                        }
+                    }
+
+                    Value::DKIM { value }
+                }
 
                 (Property::Quota, value @ (Value::Number { .. } | Value::Null)) => value,
 
@@ -718,6 +908,20 @@ where
             self.set(property, value);
         }
 
+        // Publish the DNS record of the DKIM key provided by the client
+        if let Some((algorithm, record)) = dkim_key {
+            let mut dkim = match self.get(&Property::DKIM).or_else(|| {
+                current_fields.and_then(|current_fields| current_fields.get(&Property::DKIM))
+            }) {
+                Some(Value::DKIM { value }) => value.clone(),
+                _ => DKIM::default(),
+            };
+            dkim.promote(unix_timestamp());
+            dkim.dkim_algorithm = algorithm.into();
+            dkim.dkim_record = record.into();
+            self.set(Property::DKIM, Value::DKIM { value: dkim });
+        }
+
         // Validate e-mail addresses
         if !validate_emails.is_empty() {
             // Check that the domains exist
@@ -839,3 +1043,27 @@ where
         Ok(self)
     }
 }
+
+fn new_dkim_key(
+    algorithm: DKIMAlgorithm,
+    property: Property,
+) -> jmap::error::set::Result<(String, String), Property> {
+    generate_key(algorithm)
+        .and_then(|secret| dns_record(&secret).map(|(_, record)| (secret, record)))
+        .map_err(|err| {
+            SetError::invalid_property(property, format!("Failed to generate DKIM key: {}", err))
+        })
+}
+
+fn dkim_response(fields: &TinyORM<Principal>) -> Option<Value> {
+    match fields.get(&Property::DKIM) {
+        Some(Value::DKIM { value }) if value.dkim_record.is_some() => {
+            let mut value = value.clone();
+            if let Some(rotation) = &mut value.dkim_rotation {
+                rotation.secret = None;
+            }
+            Some(Value::DKIM { value })
+        }
+        _ => None,
+    }
+}
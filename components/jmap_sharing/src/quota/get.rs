@@ -0,0 +1,136 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use super::schema::{Property, Quota, ResourceType, Scope};
+use jmap::{
+    error::method::MethodError,
+    jmap_store::get::{GetHelper, GetObject, IdMapper, SharedDocsFnc},
+    principal::store::JMAPPrincipals,
+    request::{
+        get::{GetRequest, GetResponse},
+        MaybeResultReference,
+    },
+    types::jmap::JMAPId,
+};
+use store::{write::batch::QUOTA_ID, JMAPStore, Store};
+
+impl GetObject for Quota {
+    type GetArguments = ();
+
+    fn default_properties() -> Vec<Self::Property> {
+        vec![
+            Property::Id,
+            Property::ResourceType,
+            Property::Used,
+            Property::HardLimit,
+            Property::Scope,
+            Property::Name,
+            Property::Types,
+        ]
+    }
+
+    fn get_as_id(&self, property: &Self::Property) -> Option<Vec<JMAPId>> {
+        match property {
+            Property::Id => vec![*self.id.as_ref()?].into(),
+            _ => None,
+        }
+    }
+}
+
+pub trait JMAPGetQuota<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn quota_get(&self, request: GetRequest<Quota>) -> jmap::Result<GetResponse<Quota>>;
+}
+
+impl<T> JMAPGetQuota<T> for JMAPStore<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn quota_get(&self, mut request: GetRequest<Quota>) -> jmap::Result<GetResponse<Quota>> {
+        // Quotas are only reported for accounts that have a storage limit
+        let account_id = request.account_id.get_document_id();
+        let hard_limit = self.principal_quota(account_id)?;
+        if request.ids.is_none() && hard_limit.is_some() {
+            request.ids = MaybeResultReference::Value(vec![QUOTA_ID.into()]).into();
+        }
+
+        let mut helper = GetHelper::new(self, request, None::<IdMapper>, None::<SharedDocsFnc>)?;
+
+        // Unknown properties are rejected rather than silently ignored
+        if helper.properties.contains(&Property::Invalid) {
+            return Err(MethodError::InvalidArguments(
+                "One or more properties are not supported.".to_string(),
+            ));
+        }
+
+        // Add Id Property
+        if !helper.properties.contains(&Property::Id) {
+            helper.properties.push(Property::Id);
+        }
+
+        helper.get(|id, properties| {
+            let hard_limit = match hard_limit {
+                Some(hard_limit) if u64::from(id) == QUOTA_ID => hard_limit,
+                _ => return Ok(None),
+            };
+            let mut quota = Quota::default();
+
+            for property in properties {
+                match property {
+                    Property::Id => {
+                        quota.id = id.into();
+                    }
+                    Property::ResourceType => {
+                        quota.resource_type = ResourceType::Octets.into();
+                    }
+                    Property::Used => {
+                        quota.used = (self.get_used_quota(account_id)?.max(0) as u64).into();
+                    }
+                    Property::HardLimit => {
+                        quota.hard_limit = hard_limit.into();
+                    }
+                    Property::Scope => {
+                        quota.scope = Scope::Account.into();
+                    }
+                    Property::Name => {
+                        quota.name = self
+                            .principal_to_email(account_id)?
+                            .unwrap_or_default()
+                            .into();
+                    }
+                    Property::Types => {
+                        quota.types = vec!["Mail".to_string()].into();
+                    }
+                    Property::WarnLimit
+                    | Property::SoftLimit
+                    | Property::Description
+                    | Property::Invalid => (),
+                }
+            }
+
+            Ok(Some(quota))
+        })
+    }
+}
@@ -0,0 +1,136 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap::{
+    error::method::MethodError,
+    jmap_store::{changes::JMAPChanges, query::QueryObject},
+    principal::store::JMAPPrincipals,
+    request::query::{self, QueryRequest, QueryResponse},
+};
+use store::{core::collection::Collection, write::batch::QUOTA_ID, JMAPStore, Store};
+
+use super::schema::{Comparator, Filter, Quota, ResourceType, Scope};
+
+impl QueryObject for Quota {
+    type QueryArguments = ();
+
+    type Filter = Filter;
+
+    type Comparator = Comparator;
+}
+
+pub trait JMAPQuotaQuery<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn quota_query(&self, request: QueryRequest<Quota>) -> jmap::Result<QueryResponse>;
+}
+
+impl<T> JMAPQuotaQuery<T> for JMAPStore<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn quota_query(&self, request: QueryRequest<Quota>) -> jmap::Result<QueryResponse> {
+        let account_id = request.account_id.get_document_id();
+        let mut response = QueryResponse {
+            account_id: request.account_id,
+            position: 0,
+            query_state: self.get_state(account_id, Collection::Quota)?,
+            total: None,
+            limit: None,
+            ids: Vec::with_capacity(0),
+            is_immutable: false,
+            can_calculate_changes: true,
+        };
+
+        // Each account has at most one quota, so filters are evaluated
+        // in memory rather than through the store.
+        let mut ids = Vec::with_capacity(1);
+        if self.principal_quota(account_id)?.is_some() {
+            let name = self.principal_to_email(account_id)?.unwrap_or_default();
+            if request
+                .filter
+                .as_ref()
+                .map_or(Ok(true), |filter| matches_filter(filter, &name))?
+            {
+                ids.push(QUOTA_ID.into());
+            }
+        }
+
+        let limit = match request.limit {
+            Some(0) => {
+                if request.calculate_total.unwrap_or(false) {
+                    response.total = ids.len().into();
+                }
+                return Ok(response);
+            }
+            Some(limit) => std::cmp::min(limit, self.config.query_max_results),
+            None => self.config.query_max_results,
+        };
+        let total_results = ids.len();
+
+        response.paginate(
+            ids.into_iter(),
+            limit,
+            request.position.unwrap_or(0),
+            request.anchor,
+            request.anchor_offset.unwrap_or(0),
+        )?;
+
+        if limit < total_results {
+            response.limit = limit.into();
+        }
+
+        if request.calculate_total.unwrap_or(false) {
+            response.total = total_results.into();
+        }
+
+        Ok(response)
+    }
+}
+
+fn matches_filter(filter: &query::Filter<Filter>, name: &str) -> jmap::Result<bool> {
+    Ok(match filter {
+        query::Filter::FilterOperator(op) => {
+            let mut results = Vec::with_capacity(op.conditions.len());
+            for condition in &op.conditions {
+                results.push(matches_filter(condition, name)?);
+            }
+            match op.operator {
+                query::Operator::And => results.into_iter().all(|r| r),
+                query::Operator::Or => results.into_iter().any(|r| r),
+                query::Operator::Not => !results.into_iter().any(|r| r),
+            }
+        }
+        query::Filter::FilterCondition(condition) => match condition {
+            Filter::Name { value } => name.contains(value.as_str()),
+            Filter::Scope { value } => *value == Scope::Account,
+            Filter::ResourceType { value } => *value == ResourceType::Octets,
+            Filter::Type { value } => value == "Mail",
+            Filter::Unsupported { value } => {
+                return Err(MethodError::UnsupportedFilter(value.to_string()));
+            }
+        },
+        query::Filter::Empty => true,
+    })
+}
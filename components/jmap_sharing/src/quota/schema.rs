@@ -0,0 +1,234 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::fmt;
+
+use jmap::{request::query::FilterDeserializer, types::jmap::JMAPId};
+use serde::{de::IgnoredAny, Deserialize, Serialize};
+use store::FieldId;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Quota {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<JMAPId>,
+
+    #[serde(rename = "resourceType")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resource_type: Option<ResourceType>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub used: Option<u64>,
+
+    #[serde(rename = "hardLimit")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hard_limit: Option<u64>,
+
+    #[serde(rename = "warnLimit")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warn_limit: Option<u64>,
+
+    #[serde(rename = "softLimit")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub soft_limit: Option<u64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<Scope>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub types: Option<Vec<String>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ResourceType {
+    #[serde(rename = "count")]
+    Count,
+    #[serde(rename = "octets")]
+    Octets,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Scope {
+    #[serde(rename = "account")]
+    Account,
+    #[serde(rename = "domain")]
+    Domain,
+    #[serde(rename = "global")]
+    Global,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq, Hash, Copy)]
+#[repr(u8)]
+pub enum Property {
+    #[serde(rename = "id")]
+    Id = 0,
+    #[serde(rename = "resourceType")]
+    ResourceType = 1,
+    #[serde(rename = "used")]
+    Used = 2,
+    #[serde(rename = "hardLimit")]
+    HardLimit = 3,
+    #[serde(rename = "warnLimit")]
+    WarnLimit = 4,
+    #[serde(rename = "softLimit")]
+    SoftLimit = 5,
+    #[serde(rename = "scope")]
+    Scope = 6,
+    #[serde(rename = "name")]
+    Name = 7,
+    #[serde(rename = "types")]
+    Types = 8,
+    #[serde(rename = "description")]
+    Description = 9,
+    #[serde(skip)]
+    Invalid = 10,
+}
+
+impl Property {
+    pub fn parse(value: &str) -> Self {
+        match value {
+            "id" => Property::Id,
+            "resourceType" => Property::ResourceType,
+            "used" => Property::Used,
+            "hardLimit" => Property::HardLimit,
+            "warnLimit" => Property::WarnLimit,
+            "softLimit" => Property::SoftLimit,
+            "scope" => Property::Scope,
+            "name" => Property::Name,
+            "types" => Property::Types,
+            "description" => Property::Description,
+            _ => Property::Invalid,
+        }
+    }
+}
+
+impl From<Property> for FieldId {
+    fn from(property: Property) -> Self {
+        property as FieldId
+    }
+}
+
+impl From<FieldId> for Property {
+    fn from(field: FieldId) -> Self {
+        match field {
+            0 => Property::Id,
+            1 => Property::ResourceType,
+            2 => Property::Used,
+            3 => Property::HardLimit,
+            4 => Property::WarnLimit,
+            5 => Property::SoftLimit,
+            6 => Property::Scope,
+            7 => Property::Name,
+            8 => Property::Types,
+            9 => Property::Description,
+            _ => Property::Invalid,
+        }
+    }
+}
+
+impl TryFrom<&str> for Property {
+    type Error = ();
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match Property::parse(value) {
+            Property::Invalid => Err(()),
+            property => Ok(property),
+        }
+    }
+}
+
+struct PropertyVisitor;
+
+impl<'de> serde::de::Visitor<'de> for PropertyVisitor {
+    type Value = Property;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a valid JMAP Quota property")
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        Ok(Property::parse(v))
+    }
+}
+
+impl<'de> Deserialize<'de> for Property {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_str(PropertyVisitor)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Filter {
+    Name { value: String },
+    Scope { value: Scope },
+    ResourceType { value: ResourceType },
+    Type { value: String },
+    Unsupported { value: String },
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "property")]
+pub enum Comparator {
+    #[serde(rename = "name")]
+    Name,
+    #[serde(rename = "used")]
+    Used,
+}
+
+// Filter deserializer
+impl FilterDeserializer for Filter {
+    fn deserialize<'x>(property: &str, map: &mut impl serde::de::MapAccess<'x>) -> Option<Self> {
+        match property {
+            "name" => Filter::Name {
+                value: map.next_value().ok()?,
+            },
+            "scope" => Filter::Scope {
+                value: map.next_value().ok()?,
+            },
+            "resourceType" => Filter::ResourceType {
+                value: map.next_value().ok()?,
+            },
+            "type" => Filter::Type {
+                value: map.next_value().ok()?,
+            },
+            unsupported => {
+                map.next_value::<IgnoredAny>().ok()?;
+                Filter::Unsupported {
+                    value: unsupported.to_string(),
+                }
+            }
+        }
+        .into()
+    }
+}
@@ -0,0 +1,475 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    io::{Read, Write},
+    ops::Range,
+};
+
+use crate::{config::env_settings::EnvSettings, core::error::StoreError};
+
+pub const COMPRESSED_MAGIC: &[u8; 4] = &[0xFF, b'C', b'M', b'P'];
+// Magic, codec, chunk size, blob size and number of chunks
+pub const HEADER_LEN: usize = COMPRESSED_MAGIC.len() + 1 + 3 * std::mem::size_of::<u32>();
+// Set on the length of chunks that did not compress and are stored as is
+pub(crate) const CHUNK_RAW: u32 = 1 << 31;
+// Set on the codec of frames whose chunk index and data are encrypted
+pub const FRAME_ENCRYPTED: u8 = 0x80;
+
+const MIN_CHUNK_SIZE: usize = 4 * 1024;
+const MAX_CHUNK_SIZE: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    None = 0,
+    Lz4 = 1,
+    Zstd = 2,
+}
+
+pub struct BlobCompression {
+    pub codec: Codec,
+    pub level: i32,
+    pub chunk_size: usize,
+}
+
+/// Blobs are split in chunks that are compressed independently, which allows
+/// reading a range without having to inflate the whole blob. All blobs are
+/// framed, including the ones stored with no compression, so that only blobs
+/// written before compression was supported lack a header. The header is
+/// never encrypted, it flags whether the rest of the frame is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameHeader {
+    pub codec: Codec,
+    pub encrypted: bool,
+    pub chunk_size: u32,
+    pub blob_size: u32,
+    pub chunk_count: u32,
+}
+
+impl BlobCompression {
+    pub fn new(settings: &EnvSettings) -> crate::Result<Self> {
+        Ok(BlobCompression {
+            codec: match settings.get("blob-compression").as_deref() {
+                Some("lz4") | None => Codec::Lz4,
+                Some("zstd") => Codec::Zstd,
+                Some("none") => Codec::None,
+                Some(other) => {
+                    return Err(StoreError::InvalidArguments(format!(
+                        "Unknown blob compression codec '{}'.",
+                        other
+                    )))
+                }
+            },
+            level: settings.parse("blob-compression-level").unwrap_or(3),
+            chunk_size: settings
+                .parse("blob-compression-chunk-size")
+                .unwrap_or(64 * 1024)
+                .clamp(MIN_CHUNK_SIZE, MAX_CHUNK_SIZE),
+        })
+    }
+
+    /// Compresses a blob, chunks that do not compress are stored as is.
+    pub fn compress(&self, bytes: Vec<u8>) -> crate::Result<Vec<u8>> {
+        let mut data = Vec::with_capacity(if self.codec != Codec::None {
+            bytes.len() / 2
+        } else {
+            bytes.len()
+        });
+        let mut result = self.compress_stream(&mut &bytes[..], bytes.len() as u64, &mut data)?;
+        result.extend_from_slice(&data);
+        Ok(result)
+    }
+
+    /// Compresses a blob of the specified size read from `source`, the chunks are
+    /// written to `data` and the frame header and chunk index that have to
+    /// precede them are returned.
+    pub fn compress_stream(
+        &self,
+        source: &mut impl Read,
+        size: u64,
+        data: &mut impl Write,
+    ) -> crate::Result<Vec<u8>> {
+        if size > u32::MAX as u64 {
+            return Err(StoreError::InvalidArguments(format!(
+                "Blob size {} exceeds the maximum supported size.",
+                size
+            )));
+        }
+
+        let chunk_count = (size as usize + self.chunk_size - 1) / self.chunk_size;
+        let mut frame = Vec::with_capacity(HEADER_LEN + chunk_count * std::mem::size_of::<u32>());
+        frame.extend_from_slice(COMPRESSED_MAGIC);
+        frame.push(self.codec as u8);
+        frame.extend_from_slice(&(self.chunk_size as u32).to_le_bytes());
+        frame.extend_from_slice(&(size as u32).to_le_bytes());
+        frame.extend_from_slice(&(chunk_count as u32).to_le_bytes());
+
+        let mut buf = vec![0u8; std::cmp::min(self.chunk_size, size as usize)];
+        let mut remaining = size as usize;
+        while remaining > 0 {
+            let chunk = &mut buf[..std::cmp::min(remaining, self.chunk_size)];
+            source.read_exact(chunk)?;
+            remaining -= chunk.len();
+
+            let compressed = if self.codec != Codec::None {
+                Some(self.codec.compress(chunk, self.level)?)
+            } else {
+                None
+            };
+            match compressed {
+                Some(compressed) if compressed.len() < chunk.len() => {
+                    frame.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+                    data.write_all(&compressed)?;
+                }
+                _ => {
+                    frame.extend_from_slice(&(chunk.len() as u32 | CHUNK_RAW).to_le_bytes());
+                    data.write_all(chunk)?;
+                }
+            }
+        }
+
+        Ok(frame)
+    }
+}
+
+impl Codec {
+    fn compress(&self, bytes: &[u8], level: i32) -> crate::Result<Vec<u8>> {
+        match self {
+            Codec::Lz4 => Ok(lz4_flex::compress(bytes)),
+            Codec::Zstd => zstd::bulk::compress(bytes, level).map_err(|err| {
+                StoreError::InternalError(format!("Failed to compress blob: {}", err))
+            }),
+            Codec::None => Ok(bytes.to_vec()),
+        }
+    }
+
+    fn decompress(&self, bytes: &[u8], size: usize) -> crate::Result<Vec<u8>> {
+        match self {
+            Codec::Lz4 => lz4_flex::decompress(bytes, size).map_err(|err| err.to_string()),
+            Codec::Zstd => zstd::bulk::decompress(bytes, size).map_err(|err| err.to_string()),
+            Codec::None => Ok(bytes.to_vec()),
+        }
+        .and_then(|bytes| {
+            if bytes.len() == size {
+                Ok(bytes)
+            } else {
+                Err(format!("expected {} bytes, got {}", size, bytes.len()))
+            }
+        })
+        .map_err(|err| StoreError::DataCorruption(format!("Failed to decompress blob: {}", err)))
+    }
+}
+
+impl FrameHeader {
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < HEADER_LEN || !bytes.starts_with(COMPRESSED_MAGIC) {
+            return None;
+        }
+        let read_u32 = |pos: usize| {
+            u32::from_le_bytes(
+                bytes[pos..pos + std::mem::size_of::<u32>()]
+                    .try_into()
+                    .unwrap(),
+            )
+        };
+
+        let header = FrameHeader {
+            codec: match bytes[COMPRESSED_MAGIC.len()] & !FRAME_ENCRYPTED {
+                0 => Codec::None,
+                1 => Codec::Lz4,
+                2 => Codec::Zstd,
+                _ => return None,
+            },
+            encrypted: bytes[COMPRESSED_MAGIC.len()] & FRAME_ENCRYPTED != 0,
+            chunk_size: read_u32(COMPRESSED_MAGIC.len() + 1),
+            blob_size: read_u32(COMPRESSED_MAGIC.len() + 5),
+            chunk_count: read_u32(COMPRESSED_MAGIC.len() + 9),
+        };
+
+        // Reject headers that could not have been written by `compress`
+        if (MIN_CHUNK_SIZE..=MAX_CHUNK_SIZE).contains(&(header.chunk_size as usize))
+            && header.chunk_count as u64
+                == (header.blob_size as u64 + header.chunk_size as u64 - 1)
+                    / header.chunk_size as u64
+        {
+            Some(header)
+        } else {
+            None
+        }
+    }
+
+    /// Position of the chunk length index within the compressed blob.
+    pub fn index_range(&self) -> Range<u32> {
+        HEADER_LEN as u32..self.data_offset()
+    }
+
+    pub fn parse_index(&self, bytes: &[u8]) -> Option<Vec<u32>> {
+        if bytes.len() == self.chunk_count as usize * std::mem::size_of::<u32>() {
+            Some(
+                bytes
+                    .chunks_exact(std::mem::size_of::<u32>())
+                    .map(|len| u32::from_le_bytes(len.try_into().unwrap()))
+                    .collect(),
+            )
+        } else {
+            None
+        }
+    }
+
+    /// Returns the chunks spanning a range of the uncompressed blob along with
+    /// their position within the compressed blob.
+    pub fn locate(&self, index: &[u32], range: &Range<u32>) -> (Range<usize>, Range<u32>) {
+        let end = std::cmp::min(range.end, self.blob_size);
+        if range.start >= end || self.chunk_size == 0 {
+            return (0..0, 0..0);
+        }
+        let chunks = (range.start / self.chunk_size) as usize
+            ..std::cmp::min((end - 1) / self.chunk_size + 1, self.chunk_count) as usize;
+        let offset = self.data_offset()
+            + index[..chunks.start]
+                .iter()
+                .map(|len| len & !CHUNK_RAW)
+                .sum::<u32>();
+        let length = index[chunks.clone()]
+            .iter()
+            .map(|len| len & !CHUNK_RAW)
+            .sum::<u32>();
+
+        (chunks, offset..offset + length)
+    }
+
+    /// Decompresses the chunks returned by `locate` and returns the requested range.
+    pub fn decompress_chunks(
+        &self,
+        index: &[u32],
+        chunks: Range<usize>,
+        data: &[u8],
+        range: &Range<u32>,
+    ) -> crate::Result<Vec<u8>> {
+        if chunks.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let chunk_size = self.chunk_size as usize;
+        let mut bytes = Vec::with_capacity(chunks.len() * chunk_size);
+        let mut pos = 0;
+        for chunk_id in chunks.clone() {
+            let length = (index[chunk_id] & !CHUNK_RAW) as usize;
+            let chunk = data.get(pos..pos + length).ok_or_else(|| {
+                StoreError::DataCorruption("Compressed blob is truncated.".to_string())
+            })?;
+            pos += length;
+
+            if index[chunk_id] & CHUNK_RAW != 0 {
+                bytes.extend_from_slice(chunk);
+            } else {
+                bytes.extend_from_slice(&self.codec.decompress(
+                    chunk,
+                    std::cmp::min(chunk_size, self.blob_size as usize - chunk_id * chunk_size),
+                )?);
+            }
+        }
+
+        let base = chunks.start * chunk_size;
+        let from = range.start as usize - base;
+        let to = std::cmp::min(range.end, self.blob_size) as usize - base;
+        if from > 0 || to < bytes.len() {
+            Ok(bytes.get(from..to).unwrap_or_default().to_vec())
+        } else {
+            Ok(bytes)
+        }
+    }
+
+    pub(crate) fn data_offset(&self) -> u32 {
+        (HEADER_LEN + self.chunk_count as usize * std::mem::size_of::<u32>()) as u32
+    }
+}
+
+/// Decompresses a blob, blobs stored before compression was supported are
+/// returned unchanged.
+pub fn decompress(bytes: Vec<u8>) -> crate::Result<Vec<u8>> {
+    if FrameHeader::parse(&bytes).is_some() {
+        decompress_range(&bytes, 0..u32::MAX)
+    } else {
+        Ok(bytes)
+    }
+}
+
+/// Returns a range of a compressed blob that is already in memory.
+pub fn decompress_range(bytes: &[u8], range: Range<u32>) -> crate::Result<Vec<u8>> {
+    let header = FrameHeader::parse(bytes)
+        .ok_or_else(|| StoreError::DataCorruption("Invalid compressed blob header.".to_string()))?;
+    if header.encrypted {
+        return Err(StoreError::InvalidArguments(
+            "Found an encrypted blob but no encryption master key was configured.".to_string(),
+        ));
+    }
+    let index = bytes
+        .get(header.index_range().start as usize..header.index_range().end as usize)
+        .and_then(|index| header.parse_index(index))
+        .ok_or_else(|| StoreError::DataCorruption("Compressed blob is truncated.".to_string()))?;
+    let (chunks, data) = header.locate(&index, &range);
+    header.decompress_chunks(
+        &index,
+        chunks,
+        bytes
+            .get(data.start as usize..data.end as usize)
+            .ok_or_else(|| {
+                StoreError::DataCorruption("Compressed blob is truncated.".to_string())
+            })?,
+        &range,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        decompress, decompress_range, BlobCompression, Codec, FrameHeader, COMPRESSED_MAGIC,
+        HEADER_LEN,
+    };
+
+    #[test]
+    fn chunked_compression() {
+        let blob = (0..300_000u32)
+            .map(|n| format!("Line {} of a rather repetitive message.\r\n", n % 1000))
+            .collect::<String>()
+            .into_bytes();
+
+        for codec in [Codec::Lz4, Codec::Zstd] {
+            let compression = BlobCompression {
+                codec,
+                level: 3,
+                chunk_size: 16 * 1024,
+            };
+            let compressed = compression.compress(blob.clone()).unwrap();
+            let header = FrameHeader::parse(&compressed).unwrap();
+            assert_eq!(header.codec, codec);
+            assert_eq!(header.blob_size as usize, blob.len());
+            assert!(compressed.len() < blob.len() / 2, "{:?}", codec);
+            assert_eq!(decompress(compressed.clone()).unwrap(), blob);
+
+            for range in [
+                0..1,
+                0..16 * 1024,
+                16 * 1024 - 10..16 * 1024 + 10,
+                100_000..100_100,
+                blob.len() as u32 - 5..blob.len() as u32,
+                blob.len() as u32 - 5..u32::MAX,
+                500_000..2_000_000,
+            ] {
+                assert_eq!(
+                    decompress_range(&compressed, range.clone()).unwrap(),
+                    blob[range.start as usize..std::cmp::min(range.end as usize, blob.len())],
+                    "{:?} {:?}",
+                    codec,
+                    range
+                );
+            }
+
+            // Only the chunks spanning the range have to be read
+            let index = header
+                .parse_index(
+                    &compressed
+                        [header.index_range().start as usize..header.index_range().end as usize],
+                )
+                .unwrap();
+            let range = 100_000..100_100;
+            let (chunks, data) = header.locate(&index, &range);
+            assert_eq!(chunks, 6..7);
+            assert!(data.end - data.start < 16 * 1024);
+            assert_eq!(
+                header
+                    .decompress_chunks(
+                        &index,
+                        chunks,
+                        &compressed[data.start as usize..data.end as usize],
+                        &range
+                    )
+                    .unwrap(),
+                blob[100_000..100_100]
+            );
+
+            // Out of range reads return no data
+            assert_eq!(
+                decompress_range(&compressed, blob.len() as u32..u32::MAX).unwrap(),
+                Vec::<u8>::new()
+            );
+        }
+
+        // Chunks that do not compress are stored as is, blobs are always framed
+        let random = (0..10_000)
+            .map(|_| rand::random::<u8>())
+            .collect::<Vec<_>>();
+        for codec in [Codec::Lz4, Codec::None] {
+            let compression = BlobCompression {
+                codec,
+                level: 3,
+                chunk_size: 4096,
+            };
+            let compressed = compression.compress(random.clone()).unwrap();
+            assert_eq!(compressed.len(), HEADER_LEN + 3 * 4 + random.len());
+            assert_eq!(decompress(compressed.clone()).unwrap(), random);
+            assert_eq!(
+                decompress_range(&compressed, 5000..5010).unwrap(),
+                random[5000..5010]
+            );
+        }
+
+        // Streamed blobs produce the same frames
+        let compression = BlobCompression {
+            codec: Codec::Zstd,
+            level: 3,
+            chunk_size: 4096,
+        };
+        let blob = [&random[..], &b"Compressible ".repeat(2000)[..]].concat();
+        let mut data = Vec::new();
+        let mut frame = compression
+            .compress_stream(&mut &blob[..], blob.len() as u64, &mut data)
+            .unwrap();
+        frame.extend_from_slice(&data);
+        assert_eq!(frame, compression.compress(blob.clone()).unwrap());
+        assert_eq!(decompress(frame).unwrap(), blob);
+
+        // Raw blobs that look like a frame header are not mistaken for one
+        let compression = BlobCompression {
+            codec: Codec::Lz4,
+            level: 3,
+            chunk_size: 4096,
+        };
+        for blob in [
+            Vec::new(),
+            b"hello".to_vec(),
+            COMPRESSED_MAGIC.to_vec(),
+            [
+                &COMPRESSED_MAGIC[..],
+                &[1, 0, 16, 0, 0, 5, 0, 0, 0, 1, 0, 0, 0, 5],
+            ]
+            .concat(),
+            [&COMPRESSED_MAGIC[..], &random[..]].concat(),
+        ] {
+            let compressed = compression.compress(blob.clone()).unwrap();
+            assert!(FrameHeader::parse(&compressed).is_some());
+            assert_eq!(decompress(compressed).unwrap(), blob);
+        }
+    }
+}
@@ -23,17 +23,16 @@
 
 use std::{
     fs::{self, File},
-    io::{Read, Seek, SeekFrom, Write},
+    io::{self, Read, Seek, SeekFrom, Write},
     ops::Range,
     path::PathBuf,
 };
 
-use crate::{config::env_settings::EnvSettings, write::mutex_map::MutexMap};
+use crate::config::env_settings::EnvSettings;
 
 use super::{BlobId, BlobStore};
 
 pub struct LocalBlobStore {
-    pub lock: MutexMap<()>,
     pub base_path: PathBuf,
     pub hash_levels: usize,
 }
@@ -47,30 +46,50 @@ impl BlobStore for LocalBlobStore {
         );
         base_path.push("blobs");
         Ok(LocalBlobStore {
-            lock: MutexMap::with_capacity(1024),
             base_path,
             hash_levels: std::cmp::min(settings.parse("blob-nested-levels").unwrap_or(2), 5),
         })
     }
 
     fn put(&self, blob_id: &BlobId, blob: &[u8]) -> crate::Result<bool> {
+        self.put_stream(blob_id, &mut &blob[..], blob.len() as u64)
+    }
+
+    fn put_stream(
+        &self,
+        blob_id: &BlobId,
+        reader: &mut dyn Read,
+        size: u64,
+    ) -> crate::Result<bool> {
         let blob_path = self.get_path(blob_id)?;
 
         if blob_path.exists() {
             let metadata = fs::metadata(&blob_path)?;
-            if metadata.len() as usize == blob.len() {
+            if metadata.len() == size {
                 return Ok(false);
             }
         }
 
         fs::create_dir_all(blob_path.parent().unwrap())?;
         let mut blob_file = File::create(&blob_path)?;
-        blob_file.write_all(blob)?;
+        io::copy(reader, &mut blob_file)?;
         blob_file.flush()?;
 
         Ok(true)
     }
 
+    fn replace(&self, blob_id: &BlobId, blob: &[u8]) -> crate::Result<()> {
+        // Write to a temporary file first, the blob is never left truncated
+        let blob_path = self.get_path(blob_id)?;
+        let temp_path = blob_path.with_extension("tmp");
+        let mut blob_file = File::create(&temp_path)?;
+        blob_file.write_all(blob)?;
+        blob_file.sync_all()?;
+        fs::rename(&temp_path, &blob_path)?;
+
+        Ok(())
+    }
+
     fn get_range(&self, blob_id: &BlobId, range: Range<u32>) -> crate::Result<Option<Vec<u8>>> {
         let blob_path = self.get_path(blob_id)?;
         if !blob_path.exists() {
@@ -109,6 +128,15 @@ impl BlobStore for LocalBlobStore {
             Ok(false)
         }
     }
+
+    fn size(&self, blob_id: &BlobId) -> crate::Result<Option<u64>> {
+        let blob_path = self.get_path(blob_id)?;
+        if blob_path.exists() {
+            Ok(Some(fs::metadata(&blob_path)?.len()))
+        } else {
+            Ok(None)
+        }
+    }
 }
 
 impl LocalBlobStore {
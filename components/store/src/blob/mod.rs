@@ -21,17 +21,28 @@
  * for more details.
 */
 
-use std::{convert::TryInto, fmt::Display, io::Write, ops::Range};
+use std::{
+    convert::TryInto,
+    fmt::Display,
+    io::{Read, Write},
+    ops::Range,
+};
 
 use sha2::{Digest, Sha256};
 
 use crate::{
     config::env_settings::EnvSettings,
+    core::error::StoreError,
     serialize::{base32::Base32Writer, StoreDeserialize, StoreSerialize},
+    write::mutex_map::MutexMap,
 };
 
+use self::{compress::BlobCompression, local::LocalBlobStore, s3::S3BlobStore};
+
+pub mod compress;
 pub mod local;
 pub mod purge;
+pub mod s3;
 pub mod store;
 
 pub const BLOB_HASH_LEN: usize = 32;
@@ -117,6 +128,27 @@ impl StoreDeserialize for BlobId {
     }
 }
 
+// Ephemeral links hold their creation time followed by the number of
+// bytes the upload counts against the account's quota until it is linked
+// to a document or expires. Links without a size are not counted.
+pub(crate) fn serialize_ephemeral_link(timestamp: u64, size: u64) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(std::mem::size_of::<u64>() * 2);
+    bytes.extend_from_slice(&timestamp.to_le_bytes());
+    if size > 0 {
+        bytes.extend_from_slice(&size.to_le_bytes());
+    }
+    bytes
+}
+
+pub(crate) fn deserialize_ephemeral_link(bytes: &[u8]) -> Option<(u64, u64)> {
+    let timestamp = u64::from_le_bytes(bytes.get(..8)?.try_into().ok()?);
+    let size = match bytes.get(8..) {
+        Some(size) if !size.is_empty() => u64::from_le_bytes(size.try_into().ok()?),
+        _ => 0,
+    };
+    Some((timestamp, size))
+}
+
 pub trait BlobStore: Sized {
     fn new(settings: &EnvSettings) -> crate::Result<Self>;
     fn get_range(&self, blob_id: &BlobId, range: Range<u32>) -> crate::Result<Option<Vec<u8>>>;
@@ -124,5 +156,86 @@ pub trait BlobStore: Sized {
         self.get_range(blob_id, 0..u32::MAX)
     }
     fn put(&self, blob_id: &BlobId, blob: &[u8]) -> crate::Result<bool>;
+    fn put_stream(&self, blob_id: &BlobId, reader: &mut dyn Read, size: u64)
+        -> crate::Result<bool>;
+    fn replace(&self, blob_id: &BlobId, blob: &[u8]) -> crate::Result<()>;
     fn delete(&self, blob_id: &BlobId) -> crate::Result<bool>;
+    fn size(&self, blob_id: &BlobId) -> crate::Result<Option<u64>>;
+}
+
+pub enum BlobBackend {
+    Local(LocalBlobStore),
+    S3(S3BlobStore),
+}
+
+pub struct BlobStorage {
+    pub lock: MutexMap<()>,
+    pub backend: BlobBackend,
+    pub compression: BlobCompression,
+}
+
+impl BlobStore for BlobStorage {
+    fn new(settings: &EnvSettings) -> crate::Result<Self> {
+        Ok(BlobStorage {
+            lock: MutexMap::with_capacity(1024),
+            backend: match settings.get("blob-store").as_deref() {
+                Some("local") | None => BlobBackend::Local(LocalBlobStore::new(settings)?),
+                Some("s3") => BlobBackend::S3(S3BlobStore::new(settings)?),
+                Some(other) => {
+                    return Err(StoreError::InvalidArguments(format!(
+                        "Unknown blob store type '{}'.",
+                        other
+                    )))
+                }
+            },
+            compression: BlobCompression::new(settings)?,
+        })
+    }
+
+    fn get_range(&self, blob_id: &BlobId, range: Range<u32>) -> crate::Result<Option<Vec<u8>>> {
+        match &self.backend {
+            BlobBackend::Local(store) => store.get_range(blob_id, range),
+            BlobBackend::S3(store) => store.get_range(blob_id, range),
+        }
+    }
+
+    fn put(&self, blob_id: &BlobId, blob: &[u8]) -> crate::Result<bool> {
+        match &self.backend {
+            BlobBackend::Local(store) => store.put(blob_id, blob),
+            BlobBackend::S3(store) => store.put(blob_id, blob),
+        }
+    }
+
+    fn put_stream(
+        &self,
+        blob_id: &BlobId,
+        reader: &mut dyn Read,
+        size: u64,
+    ) -> crate::Result<bool> {
+        match &self.backend {
+            BlobBackend::Local(store) => store.put_stream(blob_id, reader, size),
+            BlobBackend::S3(store) => store.put_stream(blob_id, reader, size),
+        }
+    }
+
+    fn replace(&self, blob_id: &BlobId, blob: &[u8]) -> crate::Result<()> {
+        match &self.backend {
+            BlobBackend::Local(store) => store.replace(blob_id, blob),
+            BlobBackend::S3(store) => store.replace(blob_id, blob),
+        }
+    }
+
+    fn delete(&self, blob_id: &BlobId) -> crate::Result<bool> {
+        match &self.backend {
+            BlobBackend::Local(store) => store.delete(blob_id),
+            BlobBackend::S3(store) => store.delete(blob_id),
+        }
+    }
+
+    fn size(&self, blob_id: &BlobId) -> crate::Result<Option<u64>> {
+        match &self.backend {
+            BlobBackend::Local(store) => store.size(blob_id),
+            BlobBackend::S3(store) => store.size(blob_id),
+        }
+    }
 }
@@ -25,12 +25,13 @@ use std::time::SystemTime;
 
 use tracing::error;
 
+use crate::serialize::key::ValueKey;
 use crate::serialize::leb128::Leb128Reader;
-use crate::serialize::StoreDeserialize;
+use crate::serialize::{StoreDeserialize, StoreSerialize};
 use crate::WriteOperation;
-use crate::{ColumnFamily, Direction, JMAPStore, Store, StoreError};
+use crate::{AccountId, ColumnFamily, Direction, JMAPStore, Store, StoreError};
 
-use super::{BlobId, BlobStore, BLOB_EXTERNAL, BLOB_HASH_LEN};
+use super::{deserialize_ephemeral_link, BlobId, BlobStore, BLOB_EXTERNAL, BLOB_HASH_LEN};
 
 impl<T> JMAPStore<T>
 where
@@ -65,14 +66,17 @@ where
 
             // Blob link
             if key.len() > BLOB_HASH_LEN + 1 {
-                if let Some(bytes_read) = (&key[BLOB_HASH_LEN + 1..]).skip_leb128() {
+                if let Some((account_id, bytes_read)) =
+                    (&key[BLOB_HASH_LEN + 1..]).read_leb128::<AccountId>()
+                {
                     if key.len() == BLOB_HASH_LEN + 1 + bytes_read {
-                        let timestamp = u64::deserialize(&value).ok_or_else(|| {
-                            StoreError::InternalError(format!(
-                                "Failed to deserialize timestamp from key {:?}",
-                                key
-                            ))
-                        })?;
+                        let (timestamp, size) =
+                            deserialize_ephemeral_link(&value).ok_or_else(|| {
+                                StoreError::InternalError(format!(
+                                    "Failed to deserialize timestamp from key {:?}",
+                                    key
+                                ))
+                            })?;
 
                         if (now >= timestamp && now - timestamp > self.config.blob_temp_ttl)
                             || (now < timestamp && timestamp - now > self.config.blob_temp_ttl)
@@ -82,6 +86,15 @@ where
                                 cf: ColumnFamily::Blobs,
                                 key: key.to_vec(),
                             });
+
+                            // Release the quota held by the upload
+                            if size > 0 {
+                                batch.push(WriteOperation::merge(
+                                    ColumnFamily::Values,
+                                    ValueKey::serialize_pending_quota(account_id),
+                                    (-(size as i64)).serialize().unwrap(),
+                                ));
+                            }
                         } else {
                             blob_link_count += 1;
                         }
@@ -0,0 +1,248 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{io::Read, ops::Range};
+
+use s3::{bucket::Bucket, creds::Credentials, region::Region, serde_types::Part};
+
+use crate::{config::env_settings::EnvSettings, core::error::StoreError};
+
+use super::{BlobId, BlobStore};
+
+// S3 rejects multipart uploads with parts (other than the last one) below 5 MiB.
+const MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+const CONTENT_TYPE: &str = "application/octet-stream";
+
+pub struct S3BlobStore {
+    pub bucket: Bucket,
+    pub prefix: String,
+    pub part_size: usize,
+}
+
+impl BlobStore for S3BlobStore {
+    fn new(settings: &EnvSettings) -> crate::Result<Self> {
+        let region = settings
+            .get("blob-s3-region")
+            .unwrap_or_else(|| "us-east-1".to_string());
+        let has_endpoint = settings.contains_key("blob-s3-endpoint");
+        let region = if let Some(endpoint) = settings.get("blob-s3-endpoint") {
+            Region::Custom { region, endpoint }
+        } else {
+            region.parse().map_err(|err| {
+                StoreError::InvalidArguments(format!("Invalid S3 region: {}", err))
+            })?
+        };
+        let credentials = Credentials::new(
+            settings.get("blob-s3-access-key").as_deref(),
+            settings.get("blob-s3-secret-key").as_deref(),
+            settings.get("blob-s3-security-token").as_deref(),
+            None,
+            settings.get("blob-s3-profile").as_deref(),
+        )
+        .map_err(|err| StoreError::InvalidArguments(format!("Invalid S3 credentials: {}", err)))?;
+        let bucket_name = settings.get("blob-s3-bucket").ok_or_else(|| {
+            StoreError::InvalidArguments("Missing 'blob-s3-bucket' setting.".to_string())
+        })?;
+        let mut bucket = Bucket::new(&bucket_name, region, credentials)
+            .map_err(|err| StoreError::InvalidArguments(format!("Invalid S3 bucket: {}", err)))?;
+        // Self-hosted S3-compatible services usually require path-style URLs
+        if settings.parse("blob-s3-path-style").unwrap_or(has_endpoint) {
+            bucket = bucket.with_path_style();
+        }
+
+        Ok(S3BlobStore {
+            bucket,
+            prefix: settings.get("blob-s3-prefix").unwrap_or_default(),
+            part_size: std::cmp::max(
+                settings
+                    .parse("blob-s3-part-size")
+                    .unwrap_or(8 * 1024 * 1024),
+                MIN_PART_SIZE,
+            ),
+        })
+    }
+
+    fn put(&self, blob_id: &BlobId, blob: &[u8]) -> crate::Result<bool> {
+        self.put_stream(blob_id, &mut &blob[..], blob.len() as u64)
+    }
+
+    fn put_stream(
+        &self,
+        blob_id: &BlobId,
+        reader: &mut dyn Read,
+        size: u64,
+    ) -> crate::Result<bool> {
+        let path = self.get_path(blob_id);
+
+        if size <= self.part_size as u64 {
+            let mut blob = Vec::with_capacity(size as usize);
+            reader.read_to_end(&mut blob)?;
+            let response = self
+                .bucket
+                .put_object(&path, &blob)
+                .map_err(|err| s3_error("put", blob_id, err))?;
+            check_status("put", blob_id, response.status_code())?;
+        } else {
+            // Upload large messages in parts, only one part is kept in memory
+            let upload_id = self
+                .bucket
+                .initiate_multipart_upload(&path, CONTENT_TYPE)
+                .map_err(|err| s3_error("initiate upload of", blob_id, err))?
+                .upload_id;
+            let mut parts: Vec<Part> =
+                Vec::with_capacity((size / self.part_size as u64) as usize + 1);
+
+            for part_number in 1.. {
+                let mut chunk = Vec::with_capacity(self.part_size);
+                if let Err(err) = (&mut *reader)
+                    .take(self.part_size as u64)
+                    .read_to_end(&mut chunk)
+                {
+                    self.bucket.abort_upload(&path, &upload_id).ok();
+                    return Err(err.into());
+                } else if chunk.is_empty() {
+                    break;
+                }
+                match self.bucket.put_multipart_chunk(
+                    chunk,
+                    &path,
+                    part_number,
+                    &upload_id,
+                    CONTENT_TYPE,
+                ) {
+                    Ok(part) => parts.push(part),
+                    Err(err) => {
+                        self.bucket.abort_upload(&path, &upload_id).ok();
+                        return Err(s3_error("upload part of", blob_id, err));
+                    }
+                }
+            }
+
+            // Abort failed uploads, otherwise their parts are kept and billed
+            if let Err(err) = self
+                .bucket
+                .complete_multipart_upload(&path, &upload_id, parts)
+                .map_err(|err| s3_error("complete upload of", blob_id, err))
+                .and_then(|response| {
+                    check_status("complete upload of", blob_id, response.status_code())
+                })
+            {
+                self.bucket.abort_upload(&path, &upload_id).ok();
+                return Err(err);
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn replace(&self, blob_id: &BlobId, blob: &[u8]) -> crate::Result<()> {
+        // Objects are overwritten atomically
+        self.put(blob_id, blob).map(|_| ())
+    }
+
+    fn get_range(&self, blob_id: &BlobId, range: Range<u32>) -> crate::Result<Option<Vec<u8>>> {
+        let path = self.get_path(blob_id);
+        if range.start >= range.end {
+            return Ok(Some(Vec::new()));
+        }
+        let response = if range.start != 0 || range.end != u32::MAX {
+            // Ranges spanning a single byte are widened, as the S3 client
+            // requires the last byte to be greater than the first one.
+            self.bucket.get_object_range(
+                &path,
+                range.start as u64,
+                if range.end != u32::MAX {
+                    Some(std::cmp::max(range.end - 1, range.start + 1) as u64)
+                } else {
+                    None
+                },
+            )
+        } else {
+            self.bucket.get_object(&path)
+        }
+        .map_err(|err| s3_error("get", blob_id, err))?;
+
+        match response.status_code() {
+            200..=299 => {
+                let mut bytes = response.bytes().to_vec();
+                bytes.truncate((range.end - range.start) as usize);
+                Ok(Some(bytes))
+            }
+            404 => Ok(None),
+            // The requested range starts past the end of the blob
+            416 => Ok(Some(Vec::new())),
+            code => Err(status_error("get", blob_id, code)),
+        }
+    }
+
+    fn delete(&self, blob_id: &BlobId) -> crate::Result<bool> {
+        let response = self
+            .bucket
+            .delete_object(&self.get_path(blob_id))
+            .map_err(|err| s3_error("delete", blob_id, err))?;
+
+        match response.status_code() {
+            200..=299 => Ok(true),
+            404 => Ok(false),
+            code => Err(status_error("delete", blob_id, code)),
+        }
+    }
+
+    fn size(&self, blob_id: &BlobId) -> crate::Result<Option<u64>> {
+        let (result, code) = self
+            .bucket
+            .head_object(&self.get_path(blob_id))
+            .map_err(|err| s3_error("obtain size of", blob_id, err))?;
+
+        match code {
+            200..=299 => Ok(Some(result.content_length.unwrap_or(0) as u64)),
+            404 => Ok(None),
+            code => Err(status_error("obtain size of", blob_id, code)),
+        }
+    }
+}
+
+impl S3BlobStore {
+    fn get_path(&self, blob_id: &BlobId) -> String {
+        format!("{}{}", self.prefix, blob_id)
+    }
+}
+
+fn check_status(action: &str, blob_id: &BlobId, code: u16) -> crate::Result<()> {
+    if (200..=299).contains(&code) {
+        Ok(())
+    } else {
+        Err(status_error(action, blob_id, code))
+    }
+}
+
+fn status_error(action: &str, blob_id: &BlobId, code: u16) -> StoreError {
+    StoreError::InternalError(format!(
+        "Failed to {} blob {}: S3 returned status {}.",
+        action, blob_id, code
+    ))
+}
+
+fn s3_error(action: &str, blob_id: &BlobId, err: impl std::fmt::Display) -> StoreError {
+    StoreError::InternalError(format!("Failed to {} blob {}: {}", action, blob_id, err))
+}
@@ -21,20 +21,33 @@
  * for more details.
 */
 
-use std::{ops::Range, time::SystemTime};
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter, Cursor, Read, Write},
+    ops::Range,
+    path::Path,
+    time::SystemTime,
+};
 
 use roaring::RoaringBitmap;
+use sha2::{Digest, Sha256};
 use tracing::error;
 
 use crate::serialize::leb128::Leb128Reader;
 use crate::write::operation::WriteOperation;
 use crate::{
-    core::collection::Collection,
-    serialize::{key::BlobKey, StoreSerialize},
+    core::{collection::Collection, error::StoreError},
+    serialize::{
+        key::{BlobKey, ValueKey},
+        StoreSerialize,
+    },
     AccountId, ColumnFamily, Direction, DocumentId, JMAPStore, Store,
 };
 
-use super::{BlobId, BlobStore};
+use super::{
+    compress::{decompress, decompress_range, FrameHeader, HEADER_LEN},
+    deserialize_ephemeral_link, serialize_ephemeral_link, BlobId, BlobStore,
+};
 
 impl<T> JMAPStore<T>
 where
@@ -51,6 +64,14 @@ where
             return Ok(());
         }
 
+        // Compress and encrypt blob
+        let bytes = self.blob_store.compression.compress(bytes)?;
+        let bytes = if let Some(encryption) = &self.encryption {
+            encryption.encrypt_blob(blob_id, &bytes)?
+        } else {
+            bytes
+        };
+
         // Write blob
         let value = if blob_id.is_external() {
             self.blob_store.put(blob_id, &bytes)?;
@@ -59,7 +80,62 @@ where
             bytes
         };
 
-        // Write blob or blob reference to database
+        self.blob_commit(blob_id, key, value)
+    }
+
+    /// Stores an external blob from a file, compressing it one chunk at a time.
+    /// Chunks are encrypted once compressed, so the file is read in full when
+    /// encryption is enabled.
+    pub fn blob_store_file(&self, blob_id: &BlobId, path: &Path) -> crate::Result<()> {
+        if self.encryption.is_some() || blob_id.is_local() {
+            return self.blob_store(blob_id, fs::read(path)?);
+        }
+
+        let key = BlobKey::serialize(blob_id);
+
+        // Lock blob hash
+        let _lock = self.blob_store.lock.lock_hash(blob_id);
+
+        // Blob already exists, return.
+        if self.db.exists(ColumnFamily::Blobs, &key)? {
+            return Ok(());
+        }
+
+        // Compressed chunks are written to a temporary file, as the frame
+        // header and chunk index have to be stored first.
+        let data_path = path.with_extension("chunks");
+        let result = (|| -> crate::Result<bool> {
+            let mut source = BufReader::new(File::open(path)?);
+            let size = source.get_ref().metadata()?.len();
+            let mut data = BufWriter::new(File::create(&data_path)?);
+            let frame =
+                self.blob_store
+                    .compression
+                    .compress_stream(&mut source, size, &mut data)?;
+            data.flush()?;
+            drop(data);
+
+            let data_size = fs::metadata(&data_path)?.len();
+            self.blob_store.put_stream(
+                blob_id,
+                &mut Cursor::new(&frame).chain(BufReader::new(File::open(&data_path)?)),
+                frame.len() as u64 + data_size,
+            )
+        })();
+        if let Err(err) = fs::remove_file(&data_path) {
+            error!(
+                "Failed to delete temporary file {}: {}",
+                data_path.display(),
+                err
+            );
+        }
+        result?;
+
+        self.blob_commit(blob_id, key, Vec::new())
+    }
+
+    // Writes the blob or blob reference to the database
+    fn blob_commit(&self, blob_id: &BlobId, key: Vec<u8>, value: Vec<u8>) -> crate::Result<()> {
         let mut batch = Vec::with_capacity(2);
         batch.push(WriteOperation::Set {
             cf: ColumnFamily::Blobs,
@@ -110,35 +186,185 @@ where
         self.db.set(
             ColumnFamily::Blobs,
             &BlobKey::serialize_prefix(blob_id, account_id),
-            &timestamp.serialize().unwrap(),
+            &serialize_ephemeral_link(timestamp, 0),
         )
     }
 
+    pub fn blob_link_upload(
+        &self,
+        blob_id: &BlobId,
+        account_id: AccountId,
+        size: u64,
+    ) -> crate::Result<()> {
+        // Obtain seconds from Unix epoch
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        // Uploading the same blob twice only counts it once
+        let key = BlobKey::serialize_prefix(blob_id, account_id);
+        let _lock = self.blob_store.lock.lock_hash(blob_id);
+        let prev_size = self
+            .db
+            .get::<Vec<u8>>(ColumnFamily::Blobs, &key)?
+            .and_then(|bytes| deserialize_ephemeral_link(&bytes))
+            .map_or(0, |(_, size)| size);
+
+        let mut batch = vec![WriteOperation::set(
+            ColumnFamily::Blobs,
+            key,
+            serialize_ephemeral_link(timestamp, size),
+        )];
+        if size != prev_size {
+            batch.push(WriteOperation::merge(
+                ColumnFamily::Values,
+                ValueKey::serialize_pending_quota(account_id),
+                (size as i64 - prev_size as i64).serialize().unwrap(),
+            ));
+        }
+        self.db.write(batch)
+    }
+
     pub fn blob_get(&self, blob_id: &BlobId) -> crate::Result<Option<Vec<u8>>> {
-        if !blob_id.is_local() {
-            self.blob_store.get(blob_id)
+        let bytes = if !blob_id.is_local() {
+            self.blob_store.get(blob_id)?
         } else {
             self.db
-                .get(ColumnFamily::Blobs, &BlobKey::serialize(blob_id))
+                .get(ColumnFamily::Blobs, &BlobKey::serialize(blob_id))?
+        };
+
+        if let Some(bytes) = bytes {
+            if FrameHeader::parse(&bytes).map_or(false, |header| header.encrypted) {
+                let result = if let Some(encryption) = &self.encryption {
+                    encryption.decrypt_blob(blob_id, &bytes)
+                } else {
+                    Err(StoreError::InvalidArguments(
+                        "Found an encrypted blob but no encryption master key was configured."
+                            .to_string(),
+                    ))
+                };
+                match result {
+                    Ok(frame) => decompress(frame).map(Some),
+                    // Blobs stored before encryption was supported can start
+                    // with an encrypted frame header, their hash tells them apart.
+                    Err(_) if blob_id.hash() == &Sha256::digest(&bytes)[..] => Ok(Some(bytes)),
+                    Err(err) => Err(err),
+                }
+            } else {
+                decompress(bytes).map(Some)
+            }
+        } else {
+            Ok(None)
         }
     }
 
+    fn blob_get_slice(
+        &self,
+        blob_id: &BlobId,
+        range: Range<u32>,
+    ) -> crate::Result<Option<Vec<u8>>> {
+        Ok(self.blob_get(blob_id)?.map(|bytes| {
+            bytes
+                .get(range.start as usize..std::cmp::min(range.end as usize, bytes.len()))
+                .unwrap_or_default()
+                .to_vec()
+        }))
+    }
+
     pub fn blob_get_range(
         &self,
         blob_id: &BlobId,
         range: Range<u32>,
     ) -> crate::Result<Option<Vec<u8>>> {
         if !blob_id.is_local() {
-            self.blob_store.get_range(blob_id, range)
+            // Read the frame header to find out which chunks have to be fetched
+            let header =
+                if let Some(header) = self.blob_store.get_range(blob_id, 0..HEADER_LEN as u32)? {
+                    header
+                } else {
+                    return Ok(None);
+                };
+            if let Some(header) = FrameHeader::parse(&header) {
+                let encryption = match &self.encryption {
+                    Some(encryption) if header.encrypted => Some(encryption),
+                    None if header.encrypted => return self.blob_get_slice(blob_id, range),
+                    _ => None,
+                };
+                let index = self
+                    .blob_store
+                    .get_range(blob_id, header.index_range())?
+                    .and_then(|index| header.parse_index(&index))
+                    .ok_or_else(|| {
+                        StoreError::DataCorruption(format!(
+                            "Compressed blob {} is truncated.",
+                            blob_id
+                        ))
+                    })?;
+                let (chunks, chunks_range) = header.locate(&index, &range);
+                if chunks.is_empty() {
+                    return Ok(Some(Vec::new()));
+                }
+                let data = self
+                    .blob_store
+                    .get_range(blob_id, chunks_range)?
+                    .ok_or_else(|| {
+                        StoreError::DataCorruption(format!(
+                            "Compressed blob {} is truncated.",
+                            blob_id
+                        ))
+                    })?;
+                if let Some(encryption) = encryption {
+                    // Only the chunks spanning the range are decrypted
+                    match encryption.decrypt_blob_chunks(blob_id, &index, chunks.clone(), &data) {
+                        Ok((index, data)) => header
+                            .decompress_chunks(&index, chunks, &data, &range)
+                            .map(Some),
+                        // Blobs stored before encryption was supported can start
+                        // with an encrypted frame header.
+                        Err(_) => self.blob_get_slice(blob_id, range),
+                    }
+                } else {
+                    header
+                        .decompress_chunks(&index, chunks, &data, &range)
+                        .map(Some)
+                }
+            } else {
+                self.blob_store.get_range(blob_id, range)
+            }
         } else {
-            Ok(self
-                .db
-                .get::<Vec<u8>>(ColumnFamily::Blobs, &BlobKey::serialize(blob_id))?
-                .and_then(|bytes| {
-                    bytes
+            Ok(
+                match self
+                    .db
+                    .get::<Vec<u8>>(ColumnFamily::Blobs, &BlobKey::serialize(blob_id))?
+                {
+                    Some(bytes) if FrameHeader::parse(&bytes).map_or(false, |h| h.encrypted) => {
+                        return self.blob_get_slice(blob_id, range);
+                    }
+                    Some(bytes) if FrameHeader::parse(&bytes).is_some() => {
+                        Some(decompress_range(&bytes, range)?)
+                    }
+                    Some(bytes) => bytes
                         .get(range.start as usize..range.end as usize)
-                        .map(|bytes| bytes.to_vec())
-                }))
+                        .map(|bytes| bytes.to_vec()),
+                    None => None,
+                },
+            )
+        }
+    }
+
+    /// Returns the uncompressed size of a blob without reading it, when possible.
+    pub fn blob_size(&self, blob_id: &BlobId) -> crate::Result<Option<u32>> {
+        if blob_id.is_local() {
+            Ok(self.blob_get(blob_id)?.map(|bytes| bytes.len() as u32))
+        } else if let Some(header) = self.blob_store.get_range(blob_id, 0..HEADER_LEN as u32)? {
+            if let Some(header) = FrameHeader::parse(&header) {
+                Ok(Some(header.blob_size))
+            } else {
+                Ok(self.blob_store.size(blob_id)?.map(|size| size as u32))
+            }
+        } else {
+            Ok(None)
         }
     }
 
@@ -222,4 +448,31 @@ where
 
         Ok(None)
     }
+
+    pub fn blob_linked_documents(
+        &self,
+        blob_id: &BlobId,
+        account_id: AccountId,
+        collection: Collection,
+    ) -> crate::Result<RoaringBitmap> {
+        let prefix = BlobKey::serialize_collection(blob_id, account_id, collection);
+        let mut documents = RoaringBitmap::new();
+
+        for (key, _) in self
+            .db
+            .iterator(ColumnFamily::Blobs, &prefix, Direction::Forward)?
+        {
+            if key.starts_with(&prefix) && key.len() > prefix.len() {
+                if let Some((document_id, _)) = (&key[prefix.len()..]).read_leb128() {
+                    documents.insert(document_id);
+                } else {
+                    break;
+                }
+            } else {
+                break;
+            }
+        }
+
+        Ok(documents)
+    }
 }
@@ -21,21 +21,25 @@
  * for more details.
 */
 
+use std::path::PathBuf;
+
 use crate::nlp::Language;
 
-use super::env_settings::EnvSettings;
+use super::{env_settings::EnvSettings, ldap::LdapConfig};
 
 pub struct JMAPConfig {
     pub blob_temp_ttl: u64,
     pub default_language: Language,
 
     pub max_size_upload: usize,
+    pub upload_temp_path: PathBuf,
     pub max_concurrent_uploads: usize,
     pub max_size_request: usize,
     pub max_concurrent_requests: usize,
     pub max_calls_in_request: usize,
     pub max_objects_in_get: usize,
     pub max_objects_in_set: usize,
+    pub blob_max_data_sources: usize,
 
     pub rate_limit_authenticated: (u64, u64),
     pub rate_limit_anonymous: (u64, u64),
@@ -51,6 +55,14 @@ pub struct JMAPConfig {
     pub mail_attachments_max_size: usize,
     pub mail_import_max_items: usize,
     pub mail_parse_max_items: usize,
+    pub sieve_max_script_name_len: usize,
+    pub sieve_max_script_size: usize,
+    pub sieve_max_scripts: usize,
+    pub sieve_max_redirects: usize,
+    pub spam_headers: Vec<(String, String)>,
+    pub spam_bayes: bool,
+    pub spam_bayes_min_learns: i64,
+    pub spam_bayes_threshold: f64,
 
     pub push_max_total: usize,
     pub ws_heartbeat_interval: u64,
@@ -59,18 +71,33 @@ pub struct JMAPConfig {
     pub event_source_throttle: u64,
 
     pub raft_commit_timeout: u64,
+
+    pub ldap: Option<LdapConfig>,
 }
 
 impl From<&EnvSettings> for JMAPConfig {
     fn from(settings: &EnvSettings) -> Self {
         JMAPConfig {
             max_size_upload: settings.parse("max-size-upload").unwrap_or(50000000),
+            upload_temp_path: settings
+                .get("upload-temp-path")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| {
+                    let mut path = PathBuf::from(
+                        settings
+                            .get("db-path")
+                            .unwrap_or_else(|| "/usr/local/stalwart-jmap/data".to_string()),
+                    );
+                    path.push("tmp");
+                    path
+                }),
             max_concurrent_uploads: settings.parse("max-concurrent-uploads").unwrap_or(4),
             max_concurrent_requests: settings.parse("max-concurrent-requests").unwrap_or(4),
             max_size_request: settings.parse("max-size-request").unwrap_or(10000000),
             max_calls_in_request: settings.parse("max-calls-in-request").unwrap_or(16),
             max_objects_in_get: settings.parse("max-objects-in-get").unwrap_or(500),
             max_objects_in_set: settings.parse("max-objects-in-set").unwrap_or(500),
+            blob_max_data_sources: settings.parse("blob-max-data-sources").unwrap_or(64),
             blob_temp_ttl: settings.parse("blob-temp-ttl").unwrap_or(3600),
             changes_max_results: settings.parse("changes-max-results").unwrap_or(5000),
             query_max_results: settings.parse("query-max-results").unwrap_or(5000),
@@ -83,6 +110,32 @@ impl From<&EnvSettings> for JMAPConfig {
             mail_max_size: settings.parse("mail-max-size").unwrap_or(104857600),
             mail_import_max_items: settings.parse("mail-import-max-items").unwrap_or(5),
             mail_parse_max_items: settings.parse("mail-parse-max-items").unwrap_or(5),
+            sieve_max_script_name_len: settings.parse("sieve-max-script-name-len").unwrap_or(512),
+            sieve_max_script_size: settings.parse("sieve-max-script-size").unwrap_or(102400),
+            sieve_max_scripts: settings.parse("sieve-max-scripts").unwrap_or(100),
+            sieve_max_redirects: settings.parse("sieve-max-redirects").unwrap_or(1),
+            spam_headers: settings
+                .parse_list("spam-headers")
+                .unwrap_or_else(|| {
+                    vec![
+                        "X-Spam-Flag: YES".to_string(),
+                        "X-Spam-Status: Yes".to_string(),
+                    ]
+                })
+                .into_iter()
+                .filter_map(|rule| {
+                    let (name, value) = rule.split_once(':').unwrap_or((&rule, ""));
+                    let name = name.trim();
+                    if !name.is_empty() {
+                        Some((name.to_lowercase(), value.trim().to_lowercase()))
+                    } else {
+                        None
+                    }
+                })
+                .collect(),
+            spam_bayes: settings.parse("spam-bayes").unwrap_or(true),
+            spam_bayes_min_learns: settings.parse("spam-bayes-min-learns").unwrap_or(20),
+            spam_bayes_threshold: settings.parse("spam-bayes-threshold").unwrap_or(0.9),
             push_max_total: settings.parse("push-max-total").unwrap_or(100),
             ws_client_timeout: settings.parse("ws-client-timeout").unwrap_or(10 * 1000),
             ws_heartbeat_interval: settings.parse("ws-heartbeat-interval").unwrap_or(5 * 1000),
@@ -126,6 +179,7 @@ impl From<&EnvSettings> for JMAPConfig {
                 })
                 .unwrap_or((100, 60)),
             use_forwarded_header: settings.parse("use-forwarded-header").unwrap_or(false),
+            ldap: LdapConfig::new(settings),
         }
     }
 }
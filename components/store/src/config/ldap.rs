@@ -0,0 +1,88 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use super::env_settings::EnvSettings;
+
+pub struct LdapConfig {
+    pub url: String,
+    pub base_dn: String,
+    pub bind_dn: Option<String>,
+    pub bind_secret: Option<String>,
+    pub bind_template: Option<String>,
+    pub timeout: u64,
+    pub sync_on_login: bool,
+    pub pool_size: usize,
+
+    pub filter_user: String,
+    pub filter_group: String,
+    pub filter_all_users: String,
+    pub filter_all_groups: String,
+
+    pub attr_email: String,
+    pub attr_name: String,
+    pub attr_aliases: String,
+    pub attr_members: String,
+    pub attr_dn: String,
+}
+
+impl LdapConfig {
+    pub fn new(settings: &EnvSettings) -> Option<Self> {
+        Some(LdapConfig {
+            url: settings.get("ldap-url")?,
+            base_dn: settings.get("ldap-base-dn").unwrap_or_default(),
+            bind_dn: settings.get("ldap-bind-dn"),
+            bind_secret: settings.get("ldap-bind-secret"),
+            bind_template: settings.get("ldap-bind-template"),
+            timeout: settings.parse("ldap-timeout").unwrap_or(10),
+            sync_on_login: settings.parse("ldap-sync-on-login").unwrap_or(true),
+            pool_size: settings.parse("ldap-pool-size").unwrap_or(10),
+            filter_user: settings
+                .get("ldap-filter-user")
+                .unwrap_or_else(|| "(&(objectClass=inetOrgPerson)(mail=?))".to_string()),
+            filter_group: settings
+                .get("ldap-filter-group")
+                .unwrap_or_else(|| "(&(objectClass=groupOfNames)(mail=?))".to_string()),
+            filter_all_users: settings
+                .get("ldap-filter-all-users")
+                .unwrap_or_else(|| "(objectClass=inetOrgPerson)".to_string()),
+            filter_all_groups: settings
+                .get("ldap-filter-all-groups")
+                .unwrap_or_else(|| "(objectClass=groupOfNames)".to_string()),
+            attr_email: settings
+                .get("ldap-attr-email")
+                .unwrap_or_else(|| "mail".to_string()),
+            attr_name: settings
+                .get("ldap-attr-name")
+                .unwrap_or_else(|| "cn".to_string()),
+            attr_aliases: settings
+                .get("ldap-attr-aliases")
+                .unwrap_or_else(|| "mailAlternateAddress".to_string()),
+            attr_members: settings
+                .get("ldap-attr-members")
+                .unwrap_or_else(|| "member".to_string()),
+            attr_dn: settings
+                .get("ldap-attr-dn")
+                .unwrap_or_else(|| "entryDN".to_string()),
+        })
+    }
+}
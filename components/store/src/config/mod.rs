@@ -23,3 +23,4 @@
 
 pub mod env_settings;
 pub mod jmap;
+pub mod ldap;
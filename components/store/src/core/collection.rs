@@ -34,7 +34,13 @@ pub enum Collection {
     Identity = 5,
     EmailSubmission = 6,
     VacationResponse = 7,
-    None = 8,
+    Quota = 8,
+    SieveScript = 9,
+    AddressBook = 10,
+    ContactCard = 11,
+    Calendar = 12,
+    CalendarEvent = 13,
+    None = 14,
 }
 
 impl Default for Collection {
@@ -54,6 +60,12 @@ impl From<u8> for Collection {
             5 => Collection::Identity,
             6 => Collection::EmailSubmission,
             7 => Collection::VacationResponse,
+            8 => Collection::Quota,
+            9 => Collection::SieveScript,
+            10 => Collection::AddressBook,
+            11 => Collection::ContactCard,
+            12 => Collection::Calendar,
+            13 => Collection::CalendarEvent,
             _ => {
                 debug_assert!(false, "Invalid collection value: {}", value);
                 Collection::None
@@ -85,6 +97,12 @@ impl From<u64> for Collection {
             5 => Collection::Identity,
             6 => Collection::EmailSubmission,
             7 => Collection::VacationResponse,
+            8 => Collection::Quota,
+            9 => Collection::SieveScript,
+            10 => Collection::AddressBook,
+            11 => Collection::ContactCard,
+            12 => Collection::Calendar,
+            13 => Collection::CalendarEvent,
             _ => {
                 debug_assert!(false, "Invalid collection value: {}", value);
                 Collection::None
@@ -21,7 +21,12 @@
  * for more details.
 */
 
-use crate::{blob::BlobId, nlp::Language, write::field::Field, DocumentId, FieldId};
+use crate::{
+    blob::BlobId,
+    nlp::Language,
+    write::{field::Field, options::Options},
+    DocumentId, FieldId,
+};
 
 use super::{acl::Permission, collection::Collection, number::Number, tag::Tag};
 
@@ -47,6 +52,8 @@ pub struct Document {
     pub tag_fields: Vec<Field<Tag>>,
     pub acls: Vec<(Permission, u64)>,
     pub blobs: Vec<(BlobId, u64)>,
+    pub quota: i64,
+    pub bayes: Vec<(Vec<String>, bool, i64)>,
 }
 
 impl Document {
@@ -61,6 +68,8 @@ impl Document {
             blobs: Vec::new(),
             acls: Vec::new(),
             term_index: None,
+            quota: 0,
+            bayes: Vec::new(),
         }
     }
 
@@ -108,6 +117,19 @@ impl Document {
         self.term_index = Some((blob, options));
     }
 
+    pub fn quota(&mut self, size: u64, options: u64) {
+        if !options.is_clear() {
+            self.quota += size as i64;
+        } else {
+            self.quota -= size as i64;
+        }
+    }
+
+    pub fn bayes(&mut self, tokens: Vec<String>, is_spam: bool, options: u64) {
+        self.bayes
+            .push((tokens, is_spam, if !options.is_clear() { 1 } else { -1 }));
+    }
+
     pub fn is_empty(&self) -> bool {
         self.text_fields.is_empty()
             && self.number_fields.is_empty()
@@ -0,0 +1,661 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+pub mod store;
+
+use std::{ops::Range, sync::Arc};
+
+use aes_gcm_siv::{
+    aead::{generic_array::GenericArray, Aead},
+    AeadInPlace, Aes256GcmSiv, KeyInit, Nonce,
+};
+use moka::sync::Cache;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    blob::{
+        compress::{FrameHeader, CHUNK_RAW, COMPRESSED_MAGIC, FRAME_ENCRYPTED, HEADER_LEN},
+        BlobId, BlobStorage, BlobStore, BLOB_HASH_LEN,
+    },
+    config::env_settings::EnvSettings,
+    core::error::StoreError,
+    serialize::{
+        key::{BAYES_KEY, ENCRYPTED_VALUE_KEY, GRANT_KEY, PENDING_QUOTA_KEY, QUOTA_KEY, UIDS_KEY},
+        leb128::Leb128Reader,
+        StoreDeserialize,
+    },
+    write::operation::WriteOperation,
+    AccountId, ColumnFamily, Direction, Store,
+};
+
+pub const DATA_KEY_LEN: usize = 32;
+pub const MIN_MASTER_KEY_LEN: usize = 32;
+
+const DATA_KEY_CONTEXT: &str = "account data key context";
+const BLOB_DATA_KEY_CONTEXT: &str = "blob data key context";
+const VALUE_KEY_CONTEXT: &str = "value encryption context key";
+const BLOB_KEY_CONTEXT: &str = "blob encryption context key";
+
+const ROTATE_BATCH_SIZE: usize = 500;
+
+pub struct SymmetricEncrypt {
+    aes: Aes256GcmSiv,
+}
+
+impl SymmetricEncrypt {
+    pub const ENCRYPT_TAG_LEN: usize = 16;
+    pub const NONCE_LEN: usize = 12;
+
+    pub fn new(key: &[u8], context: &str) -> Self {
+        SymmetricEncrypt {
+            aes: Aes256GcmSiv::new(&GenericArray::clone_from_slice(
+                &blake3::derive_key(context, key)[..],
+            )),
+        }
+    }
+
+    #[allow(clippy::ptr_arg)]
+    pub fn encrypt_in_place(&self, bytes: &mut Vec<u8>, nonce: &[u8]) -> Result<(), String> {
+        self.aes
+            .encrypt_in_place(Nonce::from_slice(nonce), b"", bytes)
+            .map_err(|e| e.to_string())
+    }
+
+    pub fn encrypt(&self, bytes: &[u8], nonce: &[u8]) -> Result<Vec<u8>, String> {
+        self.aes
+            .encrypt(Nonce::from_slice(nonce), bytes)
+            .map_err(|e| e.to_string())
+    }
+
+    pub fn decrypt(&self, bytes: &[u8], nonce: &[u8]) -> Result<Vec<u8>, String> {
+        self.aes
+            .decrypt(Nonce::from_slice(nonce), bytes)
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Encryption keys. The blob key and the per-account data keys are derived
+/// from the master key rather than generated, which keeps them identical on
+/// all nodes of a cluster.
+pub struct Encryption {
+    pub blob_key: [u8; DATA_KEY_LEN],
+    account_key: [u8; DATA_KEY_LEN],
+    pub data_keys: Cache<AccountId, Arc<SymmetricEncrypt>>,
+}
+
+impl Encryption {
+    pub fn new(settings: &EnvSettings) -> crate::Result<Option<Self>> {
+        Ok(
+            read_master_key(settings, "encryption-master-key")?.map(|master_key| {
+                Encryption::with_master_key(
+                    &master_key,
+                    settings.parse("cache-size-keys").unwrap_or(10_000),
+                )
+            }),
+        )
+    }
+
+    pub fn with_master_key(master_key: &[u8], cache_size: u64) -> Self {
+        Encryption {
+            blob_key: blake3::derive_key(BLOB_DATA_KEY_CONTEXT, master_key),
+            account_key: blake3::derive_key(DATA_KEY_CONTEXT, master_key),
+            data_keys: Cache::builder()
+                .initial_capacity(128)
+                .max_capacity(cache_size)
+                .build(),
+        }
+    }
+
+    /// Returns the cipher used to encrypt the values of an account.
+    pub fn value_cipher(&self, account_id: AccountId) -> Arc<SymmetricEncrypt> {
+        self.data_keys.get_with(account_id, || {
+            Arc::new(SymmetricEncrypt::new(
+                blake3::keyed_hash(&self.account_key, &account_id.to_be_bytes()).as_bytes(),
+                VALUE_KEY_CONTEXT,
+            ))
+        })
+    }
+
+    /// Encrypts a value using a random nonce.
+    pub fn encrypt_value(cipher: &SymmetricEncrypt, bytes: &[u8]) -> crate::Result<Vec<u8>> {
+        let mut nonce = [0u8; SymmetricEncrypt::NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+
+        let mut result = Vec::with_capacity(
+            SymmetricEncrypt::NONCE_LEN + bytes.len() + SymmetricEncrypt::ENCRYPT_TAG_LEN,
+        );
+        result.extend_from_slice(&nonce);
+        result.extend_from_slice(&cipher.encrypt(bytes, &nonce).map_err(|err| {
+            StoreError::InternalError(format!("Failed to encrypt value: {}", err))
+        })?);
+        Ok(result)
+    }
+
+    /// Decrypts a value previously encrypted with `encrypt_value`.
+    pub fn decrypt_value(cipher: &SymmetricEncrypt, bytes: &[u8]) -> crate::Result<Vec<u8>> {
+        if bytes.len() < SymmetricEncrypt::NONCE_LEN {
+            return Err(StoreError::DataCorruption(
+                "Encrypted value is truncated.".into(),
+            ));
+        }
+        let (nonce, bytes) = bytes.split_at(SymmetricEncrypt::NONCE_LEN);
+        cipher
+            .decrypt(bytes, nonce)
+            .map_err(|err| StoreError::DataCorruption(format!("Failed to decrypt value: {}", err)))
+    }
+
+    /// Encrypts a framed blob. The frame header and chunk index are kept in the
+    /// clear, with the header flagged as encrypted, and each chunk is encrypted
+    /// separately so that a range can be read without decrypting the whole blob.
+    /// The key is derived from the blob hash and the nonce from the chunk
+    /// position, which produces the same ciphertext for identical blobs and
+    /// keeps deduplication by BlobId working.
+    pub fn encrypt_blob(&self, blob_id: &BlobId, bytes: &[u8]) -> crate::Result<Vec<u8>> {
+        let header = match FrameHeader::parse(bytes) {
+            Some(header) if !header.encrypted => header,
+            _ => {
+                return Err(StoreError::InternalError(format!(
+                    "Blob {} has to be framed before being encrypted.",
+                    blob_id
+                )))
+            }
+        };
+        let index = parse_frame_index(blob_id, &header, bytes)?;
+
+        let cipher = self.blob_cipher(blob_id);
+        let mut result =
+            Vec::with_capacity(bytes.len() + index.len() * SymmetricEncrypt::ENCRYPT_TAG_LEN);
+        result.extend_from_slice(&bytes[..HEADER_LEN]);
+        result[COMPRESSED_MAGIC.len()] |= FRAME_ENCRYPTED;
+        let mut data = Vec::with_capacity(bytes.len());
+        let mut pos = header.data_offset() as usize;
+        for (chunk_id, length) in index.into_iter().enumerate() {
+            let chunk_len = (length & !CHUNK_RAW) as usize;
+            let chunk = bytes.get(pos..pos + chunk_len).ok_or_else(|| {
+                StoreError::InternalError(format!("Blob {} is truncated.", blob_id))
+            })?;
+            pos += chunk_len;
+
+            let chunk = cipher
+                .encrypt(chunk, &chunk_nonce(blob_id, chunk_id))
+                .map_err(|err| {
+                    StoreError::InternalError(format!(
+                        "Failed to encrypt blob {}: {}",
+                        blob_id, err
+                    ))
+                })?;
+            result.extend_from_slice(&(chunk.len() as u32 | (length & CHUNK_RAW)).to_le_bytes());
+            data.extend_from_slice(&chunk);
+        }
+        result.extend_from_slice(&data);
+        Ok(result)
+    }
+
+    /// Decrypts a blob flagged as encrypted in its frame header and returns
+    /// the plaintext frame.
+    pub fn decrypt_blob(&self, blob_id: &BlobId, bytes: &[u8]) -> crate::Result<Vec<u8>> {
+        let header = match FrameHeader::parse(bytes) {
+            Some(header) if header.encrypted => header,
+            _ => {
+                return Err(StoreError::DataCorruption(format!(
+                    "Blob {} is not encrypted.",
+                    blob_id
+                )))
+            }
+        };
+        let index = parse_frame_index(blob_id, &header, bytes)?;
+        let data_offset = header.data_offset() as usize;
+        if bytes.len()
+            != data_offset
+                + index
+                    .iter()
+                    .map(|length| (length & !CHUNK_RAW) as usize)
+                    .sum::<usize>()
+        {
+            return Err(StoreError::DataCorruption(format!(
+                "Encrypted blob {} has an invalid length.",
+                blob_id
+            )));
+        }
+
+        let (index, data) =
+            self.decrypt_blob_chunks(blob_id, &index, 0..index.len(), &bytes[data_offset..])?;
+        let mut result = Vec::with_capacity(data_offset + data.len());
+        result.extend_from_slice(&bytes[..HEADER_LEN]);
+        result[COMPRESSED_MAGIC.len()] &= !FRAME_ENCRYPTED;
+        for length in index {
+            result.extend_from_slice(&length.to_le_bytes());
+        }
+        result.extend_from_slice(&data);
+        Ok(result)
+    }
+
+    /// Decrypts the chunks returned by `FrameHeader::locate`, along with the
+    /// chunk index updated with their plaintext lengths.
+    pub fn decrypt_blob_chunks(
+        &self,
+        blob_id: &BlobId,
+        index: &[u32],
+        chunks: Range<usize>,
+        data: &[u8],
+    ) -> crate::Result<(Vec<u32>, Vec<u8>)> {
+        let cipher = self.blob_cipher(blob_id);
+        let mut plain_index = index.to_vec();
+        let mut plain_data = Vec::with_capacity(data.len());
+        let mut pos = 0;
+        for chunk_id in chunks {
+            let length = index[chunk_id];
+            let chunk_len = (length & !CHUNK_RAW) as usize;
+            let chunk = data.get(pos..pos + chunk_len).ok_or_else(|| {
+                StoreError::DataCorruption(format!("Encrypted blob {} is truncated.", blob_id))
+            })?;
+            pos += chunk_len;
+
+            let chunk = cipher
+                .decrypt(chunk, &chunk_nonce(blob_id, chunk_id))
+                .map_err(|err| {
+                    StoreError::DataCorruption(format!(
+                        "Failed to decrypt blob {}: {}",
+                        blob_id, err
+                    ))
+                })?;
+            plain_index[chunk_id] = chunk.len() as u32 | (length & CHUNK_RAW);
+            plain_data.extend_from_slice(&chunk);
+        }
+        Ok((plain_index, plain_data))
+    }
+
+    fn blob_cipher(&self, blob_id: &BlobId) -> SymmetricEncrypt {
+        let mut key = Vec::with_capacity(DATA_KEY_LEN + blob_id.hash().len());
+        key.extend_from_slice(&self.blob_key);
+        key.extend_from_slice(blob_id.hash());
+        SymmetricEncrypt::new(&key, BLOB_KEY_CONTEXT)
+    }
+
+    // Re-encrypts a value with this key, returns `None` if it was already
+    // encrypted with the new key.
+    fn rotate_value(
+        &self,
+        new_key: &Encryption,
+        account_id: AccountId,
+        bytes: &[u8],
+    ) -> crate::Result<Option<Vec<u8>>> {
+        match Encryption::decrypt_value(&self.value_cipher(account_id), bytes) {
+            Ok(value) => {
+                Encryption::encrypt_value(&new_key.value_cipher(account_id), &value).map(Some)
+            }
+            Err(_) if Encryption::decrypt_value(&new_key.value_cipher(account_id), bytes).is_ok() => {
+                Ok(None)
+            }
+            Err(_) => Err(StoreError::InvalidArguments(format!(
+                "Failed to decrypt a value of account {}, please make sure the master key is correct.",
+                account_id
+            ))),
+        }
+    }
+
+    // Re-encrypts a blob with this key, returns `None` if it was not encrypted
+    // or was already encrypted with the new key.
+    fn rotate_blob(
+        &self,
+        new_key: &Encryption,
+        blob_id: &BlobId,
+        bytes: &[u8],
+    ) -> crate::Result<Option<Vec<u8>>> {
+        if !FrameHeader::parse(bytes).map_or(false, |header| header.encrypted) {
+            Ok(None)
+        } else if let Ok(frame) = self.decrypt_blob(blob_id, bytes) {
+            new_key.encrypt_blob(blob_id, &frame).map(Some)
+        } else if new_key.decrypt_blob(blob_id, bytes).is_ok()
+            || blob_id.hash() == &Sha256::digest(bytes)[..]
+        {
+            // Already rotated or stored in plaintext before encryption was supported
+            Ok(None)
+        } else {
+            Err(StoreError::InvalidArguments(format!(
+                "Failed to decrypt blob {}, please make sure the master key is correct.",
+                blob_id
+            )))
+        }
+    }
+}
+
+/// Re-encrypts all values and blobs with a new master key. Items that are
+/// already encrypted with the new key are skipped, which allows resuming an
+/// interrupted rotation and running it on each node sharing a blob store.
+/// The server has to be stopped while the master key is being rotated.
+pub fn rotate_master_key<T>(db: &T, settings: &EnvSettings) -> crate::Result<usize>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    let old_key = Encryption::with_master_key(
+        &read_master_key(settings, "encryption-master-key")?.ok_or_else(|| {
+            StoreError::InvalidArguments(
+                "Missing 'encryption-master-key' or 'encryption-master-key-file' setting."
+                    .to_string(),
+            )
+        })?,
+        1024,
+    );
+    let new_key = Encryption::with_master_key(
+        &read_master_key(settings, "encryption-new-master-key")?.ok_or_else(|| {
+            StoreError::InvalidArguments(
+                "Missing 'encryption-new-master-key' or 'encryption-new-master-key-file' setting."
+                    .to_string(),
+            )
+        })?,
+        1024,
+    );
+    let blob_store = BlobStorage::new(settings)?;
+    let mut total_items = 0;
+    let mut batch = Vec::with_capacity(16);
+
+    // Re-encrypt values
+    for (key, value) in db.iterator(ColumnFamily::Values, &[], Direction::Forward)? {
+        if let Some(account_id) = encrypted_value_account(&key) {
+            if let Some(value) = old_key.rotate_value(&new_key, account_id, &value)? {
+                batch.push(WriteOperation::set(
+                    ColumnFamily::Values,
+                    key.to_vec(),
+                    value,
+                ));
+                total_items += 1;
+                if batch.len() == ROTATE_BATCH_SIZE {
+                    db.write(batch)?;
+                    batch = Vec::with_capacity(16);
+                }
+            }
+        }
+    }
+
+    // Re-encrypt blobs
+    for (key, value) in db.iterator(ColumnFamily::Blobs, &[], Direction::Forward)? {
+        if key.len() != BLOB_HASH_LEN + 1 {
+            continue;
+        }
+        let blob_id = BlobId::deserialize(&key).ok_or_else(|| {
+            StoreError::DataCorruption(format!("Failed to deserialize blob key {:?}", key))
+        })?;
+
+        if blob_id.is_local() {
+            if let Some(value) = old_key.rotate_blob(&new_key, &blob_id, &value)? {
+                batch.push(WriteOperation::set(
+                    ColumnFamily::Blobs,
+                    key.to_vec(),
+                    value,
+                ));
+                total_items += 1;
+                if batch.len() == ROTATE_BATCH_SIZE {
+                    db.write(batch)?;
+                    batch = Vec::with_capacity(16);
+                }
+            }
+        } else if let Some(bytes) = blob_store.get(&blob_id)? {
+            if let Some(bytes) = old_key.rotate_blob(&new_key, &blob_id, &bytes)? {
+                blob_store.replace(&blob_id, &bytes)?;
+                total_items += 1;
+            }
+        }
+    }
+
+    if !batch.is_empty() {
+        db.write(batch)?;
+    }
+
+    Ok(total_items)
+}
+
+// Chunks are encrypted with a key derived from the blob hash, so the nonce
+// only has to be unique within the blob.
+fn chunk_nonce(blob_id: &BlobId, chunk_id: usize) -> [u8; SymmetricEncrypt::NONCE_LEN] {
+    let mut nonce = [0u8; SymmetricEncrypt::NONCE_LEN];
+    nonce[..8].copy_from_slice(&blob_id.hash()[..8]);
+    nonce[8..].copy_from_slice(&(chunk_id as u32).to_be_bytes());
+    nonce
+}
+
+fn parse_frame_index(
+    blob_id: &BlobId,
+    header: &FrameHeader,
+    bytes: &[u8],
+) -> crate::Result<Vec<u32>> {
+    let index_range = header.index_range();
+    bytes
+        .get(index_range.start as usize..index_range.end as usize)
+        .and_then(|index| header.parse_index(index))
+        .ok_or_else(|| StoreError::DataCorruption(format!("Blob {} is truncated.", blob_id)))
+}
+
+// Returns the account id of an encrypted value key
+fn encrypted_value_account(key: &[u8]) -> Option<AccountId> {
+    let (account_id, pos) = key.read_leb128::<AccountId>()?;
+    let collection = *key.get(pos)?;
+    let document_len = key.get(pos + 1..)?.skip_leb128()?;
+
+    if key.len() == pos + document_len + 3
+        && key[key.len() - 1] == ENCRYPTED_VALUE_KEY
+        && ![
+            u8::MAX,
+            QUOTA_KEY,
+            BAYES_KEY,
+            UIDS_KEY,
+            GRANT_KEY,
+            PENDING_QUOTA_KEY,
+        ]
+        .contains(&collection)
+    {
+        Some(account_id)
+    } else {
+        None
+    }
+}
+
+pub fn read_master_key(settings: &EnvSettings, name: &str) -> crate::Result<Option<Vec<u8>>> {
+    let master_key = if let Some(master_key) = settings.get(name) {
+        master_key.into_bytes()
+    } else if let Some(path) = settings.get(&format!("{}-file", name)) {
+        let mut master_key = std::fs::read(&path).map_err(|err| {
+            StoreError::InvalidArguments(format!("Failed to read key file {}: {}", path, err))
+        })?;
+        while master_key
+            .last()
+            .map_or(false, |ch| ch.is_ascii_whitespace())
+        {
+            master_key.pop();
+        }
+        master_key
+    } else {
+        return Ok(None);
+    };
+
+    if master_key.len() >= MIN_MASTER_KEY_LEN {
+        Ok(Some(master_key))
+    } else {
+        Err(StoreError::InvalidArguments(format!(
+            "Parameter '{}' has to be at least {} bytes long.",
+            name, MIN_MASTER_KEY_LEN
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        blob::{
+            compress::{BlobCompression, Codec, FrameHeader},
+            BlobId,
+        },
+        core::collection::Collection,
+        serialize::key::ValueKey,
+    };
+
+    use super::{encrypted_value_account, Encryption};
+
+    #[test]
+    fn derived_keys() {
+        // Keys are derived from the master key
+        let encryption = Encryption::with_master_key(b"0123456789abcdef0123456789abcdef", 10);
+        let other_node = Encryption::with_master_key(b"0123456789abcdef0123456789abcdef", 10);
+        let other_key = Encryption::with_master_key(b"fedcba9876543210fedcba9876543210", 10);
+        assert_eq!(encryption.blob_key, other_node.blob_key);
+        assert_ne!(encryption.blob_key, other_key.blob_key);
+
+        // Values use random nonces and per-account keys
+        let value = b"The quick brown fox jumps over the lazy dog".to_vec();
+        let encrypted = Encryption::encrypt_value(&encryption.value_cipher(1), &value).unwrap();
+        assert_ne!(
+            encrypted,
+            Encryption::encrypt_value(&encryption.value_cipher(1), &value).unwrap()
+        );
+        assert_eq!(
+            Encryption::decrypt_value(&other_node.value_cipher(1), &encrypted).unwrap(),
+            value
+        );
+        assert!(Encryption::decrypt_value(&encryption.value_cipher(2), &encrypted).is_err());
+        assert!(Encryption::decrypt_value(&other_key.value_cipher(1), &encrypted).is_err());
+
+        // Values are re-encrypted once with the new key
+        let rotated = encryption
+            .rotate_value(&other_key, 1, &encrypted)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            Encryption::decrypt_value(&other_key.value_cipher(1), &rotated).unwrap(),
+            value
+        );
+        assert_eq!(
+            encryption.rotate_value(&other_key, 1, &rotated).unwrap(),
+            None
+        );
+        assert!(encryption.rotate_value(&other_key, 2, &rotated).is_err());
+
+        // Only encrypted value keys are rotated
+        let key = ValueKey::serialize_value(300, Collection::Mail, 1000, u8::MAX);
+        let encrypted_key =
+            ValueKey::serialize_encrypted_value(300, Collection::Mail, 1000, u8::MAX);
+        assert_eq!(encrypted_value_account(&key), None);
+        assert_eq!(encrypted_value_account(&encrypted_key), Some(300));
+        assert_eq!(
+            encrypted_value_account(&ValueKey::serialize_quota(300)),
+            None
+        );
+    }
+
+    #[test]
+    fn blob_encryption() {
+        let encryption = Encryption::with_master_key(b"0123456789abcdef0123456789abcdef", 10);
+        let other_key = Encryption::with_master_key(b"fedcba9876543210fedcba9876543210", 10);
+        let compression = BlobCompression {
+            codec: Codec::Lz4,
+            level: 3,
+            chunk_size: 4096,
+        };
+        let blob = b"The quick brown fox jumps over the lazy dog".repeat(100);
+        let blob_id = BlobId::new_external(&blob);
+        let frame = compression.compress(blob.clone()).unwrap();
+
+        // Blobs are flagged as encrypted and produce the same ciphertext
+        // for identical contents
+        let encrypted = encryption.encrypt_blob(&blob_id, &frame).unwrap();
+        let header = FrameHeader::parse(&encrypted).unwrap();
+        assert!(header.encrypted);
+        assert_eq!(header.blob_size as usize, blob.len());
+        assert_eq!(
+            encrypted,
+            encryption.encrypt_blob(&blob_id, &frame).unwrap()
+        );
+        assert!(!encrypted.windows(5).any(|w| w == b"quick"));
+        assert_eq!(
+            encryption.decrypt_blob(&blob_id, &encrypted).unwrap(),
+            frame
+        );
+        assert!(other_key.decrypt_blob(&blob_id, &encrypted).is_err());
+
+        // Chunks are encrypted separately, so ranges are read from their chunks only
+        let index = header
+            .parse_index(
+                &encrypted[header.index_range().start as usize..header.index_range().end as usize],
+            )
+            .unwrap();
+        let range = 4200..4250;
+        let (chunks, data) = header.locate(&index, &range);
+        assert_eq!(chunks, 1..2);
+        let (index, data) = encryption
+            .decrypt_blob_chunks(
+                &blob_id,
+                &index,
+                chunks.clone(),
+                &encrypted[data.start as usize..data.end as usize],
+            )
+            .unwrap();
+        assert_eq!(
+            header
+                .decompress_chunks(&index, chunks, &data, &range)
+                .unwrap(),
+            &blob[4200..4250]
+        );
+
+        // Tampered and plaintext blobs are rejected
+        let mut tampered = encrypted.clone();
+        *tampered.last_mut().unwrap() ^= 0xFF;
+        assert!(encryption.decrypt_blob(&blob_id, &tampered).is_err());
+        assert!(encryption.decrypt_blob(&blob_id, &frame).is_err());
+        assert!(encryption.decrypt_blob(&blob_id, &blob).is_err());
+        assert!(encryption.encrypt_blob(&blob_id, &blob).is_err());
+        assert!(encryption.encrypt_blob(&blob_id, &encrypted).is_err());
+
+        // Only encrypted blobs are rotated, and only once
+        let rotated = encryption
+            .rotate_blob(&other_key, &blob_id, &encrypted)
+            .unwrap()
+            .unwrap();
+        assert_eq!(other_key.decrypt_blob(&blob_id, &rotated).unwrap(), frame);
+        assert_eq!(
+            encryption
+                .rotate_blob(&other_key, &blob_id, &rotated)
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            encryption
+                .rotate_blob(&other_key, &blob_id, &frame)
+                .unwrap(),
+            None
+        );
+        assert!(other_key
+            .rotate_blob(&encryption, &blob_id, &tampered)
+            .is_err());
+        let legacy_blob = [&encrypted[..], b"plaintext"].concat();
+        assert_eq!(
+            encryption
+                .rotate_blob(
+                    &other_key,
+                    &BlobId::new_external(&legacy_blob),
+                    &legacy_blob
+                )
+                .unwrap(),
+            None
+        );
+    }
+}
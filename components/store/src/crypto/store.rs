@@ -0,0 +1,49 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use crate::{core::error::StoreError, AccountId, JMAPStore, Store};
+
+use super::Encryption;
+
+impl<T> JMAPStore<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    pub fn encrypt_value(&self, account_id: AccountId, bytes: Vec<u8>) -> crate::Result<Vec<u8>> {
+        if let Some(encryption) = &self.encryption {
+            Encryption::encrypt_value(&encryption.value_cipher(account_id), &bytes)
+        } else {
+            Ok(bytes)
+        }
+    }
+
+    pub fn decrypt_value(&self, account_id: AccountId, bytes: Vec<u8>) -> crate::Result<Vec<u8>> {
+        if let Some(encryption) = &self.encryption {
+            Encryption::decrypt_value(&encryption.value_cipher(account_id), &bytes)
+        } else {
+            Err(StoreError::InvalidArguments(
+                "Found an encrypted value but no encryption master key was configured.".to_string(),
+            ))
+        }
+    }
+}
@@ -24,6 +24,7 @@
 pub mod blob;
 pub mod config;
 pub mod core;
+pub mod crypto;
 pub mod log;
 pub mod nlp;
 pub mod read;
@@ -33,9 +34,9 @@ pub mod write;
 use crate::core::acl::ACL;
 use crate::core::{acl::ACLToken, collection::Collection, error::StoreError};
 use crate::nlp::Language;
-use blob::local::LocalBlobStore;
-use blob::BlobStore;
+use blob::{BlobStorage, BlobStore};
 use config::{env_settings::EnvSettings, jmap::JMAPConfig};
+use crypto::Encryption;
 use log::raft::{LogIndex, RaftId};
 use moka::sync::Cache;
 use parking_lot::{Mutex, MutexGuard};
@@ -137,7 +138,8 @@ pub enum RecipientType {
 
 pub struct JMAPStore<T> {
     pub db: T,
-    pub blob_store: LocalBlobStore,
+    pub blob_store: BlobStorage,
+    pub encryption: Option<Encryption>,
     pub config: JMAPConfig,
 
     pub account_lock: MutexMap<()>,
@@ -159,7 +161,8 @@ where
     pub fn new(db: T, config: JMAPConfig, settings: &EnvSettings) -> Self {
         let mut store = Self {
             config,
-            blob_store: LocalBlobStore::new(settings).unwrap(),
+            blob_store: BlobStorage::new(settings).unwrap(),
+            encryption: Encryption::new(settings).unwrap(),
             id_assigner: Cache::builder()
                 .initial_capacity(128)
                 .max_capacity(settings.parse("cache-size-ids").unwrap_or(32 * 1024 * 1024))
@@ -0,0 +1,183 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use ahash::AHashSet;
+
+use crate::{serialize::key::ValueKey, AccountId, ColumnFamily, JMAPStore, Store};
+
+use super::{tokenizers::Tokenizer, Language};
+
+const MIN_TOKEN_LENGTH: usize = 3;
+const MAX_TOKEN_LENGTH: usize = 40;
+const MAX_INTERESTING_TOKENS: usize = 15;
+
+// Robinson's strength and assumed probability for rare tokens
+const RS: f64 = 1.0;
+const RX: f64 = 0.5;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BayesWeight {
+    pub spam: i64,
+    pub ham: i64,
+}
+
+pub fn bayes_tokenize(text: &str, tokens: &mut AHashSet<String>) {
+    for token in Tokenizer::new(text, Language::Unknown, MAX_TOKEN_LENGTH) {
+        if token.word.chars().count() >= MIN_TOKEN_LENGTH
+            && !token.word.chars().all(|ch| ch.is_ascii_digit())
+        {
+            tokens.insert(token.word.into_owned());
+        }
+    }
+}
+
+/// Combines the token weights using Fisher's method with Robinson's
+/// probabilities, returns the probability of the message being spam.
+pub fn bayes_classify(totals: BayesWeight, weights: impl Iterator<Item = BayesWeight>) -> f64 {
+    let mut probabilities = weights
+        .filter(|weight| weight.spam > 0 || weight.ham > 0)
+        .map(|weight| {
+            let spam_ratio = weight.spam as f64 / totals.spam.max(1) as f64;
+            let ham_ratio = weight.ham as f64 / totals.ham.max(1) as f64;
+            let probability = spam_ratio / (spam_ratio + ham_ratio);
+            let count = (weight.spam + weight.ham) as f64;
+            (RS * RX + count * probability) / (RS + count)
+        })
+        .collect::<Vec<_>>();
+    if probabilities.is_empty() {
+        return RX;
+    }
+
+    // Use only the most interesting tokens
+    probabilities.sort_unstable_by(|a, b| (b - 0.5).abs().total_cmp(&(a - 0.5).abs()));
+    probabilities.truncate(MAX_INTERESTING_TOKENS);
+
+    let (spam_ln, ham_ln) =
+        probabilities
+            .iter()
+            .fold((0.0, 0.0), |(spam_ln, ham_ln), probability| {
+                (
+                    spam_ln + (1.0 - probability).max(f64::MIN_POSITIVE).ln(),
+                    ham_ln + probability.max(f64::MIN_POSITIVE).ln(),
+                )
+            });
+    let spamminess = 1.0 - chi2_q(-2.0 * spam_ln, probabilities.len());
+    let hamminess = 1.0 - chi2_q(-2.0 * ham_ln, probabilities.len());
+
+    (spamminess - hamminess + 1.0) / 2.0
+}
+
+// Chi-square survival function with 2 * count degrees of freedom
+fn chi2_q(chi2: f64, count: usize) -> f64 {
+    let m = chi2 / 2.0;
+    let mut term = (-m).exp();
+    let mut sum = term;
+    for i in 1..count {
+        term *= m / i as f64;
+        sum += term;
+    }
+    sum.min(1.0)
+}
+
+impl<T> JMAPStore<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    pub fn bayes_classify(
+        &self,
+        account_id: AccountId,
+        tokens: &AHashSet<String>,
+    ) -> crate::Result<Option<f64>> {
+        // Do not classify until enough messages have been learned
+        let totals = self.get_bayes_weights(account_id, [""].into_iter())?[0];
+        if totals.spam < self.config.spam_bayes_min_learns
+            || totals.ham < self.config.spam_bayes_min_learns
+        {
+            return Ok(None);
+        }
+
+        Ok(Some(bayes_classify(
+            totals,
+            self.get_bayes_weights(account_id, tokens.iter().map(|token| token.as_str()))?
+                .into_iter(),
+        )))
+    }
+
+    fn get_bayes_weights<'x>(
+        &self,
+        account_id: AccountId,
+        tokens: impl Iterator<Item = &'x str>,
+    ) -> crate::Result<Vec<BayesWeight>> {
+        let mut keys = Vec::new();
+        for token in tokens {
+            keys.push(ValueKey::serialize_bayes(account_id, token, true));
+            keys.push(ValueKey::serialize_bayes(account_id, token, false));
+        }
+
+        Ok(self
+            .db
+            .multi_get::<i64, _>(ColumnFamily::Values, keys)?
+            .chunks(2)
+            .map(|weight| BayesWeight {
+                spam: weight[0].unwrap_or(0).max(0),
+                ham: weight[1].unwrap_or(0).max(0),
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ahash::AHashSet;
+
+    use super::{bayes_classify, bayes_tokenize, BayesWeight};
+
+    #[test]
+    fn bayes_classifier() {
+        let mut tokens = AHashSet::new();
+        bayes_tokenize("Buy CHEAP pills now, only 99 dollars!", &mut tokens);
+        let mut tokens = tokens.into_iter().collect::<Vec<_>>();
+        tokens.sort_unstable();
+        assert_eq!(tokens, ["buy", "cheap", "dollars", "now", "only", "pills"]);
+
+        let totals = BayesWeight { spam: 10, ham: 10 };
+        let spam = [
+            BayesWeight { spam: 9, ham: 0 },
+            BayesWeight { spam: 8, ham: 1 },
+            BayesWeight { spam: 5, ham: 5 },
+        ];
+        let ham = [
+            BayesWeight { spam: 0, ham: 9 },
+            BayesWeight { spam: 1, ham: 7 },
+            BayesWeight { spam: 5, ham: 5 },
+        ];
+
+        assert!(bayes_classify(totals, spam.into_iter()) > 0.9);
+        assert!(bayes_classify(totals, ham.into_iter()) < 0.1);
+        assert_eq!(bayes_classify(totals, [].into_iter()), 0.5);
+        assert_eq!(
+            bayes_classify(totals, [BayesWeight::default()].into_iter()),
+            0.5
+        );
+    }
+}
@@ -21,6 +21,7 @@
  * for more details.
 */
 
+pub mod bayes;
 pub mod lang;
 //pub mod pdf;
 pub mod search_snippet;
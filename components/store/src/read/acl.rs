@@ -70,6 +70,14 @@ where
                         if (acl.contains(ACL::ReadItems)) && to_collection == Collection::Mailbox {
                             collections.insert(Collection::Mail);
                         }
+                        if (acl.contains(ACL::ReadItems))
+                            && to_collection == Collection::AddressBook
+                        {
+                            collections.insert(Collection::ContactCard);
+                        }
+                        if (acl.contains(ACL::ReadItems)) && to_collection == Collection::Calendar {
+                            collections.insert(Collection::CalendarEvent);
+                        }
 
                         if !collections.is_empty() {
                             if let Some(sharing) = shared_accounts
@@ -48,10 +48,22 @@ where
     where
         U: StoreDeserialize + 'static,
     {
-        self.db.get(
+        let key = ValueKey::serialize_value(account_id, collection, document, field);
+        if self.encryption.is_none() {
+            self.db.get(ColumnFamily::Values, &key)
+        } else if let Some(bytes) = self.db.get::<Vec<u8>>(
             ColumnFamily::Values,
-            &ValueKey::serialize_value(account_id, collection, document, field),
-        )
+            &ValueKey::serialize_encrypted_value(account_id, collection, document, field),
+        )? {
+            Ok(Some(
+                U::deserialize(&self.decrypt_value(account_id, bytes)?).ok_or_else(|| {
+                    StoreError::DeserializeError(format!("Failed to deserialize key: {:?}", key))
+                })?,
+            ))
+        } else {
+            // Values not flagged for encryption or stored before enabling it
+            self.db.get(ColumnFamily::Values, &key)
+        }
     }
 
     pub fn get_multi_document_value<U>(
@@ -64,12 +76,48 @@ where
     where
         U: StoreDeserialize + 'static,
     {
-        self.db.multi_get(
+        let documents = documents.collect::<Vec<_>>();
+        let values = self.db.multi_get(
             ColumnFamily::Values,
             documents
-                .map(|document| ValueKey::serialize_value(account_id, collection, document, field))
-                .collect::<Vec<_>>(),
-        )
+                .iter()
+                .map(|document| ValueKey::serialize_value(account_id, collection, *document, field))
+                .collect(),
+        )?;
+        if self.encryption.is_none() {
+            return Ok(values);
+        }
+
+        self.db
+            .multi_get::<Vec<u8>, _>(
+                ColumnFamily::Values,
+                documents
+                    .iter()
+                    .map(|document| {
+                        ValueKey::serialize_encrypted_value(
+                            account_id, collection, *document, field,
+                        )
+                    })
+                    .collect(),
+            )?
+            .into_iter()
+            .zip(values)
+            .map(|(bytes, value)| {
+                if let Some(bytes) = bytes {
+                    Ok(Some(
+                        U::deserialize(&self.decrypt_value(account_id, bytes)?).ok_or_else(
+                            || {
+                                StoreError::DeserializeError(
+                                    "Failed to deserialize keys.".to_string(),
+                                )
+                            },
+                        )?,
+                    ))
+                } else {
+                    Ok(value)
+                }
+            })
+            .collect()
     }
 
     pub fn get_tag(
@@ -180,4 +228,21 @@ where
             &ValueKey::serialize_term_index(account_id, collection, document_id),
         )
     }
+
+    pub fn get_used_quota(&self, account_id: AccountId) -> crate::Result<i64> {
+        Ok(self
+            .db
+            .get::<i64>(ColumnFamily::Values, &ValueKey::serialize_quota(account_id))?
+            .unwrap_or(0))
+    }
+
+    pub fn get_pending_quota(&self, account_id: AccountId) -> crate::Result<i64> {
+        Ok(self
+            .db
+            .get::<i64>(
+                ColumnFamily::Values,
+                &ValueKey::serialize_pending_quota(account_id),
+            )?
+            .unwrap_or(0))
+    }
 }
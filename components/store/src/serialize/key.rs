@@ -59,6 +59,12 @@ pub const TAG_TEXT: u8 = 0x01;
 pub const TAG_STATIC: u8 = 0x02;
 
 pub const INTERNAL_KEY_PREFIX: u8 = 0;
+pub const QUOTA_KEY: u8 = u8::MAX - 1;
+pub const BAYES_KEY: u8 = u8::MAX - 2;
+pub const ENCRYPTED_VALUE_KEY: u8 = u8::MAX - 3;
+pub const UIDS_KEY: u8 = u8::MAX - 4;
+pub const GRANT_KEY: u8 = u8::MAX - 5;
+pub const PENDING_QUOTA_KEY: u8 = u8::MAX - 6;
 
 pub const FOLLOWER_COMMIT_INDEX_KEY: &[u8; 2] = &[INTERNAL_KEY_PREFIX, 1];
 pub const LEADER_COMMIT_INDEX_KEY: &[u8; 2] = &[INTERNAL_KEY_PREFIX, 2];
@@ -109,6 +115,63 @@ impl ValueKey {
         bytes
     }
 
+    pub fn serialize_quota(account: AccountId) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(std::mem::size_of::<AccountId>() + 1);
+        bytes.push_leb128(account);
+        bytes.push(QUOTA_KEY);
+        bytes
+    }
+
+    pub fn serialize_pending_quota(account: AccountId) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(std::mem::size_of::<AccountId>() + 1);
+        bytes.push_leb128(account);
+        bytes.push(PENDING_QUOTA_KEY);
+        bytes
+    }
+
+    pub fn serialize_encrypted_value(
+        account: AccountId,
+        collection: Collection,
+        document: DocumentId,
+        field: FieldId,
+    ) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(ACCOUNT_KEY_LEN + std::mem::size_of::<FieldId>() + 1);
+        bytes.push_leb128(account);
+        bytes.push(collection.into());
+        bytes.push_leb128(document);
+        bytes.push(field);
+        bytes.push(ENCRYPTED_VALUE_KEY);
+        bytes
+    }
+
+    pub fn serialize_bayes(account: AccountId, token: &str, is_spam: bool) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(std::mem::size_of::<AccountId>() + 2 + token.len());
+        bytes.push_leb128(account);
+        bytes.push(BAYES_KEY);
+        bytes.push(if is_spam { 1 } else { 0 });
+        bytes.extend_from_slice(token.as_bytes());
+        bytes
+    }
+
+    pub fn serialize_uids(account: AccountId, mailbox: DocumentId) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(
+            std::mem::size_of::<AccountId>() + std::mem::size_of::<DocumentId>() + 2,
+        );
+        bytes.push_leb128(account);
+        bytes.push(UIDS_KEY);
+        bytes.push_leb128(mailbox);
+        bytes
+    }
+
+    pub fn serialize_grant(account: AccountId, grant_id: u64) -> Vec<u8> {
+        let mut bytes =
+            Vec::with_capacity(std::mem::size_of::<AccountId>() + std::mem::size_of::<u64>() + 1);
+        bytes.push_leb128(account);
+        bytes.push(GRANT_KEY);
+        bytes.extend_from_slice(&grant_id.to_be_bytes());
+        bytes
+    }
+
     pub fn serialize_acl(
         grant_account: AccountId,
         to_account: AccountId,
@@ -28,6 +28,8 @@ use crate::core::vec_map::VecMap;
 use crate::serialize::leb128::Leb128Vec;
 use crate::{AccountId, Collection, DocumentId, JMAPId};
 
+pub const QUOTA_ID: JMAPId = 0;
+
 #[derive(Debug)]
 pub enum WriteAction {
     Insert(Document),
@@ -71,6 +71,11 @@ impl<T> Field<T> {
         self.options.is_clear()
     }
 
+    #[inline(always)]
+    pub fn is_encrypted(&self) -> bool {
+        self.options.is_encrypt()
+    }
+
     pub fn size_of(&self) -> usize {
         std::mem::size_of::<T>()
     }
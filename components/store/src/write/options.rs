@@ -34,6 +34,7 @@ pub trait Options {
     const F_STORE: u64 = 0x01 << 32;
     const F_INDEX: u64 = 0x02 << 32;
     const F_CLEAR: u64 = 0x04 << 32;
+    const F_ENCRYPT: u64 = 0x08 << 32;
     const F_NONE: u64 = 0;
     const F_KEYWORD: u64 = 1;
     const F_TOKENIZE: u64 = 2;
@@ -42,6 +43,7 @@ pub trait Options {
     fn store(self) -> Self;
     fn index(self) -> Self;
     fn clear(self) -> Self;
+    fn encrypt(self) -> Self;
     fn keyword(self) -> Self;
     fn tokenize(self) -> Self;
     fn full_text(self, part_id: u32) -> Self;
@@ -49,6 +51,7 @@ pub trait Options {
     fn is_store(&self) -> bool;
     fn is_index(&self) -> bool;
     fn is_clear(&self) -> bool;
+    fn is_encrypt(&self) -> bool;
     fn is_full_text(&self) -> bool;
     fn get_text_options(&self) -> u64;
 }
@@ -81,6 +84,11 @@ impl Options for u64 {
         self
     }
 
+    fn encrypt(mut self) -> Self {
+        self |= Self::F_ENCRYPT;
+        self
+    }
+
     fn is_store(&self) -> bool {
         self & Self::F_STORE != 0
     }
@@ -93,6 +101,10 @@ impl Options for u64 {
         self & Self::F_CLEAR != 0
     }
 
+    fn is_encrypt(&self) -> bool {
+        self & Self::F_ENCRYPT != 0
+    }
+
     fn is_full_text(&self) -> bool {
         *self & 0xFFFFFFFF >= Self::F_FULL_TEXT
     }
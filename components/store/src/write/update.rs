@@ -24,7 +24,7 @@
 use ahash::AHashMap;
 
 use crate::{
-    blob::BlobId,
+    blob::{deserialize_ephemeral_link, serialize_ephemeral_link, BlobId},
     core::{
         bitmap::Bitmap, collection::Collection, document::MAX_TOKEN_LENGTH, error::StoreError,
         tag::Tag,
@@ -46,7 +46,7 @@ use crate::{
 };
 
 use super::{
-    batch::{Change, WriteAction, WriteBatch},
+    batch::{Change, WriteAction, WriteBatch, QUOTA_ID},
     operation::WriteOperation,
     options::{IndexOptions, Options},
 };
@@ -96,11 +96,14 @@ where
     fn prepare_batch(
         &self,
         ops: &mut Vec<WriteOperation>,
-        batch: WriteBatch,
+        mut batch: WriteBatch,
         tombstone_deletions: bool,
     ) -> crate::Result<Option<Changes>> {
         let mut bitmap_list = AHashMap::default();
         let mut tombstones = Vec::new();
+        let mut quota = 0;
+        let mut uploads = AHashMap::default();
+        let mut bayes = AHashMap::default();
 
         for document in batch.documents {
             let mut document = match document {
@@ -151,6 +154,18 @@ where
                 }
             };
 
+            // Update used quota
+            quota += document.quota;
+
+            // Update Bayes token counts
+            for (tokens, is_spam, increment) in std::mem::take(&mut document.bayes) {
+                for token in tokens.iter().map(|token| token.as_str()).chain([""]) {
+                    *bayes
+                        .entry(ValueKey::serialize_bayes(batch.account_id, token, is_spam))
+                        .or_insert(0i64) += increment;
+                }
+            }
+
             // Process text fields
             if !document.text_fields.is_empty() {
                 // Detect language for unknown fields
@@ -430,11 +445,31 @@ where
                     document.document_id,
                     field.get_field(),
                 );
-                ops.push(if !field.is_clear() {
-                    WriteOperation::set(ColumnFamily::Values, key, field.value)
+                let encrypted_key = ValueKey::serialize_encrypted_value(
+                    batch.account_id,
+                    document.collection,
+                    document.document_id,
+                    field.get_field(),
+                );
+
+                // Encrypted values are stored under their own key, a value is
+                // only ever present under one of them.
+                if field.is_clear() {
+                    ops.push(WriteOperation::delete(ColumnFamily::Values, key));
+                    ops.push(WriteOperation::delete(ColumnFamily::Values, encrypted_key));
+                } else if field.is_encrypted() && self.encryption.is_some() {
+                    ops.push(WriteOperation::set(
+                        ColumnFamily::Values,
+                        encrypted_key,
+                        self.encrypt_value(batch.account_id, field.value)?,
+                    ));
+                    ops.push(WriteOperation::delete(ColumnFamily::Values, key));
                 } else {
-                    WriteOperation::delete(ColumnFamily::Values, key)
-                });
+                    ops.push(WriteOperation::set(ColumnFamily::Values, key, field.value));
+                    if field.is_encrypted() {
+                        ops.push(WriteOperation::delete(ColumnFamily::Values, encrypted_key));
+                    }
+                }
             }
 
             // Store external blobs references
@@ -452,6 +487,21 @@ where
                 } else {
                     WriteOperation::delete(ColumnFamily::Blobs, key)
                 });
+
+                // Uploads are no longer pending once linked to a document
+                if is_set && !uploads.contains_key(&id) {
+                    if let Some((timestamp, size)) = self
+                        .db
+                        .get::<Vec<u8>>(
+                            ColumnFamily::Blobs,
+                            &BlobKey::serialize_prefix(&id, batch.account_id),
+                        )?
+                        .and_then(|bytes| deserialize_ephemeral_link(&bytes))
+                        .filter(|(_, size)| *size > 0)
+                    {
+                        uploads.insert(id, (timestamp, size));
+                    }
+                }
             }
 
             // Process ACLs
@@ -486,6 +536,51 @@ where
             ));
         }
 
+        // Update Bayes token counts
+        for (key, increment) in bayes {
+            if increment != 0 {
+                ops.push(WriteOperation::merge(
+                    ColumnFamily::Values,
+                    key,
+                    increment.serialize().unwrap(),
+                ));
+            }
+        }
+
+        // Release pending uploads
+        if !uploads.is_empty() {
+            let mut pending_quota = 0;
+            for (blob_id, (timestamp, size)) in uploads {
+                ops.push(WriteOperation::set(
+                    ColumnFamily::Blobs,
+                    BlobKey::serialize_prefix(&blob_id, batch.account_id),
+                    serialize_ephemeral_link(timestamp, 0),
+                ));
+                pending_quota -= size as i64;
+            }
+            ops.push(WriteOperation::merge(
+                ColumnFamily::Values,
+                ValueKey::serialize_pending_quota(batch.account_id),
+                pending_quota.serialize().unwrap(),
+            ));
+        }
+
+        // Update quota usage
+        if quota != 0 {
+            ops.push(WriteOperation::merge(
+                ColumnFamily::Values,
+                ValueKey::serialize_quota(batch.account_id),
+                quota.serialize().unwrap(),
+            ));
+
+            // Notify quota changes
+            batch
+                .changes
+                .get_mut_or_insert(Collection::Quota)
+                .updates
+                .insert(QUOTA_ID);
+        }
+
         // Serialize Raft and change log
         if !batch.changes.is_empty() {
             let raft_id = self.assign_raft_id();
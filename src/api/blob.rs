@@ -25,34 +25,65 @@ use super::{RequestError, RequestLimitError};
 use crate::authorization::auth::RemoteAddress;
 use crate::authorization::Session;
 use crate::JMAPServer;
-use actix_web::http::header::ContentType;
+use actix_web::body::SizedStream;
+use actix_web::http::header::{self, ContentType};
 use actix_web::HttpRequest;
 use actix_web::{http::StatusCode, web, HttpResponse};
-use jmap::error::set::SetError;
-use jmap::request::blob::{CopyBlobRequest, CopyBlobResponse};
+use async_stream::stream;
+use futures::StreamExt;
+use jmap::base64;
+use jmap::error::method::MethodError;
+use jmap::error::set::{SetError, SetErrorType};
+use jmap::orm::serialize::JMAPOrm;
+use jmap::principal::store::JMAPPrincipals;
+use jmap::request::blob::{
+    BlobCreatedObject, BlobLookupObject, BlobObject, BlobProperty, CopyBlobRequest,
+    CopyBlobResponse, DataSourceObject, GetBlobRequest, GetBlobResponse, LookupBlobRequest,
+    LookupBlobResponse, UploadBlobRequest, UploadBlobResponse,
+};
 use jmap::request::ACLEnforce;
 use jmap::types::blob::JMAPBlob;
 use jmap::types::jmap::JMAPId;
 use jmap::SUPERUSER_ID;
 use jmap_mail::mail::get::{BlobResult, JMAPGetMail};
+use jmap_mail::mail::schema::{Email, Property};
 use jmap_mail::mail::sharing::JMAPShareMail;
+use jmap_mail::mail::MessageField;
 use jmap_sharing::principal::account::JMAPAccountStore;
-use reqwest::header::CONTENT_TYPE;
+use std::ops::Range;
+use std::path::PathBuf;
 use store::blob::BlobId;
 use store::core::acl::ACL;
 use store::core::collection::Collection;
 use store::core::vec_map::VecMap;
-use store::JMAPStore;
+use store::sha2::{Digest, Sha256, Sha512};
 use store::{tracing::error, Store};
+use store::{DocumentId, JMAPStore};
+use tokio::io::AsyncWriteExt;
+
+const DOWNLOAD_CHUNK_SIZE: u32 = 1024 * 1024;
+const CACHE_CONTROL: &str = "private, immutable, max-age=31536000";
 
 #[derive(serde::Deserialize)]
 pub struct Params {
     accept: Option<String>,
 }
 
+enum Download {
+    Bytes(Vec<u8>),
+    Stream { blob_id: BlobId, range: Range<u32> },
+}
+
+enum ByteRange {
+    Full,
+    Partial(Range<u32>),
+    Unsatisfiable,
+}
+
 pub async fn handle_jmap_download<T>(
     path: web::Path<(JMAPId, JMAPBlob, String)>,
     params: web::Query<Params>,
+    request: HttpRequest,
     core: web::Data<JMAPServer<T>>,
     session: Session,
 ) -> Result<HttpResponse, RequestError>
@@ -60,44 +91,220 @@ where
     T: for<'x> Store<'x> + 'static,
 {
     // Enforce access control
-    let (id, blob_id, filename) = path.into_inner();
+    let (id, blob, filename) = path.into_inner();
     let account_id = id.get_document_id();
+    let etag = format!("\"{}\"", blob);
 
     let store = core.store.clone();
-    match core
+    let download = match core
         .spawn_worker(move || {
-            store.mail_blob_get(
-                account_id,
-                &store.get_acl_token(session.account_id())?,
-                &blob_id,
+            let acl = store.get_acl_token(session.account_id())?;
+
+            // Local and encoded blobs have to be loaded in full, everything
+            // else is streamed from the blob store.
+            Ok(
+                if blob.id.is_local() || blob.section.as_ref().map_or(false, |s| s.encoding != 0) {
+                    match store.mail_blob_get(account_id, &acl, &blob)? {
+                        BlobResult::Blob(bytes) => Ok(Download::Bytes(bytes)),
+                        BlobResult::NotFound => Err(RequestError::not_found()),
+                        BlobResult::Unauthorized => Err(RequestError::forbidden()),
+                    }
+                } else if !store.mail_blob_has_access(account_id, &acl, &blob.id)? {
+                    Err(RequestError::forbidden())
+                } else if let Some(size) = store.blob_size(&blob.id)? {
+                    let range = if let Some(section) = &blob.section {
+                        let start = std::cmp::min(section.offset_start, size as usize);
+                        let end = std::cmp::min(section.offset_start + section.size, size as usize);
+                        start as u32..std::cmp::max(start, end) as u32
+                    } else {
+                        0..size
+                    };
+                    Ok(Download::Stream {
+                        blob_id: blob.id,
+                        range,
+                    })
+                } else {
+                    Err(RequestError::not_found())
+                },
             )
         })
         .await
     {
-        Ok(BlobResult::Blob(bytes)) => Ok(HttpResponse::build(StatusCode::OK)
-            .insert_header((
-                "Content-Type",
-                params
-                    .into_inner()
-                    .accept
-                    .unwrap_or_else(|| "application/octet-stream".to_string()),
-            ))
-            .insert_header((
-                "Content-Disposition",
-                format!(
-                    "attachment; filename=\"{}\"",
-                    filename.replace('\"', "\\\"")
-                ),
-            ))
-            .insert_header(("Cache-Control", "private, immutable, max-age=31536000"))
-            .body(bytes)),
-        Ok(BlobResult::NotFound) => Err(RequestError::not_found()),
-        Ok(BlobResult::Unauthorized) => Err(RequestError::forbidden()),
+        Ok(Ok(download)) => download,
+        Ok(Err(err)) => return Err(err),
         Err(err) => {
             error!("Blob download failed: {:?}", err);
-            Err(RequestError::internal_server_error())
+            return Err(RequestError::internal_server_error());
+        }
+    };
+
+    // Blobs are immutable, so a matching ETag means the client's copy is current
+    if let Some(if_none_match) = request
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|h| h.to_str().ok())
+    {
+        if if_none_match
+            .split(',')
+            .map(|tag| tag.trim())
+            .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+        {
+            return Ok(HttpResponse::build(StatusCode::NOT_MODIFIED)
+                .insert_header((header::ETAG, etag))
+                .insert_header((header::CACHE_CONTROL, CACHE_CONTROL))
+                .finish());
         }
     }
+
+    let size = match &download {
+        Download::Bytes(bytes) => bytes.len() as u32,
+        Download::Stream { range, .. } => range.end - range.start,
+    };
+
+    // Ranges are ignored when If-Range does not match the current ETag
+    let byte_range = match request
+        .headers()
+        .get(header::RANGE)
+        .and_then(|h| h.to_str().ok())
+    {
+        Some(range)
+            if request
+                .headers()
+                .get(header::IF_RANGE)
+                .map_or(true, |if_range| if_range.as_bytes() == etag.as_bytes()) =>
+        {
+            parse_range(range, size)
+        }
+        _ => ByteRange::Full,
+    };
+
+    let mut response = match &byte_range {
+        ByteRange::Full => HttpResponse::build(StatusCode::OK),
+        ByteRange::Partial(range) => {
+            let mut response = HttpResponse::build(StatusCode::PARTIAL_CONTENT);
+            response.insert_header((
+                header::CONTENT_RANGE,
+                format!("bytes {}-{}/{}", range.start, range.end - 1, size),
+            ));
+            response
+        }
+        ByteRange::Unsatisfiable => {
+            return Ok(HttpResponse::build(StatusCode::RANGE_NOT_SATISFIABLE)
+                .insert_header((header::CONTENT_RANGE, format!("bytes */{}", size)))
+                .finish());
+        }
+    };
+    response
+        .insert_header((
+            header::CONTENT_TYPE,
+            params
+                .into_inner()
+                .accept
+                .unwrap_or_else(|| "application/octet-stream".to_string()),
+        ))
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            format!(
+                "attachment; filename=\"{}\"",
+                filename.replace('\"', "\\\"")
+            ),
+        ))
+        .insert_header((header::CACHE_CONTROL, CACHE_CONTROL))
+        .insert_header((header::ACCEPT_RANGES, "bytes"))
+        .insert_header((header::ETAG, etag));
+
+    Ok(match download {
+        Download::Bytes(mut bytes) => {
+            if let ByteRange::Partial(range) = byte_range {
+                bytes.truncate(range.end as usize);
+                bytes.drain(..range.start as usize);
+            }
+            response.body(bytes)
+        }
+        Download::Stream { blob_id, range } => {
+            let range = if let ByteRange::Partial(partial) = byte_range {
+                range.start + partial.start..range.start + partial.end
+            } else {
+                range
+            };
+            let store = core.store.clone();
+            let core = core.clone();
+
+            response.body(SizedStream::new(
+                (range.end - range.start) as u64,
+                stream! {
+                    let mut offset = range.start;
+                    while offset < range.end {
+                        let store = store.clone();
+                        let blob_id = blob_id.clone();
+                        let chunk_end = std::cmp::min(
+                            offset.saturating_add(DOWNLOAD_CHUNK_SIZE),
+                            range.end,
+                        );
+
+                        match core
+                            .spawn_worker(move || store.blob_get_range(&blob_id, offset..chunk_end))
+                            .await
+                        {
+                            Ok(Some(bytes)) if !bytes.is_empty() => {
+                                offset += bytes.len() as u32;
+                                yield Ok(web::Bytes::from(bytes));
+                            }
+                            Ok(_) => {
+                                yield Err(std::io::Error::new(
+                                    std::io::ErrorKind::UnexpectedEof,
+                                    "Blob is shorter than expected.",
+                                ));
+                                break;
+                            }
+                            Err(err) => {
+                                error!("Blob download failed: {:?}", err);
+                                yield Err(std::io::Error::new(
+                                    std::io::ErrorKind::Other,
+                                    err.to_string(),
+                                ));
+                                break;
+                            }
+                        }
+                    }
+                },
+            ))
+        }
+    })
+}
+
+/// Parses a single byte range as defined in RFC 7233. Multiple and
+/// malformed ranges are ignored and the full blob is returned instead.
+fn parse_range(value: &str, size: u32) -> ByteRange {
+    let (start, end) = if let Some((start, end)) = value
+        .trim()
+        .strip_prefix("bytes=")
+        .filter(|spec| !spec.contains(','))
+        .and_then(|spec| spec.split_once('-'))
+    {
+        (start.trim(), end.trim())
+    } else {
+        return ByteRange::Full;
+    };
+    let size = size as u64;
+
+    let range = match (start.parse::<u64>(), end.parse::<u64>()) {
+        (Ok(start), Ok(end)) if start <= end => start..std::cmp::min(end.saturating_add(1), size),
+        (Ok(start), Err(_)) if end.is_empty() => start..size,
+        (Err(_), Ok(suffix)) if start.is_empty() => {
+            if suffix == 0 {
+                return ByteRange::Unsatisfiable;
+            }
+            size.saturating_sub(suffix)..size
+        }
+        _ => return ByteRange::Full,
+    };
+
+    if range.start < size {
+        ByteRange::Partial(range.start as u32..range.end as u32)
+    } else {
+        ByteRange::Unsatisfiable
+    }
 }
 
 #[derive(Debug, serde::Serialize)]
@@ -111,10 +318,18 @@ struct UploadResponse {
     size: usize,
 }
 
+struct TempFile(PathBuf);
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        std::fs::remove_file(&self.0).ok();
+    }
+}
+
 pub async fn handle_jmap_upload<T>(
     path: web::Path<(JMAPId,)>,
     request: HttpRequest,
-    bytes: web::Bytes,
+    mut payload: web::Payload,
     core: web::Data<JMAPServer<T>>,
     session: Session,
 ) -> Result<HttpResponse, RequestError>
@@ -123,6 +338,7 @@ where
 {
     let (id,) = path.into_inner();
     let account_id = id.get_document_id();
+    let max_size = core.store.config.max_size_upload;
 
     // Rate limit uploads
     let _upload_req = if session.account_id() != SUPERUSER_ID {
@@ -136,53 +352,109 @@ where
         None
     };
 
-    #[cfg(test)]
+    // Reject oversized uploads before reading the payload
+    if request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.parse::<usize>().ok())
+        .map_or(false, |size| size > max_size)
+    {
+        return Err(RequestError::limit(RequestLimitError::Size));
+    }
+
+    let store = core.store.clone();
+    match core
+        .spawn_worker(move || {
+            Ok(store
+                .get_acl_token(session.account_id())?
+                .is_member(account_id))
+        })
+        .await
     {
-        // Used for concurrent upload tests
-        if bytes == b"sleep"[..] {
-            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        Ok(true) => (),
+        Ok(false) => return Err(RequestError::forbidden()),
+        Err(err) => {
+            error!("Blob upload failed: {:?}", err);
+            return Err(RequestError::internal_server_error());
         }
     }
 
-    if bytes.len() > core.store.config.max_size_upload {
-        return Err(RequestError::limit(RequestLimitError::Size));
+    // Hash the payload while writing it to a temporary file
+    let temp_path = &core.store.config.upload_temp_path;
+    let temp_file =
+        TempFile(temp_path.join(format!("upload_{:016x}", store::rand::random::<u64>())));
+    let mut file = match tokio::fs::create_dir_all(temp_path).await {
+        Ok(_) => tokio::fs::File::create(&temp_file.0).await,
+        Err(err) => Err(err),
     }
+    .map_err(|err| {
+        error!("Failed to create temporary file {:?}: {}", temp_file.0, err);
+        RequestError::internal_server_error()
+    })?;
+    let mut hasher = Sha256::new();
+    let mut size = 0;
+
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk.map_err(|_| RequestError::invalid_parameters())?;
+
+        #[cfg(test)]
+        {
+            // Used for concurrent upload tests
+            if size == 0 && chunk == b"sleep"[..] {
+                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            }
+        }
+
+        size += chunk.len();
+        if size > max_size {
+            return Err(RequestError::limit(RequestLimitError::Size));
+        }
+        hasher.update(&chunk);
+        file.write_all(&chunk).await.map_err(|err| {
+            error!("Failed to write temporary file {:?}: {}", temp_file.0, err);
+            RequestError::internal_server_error()
+        })?;
+    }
+    file.flush().await.map_err(|err| {
+        error!("Failed to write temporary file {:?}: {}", temp_file.0, err);
+        RequestError::internal_server_error()
+    })?;
+    drop(file);
 
     let store = core.store.clone();
-    let size = bytes.len();
+    let blob_id = BlobId::External {
+        hash: hasher.finalize().into(),
+    };
     match core
         .spawn_worker(move || {
-            Ok(
-                if store
-                    .get_acl_token(session.account_id())?
-                    .is_member(account_id)
-                {
-                    let blob = bytes.to_vec();
-                    let blob_id = BlobId::new_external(&blob);
-                    store.blob_store(&blob_id, blob)?;
-                    store.blob_link_ephemeral(&blob_id, account_id)?;
-                    JMAPBlob::new(blob_id).into()
-                } else {
-                    None
-                },
-            )
+            Ok(if !store.principal_has_upload_quota(account_id, size)? {
+                Err(RequestError::over_quota())
+            } else {
+                // Identical blobs are only written once
+                if !store.blob_exists(&blob_id)? {
+                    store.blob_store_file(&blob_id, &temp_file.0)?;
+                }
+                store.blob_link_upload(&blob_id, account_id, size as u64)?;
+                Ok(JMAPBlob::new(blob_id))
+            })
         })
         .await
     {
-        Ok(Some(blob_id)) => Ok(HttpResponse::build(StatusCode::OK)
+        Ok(Ok(blob_id)) => Ok(HttpResponse::build(StatusCode::OK)
             .insert_header(ContentType::json())
             .json(UploadResponse {
                 account_id: id,
                 blob_id,
                 c_type: request
                     .headers()
-                    .get(CONTENT_TYPE)
+                    .get(header::CONTENT_TYPE)
                     .and_then(|h| h.to_str().ok())
                     .unwrap_or("application/octet-stream")
                     .to_string(),
                 size,
             })),
-        Ok(None) => Err(RequestError::forbidden()),
+        Ok(Err(err)) => Err(err),
         Err(err) => {
             error!("Blob upload failed: {:?}", err);
             Err(RequestError::internal_server_error())
@@ -256,3 +528,342 @@ where
         })
     }
 }
+
+pub trait JMAPBlobUpload<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn upload_blob(&self, request: UploadBlobRequest) -> jmap::Result<UploadBlobResponse>;
+}
+
+impl<T> JMAPBlobUpload<T> for JMAPStore<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn upload_blob(&self, request: UploadBlobRequest) -> jmap::Result<UploadBlobResponse> {
+        let acl = request.acl.unwrap();
+        let account_id = request.account_id.get_document_id();
+        let mut created = VecMap::with_capacity(request.create.len());
+        let mut not_created = VecMap::new();
+
+        if request.create.len() > self.config.max_objects_in_set {
+            return Err(MethodError::RequestTooLarge);
+        }
+
+        'outer: for (create_id, object) in request.create {
+            if object.data.len() > self.config.blob_max_data_sources {
+                not_created.append(
+                    create_id,
+                    SetError::new(SetErrorType::TooLarge, "Too many data sources."),
+                );
+                continue;
+            }
+
+            // Concatenate data sources
+            let mut blob = Vec::new();
+            for source in object.data {
+                match source {
+                    DataSourceObject::Text { value } => {
+                        blob.extend_from_slice(value.as_bytes());
+                    }
+                    DataSourceObject::Base64 { value } => {
+                        if let Ok(bytes) = base64::decode(value) {
+                            blob.extend(bytes);
+                        } else {
+                            not_created.append(
+                                create_id,
+                                SetError::new(
+                                    SetErrorType::InvalidProperties,
+                                    "Failed to decode base64 data.",
+                                ),
+                            );
+                            continue 'outer;
+                        }
+                    }
+                    DataSourceObject::Blob {
+                        blob_id,
+                        offset,
+                        length,
+                    } => {
+                        let bytes = match self.mail_blob_get(account_id, &acl, &blob_id)? {
+                            BlobResult::Blob(bytes) => bytes,
+                            BlobResult::NotFound | BlobResult::Unauthorized => {
+                                not_created.append(
+                                    create_id,
+                                    SetError::new(
+                                        SetErrorType::BlobNotFound,
+                                        format!("BlobId {} not found.", blob_id),
+                                    ),
+                                );
+                                continue 'outer;
+                            }
+                        };
+                        let start = offset.unwrap_or(0);
+                        let end = length.map_or(bytes.len(), |length| start.saturating_add(length));
+                        if let Some(bytes) = bytes.get(start..end) {
+                            blob.extend_from_slice(bytes);
+                        } else {
+                            not_created.append(
+                                create_id,
+                                SetError::new(
+                                    SetErrorType::InvalidProperties,
+                                    format!("Range is outside of blobId {}.", blob_id),
+                                ),
+                            );
+                            continue 'outer;
+                        }
+                    }
+                }
+
+                if blob.len() > self.config.max_size_upload {
+                    not_created.append(
+                        create_id,
+                        SetError::new(SetErrorType::TooLarge, "Blob exceeds the maximum size."),
+                    );
+                    continue 'outer;
+                }
+            }
+
+            if !self.principal_has_upload_quota(account_id, blob.len())? {
+                not_created.append(create_id, SetError::new_err(SetErrorType::OverQuota));
+                continue;
+            }
+
+            let size = blob.len();
+            let blob_id = BlobId::new_external(&blob);
+            self.blob_store(&blob_id, blob)?;
+            self.blob_link_upload(&blob_id, account_id, size as u64)?;
+            created.append(
+                create_id,
+                BlobCreatedObject {
+                    id: JMAPBlob::new(blob_id),
+                    type_: object.type_,
+                    size,
+                },
+            );
+        }
+
+        Ok(UploadBlobResponse {
+            account_id: request.account_id,
+            created: if !created.is_empty() {
+                created.into()
+            } else {
+                None
+            },
+            not_created: if !not_created.is_empty() {
+                not_created.into()
+            } else {
+                None
+            },
+        })
+    }
+}
+
+pub trait JMAPBlobGet<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn get_blob(&self, request: GetBlobRequest) -> jmap::Result<GetBlobResponse>;
+}
+
+impl<T> JMAPBlobGet<T> for JMAPStore<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn get_blob(&self, request: GetBlobRequest) -> jmap::Result<GetBlobResponse> {
+        let acl = request.acl.unwrap();
+        let account_id = request.account_id.get_document_id();
+        let properties = request
+            .properties
+            .unwrap_or_else(|| vec![BlobProperty::Data, BlobProperty::Size]);
+        let mut list = Vec::with_capacity(request.ids.len());
+        let mut not_found = Vec::new();
+
+        if request.ids.len() > self.config.max_objects_in_get {
+            return Err(MethodError::RequestTooLarge);
+        }
+
+        for blob_id in request.ids {
+            let bytes = match self.mail_blob_get(account_id, &acl, &blob_id)? {
+                BlobResult::Blob(bytes) => bytes,
+                BlobResult::NotFound | BlobResult::Unauthorized => {
+                    not_found.push(blob_id);
+                    continue;
+                }
+            };
+
+            // Digests and data are calculated over the requested range
+            let start = request.offset.unwrap_or(0);
+            let end = request
+                .length
+                .map_or(bytes.len(), |length| start.saturating_add(length));
+            let range = bytes
+                .get(std::cmp::min(start, bytes.len())..std::cmp::min(end, bytes.len()))
+                .unwrap_or_default();
+
+            let mut object = BlobObject {
+                id: blob_id,
+                data_as_text: None,
+                data_as_base64: None,
+                digest_sha256: None,
+                digest_sha512: None,
+                size: None,
+                is_encoding_problem: false,
+                is_truncated: end > bytes.len(),
+            };
+
+            for property in &properties {
+                match property {
+                    BlobProperty::Id => (),
+                    BlobProperty::Data => match std::str::from_utf8(range) {
+                        Ok(text) => object.data_as_text = Some(Some(text.to_string())),
+                        Err(_) => {
+                            object.is_encoding_problem = true;
+                            object.data_as_base64 = base64::encode(range).into();
+                        }
+                    },
+                    BlobProperty::DataAsText => {
+                        object.data_as_text = Some(if let Ok(text) = std::str::from_utf8(range) {
+                            Some(text.to_string())
+                        } else {
+                            object.is_encoding_problem = true;
+                            None
+                        });
+                    }
+                    BlobProperty::DataAsBase64 => {
+                        object.data_as_base64 = base64::encode(range).into();
+                    }
+                    BlobProperty::Size => {
+                        object.size = bytes.len().into();
+                    }
+                    BlobProperty::DigestSha256 => {
+                        object.digest_sha256 = base64::encode(Sha256::digest(range)).into();
+                    }
+                    BlobProperty::DigestSha512 => {
+                        object.digest_sha512 = base64::encode(Sha512::digest(range)).into();
+                    }
+                }
+            }
+
+            list.push(object);
+        }
+
+        Ok(GetBlobResponse {
+            account_id: request.account_id,
+            list,
+            not_found,
+        })
+    }
+}
+
+pub trait JMAPBlobLookup<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn lookup_blob(&self, request: LookupBlobRequest) -> jmap::Result<LookupBlobResponse>;
+}
+
+impl<T> JMAPBlobLookup<T> for JMAPStore<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn lookup_blob(&self, request: LookupBlobRequest) -> jmap::Result<LookupBlobResponse> {
+        let acl = request.acl.unwrap();
+        let account_id = request.account_id.get_document_id();
+        let mut list = Vec::with_capacity(request.ids.len());
+        let mut not_found = Vec::new();
+
+        if let Some(type_name) = request
+            .type_names
+            .iter()
+            .find(|type_name| !["Email", "Mailbox", "Thread"].contains(&type_name.as_str()))
+        {
+            return Err(MethodError::UnknownDataType(format!(
+                "Type {} is not supported.",
+                type_name
+            )));
+        } else if request.ids.len() > self.config.max_objects_in_get {
+            return Err(MethodError::RequestTooLarge);
+        }
+
+        // Only shared messages are visible to non-members
+        let shared_ids = if !acl.is_member(account_id) && !acl.is_member(SUPERUSER_ID) {
+            self.mail_shared_messages(account_id, &acl.member_of, ACL::ReadItems)?
+                .as_ref()
+                .clone()
+                .unwrap_or_default()
+                .into()
+        } else {
+            None
+        };
+
+        for blob_id in request.ids {
+            if !self.mail_blob_has_access(account_id, &acl, &blob_id.id)? {
+                not_found.push(blob_id);
+                continue;
+            }
+
+            let mut document_ids =
+                self.blob_linked_documents(&blob_id.id, account_id, Collection::Mail)?;
+            if let Some(shared_ids) = &shared_ids {
+                document_ids &= shared_ids;
+            }
+            let thread_ids: Vec<Option<DocumentId>> = self.get_multi_document_value(
+                account_id,
+                Collection::Mail,
+                document_ids.iter(),
+                MessageField::ThreadId.into(),
+            )?;
+
+            let mut matched_ids = VecMap::with_capacity(request.type_names.len());
+            for type_name in &request.type_names {
+                let mut ids = Vec::new();
+                match type_name.as_str() {
+                    "Email" => {
+                        for (document_id, thread_id) in document_ids.iter().zip(&thread_ids) {
+                            if let Some(thread_id) = thread_id {
+                                ids.push(JMAPId::from_parts(*thread_id, document_id));
+                            }
+                        }
+                    }
+                    "Thread" => {
+                        for thread_id in thread_ids.iter().flatten() {
+                            let id = JMAPId::from(*thread_id);
+                            if !ids.contains(&id) {
+                                ids.push(id);
+                            }
+                        }
+                    }
+                    _ => {
+                        for document_id in &document_ids {
+                            if let Some(tags) = self
+                                .get_orm::<Email>(account_id, document_id)?
+                                .as_ref()
+                                .and_then(|fields| fields.get_tags(&Property::MailboxIds))
+                            {
+                                for tag in tags {
+                                    let id = JMAPId::from(tag.as_id());
+                                    if !ids.contains(&id) {
+                                        ids.push(id);
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+                matched_ids.append(type_name.clone(), ids);
+            }
+
+            list.push(BlobLookupObject {
+                id: blob_id,
+                matched_ids,
+            });
+        }
+
+        Ok(LookupBlobResponse {
+            account_id: request.account_id,
+            list,
+            not_found,
+        })
+    }
+}
@@ -28,7 +28,10 @@ use jmap::{
     principal::schema::Principal,
     push_subscription::schema::PushSubscription,
     request::{
-        blob::{CopyBlobRequest, CopyBlobResponse},
+        blob::{
+            CopyBlobRequest, CopyBlobResponse, GetBlobRequest, GetBlobResponse, LookupBlobRequest,
+            LookupBlobResponse, UploadBlobRequest, UploadBlobResponse,
+        },
         changes::{ChangesRequest, ChangesResponse},
         copy::{CopyRequest, CopyResponse},
         get::{GetRequest, GetResponse},
@@ -41,19 +44,24 @@ use jmap::{
     types::{json_pointer::JSONPointerEval, type_state::TypeState},
 };
 
+use jmap_calendars::{calendar::schema::Calendar, calendar_event::schema::CalendarEvent};
+use jmap_contacts::{address_book::schema::AddressBook, contact_card::schema::ContactCard};
 use jmap_mail::{
     email_submission::schema::EmailSubmission,
     identity::schema::Identity,
     mail::{
         import::{EmailImportRequest, EmailImportResponse},
+        itip::{EmailInvitationReplyRequest, EmailInvitationReplyResponse},
         parse::{EmailParseRequest, EmailParseResponse},
         schema::Email,
         search_snippet::{SearchSnippetGetRequest, SearchSnippetGetResponse},
     },
     mailbox::schema::Mailbox,
+    sieve_script::schema::SieveScript,
     thread::schema::Thread,
     vacation_response::schema::VacationResponse,
 };
+use jmap_sharing::quota::schema::Quota;
 use serde::{de::Visitor, ser::SerializeSeq, Deserialize, Serialize};
 use store::{ahash::AHashMap, log::changes::ChangeId, AccountId};
 
@@ -110,6 +118,7 @@ pub enum Request {
     CopyEmail(CopyRequest<Email>),
     ImportEmail(EmailImportRequest),
     ParseEmail(EmailParseRequest),
+    InvitationReplyEmail(EmailInvitationReplyRequest),
     GetSearchSnippet(SearchSnippetGetRequest),
 
     // Identity
@@ -133,8 +142,52 @@ pub enum Request {
     QueryPrincipal(QueryRequest<Principal>),
     SetPrincipal(SetRequest<Principal>),
 
+    // Quota
+    GetQuota(GetRequest<Quota>),
+    ChangesQuota(ChangesRequest),
+    QueryQuota(QueryRequest<Quota>),
+    QueryChangesQuota(QueryChangesRequest<Quota>),
+
+    // Sieve Script
+    GetSieveScript(GetRequest<SieveScript>),
+    ChangesSieveScript(ChangesRequest),
+    QuerySieveScript(QueryRequest<SieveScript>),
+    QueryChangesSieveScript(QueryChangesRequest<SieveScript>),
+    SetSieveScript(SetRequest<SieveScript>),
+
+    // Address Book
+    GetAddressBook(GetRequest<AddressBook>),
+    ChangesAddressBook(ChangesRequest),
+    QueryAddressBook(QueryRequest<AddressBook>),
+    QueryChangesAddressBook(QueryChangesRequest<AddressBook>),
+    SetAddressBook(SetRequest<AddressBook>),
+
+    // Contact Card
+    GetContactCard(GetRequest<ContactCard>),
+    ChangesContactCard(ChangesRequest),
+    QueryContactCard(QueryRequest<ContactCard>),
+    QueryChangesContactCard(QueryChangesRequest<ContactCard>),
+    SetContactCard(SetRequest<ContactCard>),
+
+    // Calendar
+    GetCalendar(GetRequest<Calendar>),
+    ChangesCalendar(ChangesRequest),
+    QueryCalendar(QueryRequest<Calendar>),
+    QueryChangesCalendar(QueryChangesRequest<Calendar>),
+    SetCalendar(SetRequest<Calendar>),
+
+    // Calendar Event
+    GetCalendarEvent(GetRequest<CalendarEvent>),
+    ChangesCalendarEvent(ChangesRequest),
+    QueryCalendarEvent(QueryRequest<CalendarEvent>),
+    QueryChangesCalendarEvent(QueryChangesRequest<CalendarEvent>),
+    SetCalendarEvent(SetRequest<CalendarEvent>),
+
     // Core methods
     CopyBlob(CopyBlobRequest),
+    UploadBlob(UploadBlobRequest),
+    GetBlob(GetBlobRequest),
+    LookupBlob(LookupBlobRequest),
     Echo(serde_json::Value),
     Error(MethodError),
 }
@@ -165,6 +218,7 @@ pub enum Response {
     CopyEmail(CopyResponse<Email>),
     ImportEmail(EmailImportResponse),
     ParseEmail(EmailParseResponse),
+    InvitationReplyEmail(EmailInvitationReplyResponse),
     GetSearchSnippet(SearchSnippetGetResponse),
 
     // Identity
@@ -188,8 +242,52 @@ pub enum Response {
     QueryPrincipal(QueryResponse),
     SetPrincipal(SetResponse<Principal>),
 
+    // Quota
+    GetQuota(GetResponse<Quota>),
+    ChangesQuota(ChangesResponse<Quota>),
+    QueryQuota(QueryResponse),
+    QueryChangesQuota(QueryChangesResponse),
+
+    // Sieve Script
+    GetSieveScript(GetResponse<SieveScript>),
+    ChangesSieveScript(ChangesResponse<SieveScript>),
+    QuerySieveScript(QueryResponse),
+    QueryChangesSieveScript(QueryChangesResponse),
+    SetSieveScript(SetResponse<SieveScript>),
+
+    // Address Book
+    GetAddressBook(GetResponse<AddressBook>),
+    ChangesAddressBook(ChangesResponse<AddressBook>),
+    QueryAddressBook(QueryResponse),
+    QueryChangesAddressBook(QueryChangesResponse),
+    SetAddressBook(SetResponse<AddressBook>),
+
+    // Contact Card
+    GetContactCard(GetResponse<ContactCard>),
+    ChangesContactCard(ChangesResponse<ContactCard>),
+    QueryContactCard(QueryResponse),
+    QueryChangesContactCard(QueryChangesResponse),
+    SetContactCard(SetResponse<ContactCard>),
+
+    // Calendar
+    GetCalendar(GetResponse<Calendar>),
+    ChangesCalendar(ChangesResponse<Calendar>),
+    QueryCalendar(QueryResponse),
+    QueryChangesCalendar(QueryChangesResponse),
+    SetCalendar(SetResponse<Calendar>),
+
+    // Calendar Event
+    GetCalendarEvent(GetResponse<CalendarEvent>),
+    ChangesCalendarEvent(ChangesResponse<CalendarEvent>),
+    QueryCalendarEvent(QueryResponse),
+    QueryChangesCalendarEvent(QueryChangesResponse),
+    SetCalendarEvent(SetResponse<CalendarEvent>),
+
     // Core methods
     CopyBlob(CopyBlobResponse),
+    UploadBlob(UploadBlobResponse),
+    GetBlob(GetBlobResponse),
+    LookupBlob(LookupBlobResponse),
     Echo(serde_json::Value),
     Error(MethodError),
 }
@@ -219,6 +317,32 @@ impl Request {
             | Request::GetVacationResponse(_)
             | Request::GetPrincipal(_)
             | Request::QueryPrincipal(_)
+            | Request::GetQuota(_)
+            | Request::ChangesQuota(_)
+            | Request::QueryQuota(_)
+            | Request::QueryChangesQuota(_)
+            | Request::GetSieveScript(_)
+            | Request::ChangesSieveScript(_)
+            | Request::QuerySieveScript(_)
+            | Request::QueryChangesSieveScript(_)
+            | Request::GetAddressBook(_)
+            | Request::ChangesAddressBook(_)
+            | Request::QueryAddressBook(_)
+            | Request::QueryChangesAddressBook(_)
+            | Request::GetContactCard(_)
+            | Request::ChangesContactCard(_)
+            | Request::QueryContactCard(_)
+            | Request::QueryChangesContactCard(_)
+            | Request::GetCalendar(_)
+            | Request::ChangesCalendar(_)
+            | Request::QueryCalendar(_)
+            | Request::QueryChangesCalendar(_)
+            | Request::GetCalendarEvent(_)
+            | Request::ChangesCalendarEvent(_)
+            | Request::QueryCalendarEvent(_)
+            | Request::QueryChangesCalendarEvent(_)
+            | Request::GetBlob(_)
+            | Request::LookupBlob(_)
             | Request::Echo(_)
             | Request::Error(_) => true,
 
@@ -227,11 +351,18 @@ impl Request {
             | Request::SetEmail(_)
             | Request::CopyEmail(_)
             | Request::ImportEmail(_)
+            | Request::InvitationReplyEmail(_)
             | Request::SetIdentity(_)
             | Request::SetEmailSubmission(_)
             | Request::SetVacationResponse(_)
             | Request::SetPrincipal(_)
-            | Request::CopyBlob(_) => false,
+            | Request::SetSieveScript(_)
+            | Request::SetAddressBook(_)
+            | Request::SetContactCard(_)
+            | Request::SetCalendar(_)
+            | Request::SetCalendarEvent(_)
+            | Request::CopyBlob(_)
+            | Request::UploadBlob(_) => false,
         }
     }
 
@@ -304,6 +435,96 @@ impl Request {
                         (Method::QueryPrincipal, Response::QueryPrincipal(response)) => {
                             return response.eval_json_pointer(&rr.path);
                         }
+                        (Method::GetQuota, Response::GetQuota(response)) => {
+                            return response.eval_json_pointer(&rr.path);
+                        }
+                        (Method::ChangesQuota, Response::ChangesQuota(response)) => {
+                            return response.eval_json_pointer(&rr.path);
+                        }
+                        (Method::QueryQuota, Response::QueryQuota(response)) => {
+                            return response.eval_json_pointer(&rr.path);
+                        }
+                        (Method::QueryChangesQuota, Response::QueryChangesQuota(response)) => {
+                            return response.eval_json_pointer(&rr.path);
+                        }
+                        (Method::GetSieveScript, Response::GetSieveScript(response)) => {
+                            return response.eval_json_pointer(&rr.path);
+                        }
+                        (Method::ChangesSieveScript, Response::ChangesSieveScript(response)) => {
+                            return response.eval_json_pointer(&rr.path);
+                        }
+                        (Method::QuerySieveScript, Response::QuerySieveScript(response)) => {
+                            return response.eval_json_pointer(&rr.path);
+                        }
+                        (
+                            Method::QueryChangesSieveScript,
+                            Response::QueryChangesSieveScript(response),
+                        ) => {
+                            return response.eval_json_pointer(&rr.path);
+                        }
+                        (Method::GetAddressBook, Response::GetAddressBook(response)) => {
+                            return response.eval_json_pointer(&rr.path);
+                        }
+                        (Method::ChangesAddressBook, Response::ChangesAddressBook(response)) => {
+                            return response.eval_json_pointer(&rr.path);
+                        }
+                        (Method::QueryAddressBook, Response::QueryAddressBook(response)) => {
+                            return response.eval_json_pointer(&rr.path);
+                        }
+                        (
+                            Method::QueryChangesAddressBook,
+                            Response::QueryChangesAddressBook(response),
+                        ) => {
+                            return response.eval_json_pointer(&rr.path);
+                        }
+                        (Method::GetContactCard, Response::GetContactCard(response)) => {
+                            return response.eval_json_pointer(&rr.path);
+                        }
+                        (Method::ChangesContactCard, Response::ChangesContactCard(response)) => {
+                            return response.eval_json_pointer(&rr.path);
+                        }
+                        (Method::QueryContactCard, Response::QueryContactCard(response)) => {
+                            return response.eval_json_pointer(&rr.path);
+                        }
+                        (
+                            Method::QueryChangesContactCard,
+                            Response::QueryChangesContactCard(response),
+                        ) => {
+                            return response.eval_json_pointer(&rr.path);
+                        }
+                        (Method::GetCalendar, Response::GetCalendar(response)) => {
+                            return response.eval_json_pointer(&rr.path);
+                        }
+                        (Method::ChangesCalendar, Response::ChangesCalendar(response)) => {
+                            return response.eval_json_pointer(&rr.path);
+                        }
+                        (Method::QueryCalendar, Response::QueryCalendar(response)) => {
+                            return response.eval_json_pointer(&rr.path);
+                        }
+                        (
+                            Method::QueryChangesCalendar,
+                            Response::QueryChangesCalendar(response),
+                        ) => {
+                            return response.eval_json_pointer(&rr.path);
+                        }
+                        (Method::GetCalendarEvent, Response::GetCalendarEvent(response)) => {
+                            return response.eval_json_pointer(&rr.path);
+                        }
+                        (
+                            Method::ChangesCalendarEvent,
+                            Response::ChangesCalendarEvent(response),
+                        ) => {
+                            return response.eval_json_pointer(&rr.path);
+                        }
+                        (Method::QueryCalendarEvent, Response::QueryCalendarEvent(response)) => {
+                            return response.eval_json_pointer(&rr.path);
+                        }
+                        (
+                            Method::QueryChangesCalendarEvent,
+                            Response::QueryChangesCalendarEvent(response),
+                        ) => {
+                            return response.eval_json_pointer(&rr.path);
+                        }
                         _ => {
                             break;
                         }
@@ -357,6 +578,39 @@ impl Request {
             Request::SetPrincipal(request) => {
                 request.eval_references(&mut eval_result_ref, &response.created_ids)?;
             }
+            Request::GetQuota(request) => {
+                request.eval_result_references(&mut eval_result_ref)?;
+            }
+            Request::GetSieveScript(request) => {
+                request.eval_result_references(&mut eval_result_ref)?;
+            }
+            Request::SetSieveScript(request) => {
+                request.eval_references(&mut eval_result_ref, &response.created_ids)?;
+            }
+            Request::GetAddressBook(request) => {
+                request.eval_result_references(&mut eval_result_ref)?;
+            }
+            Request::SetAddressBook(request) => {
+                request.eval_references(&mut eval_result_ref, &response.created_ids)?;
+            }
+            Request::GetContactCard(request) => {
+                request.eval_result_references(&mut eval_result_ref)?;
+            }
+            Request::SetContactCard(request) => {
+                request.eval_references(&mut eval_result_ref, &response.created_ids)?;
+            }
+            Request::GetCalendar(request) => {
+                request.eval_result_references(&mut eval_result_ref)?;
+            }
+            Request::SetCalendar(request) => {
+                request.eval_references(&mut eval_result_ref, &response.created_ids)?;
+            }
+            Request::GetCalendarEvent(request) => {
+                request.eval_result_references(&mut eval_result_ref)?;
+            }
+            Request::SetCalendarEvent(request) => {
+                request.eval_references(&mut eval_result_ref, &response.created_ids)?;
+            }
             _ => (),
         }
         Ok(())
@@ -419,6 +673,7 @@ impl Response {
                                 (TypeState::Email, change_id),
                                 (TypeState::Mailbox, change_id),
                                 (TypeState::Thread, change_id),
+                                (TypeState::Quota, change_id),
                             ],
                         )
                         .into(),
@@ -486,6 +741,76 @@ impl Response {
                 response.account_id = None;
                 Changes::None
             }
+            Response::SetSieveScript(response) => {
+                if let Some(change_id) = response.has_changes() {
+                    Changes::Item {
+                        created_ids: response.created_ids(),
+                        change_id,
+                        state_change: response
+                            .state_changes()
+                            .map(|s| StateChange::new(response.account_id(), s)),
+                        next_call: None,
+                    }
+                } else {
+                    Changes::None
+                }
+            }
+            Response::SetAddressBook(response) => {
+                if let Some(change_id) = response.has_changes() {
+                    Changes::Item {
+                        created_ids: response.created_ids(),
+                        change_id,
+                        state_change: response
+                            .state_changes()
+                            .map(|s| StateChange::new(response.account_id(), s)),
+                        next_call: None,
+                    }
+                } else {
+                    Changes::None
+                }
+            }
+            Response::SetContactCard(response) => {
+                if let Some(change_id) = response.has_changes() {
+                    Changes::Item {
+                        created_ids: response.created_ids(),
+                        change_id,
+                        state_change: response
+                            .state_changes()
+                            .map(|s| StateChange::new(response.account_id(), s)),
+                        next_call: None,
+                    }
+                } else {
+                    Changes::None
+                }
+            }
+            Response::SetCalendar(response) => {
+                if let Some(change_id) = response.has_changes() {
+                    Changes::Item {
+                        created_ids: response.created_ids(),
+                        change_id,
+                        state_change: response
+                            .state_changes()
+                            .map(|s| StateChange::new(response.account_id(), s)),
+                        next_call: None,
+                    }
+                } else {
+                    Changes::None
+                }
+            }
+            Response::SetCalendarEvent(response) => {
+                if let Some(change_id) = response.has_changes() {
+                    Changes::Item {
+                        created_ids: response.created_ids(),
+                        change_id,
+                        state_change: response
+                            .state_changes()
+                            .map(|s| StateChange::new(response.account_id(), s)),
+                        next_call: None,
+                    }
+                } else {
+                    Changes::None
+                }
+            }
             Response::SetPrincipal(response) => {
                 if let Some(change_id) = response.has_changes() {
                     Changes::Item {
@@ -509,6 +834,7 @@ impl Response {
             | Response::QueryEmail(_)
             | Response::QueryChangesEmail(_)
             | Response::ParseEmail(_)
+            | Response::InvitationReplyEmail(_)
             | Response::GetSearchSnippet(_)
             | Response::GetIdentity(_)
             | Response::ChangesIdentity(_)
@@ -519,7 +845,34 @@ impl Response {
             | Response::GetVacationResponse(_)
             | Response::GetPrincipal(_)
             | Response::QueryPrincipal(_)
+            | Response::GetQuota(_)
+            | Response::ChangesQuota(_)
+            | Response::QueryQuota(_)
+            | Response::QueryChangesQuota(_)
+            | Response::GetSieveScript(_)
+            | Response::ChangesSieveScript(_)
+            | Response::QuerySieveScript(_)
+            | Response::QueryChangesSieveScript(_)
+            | Response::GetAddressBook(_)
+            | Response::ChangesAddressBook(_)
+            | Response::QueryAddressBook(_)
+            | Response::QueryChangesAddressBook(_)
+            | Response::GetContactCard(_)
+            | Response::ChangesContactCard(_)
+            | Response::QueryContactCard(_)
+            | Response::QueryChangesContactCard(_)
+            | Response::GetCalendar(_)
+            | Response::ChangesCalendar(_)
+            | Response::QueryCalendar(_)
+            | Response::QueryChangesCalendar(_)
+            | Response::GetCalendarEvent(_)
+            | Response::ChangesCalendarEvent(_)
+            | Response::QueryCalendarEvent(_)
+            | Response::QueryChangesCalendarEvent(_)
             | Response::CopyBlob(_)
+            | Response::UploadBlob(_)
+            | Response::GetBlob(_)
+            | Response::LookupBlob(_)
             | Response::Echo(_)
             | Response::Error(_) => Changes::None,
         }
@@ -623,6 +976,11 @@ where
                 .map_err(|err| MatchError::Parse(err.to_string()))?
                 .ok_or(MatchError::Eof)?,
         ),
+        "Email/invitationReply" => Request::InvitationReplyEmail(
+            seq.next_element()
+                .map_err(|err| MatchError::Parse(err.to_string()))?
+                .ok_or(MatchError::Eof)?,
+        ),
         "Mailbox/get" => Request::GetMailbox(
             seq.next_element()
                 .map_err(|err| MatchError::Parse(err.to_string()))?
@@ -738,11 +1096,171 @@ where
                 .map_err(|err| MatchError::Parse(err.to_string()))?
                 .ok_or(MatchError::Eof)?,
         ),
+        "Quota/get" => Request::GetQuota(
+            seq.next_element()
+                .map_err(|err| MatchError::Parse(err.to_string()))?
+                .ok_or(MatchError::Eof)?,
+        ),
+        "Quota/changes" => Request::ChangesQuota(
+            seq.next_element()
+                .map_err(|err| MatchError::Parse(err.to_string()))?
+                .ok_or(MatchError::Eof)?,
+        ),
+        "Quota/query" => Request::QueryQuota(
+            seq.next_element()
+                .map_err(|err| MatchError::Parse(err.to_string()))?
+                .ok_or(MatchError::Eof)?,
+        ),
+        "Quota/queryChanges" => Request::QueryChangesQuota(
+            seq.next_element()
+                .map_err(|err| MatchError::Parse(err.to_string()))?
+                .ok_or(MatchError::Eof)?,
+        ),
+        "SieveScript/get" => Request::GetSieveScript(
+            seq.next_element()
+                .map_err(|err| MatchError::Parse(err.to_string()))?
+                .ok_or(MatchError::Eof)?,
+        ),
+        "SieveScript/changes" => Request::ChangesSieveScript(
+            seq.next_element()
+                .map_err(|err| MatchError::Parse(err.to_string()))?
+                .ok_or(MatchError::Eof)?,
+        ),
+        "SieveScript/query" => Request::QuerySieveScript(
+            seq.next_element()
+                .map_err(|err| MatchError::Parse(err.to_string()))?
+                .ok_or(MatchError::Eof)?,
+        ),
+        "SieveScript/queryChanges" => Request::QueryChangesSieveScript(
+            seq.next_element()
+                .map_err(|err| MatchError::Parse(err.to_string()))?
+                .ok_or(MatchError::Eof)?,
+        ),
+        "SieveScript/set" => Request::SetSieveScript(
+            seq.next_element()
+                .map_err(|err| MatchError::Parse(err.to_string()))?
+                .ok_or(MatchError::Eof)?,
+        ),
+        "AddressBook/get" => Request::GetAddressBook(
+            seq.next_element()
+                .map_err(|err| MatchError::Parse(err.to_string()))?
+                .ok_or(MatchError::Eof)?,
+        ),
+        "AddressBook/changes" => Request::ChangesAddressBook(
+            seq.next_element()
+                .map_err(|err| MatchError::Parse(err.to_string()))?
+                .ok_or(MatchError::Eof)?,
+        ),
+        "AddressBook/query" => Request::QueryAddressBook(
+            seq.next_element()
+                .map_err(|err| MatchError::Parse(err.to_string()))?
+                .ok_or(MatchError::Eof)?,
+        ),
+        "AddressBook/queryChanges" => Request::QueryChangesAddressBook(
+            seq.next_element()
+                .map_err(|err| MatchError::Parse(err.to_string()))?
+                .ok_or(MatchError::Eof)?,
+        ),
+        "AddressBook/set" => Request::SetAddressBook(
+            seq.next_element()
+                .map_err(|err| MatchError::Parse(err.to_string()))?
+                .ok_or(MatchError::Eof)?,
+        ),
+        "ContactCard/get" => Request::GetContactCard(
+            seq.next_element()
+                .map_err(|err| MatchError::Parse(err.to_string()))?
+                .ok_or(MatchError::Eof)?,
+        ),
+        "ContactCard/changes" => Request::ChangesContactCard(
+            seq.next_element()
+                .map_err(|err| MatchError::Parse(err.to_string()))?
+                .ok_or(MatchError::Eof)?,
+        ),
+        "ContactCard/query" => Request::QueryContactCard(
+            seq.next_element()
+                .map_err(|err| MatchError::Parse(err.to_string()))?
+                .ok_or(MatchError::Eof)?,
+        ),
+        "ContactCard/queryChanges" => Request::QueryChangesContactCard(
+            seq.next_element()
+                .map_err(|err| MatchError::Parse(err.to_string()))?
+                .ok_or(MatchError::Eof)?,
+        ),
+        "ContactCard/set" => Request::SetContactCard(
+            seq.next_element()
+                .map_err(|err| MatchError::Parse(err.to_string()))?
+                .ok_or(MatchError::Eof)?,
+        ),
+        "Calendar/get" => Request::GetCalendar(
+            seq.next_element()
+                .map_err(|err| MatchError::Parse(err.to_string()))?
+                .ok_or(MatchError::Eof)?,
+        ),
+        "Calendar/changes" => Request::ChangesCalendar(
+            seq.next_element()
+                .map_err(|err| MatchError::Parse(err.to_string()))?
+                .ok_or(MatchError::Eof)?,
+        ),
+        "Calendar/query" => Request::QueryCalendar(
+            seq.next_element()
+                .map_err(|err| MatchError::Parse(err.to_string()))?
+                .ok_or(MatchError::Eof)?,
+        ),
+        "Calendar/queryChanges" => Request::QueryChangesCalendar(
+            seq.next_element()
+                .map_err(|err| MatchError::Parse(err.to_string()))?
+                .ok_or(MatchError::Eof)?,
+        ),
+        "Calendar/set" => Request::SetCalendar(
+            seq.next_element()
+                .map_err(|err| MatchError::Parse(err.to_string()))?
+                .ok_or(MatchError::Eof)?,
+        ),
+        "CalendarEvent/get" => Request::GetCalendarEvent(
+            seq.next_element()
+                .map_err(|err| MatchError::Parse(err.to_string()))?
+                .ok_or(MatchError::Eof)?,
+        ),
+        "CalendarEvent/changes" => Request::ChangesCalendarEvent(
+            seq.next_element()
+                .map_err(|err| MatchError::Parse(err.to_string()))?
+                .ok_or(MatchError::Eof)?,
+        ),
+        "CalendarEvent/query" => Request::QueryCalendarEvent(
+            seq.next_element()
+                .map_err(|err| MatchError::Parse(err.to_string()))?
+                .ok_or(MatchError::Eof)?,
+        ),
+        "CalendarEvent/queryChanges" => Request::QueryChangesCalendarEvent(
+            seq.next_element()
+                .map_err(|err| MatchError::Parse(err.to_string()))?
+                .ok_or(MatchError::Eof)?,
+        ),
+        "CalendarEvent/set" => Request::SetCalendarEvent(
+            seq.next_element()
+                .map_err(|err| MatchError::Parse(err.to_string()))?
+                .ok_or(MatchError::Eof)?,
+        ),
         "Blob/copy" => Request::CopyBlob(
             seq.next_element()
                 .map_err(|err| MatchError::Parse(err.to_string()))?
                 .ok_or(MatchError::Eof)?,
         ),
+        "Blob/upload" => Request::UploadBlob(
+            seq.next_element()
+                .map_err(|err| MatchError::Parse(err.to_string()))?
+                .ok_or(MatchError::Eof)?,
+        ),
+        "Blob/get" => Request::GetBlob(
+            seq.next_element()
+                .map_err(|err| MatchError::Parse(err.to_string()))?
+                .ok_or(MatchError::Eof)?,
+        ),
+        "Blob/lookup" => Request::LookupBlob(
+            seq.next_element()
+                .map_err(|err| MatchError::Parse(err.to_string()))?
+                .ok_or(MatchError::Eof)?,
+        ),
         "Core/echo" => Request::Echo(
             seq.next_element()
                 .map_err(|err| MatchError::Parse(err.to_string()))?
@@ -833,6 +1351,10 @@ impl Serialize for Call<Response> {
                 seq.serialize_element("Email/parse")?;
                 seq.serialize_element(response)?;
             }
+            Response::InvitationReplyEmail(response) => {
+                seq.serialize_element("Email/invitationReply")?;
+                seq.serialize_element(response)?;
+            }
             Response::GetSearchSnippet(response) => {
                 seq.serialize_element("SearchSnippet/get")?;
                 seq.serialize_element(response)?;
@@ -889,10 +1411,138 @@ impl Serialize for Call<Response> {
                 seq.serialize_element("Principal/set")?;
                 seq.serialize_element(response)?;
             }
+            Response::GetQuota(response) => {
+                seq.serialize_element("Quota/get")?;
+                seq.serialize_element(response)?;
+            }
+            Response::ChangesQuota(response) => {
+                seq.serialize_element("Quota/changes")?;
+                seq.serialize_element(response)?;
+            }
+            Response::QueryQuota(response) => {
+                seq.serialize_element("Quota/query")?;
+                seq.serialize_element(response)?;
+            }
+            Response::QueryChangesQuota(response) => {
+                seq.serialize_element("Quota/queryChanges")?;
+                seq.serialize_element(response)?;
+            }
+            Response::GetSieveScript(response) => {
+                seq.serialize_element("SieveScript/get")?;
+                seq.serialize_element(response)?;
+            }
+            Response::ChangesSieveScript(response) => {
+                seq.serialize_element("SieveScript/changes")?;
+                seq.serialize_element(response)?;
+            }
+            Response::QuerySieveScript(response) => {
+                seq.serialize_element("SieveScript/query")?;
+                seq.serialize_element(response)?;
+            }
+            Response::QueryChangesSieveScript(response) => {
+                seq.serialize_element("SieveScript/queryChanges")?;
+                seq.serialize_element(response)?;
+            }
+            Response::SetSieveScript(response) => {
+                seq.serialize_element("SieveScript/set")?;
+                seq.serialize_element(response)?;
+            }
+            Response::GetAddressBook(response) => {
+                seq.serialize_element("AddressBook/get")?;
+                seq.serialize_element(response)?;
+            }
+            Response::ChangesAddressBook(response) => {
+                seq.serialize_element("AddressBook/changes")?;
+                seq.serialize_element(response)?;
+            }
+            Response::QueryAddressBook(response) => {
+                seq.serialize_element("AddressBook/query")?;
+                seq.serialize_element(response)?;
+            }
+            Response::QueryChangesAddressBook(response) => {
+                seq.serialize_element("AddressBook/queryChanges")?;
+                seq.serialize_element(response)?;
+            }
+            Response::SetAddressBook(response) => {
+                seq.serialize_element("AddressBook/set")?;
+                seq.serialize_element(response)?;
+            }
+            Response::GetContactCard(response) => {
+                seq.serialize_element("ContactCard/get")?;
+                seq.serialize_element(response)?;
+            }
+            Response::ChangesContactCard(response) => {
+                seq.serialize_element("ContactCard/changes")?;
+                seq.serialize_element(response)?;
+            }
+            Response::QueryContactCard(response) => {
+                seq.serialize_element("ContactCard/query")?;
+                seq.serialize_element(response)?;
+            }
+            Response::QueryChangesContactCard(response) => {
+                seq.serialize_element("ContactCard/queryChanges")?;
+                seq.serialize_element(response)?;
+            }
+            Response::SetContactCard(response) => {
+                seq.serialize_element("ContactCard/set")?;
+                seq.serialize_element(response)?;
+            }
+            Response::GetCalendar(response) => {
+                seq.serialize_element("Calendar/get")?;
+                seq.serialize_element(response)?;
+            }
+            Response::ChangesCalendar(response) => {
+                seq.serialize_element("Calendar/changes")?;
+                seq.serialize_element(response)?;
+            }
+            Response::QueryCalendar(response) => {
+                seq.serialize_element("Calendar/query")?;
+                seq.serialize_element(response)?;
+            }
+            Response::QueryChangesCalendar(response) => {
+                seq.serialize_element("Calendar/queryChanges")?;
+                seq.serialize_element(response)?;
+            }
+            Response::SetCalendar(response) => {
+                seq.serialize_element("Calendar/set")?;
+                seq.serialize_element(response)?;
+            }
+            Response::GetCalendarEvent(response) => {
+                seq.serialize_element("CalendarEvent/get")?;
+                seq.serialize_element(response)?;
+            }
+            Response::ChangesCalendarEvent(response) => {
+                seq.serialize_element("CalendarEvent/changes")?;
+                seq.serialize_element(response)?;
+            }
+            Response::QueryCalendarEvent(response) => {
+                seq.serialize_element("CalendarEvent/query")?;
+                seq.serialize_element(response)?;
+            }
+            Response::QueryChangesCalendarEvent(response) => {
+                seq.serialize_element("CalendarEvent/queryChanges")?;
+                seq.serialize_element(response)?;
+            }
+            Response::SetCalendarEvent(response) => {
+                seq.serialize_element("CalendarEvent/set")?;
+                seq.serialize_element(response)?;
+            }
             Response::CopyBlob(response) => {
                 seq.serialize_element("Blob/copy")?;
                 seq.serialize_element(response)?;
             }
+            Response::UploadBlob(response) => {
+                seq.serialize_element("Blob/upload")?;
+                seq.serialize_element(response)?;
+            }
+            Response::GetBlob(response) => {
+                seq.serialize_element("Blob/get")?;
+                seq.serialize_element(response)?;
+            }
+            Response::LookupBlob(response) => {
+                seq.serialize_element("Blob/lookup")?;
+                seq.serialize_element(response)?;
+            }
             Response::Echo(response) => {
                 seq.serialize_element("Core/echo")?;
                 seq.serialize_element(response)?;
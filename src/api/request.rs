@@ -26,11 +26,13 @@ use actix_web::{
     web, HttpResponse, ResponseError,
 };
 use jmap::types::jmap::JMAPId;
-use store::{ahash::AHashMap, tracing::debug, Store};
+use jmap_sharing::principal::account::JMAPAccountStore;
+use store::{ahash::AHashMap, blob::BlobId, tracing::debug, AccountId, Store};
 
 use crate::{
     api::{invocation::handle_method_calls, Redirect, RequestError, RequestLimitError},
     authorization::Session,
+    cluster::rpc::command::{Command, CommandResponse},
     JMAPServer,
 };
 
@@ -109,3 +111,152 @@ where
         Err(RequestError::limit(RequestLimitError::Size))
     }
 }
+
+pub async fn handle_internal_request<T>(
+    core: web::Data<JMAPServer<T>>,
+    account_id: AccountId,
+    request: Vec<u8>,
+    blobs: Vec<Vec<u8>>,
+) -> Result<Vec<u8>, String>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    let request = serde_json::from_slice::<Request>(&request)
+        .map_err(|err| format!("Failed to parse request: {}", err))?;
+
+    // Store any blobs referenced by the request and build the session
+    let store = core.store.clone();
+    let session = core
+        .spawn_worker(move || {
+            for blob in blobs {
+                let blob_id = BlobId::new_external(&blob);
+                store.blob_store(&blob_id, blob)?;
+                store.blob_link_ephemeral(&blob_id, account_id)?;
+            }
+            Ok(Session::new(
+                account_id,
+                store.get_acl_token(account_id)?.as_ref(),
+            ))
+        })
+        .await
+        .map_err(|err| format!("Failed to prepare request: {}", err))?;
+
+    serde_json::to_vec(&handle_method_calls(request, core, session).await)
+        .map_err(|err| format!("Failed to serialize response: {}", err))
+}
+
+/// Executes a JMAP request on behalf of an account from a non-HTTP service. Write
+/// requests are forwarded to the leader, reads are executed locally as long as
+/// this node is up to date.
+pub async fn route_internal_request<T>(
+    core: &web::Data<JMAPServer<T>>,
+    account_id: AccountId,
+    request: serde_json::Value,
+    blobs: Vec<Vec<u8>>,
+) -> Result<serde_json::Value, String>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    let is_read_only = blobs.is_empty()
+        && request
+            .get("methodCalls")
+            .and_then(|calls| calls.as_array())
+            .map_or(false, |calls| {
+                calls.iter().all(|call| {
+                    call.get(0)
+                        .and_then(|method| method.as_str())
+                        .map_or(false, |method| {
+                            method.ends_with("/get")
+                                || method.ends_with("/query")
+                                || method.ends_with("/changes")
+                        })
+                })
+            });
+    let request = serde_json::to_vec(&request).map_err(|err| err.to_string())?;
+
+    let response = if core.is_leader() || (is_read_only && core.is_up_to_date()) {
+        handle_internal_request(core.clone(), account_id, request, blobs).await?
+    } else {
+        match core
+            .rpc_command(Command::JMAPRequest {
+                account_id,
+                request,
+                blobs,
+            })
+            .await
+        {
+            Some(CommandResponse::JMAPRequest { result }) => result?,
+            Some(CommandResponse::Error { message }) => return Err(message),
+            _ => return Err("No leader available".to_string()),
+        }
+    };
+
+    serde_json::from_slice(&response).map_err(|err| err.to_string())
+}
+
+#[derive(Debug)]
+pub enum InternalCallError {
+    Unavailable(String),
+    Method(String),
+}
+
+/// Executes a list of JMAP method calls on behalf of an account and returns the
+/// arguments of each response.
+pub async fn execute_internal_calls<T>(
+    core: &web::Data<JMAPServer<T>>,
+    account_id: AccountId,
+    calls: Vec<(&str, serde_json::Value)>,
+    blobs: Vec<Vec<u8>>,
+) -> Result<Vec<serde_json::Value>, InternalCallError>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    let request = serde_json::json!({
+        "using": [
+            "urn:ietf:params:jmap:core",
+            "urn:ietf:params:jmap:mail",
+            "urn:ietf:params:jmap:submission"
+        ],
+        "methodCalls": calls
+            .into_iter()
+            .enumerate()
+            .map(|(pos, (method, args))| serde_json::json!([method, args, pos.to_string()]))
+            .collect::<Vec<_>>(),
+    });
+    let response = route_internal_request(core, account_id, request, blobs)
+        .await
+        .map_err(InternalCallError::Unavailable)?;
+
+    let mut results = Vec::new();
+    if let Some(serde_json::Value::Array(responses)) = response.get("methodResponses") {
+        for response in responses {
+            match (response.get(0).and_then(|v| v.as_str()), response.get(1)) {
+                (Some("error"), Some(args)) => {
+                    return Err(InternalCallError::Method(
+                        args.get("description")
+                            .or_else(|| args.get("type"))
+                            .and_then(|v| v.as_str())
+                            .unwrap_or("Request failed.")
+                            .to_string(),
+                    ));
+                }
+                (Some(_), Some(args)) => {
+                    results.push(args.clone());
+                }
+                _ => (),
+            }
+        }
+    }
+
+    Ok(results)
+}
+
+impl std::fmt::Display for InternalCallError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InternalCallError::Unavailable(message) | InternalCallError::Method(message) => {
+                f.write_str(message)
+            }
+        }
+    }
+}
@@ -29,7 +29,7 @@ use actix_web::{
     web, HttpResponse,
 };
 use jmap::{principal::schema::Type, request::ACLEnforce, types::jmap::JMAPId, URI};
-use jmap_mail::mail::sharing::JMAPShareMail;
+use jmap_mail::{mail::sharing::JMAPShareMail, sieve_script::compiler::SIEVE_EXTENSIONS};
 use jmap_sharing::principal::account::JMAPAccountStore;
 use store::{
     config::{env_settings::EnvSettings, jmap::JMAPConfig},
@@ -87,6 +87,11 @@ enum Capabilities {
     Submission(SubmissionCapabilities),
     VacationResponse(VacationResponseCapabilities),
     WebSocket(WebSocketCapabilities),
+    Quota(QuotaCapabilities),
+    Sieve(SieveCapabilities),
+    Contacts(ContactsCapabilities),
+    Calendars(CalendarsCapabilities),
+    Blob(BlobCapabilities),
 }
 
 #[derive(Debug, Clone, serde::Serialize)]
@@ -144,6 +149,57 @@ struct SubmissionCapabilities {
 #[derive(Debug, Clone, serde::Serialize)]
 struct VacationResponseCapabilities {}
 
+#[derive(Debug, Clone, serde::Serialize)]
+struct QuotaCapabilities {}
+
+#[derive(Debug, Clone, serde::Serialize)]
+struct SieveCapabilities {
+    #[serde(rename(serialize = "implementation"))]
+    implementation: &'static str,
+    #[serde(rename(serialize = "maxSizeScriptName"))]
+    max_size_script_name: usize,
+    #[serde(rename(serialize = "maxSizeScript"))]
+    max_size_script: usize,
+    #[serde(rename(serialize = "maxNumberScripts"))]
+    max_number_scripts: usize,
+    #[serde(rename(serialize = "maxNumberRedirects"))]
+    max_number_redirects: usize,
+    #[serde(rename(serialize = "sieveExtensions"))]
+    sieve_extensions: Vec<String>,
+    #[serde(rename(serialize = "notificationMethods"))]
+    notification_methods: Option<Vec<String>>,
+    #[serde(rename(serialize = "externalLists"))]
+    external_lists: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+struct ContactsCapabilities {
+    #[serde(rename(serialize = "maxAddressBooksPerCard"))]
+    max_address_books_per_card: Option<usize>,
+    #[serde(rename(serialize = "mayCreateAddressBook"))]
+    may_create_address_book: bool,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+struct CalendarsCapabilities {
+    #[serde(rename(serialize = "maxCalendarsPerEvent"))]
+    max_calendars_per_event: Option<usize>,
+    #[serde(rename(serialize = "mayCreateCalendar"))]
+    may_create_calendar: bool,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+struct BlobCapabilities {
+    #[serde(rename(serialize = "maxSizeBlobSet"))]
+    max_size_blob_set: usize,
+    #[serde(rename(serialize = "maxDataSources"))]
+    max_data_sources: usize,
+    #[serde(rename(serialize = "supportedTypeNames"))]
+    supported_type_names: Vec<String>,
+    #[serde(rename(serialize = "supportedDigestAlgorithms"))]
+    supported_digest_algorithms: Vec<String>,
+}
+
 impl Session {
     pub fn new(settings: &EnvSettings, config: &JMAPConfig) -> Session {
         let base_url = settings.get("jmap-url").unwrap();
@@ -152,10 +208,30 @@ impl Session {
             capabilities: VecMap::from_iter([
                 (URI::Core, Capabilities::Core(CoreCapabilities::new(config))),
                 (URI::Mail, Capabilities::Mail(MailCapabilities::new(config))),
+                (URI::Quota, Capabilities::Quota(QuotaCapabilities {})),
+                (
+                    URI::Sieve,
+                    Capabilities::Sieve(SieveCapabilities::new(config)),
+                ),
+                (
+                    URI::Contacts,
+                    Capabilities::Contacts(ContactsCapabilities {
+                        max_address_books_per_card: None,
+                        may_create_address_book: true,
+                    }),
+                ),
+                (
+                    URI::Calendars,
+                    Capabilities::Calendars(CalendarsCapabilities {
+                        max_calendars_per_event: None,
+                        may_create_calendar: true,
+                    }),
+                ),
                 (
                     URI::WebSocket,
                     Capabilities::WebSocket(WebSocketCapabilities::new(&base_url)),
                 ),
+                (URI::Blob, Capabilities::Blob(BlobCapabilities::new(config))),
             ]),
             accounts: VecMap::new(),
             primary_accounts: VecMap::new(),
@@ -285,6 +361,38 @@ impl WebSocketCapabilities {
     }
 }
 
+impl SieveCapabilities {
+    pub fn new(config: &JMAPConfig) -> Self {
+        SieveCapabilities {
+            implementation: concat!("Stalwart JMAP v", env!("CARGO_PKG_VERSION")),
+            max_size_script_name: config.sieve_max_script_name_len,
+            max_size_script: config.sieve_max_script_size,
+            max_number_scripts: config.sieve_max_scripts,
+            max_number_redirects: config.sieve_max_redirects,
+            sieve_extensions: SIEVE_EXTENSIONS.iter().map(|s| s.to_string()).collect(),
+            notification_methods: None,
+            external_lists: None,
+        }
+    }
+}
+
+impl BlobCapabilities {
+    pub fn new(config: &JMAPConfig) -> Self {
+        BlobCapabilities {
+            max_size_blob_set: config.max_size_upload,
+            max_data_sources: config.blob_max_data_sources,
+            supported_type_names: ["Email", "Mailbox", "Thread"]
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+            supported_digest_algorithms: ["sha-256", "sha-512"]
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+        }
+    }
+}
+
 impl MailCapabilities {
     pub fn new(config: &JMAPConfig) -> Self {
         MailCapabilities {
@@ -344,7 +452,8 @@ where
                     }
                     response.set_primary_account(session.account_id().into(), email, name, None);
                 } else {
-                    let is_readonly = if !acl.is_member(*id) {
+                    let is_member = acl.is_member(*id);
+                    let is_readonly = if !is_member {
                         store
                             .mail_shared_folders(*id, &acl.member_of, ACL::AddItems)
                             .ok()
@@ -360,7 +469,27 @@ where
                         if !name.is_empty() { name } else { email },
                         matches!(ptype, Type::Individual),
                         is_readonly,
-                        Some(&[URI::Core, URI::Mail, URI::WebSocket]),
+                        Some(if is_member {
+                            &[
+                                URI::Core,
+                                URI::Mail,
+                                URI::Quota,
+                                URI::Sieve,
+                                URI::Contacts,
+                                URI::Calendars,
+                                URI::WebSocket,
+                                URI::Blob,
+                            ][..]
+                        } else {
+                            &[
+                                URI::Core,
+                                URI::Mail,
+                                URI::Contacts,
+                                URI::Calendars,
+                                URI::WebSocket,
+                                URI::Blob,
+                            ][..]
+                        }),
                     );
                 }
             }
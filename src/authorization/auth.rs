@@ -35,7 +35,7 @@ use actix_web::{
 };
 use futures::FutureExt;
 use futures_util::future::LocalBoxFuture;
-use jmap::{base64, types::jmap::JMAPId};
+use jmap::{base64, principal::schema::AppPasswordScope, types::jmap::JMAPId};
 use jmap_sharing::principal::account::JMAPAccountStore;
 use store::{
     core::error::StoreError,
@@ -96,7 +96,8 @@ where
                     || request_path.starts_with("/jmap/ws")
                     || request_path.starts_with("/jmap/eventsource")
                     || request_path.starts_with("/auth")
-                    || request_path.starts_with("/.well-known/oauth-authorization-server");
+                    || request_path.starts_with("/.well-known/oauth-authorization-server")
+                    || request_path.starts_with("/.well-known/openid-configuration");
 
                 // Redirect requests to /jmap are evaluated after parsing
                 if do_redirect {
@@ -147,22 +148,29 @@ where
                                 })
                             })
                         {
-                            let store = core.store.clone();
-                            core.spawn_worker(move || {
-                                // Validate password
-                                Ok(
-                                    if let Some(account_id) = store.authenticate(&login, &secret)? {
-                                        Session::new(
+                            // Validate password
+                            match core
+                                .authenticate_client(login, secret, AppPasswordScope::Jmap.into())
+                                .await
+                            {
+                                Ok(Some((account_id, password_id))) => {
+                                    let store = core.store.clone();
+                                    core.spawn_worker(move || {
+                                        let session = Session::new(
                                             account_id,
                                             store.get_acl_token(account_id)?.as_ref(),
-                                        )
-                                        .into()
-                                    } else {
-                                        None
-                                    },
-                                )
-                            })
-                            .await
+                                        );
+                                        Ok(if let Some(password_id) = password_id {
+                                            session.with_app_password_id(password_id)
+                                        } else {
+                                            session
+                                        }
+                                        .into())
+                                    })
+                                    .await
+                                }
+                                result => result.map(|_| None),
+                            }
                         } else {
                             debug!("Failed to decode Basic auth request.",);
                             Ok(None)
@@ -176,13 +184,22 @@ where
 
                         // Validate OAuth bearer token
                         match core.validate_access_token("access_token", token).await {
-                            Ok((account_id, _, _)) => {
+                            Ok((account_id, grant_id, _, _)) => {
+                                core.touch_grant(
+                                    account_id,
+                                    grant_id,
+                                    req.remote_address(core.store.config.use_forwarded_header)
+                                        .ip(),
+                                )
+                                .await;
+
                                 let store = core.store.clone();
                                 core.spawn_worker(move || {
                                     Ok(Session::new(
                                         account_id,
                                         store.get_acl_token(account_id)?.as_ref(),
                                     )
+                                    .with_grant_id(grant_id)
                                     .into())
                                 })
                                 .await
@@ -252,11 +269,17 @@ where
     }
 }
 
-trait ServiceRequestAddr {
+pub(crate) trait ServiceRequestAddr {
     fn remote_address(&self, use_forwarded: bool) -> RemoteAddress;
 }
 
 impl ServiceRequestAddr for ServiceRequest {
+    fn remote_address(&self, use_forwarded: bool) -> RemoteAddress {
+        self.request().remote_address(use_forwarded)
+    }
+}
+
+impl ServiceRequestAddr for HttpRequest {
     fn remote_address(&self, use_forwarded: bool) -> RemoteAddress {
         let peer_addr = self
             .peer_addr()
@@ -324,6 +347,16 @@ impl FromRequest for Session {
     }
 }
 
+impl RemoteAddress {
+    pub fn ip(&self) -> Option<String> {
+        match self {
+            RemoteAddress::IpAddress(addr) => addr.to_string().into(),
+            RemoteAddress::IpAddressFwd(addr) => addr.clone().into(),
+            RemoteAddress::AccountId(_) => None,
+        }
+    }
+}
+
 impl Display for RemoteAddress {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
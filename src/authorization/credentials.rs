@@ -0,0 +1,407 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use actix_web::{web, HttpResponse};
+use jmap::{principal::schema::AppPasswordScope, types::jmap::JMAPId};
+use jmap_sharing::principal::{account::JMAPAccountStore, credentials::JMAPAccountCredentials};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use store::{core::error::StoreError, log::changes::ChangeId, tracing::error, AccountId, Store};
+
+use crate::JMAPServer;
+
+use super::Session;
+
+const LABEL_MAX_LEN: usize = 100;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AppPasswordRequest {
+    pub label: String,
+    pub scope: Option<AppPasswordScope>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AppPasswordResponse {
+    pub id: String,
+    pub label: String,
+    pub scope: Option<AppPasswordScope>,
+    pub created_at: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub password: Option<String>,
+    pub current: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TOTPEnrollResponse {
+    pub secret: String,
+    pub uri: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TOTPVerifyRequest {
+    pub code: String,
+}
+
+// Lists the app passwords of the authenticated account
+pub async fn handle_app_password_list<T>(
+    session: Session,
+    core: web::Data<JMAPServer<T>>,
+) -> HttpResponse
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    let store = core.store.clone();
+    let account_id = session.account_id();
+    match core
+        .spawn_worker(move || store.app_password_list(account_id))
+        .await
+    {
+        Ok(passwords) => HttpResponse::build(StatusCode::OK)
+            .content_type("application/json")
+            .body(
+                serde_json::to_string(
+                    &passwords
+                        .into_iter()
+                        .map(|password| AppPasswordResponse {
+                            id: JMAPId::from(password.id).to_string(),
+                            current: session.app_password_id() == Some(password.id),
+                            label: password.label,
+                            scope: password.scope,
+                            created_at: password.created,
+                            password: None,
+                        })
+                        .collect::<Vec<_>>(),
+                )
+                .unwrap_or_default(),
+            ),
+        Err(err) => {
+            error!("Failed to list app passwords: {}", err);
+            HttpResponse::ServiceUnavailable().finish()
+        }
+    }
+}
+
+// Creates an app password, which is only displayed once
+pub async fn handle_app_password_create<T>(
+    session: Session,
+    core: web::Data<JMAPServer<T>>,
+    params: web::Form<AppPasswordRequest>,
+) -> HttpResponse
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    if session.app_password_id().is_some() {
+        return HttpResponse::Forbidden().body("App passwords cannot manage credentials");
+    }
+    let params = params.into_inner();
+    let label = params.label.trim().to_string();
+    if label.is_empty() || label.len() > LABEL_MAX_LEN {
+        return HttpResponse::BadRequest().body("Invalid label");
+    }
+
+    let store = core.store.clone();
+    let account_id = session.account_id();
+    match core
+        .spawn_worker(move || store.app_password_create(account_id, label, params.scope))
+        .await
+    {
+        Ok(Some((app_password, password, change_id))) => {
+            if let Err(err) = core.commit_credentials_change(change_id).await {
+                error!("Failed to create app password: {}", err);
+                return HttpResponse::ServiceUnavailable().finish();
+            }
+
+            HttpResponse::build(StatusCode::OK)
+                .content_type("application/json")
+                .body(
+                    serde_json::to_string(&AppPasswordResponse {
+                        id: JMAPId::from(app_password.id).to_string(),
+                        label: app_password.label,
+                        scope: app_password.scope,
+                        created_at: app_password.created,
+                        password: password.into(),
+                        current: false,
+                    })
+                    .unwrap_or_default(),
+                )
+        }
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(err) => {
+            error!("Failed to create app password: {}", err);
+            HttpResponse::ServiceUnavailable().finish()
+        }
+    }
+}
+
+// Revokes an app password of the authenticated account
+pub async fn handle_app_password_revoke<T>(
+    session: Session,
+    core: web::Data<JMAPServer<T>>,
+    path: web::Path<String>,
+) -> HttpResponse
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    if session.app_password_id().is_some() {
+        return HttpResponse::Forbidden().body("App passwords cannot manage credentials");
+    }
+    let password_id = if let Some(password_id) = JMAPId::parse(&path.into_inner()) {
+        u64::from(password_id)
+    } else {
+        return HttpResponse::NotFound().finish();
+    };
+
+    let store = core.store.clone();
+    let account_id = session.account_id();
+    match core
+        .spawn_worker(move || store.app_password_revoke(account_id, password_id))
+        .await
+    {
+        Ok(Some(change_id)) => {
+            if let Err(err) = core.commit_credentials_change(change_id).await {
+                error!("Failed to revoke app password: {}", err);
+                return HttpResponse::ServiceUnavailable().finish();
+            }
+            core.invalidate_cluster_sessions(account_id, password_id.into())
+                .await;
+            HttpResponse::NoContent().finish()
+        }
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(err) => {
+            error!("Failed to revoke app password: {}", err);
+            HttpResponse::ServiceUnavailable().finish()
+        }
+    }
+}
+
+// Starts a TOTP enrollment, which is completed once a valid code is verified
+pub async fn handle_totp_enroll<T>(session: Session, core: web::Data<JMAPServer<T>>) -> HttpResponse
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    if session.app_password_id().is_some() {
+        return HttpResponse::Forbidden().body("App passwords cannot manage credentials");
+    }
+
+    let store = core.store.clone();
+    let account_id = session.account_id();
+    match core
+        .spawn_worker(move || {
+            Ok(
+                if let Some((secret, change_id)) = store.totp_enroll(account_id)? {
+                    store
+                        .get_account_details(account_id)?
+                        .map(|(email, _, _)| (secret, email, change_id))
+                } else {
+                    None
+                },
+            )
+        })
+        .await
+    {
+        Ok(Some((secret, email, change_id))) => {
+            if let Err(err) = core.commit_credentials_change(change_id).await {
+                error!("Failed to enroll TOTP: {}", err);
+                return HttpResponse::ServiceUnavailable().finish();
+            }
+
+            // Key URI understood by most authenticator apps
+            let uri = format!(
+                "otpauth://totp/Stalwart%20JMAP:{}?secret={}&issuer=Stalwart%20JMAP",
+                email, secret
+            );
+
+            HttpResponse::build(StatusCode::OK)
+                .content_type("application/json")
+                .body(
+                    serde_json::to_string(&TOTPEnrollResponse { secret, uri }).unwrap_or_default(),
+                )
+        }
+        Ok(None) => HttpResponse::BadRequest().body("Two-factor authentication is already enabled"),
+        Err(err) => {
+            error!("Failed to enroll TOTP: {}", err);
+            HttpResponse::ServiceUnavailable().finish()
+        }
+    }
+}
+
+// Enables two-factor authentication after verifying a code for the pending secret
+pub async fn handle_totp_verify<T>(
+    session: Session,
+    core: web::Data<JMAPServer<T>>,
+    params: web::Form<TOTPVerifyRequest>,
+) -> HttpResponse
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    if session.app_password_id().is_some() {
+        return HttpResponse::Forbidden().body("App passwords cannot manage credentials");
+    }
+
+    let store = core.store.clone();
+    let account_id = session.account_id();
+    let code = params.into_inner().code;
+    match core
+        .spawn_worker(move || store.totp_confirm(account_id, &code))
+        .await
+    {
+        Ok(Some(change_id)) => {
+            if let Err(err) = core.commit_credentials_change(change_id).await {
+                error!("Failed to enable TOTP: {}", err);
+                return HttpResponse::ServiceUnavailable().finish();
+            }
+
+            // Main password sessions are no longer allowed
+            core.invalidate_cluster_sessions(account_id, None).await;
+            HttpResponse::NoContent().finish()
+        }
+        Ok(None) => HttpResponse::BadRequest().body("Invalid or expired code"),
+        Err(err) => {
+            error!("Failed to enable TOTP: {}", err);
+            HttpResponse::ServiceUnavailable().finish()
+        }
+    }
+}
+
+// Disables two-factor authentication, which requires a valid code
+pub async fn handle_totp_disable<T>(
+    session: Session,
+    core: web::Data<JMAPServer<T>>,
+    params: web::Form<TOTPVerifyRequest>,
+) -> HttpResponse
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    if session.app_password_id().is_some() {
+        return HttpResponse::Forbidden().body("App passwords cannot manage credentials");
+    }
+
+    let store = core.store.clone();
+    let account_id = session.account_id();
+    let code = params.into_inner().code;
+    match core
+        .spawn_worker(move || {
+            if !store.totp_is_enabled(account_id)? {
+                return Ok(None);
+            }
+            Ok(Some(match store.totp_verify(account_id, &code)? {
+                (true, verify_change_id) => {
+                    (true, store.totp_disable(account_id)?.or(verify_change_id))
+                }
+                (false, verify_change_id) => (false, verify_change_id),
+            }))
+        })
+        .await
+    {
+        Ok(Some((is_valid, change_id))) => {
+            if let Some(change_id) = change_id {
+                if let Err(err) = core.commit_credentials_change(change_id).await {
+                    error!("Failed to disable TOTP: {}", err);
+                    return HttpResponse::ServiceUnavailable().finish();
+                }
+            }
+            if is_valid {
+                HttpResponse::NoContent().finish()
+            } else {
+                HttpResponse::Forbidden().body("Invalid or expired code")
+            }
+        }
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(err) => {
+            error!("Failed to disable TOTP: {}", err);
+            HttpResponse::ServiceUnavailable().finish()
+        }
+    }
+}
+
+impl<T> JMAPServer<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    // Authenticates an account from the OAuth login forms, requiring a valid
+    // TOTP code when two-factor authentication is enabled.
+    pub async fn authenticate_login_form(
+        &self,
+        email: String,
+        password: String,
+        otp: Option<String>,
+    ) -> store::Result<Option<AccountId>> {
+        let store = self.store.clone();
+        let is_leader = self.is_leader();
+        let (account_id, change_id) = self
+            .spawn_worker(move || {
+                if let Some((account_id, sync_change_id)) =
+                    store.authenticate(&email, &password, is_leader)?
+                {
+                    if !store.totp_is_enabled(account_id)? {
+                        return Ok((Some(account_id), sync_change_id));
+                    } else if let Some(otp) = otp.filter(|otp| !otp.is_empty()) {
+                        // Failed codes are recorded as well, so they need to be committed
+                        let (is_valid, change_id) = store.totp_verify(account_id, &otp)?;
+                        return Ok((is_valid.then_some(account_id), change_id.or(sync_change_id)));
+                    }
+                    Ok((None, sync_change_id))
+                } else {
+                    Ok((None, None))
+                }
+            })
+            .await?;
+
+        if let Some(change_id) = change_id {
+            self.commit_credentials_change(change_id).await?;
+        }
+        Ok(account_id)
+    }
+
+    // Authenticates a client with its password or an app password valid for the
+    // requested scope. Directory changes are only synchronized at login on the
+    // leader, which then commits them.
+    pub async fn authenticate_client(
+        &self,
+        login: String,
+        password: String,
+        scope: Option<AppPasswordScope>,
+    ) -> store::Result<Option<(AccountId, Option<u64>)>> {
+        let store = self.store.clone();
+        let is_leader = self.is_leader();
+        let (result, change_id) = self
+            .spawn_worker(move || store.authenticate_client(&login, &password, scope, is_leader))
+            .await?;
+
+        if let Some(change_id) = change_id {
+            self.commit_credentials_change(change_id).await?;
+        }
+        Ok(result)
+    }
+
+    pub async fn commit_credentials_change(&self, change_id: ChangeId) -> store::Result<()> {
+        if self.is_in_cluster() && !self.commit_index(change_id).await {
+            Err(StoreError::InternalError(
+                "Failed to commit credential changes.".to_string(),
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
@@ -22,23 +22,26 @@
 */
 
 pub mod auth;
+pub mod credentials;
 pub mod oauth;
+pub mod oidc;
 pub mod rate_limit;
+pub mod upstream;
 
 use std::{
     collections::hash_map::DefaultHasher,
     hash::{Hash, Hasher},
 };
 
-use aes_gcm_siv::{
-    aead::{generic_array::GenericArray, Aead},
-    AeadInPlace, Aes256GcmSiv, KeyInit, Nonce,
-};
-use store::{blake3, core::acl::ACLToken, AccountId};
+use store::{core::acl::ACLToken, AccountId};
+
+pub use store::crypto::SymmetricEncrypt;
 
 #[derive(Debug, Clone)]
 pub struct Session {
     account_id: AccountId,
+    grant_id: Option<u64>,
+    app_password_id: Option<u64>,
     state: u32,
 }
 
@@ -51,51 +54,41 @@ impl Session {
 
         Self {
             account_id,
+            grant_id: None,
+            app_password_id: None,
             state: s.finish() as u32,
         }
     }
 
-    pub fn account_id(&self) -> AccountId {
-        self.account_id
+    pub fn with_grant_id(mut self, grant_id: u64) -> Self {
+        self.grant_id = grant_id.into();
+        self
     }
 
-    pub fn state(&self) -> u32 {
-        self.state
+    pub fn with_app_password_id(mut self, app_password_id: u64) -> Self {
+        self.app_password_id = app_password_id.into();
+        self
     }
-}
 
-pub struct SymmetricEncrypt {
-    aes: Aes256GcmSiv,
-}
-
-impl SymmetricEncrypt {
-    pub const ENCRYPT_TAG_LEN: usize = 16;
-    pub const NONCE_LEN: usize = 12;
+    pub fn account_id(&self) -> AccountId {
+        self.account_id
+    }
 
-    pub fn new(key: &[u8], context: &str) -> Self {
-        SymmetricEncrypt {
-            aes: Aes256GcmSiv::new(&GenericArray::clone_from_slice(
-                &blake3::derive_key(context, key)[..],
-            )),
-        }
+    pub fn grant_id(&self) -> Option<u64> {
+        self.grant_id
     }
 
-    #[allow(clippy::ptr_arg)]
-    pub fn encrypt_in_place(&self, bytes: &mut Vec<u8>, nonce: &[u8]) -> Result<(), String> {
-        self.aes
-            .encrypt_in_place(Nonce::from_slice(nonce), b"", bytes)
-            .map_err(|e| e.to_string())
+    pub fn app_password_id(&self) -> Option<u64> {
+        self.app_password_id
     }
 
-    pub fn encrypt(&self, bytes: &[u8], nonce: &[u8]) -> Result<Vec<u8>, String> {
-        self.aes
-            .encrypt(Nonce::from_slice(nonce), bytes)
-            .map_err(|e| e.to_string())
+    // Id of the OAuth grant or app password used to authenticate, None when
+    // the main password was used.
+    pub fn credential_id(&self) -> Option<u64> {
+        self.grant_id.or(self.app_password_id)
     }
 
-    pub fn decrypt(&self, bytes: &[u8], nonce: &[u8]) -> Result<Vec<u8>, String> {
-        self.aes
-            .decrypt(Nonce::from_slice(nonce), bytes)
-            .map_err(|e| e.to_string())
+    pub fn state(&self) -> u32 {
+        self.state
     }
 }
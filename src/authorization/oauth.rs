@@ -29,26 +29,33 @@ use std::{
     time::{Instant, SystemTime},
 };
 
-use crate::JMAPServer;
-use actix_web::{http::header, web, HttpResponse};
-use jmap::base64;
-use jmap_sharing::principal::account::JMAPAccountStore;
+use crate::{cluster, JMAPServer};
+use actix_web::{http::header, web, HttpRequest, HttpResponse};
+use jmap::{base64, types::jmap::JMAPId, SUPERUSER_ID};
+use jmap_sharing::principal::{account::JMAPAccountStore, grants::JMAPAccountGrants};
 use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 use std::fmt::Write;
 use store::{
     bincode, blake3,
     core::error::StoreError,
+    log::changes::ChangeId,
     rand::{
         distributions::{Alphanumeric, Standard},
         thread_rng, Rng,
     },
     serialize::leb128::{Leb128Iterator, Leb128Vec},
+    sha2::{Digest, Sha256},
     tracing::{debug, error},
     AccountId, Store,
 };
 
-use super::SymmetricEncrypt;
+use super::{
+    auth::ServiceRequestAddr,
+    oidc::{base64url, OpenIdProvider},
+    upstream::{handle_upstream_redirect, UpstreamProvider},
+    Session, SymmetricEncrypt,
+};
 
 const OAUTH_HTML_HEADER: &str = include_str!("../../resources/oauth/header.htx");
 const OAUTH_HTML_FOOTER: &str = include_str!("../../resources/oauth/footer.htx");
@@ -85,6 +92,8 @@ pub struct OAuth {
     pub expiry_refresh_token_renew: u64,
     pub max_auth_attempts: u32,
     pub metadata: String,
+    pub oidc: Option<OpenIdProvider>,
+    pub upstream: Option<UpstreamProvider>,
 }
 
 pub struct OAuthCode {
@@ -93,6 +102,9 @@ pub struct OAuthCode {
     pub expiry: Instant,
     pub client_id: String,
     pub redirect_uri: Option<String>,
+    pub scope: Option<String>,
+    pub nonce: Option<String>,
+    pub code_challenge: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -105,11 +117,13 @@ pub struct DeviceAuthPost {
     code: Option<String>,
     email: Option<String>,
     password: Option<String>,
+    otp: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DeviceAuthRequest {
     client_id: String,
+    scope: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -124,11 +138,14 @@ pub struct DeviceAuthResponse {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CodeAuthRequest {
-    response_type: String,
-    client_id: String,
-    redirect_uri: String,
-    scope: Option<String>,
-    state: Option<String>,
+    pub response_type: String,
+    pub client_id: String,
+    pub redirect_uri: String,
+    pub scope: Option<String>,
+    pub state: Option<String>,
+    pub nonce: Option<String>,
+    pub code_challenge: Option<String>,
+    pub code_challenge_method: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -136,6 +153,7 @@ pub struct CodeAuthForm {
     code: String,
     email: Option<String>,
     password: Option<String>,
+    otp: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -146,6 +164,47 @@ pub struct TokenRequest {
     pub client_id: Option<String>,
     pub refresh_token: Option<String>,
     pub redirect_uri: Option<String>,
+    pub code_verifier: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TokenRevokeRequest {
+    pub token: String,
+    pub token_type_hint: Option<String>,
+    pub client_id: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TokenIntrospectRequest {
+    pub token: String,
+    pub token_type_hint: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TokenIntrospectResponse {
+    pub active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exp: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iat: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct GrantResponse {
+    pub id: String,
+    pub client_id: String,
+    pub created_at: i64,
+    pub last_used_at: i64,
+    pub ip: Option<String>,
+    pub current: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
@@ -159,6 +218,8 @@ pub enum TokenResponse {
         refresh_token: Option<String>,
         #[serde(skip_serializing_if = "Option::is_none")]
         scope: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        id_token: Option<String>,
     },
     Error {
         error: ErrorType,
@@ -198,6 +259,11 @@ pub struct OAuthMetadata {
     pub response_types_supported: Vec<String>,
     pub scopes_supported: Vec<String>,
     pub authorization_endpoint: String,
+    pub revocation_endpoint: String,
+    pub introspection_endpoint: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jwks_uri: Option<String>,
+    pub code_challenge_methods_supported: Vec<String>,
 }
 
 // Device authorization endpoint
@@ -235,12 +301,16 @@ where
     }
 
     // Add OAuth status
+    let params = params.into_inner();
     let oauth_code = Arc::new(OAuthCode {
         status: STATUS_PENDING.into(),
         account_id: u32::MAX.into(),
         expiry: Instant::now(),
-        client_id: params.into_inner().client_id,
+        client_id: params.client_id,
         redirect_uri: None,
+        scope: params.scope,
+        nonce: None,
+        code_challenge: None,
     });
     core.oauth_codes
         .insert(device_code.clone(), oauth_code.clone())
@@ -268,6 +338,7 @@ where
 
 // Token endpoint
 pub async fn handle_token_request<T>(
+    req: HttpRequest,
     core: web::Data<JMAPServer<T>>,
     params: web::Form<TokenRequest>,
 ) -> HttpResponse
@@ -287,6 +358,7 @@ where
                     TokenResponse::error(ErrorType::InvalidClient)
                 } else if oauth.status.load(atomic::Ordering::Relaxed) == STATUS_AUTHORIZED
                     && oauth.expiry.elapsed().as_secs() < core.oauth.expiry_auth_code
+                    && oauth.verify_code_challenge(params.code_verifier.as_deref())
                 {
                     // Mark this token as issued
                     oauth
@@ -294,10 +366,10 @@ where
                         .store(STATUS_TOKEN_ISSUED, atomic::Ordering::Relaxed);
 
                     // Issue token
-                    core.issue_token(
-                        oauth.account_id.load(atomic::Ordering::Relaxed),
-                        &oauth.client_id,
-                        true,
+                    core.grant_token(
+                        &oauth,
+                        req.remote_address(core.store.config.use_forwarded_header)
+                            .ip(),
                     )
                     .await
                     .unwrap_or_else(|err| {
@@ -337,10 +409,10 @@ where
                             .store(STATUS_TOKEN_ISSUED, atomic::Ordering::Relaxed);
 
                         // Issue token
-                        core.issue_token(
-                            oauth.account_id.load(atomic::Ordering::Relaxed),
-                            &oauth.client_id,
-                            true,
+                        core.grant_token(
+                            &oauth,
+                            req.remote_address(core.store.config.use_forwarded_header)
+                                .ip(),
                         )
                         .await
                         .unwrap_or_else(|err| {
@@ -365,11 +437,19 @@ where
                 .validate_access_token("refresh_token", refresh_token)
                 .await
             {
-                Ok((account_id, client_id, time_left)) => {
-                    // TODO: implement revoking client ids
+                Ok((account_id, grant_id, client_id, time_left)) => {
+                    core.touch_grant(
+                        account_id,
+                        grant_id,
+                        req.remote_address(core.store.config.use_forwarded_header)
+                            .ip(),
+                    )
+                    .await;
+
                     response = core
                         .issue_token(
                             account_id,
+                            grant_id,
                             &client_id,
                             time_left <= core.oauth.expiry_refresh_token_renew,
                         )
@@ -398,7 +478,10 @@ where
 }
 
 // Code authorization flow, handles an authorization request
-pub async fn handle_user_code_auth<T>(params: web::Query<CodeAuthRequest>) -> HttpResponse
+pub async fn handle_user_code_auth<T>(
+    core: web::Data<JMAPServer<T>>,
+    params: web::Query<CodeAuthRequest>,
+) -> HttpResponse
 where
     T: for<'x> Store<'x> + 'static,
 {
@@ -407,9 +490,16 @@ where
         return HttpResponse::BadRequest().body("Client ID is too long");
     } else if !params.redirect_uri.starts_with("https://") {
         return HttpResponse::BadRequest().body("Redirect URI must be HTTPS");
+    } else if !params.has_pkce() {
+        return HttpResponse::BadRequest().body("PKCE with the S256 method is required");
     }
 
+    // Delegate authentication to the upstream identity provider
     let params = params.into_inner();
+    if core.oauth.upstream.is_some() {
+        return handle_upstream_redirect(core, params).await;
+    }
+
     let mut cancel_link = format!("{}?error=access_denied", params.redirect_uri);
     if let Some(state) = &params.state {
         let _ = write!(cancel_link, "&state={}", state);
@@ -446,6 +536,11 @@ pub async fn handle_user_code_auth_post<T>(
 where
     T: for<'x> Store<'x> + 'static,
 {
+    // Accounts are authenticated by the upstream identity provider
+    if core.oauth.upstream.is_some() {
+        return HttpResponse::BadRequest().body("Password authentication is disabled");
+    }
+
     let mut auth_code = None;
     let params = params.into_inner();
     let (auth_attempts, code_req) = match base64::decode(&params.code)
@@ -457,49 +552,22 @@ where
             return HttpResponse::BadRequest().body("Failed to deserialize code.");
         }
     };
+    if !code_req.has_pkce() {
+        return HttpResponse::BadRequest().body("PKCE with the S256 method is required");
+    }
 
     // Authenticate user
     if let (Some(email), Some(password)) = (params.email, params.password) {
-        let store = core.store.clone();
-
         if let Ok(Some(account_id)) = core
-            .spawn_worker(move || store.authenticate(&email, &password))
+            .authenticate_login_form(email, password, params.otp)
             .await
         {
-            // Generate client code
-            let client_code = thread_rng()
-                .sample_iter(Alphanumeric)
-                .take(DEVICE_CODE_LEN)
-                .map(char::from)
-                .collect::<String>();
-
-            // Add client code
-            core.oauth_codes
-                .insert(
-                    client_code.clone(),
-                    Arc::new(OAuthCode {
-                        status: STATUS_AUTHORIZED.into(),
-                        account_id: account_id.into(),
-                        expiry: Instant::now(),
-                        client_id: code_req.client_id.clone(),
-                        redirect_uri: code_req.redirect_uri.clone().into(),
-                    }),
-                )
-                .await;
-
-            auth_code = client_code.into();
+            auth_code = core.create_auth_code(&code_req, account_id).await.into();
         }
     }
 
     // Build redirect link
-    let mut redirect_link = if let Some(auth_code) = &auth_code {
-        format!("{}?code={}", code_req.redirect_uri, auth_code)
-    } else {
-        format!("{}?error=access_denied", code_req.redirect_uri)
-    };
-    if let Some(state) = &code_req.state {
-        let _ = write!(redirect_link, "&state={}", state);
-    }
+    let redirect_link = code_req.redirect_link(auth_code.as_deref());
 
     if auth_code.is_none() && (auth_attempts < core.oauth.max_auth_attempts) {
         let code =
@@ -584,9 +652,8 @@ where
             && oauth.expiry.elapsed().as_secs() < core.oauth.expiry_user_code
         {
             if let (Some(email), Some(password)) = (params.email, params.password) {
-                let store = core.store.clone();
                 match core
-                    .spawn_worker(move || store.authenticate(&email, &password))
+                    .authenticate_login_form(email, password, params.otp)
                     .await
                 {
                     Ok(Some(account_id)) => {
@@ -656,6 +723,159 @@ where
         .body(response)
 }
 
+// Token revocation endpoint (RFC 7009)
+pub async fn handle_token_revoke<T>(
+    core: web::Data<JMAPServer<T>>,
+    params: web::Form<TokenRevokeRequest>,
+) -> HttpResponse
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    // Invalid tokens do not cause an error response, as the purpose of
+    // the request has already been achieved.
+    if let Some((_, account_id, grant_id, client_id, _)) = core
+        .validate_any_token(&params.token, params.token_type_hint.as_deref())
+        .await
+    {
+        if params
+            .client_id
+            .as_ref()
+            .map_or(false, |param_client_id| param_client_id != &client_id)
+        {
+            return HttpResponse::build(StatusCode::BAD_REQUEST)
+                .content_type("application/json")
+                .body(
+                    serde_json::to_string(&TokenResponse::error(ErrorType::InvalidClient))
+                        .unwrap_or_default(),
+                );
+        }
+
+        if let Err(err) = core.revoke_grant(account_id, grant_id).await {
+            error!("Failed to revoke OAuth grant: {}", err);
+            return HttpResponse::ServiceUnavailable().finish();
+        }
+    }
+
+    HttpResponse::Ok().finish()
+}
+
+// Token introspection endpoint (RFC 7662)
+pub async fn handle_token_introspect<T>(
+    session: Session,
+    core: web::Data<JMAPServer<T>>,
+    params: web::Form<TokenIntrospectRequest>,
+) -> HttpResponse
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    let mut response = TokenIntrospectResponse::default();
+
+    if let Some((grant_type, account_id, _, client_id, time_left)) = core
+        .validate_any_token(&params.token, params.token_type_hint.as_deref())
+        .await
+    {
+        // Only the token owner or an administrator may inspect a token
+        if session.account_id() == account_id || session.account_id() == SUPERUSER_ID {
+            let store = core.store.clone();
+            match core
+                .spawn_worker(move || store.get_account_details(account_id))
+                .await
+            {
+                Ok(details) => {
+                    let now = SystemTime::now()
+                        .duration_since(SystemTime::UNIX_EPOCH)
+                        .map(|d| d.as_secs())
+                        .unwrap_or(0);
+                    let expiry = if grant_type == "access_token" {
+                        core.oauth.expiry_token
+                    } else {
+                        core.oauth.expiry_refresh_token
+                    };
+
+                    response = TokenIntrospectResponse {
+                        active: true,
+                        client_id: client_id.into(),
+                        username: details.map(|(email, _, _)| email),
+                        token_type: "bearer".to_string().into(),
+                        exp: (now + time_left).into(),
+                        iat: (now + time_left).saturating_sub(expiry).into(),
+                        sub: JMAPId::from(account_id).to_string().into(),
+                    };
+                }
+                Err(err) => {
+                    error!("Failed to obtain account details: {}", err);
+                    return HttpResponse::ServiceUnavailable().finish();
+                }
+            }
+        }
+    }
+
+    HttpResponse::build(StatusCode::OK)
+        .content_type("application/json")
+        .body(serde_json::to_string(&response).unwrap_or_default())
+}
+
+// Lists the OAuth grants issued for the authenticated account
+pub async fn handle_grant_list<T>(session: Session, core: web::Data<JMAPServer<T>>) -> HttpResponse
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    let store = core.store.clone();
+    let account_id = session.account_id();
+    match core
+        .spawn_worker(move || store.oauth_grant_list(account_id))
+        .await
+    {
+        Ok(grants) => HttpResponse::build(StatusCode::OK)
+            .content_type("application/json")
+            .body(
+                serde_json::to_string(
+                    &grants
+                        .into_iter()
+                        .map(|(grant, usage)| GrantResponse {
+                            id: JMAPId::from(grant.id).to_string(),
+                            current: session.grant_id() == Some(grant.id),
+                            client_id: grant.client_id,
+                            created_at: grant.created,
+                            last_used_at: usage.last_used,
+                            ip: usage.ip,
+                        })
+                        .collect::<Vec<_>>(),
+                )
+                .unwrap_or_default(),
+            ),
+        Err(err) => {
+            error!("Failed to list OAuth grants: {}", err);
+            HttpResponse::ServiceUnavailable().finish()
+        }
+    }
+}
+
+// Revokes an OAuth grant issued for the authenticated account
+pub async fn handle_grant_revoke<T>(
+    session: Session,
+    core: web::Data<JMAPServer<T>>,
+    path: web::Path<String>,
+) -> HttpResponse
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    let grant_id = if let Some(grant_id) = JMAPId::parse(&path.into_inner()) {
+        u64::from(grant_id)
+    } else {
+        return HttpResponse::NotFound().finish();
+    };
+
+    match core.revoke_grant(session.account_id(), grant_id).await {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(err) => {
+            error!("Failed to revoke OAuth grant: {}", err);
+            HttpResponse::ServiceUnavailable().finish()
+        }
+    }
+}
+
 // /.well-known/oauth-authorization-server endpoint
 pub async fn handle_oauth_metadata<T>(core: web::Data<JMAPServer<T>>) -> HttpResponse
 where
@@ -670,9 +890,81 @@ impl<T> JMAPServer<T>
 where
     T: for<'x> Store<'x> + 'static,
 {
+    pub async fn create_auth_code(
+        &self,
+        code_req: &CodeAuthRequest,
+        account_id: AccountId,
+    ) -> String {
+        // Generate client code
+        let client_code = thread_rng()
+            .sample_iter(Alphanumeric)
+            .take(DEVICE_CODE_LEN)
+            .map(char::from)
+            .collect::<String>();
+
+        // Add client code
+        self.oauth_codes
+            .insert(
+                client_code.clone(),
+                Arc::new(OAuthCode {
+                    status: STATUS_AUTHORIZED.into(),
+                    account_id: account_id.into(),
+                    expiry: Instant::now(),
+                    client_id: code_req.client_id.clone(),
+                    redirect_uri: code_req.redirect_uri.clone().into(),
+                    scope: code_req.scope.clone(),
+                    nonce: code_req.nonce.clone(),
+                    code_challenge: code_req.code_challenge.clone(),
+                }),
+            )
+            .await;
+
+        client_code
+    }
+
+    async fn grant_token(
+        &self,
+        oauth: &OAuthCode,
+        remote_ip: Option<String>,
+    ) -> store::Result<TokenResponse> {
+        let store = self.store.clone();
+        let account_id = oauth.account_id.load(atomic::Ordering::Relaxed);
+        let client_id = oauth.client_id.as_str();
+        let client_id_ = client_id.to_string();
+        let max_idle = self.oauth.expiry_refresh_token as i64;
+        let (grant_id, change_id) = self
+            .spawn_worker(move || {
+                store.oauth_grant_create(account_id, client_id_, remote_ip, max_idle)
+            })
+            .await?
+            .ok_or_else(|| StoreError::DeserializeError("Account no longer exists".into()))?;
+        self.commit_grant_change(change_id).await?;
+
+        let mut response = self
+            .issue_token(account_id, grant_id, client_id, true)
+            .await?;
+
+        // Add an ID token when the OpenID scope was requested
+        if oauth.has_scope("openid") && self.oauth.oidc.is_some() {
+            if let TokenResponse::Granted {
+                id_token, scope, ..
+            } = &mut response
+            {
+                *id_token = self
+                    .issue_id_token(account_id, client_id, oauth.nonce.clone())
+                    .await?
+                    .into();
+                *scope = oauth.scope.clone();
+            }
+        }
+
+        Ok(response)
+    }
+
     async fn issue_token(
         &self,
         account_id: AccountId,
+        grant_id: u64,
         client_id: &str,
         with_refresh_token: bool,
     ) -> store::Result<TokenResponse>
@@ -697,6 +989,7 @@ where
             access_token: self.encode_access_token(
                 "access_token",
                 account_id,
+                grant_id,
                 &password_hash,
                 client_id,
                 self.oauth.expiry_token,
@@ -707,6 +1000,7 @@ where
                 self.encode_access_token(
                     "refresh_token",
                     account_id,
+                    grant_id,
                     &password_hash,
                     client_id,
                     self.oauth.expiry_refresh_token,
@@ -716,6 +1010,7 @@ where
                 None
             },
             scope: None,
+            id_token: None,
         })
     }
 
@@ -723,6 +1018,7 @@ where
         &self,
         grant_type: &str,
         account_id: u32,
+        grant_id: u64,
         password_hash: &str,
         client_id: &str,
         expiry_in: u64,
@@ -733,8 +1029,8 @@ where
         }
         let key = self.oauth.key.clone();
         let context = format!(
-            "{} {} {} {}",
-            grant_type, client_id, account_id, password_hash
+            "{} {} {} {} {}",
+            grant_type, client_id, account_id, grant_id, password_hash
         );
         let context_nonce = format!("{} nonce {}", grant_type, password_hash);
 
@@ -764,6 +1060,7 @@ where
             .map_err(StoreError::DeserializeError)?;
         token.push_leb128(account_id);
         token.push_leb128(expiry);
+        token.push_leb128(grant_id);
         token.extend_from_slice(client_id.as_bytes());
 
         Ok(base64::encode(&token))
@@ -773,17 +1070,18 @@ where
         &self,
         grant_type: &str,
         token: &str,
-    ) -> store::Result<(AccountId, String, u64)> {
+    ) -> store::Result<(AccountId, u64, String, u64)> {
         // Base64 decode token
         let token = base64::decode(token)
             .map_err(|e| StoreError::DeserializeError(format!("Failed to decode: {}", e)))?;
-        let (account_id, expiry, client_id) = token
+        let (account_id, expiry, grant_id, client_id) = token
             .get((RANDOM_CODE_LEN + SymmetricEncrypt::ENCRYPT_TAG_LEN)..)
             .and_then(|bytes| {
                 let mut bytes = bytes.iter();
                 (
                     bytes.next_leb128()?,
                     bytes.next_leb128::<u64>()?,
+                    bytes.next_leb128::<u64>()?,
                     bytes.copied().map(char::from).collect::<String>(),
                 )
                     .into()
@@ -800,18 +1098,20 @@ where
             return Err(StoreError::DeserializeError("Token expired.".into()));
         }
 
-        // Optain password hash
+        // Optain password hash and make sure the grant was not revoked
         let store = self.store.clone();
         let password_hash = self
-            .spawn_worker(move || store.get_account_secret_hash(account_id))
+            .spawn_worker(move || store.oauth_grant_secret_hash(account_id, grant_id))
             .await?
-            .ok_or_else(|| StoreError::DeserializeError("Account no longer exists".into()))?;
+            .ok_or_else(|| {
+                StoreError::DeserializeError("Grant was revoked or account no longer exists".into())
+            })?;
 
         // Build context
         let key = self.oauth.key.clone();
         let context = format!(
-            "{} {} {} {}",
-            grant_type, client_id, account_id, password_hash
+            "{} {} {} {} {}",
+            grant_type, client_id, account_id, grant_id, password_hash
         );
         let context_nonce = format!("{} nonce {}", grant_type, password_hash);
 
@@ -836,12 +1136,115 @@ where
             .map_err(|e| StoreError::DeserializeError(format!("Failed to decrypt: {}", e)))?;
 
         // Success
-        Ok((account_id, client_id, expiry - now))
+        Ok((account_id, grant_id, client_id, expiry - now))
+    }
+
+    async fn validate_any_token(
+        &self,
+        token: &str,
+        token_type_hint: Option<&str>,
+    ) -> Option<(&'static str, AccountId, u64, String, u64)> {
+        let grant_types = if token_type_hint == Some("refresh_token") {
+            ["refresh_token", "access_token"]
+        } else {
+            ["access_token", "refresh_token"]
+        };
+
+        for grant_type in grant_types {
+            match self.validate_access_token(grant_type, token).await {
+                Ok((account_id, grant_id, client_id, time_left)) => {
+                    return Some((grant_type, account_id, grant_id, client_id, time_left));
+                }
+                Err(StoreError::DeserializeError(_)) => (),
+                Err(err) => {
+                    error!("Failed to validate OAuth token: {}", err);
+                    return None;
+                }
+            }
+        }
+
+        None
+    }
+
+    // Records the last time a grant was used on this node. This is local
+    // bookkeeping, so it is not logged nor committed to the cluster.
+    pub async fn touch_grant(&self, account_id: AccountId, grant_id: u64, ip: Option<String>) {
+        let store = self.store.clone();
+        if let Err(err) = self
+            .spawn_worker(move || store.oauth_grant_touch(account_id, grant_id, ip))
+            .await
+        {
+            error!("Failed to update OAuth grant: {}", err);
+        }
+    }
+
+    pub async fn revoke_grant(&self, account_id: AccountId, grant_id: u64) -> store::Result<bool> {
+        let store = self.store.clone();
+        if let Some(change_id) = self
+            .spawn_worker(move || store.oauth_grant_revoke(account_id, grant_id))
+            .await?
+        {
+            self.commit_grant_change(change_id).await?;
+            self.invalidate_cluster_sessions(account_id, grant_id.into())
+                .await;
+
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    // Evicts the cached sessions authenticated with an OAuth grant or app password,
+    // or with the main password when no credential id is provided.
+    pub fn invalidate_sessions(&self, account_id: AccountId, credential_id: Option<u64>) {
+        if let Err(err) = self.sessions.invalidate_entries_if(move |_, session| {
+            session.account_id() == account_id && session.credential_id() == credential_id
+        }) {
+            error!("Failed to invalidate sessions: {}", err);
+        }
+    }
+
+    // Evicts cached sessions on this node and on the rest of the cluster
+    pub async fn invalidate_cluster_sessions(
+        &self,
+        account_id: AccountId,
+        credential_id: Option<u64>,
+    ) {
+        self.invalidate_sessions(account_id, credential_id);
+        if let Some(cluster) = &self.cluster {
+            if cluster
+                .tx
+                .send(cluster::Event::InvalidateSessions {
+                    account_id,
+                    credential_id,
+                })
+                .await
+                .is_err()
+            {
+                error!("Failed to send session invalidation to cluster.");
+            }
+        }
+    }
+
+    async fn commit_grant_change(&self, change_id: ChangeId) -> store::Result<()> {
+        if self.is_in_cluster() && !self.commit_index(change_id).await {
+            Err(StoreError::InternalError(
+                "Failed to commit OAuth grant changes.".to_string(),
+            ))
+        } else {
+            Ok(())
+        }
     }
 }
 
 impl OAuthMetadata {
-    pub fn new(base_url: &str) -> Self {
+    pub fn new(base_url: &str, with_oidc: bool) -> Self {
+        let mut scopes_supported = vec!["offline_access".to_string()];
+        if with_oidc {
+            scopes_supported.push("openid".to_string());
+        }
+        scopes_supported.extend(["profile".to_string(), "email".to_string()]);
+
         OAuthMetadata {
             issuer: base_url.to_string(),
             authorization_endpoint: format!("{}/auth/code", base_url),
@@ -852,8 +1255,56 @@ impl OAuthMetadata {
                 "urn:ietf:params:oauth:grant-type:device_code".to_string(),
             ],
             device_authorization_endpoint: format!("{}/auth/device", base_url),
+            revocation_endpoint: format!("{}/auth/revoke", base_url),
+            introspection_endpoint: format!("{}/auth/introspect", base_url),
+            jwks_uri: if with_oidc {
+                format!("{}/auth/jwks", base_url).into()
+            } else {
+                None
+            },
             response_types_supported: vec!["code".to_string(), "code token".to_string()],
-            scopes_supported: vec!["offline_access".to_string()],
+            scopes_supported,
+            code_challenge_methods_supported: vec!["S256".to_string()],
+        }
+    }
+}
+
+impl CodeAuthRequest {
+    pub fn redirect_link(&self, auth_code: Option<&str>) -> String {
+        let mut redirect_link = if let Some(auth_code) = auth_code {
+            format!("{}?code={}", self.redirect_uri, auth_code)
+        } else {
+            format!("{}?error=access_denied", self.redirect_uri)
+        };
+        if let Some(state) = &self.state {
+            let _ = write!(redirect_link, "&state={}", state);
+        }
+        redirect_link
+    }
+
+    fn has_pkce(&self) -> bool {
+        self.code_challenge
+            .as_ref()
+            .map_or(false, |c| !c.is_empty())
+            && self.code_challenge_method.as_deref() == Some("S256")
+    }
+}
+
+impl OAuthCode {
+    fn has_scope(&self, scope: &str) -> bool {
+        self.scope
+            .as_deref()
+            .map_or(false, |s| s.split_ascii_whitespace().any(|s| s == scope))
+    }
+
+    // Verifies the PKCE code verifier (RFC 7636) using the S256 method
+    fn verify_code_challenge(&self, code_verifier: Option<&str>) -> bool {
+        match (&self.code_challenge, code_verifier) {
+            (Some(code_challenge), Some(code_verifier)) => {
+                &base64url(&Sha256::digest(code_verifier.as_bytes())) == code_challenge
+            }
+            (None, _) => true,
+            _ => false,
         }
     }
 }
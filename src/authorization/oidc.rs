@@ -0,0 +1,410 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::SystemTime;
+
+use crate::JMAPServer;
+use actix_web::{web, HttpResponse};
+use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signature, Signer, Verifier};
+use jmap::{
+    base64,
+    orm::serialize::JMAPOrm,
+    principal::schema::{Principal, Property, Value},
+    types::jmap::JMAPId,
+    SUPERUSER_ID,
+};
+use jmap_sharing::principal::dkim::{ed25519_seed, rsa_key};
+use reqwest::StatusCode;
+use rsa::{
+    BigUint, Hash, PaddingScheme, PublicKey as _, PublicKeyParts, RsaPrivateKey, RsaPublicKey,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use store::{
+    core::error::StoreError,
+    sha2::{Digest, Sha256},
+    tracing::error,
+    AccountId, Store,
+};
+
+use super::{oauth::OAuthMetadata, Session};
+
+pub enum SigningKey {
+    Rsa { key: RsaPrivateKey, kid: String },
+    Ed25519 { key: Keypair, kid: String },
+}
+
+pub struct OpenIdProvider {
+    pub metadata: String,
+    pub jwks: String,
+    pub signing_key: SigningKey,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OpenIdMetadata {
+    #[serde(flatten)]
+    pub oauth: OAuthMetadata,
+    pub userinfo_endpoint: String,
+    pub subject_types_supported: Vec<String>,
+    pub id_token_signing_alg_values_supported: Vec<String>,
+    pub claims_supported: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct JsonWebKeySet {
+    pub keys: Vec<JsonWebKey>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct JsonWebKey {
+    pub kty: String,
+    pub kid: String,
+    #[serde(rename = "use")]
+    pub use_: String,
+    pub alg: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub e: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crv: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub x: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct UserInfo {
+    pub sub: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub picture: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct IdTokenClaims {
+    pub iss: String,
+    pub aud: String,
+    pub exp: u64,
+    pub iat: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    #[serde(flatten)]
+    pub user: UserInfo,
+}
+
+#[derive(Serialize)]
+struct JwtHeader<'x> {
+    alg: &'x str,
+    typ: &'x str,
+    kid: &'x str,
+}
+
+#[derive(Deserialize)]
+struct JwtHeaderOwned {
+    alg: String,
+    kid: Option<String>,
+}
+
+// /.well-known/openid-configuration endpoint
+pub async fn handle_oidc_metadata<T>(core: web::Data<JMAPServer<T>>) -> HttpResponse
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    if let Some(oidc) = &core.oauth.oidc {
+        HttpResponse::build(StatusCode::OK)
+            .content_type("application/json")
+            .body(oidc.metadata.clone())
+    } else {
+        HttpResponse::NotFound().finish()
+    }
+}
+
+// JSON Web Key Set endpoint
+pub async fn handle_jwks<T>(core: web::Data<JMAPServer<T>>) -> HttpResponse
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    if let Some(oidc) = &core.oauth.oidc {
+        HttpResponse::build(StatusCode::OK)
+            .content_type("application/json")
+            .body(oidc.jwks.clone())
+    } else {
+        HttpResponse::NotFound().finish()
+    }
+}
+
+// UserInfo endpoint
+pub async fn handle_userinfo<T>(session: Session, core: web::Data<JMAPServer<T>>) -> HttpResponse
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    if core.oauth.oidc.is_none() {
+        return HttpResponse::NotFound().finish();
+    }
+
+    match core.user_info(session.account_id()).await {
+        Ok(user_info) => HttpResponse::build(StatusCode::OK)
+            .content_type("application/json")
+            .body(serde_json::to_string(&user_info).unwrap_or_default()),
+        Err(err) => {
+            error!("Failed to obtain user info: {}", err);
+            HttpResponse::ServiceUnavailable().finish()
+        }
+    }
+}
+
+impl<T> JMAPServer<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    pub async fn issue_id_token(
+        &self,
+        account_id: AccountId,
+        client_id: &str,
+        nonce: Option<String>,
+    ) -> store::Result<String> {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        self.oauth
+            .oidc
+            .as_ref()
+            .ok_or_else(|| StoreError::InternalError("OpenID Connect is disabled".into()))?
+            .signing_key
+            .sign_jwt(&IdTokenClaims {
+                iss: self.base_session.base_url().to_string(),
+                aud: client_id.to_string(),
+                exp: now + self.oauth.expiry_token,
+                iat: now,
+                nonce,
+                user: self.user_info(account_id).await?,
+            })
+            .map_err(StoreError::InternalError)
+    }
+
+    pub async fn user_info(&self, account_id: AccountId) -> store::Result<UserInfo> {
+        let store = self.store.clone();
+        let base_url = self.base_session.base_url().to_string();
+
+        self.spawn_worker(move || {
+            let mut fields = store
+                .get_orm::<Principal>(SUPERUSER_ID, account_id)?
+                .ok_or_else(|| StoreError::NotFound("Account no longer exists".into()))?;
+
+            Ok(UserInfo {
+                sub: JMAPId::from(account_id).to_string(),
+                name: match fields.remove(&Property::Name) {
+                    Some(Value::Text { value }) => value.into(),
+                    _ => None,
+                },
+                email: match fields.remove(&Property::Email) {
+                    Some(Value::Text { value }) => value.into(),
+                    _ => None,
+                },
+                picture: match fields.remove(&Property::Picture) {
+                    Some(Value::Blob { value }) => format!(
+                        "{}/jmap/download/{}/{}/picture",
+                        base_url,
+                        JMAPId::from(SUPERUSER_ID),
+                        value
+                    )
+                    .into(),
+                    _ => None,
+                },
+            })
+        })
+        .await
+    }
+}
+
+impl SigningKey {
+    /// Parses a PKCS#1 or PKCS#8 RSA private key or a PKCS#8 Ed25519 private key.
+    pub fn from_pem(pem: &str) -> Result<Self, String> {
+        if let Some(seed) = ed25519_seed(pem) {
+            Self::from_ed25519_seed(&seed)
+        } else {
+            let key = rsa_key(pem)?;
+            let mut public_key = key.n().to_bytes_be();
+            public_key.extend_from_slice(&key.e().to_bytes_be());
+            Ok(SigningKey::Rsa {
+                kid: key_id(&public_key),
+                key,
+            })
+        }
+    }
+
+    fn from_ed25519_seed(seed: &[u8]) -> Result<Self, String> {
+        let secret = SecretKey::from_bytes(seed).map_err(|err| err.to_string())?;
+        let public = PublicKey::from(&secret);
+        Ok(SigningKey::Ed25519 {
+            kid: key_id(public.as_bytes()),
+            key: Keypair { secret, public },
+        })
+    }
+
+    pub fn algorithm(&self) -> &'static str {
+        match self {
+            SigningKey::Rsa { .. } => "RS256",
+            SigningKey::Ed25519 { .. } => "EdDSA",
+        }
+    }
+
+    pub fn sign_jwt(&self, claims: &impl Serialize) -> Result<String, String> {
+        let (SigningKey::Rsa { kid, .. } | SigningKey::Ed25519 { kid, .. }) = self;
+        let mut token = format!(
+            "{}.{}",
+            base64url(
+                &serde_json::to_vec(&JwtHeader {
+                    alg: self.algorithm(),
+                    typ: "JWT",
+                    kid,
+                })
+                .map_err(|err| err.to_string())?
+            ),
+            base64url(&serde_json::to_vec(claims).map_err(|err| err.to_string())?)
+        );
+
+        let signature = match self {
+            SigningKey::Rsa { key, .. } => key
+                .sign(
+                    PaddingScheme::new_pkcs1v15_sign(Some(Hash::SHA2_256)),
+                    &Sha256::digest(token.as_bytes()),
+                )
+                .map_err(|err| err.to_string())?,
+            SigningKey::Ed25519 { key, .. } => key.sign(token.as_bytes()).to_bytes().to_vec(),
+        };
+        token.push('.');
+        token.push_str(&base64url(&signature));
+
+        Ok(token)
+    }
+
+    pub fn jwk(&self) -> JsonWebKey {
+        match self {
+            SigningKey::Rsa { key, kid } => JsonWebKey {
+                kty: "RSA".to_string(),
+                kid: kid.to_string(),
+                use_: "sig".to_string(),
+                alg: self.algorithm().to_string(),
+                n: base64url(&key.n().to_bytes_be()).into(),
+                e: base64url(&key.e().to_bytes_be()).into(),
+                crv: None,
+                x: None,
+            },
+            SigningKey::Ed25519 { key, kid } => JsonWebKey {
+                kty: "OKP".to_string(),
+                kid: kid.to_string(),
+                use_: "sig".to_string(),
+                alg: self.algorithm().to_string(),
+                n: None,
+                e: None,
+                crv: "Ed25519".to_string().into(),
+                x: base64url(key.public.as_bytes()).into(),
+            },
+        }
+    }
+}
+
+impl JsonWebKeySet {
+    /// Verifies the signature of a JWT and returns its decoded claims.
+    pub fn verify_jwt<C: DeserializeOwned>(&self, token: &str) -> Result<C, String> {
+        let (message, signature) = token.rsplit_once('.').ok_or("Malformed JWT")?;
+        let (header, claims) = message.split_once('.').ok_or("Malformed JWT")?;
+        let header: JwtHeaderOwned = serde_json::from_slice(&base64url_decode(header)?)
+            .map_err(|err| format!("Invalid JWT header: {}", err))?;
+        let signature = base64url_decode(signature)?;
+        let jwk = self
+            .keys
+            .iter()
+            .find(|jwk| {
+                jwk.alg == header.alg && header.kid.as_ref().map_or(true, |kid| kid == &jwk.kid)
+            })
+            .ok_or_else(|| format!("No key found for algorithm {}", header.alg))?;
+
+        match (jwk.alg.as_str(), &jwk.n, &jwk.e, &jwk.x) {
+            ("RS256", Some(n), Some(e), _) => RsaPublicKey::new(
+                BigUint::from_bytes_be(&base64url_decode(n)?),
+                BigUint::from_bytes_be(&base64url_decode(e)?),
+            )
+            .and_then(|key| {
+                key.verify(
+                    PaddingScheme::new_pkcs1v15_sign(Some(Hash::SHA2_256)),
+                    &Sha256::digest(message.as_bytes()),
+                    &signature,
+                )
+            })
+            .map_err(|err| err.to_string())?,
+            ("EdDSA", _, _, Some(x)) => PublicKey::from_bytes(&base64url_decode(x)?)
+                .and_then(|key| {
+                    key.verify(
+                        message.as_bytes(),
+                        &Signature::try_from(signature.as_slice())?,
+                    )
+                })
+                .map_err(|err| err.to_string())?,
+            (alg, _, _, _) => return Err(format!("Unsupported algorithm {}", alg)),
+        }
+
+        serde_json::from_slice(&base64url_decode(claims)?)
+            .map_err(|err| format!("Invalid JWT claims: {}", err))
+    }
+}
+
+impl OpenIdMetadata {
+    pub fn new(base_url: &str, signing_key: &SigningKey) -> Self {
+        OpenIdMetadata {
+            oauth: OAuthMetadata::new(base_url, true),
+            userinfo_endpoint: format!("{}/auth/userinfo", base_url),
+            subject_types_supported: vec!["public".to_string()],
+            id_token_signing_alg_values_supported: vec![signing_key.algorithm().to_string()],
+            claims_supported: vec![
+                "iss".to_string(),
+                "sub".to_string(),
+                "aud".to_string(),
+                "exp".to_string(),
+                "iat".to_string(),
+                "nonce".to_string(),
+                "name".to_string(),
+                "email".to_string(),
+                "picture".to_string(),
+            ],
+        }
+    }
+}
+
+pub fn base64url(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+fn base64url_decode(value: &str) -> Result<Vec<u8>, String> {
+    base64::decode_config(value, base64::URL_SAFE_NO_PAD)
+        .map_err(|err| format!("Failed to decode JWT: {}", err))
+}
+
+fn key_id(public_key: &[u8]) -> String {
+    base64url(&Sha256::digest(public_key)[..8])
+}
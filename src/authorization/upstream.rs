@@ -0,0 +1,476 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::{Duration, SystemTime};
+
+use crate::JMAPServer;
+use actix_web::{
+    cookie::{Cookie, SameSite},
+    http::header,
+    web, HttpRequest, HttpResponse,
+};
+use jmap::base64;
+use jmap_sharing::principal::{account::JMAPAccountStore, set::JMAPSetPrincipal};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use store::{
+    bincode,
+    config::env_settings::EnvSettings,
+    core::error::StoreError,
+    rand::{distributions::Alphanumeric, thread_rng, Rng},
+    sha2::{Digest, Sha256},
+    tracing::debug,
+    AccountId, Store,
+};
+use tokio::sync::OnceCell;
+
+use super::{oauth::CodeAuthRequest, oidc::base64url, oidc::JsonWebKeySet, SymmetricEncrypt};
+
+const STATE_CONTEXT: &str = "upstream state";
+const NONCE_LEN: usize = 32;
+const VERIFIER_LEN: usize = 43;
+const BINDING_LEN: usize = 32;
+const BINDING_COOKIE: &str = "upstream_binding";
+
+pub struct UpstreamProvider {
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub scopes: String,
+    pub auto_provision: bool,
+    pub require_verified_email: bool,
+    pub endpoints: OnceCell<UpstreamEndpoints>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpstreamEndpoints {
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UpstreamCallback {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct UpstreamState {
+    request: CodeAuthRequest,
+    nonce: String,
+    code_verifier: String,
+    binding: String,
+    expiry: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct UpstreamTokenResponse {
+    id_token: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Audience {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+#[derive(Debug, Deserialize)]
+struct UpstreamClaims {
+    iss: String,
+    aud: Audience,
+    exp: u64,
+    nonce: Option<String>,
+    email: Option<String>,
+    email_verified: Option<bool>,
+    name: Option<String>,
+}
+
+impl UpstreamProvider {
+    pub fn new(settings: &EnvSettings) -> Option<Self> {
+        let issuer = settings.get("oidc-upstream-issuer")?;
+        let endpoints =
+            if let (Some(authorization_endpoint), Some(token_endpoint), Some(jwks_uri)) = (
+                settings.get("oidc-upstream-auth-endpoint"),
+                settings.get("oidc-upstream-token-endpoint"),
+                settings.get("oidc-upstream-jwks-uri"),
+            ) {
+                OnceCell::new_with(Some(UpstreamEndpoints {
+                    authorization_endpoint,
+                    token_endpoint,
+                    jwks_uri,
+                }))
+            } else {
+                OnceCell::new()
+            };
+
+        Some(UpstreamProvider {
+            client_id: settings
+                .get("oidc-upstream-client-id")
+                .unwrap_or_else(|| "stalwart-jmap".to_string()),
+            client_secret: settings.get("oidc-upstream-client-secret"),
+            scopes: settings
+                .get("oidc-upstream-scopes")
+                .unwrap_or_else(|| "openid email profile".to_string()),
+            auto_provision: settings
+                .parse("oidc-upstream-auto-provision")
+                .unwrap_or(false),
+            require_verified_email: settings
+                .parse("oidc-upstream-require-verified-email")
+                .unwrap_or(true),
+            endpoints,
+            issuer: issuer.trim_end_matches('/').to_string(),
+        })
+    }
+
+    // Endpoints not present in the configuration are obtained from the
+    // provider's discovery document on first use.
+    async fn endpoints(&self) -> Result<&UpstreamEndpoints, String> {
+        self.endpoints
+            .get_or_try_init(|| async {
+                serde_json::from_slice::<UpstreamEndpoints>(
+                    &http_client()
+                        .get(format!("{}/.well-known/openid-configuration", self.issuer))
+                        .send()
+                        .await
+                        .map_err(|err| err.to_string())?
+                        .bytes()
+                        .await
+                        .map_err(|err| err.to_string())?,
+                )
+                .map_err(|err| format!("Invalid discovery document: {}", err))
+            })
+            .await
+    }
+}
+
+// Redirects an authorization request to the upstream identity provider
+pub async fn handle_upstream_redirect<T>(
+    core: web::Data<JMAPServer<T>>,
+    request: CodeAuthRequest,
+) -> HttpResponse
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    let upstream = core.oauth.upstream.as_ref().unwrap();
+    let endpoints = match upstream.endpoints().await {
+        Ok(endpoints) => endpoints,
+        Err(err) => {
+            debug!(
+                "Failed to obtain upstream OpenID Connect endpoints: {}",
+                err
+            );
+            return HttpResponse::ServiceUnavailable().body("Identity provider is unavailable");
+        }
+    };
+
+    let state = UpstreamState {
+        request,
+        nonce: random_string(NONCE_LEN),
+        code_verifier: random_string(VERIFIER_LEN),
+        binding: random_string(BINDING_LEN),
+        expiry: now() + core.oauth.expiry_auth_code,
+    };
+    let redirect_uri = callback_uri(&core);
+    let code_challenge = base64url(&Sha256::digest(state.code_verifier.as_bytes()));
+    let encoded_state = core.encode_upstream_state(&state);
+    let auth_url = match reqwest::Url::parse_with_params(
+        &endpoints.authorization_endpoint,
+        &[
+            ("response_type", "code"),
+            ("client_id", upstream.client_id.as_str()),
+            ("redirect_uri", redirect_uri.as_str()),
+            ("scope", upstream.scopes.as_str()),
+            ("nonce", state.nonce.as_str()),
+            ("code_challenge", code_challenge.as_str()),
+            ("code_challenge_method", "S256"),
+            ("state", encoded_state.as_str()),
+        ],
+    ) {
+        Ok(auth_url) => auth_url,
+        Err(err) => {
+            debug!("Invalid upstream authorization endpoint: {}", err);
+            return HttpResponse::ServiceUnavailable().body("Identity provider is unavailable");
+        }
+    };
+
+    // Bind the state to the browser that started the login
+    HttpResponse::build(StatusCode::TEMPORARY_REDIRECT)
+        .insert_header((header::LOCATION, auth_url.to_string()))
+        .cookie(
+            binding_cookie(&core, state.binding)
+                .max_age(actix_web::cookie::time::Duration::seconds(
+                    core.oauth.expiry_auth_code as i64,
+                ))
+                .finish(),
+        )
+        .finish()
+}
+
+// Handles the redirect from the upstream identity provider
+pub async fn handle_upstream_callback<T>(
+    request: HttpRequest,
+    core: web::Data<JMAPServer<T>>,
+    params: web::Query<UpstreamCallback>,
+) -> HttpResponse
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    let params = params.into_inner();
+    let state = match params
+        .state
+        .as_deref()
+        .and_then(|state| core.decode_upstream_state(state))
+    {
+        Some(state)
+            if state.expiry > now()
+                && core.oauth.upstream.is_some()
+                && request
+                    .cookie(BINDING_COOKIE)
+                    .map_or(false, |cookie| cookie.value() == state.binding) =>
+        {
+            state
+        }
+        _ => {
+            return HttpResponse::BadRequest().body("Invalid or expired state.");
+        }
+    };
+
+    let auth_code = match (params.code, params.error) {
+        (Some(code), None) => match core.upstream_authenticate(&code, &state).await {
+            Ok(account_id) => core
+                .create_auth_code(&state.request, account_id)
+                .await
+                .into(),
+            Err(err) => {
+                debug!("Upstream authentication failed: {}", err);
+                None
+            }
+        },
+        (_, error) => {
+            debug!("Upstream identity provider returned error {:?}", error);
+            None
+        }
+    };
+
+    let mut cookie = binding_cookie(&core, String::new()).finish();
+    cookie.make_removal();
+
+    HttpResponse::build(StatusCode::TEMPORARY_REDIRECT)
+        .insert_header((
+            header::LOCATION,
+            state.request.redirect_link(auth_code.as_deref()),
+        ))
+        .cookie(cookie)
+        .finish()
+}
+
+impl<T> JMAPServer<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    async fn upstream_authenticate(
+        &self,
+        code: &str,
+        state: &UpstreamState,
+    ) -> store::Result<AccountId> {
+        let upstream = self.oauth.upstream.as_ref().unwrap();
+        let endpoints = upstream
+            .endpoints()
+            .await
+            .map_err(StoreError::InternalError)?;
+
+        // Exchange the authorization code for an ID token
+        let callback_uri = callback_uri(self);
+        let mut params = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", callback_uri.as_str()),
+            ("client_id", upstream.client_id.as_str()),
+            ("code_verifier", state.code_verifier.as_str()),
+        ];
+        if let Some(client_secret) = &upstream.client_secret {
+            params.push(("client_secret", client_secret.as_str()));
+        }
+        let response = serde_json::from_slice::<UpstreamTokenResponse>(
+            &http_client()
+                .post(&endpoints.token_endpoint)
+                .form(&params)
+                .send()
+                .await
+                .map_err(|err| StoreError::InternalError(err.to_string()))?
+                .bytes()
+                .await
+                .map_err(|err| StoreError::InternalError(err.to_string()))?,
+        )
+        .map_err(|err| StoreError::DeserializeError(format!("Invalid token response: {}", err)))?;
+        let id_token = response.id_token.ok_or_else(|| {
+            StoreError::DeserializeError(format!(
+                "Token endpoint did not return an ID token: {:?}",
+                response.error
+            ))
+        })?;
+
+        // Validate the ID token
+        let jwks = serde_json::from_slice::<JsonWebKeySet>(
+            &http_client()
+                .get(&endpoints.jwks_uri)
+                .send()
+                .await
+                .map_err(|err| StoreError::InternalError(err.to_string()))?
+                .bytes()
+                .await
+                .map_err(|err| StoreError::InternalError(err.to_string()))?,
+        )
+        .map_err(|err| StoreError::DeserializeError(format!("Invalid JWKS: {}", err)))?;
+        let claims = jwks
+            .verify_jwt::<UpstreamClaims>(&id_token)
+            .map_err(StoreError::DeserializeError)?;
+        if claims.iss.trim_end_matches('/') != upstream.issuer {
+            return Err(StoreError::DeserializeError(format!(
+                "Unexpected issuer {}",
+                claims.iss
+            )));
+        } else if !match &claims.aud {
+            Audience::Single(aud) => aud == &upstream.client_id,
+            Audience::Multiple(aud) => aud.contains(&upstream.client_id),
+        } {
+            return Err(StoreError::DeserializeError(format!(
+                "Unexpected audience {:?}",
+                claims.aud
+            )));
+        } else if claims.exp <= now() {
+            return Err(StoreError::DeserializeError("ID token expired".into()));
+        } else if claims.nonce.as_ref() != Some(&state.nonce) {
+            return Err(StoreError::DeserializeError("Nonce mismatch".into()));
+        } else if upstream.require_verified_email && claims.email_verified != Some(true) {
+            return Err(StoreError::DeserializeError(
+                "E-mail is not verified".into(),
+            ));
+        }
+        let email = claims
+            .email
+            .ok_or_else(|| StoreError::DeserializeError("ID token has no email claim".into()))?
+            .to_lowercase();
+
+        // Find the account or provision it
+        let store = self.store.clone();
+        let auto_provision = upstream.auto_provision;
+        let (account_id, change_id) = self
+            .spawn_worker(move || {
+                if let Some(account_id) = store.find_individual(&email)? {
+                    Ok((account_id, None))
+                } else if auto_provision {
+                    store.principal_provision(&email, claims.name.as_deref().unwrap_or(&email))
+                } else {
+                    Err(StoreError::NotFound(format!(
+                        "Account {} does not exist",
+                        email
+                    )))
+                }
+            })
+            .await?;
+        if let Some(change_id) = change_id {
+            if self.is_in_cluster() && !self.commit_index(change_id).await {
+                return Err(StoreError::InternalError(
+                    "Failed to commit provisioned account.".to_string(),
+                ));
+            }
+        }
+
+        Ok(account_id)
+    }
+
+    fn encode_upstream_state(&self, state: &UpstreamState) -> String {
+        let nonce = thread_rng().gen::<[u8; SymmetricEncrypt::NONCE_LEN]>();
+        let mut bytes = nonce.to_vec();
+        bytes.extend(
+            SymmetricEncrypt::new(self.oauth.key.as_bytes(), STATE_CONTEXT)
+                .encrypt(&bincode::serialize(state).unwrap_or_default(), &nonce)
+                .unwrap_or_default(),
+        );
+        base64::encode_config(&bytes, base64::URL_SAFE_NO_PAD)
+    }
+
+    fn decode_upstream_state(&self, state: &str) -> Option<UpstreamState> {
+        let bytes = base64::decode_config(state, base64::URL_SAFE_NO_PAD).ok()?;
+        if bytes.len() <= SymmetricEncrypt::NONCE_LEN {
+            return None;
+        }
+        let (nonce, bytes) = bytes.split_at(SymmetricEncrypt::NONCE_LEN);
+        bincode::deserialize(
+            &SymmetricEncrypt::new(self.oauth.key.as_bytes(), STATE_CONTEXT)
+                .decrypt(bytes, nonce)
+                .ok()?,
+        )
+        .ok()
+    }
+}
+
+fn callback_uri<T>(core: &JMAPServer<T>) -> String
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    format!("{}/auth/callback", core.base_session.base_url())
+}
+
+fn binding_cookie<T>(
+    core: &JMAPServer<T>,
+    binding: String,
+) -> actix_web::cookie::CookieBuilder<'static>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    // SameSite=Lax is required for the cookie to be sent on the redirect
+    // from the identity provider.
+    Cookie::build(BINDING_COOKIE, binding)
+        .path("/auth/callback")
+        .http_only(true)
+        .secure(core.base_session.base_url().starts_with("https://"))
+        .same_site(SameSite::Lax)
+}
+
+fn http_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(30))
+        .build()
+        .unwrap_or_default()
+}
+
+fn random_string(len: usize) -> String {
+    thread_rng()
+        .sample_iter(Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
@@ -48,7 +48,7 @@ where
             let (account_id, collection) =
                 if let Some((account_id, collections)) = changed_accounts.last_mut() {
                     if let Some(collection) = collections.pop() {
-                        if matches!(collection, Collection::Thread) {
+                        if matches!(collection, Collection::Thread | Collection::Quota) {
                             continue;
                         }
                         (*account_id, collection)
@@ -27,10 +27,15 @@ use crate::cluster::log::{DocumentUpdate, Update};
 use crate::JMAPServer;
 use jmap::principal::schema::Principal;
 use jmap::push_subscription::schema::PushSubscription;
+use jmap_calendars::calendar::schema::Calendar;
+use jmap_calendars::calendar_event::schema::CalendarEvent;
+use jmap_contacts::address_book::schema::AddressBook;
+use jmap_contacts::contact_card::schema::ContactCard;
 use jmap_mail::email_submission::schema::EmailSubmission;
 use jmap_mail::identity::schema::Identity;
 use jmap_mail::mail::schema::Email;
 use jmap_mail::mailbox::schema::Mailbox;
+use jmap_mail::sieve_script::schema::SieveScript;
 use jmap_mail::vacation_response::schema::VacationResponse;
 use store::core::collection::Collection;
 use store::core::error::StoreError;
@@ -93,9 +98,26 @@ where
                         document_id,
                         is_insert,
                     ),
-                    Collection::Thread | Collection::None => Err(StoreError::InternalError(
-                        "Unsupported collection for changes".into(),
-                    )),
+                    Collection::SieveScript => {
+                        store.raft_prepare_update::<SieveScript>(account_id, document_id, is_insert)
+                    }
+                    Collection::AddressBook => {
+                        store.raft_prepare_update::<AddressBook>(account_id, document_id, is_insert)
+                    }
+                    Collection::ContactCard => {
+                        store.raft_prepare_update::<ContactCard>(account_id, document_id, is_insert)
+                    }
+                    Collection::Calendar => {
+                        store.raft_prepare_update::<Calendar>(account_id, document_id, is_insert)
+                    }
+                    Collection::CalendarEvent => store.raft_prepare_update::<CalendarEvent>(
+                        account_id,
+                        document_id,
+                        is_insert,
+                    ),
+                    Collection::Thread | Collection::Quota | Collection::None => Err(
+                        StoreError::InternalError("Unsupported collection for changes".into()),
+                    ),
                 })
                 .await?;
 
@@ -28,6 +28,14 @@ use jmap::orm::TinyORM;
 use jmap::principal::schema::Principal;
 use jmap::push_subscription::schema::PushSubscription;
 use jmap::push_subscription::set::JMAPSetPushSubscription;
+use jmap_calendars::calendar::schema::Calendar;
+use jmap_calendars::calendar::set::JMAPSetCalendar;
+use jmap_calendars::calendar_event::schema::CalendarEvent;
+use jmap_calendars::calendar_event::set::JMAPSetCalendarEvent;
+use jmap_contacts::address_book::schema::AddressBook;
+use jmap_contacts::address_book::set::JMAPSetAddressBook;
+use jmap_contacts::contact_card::schema::ContactCard;
+use jmap_contacts::contact_card::set::JMAPSetContactCard;
 use jmap_mail::email_submission::schema::EmailSubmission;
 use jmap_mail::email_submission::set::JMAPSetEmailSubmission;
 use jmap_mail::identity::schema::Identity;
@@ -36,6 +44,8 @@ use jmap_mail::mail::schema::Email;
 use jmap_mail::mail::set::JMAPSetMail;
 use jmap_mail::mailbox::schema::Mailbox;
 use jmap_mail::mailbox::set::JMAPSetMailbox;
+use jmap_mail::sieve_script::schema::SieveScript;
+use jmap_mail::sieve_script::set::JMAPSetSieveScript;
 use jmap_mail::vacation_response::schema::VacationResponse;
 use jmap_mail::vacation_response::set::JMAPSetVacationResponse;
 use jmap_sharing::principal::set::JMAPSetPrincipal;
@@ -99,7 +109,14 @@ where
             Collection::VacationResponse => {
                 self.raft_apply_update::<VacationResponse>(write_batch, update)
             }
-            Collection::Thread | Collection::None => {
+            Collection::SieveScript => self.raft_apply_update::<SieveScript>(write_batch, update),
+            Collection::AddressBook => self.raft_apply_update::<AddressBook>(write_batch, update),
+            Collection::ContactCard => self.raft_apply_update::<ContactCard>(write_batch, update),
+            Collection::Calendar => self.raft_apply_update::<Calendar>(write_batch, update),
+            Collection::CalendarEvent => {
+                self.raft_apply_update::<CalendarEvent>(write_batch, update)
+            }
+            Collection::Thread | Collection::Quota | Collection::None => {
                 debug_assert!(false, "Unsupported update for {:?}", collection);
                 Ok(())
             }
@@ -200,7 +217,20 @@ where
             Collection::VacationResponse => {
                 self.vacation_response_delete(write_batch.account_id, &mut document)?
             }
-            Collection::Thread | Collection::None => unreachable!(),
+            Collection::SieveScript => {
+                self.sieve_script_delete(write_batch.account_id, &mut document)?
+            }
+            Collection::AddressBook => {
+                self.address_book_delete(write_batch.account_id, &mut document)?
+            }
+            Collection::ContactCard => {
+                self.contact_card_delete(write_batch.account_id, &mut document)?
+            }
+            Collection::Calendar => self.calendar_delete(write_batch.account_id, &mut document)?,
+            Collection::CalendarEvent => {
+                self.calendar_event_delete(write_batch.account_id, &mut document)?
+            }
+            Collection::Thread | Collection::Quota | Collection::None => unreachable!(),
         }
         write_batch.delete_document(document);
         Ok(())
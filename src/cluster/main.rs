@@ -89,6 +89,15 @@ where
                 rpc::Request::Command { command } => {
                     self.handle_command(command, response_tx).await;
                 }
+                rpc::Request::InvalidateSessions {
+                    account_id,
+                    credential_id,
+                } => {
+                    self.core.invalidate_sessions(account_id, credential_id);
+                    response_tx
+                        .send(rpc::Response::None)
+                        .unwrap_or_else(|_| error!("Oneshot response channel closed."));
+                }
                 _ => response_tx
                     .send(rpc::Response::None)
                     .unwrap_or_else(|_| error!("Oneshot response channel closed.")),
@@ -142,6 +151,18 @@ where
             } => {
                 self.send_command(command, response_tx).await;
             }
+            Event::InvalidateSessions {
+                account_id,
+                credential_id,
+            } => {
+                for peer in &self.peers {
+                    peer.dispatch_request(rpc::Request::InvalidateSessions {
+                        account_id,
+                        credential_id,
+                    })
+                    .await;
+                }
+            }
             Event::Shutdown => return Ok(false),
 
             #[cfg(test)]
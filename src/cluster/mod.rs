@@ -32,7 +32,7 @@ use store::log::raft::{LogIndex, RaftId, TermId};
 use store::{
     bincode,
     serialize::{StoreDeserialize, StoreSerialize},
-    Store,
+    AccountId, Store,
 };
 use tokio::sync::{mpsc, oneshot, watch};
 use tokio_rustls::TlsConnector;
@@ -127,6 +127,10 @@ pub enum Event {
         command: Command,
         response_tx: oneshot::Sender<CommandResponse>,
     },
+    InvalidateSessions {
+        account_id: AccountId,
+        credential_id: Option<u64>,
+    },
     StepDown {
         term: TermId,
     },
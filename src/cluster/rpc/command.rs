@@ -21,16 +21,14 @@
  * for more details.
 */
 
+use jmap_mail::mailbox::uids::MailboxUids;
 use jmap_sharing::principal::account::JMAPAccountStore;
 use serde::{Deserialize, Serialize};
-use store::{
-    ahash::{AHashMap, AHashSet},
-    tracing::error,
-    AccountId, RecipientType, Store,
-};
+use store::{ahash::AHashMap, tracing::error, AccountId, DocumentId, RecipientType, Store};
 use tokio::sync::oneshot;
 
 use crate::{
+    api::request::handle_internal_request,
     cluster::{self, Cluster},
     lmtp::ingest::DeliveryStatus,
     JMAPServer,
@@ -45,8 +43,18 @@ pub enum Command {
     },
     IngestMessage {
         mail_from: String,
-        rcpt_to: AHashSet<AccountId>,
+        rcpt_to: AHashMap<AccountId, String>,
         raw_message: Vec<u8>,
+        is_phishing: bool,
+    },
+    JMAPRequest {
+        account_id: AccountId,
+        request: Vec<u8>,
+        blobs: Vec<Vec<u8>>,
+    },
+    SyncMailboxUids {
+        account_id: AccountId,
+        mailbox_id: DocumentId,
     },
 }
 
@@ -58,6 +66,12 @@ pub enum CommandResponse {
     IngestMessage {
         result: Result<AHashMap<AccountId, DeliveryStatus>, String>,
     },
+    JMAPRequest {
+        result: Result<Vec<u8>, String>,
+    },
+    SyncMailboxUids {
+        uids: Option<MailboxUids>,
+    },
     Error {
         message: String,
     },
@@ -126,8 +140,30 @@ where
                         mail_from,
                         rcpt_to,
                         raw_message,
+                        is_phishing,
                     } => CommandResponse::IngestMessage {
-                        result: core.mail_ingest(mail_from, rcpt_to, raw_message).await,
+                        result: core
+                            .mail_ingest(mail_from, rcpt_to, raw_message, is_phishing)
+                            .await,
+                    },
+                    Command::JMAPRequest {
+                        account_id,
+                        request,
+                        blobs,
+                    } => CommandResponse::JMAPRequest {
+                        result: handle_internal_request(core, account_id, request, blobs).await,
+                    },
+                    Command::SyncMailboxUids {
+                        account_id,
+                        mailbox_id,
+                    } => match core.mailbox_uids_sync(account_id, mailbox_id).await {
+                        Ok(uids) => CommandResponse::SyncMailboxUids { uids },
+                        Err(err) => {
+                            error!("Failed to synchronize mailbox UIDs: {}", err);
+                            CommandResponse::Error {
+                                message: "Temporary database failure".to_string(),
+                            }
+                        }
                     },
                 };
 
@@ -35,6 +35,7 @@ use super::{gossip::PeerInfo, PeerId};
 use serde::{Deserialize, Serialize};
 use store::log::raft::{RaftId, TermId};
 use store::tracing::error;
+use store::AccountId;
 use tokio::sync::oneshot;
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -61,6 +62,10 @@ pub enum Request {
     Command {
         command: Command,
     },
+    InvalidateSessions {
+        account_id: AccountId,
+        credential_id: Option<u64>,
+    },
     Ping,
     None,
 }
@@ -0,0 +1,138 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap::types::{blob::JMAPBlob, jmap::JMAPId};
+use serde_json::{json, Map, Value};
+use store::{blob::BlobId, Store};
+
+use super::{
+    flag_to_keyword, mailbox::set_error, parse_date_time, receiver::Request,
+    response::StatusResponse, session::Session,
+};
+
+impl<T> Session<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    pub async fn handle_append(&mut self, request: Request) -> super::Result<StatusResponse> {
+        let mut tokens = request.tokens;
+        let raw_message = match tokens.pop() {
+            Some(token) if !tokens.is_empty() => token.unwrap_bytes(),
+            _ => return Ok(StatusResponse::bad("Expected mailbox name and message.")),
+        };
+        let mut tokens = tokens.into_iter().peekable();
+        let name = match tokens.next().map(|t| t.unwrap_string()) {
+            Some(Ok(name)) => name,
+            _ => return Ok(StatusResponse::bad("Invalid mailbox name.")),
+        };
+
+        // Parse flags and internal date
+        let mut keywords = Map::new();
+        let mut received_at = None;
+        while let Some(token) = tokens.next() {
+            if token.is_parenthesis_open() {
+                for token in tokens.by_ref() {
+                    if token.is_parenthesis_close() {
+                        break;
+                    } else if let Some(keyword) =
+                        flag_to_keyword(&String::from_utf8_lossy(token.as_bytes()))
+                    {
+                        keywords.insert(keyword, Value::Bool(true));
+                    }
+                }
+            } else if let Some(date) = parse_date_time(&String::from_utf8_lossy(token.as_bytes())) {
+                received_at = date.to_rfc3339().into();
+            } else {
+                return Ok(StatusResponse::bad(format!(
+                    "Invalid APPEND argument '{}'.",
+                    token
+                )));
+            }
+        }
+
+        let mailbox = match self.get_mailbox(&name).await? {
+            Some(mailbox) if mailbox.may_write => mailbox,
+            Some(_) => {
+                return Ok(StatusResponse::no(
+                    "You are not allowed to add messages to this mailbox.",
+                )
+                .with_code("NOPERM"))
+            }
+            None => {
+                return Ok(StatusResponse::no("Destination mailbox does not exist.")
+                    .with_code("TRYCREATE"))
+            }
+        };
+        if raw_message.is_empty() {
+            return Ok(StatusResponse::no("Message is empty."));
+        }
+
+        // Import the message
+        let blob_id = JMAPBlob::new(BlobId::new_external(&raw_message)).to_string();
+        let mut email = json!({
+            "blobId": blob_id,
+            "mailboxIds": { mailbox.jmap_id(): true },
+            "keywords": keywords,
+        });
+        if let Some(received_at) = received_at {
+            email["receivedAt"] = received_at.into();
+        }
+        let response = self
+            .jmap_with_blobs(
+                vec![(
+                    "Email/import",
+                    json!({
+                        "accountId": JMAPId::from(self.account_id()),
+                        "emails": { "m": email },
+                    }),
+                )],
+                vec![raw_message],
+            )
+            .await?;
+        let response = response.first();
+        let id = match response
+            .and_then(|r| r.get("created"))
+            .and_then(|c| c.get("m"))
+            .and_then(|c| c.get("id"))
+            .and_then(|id| id.as_str())
+            .and_then(JMAPId::parse)
+        {
+            Some(id) => u64::from(id),
+            None => return Ok(set_error(response, "notCreated")),
+        };
+
+        // Obtain the UID assigned to the message
+        let uids = self.sync_uids(mailbox.id).await?;
+        let mut response = StatusResponse::ok("APPEND completed.");
+        if let Some(item) = uids.get_by_id(id) {
+            response = response.with_code(format!("APPENDUID {} {}", uids.uid_validity, item.uid));
+        }
+
+        // Notify the client of the new message if the mailbox is selected
+        if self.selected.as_ref().map_or(false, |s| s.id == mailbox.id) {
+            self.synchronize().await?;
+        }
+
+        Ok(response)
+    }
+}
@@ -0,0 +1,328 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap::base64;
+use jmap_sharing::principal::account::JMAPAccountStore;
+use store::{
+    tracing::{debug, error},
+    AccountId, Store,
+};
+
+use crate::authorization::auth::RemoteAddress;
+
+use super::{
+    receiver::{Request, Token},
+    response::StatusResponse,
+    session::{Account, PendingAuth, Session},
+};
+
+impl<T> Session<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    pub fn capabilities(&self) -> String {
+        let mut capabilities = String::from(concat!(
+            "IMAP4rev2 IMAP4rev1 ENABLE SASL-IR LITERAL+ ID UIDPLUS MOVE IDLE ",
+            "NAMESPACE UNSELECT CHILDREN SPECIAL-USE LIST-EXTENDED LIST-STATUS ",
+            "CONDSTORE QRESYNC ESEARCH STATUS=SIZE"
+        ));
+        if self.account.is_none() {
+            if self.is_tls_required() {
+                capabilities.push_str(" STARTTLS LOGINDISABLED");
+            } else {
+                capabilities.push_str(" AUTH=PLAIN AUTH=OAUTHBEARER");
+            }
+        }
+        capabilities
+    }
+
+    // Credentials and tokens are not accepted in cleartext when STARTTLS is available
+    fn is_tls_required(&self) -> bool {
+        !self.stream.is_tls() && self.tls_acceptor.is_some()
+    }
+
+    pub async fn handle_capability(&mut self) -> super::Result<StatusResponse> {
+        self.write_bytes(format!("* CAPABILITY {}\r\n", self.capabilities()).as_bytes())
+            .await?;
+        Ok(StatusResponse::ok("CAPABILITY completed."))
+    }
+
+    pub async fn handle_id(&mut self) -> super::Result<StatusResponse> {
+        self.write_bytes(
+            concat!(
+                "* ID (\"name\" \"Stalwart JMAP\" \"version\" \"",
+                env!("CARGO_PKG_VERSION"),
+                "\" \"vendor\" \"Stalwart Labs Ltd.\")\r\n"
+            )
+            .as_bytes(),
+        )
+        .await?;
+        Ok(StatusResponse::ok("ID completed."))
+    }
+
+    pub async fn handle_starttls(&mut self, tag: String) -> super::Result<StatusResponse> {
+        if self.stream.is_tls() {
+            return Ok(StatusResponse::bad("Already in TLS mode."));
+        } else if self.tls_acceptor.is_none() {
+            return Ok(StatusResponse::no("TLS not configured on this server."));
+        }
+
+        self.write_bytes(
+            &StatusResponse::ok("Begin TLS negotiation now.")
+                .with_tag(tag)
+                .into_bytes(),
+        )
+        .await?;
+        match self
+            .tls_acceptor
+            .as_ref()
+            .unwrap()
+            .accept(std::mem::take(&mut self.stream).unwrap_clear())
+            .await
+        {
+            Ok(stream) => {
+                self.stream = stream.into();
+                Ok(StatusResponse::deferred())
+            }
+            Err(e) => {
+                debug!("Failed to accept TLS connection: {}", e);
+                Err(StatusResponse::disconnect())
+            }
+        }
+    }
+
+    pub async fn handle_login(&mut self, request: Request) -> super::Result<StatusResponse> {
+        if self.is_tls_required() {
+            return Ok(privacy_required());
+        }
+        let mut tokens = request.tokens.into_iter();
+        match (
+            tokens.next().map(|t| t.unwrap_string()),
+            tokens.next().map(|t| t.unwrap_string()),
+        ) {
+            (Some(Ok(login)), Some(Ok(secret))) => self.authenticate(login, secret).await,
+            _ => Ok(StatusResponse::bad("Expected username and password.")),
+        }
+    }
+
+    pub async fn handle_authenticate(&mut self, request: Request) -> super::Result<StatusResponse> {
+        let mut tokens = request.tokens.into_iter();
+        let mechanism = match tokens.next() {
+            Some(Token::Atom(mechanism)) => String::from_utf8_lossy(&mechanism).to_uppercase(),
+            _ => return Ok(StatusResponse::bad("Expected authentication mechanism.")),
+        };
+        if !["PLAIN", "OAUTHBEARER"].contains(&mechanism.as_str()) {
+            return Ok(
+                StatusResponse::no("Authentication mechanism not supported.").with_code("CANNOT"),
+            );
+        } else if self.is_tls_required() {
+            return Ok(privacy_required());
+        }
+
+        if let Some(initial_response) = tokens.next() {
+            self.authenticate_sasl(&mechanism, initial_response.as_bytes())
+                .await
+        } else {
+            // Request the client response
+            self.write_bytes(b"+ \r\n").await?;
+            self.receiver.is_line_mode = true;
+            self.pending_auth = PendingAuth {
+                tag: request.tag,
+                mechanism,
+            }
+            .into();
+            Ok(StatusResponse::deferred())
+        }
+    }
+
+    pub async fn handle_authenticate_response(
+        &mut self,
+        pending_auth: PendingAuth,
+        line: Vec<u8>,
+    ) -> Result<StatusResponse, ()> {
+        let response = if line == b"*" {
+            StatusResponse::bad("Authentication cancelled.")
+        } else {
+            match self.authenticate_sasl(&pending_auth.mechanism, &line).await {
+                Ok(response) => response,
+                Err(response) if response.is_disconnect() => return Err(()),
+                Err(response) => response,
+            }
+        };
+        Ok(response.with_tag(pending_auth.tag))
+    }
+
+    async fn authenticate_sasl(
+        &mut self,
+        mechanism: &str,
+        response: &[u8],
+    ) -> super::Result<StatusResponse> {
+        let response = if response != b"=" {
+            match base64::decode(response) {
+                Ok(response) => response,
+                Err(_) => return Ok(StatusResponse::bad("Failed to decode SASL response.")),
+            }
+        } else {
+            Vec::new()
+        };
+
+        if mechanism == "PLAIN" {
+            // authzid NUL authcid NUL passwd
+            let mut parts = response.split(|&ch| ch == 0);
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some(_), Some(login), Some(secret)) => {
+                    self.authenticate(
+                        String::from_utf8_lossy(login).into_owned(),
+                        String::from_utf8_lossy(secret).into_owned(),
+                    )
+                    .await
+                }
+                _ => Ok(StatusResponse::bad("Invalid SASL PLAIN response.")),
+            }
+        } else {
+            // gs2-header ^A auth=Bearer <token> ^A ^A
+            let response = String::from_utf8_lossy(&response);
+            if let Some(token) = response.split('\x01').find_map(|part| {
+                part.strip_prefix("auth=")
+                    .and_then(|auth| auth.split_once(' '))
+                    .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+                    .map(|(_, token)| token.trim().to_string())
+            }) {
+                if self
+                    .core
+                    .is_auth_allowed(RemoteAddress::IpAddress(self.peer_addr.ip()))
+                    .await
+                    .is_err()
+                {
+                    return Ok(too_many_attempts());
+                }
+                match self
+                    .core
+                    .validate_access_token("access_token", &token)
+                    .await
+                {
+                    Ok((account_id, _, _, _)) => self.set_account(account_id).await,
+                    Err(err) => {
+                        debug!("Failed to validate access token: {}", err);
+                        Ok(authentication_failed())
+                    }
+                }
+            } else {
+                Ok(StatusResponse::bad("Invalid SASL OAUTHBEARER response."))
+            }
+        }
+    }
+
+    async fn authenticate(
+        &mut self,
+        login: String,
+        secret: String,
+    ) -> super::Result<StatusResponse> {
+        // Enforce rate limit for authentication requests
+        if self
+            .core
+            .is_auth_allowed(RemoteAddress::IpAddress(self.peer_addr.ip()))
+            .await
+            .is_err()
+        {
+            return Ok(too_many_attempts());
+        }
+
+        match self
+            .core
+            .authenticate_client(login.trim().to_lowercase(), secret, None)
+            .await
+        {
+            Ok(Some((account_id, _))) => self.set_account(account_id).await,
+            Ok(None) => Ok(authentication_failed()),
+            Err(err) => {
+                error!("Failed to authenticate: {}", err);
+                Err(StatusResponse::unavailable())
+            }
+        }
+    }
+
+    async fn set_account(&mut self, account_id: AccountId) -> super::Result<StatusResponse> {
+        let store = self.core.store.clone();
+        match self
+            .core
+            .spawn_worker(move || store.get_acl_token(account_id))
+            .await
+        {
+            Ok(acl) => {
+                self.account = Account { account_id, acl }.into();
+                Ok(StatusResponse::ok("Authentication successful.")
+                    .with_code(format!("CAPABILITY {}", self.capabilities())))
+            }
+            Err(err) => {
+                error!("Failed to obtain ACL token: {}", err);
+                Err(StatusResponse::unavailable())
+            }
+        }
+    }
+
+    pub async fn handle_enable(&mut self, request: Request) -> super::Result<StatusResponse> {
+        let mut enabled = Vec::new();
+        for token in request.tokens {
+            if token.eq_ignore_ascii_case("IMAP4rev2") {
+                self.is_rev2 = true;
+                enabled.push("IMAP4rev2");
+            } else if token.eq_ignore_ascii_case("CONDSTORE") {
+                self.is_condstore = true;
+                enabled.push("CONDSTORE");
+            } else if token.eq_ignore_ascii_case("QRESYNC") {
+                self.is_condstore = true;
+                self.is_qresync = true;
+                enabled.push("QRESYNC");
+            }
+        }
+        self.write_bytes(format!("* ENABLED {}\r\n", enabled.join(" ")).as_bytes())
+            .await?;
+        Ok(StatusResponse::ok("ENABLE completed."))
+    }
+
+    pub async fn handle_namespace(&mut self) -> super::Result<StatusResponse> {
+        self.write_bytes(
+            format!(
+                "* NAMESPACE ((\"\" \"{}\")) NIL NIL\r\n",
+                super::HIERARCHY_DELIMITER
+            )
+            .as_bytes(),
+        )
+        .await?;
+        Ok(StatusResponse::ok("NAMESPACE completed."))
+    }
+}
+
+fn too_many_attempts() -> StatusResponse {
+    StatusResponse::no("Too many authentication attempts, please try again later.")
+        .with_code("LIMIT")
+}
+
+fn privacy_required() -> StatusResponse {
+    StatusResponse::no("Use STARTTLS before authenticating.").with_code("PRIVACYREQUIRED")
+}
+
+fn authentication_failed() -> StatusResponse {
+    StatusResponse::no("Authentication failed.").with_code("AUTHENTICATIONFAILED")
+}
@@ -0,0 +1,286 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap::types::{blob::JMAPBlob, jmap::JMAPId};
+use jmap_mail::{
+    mail::get::{BlobResult, JMAPGetMail},
+    mailbox::uids::{JMAPMailboxUids, MailboxUids},
+};
+use serde_json::{json, Value};
+use store::{
+    ahash::AHashMap,
+    core::error::StoreError,
+    tracing::{debug, error},
+    AccountId, DocumentId, Store,
+};
+
+use crate::{
+    api::request::{execute_internal_calls, InternalCallError},
+    cluster::rpc::command::{Command, CommandResponse},
+    JMAPServer,
+};
+
+use super::{response::StatusResponse, session::Session};
+
+impl<T> Session<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    /// Executes a list of JMAP method calls and returns the arguments of each response.
+    pub async fn jmap(&self, calls: Vec<(&str, Value)>) -> super::Result<Vec<Value>> {
+        self.jmap_with_blobs(calls, Vec::new()).await
+    }
+
+    pub async fn jmap_with_blobs(
+        &self,
+        calls: Vec<(&str, Value)>,
+        blobs: Vec<Vec<u8>>,
+    ) -> super::Result<Vec<Value>> {
+        execute_internal_calls(&self.core, self.account_id(), calls, blobs)
+            .await
+            .map_err(|err| match err {
+                InternalCallError::Method(description) => StatusResponse::no(description),
+                InternalCallError::Unavailable(err) => {
+                    debug!("JMAP request from IMAP session failed: {}", err);
+                    StatusResponse::unavailable()
+                }
+            })
+    }
+
+    /// Fetches the requested properties of a list of emails, splitting the
+    /// request in chunks of up to `max_objects_in_get` ids.
+    pub async fn get_emails(
+        &self,
+        ids: &[store::JMAPId],
+        properties: &[&str],
+    ) -> super::Result<AHashMap<store::JMAPId, Value>> {
+        let mut results = AHashMap::with_capacity(ids.len());
+        for chunk in ids.chunks(self.core.store.config.max_objects_in_get) {
+            for response in self
+                .jmap(vec![(
+                    "Email/get",
+                    json!({
+                        "accountId": JMAPId::from(self.account_id()),
+                        "ids": chunk.iter().map(|id| JMAPId::new(*id)).collect::<Vec<_>>(),
+                        "properties": properties,
+                    }),
+                )])
+                .await?
+            {
+                if let Some(Value::Array(list)) = response.get("list") {
+                    for item in list {
+                        if let Some(id) = item
+                            .get("id")
+                            .and_then(|id| id.as_str())
+                            .and_then(JMAPId::parse)
+                        {
+                            results.insert(id.into(), item.clone());
+                        }
+                    }
+                }
+            }
+        }
+        Ok(results)
+    }
+
+    /// Returns all the email ids matching a filter.
+    pub async fn query_emails(&self, filter: Value) -> super::Result<Vec<store::JMAPId>> {
+        let limit = self.core.store.config.query_max_results;
+        let mut results = Vec::new();
+        loop {
+            let response = self
+                .jmap(vec![(
+                    "Email/query",
+                    json!({
+                        "accountId": JMAPId::from(self.account_id()),
+                        "filter": filter,
+                        "position": results.len(),
+                        "limit": limit,
+                    }),
+                )])
+                .await?;
+            let ids = match response.first().and_then(|r| r.get("ids")) {
+                Some(Value::Array(ids)) => ids,
+                _ => break,
+            };
+            let count = ids.len();
+            results.extend(
+                ids.iter()
+                    .filter_map(|id| id.as_str().and_then(JMAPId::parse))
+                    .map(store::JMAPId::from),
+            );
+            if count < limit {
+                break;
+            }
+        }
+        Ok(results)
+    }
+
+    /// Applies a list of updates to emails, returning the ids that were updated.
+    pub async fn update_emails(
+        &self,
+        updates: Vec<(store::JMAPId, Value)>,
+    ) -> super::Result<Vec<store::JMAPId>> {
+        let mut updated = Vec::with_capacity(updates.len());
+        for chunk in updates.chunks(self.core.store.config.max_objects_in_set) {
+            let mut update = serde_json::Map::with_capacity(chunk.len());
+            for (id, patch) in chunk {
+                update.insert(JMAPId::new(*id).to_string(), patch.clone());
+            }
+            for response in self
+                .jmap(vec![(
+                    "Email/set",
+                    json!({
+                        "accountId": JMAPId::from(self.account_id()),
+                        "update": update,
+                    }),
+                )])
+                .await?
+            {
+                if let Some(Value::Object(ids)) = response.get("updated") {
+                    updated.extend(
+                        ids.keys()
+                            .filter_map(|id| JMAPId::parse(id))
+                            .map(store::JMAPId::from),
+                    );
+                }
+            }
+        }
+        Ok(updated)
+    }
+
+    /// Destroys a list of emails, returning the ids that were destroyed.
+    pub async fn destroy_emails(&self, ids: &[store::JMAPId]) -> super::Result<Vec<store::JMAPId>> {
+        let mut destroyed = Vec::with_capacity(ids.len());
+        for chunk in ids.chunks(self.core.store.config.max_objects_in_set) {
+            for response in self
+                .jmap(vec![(
+                    "Email/set",
+                    json!({
+                        "accountId": JMAPId::from(self.account_id()),
+                        "destroy": chunk.iter().map(|id| JMAPId::new(*id)).collect::<Vec<_>>(),
+                    }),
+                )])
+                .await?
+            {
+                if let Some(Value::Array(ids)) = response.get("destroyed") {
+                    destroyed.extend(
+                        ids.iter()
+                            .filter_map(|id| id.as_str().and_then(JMAPId::parse))
+                            .map(store::JMAPId::from),
+                    );
+                }
+            }
+        }
+        Ok(destroyed)
+    }
+
+    /// Synchronizes the UID map of a mailbox with the changes log.
+    pub async fn sync_uids(&self, mailbox_id: DocumentId) -> super::Result<MailboxUids> {
+        let account_id = self.account_id();
+        let result = if self.core.is_leader() {
+            self.core.mailbox_uids_sync(account_id, mailbox_id).await
+        } else {
+            match self
+                .core
+                .rpc_command(Command::SyncMailboxUids {
+                    account_id,
+                    mailbox_id,
+                })
+                .await
+            {
+                Some(CommandResponse::SyncMailboxUids { uids }) => Ok(uids),
+                Some(CommandResponse::Error { message }) => {
+                    debug!("RPC failed: {}", message);
+                    return Err(StatusResponse::unavailable());
+                }
+                _ => return Err(StatusResponse::unavailable()),
+            }
+        };
+
+        match result {
+            Ok(Some(uids)) => Ok(uids),
+            Ok(None) => Err(StatusResponse::no("Mailbox does not exist.").with_code("NONEXISTENT")),
+            Err(err) => {
+                error!("Failed to synchronize mailbox UIDs: {}", err);
+                Err(StatusResponse::unavailable())
+            }
+        }
+    }
+
+    /// Fetches the raw message from the local blob store.
+    pub async fn get_blob(&self, blob_id: &str) -> super::Result<Option<Vec<u8>>> {
+        let blob = if let Some(blob) = JMAPBlob::parse(blob_id) {
+            blob
+        } else {
+            return Ok(None);
+        };
+        let account = self.account.as_ref().unwrap();
+        let (account_id, acl) = (account.account_id, account.acl.clone());
+        let store = self.core.store.clone();
+
+        match self
+            .core
+            .spawn_worker(move || store.mail_blob_get(account_id, &acl, &blob))
+            .await
+        {
+            Ok(BlobResult::Blob(bytes)) => Ok(Some(bytes)),
+            Ok(BlobResult::NotFound | BlobResult::Unauthorized) => Ok(None),
+            Err(err) => {
+                error!("Failed to fetch blob: {}", err);
+                Err(StatusResponse::unavailable())
+            }
+        }
+    }
+}
+
+impl<T> JMAPServer<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    pub async fn mailbox_uids_sync(
+        &self,
+        account_id: AccountId,
+        mailbox_id: DocumentId,
+    ) -> store::Result<Option<MailboxUids>> {
+        let store = self.store.clone();
+        let (uids, change_id) = match self
+            .spawn_worker(move || store.mailbox_uids_sync(account_id, mailbox_id))
+            .await?
+        {
+            Some(result) => result,
+            None => return Ok(None),
+        };
+
+        // Wait for the updated UID map to be committed
+        if let Some(change_id) = change_id {
+            if self.is_in_cluster() && !self.commit_index(change_id).await {
+                return Err(StoreError::InternalError(
+                    "Failed to commit mailbox UIDs.".to_string(),
+                ));
+            }
+        }
+
+        Ok(Some(uids))
+    }
+}
@@ -0,0 +1,142 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use serde_json::{json, Value};
+use store::Store;
+
+use super::{
+    receiver::Request,
+    response::{serialize_ordered_sequence, StatusResponse},
+    session::Session,
+    Sequence,
+};
+
+impl<T> Session<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    pub async fn handle_copy_move(&mut self, request: Request) -> super::Result<StatusResponse> {
+        let is_uid = request.command.starts_with("UID ");
+        let is_move = request.command.ends_with("MOVE");
+        let selected = self.selected.as_ref().unwrap();
+        if is_move && selected.is_read_only {
+            return Ok(StatusResponse::no("Mailbox is read-only.").with_code("READ-ONLY"));
+        }
+
+        let mut tokens = request.tokens.into_iter();
+        let (items, name) = match (
+            tokens.next().and_then(|t| Sequence::parse(t.as_bytes())),
+            tokens.next().map(|t| t.unwrap_string()),
+        ) {
+            (Some(sequence), Some(Ok(name))) => (selected.resolve(&sequence, is_uid), name),
+            _ => {
+                return Ok(StatusResponse::bad(
+                    "Expected sequence set and mailbox name.",
+                ))
+            }
+        };
+        let source_id = selected.jmap_id();
+        let source_mailbox = selected.id;
+
+        let mailbox = match self.get_mailbox(&name).await? {
+            Some(mailbox) if mailbox.may_write => mailbox,
+            Some(_) => {
+                return Ok(StatusResponse::no(
+                    "You are not allowed to add messages to this mailbox.",
+                )
+                .with_code("NOPERM"))
+            }
+            None => {
+                return Ok(StatusResponse::no("Destination mailbox does not exist.")
+                    .with_code("TRYCREATE"))
+            }
+        };
+        if items.is_empty() {
+            return Ok(StatusResponse::ok(format!(
+                "{} completed.",
+                request.command
+            )));
+        } else if is_move && mailbox.id == source_mailbox {
+            return Ok(
+                StatusResponse::no("Source and destination mailboxes are the same.")
+                    .with_code("CANNOT"),
+            );
+        }
+
+        let destination_id = mailbox.jmap_id();
+        let updated = self
+            .update_emails(
+                items
+                    .iter()
+                    .map(|(_, item)| {
+                        let mut patch = json!({
+                            format!("mailboxIds/{}", destination_id): true,
+                        });
+                        if is_move {
+                            patch[format!("mailboxIds/{}", source_id)] = Value::Null;
+                        }
+                        (item.id, patch)
+                    })
+                    .collect(),
+            )
+            .await?;
+        if updated.is_empty() {
+            return Ok(StatusResponse::no(format!("{} failed.", request.command)));
+        }
+
+        // Obtain the UIDs assigned in the destination mailbox
+        let uids = self.sync_uids(mailbox.id).await?;
+        let mut source_uids = Vec::with_capacity(updated.len());
+        let mut destination_uids = Vec::with_capacity(updated.len());
+        for (_, item) in &items {
+            if updated.contains(&item.id) {
+                if let Some(destination_item) = uids.get_by_id(item.id) {
+                    source_uids.push(item.uid);
+                    destination_uids.push(destination_item.uid);
+                }
+            }
+        }
+        let mut code = format!("COPYUID {} ", uids.uid_validity).into_bytes();
+        serialize_ordered_sequence(&mut code, &source_uids);
+        code.push(b' ');
+        serialize_ordered_sequence(&mut code, &destination_uids);
+        let code = String::from_utf8(code).unwrap_or_default();
+
+        if is_move {
+            // The COPYUID code is sent untagged, followed by the expunged messages
+            self.write_bytes(
+                &StatusResponse::ok("Moved UIDs.")
+                    .with_code(code)
+                    .into_bytes(),
+            )
+            .await?;
+            self.synchronize().await?;
+            Ok(StatusResponse::ok(format!(
+                "{} completed.",
+                request.command
+            )))
+        } else {
+            Ok(StatusResponse::ok(format!("{} completed.", request.command)).with_code(code))
+        }
+    }
+}
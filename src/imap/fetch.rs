@@ -0,0 +1,522 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use serde_json::{json, Value};
+use store::Store;
+
+use super::{
+    flags::keywords_to_flags,
+    format_date_time,
+    message::{Message, Section},
+    receiver::Request,
+    response::{serialize_sequence, write_literal, StatusResponse},
+    select::parse_number,
+    session::Session,
+    Sequence,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Attribute {
+    Uid,
+    Flags,
+    InternalDate,
+    Rfc822Size,
+    Envelope,
+    Body,
+    BodyStructure,
+    BodySection {
+        peek: bool,
+        name: String,
+        parts: Vec<u32>,
+        section: Section,
+        partial: Option<(usize, usize)>,
+    },
+    Rfc822,
+    Rfc822Header,
+    Rfc822Text,
+    ModSeq,
+}
+
+impl Attribute {
+    pub fn parse(value: &[u8]) -> Option<Vec<Attribute>> {
+        let value = std::str::from_utf8(value).ok()?;
+        let upper_value = value.to_ascii_uppercase();
+        Some(match upper_value.as_str() {
+            "ALL" => vec![
+                Attribute::Flags,
+                Attribute::InternalDate,
+                Attribute::Rfc822Size,
+                Attribute::Envelope,
+            ],
+            "FAST" => vec![
+                Attribute::Flags,
+                Attribute::InternalDate,
+                Attribute::Rfc822Size,
+            ],
+            "FULL" => vec![
+                Attribute::Flags,
+                Attribute::InternalDate,
+                Attribute::Rfc822Size,
+                Attribute::Envelope,
+                Attribute::Body,
+            ],
+            "UID" => vec![Attribute::Uid],
+            "FLAGS" => vec![Attribute::Flags],
+            "INTERNALDATE" => vec![Attribute::InternalDate],
+            "RFC822.SIZE" => vec![Attribute::Rfc822Size],
+            "ENVELOPE" => vec![Attribute::Envelope],
+            "BODY" => vec![Attribute::Body],
+            "BODYSTRUCTURE" => vec![Attribute::BodyStructure],
+            "RFC822" => vec![Attribute::Rfc822],
+            "RFC822.HEADER" => vec![Attribute::Rfc822Header],
+            "RFC822.TEXT" => vec![Attribute::Rfc822Text],
+            "MODSEQ" => vec![Attribute::ModSeq],
+            _ => {
+                let (peek, rest) = if let Some(rest) = upper_value.strip_prefix("BODY.PEEK[") {
+                    (true, rest.len())
+                } else if let Some(rest) = upper_value.strip_prefix("BODY[") {
+                    (false, rest.len())
+                } else {
+                    return None;
+                };
+                let spec = &value[value.len() - rest..];
+                let (spec, partial) = spec.split_once(']')?;
+                let partial = if !partial.is_empty() {
+                    let (offset, count) = partial
+                        .strip_prefix('<')?
+                        .strip_suffix('>')?
+                        .split_once('.')?;
+                    Some((offset.parse().ok()?, count.parse().ok()?))
+                } else {
+                    None
+                };
+
+                // Part numbers followed by an optional section text
+                let mut parts = Vec::new();
+                let mut text = spec;
+                while let Some(part) = text.split('.').next() {
+                    if let Ok(part_id) = part.parse::<u32>() {
+                        if part_id == 0 {
+                            return None;
+                        }
+                        parts.push(part_id);
+                        text = text.get(part.len() + 1..).unwrap_or("");
+                    } else {
+                        break;
+                    }
+                }
+                let upper_text = text.to_ascii_uppercase();
+                let section = if text.is_empty() {
+                    Section::Full
+                } else if upper_text == "HEADER" {
+                    Section::Header
+                } else if upper_text == "TEXT" {
+                    Section::Text
+                } else if upper_text == "MIME" && !parts.is_empty() {
+                    Section::Mime
+                } else if let Some(fields) = upper_text
+                    .strip_prefix("HEADER.FIELDS.NOT")
+                    .map(|f| (f, true))
+                    .or_else(|| upper_text.strip_prefix("HEADER.FIELDS").map(|f| (f, false)))
+                {
+                    let (fields, not) = fields;
+                    Section::HeaderFields {
+                        fields: fields
+                            .trim()
+                            .trim_start_matches('(')
+                            .trim_end_matches(')')
+                            .split_ascii_whitespace()
+                            .map(|f| f.trim_matches('"').to_string())
+                            .collect(),
+                        not,
+                    }
+                } else {
+                    return None;
+                };
+
+                vec![Attribute::BodySection {
+                    peek,
+                    name: spec.to_string(),
+                    parts,
+                    section,
+                    partial,
+                }]
+            }
+        })
+    }
+
+    fn needs_blob(&self) -> bool {
+        !matches!(
+            self,
+            Attribute::Uid
+                | Attribute::Flags
+                | Attribute::InternalDate
+                | Attribute::Rfc822Size
+                | Attribute::ModSeq
+        )
+    }
+
+    fn sets_seen(&self) -> bool {
+        matches!(
+            self,
+            Attribute::BodySection { peek: false, .. } | Attribute::Rfc822 | Attribute::Rfc822Text
+        )
+    }
+}
+
+impl<T> Session<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    pub async fn handle_fetch(&mut self, request: Request) -> super::Result<StatusResponse> {
+        let is_uid = request.command == "UID FETCH";
+        let mut tokens = request.tokens.into_iter().peekable();
+        let sequence = match tokens.next().and_then(|t| Sequence::parse(t.as_bytes())) {
+            Some(sequence) => sequence,
+            None => return Ok(StatusResponse::bad("Invalid sequence set.")),
+        };
+
+        // Parse attributes
+        let mut attributes = Vec::new();
+        let is_list = tokens.peek().map_or(false, |t| t.is_parenthesis_open());
+        if is_list {
+            tokens.next();
+        }
+        for token in tokens.by_ref() {
+            if token.is_parenthesis_close() {
+                break;
+            }
+            match Attribute::parse(token.as_bytes()) {
+                Some(attrs) => attributes.extend(attrs),
+                None => {
+                    return Ok(StatusResponse::bad(format!(
+                        "Invalid fetch attribute '{}'.",
+                        token
+                    )))
+                }
+            }
+            if !is_list {
+                break;
+            }
+        }
+        if attributes.is_empty() {
+            return Ok(StatusResponse::bad("Missing fetch attributes."));
+        }
+
+        // Parse modifiers
+        let mut changed_since = None;
+        let mut include_vanished = false;
+        if tokens.next().map_or(false, |t| t.is_parenthesis_open()) {
+            while let Some(token) = tokens.next() {
+                if token.eq_ignore_ascii_case("CHANGEDSINCE") {
+                    changed_since = tokens.next().and_then(|t| parse_number::<u64>(&t));
+                    if changed_since.is_none() {
+                        return Ok(StatusResponse::bad("Invalid CHANGEDSINCE value."));
+                    }
+                } else if token.eq_ignore_ascii_case("VANISHED") {
+                    include_vanished = true;
+                }
+            }
+            if include_vanished && (!is_uid || !self.is_qresync || changed_since.is_none()) {
+                return Ok(StatusResponse::bad(
+                    "VANISHED requires QRESYNC, UID FETCH and CHANGEDSINCE.",
+                ));
+            }
+        }
+        if is_uid && !attributes.contains(&Attribute::Uid) {
+            attributes.insert(0, Attribute::Uid);
+        }
+        if changed_since.is_some() && !attributes.contains(&Attribute::ModSeq) {
+            self.is_condstore = true;
+            attributes.push(Attribute::ModSeq);
+        }
+
+        let selected = self.selected.as_ref().unwrap();
+        let mut items = selected.resolve(&sequence, is_uid);
+        let mut buf = Vec::new();
+        if let Some(changed_since) = changed_since {
+            items.retain(|(_, item)| item.modseq > changed_since);
+            if include_vanished {
+                let largest = selected.uids.uid_next.saturating_sub(1);
+                let vanished = selected
+                    .uids
+                    .expunged_since(changed_since)
+                    .unwrap_or_else(|| {
+                        (1..selected.uids.uid_next)
+                            .filter(|uid| selected.uids.get_by_uid(*uid).is_none())
+                            .collect()
+                    })
+                    .into_iter()
+                    .filter(|uid| sequence.contains(*uid, largest))
+                    .collect::<Vec<_>>();
+                if !vanished.is_empty() {
+                    buf.extend_from_slice(b"* VANISHED (EARLIER) ");
+                    serialize_sequence(&mut buf, &vanished);
+                    buf.extend_from_slice(b"\r\n");
+                }
+            }
+        }
+        if !buf.is_empty() {
+            self.write_bytes(&buf).await?;
+        }
+        if items.is_empty() {
+            return Ok(StatusResponse::ok(format!(
+                "{} completed.",
+                request.command
+            )));
+        }
+
+        // Fetch the properties needed to build the response
+        let needs_blob = attributes.iter().any(|a| a.needs_blob());
+        let mut properties = vec!["keywords"];
+        if attributes.contains(&Attribute::InternalDate) {
+            properties.push("receivedAt");
+        }
+        if attributes.contains(&Attribute::Rfc822Size) {
+            properties.push("size");
+        }
+        if needs_blob {
+            properties.push("blobId");
+        }
+        let mut emails = self
+            .get_emails(
+                &items.iter().map(|(_, item)| item.id).collect::<Vec<_>>(),
+                &properties,
+            )
+            .await?;
+
+        // Mark messages as seen when the body is fetched without PEEK
+        let selected = self.selected.as_ref().unwrap();
+        if !selected.is_read_only && attributes.iter().any(|a| a.sets_seen()) {
+            let unseen = emails
+                .iter()
+                .filter(|(_, email)| email.get("keywords").and_then(|k| k.get("$seen")).is_none())
+                .map(|(id, _)| (*id, json!({ "keywords/$seen": true })))
+                .collect::<Vec<_>>();
+            if !unseen.is_empty() {
+                for id in self.update_emails(unseen).await? {
+                    if let Some(keywords) = emails
+                        .get_mut(&id)
+                        .and_then(|email| email.get_mut("keywords"))
+                        .and_then(|k| k.as_object_mut())
+                    {
+                        keywords.insert("$seen".to_string(), Value::Bool(true));
+                    }
+                }
+                if !attributes.contains(&Attribute::Flags) {
+                    attributes.push(Attribute::Flags);
+                }
+            }
+        }
+
+        for (seqnum, item) in items {
+            let email = if let Some(email) = emails.get(&item.id) {
+                email
+            } else {
+                continue;
+            };
+            let raw = if needs_blob {
+                match email
+                    .get("blobId")
+                    .and_then(|b| b.as_str())
+                    .map(|b| b.to_string())
+                {
+                    Some(blob_id) => match self.get_blob(&blob_id).await? {
+                        Some(raw) => raw,
+                        None => continue,
+                    },
+                    None => continue,
+                }
+            } else {
+                Vec::new()
+            };
+            let message = if needs_blob {
+                Some(Message::parse(&raw))
+            } else {
+                None
+            };
+
+            let mut buf = Vec::with_capacity(128);
+            buf.extend_from_slice(format!("* {} FETCH (", seqnum).as_bytes());
+            for (pos, attribute) in attributes.iter().enumerate() {
+                if pos > 0 {
+                    buf.push(b' ');
+                }
+                match attribute {
+                    Attribute::Uid => {
+                        buf.extend_from_slice(format!("UID {}", item.uid).as_bytes());
+                    }
+                    Attribute::Flags => {
+                        buf.extend_from_slice(
+                            format!("FLAGS ({})", keywords_to_flags(email)).as_bytes(),
+                        );
+                    }
+                    Attribute::InternalDate => {
+                        buf.extend_from_slice(
+                            format!(
+                                "INTERNALDATE \"{}\"",
+                                email
+                                    .get("receivedAt")
+                                    .and_then(|d| d.as_str())
+                                    .and_then(format_date_time)
+                                    .unwrap_or_else(|| "01-Jan-1970 00:00:00 +0000".to_string())
+                            )
+                            .as_bytes(),
+                        );
+                    }
+                    Attribute::Rfc822Size => {
+                        buf.extend_from_slice(
+                            format!(
+                                "RFC822.SIZE {}",
+                                email.get("size").and_then(|s| s.as_u64()).unwrap_or(0)
+                            )
+                            .as_bytes(),
+                        );
+                    }
+                    Attribute::ModSeq => {
+                        buf.extend_from_slice(format!("MODSEQ ({})", item.modseq).as_bytes());
+                    }
+                    Attribute::Envelope => {
+                        buf.extend_from_slice(b"ENVELOPE ");
+                        message.as_ref().unwrap().write_envelope(&mut buf);
+                    }
+                    Attribute::Body => {
+                        buf.extend_from_slice(b"BODY ");
+                        message
+                            .as_ref()
+                            .unwrap()
+                            .write_body_structure(&mut buf, false);
+                    }
+                    Attribute::BodyStructure => {
+                        buf.extend_from_slice(b"BODYSTRUCTURE ");
+                        message
+                            .as_ref()
+                            .unwrap()
+                            .write_body_structure(&mut buf, true);
+                    }
+                    Attribute::Rfc822 => {
+                        buf.extend_from_slice(b"RFC822 ");
+                        write_literal(&mut buf, &raw);
+                    }
+                    Attribute::Rfc822Header => {
+                        buf.extend_from_slice(b"RFC822.HEADER ");
+                        write_literal(
+                            &mut buf,
+                            &message
+                                .as_ref()
+                                .unwrap()
+                                .section(&[], &Section::Header)
+                                .unwrap_or_default(),
+                        );
+                    }
+                    Attribute::Rfc822Text => {
+                        buf.extend_from_slice(b"RFC822.TEXT ");
+                        write_literal(
+                            &mut buf,
+                            &message
+                                .as_ref()
+                                .unwrap()
+                                .section(&[], &Section::Text)
+                                .unwrap_or_default(),
+                        );
+                    }
+                    Attribute::BodySection {
+                        name,
+                        parts,
+                        section,
+                        partial,
+                        ..
+                    } => {
+                        let contents = message
+                            .as_ref()
+                            .unwrap()
+                            .section(parts, section)
+                            .unwrap_or_default();
+                        buf.extend_from_slice(format!("BODY[{}]", name).as_bytes());
+                        if let Some((offset, count)) = partial {
+                            buf.extend_from_slice(format!("<{}> ", offset).as_bytes());
+                            let start = (*offset).min(contents.len());
+                            let end = (start + *count).min(contents.len());
+                            write_literal(&mut buf, &contents[start..end]);
+                        } else {
+                            buf.push(b' ');
+                            write_literal(&mut buf, &contents);
+                        }
+                    }
+                }
+            }
+            buf.extend_from_slice(b")\r\n");
+            self.write_bytes(&buf).await?;
+        }
+
+        Ok(StatusResponse::ok(format!(
+            "{} completed.",
+            request.command
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Attribute, Section};
+
+    #[test]
+    fn parse_fetch_attributes() {
+        assert_eq!(
+            Attribute::parse(b"BODY.PEEK[1.2.HEADER.FIELDS (From To)]<0.100>").unwrap(),
+            vec![Attribute::BodySection {
+                peek: true,
+                name: "1.2.HEADER.FIELDS (From To)".to_string(),
+                parts: vec![1, 2],
+                section: Section::HeaderFields {
+                    fields: vec!["FROM".to_string(), "TO".to_string()],
+                    not: false
+                },
+                partial: Some((0, 100)),
+            }]
+        );
+        assert_eq!(
+            Attribute::parse(b"body[]").unwrap(),
+            vec![Attribute::BodySection {
+                peek: false,
+                name: "".to_string(),
+                parts: vec![],
+                section: Section::Full,
+                partial: None,
+            }]
+        );
+        assert_eq!(
+            Attribute::parse(b"BODY[3.MIME]").unwrap(),
+            vec![Attribute::BodySection {
+                peek: false,
+                name: "3.MIME".to_string(),
+                parts: vec![3],
+                section: Section::Mime,
+                partial: None,
+            }]
+        );
+        assert_eq!(Attribute::parse(b"fast").unwrap().len(), 3);
+        assert!(Attribute::parse(b"BODY[MIME]").is_none());
+        assert!(Attribute::parse(b"BODY[0]").is_none());
+    }
+}
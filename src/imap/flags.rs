@@ -0,0 +1,229 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap_mail::mailbox::uids::UidItem;
+use serde_json::{json, Map, Value};
+use store::Store;
+
+use super::{
+    flag_to_keyword, keyword_to_flag,
+    receiver::{Request, Token},
+    response::{serialize_sequence, StatusResponse},
+    select::parse_number,
+    session::Session,
+    Sequence,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StoreAction {
+    Set,
+    Add,
+    Remove,
+}
+
+impl<T> Session<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    pub async fn handle_store(&mut self, request: Request) -> super::Result<StatusResponse> {
+        let is_uid = request.command == "UID STORE";
+        let selected = self.selected.as_ref().unwrap();
+        if selected.is_read_only {
+            return Ok(StatusResponse::no("Mailbox is read-only.").with_code("READ-ONLY"));
+        }
+
+        let mut tokens = request.tokens.into_iter().peekable();
+        let items = match tokens.next().and_then(|t| Sequence::parse(t.as_bytes())) {
+            Some(sequence) => selected.resolve(&sequence, is_uid),
+            None => return Ok(StatusResponse::bad("Invalid sequence set.")),
+        };
+
+        // Parse modifiers
+        let mut unchanged_since = None;
+        if tokens.peek().map_or(false, |t| t.is_parenthesis_open()) {
+            tokens.next();
+            while let Some(token) = tokens.next() {
+                if token.is_parenthesis_close() {
+                    break;
+                } else if token.eq_ignore_ascii_case("UNCHANGEDSINCE") {
+                    unchanged_since = tokens.next().and_then(|t| parse_number::<u64>(&t));
+                    if unchanged_since.is_none() {
+                        return Ok(StatusResponse::bad("Invalid UNCHANGEDSINCE value."));
+                    }
+                }
+            }
+        }
+
+        // Parse action
+        let (action, is_silent) = match tokens.next() {
+            Some(Token::Atom(action)) => {
+                let action = String::from_utf8_lossy(&action).to_ascii_uppercase();
+                let (action, is_silent) = match action.strip_suffix(".SILENT") {
+                    Some(action) => (action.to_string(), true),
+                    None => (action, false),
+                };
+                (
+                    match action.as_str() {
+                        "FLAGS" => StoreAction::Set,
+                        "+FLAGS" => StoreAction::Add,
+                        "-FLAGS" => StoreAction::Remove,
+                        _ => return Ok(StatusResponse::bad("Invalid STORE action.")),
+                    },
+                    is_silent,
+                )
+            }
+            _ => return Ok(StatusResponse::bad("Missing STORE action.")),
+        };
+        let keywords = tokens
+            .filter(|t| !t.is_parenthesis_open() && !t.is_parenthesis_close())
+            .filter_map(|t| flag_to_keyword(&String::from_utf8_lossy(t.as_bytes())))
+            .collect::<Vec<_>>();
+        if keywords.is_empty() && action != StoreAction::Set {
+            return Ok(StatusResponse::bad("Missing flags."));
+        }
+
+        // Messages modified after UNCHANGEDSINCE are not updated
+        let mut modified = Vec::new();
+        let mut updates = Vec::with_capacity(items.len());
+        for (_, item) in &items {
+            if unchanged_since.map_or(false, |modseq| item.modseq > modseq) {
+                modified.push(if is_uid {
+                    item.uid
+                } else {
+                    selected.uids.uid_to_seqnum(item.uid).unwrap_or(0)
+                });
+                continue;
+            }
+            let patch = match action {
+                StoreAction::Set => json!({
+                    "keywords": keywords
+                        .iter()
+                        .map(|k| (k.clone(), Value::Bool(true)))
+                        .collect::<Map<_, _>>()
+                }),
+                StoreAction::Add | StoreAction::Remove => Value::Object(
+                    keywords
+                        .iter()
+                        .map(|k| {
+                            (
+                                format!("keywords/{}", k),
+                                if action == StoreAction::Add {
+                                    Value::Bool(true)
+                                } else {
+                                    Value::Null
+                                },
+                            )
+                        })
+                        .collect(),
+                ),
+            };
+            updates.push((item.id, patch));
+        }
+
+        let updated = if !updates.is_empty() {
+            self.update_emails(updates).await?
+        } else {
+            Vec::new()
+        };
+
+        // Send the new flags and mod-sequences of the updated messages
+        if !updated.is_empty() && (!is_silent || self.is_condstore) {
+            let selected = self.selected.as_ref().unwrap();
+            let uids = self.sync_uids(selected.id).await?;
+            let items = items
+                .into_iter()
+                .filter(|(_, item)| updated.contains(&item.id))
+                .map(|(seqnum, item)| (seqnum, uids.get_by_uid(item.uid).copied().unwrap_or(item)))
+                .collect::<Vec<_>>();
+            let buf = if !is_silent {
+                self.fetch_flags(&items, is_uid).await?
+            } else {
+                let mut buf = Vec::new();
+                for (seqnum, item) in items {
+                    buf.extend_from_slice(
+                        format!(
+                            "* {} FETCH (UID {} MODSEQ ({}))\r\n",
+                            seqnum, item.uid, item.modseq
+                        )
+                        .as_bytes(),
+                    );
+                }
+                buf
+            };
+            self.write_bytes(&buf).await?;
+        }
+
+        let mut response = StatusResponse::ok(format!("{} completed.", request.command));
+        if !modified.is_empty() {
+            let mut buf = b"MODIFIED ".to_vec();
+            serialize_sequence(&mut buf, &modified);
+            response = response.with_code(String::from_utf8(buf).unwrap_or_default());
+        }
+        Ok(response)
+    }
+
+    /// Builds untagged FETCH responses containing the flags of a list of messages.
+    pub async fn fetch_flags(
+        &self,
+        items: &[(u32, UidItem)],
+        with_uid: bool,
+    ) -> super::Result<Vec<u8>> {
+        let emails = self
+            .get_emails(
+                &items.iter().map(|(_, item)| item.id).collect::<Vec<_>>(),
+                &["keywords"],
+            )
+            .await?;
+
+        let mut buf = Vec::with_capacity(items.len() * 32);
+        for (seqnum, item) in items {
+            if let Some(email) = emails.get(&item.id) {
+                buf.extend_from_slice(format!("* {} FETCH (", seqnum).as_bytes());
+                if with_uid || self.is_qresync {
+                    buf.extend_from_slice(format!("UID {} ", item.uid).as_bytes());
+                }
+                buf.extend_from_slice(format!("FLAGS ({})", keywords_to_flags(email)).as_bytes());
+                if self.is_condstore {
+                    buf.extend_from_slice(format!(" MODSEQ ({})", item.modseq).as_bytes());
+                }
+                buf.extend_from_slice(b")\r\n");
+            }
+        }
+        Ok(buf)
+    }
+}
+
+/// Returns the IMAP flags of an email object.
+pub fn keywords_to_flags(email: &Value) -> String {
+    email
+        .get("keywords")
+        .and_then(|k| k.as_object())
+        .map(|keywords| {
+            keywords
+                .keys()
+                .map(|k| keyword_to_flag(k))
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .unwrap_or_default()
+}
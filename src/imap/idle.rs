@@ -0,0 +1,133 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use jmap::types::type_state::TypeState;
+use store::{core::bitmap::Bitmap, DocumentId, Store};
+
+use super::{
+    response::{serialize_sequence, StatusResponse},
+    session::{Idle, Session},
+};
+
+// Subscriber ids are kept apart from the account ids used by EventSource and
+// WebSocket subscribers and from push subscription ids.
+static SUBSCRIBER_ID: AtomicU32 = AtomicU32::new(0);
+
+impl<T> Session<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    pub async fn handle_idle(&mut self, tag: String) -> super::Result<StatusResponse> {
+        self.synchronize().await?;
+
+        let subscriber_id = DocumentId::MAX / 2
+            - (SUBSCRIBER_ID.fetch_add(1, Ordering::Relaxed) % (DocumentId::MAX / 4));
+        let rx = self
+            .core
+            .subscribe_state_manager(
+                subscriber_id,
+                self.account_id(),
+                Bitmap::from(vec![TypeState::Email, TypeState::Mailbox]),
+            )
+            .await;
+
+        self.write_bytes(b"+ Idling, waiting for DONE.\r\n").await?;
+        self.receiver.is_line_mode = true;
+        self.idle = Idle { tag, rx }.into();
+
+        Ok(StatusResponse::deferred())
+    }
+
+    /// Synchronizes the selected mailbox, sending untagged EXPUNGE or VANISHED,
+    /// EXISTS and FETCH responses for any changes since the last synchronization.
+    pub async fn synchronize(&mut self) -> super::Result<()> {
+        let selected = if let Some(selected) = &self.selected {
+            selected
+        } else {
+            return Ok(());
+        };
+        let uids = self.sync_uids(selected.id).await?;
+        if uids == selected.uids {
+            return Ok(());
+        } else if uids.uid_validity != selected.uids.uid_validity {
+            self.selected = None;
+            self.write_bytes(b"* BYE UIDVALIDITY changed, please reconnect.\r\n")
+                .await?;
+            return Err(StatusResponse::disconnect());
+        }
+
+        let mut buf = Vec::new();
+
+        // Expunged messages, sequence numbers are sent in descending order
+        // so that they remain valid as messages are removed.
+        let mut expunged = Vec::new();
+        for (pos, item) in selected.uids.items.iter().enumerate() {
+            if uids.get_by_uid(item.uid).is_none() {
+                expunged.push((pos as u32 + 1, item.uid));
+            }
+        }
+        if !expunged.is_empty() {
+            if self.is_qresync {
+                buf.extend_from_slice(b"* VANISHED ");
+                serialize_sequence(
+                    &mut buf,
+                    &expunged.iter().map(|(_, uid)| *uid).collect::<Vec<_>>(),
+                );
+                buf.extend_from_slice(b"\r\n");
+            } else {
+                for (seqnum, _) in expunged.iter().rev() {
+                    buf.extend_from_slice(format!("* {} EXPUNGE\r\n", seqnum).as_bytes());
+                }
+            }
+        }
+
+        // New messages
+        if uids.uid_next != selected.uids.uid_next || !expunged.is_empty() {
+            buf.extend_from_slice(format!("* {} EXISTS\r\n", uids.items.len()).as_bytes());
+        }
+
+        // Messages with modified flags
+        let changed = uids
+            .items
+            .iter()
+            .enumerate()
+            .filter(|(_, item)| {
+                item.uid < selected.uids.uid_next && item.modseq > selected.uids.highest_modseq
+            })
+            .map(|(pos, item)| (pos as u32 + 1, *item))
+            .collect::<Vec<_>>();
+        if !changed.is_empty() {
+            let flags = self.fetch_flags(&changed, false).await?;
+            buf.extend_from_slice(&flags);
+        }
+
+        self.selected.as_mut().unwrap().uids = uids;
+        if !buf.is_empty() {
+            self.write_bytes(&buf).await?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,225 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use actix_web::web;
+use store::{
+    config::env_settings::EnvSettings,
+    tracing::{debug, error, info, warn},
+    Store,
+};
+use tokio::{net::TcpListener, sync::watch};
+use tokio_rustls::TlsAcceptor;
+
+use crate::{cluster::rpc::tls::load_tls_server_config, imap::session::Session, JMAPServer};
+
+const TIMEOUT: Duration = Duration::from_secs(30 * 60); // 30 minutes
+const IDLE_POLL_INTERVAL: Duration = Duration::from_secs(30);
+const DEFAULT_IMAP_PORT: u16 = 1143;
+
+pub fn init_imap() -> (watch::Sender<bool>, watch::Receiver<bool>) {
+    watch::channel::<bool>(true)
+}
+
+pub fn spawn_imap<T>(
+    core: web::Data<JMAPServer<T>>,
+    settings: &EnvSettings,
+    mut shutdown_rx: watch::Receiver<bool>,
+) where
+    T: for<'x> Store<'x> + 'static,
+{
+    // Parse bind address
+    let bind_addr = SocketAddr::from((
+        settings.parse_ipaddr("imap-bind-addr", "127.0.0.1"),
+        settings.parse("imap-port").unwrap_or(DEFAULT_IMAP_PORT),
+    ));
+    info!("Starting IMAP service at {}...", bind_addr);
+
+    // Build TLS acceptor
+    let tls_acceptor = if let (Some(cert_path), Some(key_path)) = (
+        settings.get("imap-cert-path"),
+        settings.get("imap-key-path"),
+    ) {
+        Arc::new(TlsAcceptor::from(Arc::new(load_tls_server_config(
+            &cert_path, &key_path,
+        ))))
+        .into()
+    } else {
+        None
+    };
+    let mut tls_only = settings.parse("imap-tls-only").unwrap_or(false);
+    if tls_only && tls_acceptor.is_none() {
+        warn!("IMAP server is configured to only accept TLS connections, but no TLS certificate was provided.");
+        tls_only = false;
+    }
+
+    tokio::spawn(async move {
+        // Start listening for IMAP connections.
+        let listener = match TcpListener::bind(bind_addr).await {
+            Ok(listener) => listener,
+            Err(err) => {
+                error!("Failed to bind IMAP service to {}: {}", bind_addr, err);
+                return;
+            }
+        };
+
+        let hostname = Arc::new(
+            gethostname::gethostname()
+                .to_str()
+                .unwrap_or("localhost")
+                .to_string(),
+        );
+
+        loop {
+            tokio::select! {
+                stream = listener.accept() => {
+                    match stream {
+                        Ok((stream, peer_addr)) => {
+                            let shutdown_rx = shutdown_rx.clone();
+                            let core = core.clone();
+                            let tls_acceptor = tls_acceptor.clone();
+                            let hostname = hostname.clone();
+
+                            tokio::spawn(async move {
+                                let session = if tls_only {
+                                    let stream = match tls_acceptor.as_ref().unwrap().accept(stream).await {
+                                        Ok(stream) => stream,
+                                        Err(e) => {
+                                            debug!("Failed to accept TLS connection: {}", e);
+                                            return;
+                                        }
+                                    };
+                                    Session::new(core, peer_addr, stream.into(), None, hostname)
+                                } else {
+                                    Session::new(core, peer_addr, stream.into(), tls_acceptor, hostname)
+                                };
+
+                                handle_conn(session, shutdown_rx).await;
+                            });
+                        }
+                        Err(err) => {
+                            error!("Failed to accept TCP connection: {}", err);
+                        }
+                    }
+                },
+                _ = shutdown_rx.changed() => {
+                    debug!("IMAP listener shutting down.");
+                    break;
+                }
+            };
+        }
+    });
+}
+
+pub async fn handle_conn<T>(mut session: Session<T>, mut shutdown_rx: watch::Receiver<bool>)
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    // Send greeting
+    let greeting = format!(
+        concat!(
+            "* OK [CAPABILITY {}] Stalwart IMAP4rev2 v",
+            env!("CARGO_PKG_VERSION"),
+            " at your service.\r\n"
+        ),
+        session.capabilities()
+    );
+    if session.write_bytes(greeting.as_bytes()).await.is_err() {
+        debug!("Failed to send greeting to {}.", session.peer_addr);
+        return;
+    }
+
+    let mut buf = vec![0; 4096];
+
+    loop {
+        // While idling, changes are received from the state manager. Followers
+        // do not receive state changes, so the mailbox is also polled periodically.
+        let is_idle = session.idle.is_some();
+        let mut changes_rx = session.idle.as_mut().and_then(|idle| idle.rx.take());
+        let mut needs_sync = false;
+
+        tokio::select! {
+            result = tokio::time::timeout(
+                TIMEOUT,
+                session.read_bytes(&mut buf)) => {
+                match result {
+                    Ok(Ok(bytes_read)) => {
+                        if bytes_read > 0 {
+                            if session.ingest(&buf[..bytes_read]).await.is_err() {
+                                debug!("Disconnecting client.");
+                                return;
+                            }
+                        } else {
+                            debug!("IMAP connection closed by {}", session.peer_addr);
+                            break;
+                        }
+                    },
+                    Ok(Err(_)) => {
+                        break;
+                    },
+                    Err(_) => {
+                        session.write_bytes(b"* BYE Disconnecting inactive client.\r\n").await.ok();
+                        debug!("IMAP connection timed out with {}.", session.peer_addr);
+                        break;
+                    }
+                }
+            },
+            change = async {
+                match changes_rx.as_mut() {
+                    Some(rx) => rx.recv().await,
+                    None => std::future::pending().await,
+                }
+            }, if is_idle => {
+                if change.is_none() {
+                    changes_rx = None;
+                }
+                needs_sync = true;
+            },
+            _ = tokio::time::sleep(IDLE_POLL_INTERVAL), if is_idle => {
+                needs_sync = true;
+            },
+            _ = shutdown_rx.changed() => {
+                session.write_bytes(b"* BYE Server shutting down.\r\n").await.ok();
+                debug!("IMAP connection with peer {} shutting down.", session.peer_addr);
+                return;
+            }
+        };
+
+        if let Some(idle) = session.idle.as_mut() {
+            idle.rx = changes_rx;
+            if needs_sync {
+                if let Err(response) = session.synchronize().await {
+                    if response.is_disconnect() {
+                        debug!("Disconnecting client.");
+                        return;
+                    }
+                    debug!(
+                        "Failed to synchronize mailbox for {}: {}",
+                        session.peer_addr, response.message
+                    );
+                }
+            }
+        }
+    }
+}
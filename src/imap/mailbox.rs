@@ -0,0 +1,782 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap::types::jmap::JMAPId;
+use serde_json::{json, Value};
+use store::{DocumentId, Store};
+
+use super::{
+    receiver::{Request, Token},
+    response::{write_string, StatusResponse},
+    session::Session,
+    HIERARCHY_DELIMITER,
+};
+
+#[derive(Debug, Clone)]
+pub struct MailboxInfo {
+    pub id: DocumentId,
+    pub name: String,
+    pub parent_id: Option<DocumentId>,
+    pub role: Option<String>,
+    pub is_subscribed: bool,
+    pub has_children: bool,
+    pub total_emails: u64,
+    pub unread_emails: u64,
+    pub may_write: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatusItem {
+    Messages,
+    UidNext,
+    UidValidity,
+    Unseen,
+    Deleted,
+    Size,
+    HighestModSeq,
+    Recent,
+}
+
+impl MailboxInfo {
+    pub fn special_use(&self) -> Option<&'static str> {
+        match self.role.as_deref()? {
+            "archive" => "\\Archive".into(),
+            "drafts" => "\\Drafts".into(),
+            "junk" => "\\Junk".into(),
+            "sent" => "\\Sent".into(),
+            "trash" => "\\Trash".into(),
+            "all" => "\\All".into(),
+            "flagged" => "\\Flagged".into(),
+            _ => None,
+        }
+    }
+
+    pub fn jmap_id(&self) -> String {
+        JMAPId::from(self.id).to_string()
+    }
+}
+
+impl StatusItem {
+    pub fn parse(token: &Token) -> Option<Self> {
+        Some(
+            match String::from_utf8_lossy(token.as_bytes())
+                .to_ascii_uppercase()
+                .as_str()
+            {
+                "MESSAGES" => StatusItem::Messages,
+                "UIDNEXT" => StatusItem::UidNext,
+                "UIDVALIDITY" => StatusItem::UidValidity,
+                "UNSEEN" => StatusItem::Unseen,
+                "DELETED" => StatusItem::Deleted,
+                "SIZE" => StatusItem::Size,
+                "HIGHESTMODSEQ" => StatusItem::HighestModSeq,
+                "RECENT" => StatusItem::Recent,
+                _ => return None,
+            },
+        )
+    }
+}
+
+impl<T> Session<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    /// Returns the mailboxes of the account, named by their full path.
+    pub async fn get_mailboxes(&self) -> super::Result<Vec<MailboxInfo>> {
+        let response = self
+            .jmap(vec![(
+                "Mailbox/get",
+                json!({
+                    "accountId": JMAPId::from(self.account_id()),
+                    "properties": ["name", "parentId", "role", "isSubscribed",
+                        "totalEmails", "unreadEmails", "myRights"],
+                }),
+            )])
+            .await?;
+
+        let mut mailboxes = Vec::new();
+        if let Some(Value::Array(list)) = response.first().and_then(|r| r.get("list")) {
+            for item in list {
+                let id = if let Some(id) = item
+                    .get("id")
+                    .and_then(|id| id.as_str())
+                    .and_then(JMAPId::parse)
+                {
+                    id.get_document_id()
+                } else {
+                    continue;
+                };
+                mailboxes.push(MailboxInfo {
+                    id,
+                    name: item
+                        .get("name")
+                        .and_then(|v| v.as_str())
+                        .unwrap_or_default()
+                        .to_string(),
+                    parent_id: item
+                        .get("parentId")
+                        .and_then(|v| v.as_str())
+                        .and_then(JMAPId::parse)
+                        .map(|id| id.get_document_id()),
+                    role: item
+                        .get("role")
+                        .and_then(|v| v.as_str())
+                        .map(|v| v.to_string()),
+                    is_subscribed: item
+                        .get("isSubscribed")
+                        .and_then(|v| v.as_bool())
+                        .unwrap_or(false),
+                    has_children: false,
+                    total_emails: item
+                        .get("totalEmails")
+                        .and_then(|v| v.as_u64())
+                        .unwrap_or(0),
+                    unread_emails: item
+                        .get("unreadEmails")
+                        .and_then(|v| v.as_u64())
+                        .unwrap_or(0),
+                    may_write: item
+                        .get("myRights")
+                        .and_then(|v| v.get("mayAddItems"))
+                        .and_then(|v| v.as_bool())
+                        .unwrap_or(true),
+                });
+            }
+        }
+
+        // Build the full path of each mailbox, the Inbox is always named 'INBOX'.
+        let mut paths = Vec::with_capacity(mailboxes.len());
+        for mailbox in &mailboxes {
+            let mut path = Vec::new();
+            let mut current = Some(mailbox);
+            while let Some(mailbox) = current {
+                if path.len() > mailboxes.len() {
+                    break;
+                }
+                path.push(if mailbox.role.as_deref() == Some("inbox") {
+                    "INBOX"
+                } else {
+                    mailbox.name.as_str()
+                });
+                current = mailbox
+                    .parent_id
+                    .and_then(|parent_id| mailboxes.iter().find(|m| m.id == parent_id));
+            }
+            path.reverse();
+            paths.push(path.join(&HIERARCHY_DELIMITER.to_string()));
+        }
+        let parent_ids = mailboxes
+            .iter()
+            .filter_map(|m| m.parent_id)
+            .collect::<Vec<_>>();
+        for (mailbox, path) in mailboxes.iter_mut().zip(paths) {
+            mailbox.name = path;
+            mailbox.has_children = parent_ids.contains(&mailbox.id);
+        }
+        mailboxes.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(mailboxes)
+    }
+
+    pub async fn get_mailbox(&self, name: &str) -> super::Result<Option<MailboxInfo>> {
+        let name = normalize_name(name);
+        Ok(self
+            .get_mailboxes()
+            .await?
+            .into_iter()
+            .find(|mailbox| mailbox.name == name))
+    }
+
+    pub async fn handle_list(&mut self, request: Request) -> super::Result<StatusResponse> {
+        let is_lsub = request.command == "LSUB";
+        let mut tokens = request.tokens.into_iter().peekable();
+
+        // Selection options
+        let mut select_subscribed = false;
+        let mut select_special_use = false;
+        if !is_lsub && tokens.peek().map_or(false, |t| t.is_parenthesis_open()) {
+            tokens.next();
+            for token in tokens.by_ref() {
+                if token.is_parenthesis_close() {
+                    break;
+                } else if token.eq_ignore_ascii_case("SUBSCRIBED") {
+                    select_subscribed = true;
+                } else if token.eq_ignore_ascii_case("SPECIAL-USE") {
+                    select_special_use = true;
+                }
+            }
+        }
+
+        // Reference and patterns
+        let reference = match tokens.next().map(|t| t.unwrap_string()) {
+            Some(Ok(reference)) => reference,
+            _ => return Ok(StatusResponse::bad("Missing reference name.")),
+        };
+        let mut patterns = Vec::new();
+        match tokens.next() {
+            Some(Token::ParenthesisOpen) => {
+                for token in tokens.by_ref() {
+                    if token.is_parenthesis_close() {
+                        break;
+                    } else if let Ok(pattern) = token.unwrap_string() {
+                        patterns.push(format!("{}{}", reference, pattern));
+                    }
+                }
+            }
+            Some(token) => {
+                if let Ok(pattern) = token.unwrap_string() {
+                    patterns.push(format!("{}{}", reference, pattern));
+                }
+            }
+            None => return Ok(StatusResponse::bad("Missing mailbox pattern.")),
+        }
+
+        // Return options
+        let mut return_subscribed = is_lsub || select_subscribed;
+        let mut return_special_use = true;
+        let mut return_status = Vec::new();
+        if tokens
+            .next()
+            .map_or(false, |t| t.eq_ignore_ascii_case("RETURN"))
+        {
+            return_special_use = false;
+            while let Some(token) = tokens.next() {
+                if token.eq_ignore_ascii_case("SUBSCRIBED") {
+                    return_subscribed = true;
+                } else if token.eq_ignore_ascii_case("SPECIAL-USE") {
+                    return_special_use = true;
+                } else if token.eq_ignore_ascii_case("STATUS") {
+                    if tokens.next().map_or(false, |t| t.is_parenthesis_open()) {
+                        for token in tokens.by_ref() {
+                            if token.is_parenthesis_close() {
+                                break;
+                            } else if let Some(item) = StatusItem::parse(&token) {
+                                return_status.push(item);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        let command = if is_lsub { "LSUB" } else { "LIST" };
+
+        // An empty pattern returns the hierarchy delimiter
+        if patterns.len() == 1 && patterns[0].is_empty() {
+            self.write_bytes(
+                format!(
+                    "* {} (\\Noselect) \"{}\" \"\"\r\n",
+                    command, HIERARCHY_DELIMITER
+                )
+                .as_bytes(),
+            )
+            .await?;
+            return Ok(StatusResponse::ok(format!("{} completed.", command)));
+        }
+
+        let mut buf = Vec::new();
+        let mut status_mailboxes = Vec::new();
+        for mailbox in self.get_mailboxes().await? {
+            if !patterns
+                .iter()
+                .any(|pattern| matches_pattern(pattern, &mailbox.name))
+                || ((select_subscribed || is_lsub) && !mailbox.is_subscribed)
+                || (select_special_use && mailbox.special_use().is_none())
+            {
+                continue;
+            }
+
+            let mut attributes = Vec::new();
+            if !is_lsub {
+                attributes.push(if mailbox.has_children {
+                    "\\HasChildren"
+                } else {
+                    "\\HasNoChildren"
+                });
+            }
+            if return_subscribed && mailbox.is_subscribed && !is_lsub {
+                attributes.push("\\Subscribed");
+            }
+            if return_special_use {
+                if let Some(special_use) = mailbox.special_use() {
+                    attributes.push(special_use);
+                }
+            }
+
+            buf.extend_from_slice(
+                format!(
+                    "* {} ({}) \"{}\" ",
+                    command,
+                    attributes.join(" "),
+                    HIERARCHY_DELIMITER
+                )
+                .as_bytes(),
+            );
+            write_string(&mut buf, mailbox.name.as_bytes());
+            buf.extend_from_slice(b"\r\n");
+
+            if !return_status.is_empty() {
+                status_mailboxes.push(mailbox);
+            }
+        }
+        self.write_bytes(&buf).await?;
+
+        for mailbox in status_mailboxes {
+            let status = self.write_status(&mailbox, &return_status).await?;
+            self.write_bytes(&status).await?;
+        }
+
+        Ok(StatusResponse::ok(format!("{} completed.", command)))
+    }
+
+    pub async fn handle_status(&mut self, request: Request) -> super::Result<StatusResponse> {
+        let mut tokens = request.tokens.into_iter();
+        let name = match tokens.next().map(|t| t.unwrap_string()) {
+            Some(Ok(name)) => name,
+            _ => return Ok(StatusResponse::bad("Missing mailbox name.")),
+        };
+        let items = tokens
+            .filter_map(|t| StatusItem::parse(&t))
+            .collect::<Vec<_>>();
+        if items.is_empty() {
+            return Ok(StatusResponse::bad("Missing status items."));
+        }
+
+        let mailbox = match self.get_mailbox(&name).await? {
+            Some(mailbox) => mailbox,
+            None => {
+                return Ok(StatusResponse::no("Mailbox does not exist.").with_code("NONEXISTENT"))
+            }
+        };
+        let status = self.write_status(&mailbox, &items).await?;
+        self.write_bytes(&status).await?;
+
+        Ok(StatusResponse::ok("STATUS completed."))
+    }
+
+    async fn write_status(
+        &self,
+        mailbox: &MailboxInfo,
+        items: &[StatusItem],
+    ) -> super::Result<Vec<u8>> {
+        let uids = if items.iter().any(|item| {
+            matches!(
+                item,
+                StatusItem::UidNext
+                    | StatusItem::UidValidity
+                    | StatusItem::HighestModSeq
+                    | StatusItem::Size
+                    | StatusItem::Deleted
+            )
+        }) {
+            Some(self.sync_uids(mailbox.id).await?)
+        } else {
+            None
+        };
+        let emails = if items
+            .iter()
+            .any(|item| matches!(item, StatusItem::Size | StatusItem::Deleted))
+        {
+            self.get_emails(
+                &uids
+                    .as_ref()
+                    .unwrap()
+                    .items
+                    .iter()
+                    .map(|item| item.id)
+                    .collect::<Vec<_>>(),
+                &["size", "keywords"],
+            )
+            .await?
+        } else {
+            Default::default()
+        };
+
+        let mut buf = Vec::with_capacity(64);
+        buf.extend_from_slice(b"* STATUS ");
+        write_string(&mut buf, mailbox.name.as_bytes());
+        buf.extend_from_slice(b" (");
+        for (pos, item) in items.iter().enumerate() {
+            if pos > 0 {
+                buf.push(b' ');
+            }
+            let (name, value) = match item {
+                StatusItem::Messages => ("MESSAGES", mailbox.total_emails),
+                StatusItem::UidNext => ("UIDNEXT", uids.as_ref().unwrap().uid_next as u64),
+                StatusItem::UidValidity => {
+                    ("UIDVALIDITY", uids.as_ref().unwrap().uid_validity as u64)
+                }
+                StatusItem::Unseen => ("UNSEEN", mailbox.unread_emails),
+                StatusItem::Deleted => (
+                    "DELETED",
+                    emails
+                        .values()
+                        .filter(|email| {
+                            email
+                                .get("keywords")
+                                .and_then(|k| k.get("$deleted"))
+                                .is_some()
+                        })
+                        .count() as u64,
+                ),
+                StatusItem::Size => (
+                    "SIZE",
+                    emails
+                        .values()
+                        .filter_map(|email| email.get("size").and_then(|s| s.as_u64()))
+                        .sum(),
+                ),
+                StatusItem::HighestModSeq => {
+                    ("HIGHESTMODSEQ", uids.as_ref().unwrap().highest_modseq)
+                }
+                StatusItem::Recent => ("RECENT", 0),
+            };
+            buf.extend_from_slice(format!("{} {}", name, value).as_bytes());
+        }
+        buf.extend_from_slice(b")\r\n");
+
+        Ok(buf)
+    }
+
+    pub async fn handle_create(&mut self, request: Request) -> super::Result<StatusResponse> {
+        let name = match request.tokens.into_iter().next().map(|t| t.unwrap_string()) {
+            Some(Ok(name)) => normalize_name(name.trim_end_matches(HIERARCHY_DELIMITER)),
+            _ => return Ok(StatusResponse::bad("Missing mailbox name.")),
+        };
+        if name.is_empty() || name.eq_ignore_ascii_case("INBOX") {
+            return Ok(StatusResponse::no("Mailbox already exists.").with_code("ALREADYEXISTS"));
+        }
+
+        let mailboxes = self.get_mailboxes().await?;
+        if mailboxes.iter().any(|m| m.name == name) {
+            return Ok(StatusResponse::no("Mailbox already exists.").with_code("ALREADYEXISTS"));
+        }
+        self.create_path(&mailboxes, &name).await?;
+
+        Ok(StatusResponse::ok("CREATE completed."))
+    }
+
+    /// Creates a mailbox and any missing intermediate mailboxes, returning the id of the last one.
+    async fn create_path(
+        &self,
+        mailboxes: &[MailboxInfo],
+        name: &str,
+    ) -> super::Result<Option<DocumentId>> {
+        let mut parent_id = None;
+        let mut path = String::new();
+        let mut create = serde_json::Map::new();
+        let mut last_create_id = None;
+
+        for (pos, part) in name.split(HIERARCHY_DELIMITER).enumerate() {
+            if part.is_empty() {
+                return Err(StatusResponse::bad("Invalid mailbox name."));
+            }
+            if !path.is_empty() {
+                path.push(HIERARCHY_DELIMITER);
+            }
+            path.push_str(part);
+
+            if last_create_id.is_none() {
+                if let Some(mailbox) = mailboxes.iter().find(|m| m.name == path) {
+                    parent_id = Some(mailbox.id);
+                    continue;
+                }
+            }
+
+            let create_id = format!("m{}", pos);
+            let mut mailbox = json!({ "name": part, "isSubscribed": true });
+            if let Some(last_create_id) = &last_create_id {
+                mailbox["parentId"] = format!("#{}", last_create_id).into();
+            } else if let Some(parent_id) = parent_id {
+                mailbox["parentId"] = JMAPId::from(parent_id).to_string().into();
+            }
+            create.insert(create_id.clone(), mailbox);
+            last_create_id = Some(create_id);
+        }
+
+        let last_create_id = if let Some(last_create_id) = last_create_id {
+            last_create_id
+        } else {
+            return Ok(parent_id);
+        };
+        let response = self
+            .jmap(vec![(
+                "Mailbox/set",
+                json!({
+                    "accountId": JMAPId::from(self.account_id()),
+                    "create": create,
+                }),
+            )])
+            .await?;
+        let response = response.first();
+        if let Some(id) = response
+            .and_then(|r| r.get("created"))
+            .and_then(|c| c.get(&last_create_id))
+            .and_then(|c| c.get("id"))
+            .and_then(|id| id.as_str())
+            .and_then(JMAPId::parse)
+        {
+            Ok(Some(id.get_document_id()))
+        } else {
+            Err(set_error(response, "notCreated"))
+        }
+    }
+
+    pub async fn handle_delete(&mut self, request: Request) -> super::Result<StatusResponse> {
+        let name = match request.tokens.into_iter().next().map(|t| t.unwrap_string()) {
+            Some(Ok(name)) => name,
+            _ => return Ok(StatusResponse::bad("Missing mailbox name.")),
+        };
+        let mailbox = match self.get_mailbox(&name).await? {
+            Some(mailbox) if mailbox.role.as_deref() == Some("inbox") => {
+                return Ok(StatusResponse::no("INBOX cannot be deleted.").with_code("CANNOT"));
+            }
+            Some(mailbox) => mailbox,
+            None => {
+                return Ok(StatusResponse::no("Mailbox does not exist.").with_code("NONEXISTENT"))
+            }
+        };
+
+        let response = self
+            .jmap(vec![(
+                "Mailbox/set",
+                json!({
+                    "accountId": JMAPId::from(self.account_id()),
+                    "destroy": [mailbox.jmap_id()],
+                    "onDestroyRemoveEmails": true,
+                }),
+            )])
+            .await?;
+        let response = response.first();
+        if response
+            .and_then(|r| r.get("destroyed"))
+            .and_then(|d| d.as_array())
+            .map_or(false, |d| !d.is_empty())
+        {
+            if self.selected.as_ref().map_or(false, |s| s.id == mailbox.id) {
+                self.selected = None;
+            }
+            Ok(StatusResponse::ok("DELETE completed."))
+        } else {
+            Ok(set_error(response, "notDestroyed"))
+        }
+    }
+
+    pub async fn handle_rename(&mut self, request: Request) -> super::Result<StatusResponse> {
+        let mut tokens = request.tokens.into_iter();
+        let (name, new_name) = match (
+            tokens.next().map(|t| t.unwrap_string()),
+            tokens.next().map(|t| t.unwrap_string()),
+        ) {
+            (Some(Ok(name)), Some(Ok(new_name))) => (
+                name,
+                normalize_name(new_name.trim_end_matches(HIERARCHY_DELIMITER)),
+            ),
+            _ => {
+                return Ok(StatusResponse::bad(
+                    "Expected existing and new mailbox names.",
+                ))
+            }
+        };
+
+        let mailboxes = self.get_mailboxes().await?;
+        let name = normalize_name(&name);
+        let mailbox = match mailboxes.iter().find(|m| m.name == name) {
+            Some(mailbox) if mailbox.role.as_deref() == Some("inbox") => {
+                return Ok(
+                    StatusResponse::no("Renaming INBOX is not supported.").with_code("CANNOT")
+                );
+            }
+            Some(mailbox) => mailbox.clone(),
+            None => {
+                return Ok(StatusResponse::no("Mailbox does not exist.").with_code("NONEXISTENT"))
+            }
+        };
+        if new_name.is_empty()
+            || new_name.eq_ignore_ascii_case("INBOX")
+            || mailboxes.iter().any(|m| m.name == new_name)
+        {
+            return Ok(StatusResponse::no("Mailbox already exists.").with_code("ALREADYEXISTS"));
+        } else if new_name.starts_with(&format!("{}{}", name, HIERARCHY_DELIMITER)) {
+            return Ok(
+                StatusResponse::no("Cannot move a mailbox under itself.").with_code("CANNOT")
+            );
+        }
+
+        // Create any missing parent mailboxes
+        let (parent_id, part) = match new_name.rsplit_once(HIERARCHY_DELIMITER) {
+            Some((parent_name, part)) => (self.create_path(&mailboxes, parent_name).await?, part),
+            None => (None, new_name.as_str()),
+        };
+
+        let response = self
+            .jmap(vec![(
+                "Mailbox/set",
+                json!({
+                    "accountId": JMAPId::from(self.account_id()),
+                    "update": {
+                        mailbox.jmap_id(): {
+                            "name": part,
+                            "parentId": parent_id.map(|id| JMAPId::from(id).to_string()),
+                        }
+                    },
+                }),
+            )])
+            .await?;
+        let response = response.first();
+        if response
+            .and_then(|r| r.get("updated"))
+            .and_then(|u| u.get(&mailbox.jmap_id()))
+            .is_some()
+        {
+            Ok(StatusResponse::ok("RENAME completed."))
+        } else {
+            Ok(set_error(response, "notUpdated"))
+        }
+    }
+
+    pub async fn handle_subscribe(&mut self, request: Request) -> super::Result<StatusResponse> {
+        let is_subscribe = request.command == "SUBSCRIBE";
+        let name = match request.tokens.into_iter().next().map(|t| t.unwrap_string()) {
+            Some(Ok(name)) => name,
+            _ => return Ok(StatusResponse::bad("Missing mailbox name.")),
+        };
+        let mailbox = match self.get_mailbox(&name).await? {
+            Some(mailbox) => mailbox,
+            None => {
+                return Ok(StatusResponse::no("Mailbox does not exist.").with_code("NONEXISTENT"))
+            }
+        };
+
+        if mailbox.is_subscribed != is_subscribe {
+            let response = self
+                .jmap(vec![(
+                    "Mailbox/set",
+                    json!({
+                        "accountId": JMAPId::from(self.account_id()),
+                        "update": {
+                            mailbox.jmap_id(): {
+                                "isSubscribed": is_subscribe,
+                            }
+                        },
+                    }),
+                )])
+                .await?;
+            let response = response.first();
+            if response
+                .and_then(|r| r.get("updated"))
+                .and_then(|u| u.get(&mailbox.jmap_id()))
+                .is_none()
+            {
+                return Ok(set_error(response, "notUpdated"));
+            }
+        }
+
+        Ok(StatusResponse::ok(format!(
+            "{} completed.",
+            request.command
+        )))
+    }
+}
+
+/// Converts any case variant of INBOX to its canonical form.
+pub fn normalize_name(name: &str) -> String {
+    match name.split_once(HIERARCHY_DELIMITER) {
+        Some((first, rest)) if first.eq_ignore_ascii_case("INBOX") => {
+            format!("INBOX{}{}", HIERARCHY_DELIMITER, rest)
+        }
+        None if name.eq_ignore_ascii_case("INBOX") => "INBOX".to_string(),
+        _ => name.to_string(),
+    }
+}
+
+/// Matches a mailbox name against a LIST pattern, '*' matches any sequence of
+/// characters while '%' does not match the hierarchy delimiter.
+pub fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let pattern = normalize_name(pattern);
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let name = name.chars().collect::<Vec<_>>();
+    matches_pattern_(&pattern, &name)
+}
+
+fn matches_pattern_(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some('*') => (0..=name.len()).any(|pos| matches_pattern_(&pattern[1..], &name[pos..])),
+        Some('%') => (0..=name.len())
+            .take_while(|&pos| pos == 0 || name[pos - 1] != HIERARCHY_DELIMITER)
+            .any(|pos| matches_pattern_(&pattern[1..], &name[pos..])),
+        Some(ch) => name.first() == Some(ch) && matches_pattern_(&pattern[1..], &name[1..]),
+    }
+}
+
+/// Builds a NO response from a JMAP set error.
+pub fn set_error(response: Option<&Value>, property: &str) -> StatusResponse {
+    let error = response
+        .and_then(|r| r.get(property))
+        .and_then(|e| e.as_object())
+        .and_then(|e| e.values().next());
+    let description = error
+        .and_then(|e| e.get("description"))
+        .and_then(|d| d.as_str())
+        .unwrap_or("Operation failed.")
+        .to_string();
+    match error.and_then(|e| e.get("type")).and_then(|t| t.as_str()) {
+        Some("mailboxHasChild") => StatusResponse::no(description).with_code("INUSE"),
+        Some("forbidden") => StatusResponse::no(description).with_code("NOPERM"),
+        Some("overQuota") => StatusResponse::no(description).with_code("OVERQUOTA"),
+        _ => StatusResponse::no(description).with_code("CANNOT"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{matches_pattern, normalize_name};
+
+    #[test]
+    fn list_patterns() {
+        for (pattern, name, expected) in [
+            ("*", "INBOX", true),
+            ("*", "Archive/2022", true),
+            ("%", "Archive/2022", false),
+            ("%", "Archive", true),
+            ("Archive/%", "Archive/2022", true),
+            ("Archive/%", "Archive/2022/June", false),
+            ("Archive/*", "Archive/2022/June", true),
+            ("inbox", "INBOX", true),
+            ("Inbox/%", "INBOX/Work", true),
+            ("Sent", "Sent Items", false),
+            ("Sent*", "Sent Items", true),
+        ] {
+            assert_eq!(
+                matches_pattern(pattern, name),
+                expected,
+                "{} {}",
+                pattern,
+                name
+            );
+        }
+        assert_eq!(normalize_name("inBox/Work"), "INBOX/Work");
+        assert_eq!(normalize_name("Inboxes"), "Inboxes");
+    }
+}
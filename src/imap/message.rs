@@ -0,0 +1,774 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::borrow::Cow;
+
+use super::response::{write_nstring, write_string};
+
+const MAX_NESTING: usize = 20;
+
+#[derive(Debug)]
+pub struct MimePart<'x> {
+    pub headers: Vec<(&'x str, &'x [u8])>,
+    pub offset_header: usize,
+    pub offset_body: usize,
+    pub offset_end: usize,
+    pub content_type: ContentType,
+    pub body: PartBody<'x>,
+}
+
+#[derive(Debug)]
+pub enum PartBody<'x> {
+    Leaf,
+    Multipart(Vec<MimePart<'x>>),
+    Message(Box<MimePart<'x>>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentType {
+    pub c_type: String,
+    pub c_subtype: String,
+    pub attributes: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Section {
+    Full,
+    Header,
+    HeaderFields { fields: Vec<String>, not: bool },
+    Text,
+    Mime,
+}
+
+pub struct Message<'x> {
+    pub raw: &'x [u8],
+    pub root: MimePart<'x>,
+}
+
+impl<'x> Message<'x> {
+    pub fn parse(raw: &'x [u8]) -> Self {
+        Message {
+            raw,
+            root: MimePart::parse(raw, 0, raw.len(), false, 0),
+        }
+    }
+
+    /// Returns the contents of a body section, as requested by BODY[...].
+    pub fn section(&self, parts: &[u32], section: &Section) -> Option<Cow<'x, [u8]>> {
+        let mut part = &self.root;
+        let mut is_message = true;
+
+        for &part_id in parts {
+            // Parts of an encapsulated message refer to the message itself
+            if !is_message {
+                if let PartBody::Message(message) = &part.body {
+                    part = message;
+                }
+            }
+            part = match &part.body {
+                PartBody::Multipart(subparts) => {
+                    subparts.get((part_id as usize).checked_sub(1)?)?
+                }
+                _ if part_id == 1 => part,
+                _ => return None,
+            };
+            is_message = false;
+        }
+
+        let raw = self.raw;
+        match section {
+            Section::Full if parts.is_empty() => Some(raw.into()),
+            Section::Full => raw.get(part.offset_body..part.offset_end).map(Cow::from),
+            Section::Mime if !parts.is_empty() => {
+                raw.get(part.offset_header..part.offset_body).map(Cow::from)
+            }
+            Section::Mime => None,
+            Section::Header | Section::HeaderFields { .. } | Section::Text => {
+                let message = if parts.is_empty() {
+                    part
+                } else if let PartBody::Message(message) = &part.body {
+                    message
+                } else {
+                    return None;
+                };
+                match section {
+                    Section::Header => raw
+                        .get(message.offset_header..message.offset_body)
+                        .map(Cow::from),
+                    Section::Text => raw
+                        .get(message.offset_body..message.offset_end)
+                        .map(Cow::from),
+                    Section::HeaderFields { fields, not } => {
+                        let mut buf = Vec::new();
+                        for (name, value) in &message.headers {
+                            if fields.iter().any(|f| f.eq_ignore_ascii_case(name)) != *not {
+                                buf.extend_from_slice(name.as_bytes());
+                                buf.push(b':');
+                                buf.extend_from_slice(value);
+                            }
+                        }
+                        buf.extend_from_slice(b"\r\n");
+                        Some(buf.into())
+                    }
+                    _ => unreachable!(),
+                }
+            }
+        }
+    }
+
+    pub fn write_envelope(&self, buf: &mut Vec<u8>) {
+        self.root.write_envelope(buf);
+    }
+
+    pub fn write_body_structure(&self, buf: &mut Vec<u8>, is_extended: bool) {
+        self.root.write_part_structure(self.raw, buf, is_extended);
+    }
+}
+
+impl<'x> MimePart<'x> {
+    fn parse(raw: &'x [u8], start: usize, end: usize, is_digest: bool, depth: usize) -> Self {
+        let (headers, offset_body) = parse_headers(raw, start, end);
+        let content_type = headers
+            .iter()
+            .rev()
+            .find(|(name, _)| name.eq_ignore_ascii_case("Content-Type"))
+            .and_then(|(_, value)| ContentType::parse(value))
+            .unwrap_or_else(|| {
+                if is_digest {
+                    ContentType::new("message", "rfc822")
+                } else {
+                    ContentType::new("text", "plain")
+                }
+            });
+
+        let body = if depth >= MAX_NESTING {
+            PartBody::Leaf
+        } else if content_type.c_type == "multipart" {
+            if let Some(boundary) = content_type.attribute("boundary") {
+                let is_digest = content_type.c_subtype == "digest";
+                PartBody::Multipart(
+                    split_multipart(raw, offset_body, end, boundary.as_bytes())
+                        .into_iter()
+                        .map(|(start, end)| MimePart::parse(raw, start, end, is_digest, depth + 1))
+                        .collect(),
+                )
+            } else {
+                PartBody::Leaf
+            }
+        } else if content_type.c_type == "message"
+            && (content_type.c_subtype == "rfc822" || content_type.c_subtype == "global")
+        {
+            PartBody::Message(Box::new(MimePart::parse(
+                raw,
+                offset_body,
+                end,
+                false,
+                depth + 1,
+            )))
+        } else {
+            PartBody::Leaf
+        };
+
+        MimePart {
+            headers,
+            offset_header: start,
+            offset_body,
+            offset_end: end,
+            content_type,
+            body,
+        }
+    }
+
+    fn header(&self, name: &str) -> Option<&'x [u8]> {
+        self.headers
+            .iter()
+            .rev()
+            .find(|(header_name, _)| header_name.eq_ignore_ascii_case(name))
+            .map(|(_, value)| trim_bytes(value))
+    }
+
+    fn write_envelope(&self, buf: &mut Vec<u8>) {
+        buf.push(b'(');
+        for name in ["Date", "Subject"] {
+            write_nstring(buf, self.header(name).map(unfold).as_deref());
+            buf.push(b' ');
+        }
+        let from = self.header("From");
+        for (name, default) in [
+            ("From", None),
+            ("Sender", from),
+            ("Reply-To", from),
+            ("To", None),
+            ("Cc", None),
+            ("Bcc", None),
+        ] {
+            write_addresses(buf, self.header(name).or(default));
+            buf.push(b' ');
+        }
+        write_nstring(buf, self.header("In-Reply-To").map(unfold).as_deref());
+        buf.push(b' ');
+        write_nstring(buf, self.header("Message-ID").map(unfold).as_deref());
+        buf.push(b')');
+    }
+
+    fn write_part_structure(&self, raw: &[u8], buf: &mut Vec<u8>, is_extended: bool) {
+        buf.push(b'(');
+        match &self.body {
+            PartBody::Multipart(subparts) if !subparts.is_empty() => {
+                for subpart in subparts {
+                    subpart.write_part_structure(raw, buf, is_extended);
+                }
+                buf.push(b' ');
+                write_string(buf, self.content_type.c_subtype.as_bytes());
+                if is_extended {
+                    buf.push(b' ');
+                    self.content_type.write_attributes(buf);
+                    buf.push(b' ');
+                    self.write_disposition(buf);
+                    buf.extend_from_slice(b" ");
+                    write_nstring(buf, self.header("Content-Language").map(unfold).as_deref());
+                    buf.extend_from_slice(b" ");
+                    write_nstring(buf, self.header("Content-Location").map(unfold).as_deref());
+                }
+            }
+            _ => {
+                let (c_type, c_subtype) = if self.content_type.c_type == "multipart" {
+                    ("text", "plain")
+                } else {
+                    (
+                        self.content_type.c_type.as_str(),
+                        self.content_type.c_subtype.as_str(),
+                    )
+                };
+                write_string(buf, c_type.as_bytes());
+                buf.push(b' ');
+                write_string(buf, c_subtype.as_bytes());
+                buf.push(b' ');
+                self.content_type.write_attributes(buf);
+                buf.push(b' ');
+                write_nstring(buf, self.header("Content-ID").map(unfold).as_deref());
+                buf.push(b' ');
+                write_nstring(
+                    buf,
+                    self.header("Content-Description").map(unfold).as_deref(),
+                );
+                buf.push(b' ');
+                write_string(
+                    buf,
+                    self.header("Content-Transfer-Encoding")
+                        .map(unfold)
+                        .as_deref()
+                        .unwrap_or(&b"7BIT"[..]),
+                );
+                let body = raw
+                    .get(self.offset_body..self.offset_end)
+                    .unwrap_or_default();
+                buf.extend_from_slice(format!(" {}", body.len()).as_bytes());
+                match &self.body {
+                    PartBody::Message(message) => {
+                        buf.push(b' ');
+                        message.write_envelope(buf);
+                        buf.push(b' ');
+                        message.write_part_structure(raw, buf, is_extended);
+                        buf.extend_from_slice(format!(" {}", count_lines(body)).as_bytes());
+                    }
+                    _ if c_type == "text" => {
+                        buf.extend_from_slice(format!(" {}", count_lines(body)).as_bytes());
+                    }
+                    _ => (),
+                }
+                if is_extended {
+                    buf.extend_from_slice(b" ");
+                    write_nstring(buf, self.header("Content-MD5").map(unfold).as_deref());
+                    buf.push(b' ');
+                    self.write_disposition(buf);
+                    buf.extend_from_slice(b" ");
+                    write_nstring(buf, self.header("Content-Language").map(unfold).as_deref());
+                    buf.extend_from_slice(b" ");
+                    write_nstring(buf, self.header("Content-Location").map(unfold).as_deref());
+                }
+            }
+        }
+        buf.push(b')');
+    }
+
+    fn write_disposition(&self, buf: &mut Vec<u8>) {
+        if let Some(disposition) = self
+            .header("Content-Disposition")
+            .and_then(ContentType::parse_disposition)
+        {
+            buf.push(b'(');
+            write_string(buf, disposition.c_type.as_bytes());
+            buf.push(b' ');
+            disposition.write_attributes(buf);
+            buf.push(b')');
+        } else {
+            buf.extend_from_slice(b"NIL");
+        }
+    }
+}
+
+impl ContentType {
+    pub fn new(c_type: &str, c_subtype: &str) -> Self {
+        ContentType {
+            c_type: c_type.to_string(),
+            c_subtype: c_subtype.to_string(),
+            attributes: Vec::new(),
+        }
+    }
+
+    pub fn parse(value: &[u8]) -> Option<Self> {
+        let value = String::from_utf8_lossy(&unfold(value)).into_owned();
+        let mut parts = split_unquoted(&value, ';').into_iter();
+        let (c_type, c_subtype) = parts.next()?.split_once('/')?;
+        let c_type = c_type.trim().to_ascii_lowercase();
+        let c_subtype = c_subtype.trim().to_ascii_lowercase();
+        if c_type.is_empty() || c_subtype.is_empty() {
+            return None;
+        }
+
+        Some(ContentType {
+            c_type,
+            c_subtype,
+            attributes: parse_attributes(parts),
+        })
+    }
+
+    pub fn parse_disposition(value: &[u8]) -> Option<Self> {
+        let value = String::from_utf8_lossy(&unfold(value)).into_owned();
+        let mut parts = split_unquoted(&value, ';').into_iter();
+        let c_type = parts.next()?.trim().to_ascii_lowercase();
+        if c_type.is_empty() {
+            return None;
+        }
+
+        Some(ContentType {
+            c_type,
+            c_subtype: String::new(),
+            attributes: parse_attributes(parts),
+        })
+    }
+
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(attr_name, _)| attr_name == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn write_attributes(&self, buf: &mut Vec<u8>) {
+        if !self.attributes.is_empty() {
+            buf.push(b'(');
+            for (pos, (name, value)) in self.attributes.iter().enumerate() {
+                if pos > 0 {
+                    buf.push(b' ');
+                }
+                write_string(buf, name.as_bytes());
+                buf.push(b' ');
+                write_string(buf, value.as_bytes());
+            }
+            buf.push(b')');
+        } else {
+            buf.extend_from_slice(b"NIL");
+        }
+    }
+}
+
+fn parse_attributes<'x>(parts: impl Iterator<Item = &'x str>) -> Vec<(String, String)> {
+    parts
+        .filter_map(|part| {
+            let (name, value) = part.split_once('=')?;
+            let name = name.trim().to_ascii_lowercase();
+            let value = value.trim();
+            let value = if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+                value[1..value.len() - 1].replace("\\\"", "\"")
+            } else {
+                value.to_string()
+            };
+            if !name.is_empty() {
+                Some((name, value))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+fn parse_headers(raw: &[u8], start: usize, end: usize) -> (Vec<(&str, &[u8])>, usize) {
+    let mut headers = Vec::new();
+    let mut pos = start;
+
+    while pos < end {
+        let line_end = raw[pos..end]
+            .iter()
+            .position(|&ch| ch == b'\n')
+            .map(|p| pos + p + 1)
+            .unwrap_or(end);
+        let line = &raw[pos..line_end];
+
+        if line == b"\r\n" || line == b"\n" {
+            return (headers, line_end);
+        } else if matches!(line.first(), Some(b' ' | b'\t')) {
+            // Folded line, extend the previous header
+            if let Some((_, value)) = headers.last_mut() {
+                let value_start = value.as_ptr() as usize - raw.as_ptr() as usize;
+                *value = &raw[value_start..line_end];
+            }
+        } else if let Some(colon) = line.iter().position(|&ch| ch == b':') {
+            if let Ok(name) = std::str::from_utf8(&line[..colon]) {
+                let name = name.trim();
+                if !name.is_empty() && !name.contains(' ') {
+                    headers.push((name, &raw[pos + colon + 1..line_end]));
+                } else if headers.is_empty() {
+                    // Not a header, the message has no header section
+                    return (headers, start);
+                }
+            }
+        } else if headers.is_empty() {
+            return (headers, start);
+        }
+
+        pos = line_end;
+    }
+
+    (headers, end)
+}
+
+fn split_multipart(raw: &[u8], start: usize, end: usize, boundary: &[u8]) -> Vec<(usize, usize)> {
+    let mut parts = Vec::new();
+    let mut part_start = None;
+    let mut pos = start;
+
+    while pos < end {
+        let line_end = raw[pos..end]
+            .iter()
+            .position(|&ch| ch == b'\n')
+            .map(|p| pos + p + 1)
+            .unwrap_or(end);
+        let line = &raw[pos..line_end];
+
+        if line.starts_with(b"--") && line[2..].starts_with(boundary) {
+            let rest = trim_bytes(&line[2 + boundary.len()..]);
+            if rest.is_empty() || rest == b"--" {
+                if let Some(part_start) = part_start {
+                    // The CRLF preceding the delimiter belongs to the delimiter
+                    let mut part_end = pos;
+                    if part_end > part_start && raw[part_end - 1] == b'\n' {
+                        part_end -= 1;
+                        if part_end > part_start && raw[part_end - 1] == b'\r' {
+                            part_end -= 1;
+                        }
+                    }
+                    parts.push((part_start, part_end));
+                }
+                if rest == b"--" {
+                    return parts;
+                }
+                part_start = Some(line_end);
+            }
+        }
+
+        pos = line_end;
+    }
+
+    if let Some(part_start) = part_start {
+        parts.push((part_start, end));
+    }
+
+    parts
+}
+
+fn split_unquoted(value: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut in_quote = false;
+    let mut is_escaped = false;
+    let mut start = 0;
+
+    for (pos, ch) in value.char_indices() {
+        match ch {
+            '\\' if in_quote && !is_escaped => {
+                is_escaped = true;
+                continue;
+            }
+            '"' if !is_escaped => in_quote = !in_quote,
+            _ if ch == separator && !in_quote => {
+                parts.push(&value[start..pos]);
+                start = pos + 1;
+            }
+            _ => (),
+        }
+        is_escaped = false;
+    }
+    parts.push(&value[start..]);
+    parts
+}
+
+fn write_addresses(buf: &mut Vec<u8>, value: Option<&[u8]>) {
+    let value = match value {
+        Some(value) if !value.is_empty() => String::from_utf8_lossy(&unfold(value)).into_owned(),
+        _ => {
+            buf.extend_from_slice(b"NIL");
+            return;
+        }
+    };
+
+    let mut addresses = Vec::new();
+    let mut value = value.as_str();
+
+    while !value.trim().is_empty() {
+        // Groups
+        if let Some((group_name, rest)) = split_group(value) {
+            let (members, rest) = rest.split_once(';').unwrap_or((rest, ""));
+            addresses.push((None, None, Some(group_name.trim().to_string())));
+            for member in split_unquoted(members, ',') {
+                if let Some(address) = parse_address(member) {
+                    addresses.push(address);
+                }
+            }
+            addresses.push((None, None, None));
+            value = rest.trim_start_matches(|c: char| c == ',' || c.is_whitespace());
+        } else {
+            let (address, rest) = match split_unquoted(value, ',').first() {
+                Some(address) => (*address, &value[address.len()..]),
+                None => break,
+            };
+            if let Some(address) = parse_address(address) {
+                addresses.push(address);
+            }
+            value = rest.trim_start_matches(',');
+        }
+    }
+
+    if addresses.is_empty() {
+        buf.extend_from_slice(b"NIL");
+        return;
+    }
+
+    buf.push(b'(');
+    for (name, address, group) in addresses {
+        buf.push(b'(');
+        write_nstring(buf, name.as_deref().map(|n| n.as_bytes()));
+        buf.extend_from_slice(b" NIL ");
+        match (address, group) {
+            (Some(address), _) => {
+                let (mailbox, host) = address.rsplit_once('@').unwrap_or((address.as_str(), ""));
+                write_string(buf, mailbox.as_bytes());
+                buf.push(b' ');
+                if !host.is_empty() {
+                    write_string(buf, host.as_bytes());
+                } else {
+                    buf.extend_from_slice(b"NIL");
+                }
+            }
+            (None, Some(group)) => {
+                write_string(buf, group.as_bytes());
+                buf.extend_from_slice(b" NIL");
+            }
+            (None, None) => {
+                buf.extend_from_slice(b"NIL NIL");
+            }
+        }
+        buf.push(b')');
+    }
+    buf.push(b')');
+}
+
+type Address = (Option<String>, Option<String>, Option<String>);
+
+fn split_group(value: &str) -> Option<(&str, &str)> {
+    let mut in_quote = false;
+    for (pos, ch) in value.char_indices() {
+        match ch {
+            '"' => in_quote = !in_quote,
+            ':' if !in_quote => return Some((&value[..pos], &value[pos + 1..])),
+            '<' | ',' | '@' if !in_quote => return None,
+            _ => (),
+        }
+    }
+    None
+}
+
+fn parse_address(value: &str) -> Option<Address> {
+    let value = value.trim();
+    if value.is_empty() {
+        return None;
+    }
+
+    let (name, address) = if let (Some(start), Some(end)) = (value.rfind('<'), value.rfind('>')) {
+        if start < end {
+            (value[..start].trim(), value[start + 1..end].trim())
+        } else {
+            ("", value)
+        }
+    } else {
+        // Strip comments, which are sometimes used as display names
+        match (value.find('('), value.rfind(')')) {
+            (Some(start), Some(end)) if start < end => {
+                (value[start + 1..end].trim(), value[..start].trim())
+            }
+            _ => ("", value),
+        }
+    };
+
+    let name = name.trim_matches('"').replace("\\\"", "\"");
+    Some((
+        if !name.is_empty() { Some(name) } else { None },
+        Some(address.to_string()),
+        None,
+    ))
+}
+
+fn unfold(value: &[u8]) -> Cow<'_, [u8]> {
+    let value = trim_bytes(value);
+    if value.contains(&b'\n') {
+        let mut unfolded = Vec::with_capacity(value.len());
+        for &ch in value {
+            if ch != b'\r' && ch != b'\n' {
+                unfolded.push(ch);
+            }
+        }
+        unfolded.into()
+    } else {
+        value.into()
+    }
+}
+
+fn trim_bytes(value: &[u8]) -> &[u8] {
+    let start = value
+        .iter()
+        .position(|ch| !ch.is_ascii_whitespace())
+        .unwrap_or(value.len());
+    let end = value
+        .iter()
+        .rposition(|ch| !ch.is_ascii_whitespace())
+        .map(|p| p + 1)
+        .unwrap_or(start);
+    &value[start..end]
+}
+
+fn count_lines(value: &[u8]) -> usize {
+    let lines = value.iter().filter(|&&ch| ch == b'\n').count();
+    if !value.is_empty() && !value.ends_with(b"\n") {
+        lines + 1
+    } else {
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Message, Section};
+
+    const MESSAGE: &str = concat!(
+        "From: \"John Doe\" <jdoe@example.com>\r\n",
+        "To: Jane <jane@example.com>, bill@example.com\r\n",
+        "Subject: Test\r\n",
+        " message\r\n",
+        "Message-ID: <1234@example.com>\r\n",
+        "Content-Type: multipart/mixed; boundary=\"b1\"\r\n",
+        "\r\n",
+        "Preamble\r\n",
+        "--b1\r\n",
+        "Content-Type: text/plain; charset=us-ascii\r\n",
+        "\r\n",
+        "Hello\r\n",
+        "world\r\n",
+        "--b1\r\n",
+        "Content-Type: message/rfc822\r\n",
+        "\r\n",
+        "Subject: Inner\r\n",
+        "\r\n",
+        "Inner body\r\n",
+        "--b1--\r\n",
+    );
+
+    #[test]
+    fn message_sections() {
+        let message = Message::parse(MESSAGE.as_bytes());
+
+        assert_eq!(
+            message.section(&[1], &Section::Full).unwrap().as_ref(),
+            b"Hello\r\nworld"
+        );
+        assert_eq!(
+            message.section(&[1], &Section::Mime).unwrap().as_ref(),
+            b"Content-Type: text/plain; charset=us-ascii\r\n\r\n"
+        );
+        assert_eq!(
+            message.section(&[2], &Section::Header).unwrap().as_ref(),
+            b"Subject: Inner\r\n\r\n"
+        );
+        assert_eq!(
+            message.section(&[2], &Section::Text).unwrap().as_ref(),
+            b"Inner body"
+        );
+        assert_eq!(
+            message.section(&[2, 1], &Section::Full).unwrap().as_ref(),
+            b"Inner body"
+        );
+        assert_eq!(
+            message
+                .section(
+                    &[],
+                    &Section::HeaderFields {
+                        fields: vec!["subject".to_string()],
+                        not: false
+                    }
+                )
+                .unwrap()
+                .as_ref(),
+            b"Subject: Test\r\n message\r\n\r\n"
+        );
+        assert!(message.section(&[3], &Section::Full).is_none());
+    }
+
+    #[test]
+    fn message_structure() {
+        let message = Message::parse(MESSAGE.as_bytes());
+
+        let mut envelope = Vec::new();
+        message.write_envelope(&mut envelope);
+        assert_eq!(
+            String::from_utf8(envelope).unwrap(),
+            concat!(
+                "(NIL \"Test message\" ((\"John Doe\" NIL \"jdoe\" \"example.com\")) ",
+                "((\"John Doe\" NIL \"jdoe\" \"example.com\")) ",
+                "((\"John Doe\" NIL \"jdoe\" \"example.com\")) ",
+                "((\"Jane\" NIL \"jane\" \"example.com\")(NIL NIL \"bill\" \"example.com\")) ",
+                "NIL NIL NIL \"<1234@example.com>\")"
+            )
+        );
+
+        let mut body = Vec::new();
+        message.write_body_structure(&mut body, false);
+        assert_eq!(
+            String::from_utf8(body).unwrap(),
+            concat!(
+                "((\"text\" \"plain\" (\"charset\" \"us-ascii\") NIL NIL \"7BIT\" 12 2)",
+                "(\"message\" \"rfc822\" NIL NIL NIL \"7BIT\" 28 ",
+                "(NIL \"Inner\" NIL NIL NIL NIL NIL NIL NIL NIL) ",
+                "(\"text\" \"plain\" NIL NIL NIL \"7BIT\" 10 1) 3) \"mixed\")"
+            )
+        );
+    }
+}
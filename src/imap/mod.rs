@@ -0,0 +1,178 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+pub mod append;
+pub mod authenticate;
+pub mod client;
+pub mod copy_move;
+pub mod fetch;
+pub mod flags;
+pub mod idle;
+pub mod listener;
+pub mod mailbox;
+pub mod message;
+pub mod receiver;
+pub mod response;
+pub mod search;
+pub mod select;
+pub mod session;
+
+use store::chrono::{DateTime, FixedOffset, NaiveDate};
+
+use self::response::StatusResponse;
+
+pub type Result<T> = std::result::Result<T, StatusResponse>;
+
+pub const HIERARCHY_DELIMITER: char = '/';
+
+pub const SYSTEM_FLAGS: [(&str, &str); 5] = [
+    ("\\Seen", "$seen"),
+    ("\\Answered", "$answered"),
+    ("\\Flagged", "$flagged"),
+    ("\\Deleted", "$deleted"),
+    ("\\Draft", "$draft"),
+];
+
+/// Maps a JMAP keyword to an IMAP flag.
+pub fn keyword_to_flag(keyword: &str) -> String {
+    for (flag, system_keyword) in SYSTEM_FLAGS {
+        if keyword.eq_ignore_ascii_case(system_keyword) {
+            return flag.to_string();
+        }
+    }
+    keyword.to_string()
+}
+
+/// Maps an IMAP flag to a JMAP keyword, \Recent and unknown system flags are ignored.
+pub fn flag_to_keyword(flag: &str) -> Option<String> {
+    if flag.starts_with('\\') {
+        SYSTEM_FLAGS.iter().find_map(|(system_flag, keyword)| {
+            if system_flag.eq_ignore_ascii_case(flag) {
+                Some(keyword.to_string())
+            } else {
+                None
+            }
+        })
+    } else if !flag.is_empty()
+        && flag
+            .chars()
+            .all(|ch| ch.is_ascii_graphic() && !"(){]%*\"\\".contains(ch))
+    {
+        Some(flag.to_ascii_lowercase())
+    } else {
+        None
+    }
+}
+
+/// A sequence set, '*' is represented as u32::MAX.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sequence {
+    pub ranges: Vec<(u32, u32)>,
+}
+
+impl Sequence {
+    pub fn parse(value: &[u8]) -> Option<Self> {
+        let value = std::str::from_utf8(value).ok()?;
+        let mut ranges = Vec::new();
+
+        for item in value.split(',') {
+            let (start, end) = item.split_once(':').unwrap_or((item, item));
+            let parse = |value: &str| -> Option<u32> {
+                if value == "*" {
+                    Some(u32::MAX)
+                } else {
+                    value.parse::<u32>().ok().filter(|&v| v > 0)
+                }
+            };
+            ranges.push((parse(start)?, parse(end)?));
+        }
+
+        if !ranges.is_empty() {
+            Some(Sequence { ranges })
+        } else {
+            None
+        }
+    }
+
+    pub fn contains(&self, value: u32, largest: u32) -> bool {
+        self.ranges.iter().any(|&(start, end)| {
+            let start = if start == u32::MAX { largest } else { start };
+            let end = if end == u32::MAX { largest } else { end };
+            if start <= end {
+                (start..=end).contains(&value)
+            } else {
+                (end..=start).contains(&value)
+            }
+        })
+    }
+}
+
+pub fn format_date_time(value: &str) -> Option<String> {
+    DateTime::parse_from_rfc3339(value)
+        .ok()
+        .map(|date| date.format("%d-%b-%Y %H:%M:%S %z").to_string())
+}
+
+pub fn parse_date_time(value: &str) -> Option<DateTime<FixedOffset>> {
+    DateTime::parse_from_str(value.trim(), "%d-%b-%Y %H:%M:%S %z").ok()
+}
+
+pub fn parse_date(value: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(value.trim(), "%d-%b-%Y").ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{flag_to_keyword, format_date_time, keyword_to_flag, parse_date_time, Sequence};
+
+    #[test]
+    fn sequences_and_flags() {
+        let sequence = Sequence::parse(b"1:3,7,10:*").unwrap();
+        for (value, expected) in [(1, true), (3, true), (4, false), (7, true), (12, true)] {
+            assert_eq!(sequence.contains(value, 12), expected, "{}", value);
+        }
+        assert!(!sequence.contains(9, 12));
+        assert!(Sequence::parse(b"0:3").is_none());
+        assert!(Sequence::parse(b"a").is_none());
+
+        assert_eq!(flag_to_keyword("\\SEEN"), Some("$seen".to_string()));
+        assert_eq!(flag_to_keyword("\\Recent"), None);
+        assert_eq!(
+            flag_to_keyword("$Forwarded"),
+            Some("$forwarded".to_string())
+        );
+        assert_eq!(keyword_to_flag("$deleted"), "\\Deleted");
+        assert_eq!(keyword_to_flag("$junk"), "$junk");
+
+        assert_eq!(
+            format_date_time("2022-07-17T02:44:25Z").unwrap(),
+            "17-Jul-2022 02:44:25 +0000"
+        );
+        assert_eq!(
+            parse_date_time("17-Jul-1996 02:44:25 -0700")
+                .unwrap()
+                .timestamp(),
+            837596665
+        );
+    }
+}
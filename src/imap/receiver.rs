@@ -0,0 +1,506 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::fmt::Display;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token {
+    Atom(Vec<u8>),
+    String(Vec<u8>),
+    ParenthesisOpen,
+    ParenthesisClose,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub tag: String,
+    pub command: String,
+    pub tokens: Vec<Token>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    Request(Request),
+    Line(Vec<u8>),
+    NeedsMoreBytes,
+    NeedsLiteral {
+        size: usize,
+    },
+    Error {
+        tag: Option<String>,
+        message: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Line,
+    Literal { remaining: usize },
+    Skip { remaining: usize },
+    Discard { report: bool },
+}
+
+pub struct Receiver {
+    pub buf: Vec<u8>,
+    pub is_line_mode: bool,
+    state: State,
+    line_start: usize,
+    max_request_size: usize,
+}
+
+impl Receiver {
+    pub fn new(max_request_size: usize) -> Self {
+        Receiver {
+            buf: Vec::with_capacity(1024),
+            is_line_mode: false,
+            state: State::Line,
+            line_start: 0,
+            max_request_size,
+        }
+    }
+
+    pub fn parse(&mut self, bytes: &mut std::slice::Iter<'_, u8>) -> Event {
+        loop {
+            match self.state {
+                State::Line => {
+                    let mut is_done = true;
+                    for &ch in bytes.by_ref() {
+                        self.buf.push(ch);
+                        if ch == b'\n' {
+                            if let Some(event) = self.end_of_line() {
+                                return event;
+                            } else {
+                                // Non-synchronizing literal, keep reading
+                                is_done = false;
+                                break;
+                            }
+                        } else if self.buf.len() > self.max_request_size {
+                            self.state = State::Discard { report: true };
+                            is_done = false;
+                            break;
+                        }
+                    }
+                    if is_done {
+                        return Event::NeedsMoreBytes;
+                    }
+                }
+                State::Literal { remaining } => {
+                    let bytes_left = bytes.as_slice();
+                    if bytes_left.len() >= remaining {
+                        self.buf.extend_from_slice(&bytes_left[..remaining]);
+                        bytes.nth(remaining - 1);
+                        self.line_start = self.buf.len();
+                        self.state = State::Line;
+                    } else if !bytes_left.is_empty() {
+                        self.buf.extend_from_slice(bytes_left);
+                        bytes.nth(bytes_left.len() - 1);
+                        self.state = State::Literal {
+                            remaining: remaining - bytes_left.len(),
+                        };
+                        return Event::NeedsMoreBytes;
+                    } else {
+                        return Event::NeedsMoreBytes;
+                    }
+                }
+                State::Skip { remaining } => {
+                    // Skip the contents of an oversized non-synchronizing literal
+                    let bytes_left = bytes.len();
+                    if bytes_left >= remaining {
+                        if remaining > 0 {
+                            bytes.nth(remaining - 1);
+                        }
+                        self.state = State::Discard { report: false };
+                    } else {
+                        if bytes_left > 0 {
+                            bytes.nth(bytes_left - 1);
+                        }
+                        self.state = State::Skip {
+                            remaining: remaining - bytes_left,
+                        };
+                        return Event::NeedsMoreBytes;
+                    }
+                }
+                State::Discard { report } => {
+                    // Skip the remainder of an oversized line
+                    for &ch in bytes.by_ref() {
+                        if ch == b'\n' {
+                            let tag = self.tag();
+                            self.reset();
+                            if report {
+                                return Event::Error {
+                                    tag,
+                                    message: "Request too large.".to_string(),
+                                };
+                            } else {
+                                return self.parse(bytes);
+                            }
+                        }
+                    }
+                    return Event::NeedsMoreBytes;
+                }
+            }
+        }
+    }
+
+    fn end_of_line(&mut self) -> Option<Event> {
+        if self.is_line_mode {
+            let mut line = std::mem::take(&mut self.buf);
+            self.reset();
+            while matches!(line.last(), Some(b'\r' | b'\n')) {
+                line.pop();
+            }
+            return Some(Event::Line(line));
+        }
+
+        // Look for a literal at the end of the line
+        let line = &self.buf[self.line_start..];
+        let line = line
+            .strip_suffix(b"\r\n")
+            .or_else(|| line.strip_suffix(b"\n"))
+            .unwrap_or(line);
+        if let Some(literal) = line.strip_suffix(b"}") {
+            if let Some(pos) = literal.iter().rposition(|&ch| ch == b'{') {
+                let literal = &literal[pos + 1..];
+                let (literal, is_sync) = if let Some(literal) = literal.strip_suffix(b"+") {
+                    (literal, false)
+                } else {
+                    (literal, true)
+                };
+                if let Some(size) = std::str::from_utf8(literal)
+                    .ok()
+                    .and_then(|size| size.parse::<usize>().ok())
+                {
+                    if self.buf.len() + size > self.max_request_size {
+                        let tag = self.tag();
+                        self.reset();
+                        if !is_sync {
+                            self.state = State::Skip { remaining: size };
+                        }
+                        return Some(Event::Error {
+                            tag,
+                            message: "Literal exceeds the maximum request size.".to_string(),
+                        });
+                    } else if size > 0 {
+                        self.state = State::Literal { remaining: size };
+                    } else {
+                        self.line_start = self.buf.len();
+                    }
+                    return if is_sync {
+                        Some(Event::NeedsLiteral { size })
+                    } else {
+                        None
+                    };
+                }
+            }
+        }
+
+        let buf = std::mem::take(&mut self.buf);
+        self.reset();
+        Some(match tokenize(&buf) {
+            Ok(request) => Event::Request(request),
+            Err(message) => Event::Error {
+                tag: tokenize_tag(&buf),
+                message,
+            },
+        })
+    }
+
+    fn tag(&self) -> Option<String> {
+        tokenize_tag(&self.buf)
+    }
+
+    fn reset(&mut self) {
+        self.buf.clear();
+        self.state = State::Line;
+        self.line_start = 0;
+    }
+}
+
+fn tokenize_tag(buf: &[u8]) -> Option<String> {
+    let tag = buf
+        .iter()
+        .take_while(|ch| !ch.is_ascii_whitespace())
+        .copied()
+        .collect::<Vec<_>>();
+    if !tag.is_empty() {
+        String::from_utf8(tag).ok()
+    } else {
+        None
+    }
+}
+
+pub fn tokenize(buf: &[u8]) -> Result<Request, String> {
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+
+    while pos < buf.len() {
+        match buf[pos] {
+            b' ' | b'\t' | b'\r' | b'\n' => {
+                pos += 1;
+            }
+            b'(' => {
+                tokens.push(Token::ParenthesisOpen);
+                pos += 1;
+            }
+            b')' => {
+                tokens.push(Token::ParenthesisClose);
+                pos += 1;
+            }
+            b'"' => {
+                let mut value = Vec::new();
+                let mut is_escaped = false;
+                pos += 1;
+                loop {
+                    match buf.get(pos) {
+                        Some(b'\\') if !is_escaped => {
+                            is_escaped = true;
+                        }
+                        Some(b'"') if !is_escaped => {
+                            pos += 1;
+                            break;
+                        }
+                        Some(b'\r' | b'\n') | None => {
+                            return Err("Unterminated quoted string.".to_string());
+                        }
+                        Some(&ch) => {
+                            value.push(ch);
+                            is_escaped = false;
+                        }
+                    }
+                    pos += 1;
+                }
+                tokens.push(Token::String(value));
+            }
+            b'{' | b'~' if buf[pos] == b'{' || buf.get(pos + 1) == Some(&b'{') => {
+                let start = if buf[pos] == b'~' { pos + 2 } else { pos + 1 };
+                let end = buf[start..]
+                    .iter()
+                    .position(|&ch| ch == b'}')
+                    .map(|end| start + end)
+                    .ok_or_else(|| "Invalid literal.".to_string())?;
+                let size = std::str::from_utf8(&buf[start..end])
+                    .ok()
+                    .and_then(|size| size.trim_end_matches('+').parse::<usize>().ok())
+                    .ok_or_else(|| "Invalid literal size.".to_string())?;
+                pos = end + 1;
+                if buf.get(pos) == Some(&b'\r') {
+                    pos += 1;
+                }
+                if buf.get(pos) == Some(&b'\n') {
+                    pos += 1;
+                } else {
+                    return Err("Expected CRLF after literal.".to_string());
+                }
+                let value = buf
+                    .get(pos..pos + size)
+                    .ok_or_else(|| "Literal is truncated.".to_string())?;
+                tokens.push(Token::String(value.to_vec()));
+                pos += size;
+            }
+            _ => {
+                let start = pos;
+                let mut depth = 0;
+                while pos < buf.len() {
+                    match buf[pos] {
+                        b'[' => depth += 1,
+                        b']' => depth -= 1,
+                        b' ' | b'(' | b')' if depth > 0 => (),
+                        b' ' | b'\t' | b'\r' | b'\n' | b'(' | b')' => break,
+                        _ => (),
+                    }
+                    pos += 1;
+                }
+                tokens.push(Token::Atom(buf[start..pos].to_vec()));
+            }
+        }
+    }
+
+    let mut tokens = tokens.into_iter();
+    let tag = match tokens.next() {
+        Some(Token::Atom(tag)) => String::from_utf8(tag).map_err(|_| "Invalid tag.".to_string())?,
+        _ => return Err("Missing tag.".to_string()),
+    };
+    let mut command = match tokens.next() {
+        Some(Token::Atom(command)) => String::from_utf8(command)
+            .map_err(|_| "Invalid command.".to_string())?
+            .to_ascii_uppercase(),
+        _ => return Err("Missing command.".to_string()),
+    };
+    if command == "UID" {
+        match tokens.next() {
+            Some(Token::Atom(sub_command)) => {
+                command.push(' ');
+                command.push_str(
+                    &String::from_utf8(sub_command)
+                        .map_err(|_| "Invalid command.".to_string())?
+                        .to_ascii_uppercase(),
+                );
+            }
+            _ => return Err("Missing UID command.".to_string()),
+        }
+    }
+
+    Ok(Request {
+        tag,
+        command,
+        tokens: tokens.collect(),
+    })
+}
+
+impl Token {
+    pub fn is_parenthesis_open(&self) -> bool {
+        matches!(self, Token::ParenthesisOpen)
+    }
+
+    pub fn is_parenthesis_close(&self) -> bool {
+        matches!(self, Token::ParenthesisClose)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Token::Atom(value) | Token::String(value) => value,
+            Token::ParenthesisOpen => b"(",
+            Token::ParenthesisClose => b")",
+        }
+    }
+
+    pub fn unwrap_string(self) -> Result<String, String> {
+        match self {
+            Token::Atom(value) | Token::String(value) => {
+                String::from_utf8(value).map_err(|_| "Invalid UTF-8 string.".to_string())
+            }
+            _ => Err("Expected a string.".to_string()),
+        }
+    }
+
+    pub fn unwrap_bytes(self) -> Vec<u8> {
+        match self {
+            Token::Atom(value) | Token::String(value) => value,
+            Token::ParenthesisOpen => b"(".to_vec(),
+            Token::ParenthesisClose => b")".to_vec(),
+        }
+    }
+
+    pub fn eq_ignore_ascii_case(&self, value: &str) -> bool {
+        matches!(self, Token::Atom(atom) if atom.eq_ignore_ascii_case(value.as_bytes()))
+    }
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", String::from_utf8_lossy(self.as_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Event, Receiver, Request, Token};
+
+    #[test]
+    fn receive_requests() {
+        let mut receiver = Receiver::new(1024);
+
+        for (chunks, expected) in [
+            (
+                vec!["a1 NOOP\r\n"],
+                vec![Event::Request(Request {
+                    tag: "a1".to_string(),
+                    command: "NOOP".to_string(),
+                    tokens: vec![],
+                })],
+            ),
+            (
+                vec!["a2 uid fetch 1:* (FLAGS BODY.PEEK[HEADER.FIELDS (From To)]<0.100>)\r\n"],
+                vec![Event::Request(Request {
+                    tag: "a2".to_string(),
+                    command: "UID FETCH".to_string(),
+                    tokens: vec![
+                        Token::Atom(b"1:*".to_vec()),
+                        Token::ParenthesisOpen,
+                        Token::Atom(b"FLAGS".to_vec()),
+                        Token::Atom(b"BODY.PEEK[HEADER.FIELDS (From To)]<0.100>".to_vec()),
+                        Token::ParenthesisClose,
+                    ],
+                })],
+            ),
+            (
+                vec!["a3 LOGIN {4}\r\n", "john \"pass\\\"word\"\r\n"],
+                vec![
+                    Event::NeedsLiteral { size: 4 },
+                    Event::Request(Request {
+                        tag: "a3".to_string(),
+                        command: "LOGIN".to_string(),
+                        tokens: vec![
+                            Token::String(b"john".to_vec()),
+                            Token::String(b"pass\"word".to_vec()),
+                        ],
+                    }),
+                ],
+            ),
+            (
+                vec!["a4 APPEND INBOX (\\Seen) {1", "1+}\r\nHello", " world\r\n"],
+                vec![Event::Request(Request {
+                    tag: "a4".to_string(),
+                    command: "APPEND".to_string(),
+                    tokens: vec![
+                        Token::Atom(b"INBOX".to_vec()),
+                        Token::ParenthesisOpen,
+                        Token::Atom(b"\\Seen".to_vec()),
+                        Token::ParenthesisClose,
+                        Token::String(b"Hello world".to_vec()),
+                    ],
+                })],
+            ),
+        ] {
+            let mut events = Vec::new();
+            for chunk in chunks {
+                let mut bytes = chunk.as_bytes().iter();
+                loop {
+                    match receiver.parse(&mut bytes) {
+                        Event::NeedsMoreBytes => {
+                            if bytes.len() == 0 {
+                                break;
+                            }
+                        }
+                        event => events.push(event),
+                    }
+                }
+            }
+            assert_eq!(events, expected);
+        }
+    }
+
+    #[test]
+    fn receive_oversized_request() {
+        let mut receiver = Receiver::new(32);
+        let mut bytes = b"a1 APPEND INBOX {100}\r\na2 NOOP\r\n".iter();
+        assert!(matches!(
+            receiver.parse(&mut bytes),
+            Event::Error { tag: Some(tag), .. } if tag == "a1"
+        ));
+        assert!(matches!(
+            receiver.parse(&mut bytes),
+            Event::Request(Request { tag, .. }) if tag == "a2"
+        ));
+    }
+}
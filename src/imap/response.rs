@@ -0,0 +1,221 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::borrow::Cow;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseType {
+    Ok,
+    No,
+    Bad,
+    Bye,
+    Disconnect,
+    Deferred,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusResponse {
+    pub tag: Option<String>,
+    pub rtype: ResponseType,
+    pub code: Option<Cow<'static, str>>,
+    pub message: Cow<'static, str>,
+}
+
+impl StatusResponse {
+    pub fn new(rtype: ResponseType, message: impl Into<Cow<'static, str>>) -> Self {
+        StatusResponse {
+            tag: None,
+            rtype,
+            code: None,
+            message: message.into(),
+        }
+    }
+
+    pub fn ok(message: impl Into<Cow<'static, str>>) -> Self {
+        StatusResponse::new(ResponseType::Ok, message)
+    }
+
+    pub fn no(message: impl Into<Cow<'static, str>>) -> Self {
+        StatusResponse::new(ResponseType::No, message)
+    }
+
+    pub fn bad(message: impl Into<Cow<'static, str>>) -> Self {
+        StatusResponse::new(ResponseType::Bad, message)
+    }
+
+    pub fn bye(message: impl Into<Cow<'static, str>>) -> Self {
+        StatusResponse::new(ResponseType::Bye, message)
+    }
+
+    pub fn disconnect() -> Self {
+        StatusResponse::new(ResponseType::Disconnect, "")
+    }
+
+    /// The response was already sent or will be sent once a continuation completes.
+    pub fn deferred() -> Self {
+        StatusResponse::new(ResponseType::Deferred, "")
+    }
+
+    pub fn unavailable() -> Self {
+        StatusResponse::no("Temporary server failure, please try again later.")
+            .with_code("UNAVAILABLE")
+    }
+
+    pub fn with_code(mut self, code: impl Into<Cow<'static, str>>) -> Self {
+        self.code = Some(code.into());
+        self
+    }
+
+    pub fn with_tag(mut self, tag: impl Into<String>) -> Self {
+        self.tag = Some(tag.into());
+        self
+    }
+
+    pub fn is_disconnect(&self) -> bool {
+        self.rtype == ResponseType::Disconnect
+    }
+
+    pub fn is_deferred(&self) -> bool {
+        self.rtype == ResponseType::Deferred
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        if self.is_deferred() {
+            return Vec::new();
+        }
+        let mut buf = Vec::with_capacity(self.message.len() + 32);
+        buf.extend_from_slice(self.tag.as_deref().unwrap_or("*").as_bytes());
+        buf.extend_from_slice(match self.rtype {
+            ResponseType::Ok => b" OK ",
+            ResponseType::No => b" NO ",
+            ResponseType::Bad => b" BAD ",
+            ResponseType::Bye | ResponseType::Disconnect | ResponseType::Deferred => b" BYE ",
+        });
+        if let Some(code) = &self.code {
+            buf.push(b'[');
+            buf.extend_from_slice(code.as_bytes());
+            buf.extend_from_slice(b"] ");
+        }
+        buf.extend_from_slice(self.message.as_bytes());
+        buf.extend_from_slice(b"\r\n");
+        buf
+    }
+}
+
+impl From<()> for StatusResponse {
+    fn from(_: ()) -> Self {
+        StatusResponse::disconnect()
+    }
+}
+
+/// Writes an IMAP string, using a quoted string when possible and a literal otherwise.
+pub fn write_string(buf: &mut Vec<u8>, value: &[u8]) {
+    if value.len() < 1024
+        && value
+            .iter()
+            .all(|&ch| ch.is_ascii() && ch != b'\r' && ch != b'\n' && ch != 0)
+    {
+        buf.push(b'"');
+        for &ch in value {
+            if ch == b'"' || ch == b'\\' {
+                buf.push(b'\\');
+            }
+            buf.push(ch);
+        }
+        buf.push(b'"');
+    } else {
+        write_literal(buf, value);
+    }
+}
+
+pub fn write_nstring(buf: &mut Vec<u8>, value: Option<&[u8]>) {
+    if let Some(value) = value {
+        write_string(buf, value);
+    } else {
+        buf.extend_from_slice(b"NIL");
+    }
+}
+
+pub fn write_literal(buf: &mut Vec<u8>, value: &[u8]) {
+    buf.extend_from_slice(format!("{{{}}}\r\n", value.len()).as_bytes());
+    buf.extend_from_slice(value);
+}
+
+/// Serializes a list of UIDs or sequence numbers as a compact sequence set.
+pub fn serialize_sequence(buf: &mut Vec<u8>, ids: &[u32]) {
+    let mut ids = ids.to_vec();
+    ids.sort_unstable();
+    ids.dedup();
+    serialize_ordered_sequence(buf, &ids);
+}
+
+/// Serializes a list of UIDs preserving their order, as required by COPYUID.
+pub fn serialize_ordered_sequence(buf: &mut Vec<u8>, ids: &[u32]) {
+    let mut iter = ids.iter().copied().peekable();
+    let mut is_first = true;
+    while let Some(start) = iter.next() {
+        let mut end = start;
+        while iter.peek() == Some(&(end + 1)) {
+            end = iter.next().unwrap();
+        }
+        if !is_first {
+            buf.push(b',');
+        } else {
+            is_first = false;
+        }
+        if start == end {
+            buf.extend_from_slice(start.to_string().as_bytes());
+        } else {
+            buf.extend_from_slice(format!("{}:{}", start, end).as_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{serialize_ordered_sequence, serialize_sequence, write_string, StatusResponse};
+
+    #[test]
+    fn serialize_responses() {
+        let mut buf = Vec::new();
+        serialize_sequence(&mut buf, &[7, 1, 2, 3, 5, 8, 9]);
+        assert_eq!(buf, b"1:3,5,7:9");
+
+        let mut buf = Vec::new();
+        serialize_ordered_sequence(&mut buf, &[10, 11, 12, 4, 5, 20]);
+        assert_eq!(buf, b"10:12,4:5,20");
+
+        let mut buf = Vec::new();
+        write_string(&mut buf, b"Hello \"world\"");
+        write_string(&mut buf, b"Line\r\nbreak");
+        assert_eq!(buf, b"\"Hello \\\"world\\\"\"{11}\r\nLine\r\nbreak");
+
+        assert_eq!(
+            StatusResponse::ok("SELECT completed.")
+                .with_code("READ-WRITE")
+                .with_tag("a1")
+                .into_bytes(),
+            b"a1 OK [READ-WRITE] SELECT completed.\r\n"
+        );
+    }
+}
@@ -0,0 +1,476 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::iter::Peekable;
+
+use serde_json::{json, Value};
+use store::{
+    ahash::AHashSet,
+    chrono::{Duration, NaiveDate},
+    Store,
+};
+
+use super::{
+    flag_to_keyword, parse_date,
+    receiver::{Request, Token},
+    response::{serialize_sequence, StatusResponse},
+    select::parse_number,
+    session::{SelectedMailbox, Session},
+    Sequence,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    All,
+    None,
+    Sequence { sequence: Sequence, is_uid: bool },
+    ModSeq { value: u64 },
+    Jmap { value: Value },
+    Not { filter: Box<Filter> },
+    And { filters: Vec<Filter> },
+    Or { filters: Vec<Filter> },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResultOption {
+    Min,
+    Max,
+    Count,
+    All,
+}
+
+impl Filter {
+    pub fn parse(tokens: &mut Peekable<impl Iterator<Item = Token>>) -> Result<Filter, String> {
+        let token = tokens.next().ok_or("Missing search criteria.")?;
+        if token.is_parenthesis_open() {
+            let mut filters = Vec::new();
+            while !tokens.peek().map_or(true, |t| t.is_parenthesis_close()) {
+                filters.push(Filter::parse(tokens)?);
+            }
+            tokens.next().ok_or("Missing closing parenthesis.")?;
+            return Ok(Filter::And { filters });
+        }
+
+        let key = String::from_utf8_lossy(token.as_bytes()).to_ascii_uppercase();
+        let next_string = |tokens: &mut Peekable<_>| -> Result<String, String> {
+            match Iterator::next(tokens) {
+                Some(Token::Atom(value) | Token::String(value)) => {
+                    Ok(String::from_utf8_lossy(&value).into_owned())
+                }
+                _ => Err(format!("Missing argument for {}.", key)),
+            }
+        };
+
+        Ok(match key.as_str() {
+            "ALL" | "OLD" => Filter::All,
+            "RECENT" => Filter::None,
+            "NEW" | "UNSEEN" => jmap(json!({"notKeyword": "$seen"})),
+            "SEEN" => jmap(json!({"hasKeyword": "$seen"})),
+            "ANSWERED" => jmap(json!({"hasKeyword": "$answered"})),
+            "UNANSWERED" => jmap(json!({"notKeyword": "$answered"})),
+            "DELETED" => jmap(json!({"hasKeyword": "$deleted"})),
+            "UNDELETED" => jmap(json!({"notKeyword": "$deleted"})),
+            "DRAFT" => jmap(json!({"hasKeyword": "$draft"})),
+            "UNDRAFT" => jmap(json!({"notKeyword": "$draft"})),
+            "FLAGGED" => jmap(json!({"hasKeyword": "$flagged"})),
+            "UNFLAGGED" => jmap(json!({"notKeyword": "$flagged"})),
+            "KEYWORD" | "UNKEYWORD" => {
+                let keyword = next_string(tokens)?;
+                match flag_to_keyword(&keyword) {
+                    Some(keyword) if key == "KEYWORD" => jmap(json!({ "hasKeyword": keyword })),
+                    Some(keyword) => jmap(json!({ "notKeyword": keyword })),
+                    None if key == "KEYWORD" => Filter::None,
+                    None => Filter::All,
+                }
+            }
+            "FROM" => jmap(json!({"from": next_string(tokens)?})),
+            "TO" => jmap(json!({"to": next_string(tokens)?})),
+            "CC" => jmap(json!({"cc": next_string(tokens)?})),
+            "BCC" => jmap(json!({"bcc": next_string(tokens)?})),
+            "SUBJECT" => jmap(json!({"subject": next_string(tokens)?})),
+            "BODY" => jmap(json!({"body": next_string(tokens)?})),
+            "TEXT" => jmap(json!({"text": next_string(tokens)?})),
+            "HEADER" => {
+                let name = next_string(tokens)?;
+                let value = next_string(tokens)?;
+                if !value.is_empty() {
+                    jmap(json!({ "header": [name, value] }))
+                } else {
+                    jmap(json!({ "header": [name] }))
+                }
+            }
+            "LARGER" => jmap(json!({
+                "minSize": parse_number::<u32>(&Token::Atom(next_string(tokens)?.into_bytes()))
+                    .ok_or("Invalid size.")?
+                    .saturating_add(1)
+            })),
+            "SMALLER" => jmap(json!({
+                "maxSize": parse_number::<u32>(&Token::Atom(next_string(tokens)?.into_bytes()))
+                    .ok_or("Invalid size.")?
+            })),
+            "BEFORE" | "SINCE" | "ON" | "SENTBEFORE" | "SENTSINCE" | "SENTON" => {
+                let date = parse_date(&next_string(tokens)?).ok_or("Invalid date.")?;
+                let (before, after) = if key.starts_with("SENT") {
+                    ("sentBefore", "sentAfter")
+                } else {
+                    ("before", "after")
+                };
+                match key.trim_start_matches("SENT") {
+                    "BEFORE" => jmap(json!({ before: utc_date(date) })),
+                    "SINCE" => jmap(json!({ after: utc_date(date) })),
+                    _ => jmap(json!({
+                        "operator": "AND",
+                        "conditions": [
+                            { after: utc_date(date) },
+                            { before: utc_date(date + Duration::days(1)) },
+                        ]
+                    })),
+                }
+            }
+            "UID" => Filter::Sequence {
+                sequence: Sequence::parse(next_string(tokens)?.as_bytes())
+                    .ok_or("Invalid UID sequence.")?,
+                is_uid: true,
+            },
+            "MODSEQ" => {
+                // Skip the optional entry name and type
+                let mut value = next_string(tokens)?;
+                if value.starts_with('/') || value.starts_with('"') {
+                    next_string(tokens)?;
+                    value = next_string(tokens)?;
+                }
+                Filter::ModSeq {
+                    value: value.parse().map_err(|_| "Invalid MODSEQ value.")?,
+                }
+            }
+            "NOT" => Filter::Not {
+                filter: Box::new(Filter::parse(tokens)?),
+            },
+            "OR" => Filter::Or {
+                filters: vec![Filter::parse(tokens)?, Filter::parse(tokens)?],
+            },
+            _ => Filter::Sequence {
+                sequence: Sequence::parse(token.as_bytes())
+                    .ok_or_else(|| format!("Invalid search criteria '{}'.", token))?,
+                is_uid: false,
+            },
+        })
+    }
+
+    fn collect_queries(&self, queries: &mut Vec<Value>) {
+        match self {
+            Filter::Jmap { value } => queries.push(value.clone()),
+            Filter::Not { filter } => filter.collect_queries(queries),
+            Filter::And { filters } | Filter::Or { filters } => {
+                for filter in filters {
+                    filter.collect_queries(queries);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn has_modseq(&self) -> bool {
+        match self {
+            Filter::ModSeq { .. } => true,
+            Filter::Not { filter } => filter.has_modseq(),
+            Filter::And { filters } | Filter::Or { filters } => {
+                filters.iter().any(|f| f.has_modseq())
+            }
+            _ => false,
+        }
+    }
+
+    /// Evaluates the filter, returning for each message in the selected
+    /// mailbox whether it matches.
+    fn eval(
+        &self,
+        selected: &SelectedMailbox,
+        results: &mut impl Iterator<Item = AHashSet<store::JMAPId>>,
+    ) -> Vec<bool> {
+        let items = &selected.uids.items;
+        match self {
+            Filter::All => vec![true; items.len()],
+            Filter::None => vec![false; items.len()],
+            Filter::Sequence { sequence, is_uid } => {
+                let mut matches = vec![false; items.len()];
+                for (seqnum, _) in selected.resolve(sequence, *is_uid) {
+                    matches[seqnum as usize - 1] = true;
+                }
+                matches
+            }
+            Filter::ModSeq { value } => items.iter().map(|item| item.modseq >= *value).collect(),
+            Filter::Jmap { .. } => {
+                let ids = results.next().unwrap_or_default();
+                items.iter().map(|item| ids.contains(&item.id)).collect()
+            }
+            Filter::Not { filter } => filter
+                .eval(selected, results)
+                .into_iter()
+                .map(|m| !m)
+                .collect(),
+            Filter::And { filters } => {
+                let mut matches = vec![true; items.len()];
+                for filter in filters {
+                    for (m, r) in matches.iter_mut().zip(filter.eval(selected, results)) {
+                        *m = *m && r;
+                    }
+                }
+                matches
+            }
+            Filter::Or { filters } => {
+                let mut matches = vec![false; items.len()];
+                for filter in filters {
+                    for (m, r) in matches.iter_mut().zip(filter.eval(selected, results)) {
+                        *m = *m || r;
+                    }
+                }
+                matches
+            }
+        }
+    }
+}
+
+impl<T> Session<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    pub async fn handle_search(&mut self, request: Request) -> super::Result<StatusResponse> {
+        let is_uid = request.command == "UID SEARCH";
+        let mut tokens = request.tokens.into_iter().peekable();
+
+        // Parse result options
+        let mut result_options = None;
+        if tokens
+            .peek()
+            .map_or(false, |t| t.eq_ignore_ascii_case("RETURN"))
+        {
+            tokens.next();
+            let mut options = Vec::new();
+            if tokens.next().map_or(false, |t| t.is_parenthesis_open()) {
+                for token in tokens.by_ref() {
+                    if token.is_parenthesis_close() {
+                        break;
+                    }
+                    options.push(
+                        match String::from_utf8_lossy(token.as_bytes())
+                            .to_ascii_uppercase()
+                            .as_str()
+                        {
+                            "MIN" => ResultOption::Min,
+                            "MAX" => ResultOption::Max,
+                            "COUNT" => ResultOption::Count,
+                            "ALL" => ResultOption::All,
+                            _ => {
+                                return Ok(StatusResponse::bad(format!(
+                                    "Unsupported result option '{}'.",
+                                    token
+                                )))
+                            }
+                        },
+                    );
+                }
+            }
+            if options.is_empty() {
+                options.push(ResultOption::All);
+            }
+            result_options = options.into();
+        }
+
+        // Only UTF-8 and US-ASCII are supported
+        if tokens
+            .peek()
+            .map_or(false, |t| t.eq_ignore_ascii_case("CHARSET"))
+        {
+            tokens.next();
+            let charset = tokens
+                .next()
+                .map(|t| String::from_utf8_lossy(t.as_bytes()).to_ascii_uppercase())
+                .unwrap_or_default();
+            if !["UTF-8", "US-ASCII"].contains(&charset.as_str()) {
+                return Ok(StatusResponse::no("Unsupported charset.")
+                    .with_code("BADCHARSET (UTF-8 US-ASCII)"));
+            }
+        }
+
+        let mut filters = Vec::new();
+        while tokens.peek().is_some() {
+            match Filter::parse(&mut tokens) {
+                Ok(filter) => filters.push(filter),
+                Err(message) => return Ok(StatusResponse::bad(message)),
+            }
+        }
+        if filters.is_empty() {
+            return Ok(StatusResponse::bad("Missing search criteria."));
+        }
+        let filter = Filter::And { filters };
+
+        // Run the JMAP queries
+        let selected = self.selected.as_ref().unwrap();
+        let mailbox_id = selected.jmap_id();
+        let mut queries = Vec::new();
+        filter.collect_queries(&mut queries);
+        let mut results = Vec::with_capacity(queries.len());
+        for query in queries {
+            results.push(
+                self.query_emails(json!({
+                    "operator": "AND",
+                    "conditions": [{ "inMailbox": mailbox_id }, query],
+                }))
+                .await?
+                .into_iter()
+                .collect::<AHashSet<_>>(),
+            );
+        }
+
+        let selected = self.selected.as_ref().unwrap();
+        let matches = filter.eval(selected, &mut results.into_iter());
+        let mut ids = Vec::new();
+        let mut highest_modseq = 0;
+        for ((pos, item), is_match) in selected.uids.items.iter().enumerate().zip(matches) {
+            if is_match {
+                ids.push(if is_uid { item.uid } else { pos as u32 + 1 });
+                highest_modseq = highest_modseq.max(item.modseq);
+            }
+        }
+        let with_modseq = filter.has_modseq() && !ids.is_empty();
+
+        // Build response
+        let mut buf = Vec::with_capacity(ids.len() * 4 + 32);
+        if let Some(result_options) = result_options.or_else(|| {
+            if self.is_rev2 {
+                vec![ResultOption::All].into()
+            } else {
+                None
+            }
+        }) {
+            buf.extend_from_slice(format!("* ESEARCH (TAG \"{}\")", request.tag).as_bytes());
+            if is_uid {
+                buf.extend_from_slice(b" UID");
+            }
+            for option in result_options {
+                match option {
+                    ResultOption::Min if !ids.is_empty() => {
+                        buf.extend_from_slice(format!(" MIN {}", ids[0]).as_bytes());
+                    }
+                    ResultOption::Max if !ids.is_empty() => {
+                        buf.extend_from_slice(format!(" MAX {}", ids[ids.len() - 1]).as_bytes());
+                    }
+                    ResultOption::Count => {
+                        buf.extend_from_slice(format!(" COUNT {}", ids.len()).as_bytes());
+                    }
+                    ResultOption::All if !ids.is_empty() => {
+                        buf.extend_from_slice(b" ALL ");
+                        serialize_sequence(&mut buf, &ids);
+                    }
+                    _ => (),
+                }
+            }
+            if with_modseq {
+                buf.extend_from_slice(format!(" MODSEQ {}", highest_modseq).as_bytes());
+            }
+        } else {
+            buf.extend_from_slice(b"* SEARCH");
+            for id in &ids {
+                buf.extend_from_slice(format!(" {}", id).as_bytes());
+            }
+            if with_modseq {
+                buf.extend_from_slice(format!(" (MODSEQ {})", highest_modseq).as_bytes());
+            }
+        }
+        buf.extend_from_slice(b"\r\n");
+        self.write_bytes(&buf).await?;
+
+        Ok(StatusResponse::ok(format!(
+            "{} completed.",
+            request.command
+        )))
+    }
+}
+
+fn jmap(value: Value) -> Filter {
+    Filter::Jmap { value }
+}
+
+fn utc_date(date: NaiveDate) -> String {
+    format!("{}T00:00:00Z", date.format("%Y-%m-%d"))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{Filter, Sequence};
+    use crate::imap::receiver::tokenize;
+
+    #[test]
+    fn parse_search_criteria() {
+        let request =
+            tokenize(b"a1 SEARCH OR FROM \"john\" (UNSEEN 1:3) NOT UID 5 ON 1-Feb-2022\r\n")
+                .unwrap();
+        let mut tokens = request.tokens.into_iter().peekable();
+        let mut filters = Vec::new();
+        while tokens.peek().is_some() {
+            filters.push(Filter::parse(&mut tokens).unwrap());
+        }
+
+        assert_eq!(
+            filters,
+            vec![
+                Filter::Or {
+                    filters: vec![
+                        Filter::Jmap {
+                            value: json!({"from": "john"})
+                        },
+                        Filter::And {
+                            filters: vec![
+                                Filter::Jmap {
+                                    value: json!({"notKeyword": "$seen"})
+                                },
+                                Filter::Sequence {
+                                    sequence: Sequence::parse(b"1:3").unwrap(),
+                                    is_uid: false
+                                }
+                            ]
+                        }
+                    ]
+                },
+                Filter::Not {
+                    filter: Box::new(Filter::Sequence {
+                        sequence: Sequence::parse(b"5").unwrap(),
+                        is_uid: true
+                    })
+                },
+                Filter::Jmap {
+                    value: json!({
+                        "operator": "AND",
+                        "conditions": [
+                            { "after": "2022-02-01T00:00:00Z" },
+                            { "before": "2022-02-02T00:00:00Z" },
+                        ]
+                    })
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,327 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap::types::jmap::JMAPId;
+use jmap_mail::mailbox::uids::UidItem;
+use serde_json::{json, Value};
+use store::Store;
+
+use super::{
+    receiver::{Request, Token},
+    response::{serialize_sequence, StatusResponse},
+    session::{SelectedMailbox, Session},
+    Sequence,
+};
+
+pub const FLAGS: &str =
+    "\\Answered \\Flagged \\Deleted \\Seen \\Draft $Forwarded $MDNSent $Junk $NotJunk $Phishing";
+
+impl SelectedMailbox {
+    /// Resolves a sequence set of message numbers or UIDs, returning the sequence
+    /// number and UID item of each matching message.
+    pub fn resolve(&self, sequence: &Sequence, is_uid: bool) -> Vec<(u32, UidItem)> {
+        let items = &self.uids.items;
+        if is_uid {
+            let largest = items.last().map(|item| item.uid).unwrap_or(0);
+            items
+                .iter()
+                .enumerate()
+                .filter(|(_, item)| sequence.contains(item.uid, largest))
+                .map(|(pos, item)| (pos as u32 + 1, *item))
+                .collect()
+        } else {
+            let largest = items.len() as u32;
+            items
+                .iter()
+                .enumerate()
+                .filter(|(pos, _)| sequence.contains(*pos as u32 + 1, largest))
+                .map(|(pos, item)| (pos as u32 + 1, *item))
+                .collect()
+        }
+    }
+
+    pub fn jmap_id(&self) -> String {
+        JMAPId::from(self.id).to_string()
+    }
+}
+
+impl<T> Session<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    pub async fn handle_select(&mut self, request: Request) -> super::Result<StatusResponse> {
+        let is_examine = request.command == "EXAMINE";
+        let mut tokens = request.tokens.into_iter();
+        let name = match tokens.next().map(|t| t.unwrap_string()) {
+            Some(Ok(name)) => name,
+            _ => return Ok(StatusResponse::bad("Missing mailbox name.")),
+        };
+
+        // Parse CONDSTORE and QRESYNC parameters
+        let mut qresync = None;
+        let mut tokens = tokens.skip_while(|t| t.is_parenthesis_open()).peekable();
+        while let Some(token) = tokens.next() {
+            if token.eq_ignore_ascii_case("CONDSTORE") {
+                self.is_condstore = true;
+            } else if token.eq_ignore_ascii_case("QRESYNC") {
+                if !self.is_qresync {
+                    return Ok(StatusResponse::bad("QRESYNC is not enabled."));
+                }
+                let mut params = Vec::new();
+                let mut depth = 0;
+                for token in tokens.by_ref() {
+                    match token {
+                        Token::ParenthesisOpen => depth += 1,
+                        Token::ParenthesisClose if depth <= 1 => break,
+                        Token::ParenthesisClose => depth -= 1,
+                        token => params.push(token),
+                    }
+                }
+                let mut params = params.into_iter();
+                match (
+                    params.next().and_then(|t| parse_number::<u32>(&t)),
+                    params.next().and_then(|t| parse_number::<u64>(&t)),
+                ) {
+                    (Some(uid_validity), Some(modseq)) => {
+                        qresync = Some((
+                            uid_validity,
+                            modseq,
+                            params.next().and_then(|t| Sequence::parse(t.as_bytes())),
+                        ));
+                    }
+                    _ => return Ok(StatusResponse::bad("Invalid QRESYNC parameters.")),
+                }
+            }
+        }
+
+        self.selected = None;
+        let mailbox = match self.get_mailbox(&name).await? {
+            Some(mailbox) => mailbox,
+            None => {
+                return Ok(StatusResponse::no("Mailbox does not exist.").with_code("NONEXISTENT"))
+            }
+        };
+        let uids = self.sync_uids(mailbox.id).await?;
+
+        // Build response
+        let mut buf = Vec::with_capacity(256);
+        buf.extend_from_slice(
+            format!("* FLAGS ({})\r\n* {} EXISTS\r\n", FLAGS, uids.items.len()).as_bytes(),
+        );
+        if !self.is_rev2 {
+            buf.extend_from_slice(b"* 0 RECENT\r\n");
+        }
+        let is_read_only = is_examine || !mailbox.may_write;
+        buf.extend_from_slice(
+            format!(
+                concat!(
+                    "* OK [PERMANENTFLAGS ({})] Flags permitted.\r\n",
+                    "* OK [UIDVALIDITY {}] UIDs valid.\r\n",
+                    "* OK [UIDNEXT {}] Next predicted UID.\r\n",
+                ),
+                if !is_read_only {
+                    concat!(
+                        "\\Answered \\Flagged \\Deleted \\Seen \\Draft $Forwarded ",
+                        "$MDNSent $Junk $NotJunk $Phishing \\*"
+                    )
+                } else {
+                    ""
+                },
+                uids.uid_validity,
+                uids.uid_next
+            )
+            .as_bytes(),
+        );
+        if self.is_condstore {
+            buf.extend_from_slice(
+                format!(
+                    "* OK [HIGHESTMODSEQ {}] Highest mod-sequence.\r\n",
+                    uids.highest_modseq
+                )
+                .as_bytes(),
+            );
+        }
+
+        self.selected = SelectedMailbox {
+            id: mailbox.id,
+            name: mailbox.name,
+            uids,
+            is_read_only,
+        }
+        .into();
+
+        // Quick resynchronization
+        if let Some((uid_validity, modseq, known_uids)) = qresync {
+            let selected = self.selected.as_ref().unwrap();
+            if uid_validity == selected.uids.uid_validity {
+                let largest = selected.uids.uid_next.saturating_sub(1);
+                let mut vanished = match selected.uids.expunged_since(modseq) {
+                    Some(vanished) => vanished,
+                    None => {
+                        // Tombstones were discarded, report every UID not currently in use
+                        (1..selected.uids.uid_next)
+                            .filter(|uid| selected.uids.get_by_uid(*uid).is_none())
+                            .collect()
+                    }
+                };
+                if let Some(known_uids) = &known_uids {
+                    vanished.retain(|uid| known_uids.contains(*uid, largest));
+                }
+                if !vanished.is_empty() {
+                    buf.extend_from_slice(b"* VANISHED (EARLIER) ");
+                    serialize_sequence(&mut buf, &vanished);
+                    buf.extend_from_slice(b"\r\n");
+                }
+
+                let changed = selected
+                    .uids
+                    .items
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, item)| {
+                        item.modseq > modseq
+                            && known_uids
+                                .as_ref()
+                                .map_or(true, |known_uids| known_uids.contains(item.uid, largest))
+                    })
+                    .map(|(pos, item)| (pos as u32 + 1, *item))
+                    .collect::<Vec<_>>();
+                if !changed.is_empty() {
+                    let flags = self.fetch_flags(&changed, true).await?;
+                    buf.extend_from_slice(&flags);
+                }
+            }
+        }
+
+        self.write_bytes(&buf).await?;
+
+        Ok(
+            StatusResponse::ok(format!("{} completed.", request.command)).with_code(
+                if is_read_only {
+                    "READ-ONLY"
+                } else {
+                    "READ-WRITE"
+                },
+            ),
+        )
+    }
+
+    pub async fn handle_close(&mut self, request: Request) -> super::Result<StatusResponse> {
+        if request.command == "CLOSE" {
+            let selected = self.selected.as_ref().unwrap();
+            if !selected.is_read_only {
+                let items = selected.uids.items.clone();
+                self.expunge(&items).await?;
+            }
+        }
+        self.selected = None;
+        Ok(StatusResponse::ok(format!(
+            "{} completed.",
+            request.command
+        )))
+    }
+
+    pub async fn handle_expunge(&mut self, request: Request) -> super::Result<StatusResponse> {
+        let selected = self.selected.as_ref().unwrap();
+        if selected.is_read_only {
+            return Ok(StatusResponse::no("Mailbox is read-only.").with_code("READ-ONLY"));
+        }
+        let items = if request.command == "UID EXPUNGE" {
+            match request
+                .tokens
+                .first()
+                .and_then(|t| Sequence::parse(t.as_bytes()))
+            {
+                Some(sequence) => selected
+                    .resolve(&sequence, true)
+                    .into_iter()
+                    .map(|(_, item)| item)
+                    .collect(),
+                None => return Ok(StatusResponse::bad("Invalid UID sequence.")),
+            }
+        } else {
+            selected.uids.items.clone()
+        };
+
+        self.expunge(&items).await?;
+        self.synchronize().await?;
+
+        Ok(StatusResponse::ok(format!(
+            "{} completed.",
+            request.command
+        )))
+    }
+
+    /// Removes the messages flagged as \Deleted from the selected mailbox, messages
+    /// that belong to other mailboxes are only unlinked from this one.
+    async fn expunge(&self, items: &[UidItem]) -> super::Result<()> {
+        let mailbox_id = self.selected.as_ref().unwrap().jmap_id();
+        let emails = self
+            .get_emails(
+                &items.iter().map(|item| item.id).collect::<Vec<_>>(),
+                &["keywords", "mailboxIds"],
+            )
+            .await?;
+
+        let mut destroy = Vec::new();
+        let mut update = Vec::new();
+        for (id, email) in emails {
+            if email
+                .get("keywords")
+                .and_then(|k| k.get("$deleted"))
+                .is_none()
+            {
+                continue;
+            }
+            if email
+                .get("mailboxIds")
+                .and_then(|m| m.as_object())
+                .map_or(0, |m| m.len())
+                > 1
+            {
+                update.push((
+                    id,
+                    json!({ format!("mailboxIds/{}", mailbox_id): Value::Null }),
+                ));
+            } else {
+                destroy.push(id);
+            }
+        }
+
+        if !update.is_empty() {
+            self.update_emails(update).await?;
+        }
+        if !destroy.is_empty() {
+            self.destroy_emails(&destroy).await?;
+        }
+
+        Ok(())
+    }
+}
+
+pub fn parse_number<N: std::str::FromStr>(token: &Token) -> Option<N> {
+    std::str::from_utf8(token.as_bytes())
+        .ok()?
+        .parse::<N>()
+        .ok()
+}
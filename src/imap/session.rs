@@ -0,0 +1,273 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{net::SocketAddr, sync::Arc};
+
+use actix_web::web;
+use jmap_mail::mailbox::uids::MailboxUids;
+use store::{core::acl::ACLToken, tracing::debug, AccountId, DocumentId, Store};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    sync::mpsc,
+};
+use tokio_rustls::TlsAcceptor;
+
+use crate::{lmtp::session::Stream, services::state_change::StateChange, JMAPServer};
+
+use super::{
+    receiver::{Event, Receiver, Request},
+    response::{ResponseType, StatusResponse},
+};
+
+pub struct Session<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    pub core: web::Data<JMAPServer<T>>,
+    pub tls_acceptor: Option<Arc<TlsAcceptor>>,
+    pub hostname: Arc<String>,
+    pub receiver: Receiver,
+    pub peer_addr: SocketAddr,
+    pub stream: Stream,
+
+    // State
+    pub account: Option<Account>,
+    pub selected: Option<SelectedMailbox>,
+    pub is_rev2: bool,
+    pub is_condstore: bool,
+    pub is_qresync: bool,
+    pub idle: Option<Idle>,
+    pub pending_auth: Option<PendingAuth>,
+}
+
+pub struct Account {
+    pub account_id: AccountId,
+    pub acl: Arc<ACLToken>,
+}
+
+pub struct SelectedMailbox {
+    pub id: DocumentId,
+    pub name: String,
+    pub uids: MailboxUids,
+    pub is_read_only: bool,
+}
+
+pub struct PendingAuth {
+    pub tag: String,
+    pub mechanism: String,
+}
+
+pub struct Idle {
+    pub tag: String,
+    pub rx: Option<mpsc::Receiver<StateChange>>,
+}
+
+impl<T> Session<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    pub fn new(
+        core: web::Data<JMAPServer<T>>,
+        peer_addr: SocketAddr,
+        stream: Stream,
+        tls_acceptor: Option<Arc<TlsAcceptor>>,
+        hostname: Arc<String>,
+    ) -> Self {
+        Self {
+            receiver: Receiver::new(core.store.config.mail_max_size + 1024),
+            tls_acceptor,
+            peer_addr,
+            stream,
+            core,
+            hostname,
+            account: None,
+            selected: None,
+            is_rev2: false,
+            is_condstore: false,
+            is_qresync: false,
+            idle: None,
+            pending_auth: None,
+        }
+    }
+
+    pub async fn ingest(&mut self, bytes: &[u8]) -> Result<(), ()> {
+        let mut bytes = bytes.iter();
+
+        loop {
+            match self.receiver.parse(&mut bytes) {
+                Event::Request(request) => {
+                    let tag = request.tag.clone();
+                    let is_starttls = request.command == "STARTTLS";
+                    let response = match self.handle_request(request).await {
+                        Ok(response) | Err(response) => response,
+                    };
+                    match response.rtype {
+                        ResponseType::Disconnect => return Err(()),
+                        ResponseType::Deferred if is_starttls => {
+                            // Discard any plaintext commands pipelined after STARTTLS
+                            return Ok(());
+                        }
+                        ResponseType::Deferred => (),
+                        ResponseType::Bye => {
+                            self.write_bytes(&response.with_tag(tag).into_bytes())
+                                .await?;
+                            return Err(());
+                        }
+                        _ => {
+                            self.write_bytes(&response.with_tag(tag).into_bytes())
+                                .await?;
+                        }
+                    }
+                }
+                Event::Line(line) => {
+                    if let Some(response) = self.handle_line(line).await? {
+                        self.write_bytes(&response.into_bytes()).await?;
+                    }
+                }
+                Event::NeedsLiteral { .. } => {
+                    self.write_bytes(b"+ Ready for literal data\r\n").await?;
+                }
+                Event::NeedsMoreBytes => {
+                    break;
+                }
+                Event::Error { tag, message } => {
+                    let mut response = StatusResponse::bad(message);
+                    response.tag = tag;
+                    self.write_bytes(&response.into_bytes()).await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn handle_request(&mut self, request: Request) -> super::Result<StatusResponse> {
+        let is_authenticated = self.account.is_some();
+        let is_selected = self.selected.is_some();
+
+        match request.command.as_str() {
+            "CAPABILITY" => self.handle_capability().await,
+            "NOOP" | "CHECK" if !is_selected => Ok(StatusResponse::ok("NOOP completed.")),
+            "NOOP" | "CHECK" => {
+                self.synchronize().await?;
+                Ok(StatusResponse::ok(format!(
+                    "{} completed.",
+                    request.command
+                )))
+            }
+            "LOGOUT" => {
+                self.write_bytes(b"* BYE Stalwart IMAP bids you farewell.\r\n")
+                    .await?;
+                self.write_bytes(
+                    &StatusResponse::ok("LOGOUT completed.")
+                        .with_tag(request.tag)
+                        .into_bytes(),
+                )
+                .await?;
+                Err(StatusResponse::disconnect())
+            }
+            "ID" => self.handle_id().await,
+            "STARTTLS" if !is_authenticated => self.handle_starttls(request.tag).await,
+            "LOGIN" if !is_authenticated => self.handle_login(request).await,
+            "AUTHENTICATE" if !is_authenticated => self.handle_authenticate(request).await,
+            "LOGIN" | "AUTHENTICATE" | "STARTTLS" => {
+                Ok(StatusResponse::bad("Already authenticated."))
+            }
+            _ if !is_authenticated => Ok(StatusResponse::no("Not authenticated.")),
+            "ENABLE" => self.handle_enable(request).await,
+            "NAMESPACE" => self.handle_namespace().await,
+            "LIST" | "LSUB" => self.handle_list(request).await,
+            "CREATE" => self.handle_create(request).await,
+            "DELETE" => self.handle_delete(request).await,
+            "RENAME" => self.handle_rename(request).await,
+            "SUBSCRIBE" | "UNSUBSCRIBE" => self.handle_subscribe(request).await,
+            "STATUS" => self.handle_status(request).await,
+            "SELECT" | "EXAMINE" => self.handle_select(request).await,
+            "APPEND" => self.handle_append(request).await,
+            "IDLE" => self.handle_idle(request.tag).await,
+            _ if !is_selected => Ok(StatusResponse::bad(format!(
+                "Command '{}' is not valid in this state.",
+                request.command
+            ))),
+            "CLOSE" | "UNSELECT" => self.handle_close(request).await,
+            "EXPUNGE" | "UID EXPUNGE" => self.handle_expunge(request).await,
+            "FETCH" | "UID FETCH" => self.handle_fetch(request).await,
+            "STORE" | "UID STORE" => self.handle_store(request).await,
+            "SEARCH" | "UID SEARCH" => self.handle_search(request).await,
+            "COPY" | "UID COPY" | "MOVE" | "UID MOVE" => self.handle_copy_move(request).await,
+            _ => Ok(StatusResponse::bad(format!(
+                "Unknown command '{}'.",
+                request.command
+            ))),
+        }
+    }
+
+    async fn handle_line(&mut self, line: Vec<u8>) -> Result<Option<StatusResponse>, ()> {
+        self.receiver.is_line_mode = false;
+        if let Some(pending_auth) = self.pending_auth.take() {
+            Ok(Some(
+                self.handle_authenticate_response(pending_auth, line)
+                    .await?,
+            ))
+        } else if let Some(idle) = self.idle.take() {
+            Ok(Some(if line.eq_ignore_ascii_case(b"DONE") {
+                StatusResponse::ok("IDLE terminated.").with_tag(idle.tag)
+            } else {
+                StatusResponse::bad("Expected DONE.").with_tag(idle.tag)
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn account_id(&self) -> AccountId {
+        self.account
+            .as_ref()
+            .map(|a| a.account_id)
+            .unwrap_or_default()
+    }
+
+    pub async fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), ()> {
+        match &mut self.stream {
+            Stream::Clear(stream) => stream.write_all(bytes).await.map_err(|err| {
+                debug!("Failed to write to stream: {}", err);
+            }),
+            Stream::Tls(stream) => stream.write_all(bytes).await.map_err(|err| {
+                debug!("Failed to write to TLS stream: {}", err);
+            }),
+            _ => unreachable!(),
+        }
+    }
+
+    pub async fn read_bytes(&mut self, bytes: &mut [u8]) -> Result<usize, ()> {
+        match &mut self.stream {
+            Stream::Clear(stream) => stream.read(bytes).await.map_err(|err| {
+                debug!("Failed to read from stream: {}", err);
+            }),
+            Stream::Tls(stream) => stream.read(bytes).await.map_err(|err| {
+                debug!("Failed to read from TLS stream: {}", err);
+            }),
+            _ => unreachable!(),
+        }
+    }
+}
@@ -31,9 +31,12 @@ use tokio::sync::{mpsc, watch};
 pub mod api;
 pub mod authorization;
 pub mod cluster;
+pub mod imap;
 pub mod lmtp;
+pub mod pop3;
 pub mod server;
 pub mod services;
+pub mod smtp;
 
 #[cfg(test)]
 pub mod tests;
@@ -51,6 +54,9 @@ pub struct JMAPServer<T> {
     pub email_delivery: mpsc::Sender<services::email_delivery::Event>,
     pub housekeeper: mpsc::Sender<services::housekeeper::Event>,
     pub lmtp: watch::Sender<bool>,
+    pub imap: watch::Sender<bool>,
+    pub pop3: watch::Sender<bool>,
+    pub smtp: watch::Sender<bool>,
 
     pub oauth: Box<authorization::oauth::OAuth>,
     pub oauth_codes: Cache<String, Arc<authorization::oauth::OAuthCode>>,
@@ -24,16 +24,24 @@
 use std::{borrow::Cow, sync::Arc};
 
 use jmap::{
-    orm::TinyORM,
+    orm::{serialize::JMAPOrm, TinyORM},
+    principal::store::JMAPPrincipals,
     sanitize_email,
     types::{jmap::JMAPId, type_state::TypeState},
 };
 use jmap_mail::{
     mail::{
         import::JMAPMailImport,
-        schema::{Email, Property},
+        itip::{Invitation, JMAPMailInvitation},
+        schema::{Email, Keyword, Property},
+        spam::JMAPMailSpam,
     },
     mail_parser::Message,
+    mailbox::schema::{self as mailbox_schema, Mailbox},
+    sieve_script::{
+        get::JMAPGetSieveScript,
+        runtime::{Action, Envelope, MessageHeaders},
+    },
     vacation_response::get::{JMAPGetVacationResponse, VacationMessage},
     INBOX_ID,
 };
@@ -44,6 +52,11 @@ use store::{
     blob::BlobId,
     core::{collection::Collection, document::Document, tag::Tag},
     log::changes::ChangeId,
+    read::{
+        comparator::Comparator,
+        filter::{ComparisonOperator, Filter, Query},
+        FilterMapper,
+    },
     tracing::{debug, error},
     write::{batch::WriteBatch, update::Changes},
     AccountId, DocumentId, JMAPStore, RecipientType, Store,
@@ -55,7 +68,12 @@ use crate::{
     JMAPServer,
 };
 
-use super::session::{RcptType, Session};
+use super::{
+    session::{RcptType, Session},
+    verify::{remove_auth_results, Verdict},
+};
+
+const MAX_REJECT_LEN: usize = 256;
 
 impl<T> Session<T>
 where
@@ -78,11 +96,68 @@ where
         };
         let rcpt_to = std::mem::take(&mut self.rcpt_to);
         let rcpt_to_ids = std::mem::take(&mut self.rcpt_to_ids);
-        let message = std::mem::take(&mut self.message);
+        let mut message = std::mem::take(&mut self.message);
+        let client_addr = self.client_addr.take();
+        let client_helo = self.client_helo.take();
+
+        // Verify SPF, DKIM, ARC and DMARC
+        let mut is_phishing = false;
+        if let Some(mail_auth) = &self.mail_auth {
+            if let Some(verification) = mail_auth
+                .verify(
+                    &self.hostname,
+                    client_addr.unwrap_or_else(|| self.peer_addr.ip()),
+                    client_helo
+                        .as_deref()
+                        .or(self.remote_hostname.as_deref())
+                        .unwrap_or("unknown"),
+                    &mail_from,
+                    &message,
+                )
+                .await
+            {
+                match verification.verdict {
+                    Verdict::Accept {
+                        is_phishing: is_phishing_,
+                    } => {
+                        is_phishing = is_phishing_;
+                    }
+                    Verdict::Reject { domain } => {
+                        debug!(
+                            "Rejecting message from <{}>: DMARC policy of {} failed.",
+                            mail_from, domain
+                        );
+                        let mut buf = Vec::with_capacity(64 * rcpt_to.len());
+                        for rcpt in &rcpt_to {
+                            let name = match rcpt {
+                                RcptType::Mailbox { name, .. } | RcptType::List { name, .. } => {
+                                    name
+                                }
+                            };
+                            buf.extend_from_slice(
+                                format!(
+                                    "550 5.7.1 <{}> rejected by the DMARC policy of {}.\r\n",
+                                    name, domain
+                                )
+                                .as_bytes(),
+                            );
+                        }
+                        return self.write_bytes(&buf).await;
+                    }
+                }
+
+                remove_auth_results(&self.hostname, &mut message);
+                let mut header = verification.header.into_bytes();
+                header.extend_from_slice(&message);
+                message = header;
+            }
+        }
 
         // Ingest
         let result = if self.core.is_leader() {
-            self.core.mail_ingest(mail_from, rcpt_to_ids, message).await
+            self.core
+                .mail_ingest(mail_from, rcpt_to_ids, message, is_phishing)
+                .await
         } else {
             // Send request to leader
             match self
@@ -91,6 +166,7 @@ where
                     mail_from,
                     rcpt_to: rcpt_to_ids,
                     raw_message: message,
+                    is_phishing,
                 })
                 .await
             {
@@ -124,6 +200,12 @@ where
                     DeliveryStatus::PermanentFailure { reason } => {
                         (b"550 5.5.0", name.as_bytes(), reason.as_bytes())
                     }
+                    DeliveryStatus::Rejected { reason } => {
+                        (b"550 5.7.1", name.as_bytes(), reason.as_bytes())
+                    }
+                    DeliveryStatus::OverQuota => {
+                        (b"552 5.2.2", name.as_bytes(), &b"mailbox full."[..])
+                    }
                 },
                 RcptType::List { ids, name } => {
                     // Count number of successes and failures
@@ -211,13 +293,16 @@ where
     pub async fn mail_ingest(
         &self,
         mail_from: String,
-        rcpt_to: AHashSet<AccountId>,
+        rcpt_to: AHashMap<AccountId, String>,
         raw_message: Vec<u8>,
+        is_phishing: bool,
     ) -> Result<AHashMap<AccountId, DeliveryStatus>, String> {
         // Ingest message
         let store = self.store.clone();
         let (change_id, status) = match self
-            .spawn_worker(move || Ok(store.mail_ingest(mail_from, rcpt_to, raw_message)))
+            .spawn_worker(move || {
+                Ok(store.mail_ingest(mail_from, rcpt_to, raw_message, is_phishing))
+            })
             .await
             .unwrap()
         {
@@ -225,7 +310,11 @@ where
                 let mut change_id = ChangeId::MAX;
 
                 for rcpt_status in &status {
-                    if let Status::Success { changes, .. } = rcpt_status {
+                    if let Status::Success {
+                        changes: Some(changes),
+                        ..
+                    } = rcpt_status
+                    {
                         change_id = changes.change_id;
                     }
                 }
@@ -255,20 +344,42 @@ where
                     account_id,
                     changes,
                     vacation_response,
+                    redirect,
                 } => {
                     // Publish state change
-                    let mut types = changes
-                        .collections
-                        .into_iter()
-                        .filter_map(|c| Some((TypeState::try_from(c).ok()?, change_id)))
-                        .collect::<Vec<_>>();
-                    types.push((TypeState::EmailDelivery, change_id));
-
-                    if let Err(err) = self
-                        .publish_state_change(StateChange::new(account_id, types))
-                        .await
-                    {
-                        error!("Failed to publish state change: {}", err);
+                    if let Some(changes) = changes {
+                        let mut types = changes
+                            .collections
+                            .into_iter()
+                            .filter_map(|c| Some((TypeState::try_from(c).ok()?, change_id)))
+                            .collect::<Vec<_>>();
+                        types.push((TypeState::EmailDelivery, change_id));
+
+                        if let Err(err) = self
+                            .publish_state_change(StateChange::new(account_id, types))
+                            .await
+                        {
+                            error!("Failed to publish state change: {}", err);
+                        }
+                    }
+
+                    // Redirect message
+                    if let Some(redirect) = redirect {
+                        for to in redirect.to {
+                            if let Err(err) = self
+                                .notify_email_delivery(email_delivery::Event::redirect(
+                                    redirect.from.clone(),
+                                    to,
+                                    redirect.message.clone(),
+                                ))
+                                .await
+                            {
+                                error!(
+                                    "No e-mail delivery configured or something else happened: {}",
+                                    err
+                                );
+                            }
+                        }
                     }
 
                     // Send vacation response
@@ -296,6 +407,12 @@ where
                 Status::PermanentFailure { account_id, reason } => {
                     delivery_status.insert(account_id, DeliveryStatus::PermanentFailure { reason });
                 }
+                Status::Rejected { account_id, reason } => {
+                    delivery_status.insert(account_id, DeliveryStatus::Rejected { reason });
+                }
+                Status::OverQuota { account_id } => {
+                    delivery_status.insert(account_id, DeliveryStatus::OverQuota);
+                }
             }
         }
 
@@ -307,15 +424,28 @@ pub trait JMAPMailIngest {
     fn mail_ingest(
         &self,
         mail_from: String,
-        rcpt_to: AHashSet<AccountId>,
+        rcpt_to: AHashMap<AccountId, String>,
         raw_message: Vec<u8>,
+        is_phishing: bool,
     ) -> Result<Vec<Status>, Status>;
     fn mail_deliver_rcpt(
         &self,
         account_id: AccountId,
-        document: &Document,
+        rcpt_to: &str,
+        message: &IngestMessage,
+        mail_from: &str,
         return_address: Option<&str>,
     ) -> Status;
+    fn mailbox_by_path(
+        &self,
+        account_id: AccountId,
+        path: &str,
+    ) -> store::Result<Option<DocumentId>>;
+    fn mailbox_by_role(
+        &self,
+        account_id: AccountId,
+        role: &str,
+    ) -> store::Result<Option<DocumentId>>;
 }
 
 impl<T> JMAPMailIngest for JMAPStore<T>
@@ -325,8 +455,9 @@ where
     fn mail_ingest(
         &self,
         mail_from: String,
-        rcpt_to: AHashSet<AccountId>,
+        rcpt_to: AHashMap<AccountId, String>,
         raw_message: Vec<u8>,
+        is_phishing: bool,
     ) -> Result<Vec<Status>, Status> {
         // Parse message
         let message = if let Some(message) = Message::parse(&raw_message) {
@@ -356,6 +487,22 @@ where
             }
         });
 
+        // Tokenize message for spam classification
+        let spam_tokens = self.mail_spam_tokens(&message);
+
+        // Detect iMIP invitations sent by the organizer or by an attendee
+        let invitation = Invitation::parse(&message).filter(|invitation| {
+            if invitation.is_valid_sender(&mail_from) {
+                true
+            } else {
+                debug!(
+                    "Ignoring calendar invitation {:?} not sent by its organizer or attendees.",
+                    invitation.uid
+                );
+                false
+            }
+        });
+
         // Build message document
         let mut document = Document::new(Collection::Mail, DocumentId::MAX);
         let blob_id = BlobId::new_external(&raw_message);
@@ -363,17 +510,52 @@ where
             error!("Failed to parse message during ingestion: {}", err);
             return Err(Status::internal_error(AccountId::MAX));
         }
+        if let Some(invitation) = &invitation {
+            if let Err(err) = self.mail_link_invitation(&mut document, invitation) {
+                error!(
+                    "Failed to store invitation during message ingestion: {}",
+                    err
+                );
+                return Err(Status::internal_error(AccountId::MAX));
+            }
+        }
+
+        // Parse headers for Sieve and spam filtering
+        let headers = MessageHeaders::parse(&raw_message);
+        let is_spam = self.mail_spam_headers(&headers);
 
         // Store raw message as a blob
+        let size = raw_message.len();
         if let Err(err) = self.blob_store(&blob_id, raw_message) {
             error!("Failed to store blob during message ingestion: {}", err);
             return Err(Status::internal_error(AccountId::MAX));
         }
 
         // Deliver message to recipients
+        let message = IngestMessage {
+            document,
+            blob_id,
+            headers,
+            spam_tokens,
+            is_spam,
+            is_phishing,
+        };
         let mut result = Vec::with_capacity(rcpt_to.len());
-        for account_id in rcpt_to {
-            result.push(self.mail_deliver_rcpt(account_id, &document, return_address.as_deref()));
+        for (account_id, rcpt) in rcpt_to {
+            result.push(match self.principal_has_quota(account_id, size) {
+                Ok(true) => self.mail_deliver_rcpt(
+                    account_id,
+                    &rcpt,
+                    &message,
+                    &mail_from,
+                    return_address.as_deref(),
+                ),
+                Ok(false) => Status::OverQuota { account_id },
+                Err(err) => {
+                    error!("Failed to obtain quota during ingestion: {}", err);
+                    Status::internal_error(account_id)
+                }
+            });
         }
 
         Ok(result)
@@ -382,12 +564,14 @@ where
     fn mail_deliver_rcpt(
         &self,
         account_id: AccountId,
-        document: &Document,
+        rcpt_to: &str,
+        message: &IngestMessage,
+        mail_from: &str,
         return_address: Option<&str>,
     ) -> Status {
         // Prepare batch
         let mut batch = WriteBatch::new(account_id);
-        let mut document = document.clone();
+        let mut document = message.document.clone();
 
         // Verify that this account has an Inbox mailbox
         match self.get_document_ids(account_id, Collection::Mailbox) {
@@ -398,10 +582,234 @@ where
             }
         }
 
-        // Add mailbox tags
+        // Obtain account details
+        let (email, from_name) = match self.get_account_details(account_id) {
+            Ok(Some((email, from_name, _))) => (email, from_name),
+            Ok(None) => (String::new(), String::new()),
+            Err(err) => {
+                error!("Failed to obtain account details during ingestion: {}", err);
+                return Status::internal_error(account_id);
+            }
+        };
+
+        // Run active Sieve script
+        let mut mailbox_ids = Vec::new();
+        let mut keywords = Vec::new();
+        let mut redirect_to = Vec::new();
+        let mut sieve_vacation = None;
+        match self.sieve_script_get_active(account_id) {
+            Ok(Some((script_id, sieve))) => {
+                for action in sieve.run(
+                    &Envelope {
+                        from: mail_from,
+                        to: rcpt_to,
+                    },
+                    &message.headers,
+                    self.config.sieve_max_redirects,
+                ) {
+                    match action {
+                        Action::Keep { flags } => {
+                            mailbox_ids.push(INBOX_ID);
+                            keywords.extend(flags);
+                        }
+                        Action::FileInto { mailbox, flags } => {
+                            mailbox_ids.push(match self.mailbox_by_path(account_id, &mailbox) {
+                                Ok(Some(mailbox_id)) => mailbox_id,
+                                Ok(None) => {
+                                    debug!(
+                                        "Mailbox {:?} not found for account {}, filing into Inbox.",
+                                        mailbox, account_id
+                                    );
+                                    INBOX_ID
+                                }
+                                Err(err) => {
+                                    error!("Failed to obtain mailbox during ingestion: {}", err);
+                                    return Status::internal_error(account_id);
+                                }
+                            });
+                            keywords.extend(flags);
+                        }
+                        Action::Redirect { address } => {
+                            redirect_to.push(address);
+                        }
+                        Action::Reject { reason } => {
+                            return Status::reject(account_id, &reason);
+                        }
+                        Action::Vacation(vacation) => {
+                            sieve_vacation = Some((script_id, vacation));
+                        }
+                    }
+                }
+            }
+            Ok(None) => {
+                mailbox_ids.push(INBOX_ID);
+            }
+            Err(err) => {
+                error!("Failed to obtain Sieve script during ingestion: {}", err);
+                mailbox_ids.push(INBOX_ID);
+            }
+        }
+
+        // Messages that were already delivered to this recipient are not redirected
+        // again, otherwise two accounts redirecting to each other would loop forever.
+        if !redirect_to.is_empty()
+            && message.headers.get_all("Delivered-To").any(|delivered_to| {
+                let delivered_to = delivered_to.trim_matches(|ch| ch == '<' || ch == '>');
+                delivered_to.eq_ignore_ascii_case(rcpt_to)
+                    || delivered_to.eq_ignore_ascii_case(&email)
+            })
+        {
+            debug!(
+                "Mail loop detected for <{}>, delivering to account {} instead of redirecting.",
+                rcpt_to, account_id
+            );
+            redirect_to.clear();
+            if mailbox_ids.is_empty() {
+                mailbox_ids.push(INBOX_ID);
+            }
+        }
+
+        // Classify message as spam
+        let is_spam = !mailbox_ids.is_empty()
+            && (message.is_spam
+                || match self.mail_is_spam(account_id, &message.spam_tokens) {
+                    Ok(is_spam) => is_spam,
+                    Err(err) => {
+                        error!("Failed to classify message during ingestion: {}", err);
+                        false
+                    }
+                });
+        if is_spam {
+            match self.mailbox_by_role(account_id, "junk") {
+                Ok(Some(junk_id)) => {
+                    for mailbox_id in mailbox_ids.iter_mut() {
+                        if *mailbox_id == INBOX_ID {
+                            *mailbox_id = junk_id;
+                        }
+                    }
+                }
+                Ok(None) => {
+                    debug!(
+                        "Account {} does not have a Junk mailbox, filing spam into Inbox.",
+                        account_id
+                    );
+                }
+                Err(err) => {
+                    error!("Failed to obtain mailbox during ingestion: {}", err);
+                    return Status::internal_error(account_id);
+                }
+            }
+            keywords.push("$junk".to_string());
+        }
+
+        // Tag messages that failed DMARC verification
+        if message.is_phishing {
+            keywords.push("$phishing".to_string());
+        }
+
+        // Build vacation response
+        let vacation_response = match (return_address, sieve_vacation) {
+            _ if is_spam => None,
+            (Some(return_address), _) if email.is_empty() => {
+                debug!(
+                    "Account {} has no e-mail address, skipping vacation response to {}.",
+                    account_id, return_address
+                );
+                None
+            }
+            (Some(return_address), Some((script_id, vacation))) => {
+                match self.build_sieve_vacation_response(
+                    account_id,
+                    script_id,
+                    vacation,
+                    (from_name.as_str().into(), &email),
+                    return_address,
+                    &message.headers,
+                ) {
+                    Ok(vr) => vr,
+                    Err(err) => {
+                        error!(
+                            "Failed to build vacation response during ingestion: {}",
+                            err
+                        );
+                        None
+                    }
+                }
+            }
+            (Some(return_address), None) => {
+                match self.build_vacation_response(
+                    account_id,
+                    from_name.as_str().into(),
+                    &email,
+                    return_address,
+                ) {
+                    Ok(vr) => vr,
+                    Err(err) => {
+                        error!(
+                            "Failed to build vacation response during ingestion: {}",
+                            err
+                        );
+                        None
+                    }
+                }
+            }
+            (None, _) => None,
+        };
+
+        // Obtain message to redirect
+        let redirect = if !redirect_to.is_empty() {
+            match self.blob_get(&message.blob_id) {
+                Ok(Some(raw_message)) => {
+                    let mut redirected_message =
+                        format!("Delivered-To: {}\r\n", rcpt_to).into_bytes();
+                    redirected_message.extend_from_slice(&raw_message);
+                    Redirect {
+                        from: mail_from.to_string(),
+                        to: redirect_to,
+                        message: redirected_message,
+                    }
+                    .into()
+                }
+                Ok(None) => {
+                    error!("Failed to fetch message blob for redirect.");
+                    return Status::internal_error(account_id);
+                }
+                Err(err) => {
+                    error!("Failed to fetch message blob for redirect: {}", err);
+                    return Status::internal_error(account_id);
+                }
+            }
+        } else {
+            None
+        };
+
+        // Message was discarded or redirected
+        if mailbox_ids.is_empty() {
+            return Status::Success {
+                account_id,
+                changes: None,
+                vacation_response,
+                redirect,
+            };
+        }
+
+        // Add mailbox and keyword tags
         let mut orm = TinyORM::<Email>::new();
-        batch.log_child_update(Collection::Mailbox, JMAPId::new(INBOX_ID.into()));
-        orm.tag(Property::MailboxIds, Tag::Id(INBOX_ID));
+        for mailbox_id in mailbox_ids {
+            batch.log_child_update(Collection::Mailbox, JMAPId::new(mailbox_id.into()));
+            orm.tag(Property::MailboxIds, Tag::Id(mailbox_id));
+        }
+        for keyword in keywords {
+            orm.tag(
+                Property::Keywords,
+                if let Some(flag) = keyword.strip_prefix('\\') {
+                    Keyword::parse(&format!("${}", flag))
+                } else {
+                    Keyword::parse(&keyword)
+                }
+                .tag,
+            );
+        }
 
         // Serialize ORM
         if let Err(err) = orm.insert(&mut document) {
@@ -419,39 +827,6 @@ where
         };
         document.document_id = document_id;
 
-        // Build vacation response
-        let vacation_response = if let Some(return_address) = &return_address {
-            match self.get_account_details(account_id) {
-                Ok(Some((email, from_name, _))) => {
-                    match self.build_vacation_response(
-                        account_id,
-                        from_name.as_str().into(),
-                        &email,
-                        return_address,
-                    ) {
-                        Ok(vr) => vr,
-                        Err(err) => {
-                            error!(
-                                "Failed to build vacation response during ingestion: {}",
-                                err
-                            );
-                            None
-                        }
-                    }
-                }
-                Ok(None) => None,
-                Err(err) => {
-                    error!(
-                        "Failed to build vacation response during ingestion: {}",
-                        err
-                    );
-                    None
-                }
-            }
-        } else {
-            None
-        };
-
         // Lock account while threads are merged
         let _lock = self.lock_collection(account_id, Collection::Mail);
 
@@ -464,8 +839,9 @@ where
                 match self.write(batch) {
                     Ok(Some(changes)) => Status::Success {
                         account_id,
-                        changes,
+                        changes: changes.into(),
                         vacation_response,
+                        redirect,
                     },
                     Ok(None) => {
                         error!("Unexpected error during ingestion.");
@@ -483,13 +859,93 @@ where
             }
         }
     }
+
+    fn mailbox_by_path(
+        &self,
+        account_id: AccountId,
+        path: &str,
+    ) -> store::Result<Option<DocumentId>> {
+        if path.eq_ignore_ascii_case("INBOX") {
+            return Ok(Some(INBOX_ID));
+        }
+
+        // Obtain the name and parent of each mailbox
+        let mut mailboxes = Vec::new();
+        for document_id in self
+            .get_document_ids(account_id, Collection::Mailbox)?
+            .unwrap_or_default()
+        {
+            if let Some(mailbox) = self.get_orm::<Mailbox>(account_id, document_id)? {
+                if let Some(mailbox_schema::Value::Text { value }) =
+                    mailbox.get(&mailbox_schema::Property::Name)
+                {
+                    mailboxes.push((
+                        document_id,
+                        value.to_string(),
+                        mailbox
+                            .get(&mailbox_schema::Property::ParentId)
+                            .and_then(|v| v.as_id())
+                            .unwrap_or(0),
+                    ));
+                }
+            }
+        }
+
+        // Walk the path, parent ids are stored as document_id + 1
+        let mut mailbox_id = None;
+        for name in path.split('/') {
+            let parent_id = mailbox_id.map_or(0, |id: DocumentId| id as u64 + 1);
+            if let Some((document_id, _, _)) =
+                mailboxes
+                    .iter()
+                    .find(|(_, mailbox_name, mailbox_parent_id)| {
+                        *mailbox_parent_id == parent_id && mailbox_name == name
+                    })
+            {
+                mailbox_id = Some(*document_id);
+            } else {
+                return Ok(None);
+            }
+        }
+
+        Ok(mailbox_id)
+    }
+
+    fn mailbox_by_role(
+        &self,
+        account_id: AccountId,
+        role: &str,
+    ) -> store::Result<Option<DocumentId>> {
+        Ok(self
+            .query_store::<FilterMapper>(
+                account_id,
+                Collection::Mailbox,
+                Filter::new_condition(
+                    mailbox_schema::Property::Role.into(),
+                    ComparisonOperator::Equal,
+                    Query::Keyword(role.to_string()),
+                ),
+                Comparator::None,
+            )?
+            .get_min())
+    }
+}
+
+pub struct IngestMessage {
+    pub document: Document,
+    pub blob_id: BlobId,
+    pub headers: MessageHeaders,
+    pub spam_tokens: AHashSet<String>,
+    pub is_spam: bool,
+    pub is_phishing: bool,
 }
 
 pub enum Status {
     Success {
         account_id: AccountId,
-        changes: Changes,
+        changes: Option<Changes>,
         vacation_response: Option<VacationMessage>,
+        redirect: Option<Redirect>,
     },
     TemporaryFailure {
         account_id: AccountId,
@@ -499,6 +955,19 @@ pub enum Status {
         account_id: AccountId,
         reason: Cow<'static, str>,
     },
+    Rejected {
+        account_id: AccountId,
+        reason: String,
+    },
+    OverQuota {
+        account_id: AccountId,
+    },
+}
+
+pub struct Redirect {
+    pub from: String,
+    pub to: Vec<String>,
+    pub message: Vec<u8>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -506,6 +975,8 @@ pub enum DeliveryStatus {
     Success,
     TemporaryFailure { reason: Cow<'static, str> },
     PermanentFailure { reason: Cow<'static, str> },
+    Rejected { reason: String },
+    OverQuota,
 }
 
 impl Status {
@@ -529,4 +1000,34 @@ impl Status {
             reason: reason.into(),
         }
     }
+
+    // Reasons come from user scripts, so they are collapsed into a single
+    // line before being included in the LMTP reply.
+    pub fn reject(account_id: AccountId, reason: &str) -> Status {
+        let mut line = String::with_capacity(std::cmp::min(reason.len(), MAX_REJECT_LEN));
+        for word in reason
+            .split(|ch: char| ch.is_whitespace() || ch.is_control())
+            .filter(|word| !word.is_empty())
+        {
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(word);
+        }
+        if line.len() > MAX_REJECT_LEN {
+            let mut len = MAX_REJECT_LEN;
+            while !line.is_char_boundary(len) {
+                len -= 1;
+            }
+            line.truncate(len);
+        }
+        if line.is_empty() {
+            line.push_str("Message rejected.");
+        }
+
+        Status::Rejected {
+            account_id,
+            reason: line,
+        }
+    }
 }
@@ -37,7 +37,9 @@ use tokio::{io::AsyncWriteExt, net::TcpListener, sync::watch};
 use tokio_rustls::TlsAcceptor;
 
 use crate::{
-    cluster::rpc::tls::load_tls_server_config, lmtp::session::Session, server::failed_to,
+    cluster::rpc::tls::load_tls_server_config,
+    lmtp::{session::Session, verify::MailAuth},
+    server::failed_to,
     JMAPServer,
 };
 
@@ -97,6 +99,9 @@ pub fn spawn_lmtp<T>(
         tls_only = false;
     }
 
+    // Build message authentication verifier
+    let mail_auth = MailAuth::from_settings(settings).map(Arc::new);
+
     tokio::spawn(async move {
         // Start listening for LMTP connections.
         let listener = match TcpListener::bind(bind_addr).await {
@@ -142,6 +147,7 @@ pub fn spawn_lmtp<T>(
                             let greeting = greeting.clone();
                             let tls_acceptor = tls_acceptor.clone();
                             let hostname = hostname.clone();
+                            let mail_auth = mail_auth.clone();
 
                             tokio::spawn(async move {
                                 if tls_only {
@@ -160,7 +166,7 @@ pub fn spawn_lmtp<T>(
                                     }
 
                                     handle_conn(
-                                        Session::new(core, peer_addr, stream.into(), None, hostname),
+                                        Session::new(core, peer_addr, stream.into(), None, hostname, mail_auth),
                                         shutdown_rx
                                     ).await;
                                 } else {
@@ -171,7 +177,7 @@ pub fn spawn_lmtp<T>(
                                     }
 
                                     handle_conn(
-                                        Session::new(core, peer_addr, stream.into(), tls_acceptor, hostname),
+                                        Session::new(core, peer_addr, stream.into(), tls_acceptor, hostname, mail_auth),
                                         shutdown_rx
                                     ).await;
                                 }
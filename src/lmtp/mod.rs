@@ -26,3 +26,4 @@ pub mod listener;
 pub mod request;
 pub mod response;
 pub mod session;
+pub mod verify;
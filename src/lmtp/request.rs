@@ -39,6 +39,22 @@ pub enum Request {
     Lhlo {
         domain: String,
     },
+    Ehlo {
+        domain: String,
+    },
+    Helo {
+        domain: String,
+    },
+    Auth {
+        mechanism: String,
+        initial_response: Option<String>,
+    },
+    AuthResponse {
+        response: Vec<u8>,
+    },
+    Xforward {
+        attributes: Vec<(String, String)>,
+    },
     Mail {
         sender: String,
         params: Vec<Param>,
@@ -82,6 +98,7 @@ pub enum State {
     Request { in_addr: bool },
     Bdat { chunk_size: usize, is_last: bool },
     Data { state: StateData },
+    AuthResponse,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -108,6 +125,7 @@ pub struct RequestParser {
     pub command_size: usize,
     pub max_command_size: usize,
     pub max_message_size: usize,
+    is_case_sensitive: bool,
 }
 
 impl RequestParser {
@@ -119,6 +137,7 @@ impl RequestParser {
             command_size: 0,
             max_command_size,
             max_message_size,
+            is_case_sensitive: false,
         }
     }
 
@@ -127,11 +146,21 @@ impl RequestParser {
         self.state = State::Start;
         self.tokens.clear();
         self.command_size = 0;
+        self.is_case_sensitive = false;
         Event::parse_error(message)
     }
 
+    /// Reads the next line as a SASL response instead of a command.
+    pub fn expect_auth_response(&mut self) {
+        self.state = State::AuthResponse;
+    }
+
     fn push_buf(&mut self) -> Result<(), Event> {
         if !self.buf.is_empty() {
+            // SASL initial responses and XFORWARD attributes are case sensitive
+            if self.tokens.is_empty() && (self.buf == b"auth" || self.buf == b"xforward") {
+                self.is_case_sensitive = true;
+            }
             self.tokens.push(Token::Text(
                 String::from_utf8(std::mem::take(&mut self.buf))
                     .map_err(|_| Event::parse_error("Invalid UTF-8"))?,
@@ -164,11 +193,11 @@ impl RequestParser {
                     }
                 }
                 State::Request { in_addr } => match ch {
-                    b':' if !in_addr => {
+                    b':' if !in_addr && !self.is_case_sensitive => {
                         self.push_buf()?;
                         self.push_token(Token::Colon)?;
                     }
-                    b'=' if !in_addr => {
+                    b'=' if !in_addr && !self.is_case_sensitive => {
                         self.push_buf()?;
                         self.push_token(Token::Eq)?;
                     }
@@ -189,6 +218,7 @@ impl RequestParser {
                         let mut tokens = std::mem::take(&mut self.tokens).into_iter().peekable();
                         self.tokens = Vec::with_capacity(5);
                         self.state = State::Start;
+                        self.is_case_sensitive = false;
 
                         return match tokens
                             .next()
@@ -205,6 +235,61 @@ impl RequestParser {
                                     },
                                 )?,
                             }),
+                            cmd @ ("ehlo" | "helo") => {
+                                let domain = tokens
+                                    .next()
+                                    .and_then(|t| t.unwrap_text())
+                                    .ok_or_else(|| {
+                                        Event::parse_error(format!(
+                                            "{} requires a domain name as argument.",
+                                            cmd.to_uppercase()
+                                        ))
+                                    })?;
+                                Ok(if cmd == "ehlo" {
+                                    Request::Ehlo { domain }
+                                } else {
+                                    Request::Helo { domain }
+                                })
+                            }
+                            "auth" => Ok(Request::Auth {
+                                mechanism: tokens
+                                    .next()
+                                    .and_then(|t| t.unwrap_text())
+                                    .ok_or_else(|| {
+                                        Event::parse_error(
+                                            "AUTH requires a SASL mechanism as argument.",
+                                        )
+                                    })?
+                                    .to_ascii_uppercase(),
+                                initial_response: tokens.next().and_then(|t| t.unwrap_text()),
+                            }),
+                            "xforward" => {
+                                let mut attributes = Vec::new();
+                                for token in tokens {
+                                    match token
+                                        .unwrap_text()
+                                        .as_deref()
+                                        .and_then(|attr| attr.split_once('='))
+                                    {
+                                        Some((name, value)) if !name.is_empty() => {
+                                            attributes.push((
+                                                name.to_ascii_uppercase(),
+                                                xtext_decode(value),
+                                            ));
+                                        }
+                                        _ => {
+                                            return Err(Event::parse_error(
+                                                "Invalid XFORWARD attribute.",
+                                            ));
+                                        }
+                                    }
+                                }
+                                if !attributes.is_empty() {
+                                    Ok(Request::Xforward { attributes })
+                                } else {
+                                    Err(Event::parse_error("XFORWARD requires attributes."))
+                                }
+                            }
                             "mail" => {
                                 if matches!(tokens.next(), Some(Token::Text(from)) if from == "from")
                                     && matches!(tokens.next(), Some(Token::Colon))
@@ -329,8 +414,11 @@ impl RequestParser {
                             if self.command_size > self.max_command_size {
                                 return Err(Event::parse_error("Request is too long."));
                             }
-                            self.buf
-                                .push(if in_addr { ch } else { ch.to_ascii_lowercase() });
+                            self.buf.push(if in_addr || self.is_case_sensitive {
+                                ch
+                            } else {
+                                ch.to_ascii_lowercase()
+                            });
                         } else {
                             self.push_buf()?;
                         }
@@ -426,6 +514,23 @@ impl RequestParser {
 
                     self.state = State::Data { state };
                 }
+                State::AuthResponse => match ch {
+                    b'\n' => {
+                        if self.buf.last() == Some(&b'\r') {
+                            self.buf.pop();
+                        }
+                        let response = std::mem::take(&mut self.buf);
+                        self.buf = Vec::with_capacity(10);
+                        self.state = State::Start;
+                        return Ok(Request::AuthResponse { response });
+                    }
+                    _ if self.buf.len() < self.max_command_size => {
+                        self.buf.push(ch);
+                    }
+                    _ => {
+                        return Err(self.error_reset("SASL response is too long."));
+                    }
+                },
                 State::Bdat {
                     chunk_size,
                     is_last,
@@ -532,6 +637,28 @@ impl RequestParser {
     }
 }
 
+// Decodes an xtext encoded value (RFC 3461, section 4)
+fn xtext_decode(value: &str) -> String {
+    let mut result = Vec::with_capacity(value.len());
+    let mut bytes = value.as_bytes().iter();
+    while let Some(&ch) = bytes.next() {
+        if ch == b'+' {
+            let hex = bytes
+                .as_slice()
+                .get(..2)
+                .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+            if let Some(hex) = hex {
+                result.push(hex);
+                bytes.nth(1);
+                continue;
+            }
+        }
+        result.push(ch);
+    }
+    String::from_utf8(result)
+        .unwrap_or_else(|err| String::from_utf8_lossy(err.as_bytes()).into_owned())
+}
+
 impl Token {
     pub fn unwrap_text(self) -> Option<String> {
         match self {
@@ -630,6 +757,50 @@ mod tests {
                     },
                 ],
             ),
+            (
+                vec![
+                    "EHLO Foo.Example\r\n",
+                    "helo bar.example\r\n",
+                    "AUTH plain AGpkb2UAMTIzNDU=\r\n",
+                    "auth LOGIN\r\n",
+                ],
+                vec![
+                    Request::Ehlo {
+                        domain: "foo.example".to_string(),
+                    },
+                    Request::Helo {
+                        domain: "bar.example".to_string(),
+                    },
+                    Request::Auth {
+                        mechanism: "PLAIN".to_string(),
+                        initial_response: "AGpkb2UAMTIzNDU=".to_string().into(),
+                    },
+                    Request::Auth {
+                        mechanism: "LOGIN".to_string(),
+                        initial_response: None,
+                    },
+                ],
+            ),
+            (
+                vec![
+                    "XFORWARD NAME=Mail.Example.org ADDR=IPV6:2001:db8::1\r\n",
+                    "xforward helo=Mx+2BRelay.Example.org PROTO=ESMTP\r\n",
+                ],
+                vec![
+                    Request::Xforward {
+                        attributes: vec![
+                            ("NAME".to_string(), "Mail.Example.org".to_string()),
+                            ("ADDR".to_string(), "IPV6:2001:db8::1".to_string()),
+                        ],
+                    },
+                    Request::Xforward {
+                        attributes: vec![
+                            ("HELO".to_string(), "Mx+Relay.Example.org".to_string()),
+                            ("PROTO".to_string(), "ESMTP".to_string()),
+                        ],
+                    },
+                ],
+            ),
             (
                 vec![
                     "help my-command \r\n",
@@ -49,6 +49,8 @@ pub enum Extension {
     SmtpUtf8,
     StartTls,
     EnhancedStatusCodes,
+    Auth(&'static str),
+    Xforward,
 }
 
 impl Response<'_> {
@@ -98,6 +100,13 @@ impl Response<'_> {
                         Extension::EnhancedStatusCodes => {
                             buf.extend_from_slice(b"ENHANCEDSTATUSCODES")
                         }
+                        Extension::Auth(mechanisms) => {
+                            buf.extend_from_slice(b"AUTH ");
+                            buf.extend_from_slice(mechanisms.as_bytes())
+                        }
+                        Extension::Xforward => {
+                            buf.extend_from_slice(b"XFORWARD NAME ADDR PROTO HELO")
+                        }
                     }
                     buf.extend_from_slice(b"\r\n");
                 }
@@ -137,6 +146,8 @@ mod tests {
                         Extension::Chunking,
                         Extension::SmtpUtf8,
                         Extension::StartTls,
+                        Extension::Auth("PLAIN LOGIN"),
+                        Extension::Xforward,
                     ],
                 },
                 concat!(
@@ -149,7 +160,9 @@ mod tests {
                     "250-PIPELINING\r\n",
                     "250-CHUNKING\r\n",
                     "250-SMTPUTF8\r\n",
-                    "250 STARTTLS\r\n"
+                    "250-STARTTLS\r\n",
+                    "250-AUTH PLAIN LOGIN\r\n",
+                    "250 XFORWARD NAME ADDR PROTO HELO\r\n"
                 ),
             ),
         ] {
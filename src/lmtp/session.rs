@@ -21,10 +21,13 @@
  * for more details.
 */
 
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
 
 use actix_web::web;
-use store::{ahash::AHashSet, chrono::Local, tracing::debug, AccountId, RecipientType, Store};
+use store::{ahash::AHashMap, chrono::Local, tracing::debug, AccountId, RecipientType, Store};
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
     net::TcpStream,
@@ -36,6 +39,7 @@ use crate::JMAPServer;
 use super::{
     request::{Event, Param, Request, RequestParser},
     response::{Extension, Response},
+    verify::MailAuth,
 };
 
 const MAX_COMMAND_LENGTH: usize = 1024;
@@ -50,13 +54,16 @@ where
     pub parser: RequestParser,
     pub peer_addr: SocketAddr,
     pub stream: Stream,
+    pub mail_auth: Option<Arc<MailAuth>>,
 
     // State
     pub remote_hostname: Option<String>,
+    pub client_addr: Option<IpAddr>,
+    pub client_helo: Option<String>,
     pub mail_from: Option<String>,
     pub mail_size: Option<usize>,
     pub rcpt_to: Vec<RcptType>,
-    pub rcpt_to_ids: AHashSet<AccountId>,
+    pub rcpt_to_ids: AHashMap<AccountId, String>,
     pub message: Vec<u8>,
 }
 
@@ -82,6 +89,7 @@ where
         stream: Stream,
         tls_acceptor: Option<Arc<TlsAcceptor>>,
         hostname: Arc<String>,
+        mail_auth: Option<Arc<MailAuth>>,
     ) -> Self {
         Self {
             parser: RequestParser::new(MAX_COMMAND_LENGTH, core.store.config.mail_max_size),
@@ -89,11 +97,14 @@ where
             peer_addr,
             stream,
             core,
+            mail_auth,
             remote_hostname: None,
+            client_addr: None,
+            client_helo: None,
             mail_from: None,
             mail_size: None,
             rcpt_to: Vec::new(),
-            rcpt_to_ids: AHashSet::new(),
+            rcpt_to_ids: AHashMap::new(),
             message: Vec::new(),
             hostname,
         }
@@ -120,6 +131,9 @@ where
                         if !self.stream.is_tls() {
                             extensions.push(Extension::StartTls);
                         }
+                        if self.mail_auth.is_some() {
+                            extensions.push(Extension::Xforward);
+                        }
                         self.write_bytes(
                             &Response::Lhlo {
                                 local_host: self.hostname.as_ref().into(),
@@ -131,6 +145,26 @@ where
                         .await?;
                         self.remote_hostname = domain.into();
                     }
+                    Request::Xforward { attributes } => {
+                        // Client information forwarded by the MTA (used for SPF)
+                        for (name, value) in attributes {
+                            if value.starts_with('[') {
+                                // [UNAVAILABLE] or [TEMPUNAVAIL]
+                                continue;
+                            }
+                            match name.as_str() {
+                                "ADDR" => {
+                                    self.client_addr =
+                                        value.strip_prefix("IPV6:").unwrap_or(&value).parse().ok();
+                                }
+                                "HELO" => {
+                                    self.client_helo = value.into();
+                                }
+                                _ => (),
+                            }
+                        }
+                        self.write_bytes(b"250 2.0.0 OK\r\n").await?;
+                    }
                     Request::Mail { sender, params } => {
                         self.write_bytes(
                             format!("250 2.1.0 Sender <{}> accepted.\r\n", sender).as_bytes(),
@@ -154,7 +188,9 @@ where
                                 )
                                 .await?;
 
-                                self.rcpt_to_ids.insert(*account_id);
+                                self.rcpt_to_ids
+                                    .entry(*account_id)
+                                    .or_insert_with(|| recipient.clone());
                                 self.rcpt_to.push(RcptType::Mailbox {
                                     id: *account_id,
                                     name: recipient,
@@ -169,7 +205,9 @@ where
 
                                 let mut ids = Vec::with_capacity(account_ids.len());
                                 for (account_id, _) in account_ids {
-                                    self.rcpt_to_ids.insert(*account_id);
+                                    self.rcpt_to_ids
+                                        .entry(*account_id)
+                                        .or_insert_with(|| recipient.clone());
                                     ids.push(*account_id);
                                 }
                                 self.rcpt_to.push(RcptType::List {
@@ -312,6 +350,8 @@ where
                         self.rcpt_to.clear();
                         self.rcpt_to_ids.clear();
                         self.message = Vec::new();
+                        self.client_addr = None;
+                        self.client_helo = None;
                         self.write_bytes(b"250 2.0.0 OK\r\n").await?;
                     }
                     Request::Noop => {
@@ -321,6 +361,13 @@ where
                         self.write_bytes(b"221 2.0.0 Bye\r\n").await?;
                         return Err(());
                     }
+                    Request::Ehlo { .. }
+                    | Request::Helo { .. }
+                    | Request::Auth { .. }
+                    | Request::AuthResponse { .. } => {
+                        self.write_bytes(b"502 5.5.1 Command not supported over LMTP.\r\n")
+                            .await?;
+                    }
                 },
                 Err(Event::NeedsMoreBytes) => {
                     break;
@@ -0,0 +1,359 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{net::IpAddr, time::Duration};
+
+use mail_auth::{
+    common::headers::HeaderWriter,
+    dmarc::Policy,
+    trust_dns_resolver::config::{NameServerConfigGroup, ResolverConfig, ResolverOpts},
+    AuthenticatedMessage, AuthenticationResults, DmarcResult, Resolver,
+};
+use store::{
+    config::env_settings::EnvSettings,
+    tracing::{debug, warn},
+};
+
+use crate::server::failed_to;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DmarcAction {
+    None,
+    Tag,
+    Reject,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Accept { is_phishing: bool },
+    Reject { domain: String },
+}
+
+pub struct Verification {
+    pub header: String,
+    pub verdict: Verdict,
+}
+
+pub struct MailAuth {
+    pub resolver: Resolver,
+    pub dmarc_action: DmarcAction,
+}
+
+impl MailAuth {
+    pub fn from_settings(settings: &EnvSettings) -> Option<Self> {
+        if !settings.parse("lmtp-auth-verify").unwrap_or(false) {
+            return None;
+        }
+
+        let dmarc_action = match settings.get("lmtp-auth-dmarc-action").as_deref() {
+            Some("none") => DmarcAction::None,
+            Some("tag") | None => DmarcAction::Tag,
+            Some("reject") => DmarcAction::Reject,
+            Some(action) => failed_to(&format!(
+                "parse 'lmtp-auth-dmarc-action', invalid action '{}'.",
+                action
+            )),
+        };
+
+        let mut opts = ResolverOpts::default();
+        opts.timeout =
+            Duration::from_millis(settings.parse("lmtp-auth-dns-timeout").unwrap_or(5000));
+        let resolver = if settings.contains_key("lmtp-auth-dns-server") {
+            let dns_server = settings.parse_socketaddr("lmtp-auth-dns-server", "127.0.0.1:53");
+            Resolver::new(
+                ResolverConfig::from_parts(
+                    None,
+                    vec![],
+                    NameServerConfigGroup::from_ips_clear(
+                        &[dns_server.ip()],
+                        dns_server.port(),
+                        true,
+                    ),
+                ),
+                opts,
+            )
+        } else {
+            Resolver::new_system_conf()
+        };
+
+        match resolver {
+            Ok(resolver) => Some(MailAuth {
+                resolver,
+                dmarc_action,
+            }),
+            Err(err) => {
+                warn!(
+                    "Failed to build DNS resolver, message authentication disabled: {}",
+                    err
+                );
+                None
+            }
+        }
+    }
+
+    /// Verifies the DKIM signatures, ARC chain, SPF record and DMARC policy of a
+    /// message, returning an Authentication-Results header and the action to take.
+    pub async fn verify(
+        &self,
+        hostname: &str,
+        remote_ip: IpAddr,
+        helo_domain: &str,
+        mail_from: &str,
+        message: &[u8],
+    ) -> Option<Verification> {
+        let message = if let Some(message) = AuthenticatedMessage::parse(message) {
+            message
+        } else {
+            debug!("Failed to parse message for authentication.");
+            return None;
+        };
+
+        // Verify DKIM signatures and ARC chain
+        let dkim_output = self.resolver.verify_dkim(&message).await;
+        let arc_output = self.resolver.verify_arc(&message).await;
+
+        // Verify SPF for the MAIL FROM identity, or HELO for null senders
+        let mut auth_results = AuthenticationResults::new(hostname)
+            .with_dkim_result(&dkim_output, message.from())
+            .with_arc_result(&arc_output, remote_ip);
+        let (spf_output, spf_domain) = if let Some((_, domain)) = mail_from.rsplit_once('@') {
+            let spf_output = self
+                .resolver
+                .verify_spf_sender(remote_ip, helo_domain, mail_from)
+                .await;
+            auth_results = auth_results.with_spf_mailfrom_result(
+                &spf_output,
+                remote_ip,
+                mail_from,
+                helo_domain,
+            );
+            (spf_output, domain)
+        } else {
+            let spf_output = self.resolver.verify_spf_helo(remote_ip, helo_domain).await;
+            auth_results = auth_results.with_spf_ehlo_result(&spf_output, remote_ip, helo_domain);
+            (spf_output, helo_domain)
+        };
+
+        // Evaluate DMARC policy
+        let dmarc_output = self
+            .resolver
+            .verify_dmarc(&message, &dkim_output, spf_domain, &spf_output)
+            .await;
+        let auth_results = auth_results.with_dmarc_result(&dmarc_output);
+        let is_dmarc_fail = dmarc_output.dkim_result() != &DmarcResult::Pass
+            && dmarc_output.spf_result() != &DmarcResult::Pass;
+
+        let verdict = match (self.dmarc_action, dmarc_output.policy()) {
+            (DmarcAction::Reject, Policy::Reject) if is_dmarc_fail => Verdict::Reject {
+                domain: dmarc_output.domain().to_string(),
+            },
+            (DmarcAction::Reject | DmarcAction::Tag, Policy::Reject | Policy::Quarantine)
+                if is_dmarc_fail =>
+            {
+                Verdict::Accept { is_phishing: true }
+            }
+            _ => Verdict::Accept { is_phishing: false },
+        };
+
+        Some(Verification {
+            header: auth_results.to_header(),
+            verdict,
+        })
+    }
+}
+
+/// Removes the Authentication-Results headers carrying this server's authserv-id,
+/// as they can only have been added by the sender (RFC 8601, Section 5).
+pub fn remove_auth_results(hostname: &str, message: &mut Vec<u8>) {
+    let mut remove = Vec::new();
+    let mut pos = 0;
+    while pos < message.len() {
+        let mut end = next_line(message, pos);
+        if matches!(&message[pos..end], b"\r\n" | b"\n") {
+            break;
+        }
+        // Include folded lines
+        while end < message.len() && matches!(message[end], b' ' | b'\t') {
+            end = next_line(message, end);
+        }
+        if is_own_auth_results(hostname, &message[pos..end]) {
+            remove.push(pos..end);
+        }
+        pos = end;
+    }
+
+    for range in remove.into_iter().rev() {
+        message.drain(range);
+    }
+}
+
+fn next_line(message: &[u8], pos: usize) -> usize {
+    message[pos..]
+        .iter()
+        .position(|&ch| ch == b'\n')
+        .map_or(message.len(), |end| pos + end + 1)
+}
+
+fn is_own_auth_results(hostname: &str, field: &[u8]) -> bool {
+    let (name, value) = if let Some(pos) = field.iter().position(|&ch| ch == b':') {
+        (&field[..pos], String::from_utf8_lossy(&field[pos + 1..]))
+    } else {
+        return false;
+    };
+    if !std::str::from_utf8(name).map_or(false, |name| {
+        name.trim_end()
+            .eq_ignore_ascii_case("Authentication-Results")
+    }) {
+        return false;
+    }
+
+    // Skip comments preceding the authserv-id
+    let mut value = value.trim_start();
+    while let Some(comment) = value.strip_prefix('(') {
+        value = comment
+            .split_once(')')
+            .map_or("", |(_, value)| value.trim_start());
+    }
+    value
+        .split(|ch: char| ch == ';' || ch.is_ascii_whitespace())
+        .next()
+        .map_or(false, |authserv_id| {
+            authserv_id.eq_ignore_ascii_case(hostname)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use mail_auth::{dmarc::Dmarc, spf::Spf, Resolver};
+
+    use super::{remove_auth_results, DmarcAction, MailAuth, Verdict};
+
+    const MESSAGE: &str = concat!(
+        "From: Bill <bill@example.org>\r\n",
+        "To: jdoe@example.com\r\n",
+        "Subject: TPS reports\r\n",
+        "\r\n",
+        "Did you get the memo?\r\n"
+    );
+
+    #[actix_web::test]
+    async fn verify_spf_dmarc() {
+        // Populate the resolver cache with test records
+        let resolver = Resolver::new_system_conf().unwrap();
+        let valid_until = Instant::now() + Duration::from_secs(3600);
+        resolver.txt_add(
+            "example.org.",
+            Spf::parse(b"v=spf1 ip4:10.0.0.1 -all").unwrap(),
+            valid_until,
+        );
+        resolver.txt_add(
+            "_dmarc.example.org.",
+            Dmarc::parse(b"v=DMARC1; p=reject").unwrap(),
+            valid_until,
+        );
+        let mut mail_auth = MailAuth {
+            resolver,
+            dmarc_action: DmarcAction::Reject,
+        };
+
+        // Authorized sender
+        let verification = mail_auth
+            .verify(
+                "mx.example.com",
+                "10.0.0.1".parse().unwrap(),
+                "mx.example.org",
+                "bill@example.org",
+                MESSAGE.as_bytes(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(verification.verdict, Verdict::Accept { is_phishing: false });
+        assert!(
+            verification
+                .header
+                .starts_with("Authentication-Results: mx.example.com;"),
+            "{}",
+            verification.header
+        );
+        assert!(verification.header.contains("spf=pass"));
+        assert!(verification.header.contains("dmarc=pass"));
+
+        // Unauthorized sender, the message is rejected or tagged
+        for (action, verdict) in [
+            (
+                DmarcAction::Reject,
+                Verdict::Reject {
+                    domain: "example.org".to_string(),
+                },
+            ),
+            (DmarcAction::Tag, Verdict::Accept { is_phishing: true }),
+            (DmarcAction::None, Verdict::Accept { is_phishing: false }),
+        ] {
+            mail_auth.dmarc_action = action;
+            let verification = mail_auth
+                .verify(
+                    "mx.example.com",
+                    "10.0.0.2".parse().unwrap(),
+                    "mx.forged.org",
+                    "bill@example.org",
+                    MESSAGE.as_bytes(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(verification.verdict, verdict);
+            assert!(verification.header.contains("spf=fail"));
+            assert!(verification.header.contains("dmarc=fail"));
+        }
+    }
+
+    #[test]
+    fn remove_own_auth_results() {
+        let mut message = concat!(
+            "Authentication-Results: mx.example.com; spf=pass\r\n",
+            "Received: from mx.example.org\r\n",
+            "authentication-results: (forged) MX.EXAMPLE.COM;\r\n",
+            "\tdkim=pass header.d=example.org\r\n",
+            "Authentication-Results: mx.example.org; dmarc=pass\r\n",
+            "Authentication-Results: mx.example.com.evil.org; dmarc=pass\r\n",
+            "Subject: Authentication-Results: mx.example.com;\r\n",
+            "\r\n",
+            "Authentication-Results: mx.example.com; spf=pass\r\n"
+        )
+        .as_bytes()
+        .to_vec();
+        remove_auth_results("mx.example.com", &mut message);
+        assert_eq!(
+            String::from_utf8(message).unwrap(),
+            concat!(
+                "Received: from mx.example.org\r\n",
+                "Authentication-Results: mx.example.org; dmarc=pass\r\n",
+                "Authentication-Results: mx.example.com.evil.org; dmarc=pass\r\n",
+                "Subject: Authentication-Results: mx.example.com;\r\n",
+                "\r\n",
+                "Authentication-Results: mx.example.com; spf=pass\r\n"
+            )
+        );
+    }
+}
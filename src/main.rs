@@ -45,6 +45,7 @@ use std::time::Duration;
 
 use store::{
     config::env_settings::EnvSettings,
+    crypto::rotate_master_key,
     tracing::{self, debug, info, warn, Level},
     Store,
 };
@@ -63,6 +64,20 @@ async fn main() -> std::io::Result<()> {
     )
     .failed_to("set default subscriber");
 
+    // Re-encrypt values and blobs with a new master key and exit
+    if settings.contains_key("encryption-new-master-key")
+        || settings.contains_key("encryption-new-master-key-file")
+    {
+        let db = RocksDB::open(&settings).failed_to("open database");
+        let total_items = rotate_master_key(&db, &settings).failed_to("rotate master key");
+        db.close().failed_to("close database");
+        info!(
+            "Successfully re-encrypted {} values and blobs, please update 'encryption-master-key' with the new master key.",
+            total_items
+        );
+        return Ok(());
+    }
+
     // Set base URL if missing
     if !settings.contains_key("jmap-url") {
         let jmap_url = if settings.contains_key("jmap-cert-path") {
@@ -0,0 +1,203 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use actix_web::web;
+use store::{
+    config::env_settings::EnvSettings,
+    tracing::{debug, error, info, warn},
+    Store,
+};
+use tokio::{io::AsyncWriteExt, net::TcpListener, sync::watch};
+use tokio_rustls::TlsAcceptor;
+
+use crate::{cluster::rpc::tls::load_tls_server_config, pop3::session::Session, JMAPServer};
+
+const TIMEOUT: Duration = Duration::from_secs(10 * 60); // 10 minutes
+const DEFAULT_POP3_PORT: u16 = 1110;
+
+pub fn init_pop3() -> (watch::Sender<bool>, watch::Receiver<bool>) {
+    watch::channel::<bool>(true)
+}
+
+pub fn spawn_pop3<T>(
+    core: web::Data<JMAPServer<T>>,
+    settings: &EnvSettings,
+    mut shutdown_rx: watch::Receiver<bool>,
+) where
+    T: for<'x> Store<'x> + 'static,
+{
+    // Parse bind address
+    let bind_addr = SocketAddr::from((
+        settings.parse_ipaddr("pop3-bind-addr", "127.0.0.1"),
+        settings.parse("pop3-port").unwrap_or(DEFAULT_POP3_PORT),
+    ));
+    info!("Starting POP3 service at {}...", bind_addr);
+
+    // Build TLS acceptor
+    let tls_acceptor = if let (Some(cert_path), Some(key_path)) = (
+        settings.get("pop3-cert-path"),
+        settings.get("pop3-key-path"),
+    ) {
+        Arc::new(TlsAcceptor::from(Arc::new(load_tls_server_config(
+            &cert_path, &key_path,
+        ))))
+        .into()
+    } else {
+        None
+    };
+    let mut tls_only = settings.parse("pop3-tls-only").unwrap_or(false);
+    if tls_only && tls_acceptor.is_none() {
+        warn!("POP3 server is configured to only accept TLS connections, but no TLS certificate was provided.");
+        tls_only = false;
+    }
+
+    tokio::spawn(async move {
+        // Start listening for POP3 connections.
+        let listener = match TcpListener::bind(bind_addr).await {
+            Ok(listener) => listener,
+            Err(err) => {
+                error!("Failed to bind POP3 service to {}: {}", bind_addr, err);
+                return;
+            }
+        };
+
+        let hostname = Arc::new(
+            gethostname::gethostname()
+                .to_str()
+                .unwrap_or("localhost")
+                .to_string(),
+        );
+        let greeting = Arc::new(
+            format!(
+                concat!(
+                    "+OK {} Stalwart POP3 v",
+                    env!("CARGO_PKG_VERSION"),
+                    " at your service.\r\n"
+                ),
+                &hostname
+            )
+            .into_bytes(),
+        );
+
+        loop {
+            tokio::select! {
+                stream = listener.accept() => {
+                    match stream {
+                        Ok((mut stream, peer_addr)) => {
+                            let shutdown_rx = shutdown_rx.clone();
+                            let core = core.clone();
+                            let greeting = greeting.clone();
+                            let tls_acceptor = tls_acceptor.clone();
+                            let hostname = hostname.clone();
+
+                            tokio::spawn(async move {
+                                if tls_only {
+                                    let mut stream = match tls_acceptor.as_ref().unwrap().accept(stream).await {
+                                        Ok(stream) => stream,
+                                        Err(e) => {
+                                            debug!("Failed to accept TLS connection: {}", e);
+                                            return;
+                                        }
+                                    };
+
+                                    // Send greeting
+                                    if let Err(err) = stream.write_all(&greeting).await {
+                                        debug!("Failed to send greeting to {}: {}", peer_addr, err);
+                                        return;
+                                    }
+
+                                    handle_conn(
+                                        Session::new(core, peer_addr, stream.into(), None, hostname),
+                                        shutdown_rx
+                                    ).await;
+                                } else {
+                                    // Send greeting
+                                    if let Err(err) = stream.write_all(&greeting).await {
+                                        debug!("Failed to send greeting to {}: {}", peer_addr, err);
+                                        return;
+                                    }
+
+                                    handle_conn(
+                                        Session::new(core, peer_addr, stream.into(), tls_acceptor, hostname),
+                                        shutdown_rx
+                                    ).await;
+                                }
+                            });
+                        }
+                        Err(err) => {
+                            error!("Failed to accept TCP connection: {}", err);
+                        }
+                    }
+                },
+                _ = shutdown_rx.changed() => {
+                    debug!("POP3 listener shutting down.");
+                    break;
+                }
+            };
+        }
+    });
+}
+
+pub async fn handle_conn<T>(mut session: Session<T>, mut shutdown_rx: watch::Receiver<bool>)
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    let mut buf = vec![0; 4096];
+
+    loop {
+        tokio::select! {
+            result = tokio::time::timeout(
+                TIMEOUT,
+                session.read_bytes(&mut buf)) => {
+                match result {
+                    Ok(Ok(bytes_read)) => {
+                        if bytes_read > 0 {
+                            if session.ingest(&buf[..bytes_read]).await.is_err() {
+                                debug!("Disconnecting client.");
+                                return;
+                            }
+                        } else {
+                            debug!("POP3 connection closed by {}", session.peer_addr);
+                            break;
+                        }
+                    },
+                    Ok(Err(_)) => {
+                        break;
+                    },
+                    Err(_) => {
+                        session.write_bytes(b"-ERR Disconnecting inactive client.\r\n").await.ok();
+                        debug!("POP3 connection timed out with {}.", session.peer_addr);
+                        break;
+                    }
+                }
+            },
+            _ = shutdown_rx.changed() => {
+                session.write_bytes(b"-ERR Server shutting down.\r\n").await.ok();
+                debug!("POP3 connection with peer {} shutting down.", session.peer_addr);
+                return;
+            }
+        };
+    }
+}
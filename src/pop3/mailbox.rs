@@ -0,0 +1,384 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap::types::{blob::JMAPBlob, jmap::JMAPId};
+use jmap_mail::mail::get::{BlobResult, JMAPGetMail};
+use serde_json::{json, Value};
+use store::{ahash::AHashMap, Store};
+
+use crate::api::request::execute_internal_calls;
+
+use super::session::Session;
+
+#[derive(Debug, Default)]
+pub struct Mailbox {
+    pub messages: Vec<Message>,
+}
+
+#[derive(Debug)]
+pub struct Message {
+    pub id: store::JMAPId,
+    pub blob_id: String,
+    pub size: usize,
+    pub is_deleted: bool,
+}
+
+impl Mailbox {
+    pub fn get(&self, msg: u32) -> Option<&Message> {
+        self.messages
+            .get((msg as usize).checked_sub(1)?)
+            .filter(|message| !message.is_deleted)
+    }
+
+    pub fn get_mut(&mut self, msg: u32) -> Option<&mut Message> {
+        self.messages
+            .get_mut((msg as usize).checked_sub(1)?)
+            .filter(|message| !message.is_deleted)
+    }
+
+    pub fn total(&self) -> (usize, usize) {
+        self.messages
+            .iter()
+            .filter(|message| !message.is_deleted)
+            .fold((0, 0), |(count, size), message| {
+                (count + 1, size + message.size)
+            })
+    }
+}
+
+impl<T> Session<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    /// Loads the messages in the Inbox, ordered by arrival date. Messages flagged
+    /// as \Deleted by other clients are not part of the maildrop.
+    pub async fn load_mailbox(&self) -> Result<Mailbox, String> {
+        let account_id = JMAPId::from(self.account_id());
+        let inbox_id = match self
+            .jmap(vec![(
+                "Mailbox/query",
+                json!({
+                    "accountId": account_id,
+                    "filter": { "role": "inbox" },
+                }),
+            )])
+            .await?
+            .first()
+            .and_then(|response| response.get("ids"))
+            .and_then(|ids| ids.get(0))
+            .and_then(|id| id.as_str())
+        {
+            Some(inbox_id) => inbox_id.to_string(),
+            None => return Ok(Mailbox::default()),
+        };
+
+        // Obtain the ids of the messages in the Inbox
+        let limit = self.core.store.config.query_max_results;
+        let mut ids = Vec::new();
+        loop {
+            let response = self
+                .jmap(vec![(
+                    "Email/query",
+                    json!({
+                        "accountId": account_id,
+                        "filter": { "inMailbox": inbox_id },
+                        "sort": [{ "property": "receivedAt", "isAscending": true }],
+                        "position": ids.len(),
+                        "limit": limit,
+                    }),
+                )])
+                .await?;
+            let count = match response.first().and_then(|r| r.get("ids")) {
+                Some(Value::Array(result)) => {
+                    ids.extend(result.iter().cloned());
+                    result.len()
+                }
+                _ => break,
+            };
+            if count < limit {
+                break;
+            }
+        }
+
+        // Fetch the size and blob id of each message
+        let mut messages = Vec::with_capacity(ids.len());
+        for chunk in ids.chunks(self.core.store.config.max_objects_in_get) {
+            for response in self
+                .jmap(vec![(
+                    "Email/get",
+                    json!({
+                        "accountId": account_id,
+                        "ids": chunk,
+                        "properties": ["blobId", "size", "keywords"],
+                    }),
+                )])
+                .await?
+            {
+                if let Some(Value::Array(list)) = response.get("list") {
+                    for item in list {
+                        if item
+                            .get("keywords")
+                            .and_then(|k| k.get("$deleted"))
+                            .is_some()
+                        {
+                            continue;
+                        }
+                        if let (Some(id), Some(blob_id), Some(size)) = (
+                            item.get("id")
+                                .and_then(|id| id.as_str())
+                                .and_then(JMAPId::parse),
+                            item.get("blobId").and_then(|id| id.as_str()),
+                            item.get("size").and_then(|size| size.as_u64()),
+                        ) {
+                            messages.push((
+                                id,
+                                Message {
+                                    id: id.into(),
+                                    blob_id: blob_id.to_string(),
+                                    size: size as usize,
+                                    is_deleted: false,
+                                },
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+
+        // Email/get does not preserve the order of the requested ids
+        let mut messages = messages.into_iter().collect::<AHashMap<_, _>>();
+        Ok(Mailbox {
+            messages: ids
+                .iter()
+                .filter_map(|id| messages.remove(&id.as_str().and_then(JMAPId::parse)?))
+                .collect(),
+        })
+    }
+
+    /// Removes the messages marked for deletion from the Inbox. Messages that
+    /// also belong to other mailboxes are only unlinked from the Inbox.
+    pub async fn expunge_mailbox(&self) -> Result<usize, String> {
+        let deleted = self
+            .mailbox
+            .messages
+            .iter()
+            .filter(|message| message.is_deleted)
+            .map(|message| JMAPId::new(message.id))
+            .collect::<Vec<_>>();
+        if deleted.is_empty() {
+            return Ok(0);
+        }
+
+        let account_id = JMAPId::from(self.account_id());
+        let mut removed = 0;
+        for chunk in deleted.chunks(self.core.store.config.max_objects_in_set) {
+            let response = self
+                .jmap(vec![
+                    (
+                        "Mailbox/query",
+                        json!({
+                            "accountId": account_id,
+                            "filter": { "role": "inbox" },
+                        }),
+                    ),
+                    (
+                        "Email/get",
+                        json!({
+                            "accountId": account_id,
+                            "ids": chunk,
+                            "properties": ["mailboxIds"],
+                        }),
+                    ),
+                ])
+                .await?;
+            let inbox_id = response
+                .first()
+                .and_then(|response| response.get("ids"))
+                .and_then(|ids| ids.get(0))
+                .and_then(|id| id.as_str())
+                .ok_or_else(|| "Inbox not found".to_string())?;
+
+            let mut update = serde_json::Map::new();
+            let mut destroy = Vec::new();
+            if let Some(Value::Array(list)) = response.get(1).and_then(|r| r.get("list")) {
+                for item in list {
+                    let (id, mailbox_ids) = match (
+                        item.get("id").and_then(|id| id.as_str()),
+                        item.get("mailboxIds").and_then(|ids| ids.as_object()),
+                    ) {
+                        (Some(id), Some(mailbox_ids)) => (id, mailbox_ids),
+                        _ => continue,
+                    };
+                    if !mailbox_ids.contains_key(inbox_id) {
+                        continue;
+                    } else if mailbox_ids.len() > 1 {
+                        update.insert(
+                            id.to_string(),
+                            json!({ format!("mailboxIds/{}", inbox_id): Value::Null }),
+                        );
+                    } else {
+                        destroy.push(id.to_string());
+                    }
+                }
+            }
+
+            let response = self
+                .jmap(vec![(
+                    "Email/set",
+                    json!({
+                        "accountId": account_id,
+                        "update": update,
+                        "destroy": destroy,
+                    }),
+                )])
+                .await?;
+            if let Some(response) = response.first() {
+                removed += response
+                    .get("updated")
+                    .and_then(|ids| ids.as_object())
+                    .map_or(0, |ids| ids.len())
+                    + response
+                        .get("destroyed")
+                        .and_then(|ids| ids.as_array())
+                        .map_or(0, |ids| ids.len());
+            }
+        }
+
+        Ok(removed)
+    }
+
+    /// Fetches the raw message from the local blob store.
+    pub async fn get_message(&self, blob_id: &str) -> Result<Option<Vec<u8>>, String> {
+        let blob = if let Some(blob) = JMAPBlob::parse(blob_id) {
+            blob
+        } else {
+            return Ok(None);
+        };
+        let account = self.account.as_ref().unwrap();
+        let (account_id, acl) = (account.account_id, account.acl.clone());
+        let store = self.core.store.clone();
+
+        match self
+            .core
+            .spawn_worker(move || store.mail_blob_get(account_id, &acl, &blob))
+            .await
+            .map_err(|err| err.to_string())?
+        {
+            BlobResult::Blob(bytes) => Ok(Some(bytes)),
+            BlobResult::NotFound | BlobResult::Unauthorized => Ok(None),
+        }
+    }
+
+    async fn jmap(&self, calls: Vec<(&str, Value)>) -> Result<Vec<Value>, String> {
+        execute_internal_calls(&self.core, self.account_id(), calls, Vec::new())
+            .await
+            .map_err(|err| err.to_string())
+    }
+}
+
+/// Writes a message as a multi-line response, byte-stuffing lines that begin
+/// with a termination octet. When `max_body_lines` is set, only the headers and
+/// the first lines of the body are written.
+pub fn write_message(buf: &mut Vec<u8>, message: &[u8], max_body_lines: Option<usize>) {
+    let mut in_body = false;
+    let mut body_lines = 0;
+    let mut lines = message.split(|&ch| ch == b'\n').peekable();
+
+    while let Some(line) = lines.next() {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.is_empty() && lines.peek().is_none() {
+            break;
+        }
+        if in_body {
+            if let Some(max_body_lines) = max_body_lines {
+                if body_lines == max_body_lines {
+                    break;
+                }
+                body_lines += 1;
+            }
+        } else if line.is_empty() {
+            in_body = true;
+        }
+        if line.first() == Some(&b'.') {
+            buf.push(b'.');
+        }
+        buf.extend_from_slice(line);
+        buf.extend_from_slice(b"\r\n");
+    }
+    buf.extend_from_slice(b".\r\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::write_message;
+
+    #[test]
+    fn pop3_write_message() {
+        let message = concat!(
+            "Subject: test\r\n",
+            "From: jdoe@example.com\n",
+            "\r\n",
+            "line 1\r\n",
+            ".line 2\r\n",
+            "line 3"
+        );
+        for (max_body_lines, expected_result) in [
+            (
+                None,
+                concat!(
+                    "Subject: test\r\n",
+                    "From: jdoe@example.com\r\n",
+                    "\r\n",
+                    "line 1\r\n",
+                    "..line 2\r\n",
+                    "line 3\r\n",
+                    ".\r\n"
+                ),
+            ),
+            (
+                Some(0),
+                concat!(
+                    "Subject: test\r\n",
+                    "From: jdoe@example.com\r\n",
+                    "\r\n",
+                    ".\r\n"
+                ),
+            ),
+            (
+                Some(2),
+                concat!(
+                    "Subject: test\r\n",
+                    "From: jdoe@example.com\r\n",
+                    "\r\n",
+                    "line 1\r\n",
+                    "..line 2\r\n",
+                    ".\r\n"
+                ),
+            ),
+        ] {
+            let mut buf = Vec::new();
+            write_message(&mut buf, message.as_bytes(), max_body_lines);
+            assert_eq!(String::from_utf8(buf).unwrap(), expected_result);
+        }
+    }
+}
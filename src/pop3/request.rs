@@ -0,0 +1,220 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{borrow::Cow, slice::Iter};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    User {
+        name: String,
+    },
+    Pass {
+        secret: String,
+    },
+    Auth {
+        mechanism: String,
+        initial_response: Option<String>,
+    },
+    Stls,
+    Capa,
+    Stat,
+    List {
+        msg: Option<u32>,
+    },
+    Uidl {
+        msg: Option<u32>,
+    },
+    Retr {
+        msg: u32,
+    },
+    Top {
+        msg: u32,
+        lines: u32,
+    },
+    Dele {
+        msg: u32,
+    },
+    Noop,
+    Rset,
+    Quit,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    Line(Vec<u8>),
+    NeedsMoreBytes,
+    LineTooLong,
+}
+
+pub struct RequestParser {
+    pub buf: Vec<u8>,
+    pub max_line_size: usize,
+    is_overflow: bool,
+}
+
+impl RequestParser {
+    pub fn new(max_line_size: usize) -> Self {
+        RequestParser {
+            buf: Vec::with_capacity(50),
+            max_line_size,
+            is_overflow: false,
+        }
+    }
+
+    /// Returns the next line received, without its line terminator.
+    pub fn parse(&mut self, bytes: &mut Iter<'_, u8>) -> Event {
+        for &ch in bytes {
+            match ch {
+                b'\n' => {
+                    if self.buf.last() == Some(&b'\r') {
+                        self.buf.pop();
+                    }
+                    let line = std::mem::replace(&mut self.buf, Vec::with_capacity(50));
+                    return if !std::mem::take(&mut self.is_overflow) {
+                        Event::Line(line)
+                    } else {
+                        Event::LineTooLong
+                    };
+                }
+                _ if self.buf.len() < self.max_line_size => {
+                    self.buf.push(ch);
+                }
+                _ => {
+                    self.is_overflow = true;
+                }
+            }
+        }
+
+        Event::NeedsMoreBytes
+    }
+}
+
+impl Request {
+    pub fn parse(line: &[u8]) -> Result<Request, Cow<'static, str>> {
+        let line = std::str::from_utf8(line).map_err(|_| "Invalid UTF-8 in command.")?;
+        let (command, args) = line.split_once(' ').unwrap_or((line, ""));
+        let mut args = args.split(' ').filter(|arg| !arg.is_empty());
+
+        match command.to_ascii_uppercase().as_str() {
+            "USER" => Ok(Request::User {
+                name: args.next().ok_or("Missing user name.")?.to_string(),
+            }),
+            "PASS" => {
+                // Passwords may contain spaces
+                let secret = line[command.len()..].trim_start_matches(' ');
+                if !secret.is_empty() {
+                    Ok(Request::Pass {
+                        secret: secret.to_string(),
+                    })
+                } else {
+                    Err("Missing password.".into())
+                }
+            }
+            "AUTH" => Ok(Request::Auth {
+                mechanism: args
+                    .next()
+                    .ok_or("Missing authentication mechanism.")?
+                    .to_ascii_uppercase(),
+                initial_response: args.next().map(|arg| arg.to_string()),
+            }),
+            "STLS" => Ok(Request::Stls),
+            "CAPA" => Ok(Request::Capa),
+            "STAT" => Ok(Request::Stat),
+            "LIST" => Ok(Request::List {
+                msg: args.next().map(parse_number).transpose()?,
+            }),
+            "UIDL" => Ok(Request::Uidl {
+                msg: args.next().map(parse_number).transpose()?,
+            }),
+            "RETR" => Ok(Request::Retr {
+                msg: parse_number(args.next().ok_or("Missing message number.")?)?,
+            }),
+            "TOP" => Ok(Request::Top {
+                msg: parse_number(args.next().ok_or("Missing message number.")?)?,
+                lines: parse_number(args.next().ok_or("Missing number of lines.")?)?,
+            }),
+            "DELE" => Ok(Request::Dele {
+                msg: parse_number(args.next().ok_or("Missing message number.")?)?,
+            }),
+            "NOOP" => Ok(Request::Noop),
+            "RSET" => Ok(Request::Rset),
+            "QUIT" => Ok(Request::Quit),
+            _ => Err(format!("Unknown command '{}'.", command).into()),
+        }
+    }
+}
+
+fn parse_number(arg: &str) -> Result<u32, Cow<'static, str>> {
+    arg.parse::<u32>()
+        .map_err(|_| format!("Invalid number '{}'.", arg).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Event, Request, RequestParser};
+
+    #[test]
+    fn pop3_parser() {
+        let mut parser = RequestParser::new(32);
+        let mut events = Vec::new();
+        for chunk in [
+            "USER jdoe\r\nPASS secret with spaces\r\n",
+            "LI",
+            "ST 2\r\nuidl\r\nTOP 1 10\r\n",
+            "AUTH PLAIN AGpkb2UAMTIzNDU=\r\n",
+            "RETR 123456789012345678901234567890\r\n",
+            "DELE\r\nQUIT\n",
+        ] {
+            let mut bytes = chunk.as_bytes().iter();
+            loop {
+                match parser.parse(&mut bytes) {
+                    Event::Line(line) => events.push(Request::parse(&line)),
+                    Event::NeedsMoreBytes => break,
+                    Event::LineTooLong => events.push(Err("Line too long.".into())),
+                }
+            }
+        }
+
+        assert_eq!(
+            events,
+            vec![
+                Ok(Request::User {
+                    name: "jdoe".to_string()
+                }),
+                Ok(Request::Pass {
+                    secret: "secret with spaces".to_string()
+                }),
+                Ok(Request::List { msg: Some(2) }),
+                Ok(Request::Uidl { msg: None }),
+                Ok(Request::Top { msg: 1, lines: 10 }),
+                Ok(Request::Auth {
+                    mechanism: "PLAIN".to_string(),
+                    initial_response: Some("AGpkb2UAMTIzNDU=".to_string())
+                }),
+                Err("Line too long.".into()),
+                Err("Missing message number.".into()),
+                Ok(Request::Quit),
+            ]
+        );
+    }
+}
@@ -0,0 +1,471 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{net::SocketAddr, sync::Arc};
+
+use actix_web::web;
+use jmap::base64;
+use jmap_sharing::principal::account::JMAPAccountStore;
+use store::{
+    core::acl::ACLToken,
+    tracing::{debug, error},
+    AccountId, Store,
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_rustls::TlsAcceptor;
+
+use crate::{authorization::auth::RemoteAddress, lmtp::session::Stream, JMAPServer};
+
+use super::{
+    mailbox::{write_message, Mailbox},
+    request::{Event, Request, RequestParser},
+};
+
+const MAX_LINE_LENGTH: usize = 4096;
+
+pub struct Session<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    pub core: web::Data<JMAPServer<T>>,
+    pub tls_acceptor: Option<Arc<TlsAcceptor>>,
+    pub hostname: Arc<String>,
+    pub parser: RequestParser,
+    pub peer_addr: SocketAddr,
+    pub stream: Stream,
+
+    // State
+    pub user: Option<String>,
+    pub account: Option<Account>,
+    pub mailbox: Mailbox,
+    pub pending_auth: bool,
+}
+
+pub struct Account {
+    pub account_id: AccountId,
+    pub acl: Arc<ACLToken>,
+}
+
+impl<T> Session<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    pub fn new(
+        core: web::Data<JMAPServer<T>>,
+        peer_addr: SocketAddr,
+        stream: Stream,
+        tls_acceptor: Option<Arc<TlsAcceptor>>,
+        hostname: Arc<String>,
+    ) -> Self {
+        Self {
+            parser: RequestParser::new(MAX_LINE_LENGTH),
+            tls_acceptor,
+            peer_addr,
+            stream,
+            core,
+            hostname,
+            user: None,
+            account: None,
+            mailbox: Mailbox::default(),
+            pending_auth: false,
+        }
+    }
+
+    pub async fn ingest(&mut self, bytes: &[u8]) -> Result<(), ()> {
+        let mut bytes = bytes.iter();
+
+        loop {
+            match self.parser.parse(&mut bytes) {
+                Event::Line(line) if self.pending_auth => {
+                    self.pending_auth = false;
+                    if line != b"*" {
+                        self.authenticate_plain(&line).await?;
+                    } else {
+                        self.write_err("Authentication cancelled.").await?;
+                    }
+                }
+                Event::Line(line) => match Request::parse(&line) {
+                    Ok(request) => {
+                        let is_stls = request == Request::Stls && !self.stream.is_tls();
+                        self.handle_request(request).await?;
+                        if is_stls && self.stream.is_tls() {
+                            // Discard any plaintext commands pipelined after STLS
+                            return Ok(());
+                        }
+                    }
+                    Err(message) => {
+                        self.write_err(&message).await?;
+                    }
+                },
+                Event::LineTooLong => {
+                    self.write_err("Line too long.").await?;
+                }
+                Event::NeedsMoreBytes => {
+                    break;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn handle_request(&mut self, request: Request) -> Result<(), ()> {
+        if self.account.is_none() {
+            match request {
+                Request::User { .. } | Request::Pass { .. } | Request::Auth { .. }
+                    if self.is_tls_required() =>
+                {
+                    self.write_err("Use STLS before sending credentials.").await
+                }
+                Request::User { name } => {
+                    self.user = name.into();
+                    self.write_ok("Send your password.").await
+                }
+                Request::Pass { secret } => {
+                    if let Some(login) = self.user.take() {
+                        self.authenticate(login, secret).await
+                    } else {
+                        self.write_err("Missing USER command.").await
+                    }
+                }
+                Request::Auth {
+                    mechanism,
+                    initial_response,
+                } => {
+                    if mechanism != "PLAIN" {
+                        self.write_err("Authentication mechanism not supported.")
+                            .await
+                    } else if let Some(initial_response) = initial_response {
+                        self.authenticate_plain(initial_response.as_bytes()).await
+                    } else {
+                        self.pending_auth = true;
+                        self.write_bytes(b"+ \r\n").await
+                    }
+                }
+                Request::Stls => self.handle_stls().await,
+                Request::Capa => self.handle_capa().await,
+                Request::Quit => {
+                    self.write_ok("Stalwart POP3 bids you farewell.").await?;
+                    Err(())
+                }
+                _ => self.write_err("Not authenticated.").await,
+            }
+        } else {
+            match request {
+                Request::Stat => {
+                    let (count, size) = self.mailbox.total();
+                    self.write_ok(&format!("{} {}", count, size)).await
+                }
+                Request::List { msg } | Request::Uidl { msg } if msg.is_some() => {
+                    let is_uidl = matches!(request, Request::Uidl { .. });
+                    let msg = msg.unwrap();
+                    if let Some(message) = self.mailbox.get(msg) {
+                        let response = if is_uidl {
+                            format!("{} {}", msg, message.blob_id)
+                        } else {
+                            format!("{} {}", msg, message.size)
+                        };
+                        self.write_ok(&response).await
+                    } else {
+                        self.write_err("No such message.").await
+                    }
+                }
+                Request::List { .. } | Request::Uidl { .. } => {
+                    let is_uidl = matches!(request, Request::Uidl { .. });
+                    let mut buf = Vec::with_capacity(self.mailbox.messages.len() * 16);
+                    buf.extend_from_slice(if is_uidl {
+                        b"+OK Unique-ID listing follows.\r\n"
+                    } else {
+                        b"+OK Scan listing follows.\r\n"
+                    });
+                    for (pos, message) in self.mailbox.messages.iter().enumerate() {
+                        if !message.is_deleted {
+                            buf.extend_from_slice(
+                                if is_uidl {
+                                    format!("{} {}\r\n", pos + 1, message.blob_id)
+                                } else {
+                                    format!("{} {}\r\n", pos + 1, message.size)
+                                }
+                                .as_bytes(),
+                            );
+                        }
+                    }
+                    buf.extend_from_slice(b".\r\n");
+                    self.write_bytes(&buf).await
+                }
+                Request::Retr { msg } => self.handle_retr(msg, None).await,
+                Request::Top { msg, lines } => self.handle_retr(msg, Some(lines as usize)).await,
+                Request::Dele { msg } => {
+                    if let Some(message) = self.mailbox.get_mut(msg) {
+                        message.is_deleted = true;
+                        self.write_ok("Message marked for deletion.").await
+                    } else {
+                        self.write_err("No such message.").await
+                    }
+                }
+                Request::Noop => self.write_ok("").await,
+                Request::Rset => {
+                    for message in &mut self.mailbox.messages {
+                        message.is_deleted = false;
+                    }
+                    let (count, size) = self.mailbox.total();
+                    self.write_ok(&format!(
+                        "Maildrop has {} messages ({} octets).",
+                        count, size
+                    ))
+                    .await
+                }
+                Request::Capa => self.handle_capa().await,
+                Request::Quit => {
+                    // Enter the UPDATE state
+                    match self.expunge_mailbox().await {
+                        Ok(removed) => {
+                            self.write_ok(&format!(
+                                "Stalwart POP3 bids you farewell ({} messages removed).",
+                                removed
+                            ))
+                            .await?;
+                        }
+                        Err(err) => {
+                            error!("Failed to expunge mailbox: {}", err);
+                            self.write_err("[SYS/TEMP] Some deleted messages not removed.")
+                                .await?;
+                        }
+                    }
+                    Err(())
+                }
+                Request::User { .. }
+                | Request::Pass { .. }
+                | Request::Auth { .. }
+                | Request::Stls => self.write_err("Already authenticated.").await,
+            }
+        }
+    }
+
+    async fn handle_retr(&mut self, msg: u32, max_body_lines: Option<usize>) -> Result<(), ()> {
+        let (blob_id, size) = match self.mailbox.get(msg) {
+            Some(message) => (message.blob_id.clone(), message.size),
+            None => return self.write_err("No such message.").await,
+        };
+        match self.get_message(&blob_id).await {
+            Ok(Some(bytes)) => {
+                let mut buf = Vec::with_capacity(bytes.len() + 64);
+                if max_body_lines.is_none() {
+                    buf.extend_from_slice(format!("+OK {} octets\r\n", size).as_bytes());
+                } else {
+                    buf.extend_from_slice(b"+OK Top of message follows.\r\n");
+                }
+                write_message(&mut buf, &bytes, max_body_lines);
+                self.write_bytes(&buf).await
+            }
+            Ok(None) => self.write_err("Message no longer exists.").await,
+            Err(err) => {
+                error!("Failed to fetch message: {}", err);
+                self.write_err("[SYS/TEMP] Temporary server failure.").await
+            }
+        }
+    }
+
+    async fn handle_capa(&mut self) -> Result<(), ()> {
+        let mut capabilities = String::from(concat!(
+            "+OK Capability list follows.\r\n",
+            "TOP\r\n",
+            "UIDL\r\n",
+            "RESP-CODES\r\n",
+            "AUTH-RESP-CODE\r\n",
+            "PIPELINING\r\n",
+            "EXPIRE NEVER\r\n",
+        ));
+        if self.account.is_none() {
+            if self.is_tls_required() {
+                capabilities.push_str("STLS\r\n");
+            } else {
+                capabilities.push_str("USER\r\nSASL PLAIN\r\n");
+            }
+        }
+        capabilities.push_str(concat!(
+            "IMPLEMENTATION Stalwart-JMAP-v",
+            env!("CARGO_PKG_VERSION"),
+            "\r\n.\r\n"
+        ));
+        self.write_bytes(capabilities.as_bytes()).await
+    }
+
+    // Credentials are not accepted in cleartext when STLS is available
+    fn is_tls_required(&self) -> bool {
+        !self.stream.is_tls() && self.tls_acceptor.is_some()
+    }
+
+    async fn handle_stls(&mut self) -> Result<(), ()> {
+        match (&self.stream, &self.tls_acceptor) {
+            (Stream::Clear(_), Some(_)) => {
+                self.write_ok("Begin TLS negotiation now.").await?;
+                match self
+                    .tls_acceptor
+                    .as_ref()
+                    .unwrap()
+                    .accept(std::mem::take(&mut self.stream).unwrap_clear())
+                    .await
+                {
+                    Ok(stream) => {
+                        self.stream = stream.into();
+                        self.user = None;
+                        Ok(())
+                    }
+                    Err(e) => {
+                        debug!("Failed to accept TLS connection: {}", e);
+                        Err(())
+                    }
+                }
+            }
+            (Stream::Clear(_), None) => self.write_err("TLS not configured on this server.").await,
+            (Stream::Tls(_), _) => self.write_err("Already in TLS mode.").await,
+            (_, _) => {
+                unreachable!()
+            }
+        }
+    }
+
+    async fn authenticate_plain(&mut self, response: &[u8]) -> Result<(), ()> {
+        // authzid NUL authcid NUL passwd
+        let response = match base64::decode(response) {
+            Ok(response) => response,
+            Err(_) => return self.write_err("Failed to decode SASL response.").await,
+        };
+        let mut parts = response.split(|&ch| ch == 0);
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(_), Some(login), Some(secret)) => {
+                self.authenticate(
+                    String::from_utf8_lossy(login).into_owned(),
+                    String::from_utf8_lossy(secret).into_owned(),
+                )
+                .await
+            }
+            _ => self.write_err("Invalid SASL PLAIN response.").await,
+        }
+    }
+
+    async fn authenticate(&mut self, login: String, secret: String) -> Result<(), ()> {
+        // Enforce rate limit for authentication requests
+        if self
+            .core
+            .is_auth_allowed(RemoteAddress::IpAddress(self.peer_addr.ip()))
+            .await
+            .is_err()
+        {
+            return self
+                .write_err("[SYS/TEMP] Too many authentication attempts, please try again later.")
+                .await;
+        }
+
+        let result = match self
+            .core
+            .authenticate_client(login.trim().to_lowercase(), secret, None)
+            .await
+        {
+            Ok(Some((account_id, _))) => {
+                let store = self.core.store.clone();
+                self.core
+                    .spawn_worker(move || store.get_acl_token(account_id))
+                    .await
+                    .map(|acl| Some((account_id, acl)))
+            }
+            result => result.map(|_| None),
+        };
+
+        match result {
+            Ok(Some((account_id, acl))) => {
+                self.account = Account { account_id, acl }.into();
+                match self.load_mailbox().await {
+                    Ok(mailbox) => {
+                        self.mailbox = mailbox;
+                        let (count, size) = self.mailbox.total();
+                        self.write_ok(&format!(
+                            "Maildrop has {} messages ({} octets).",
+                            count, size
+                        ))
+                        .await
+                    }
+                    Err(err) => {
+                        error!("Failed to load mailbox: {}", err);
+                        self.account = None;
+                        self.write_err("[SYS/TEMP] Unable to open maildrop.").await
+                    }
+                }
+            }
+            Ok(None) => self.write_err("[AUTH] Authentication failed.").await,
+            Err(err) => {
+                error!("Failed to authenticate: {}", err);
+                self.write_err("[SYS/TEMP] Temporary server failure.").await
+            }
+        }
+    }
+
+    pub fn account_id(&self) -> AccountId {
+        self.account
+            .as_ref()
+            .map(|a| a.account_id)
+            .unwrap_or_default()
+    }
+
+    async fn write_ok(&mut self, message: &str) -> Result<(), ()> {
+        self.write_bytes(
+            if !message.is_empty() {
+                format!("+OK {}\r\n", message)
+            } else {
+                "+OK\r\n".to_string()
+            }
+            .as_bytes(),
+        )
+        .await
+    }
+
+    async fn write_err(&mut self, message: &str) -> Result<(), ()> {
+        self.write_bytes(format!("-ERR {}\r\n", message).as_bytes())
+            .await
+    }
+
+    pub async fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), ()> {
+        match &mut self.stream {
+            Stream::Clear(stream) => stream.write_all(bytes).await.map_err(|err| {
+                debug!("Failed to write to stream: {}", err);
+            }),
+            Stream::Tls(stream) => stream.write_all(bytes).await.map_err(|err| {
+                debug!("Failed to write to TLS stream: {}", err);
+            }),
+            _ => unreachable!(),
+        }
+    }
+
+    pub async fn read_bytes(&mut self, bytes: &mut [u8]) -> Result<usize, ()> {
+        match &mut self.stream {
+            Stream::Clear(stream) => stream.read(bytes).await.map_err(|err| {
+                debug!("Failed to read from stream: {}", err);
+            }),
+            Stream::Tls(stream) => stream.read(bytes).await.map_err(|err| {
+                debug!("Failed to read from TLS stream: {}", err);
+            }),
+            _ => unreachable!(),
+        }
+    }
+}
@@ -54,20 +54,33 @@ use crate::{
     },
     authorization::{
         auth::SessionFactory,
+        credentials::{
+            handle_app_password_create, handle_app_password_list, handle_app_password_revoke,
+            handle_totp_disable, handle_totp_enroll, handle_totp_verify,
+        },
         oauth::{
-            handle_device_auth, handle_oauth_metadata, handle_token_request, handle_user_code_auth,
-            handle_user_code_auth_post, handle_user_device_auth, handle_user_device_auth_post,
-            OAuth, OAuthMetadata,
+            handle_device_auth, handle_grant_list, handle_grant_revoke, handle_oauth_metadata,
+            handle_token_introspect, handle_token_request, handle_token_revoke,
+            handle_user_code_auth, handle_user_code_auth_post, handle_user_device_auth,
+            handle_user_device_auth_post, OAuth, OAuthMetadata,
+        },
+        oidc::{
+            handle_jwks, handle_oidc_metadata, handle_userinfo, JsonWebKeySet, OpenIdMetadata,
+            OpenIdProvider, SigningKey,
         },
+        upstream::{handle_upstream_callback, UpstreamProvider},
     },
     cluster::{rpc::tls::load_tls_server_config, ClusterIpc},
+    imap::listener::{init_imap, spawn_imap},
     lmtp::listener::{init_lmtp, spawn_lmtp},
+    pop3::listener::{init_pop3, spawn_pop3},
     server::{event_source::handle_jmap_event_source, websocket::handle_ws},
     services::{
         email_delivery::{init_email_delivery, spawn_email_delivery},
         housekeeper::{init_housekeeper, spawn_housekeeper},
         state_change::{init_state_manager, spawn_state_manager},
     },
+    smtp::listener::{init_smtp, spawn_smtp},
     JMAPServer, DEFAULT_HTTP_PORT,
 };
 
@@ -146,8 +159,35 @@ where
     let (housekeeper_tx, housekeeper_rx) = init_housekeeper();
     let (change_tx, change_rx) = init_state_manager();
     let (lmtp_tx, lmtp_rx) = init_lmtp();
+    let (imap_tx, imap_rx) = init_imap();
+    let (pop3_tx, pop3_rx) = init_pop3();
+    let (smtp_tx, smtp_rx) = init_smtp();
     let is_in_cluster = cluster.is_some();
 
+    // Load OpenID Connect signing key, which has to be the same on all servers
+    let oidc = if let Some(key_path) = settings.get("oidc-signing-key-path") {
+        let signing_key = SigningKey::from_pem(
+            &std::fs::read_to_string(&key_path).failed_to("read OpenID Connect signing key"),
+        )
+        .failed_to("parse OpenID Connect signing key");
+        OpenIdProvider {
+            metadata: serde_json::to_string(&OpenIdMetadata::new(
+                base_session.base_url(),
+                &signing_key,
+            ))
+            .failed_to("serialize OpenID Connect metadata"),
+            jwks: serde_json::to_string(&JsonWebKeySet {
+                keys: vec![signing_key.jwk()],
+            })
+            .failed_to("serialize JSON Web Key Set"),
+            signing_key,
+        }
+        .into()
+    } else {
+        info!("No 'oidc-signing-key-path' specified, OpenID Connect is disabled.");
+        None
+    };
+
     // Load OAuth settings
     let oauth = Box::new(OAuth {
         key: settings.get("encryption-key").unwrap_or_else(|| {
@@ -167,8 +207,13 @@ where
             .parse("oauth-refresh-token-renew")
             .unwrap_or(4 * 86400),
         max_auth_attempts: settings.parse("oauth-max-attempts").unwrap_or(3),
-        metadata: serde_json::to_string(&OAuthMetadata::new(base_session.base_url()))
-            .failed_to("serialize OAuth metadata"),
+        metadata: serde_json::to_string(&OAuthMetadata::new(
+            base_session.base_url(),
+            oidc.is_some(),
+        ))
+        .failed_to("serialize OAuth metadata"),
+        oidc,
+        upstream: UpstreamProvider::new(settings),
     });
 
     // Refuse to start with the default key
@@ -195,9 +240,13 @@ where
         email_delivery: email_tx.clone(),
         housekeeper: housekeeper_tx,
         lmtp: lmtp_tx,
+        imap: imap_tx,
+        pop3: pop3_tx,
+        smtp: smtp_tx,
         sessions: Cache::builder()
             .initial_capacity(128)
             .time_to_live(HALF_HOUR_EXPIRY)
+            .support_invalidation_closures()
             .build(),
         rate_limiters: Cache::builder()
             .initial_capacity(128)
@@ -214,6 +263,15 @@ where
     // Spawn LMTP service
     spawn_lmtp(server.clone(), settings, lmtp_rx);
 
+    // Spawn IMAP service
+    spawn_imap(server.clone(), settings, imap_rx);
+
+    // Spawn POP3 service
+    spawn_pop3(server.clone(), settings, pop3_rx);
+
+    // Spawn SMTP submission service
+    spawn_smtp(server.clone(), settings, smtp_rx);
+
     // Spawn TypeState manager
     spawn_state_manager(server.clone(), settings, !is_in_cluster, change_rx);
 
@@ -305,10 +363,46 @@ where
             )
             .route("/auth/device", web::post().to(handle_device_auth::<T>))
             .route("/auth/token", web::post().to(handle_token_request::<T>))
+            .route("/auth/revoke", web::post().to(handle_token_revoke::<T>))
+            .route(
+                "/auth/introspect",
+                web::post().to(handle_token_introspect::<T>),
+            )
+            .route("/auth/grants", web::get().to(handle_grant_list::<T>))
+            .route(
+                "/auth/grants/{id}",
+                web::delete().to(handle_grant_revoke::<T>),
+            )
             .route(
                 "/.well-known/oauth-authorization-server",
                 web::get().to(handle_oauth_metadata::<T>),
             )
+            .route(
+                "/auth/callback",
+                web::get().to(handle_upstream_callback::<T>),
+            )
+            .route(
+                "/auth/app-passwords",
+                web::get().to(handle_app_password_list::<T>),
+            )
+            .route(
+                "/auth/app-passwords",
+                web::post().to(handle_app_password_create::<T>),
+            )
+            .route(
+                "/auth/app-passwords/{id}",
+                web::delete().to(handle_app_password_revoke::<T>),
+            )
+            .route("/auth/totp", web::post().to(handle_totp_enroll::<T>))
+            .route("/auth/totp", web::delete().to(handle_totp_disable::<T>))
+            .route("/auth/totp/verify", web::post().to(handle_totp_verify::<T>))
+            .route("/auth/jwks", web::get().to(handle_jwks::<T>))
+            .route("/auth/userinfo", web::get().to(handle_userinfo::<T>))
+            .route("/auth/userinfo", web::post().to(handle_userinfo::<T>))
+            .route(
+                "/.well-known/openid-configuration",
+                web::get().to(handle_oidc_metadata::<T>),
+            )
     });
     if let Some(tls_config) = tls_config {
         server.bind_rustls(http_addr, tls_config)
@@ -128,6 +128,18 @@ where
             error!("Failed to send shutdown event to LMTP service.");
         }
 
+        if self.imap.send(false).is_err() {
+            error!("Failed to send shutdown event to IMAP service.");
+        }
+
+        if self.pop3.send(false).is_err() {
+            error!("Failed to send shutdown event to POP3 service.");
+        }
+
+        if self.smtp.send(false).is_err() {
+            error!("Failed to send shutdown event to SMTP service.");
+        }
+
         if self
             .state_change
             .send(state_change::Event::Stop)
@@ -21,20 +21,33 @@
  * for more details.
 */
 
-use std::{collections::VecDeque, time::Duration};
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, VecDeque},
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
 use actix_web::web;
 use jmap::{
     orm::{serialize::JMAPOrm, TinyORM},
     types::type_state::TypeState,
+    SUPERUSER_ID,
 };
-use jmap_mail::email_submission::schema::{
-    Delivered, DeliveryStatus, Displayed, EmailSubmission, Property, UndoStatus, Value,
+use jmap_mail::email_submission::{
+    dsn::{build_dsn, DeliveryFailure},
+    queue::JMAPEmailSubmissionQueue,
+    schema::{
+        Address, Delivered, DeliveryQueue, DeliveryStatus, Displayed, EmailSubmission, Envelope,
+        Property, QueuedRecipient, UndoStatus, Value,
+    },
 };
-use jmap_mail::mail_send::{smtp::message::Message, Transport};
+use jmap_mail::mail::import::JMAPMailImport;
+use jmap_mail::INBOX_ID;
 use jmap_sharing::principal::get::JMAPGetPrincipal;
 use store::{
-    ahash::AHashMap,
+    ahash::{AHashMap, AHashSet},
     blob::BlobId,
     config::env_settings::EnvSettings,
     core::{collection::Collection, document::Document},
@@ -42,13 +55,31 @@ use store::{
     write::batch::WriteBatch,
     AccountId, DocumentId, Store,
 };
-use tokio::sync::mpsc;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::mpsc,
+};
+use tokio_rustls::TlsConnector;
+use trust_dns_resolver::{
+    config::{NameServerConfigGroup, ResolverConfig, ResolverOpts},
+    error::{ResolveError, ResolveErrorKind},
+    proto::op::ResponseCode,
+    TokioAsyncResolver,
+};
 
-use crate::{cluster::IPC_CHANNEL_BUFFER, JMAPServer};
+use crate::{
+    cluster::{rpc::tls::load_tls_client_config, IPC_CHANNEL_BUFFER},
+    JMAPServer,
+};
 
-use super::state_change::StateChange;
+use super::{
+    smtp_client::{Reply, SmtpClient},
+    state_change::StateChange,
+};
 
 const DEFAULT_SMTP_TIMEOUT_MS: u64 = 60000;
+const DEFAULT_SMTP_RETRY_INTERVAL: i64 = 60;
+const DEFAULT_SMTP_MAX_AGE: i64 = 5 * 86400;
 
 pub enum Event {
     EmailSubmission {
@@ -56,11 +87,26 @@ pub enum Event {
         created_ids: Vec<DocumentId>,
         updated_ids: Vec<DocumentId>,
     },
+    ScheduleSubmission {
+        account_id: AccountId,
+        document_id: DocumentId,
+        send_at: i64,
+    },
     VacationResponse {
         from: String,
         to: String,
         message: Vec<u8>,
     },
+    Redirect {
+        from: String,
+        to: String,
+        message: Vec<u8>,
+    },
+    InvitationReply {
+        from: String,
+        to: String,
+        message: Vec<u8>,
+    },
     RelayReady,
     Reload,
     Start,
@@ -80,9 +126,36 @@ impl Event {
         }
     }
 
+    pub fn schedule_submission(
+        account_id: AccountId,
+        document_id: DocumentId,
+        send_at: i64,
+    ) -> Self {
+        Event::ScheduleSubmission {
+            account_id,
+            document_id,
+            send_at,
+        }
+    }
+
     pub fn vacation_response(from: String, to: String, message: Vec<u8>) -> Self {
         Event::VacationResponse { from, to, message }
     }
+
+    pub fn redirect(from: String, to: String, message: Vec<u8>) -> Self {
+        Event::Redirect { from, to, message }
+    }
+
+    pub fn invitation_reply(from: String, to: String, message: Vec<u8>) -> Self {
+        Event::InvitationReply { from, to, message }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct ScheduledSubmission {
+    send_at: i64,
+    account_id: AccountId,
+    document_id: DocumentId,
 }
 
 pub fn init_email_delivery() -> (mpsc::Sender<Event>, mpsc::Receiver<Event>) {
@@ -98,17 +171,70 @@ pub fn spawn_email_delivery<T>(
     T: for<'x> Store<'x> + 'static,
 {
     // Parse SMTP relay
-    let relay_tx = if let Some(smtp_relay) = parse_smtp_settings(settings) {
-        spawn_email_relay(core, smtp_relay, tx)
+    let relay_tx = if let Some(smtp_settings) = parse_smtp_settings(settings) {
+        spawn_email_relay(core.clone(), smtp_settings, tx)
     } else {
         return;
     };
 
     tokio::spawn(async move {
         let mut queue = VecDeque::new();
+        let mut scheduled = BinaryHeap::new();
         let mut is_ready = true;
 
-        while let Some(event) = rx.recv().await {
+        // Load scheduled submissions, nodes in a cluster wait until they become leaders
+        if !core.is_in_cluster() {
+            load_scheduled_submissions(&core, &mut scheduled).await;
+        }
+
+        loop {
+            // Wait for the next event or until the next scheduled submission is due
+            let event = if let Some(Reverse(next)) = scheduled.peek() {
+                match tokio::time::timeout(
+                    Duration::from_secs(next.send_at.saturating_sub(unix_timestamp()).max(0) as u64),
+                    rx.recv(),
+                )
+                .await
+                {
+                    Ok(Some(event)) => event,
+                    Ok(None) => break,
+                    Err(_) => {
+                        // Group due submissions by account
+                        let now = unix_timestamp();
+                        let mut due_submissions: AHashMap<AccountId, AHashSet<DocumentId>> =
+                            AHashMap::default();
+                        while matches!(scheduled.peek(), Some(Reverse(next)) if next.send_at <= now)
+                        {
+                            let Reverse(submission) = scheduled.pop().unwrap();
+                            due_submissions
+                                .entry(submission.account_id)
+                                .or_insert_with(AHashSet::default)
+                                .insert(submission.document_id);
+                        }
+
+                        for (account_id, document_ids) in due_submissions {
+                            let event = Event::new_submission(
+                                account_id,
+                                document_ids.into_iter().collect(),
+                                Vec::new(),
+                            );
+                            if is_ready {
+                                if let Err(err) = relay_tx.send(event).await {
+                                    error!("Error sending event to relay: {}", err);
+                                }
+                            } else {
+                                queue.push_back(event);
+                            }
+                        }
+                        continue;
+                    }
+                }
+            } else if let Some(event) = rx.recv().await {
+                event
+            } else {
+                break;
+            };
+
             match event {
                 Event::RelayReady => {
                     if let Some(event) = queue.pop_front() {
@@ -119,13 +245,28 @@ pub fn spawn_email_delivery<T>(
                         is_ready = true;
                     }
                 }
+                Event::ScheduleSubmission {
+                    account_id,
+                    document_id,
+                    send_at,
+                } => {
+                    scheduled.push(Reverse(ScheduledSubmission {
+                        send_at,
+                        account_id,
+                        document_id,
+                    }));
+                }
                 Event::Stop => {
                     if let Err(err) = relay_tx.send(Event::Reload).await {
                         error!("Error sending event to relay: {}", err);
                     }
                     queue.clear();
+                    scheduled.clear();
+                }
+                Event::Start => {
+                    scheduled.clear();
+                    load_scheduled_submissions(&core, &mut scheduled).await;
                 }
-                Event::Start => (),
                 event => {
                     if is_ready {
                         if let Err(err) = relay_tx.send(event).await {
@@ -140,9 +281,43 @@ pub fn spawn_email_delivery<T>(
     });
 }
 
+async fn load_scheduled_submissions<T>(
+    core: &web::Data<JMAPServer<T>>,
+    scheduled: &mut BinaryHeap<Reverse<ScheduledSubmission>>,
+) where
+    T: for<'x> Store<'x> + 'static,
+{
+    let store = core.store.clone();
+    match core
+        .spawn_worker(move || store.email_submission_pending())
+        .await
+    {
+        Ok(pending) => {
+            debug!("Loaded {} pending e-mail submissions.", pending.len());
+            for (account_id, document_id, send_at) in pending {
+                scheduled.push(Reverse(ScheduledSubmission {
+                    send_at,
+                    account_id,
+                    document_id,
+                }));
+            }
+        }
+        Err(err) => {
+            error!("Failed to load pending e-mail submissions: {}", err);
+        }
+    }
+}
+
+fn unix_timestamp() -> i64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0) as i64
+}
+
 fn spawn_email_relay<T>(
     core: web::Data<JMAPServer<T>>,
-    smtp_relay: SMTPRelay,
+    smtp_settings: SMTPSettings,
     queue_tx: mpsc::Sender<Event>,
 ) -> mpsc::Sender<Event>
 where
@@ -150,16 +325,24 @@ where
 {
     let (tx, mut rx) = mpsc::channel::<Event>(IPC_CHANNEL_BUFFER);
     tokio::spawn(async move {
-        // Setup client
-        let mut client = Transport::new(&smtp_relay.hostname).timeout(smtp_relay.timeout);
-        if smtp_relay.port > 0 {
-            client = client.port(smtp_relay.port);
-        }
-        if let Some((username, secret)) = &smtp_relay.credentials {
-            client = client.credentials(username, secret);
-        }
-        let is_tls = smtp_relay.tls;
+        // Setup resolver for direct delivery
+        let resolver = if let SMTPDelivery::Direct { dns_server, .. } = &smtp_settings.delivery {
+            match build_resolver(*dns_server) {
+                Ok(resolver) => resolver.into(),
+                Err(err) => {
+                    error!("Failed to create DNS resolver: {}", err);
+                    return;
+                }
+            }
+        } else {
+            None
+        };
         let mut dkim_map = AHashMap::new();
+        let mut dkim_change_id = None;
+        let reporting_mta = gethostname::gethostname()
+            .to_str()
+            .unwrap_or("localhost")
+            .to_string();
 
         while let Some(event) = rx.recv().await {
             match event {
@@ -174,8 +357,20 @@ where
                     let messages = match core
                         .spawn_worker(move || {
                             let mut messages = Vec::with_capacity(created_ids.len());
+                            let mut scheduled = Vec::new();
+                            let now = unix_timestamp();
 
                             for created_id in created_ids {
+                                // Skip canceled submissions and hold future releases or retries
+                                match store.email_submission_send_at(account_id, created_id)? {
+                                    Some(send_at) if send_at > now => {
+                                        scheduled.push((created_id, send_at));
+                                        continue;
+                                    }
+                                    Some(_) => (),
+                                    None => continue,
+                                }
+
                                 if let Some(email_submission) =
                                     store.get_orm::<EmailSubmission>(account_id, created_id)?
                                 {
@@ -192,11 +387,25 @@ where
                                 }
                             }
 
-                            Ok(messages)
+                            Ok((messages, scheduled))
                         })
                         .await
                     {
-                        Ok(messages) => {
+                        Ok((messages, scheduled)) => {
+                            // Queue submissions that are not yet due
+                            for (document_id, send_at) in scheduled {
+                                if let Err(err) = queue_tx
+                                    .send(Event::schedule_submission(
+                                        account_id,
+                                        document_id,
+                                        send_at,
+                                    ))
+                                    .await
+                                {
+                                    error!("Error sending event to queue: {}", err);
+                                }
+                            }
+
                             if messages.is_empty() {
                                 continue;
                             }
@@ -208,282 +417,243 @@ where
                         }
                     };
 
-                    // Connect to relay server
-                    let mut results = Vec::with_capacity(messages.len());
-                    match if is_tls {
-                        client.clone().connect_tls().await
-                    } else {
-                        client.clone().connect().await
-                    } {
-                        Ok(mut client) => {
-                            for (email_submission_id, current_email_submission, raw_message) in
-                                messages
-                            {
-                                // Track changes
-                                let mut email_submission =
-                                    TinyORM::track_changes(&current_email_submission);
-
-                                // Access envelope
-                                let envelope = if let Some(envelope) = current_email_submission
-                                    .get(&Property::Envelope)
-                                    .and_then(|value| {
-                                        if let Value::Envelope { value } = value {
-                                            Some(value)
-                                        } else {
-                                            None
-                                        }
-                                    }) {
-                                    envelope
+                    // Group the recipients that are due for delivery by destination
+                    let now = unix_timestamp();
+                    let mut routes: AHashMap<Option<String>, Vec<Delivery>> = AHashMap::new();
+                    for (pos, (email_submission_id, current_email_submission, raw_message)) in
+                        messages.iter().enumerate()
+                    {
+                        // Access envelope
+                        let envelope = if let Some(envelope) = current_email_submission
+                            .get(&Property::Envelope)
+                            .and_then(|value| {
+                                if let Value::Envelope { value } = value {
+                                    Some(value)
                                 } else {
+                                    None
+                                }
+                            }) {
+                            envelope
+                        } else {
+                            error!(
+                                "Missing envelope for {}/{}",
+                                account_id, email_submission_id
+                            );
+                            continue;
+                        };
+
+                        let rcpt_to = due_recipients(current_email_submission, envelope, now);
+                        if rcpt_to.is_empty() {
+                            continue;
+                        }
+
+                        // Discard cached DKIM signers when principals changed since they were
+                        // loaded, which includes generated keys and scheduled rotations.
+                        match core
+                            .store
+                            .get_last_change_id(SUPERUSER_ID, Collection::Principal)
+                        {
+                            Ok(change_id) if change_id != dkim_change_id => {
+                                dkim_map.clear();
+                                dkim_change_id = change_id;
+                            }
+                            Ok(_) => (),
+                            Err(err) => {
+                                error!("Failed to obtain the last principal change id: {}", err);
+                                dkim_map.clear();
+                            }
+                        }
+
+                        // Fetch dkim settings
+                        let domain_name = envelope
+                            .mail_from
+                            .email
+                            .split_once('@')
+                            .unwrap()
+                            .1
+                            .to_string();
+                        let dkim = if let Some(dkim) = dkim_map.get(&domain_name) {
+                            dkim
+                        } else {
+                            match core.store.dkim_get(domain_name.clone()) {
+                                Ok(dkim) => {
+                                    dkim_map.insert(domain_name.clone(), dkim);
+                                    dkim_map.get(&domain_name).unwrap()
+                                }
+                                Err(err) => {
                                     error!(
-                                        "Missing envelope for {}/{}",
-                                        account_id, email_submission_id
+                                        "Error getting DKIM settings for domain '{}': {}",
+                                        domain_name, err
                                     );
                                     continue;
-                                };
-
-                                // Fetch dkim settings
-                                let domain_name = envelope
-                                    .mail_from
-                                    .email
-                                    .split_once('@')
-                                    .unwrap()
-                                    .1
-                                    .to_string();
-                                let dkim = if let Some(dkim) = dkim_map.get(&domain_name) {
-                                    dkim
-                                } else {
-                                    match core.store.dkim_get(domain_name.clone()) {
-                                        Ok(dkim) => {
-                                            dkim_map.insert(
-                                                domain_name.clone(),
-                                                if let Some(dkim) = dkim {
-                                                    dkim.headers([
-                                                        "From",
-                                                        "To",
-                                                        "Subject",
-                                                        "Date",
-                                                        "Cc",
-                                                        "Bcc",
-                                                        "Message-ID",
-                                                        "References",
-                                                        "In-Reply-To",
-                                                    ])
-                                                    .into()
-                                                } else {
-                                                    None
-                                                },
-                                            );
-                                            dkim_map.get(&domain_name).unwrap()
-                                        }
-                                        Err(err) => {
-                                            error!(
-                                                "Error getting DKIM settings for domain '{}': {}",
-                                                domain_name, err
-                                            );
-                                            continue;
-                                        }
-                                    }
-                                };
-
-                                // Create delivery status list
-                                let mut delivery_status =
-                                    AHashMap::with_capacity(envelope.rcpt_to.len());
-
-                                // Send mail-from
-                                let undo_status = if let Err(err) = client
-                                    .cmd(
-                                        format!("MAIL FROM:{}\r\n", &envelope.mail_from).as_bytes(),
-                                    )
-                                    .await
-                                {
-                                    let err = err.to_string();
-                                    for rcpt in &envelope.rcpt_to {
-                                        delivery_status.insert(
-                                            rcpt.email.to_string(),
-                                            DeliveryStatus::new(
-                                                err.clone(),
-                                                Delivered::No,
-                                                Displayed::Unknown,
-                                            ),
-                                        );
-                                    }
-                                    UndoStatus::Canceled
-                                } else {
-                                    // Send recipients
-                                    let mut accepted_rcpt = false;
-                                    for rcpt in &envelope.rcpt_to {
-                                        match client
-                                            .cmd(format!("RCPT TO:{}\r\n", &rcpt).as_bytes())
-                                            .await
-                                        {
-                                            Ok(reply) => {
-                                                delivery_status.insert(
-                                                    rcpt.email.to_string(),
-                                                    DeliveryStatus::new(
-                                                        reply.to_string(),
-                                                        if reply.is_positive_completion() {
-                                                            accepted_rcpt = true;
-                                                            Delivered::Queued
-                                                        } else {
-                                                            Delivered::No
-                                                        },
-                                                        Displayed::Unknown,
-                                                    ),
-                                                );
-                                            }
-                                            Err(err) => {
-                                                delivery_status.insert(
-                                                    rcpt.email.to_string(),
-                                                    DeliveryStatus::new(
-                                                        err.to_string(),
-                                                        Delivered::No,
-                                                        Displayed::Unknown,
-                                                    ),
-                                                );
-                                            }
-                                        }
-                                    }
+                                }
+                            }
+                        };
 
-                                    // Do not submit message if no recipients were accepted
-                                    if accepted_rcpt {
-                                        // Sign message
-                                        let mut headers = None;
-                                        if let Some(dkim) = dkim {
-                                            match dkim.sign(&raw_message) {
-                                                Ok(signature) => {
-                                                    headers = signature.to_header().into();
-                                                }
-                                                Err(err) => {
-                                                    error!(
-                                                        "Error signing message for domain '{}': {}",
-                                                        domain_name, err
-                                                    );
-                                                }
-                                            }
-                                        }
+                        // Sign message
+                        let mut headers = None;
+                        if let Some(dkim) = dkim {
+                            match dkim.sign(raw_message, now) {
+                                Ok(signature) => {
+                                    headers = signature.into();
+                                }
+                                Err(err) => {
+                                    error!(
+                                        "Error signing message for domain '{}': {}",
+                                        domain_name, err
+                                    );
+                                }
+                            }
+                        }
 
-                                        // Send message
-                                        let result = if let Some(headers) = headers {
-                                            client
-                                                .data_with_headers(headers.as_bytes(), &raw_message)
-                                                .await
-                                        } else {
-                                            client.data(&raw_message).await
-                                        };
-
-                                        match result {
-                                            Ok(_) => UndoStatus::Final,
-                                            Err(err) => {
-                                                let err = err.to_string();
-                                                for rcpt in &envelope.rcpt_to {
-                                                    delivery_status.insert(
-                                                        rcpt.email.to_string(),
-                                                        DeliveryStatus::new(
-                                                            err.clone(),
-                                                            Delivered::No,
-                                                            Displayed::Unknown,
-                                                        ),
-                                                    );
-                                                }
-                                                UndoStatus::Canceled
-                                            }
-                                        }
-                                    } else {
-                                        UndoStatus::Canceled
-                                    }
-                                };
+                        // Future release parameters are handled by the local queue
+                        let mut mail_from = envelope.mail_from.clone();
+                        if let Some(parameters) = &mut mail_from.parameters {
+                            parameters.remove("HOLDFOR");
+                            parameters.remove("HOLDUNTIL");
+                        }
 
-                                // Update submission
-                                email_submission.set(
-                                    Property::UndoStatus,
-                                    Value::UndoStatus { value: undo_status },
-                                );
-                                email_submission.set(
-                                    Property::DeliveryStatus,
-                                    Value::DeliveryStatus {
-                                        value: delivery_status,
-                                    },
-                                );
-                                results.push((
-                                    email_submission_id,
-                                    current_email_submission,
-                                    email_submission,
-                                ));
-                                client.rset().await.ok();
+                        for rcpt in rcpt_to {
+                            let deliveries = routes
+                                .entry(delivery_route(&rcpt.email, resolver.is_some()))
+                                .or_insert_with(Vec::new);
+                            match deliveries.last_mut() {
+                                Some(delivery) if delivery.pos == pos => {
+                                    delivery.rcpt_to.push(rcpt.clone());
+                                }
+                                _ => {
+                                    deliveries.push(Delivery {
+                                        pos,
+                                        mail_from: mail_from.clone(),
+                                        rcpt_to: vec![rcpt.clone()],
+                                        raw_message,
+                                        headers: headers.clone(),
+                                    });
+                                }
                             }
+                        }
+                    }
 
-                            // Send QUIT
-                            client.quit().await.ok();
+                    // Deliver messages
+                    let mut attempts = (0..messages.len()).map(|_| Vec::new()).collect::<Vec<_>>();
+                    for (route, deliveries) in routes {
+                        for (delivery, results) in deliveries.iter().zip(
+                            deliver(&smtp_settings, resolver.as_ref(), route, &deliveries).await,
+                        ) {
+                            attempts[delivery.pos].extend(results);
                         }
-                        Err(err) => {
-                            // Fail all submissions
-                            let err = err.to_string();
-                            error!("Failed to connect to relay server: {}", err);
-
-                            for (email_submission_id, current_email_submission, _) in messages {
-                                // Track changes
-                                let mut email_submission =
-                                    TinyORM::track_changes(&current_email_submission);
-
-                                // Access envelope
-                                if let Some(envelope) = current_email_submission
-                                    .get(&Property::Envelope)
-                                    .and_then(|value| {
-                                        if let Value::Envelope { value } = value {
-                                            Some(value)
-                                        } else {
-                                            None
-                                        }
-                                    })
-                                {
-                                    // Create delivery status list
-                                    let mut delivery_status =
-                                        AHashMap::with_capacity(envelope.rcpt_to.len());
-
-                                    // Fail all recipients
-                                    for rcpt in &envelope.rcpt_to {
-                                        delivery_status.insert(
-                                            rcpt.email.to_string(),
-                                            DeliveryStatus::new(
-                                                err.clone(),
-                                                Delivered::No,
-                                                Displayed::Unknown,
-                                            ),
-                                        );
-                                    }
-                                    email_submission.set(
-                                        Property::UndoStatus,
-                                        Value::UndoStatus {
-                                            value: UndoStatus::Canceled,
-                                        },
-                                    );
-                                    email_submission.set(
-                                        Property::DeliveryStatus,
-                                        Value::DeliveryStatus {
-                                            value: delivery_status,
-                                        },
-                                    );
-                                    results.push((
+                    }
+                    let results = messages
+                        .into_iter()
+                        .zip(attempts)
+                        .filter_map(
+                            |(
+                                (email_submission_id, current_email_submission, raw_message),
+                                attempts,
+                            )| {
+                                if !attempts.is_empty() {
+                                    Some((
                                         email_submission_id,
                                         current_email_submission,
-                                        email_submission,
-                                    ));
+                                        attempts,
+                                        raw_message,
+                                    ))
+                                } else {
+                                    None
                                 }
-                            }
-                        }
+                            },
+                        )
+                        .collect::<Vec<_>>();
+                    if results.is_empty() {
+                        continue;
                     }
 
                     // Update store with submission results
                     let store = core.store.clone();
+                    let reporting_mta = reporting_mta.clone();
+                    let retry_interval = smtp_settings.retry_interval;
+                    let max_age = smtp_settings.max_age;
                     match core
                         .spawn_worker(move || {
                             let mut batch = WriteBatch::new(account_id);
-                            for (email_submission_id, current_email_submission, email_submission) in
-                                results
+                            let mut retries = Vec::new();
+                            let mut has_bounces = false;
+
+                            for (
+                                email_submission_id,
+                                current_email_submission,
+                                attempts,
+                                raw_message,
+                            ) in results
                             {
                                 let mut document =
                                     Document::new(Collection::EmailSubmission, email_submission_id);
+                                let (mut email_submission, failures) = apply_delivery_attempts(
+                                    &current_email_submission,
+                                    attempts,
+                                    retry_interval,
+                                    max_age,
+                                    now,
+                                );
+
+                                // Schedule the next retry
+                                if let Some(Value::DeliveryQueue { value }) =
+                                    email_submission.get(&Property::DeliveryQueue)
+                                {
+                                    if let Some(next_attempt) = value.next_attempt() {
+                                        retries.push((email_submission_id, next_attempt));
+                                    }
+                                }
+
+                                // Notify the sender about permanent delivery failures
+                                if !failures.is_empty() {
+                                    if let Some(Value::Envelope { value: envelope }) =
+                                        current_email_submission.get(&Property::Envelope)
+                                    {
+                                        let dsn = build_dsn(
+                                            &reporting_mta,
+                                            &envelope.mail_from.email,
+                                            &failures,
+                                            &raw_message,
+                                        );
+                                        let blob_id = BlobId::new_external(&dsn);
+                                        store.blob_store(&blob_id, dsn.clone())?;
+                                        match store.mail_import_item(
+                                            account_id,
+                                            blob_id.clone(),
+                                            &dsn,
+                                            vec![INBOX_ID],
+                                            vec![],
+                                            None,
+                                        ) {
+                                            Ok(_) => {
+                                                let mut dsn_blob_ids =
+                                                    if let Some(Value::BlobIds { value }) =
+                                                        current_email_submission
+                                                            .get(&Property::DsnBlobIds)
+                                                    {
+                                                        value.clone()
+                                                    } else {
+                                                        Vec::new()
+                                                    };
+                                                dsn_blob_ids.push((&blob_id).into());
+                                                email_submission.set(
+                                                    Property::DsnBlobIds,
+                                                    Value::BlobIds {
+                                                        value: dsn_blob_ids,
+                                                    },
+                                                );
+                                                has_bounces = true;
+                                            }
+                                            Err(err) => {
+                                                error!(
+                                                    "Failed to deliver DSN for {}/{}: {:?}",
+                                                    account_id, email_submission_id, err
+                                                );
+                                            }
+                                        }
+                                    }
+                                }
 
                                 // Merge changes
                                 current_email_submission.merge(&mut document, email_submission)?;
@@ -496,50 +666,80 @@ where
                                 }
                             }
                             // Write changes
-                            store.write(batch)
+                            Ok((store.write(batch)?, retries, has_bounces))
                         })
                         .await
                     {
-                        Ok(Some(changes)) => {
-                            // Commit change
-                            if core.is_in_cluster() {
-                                core.commit_index(changes.change_id).await;
+                        Ok((changes, retries, has_bounces)) => {
+                            // Queue retries
+                            for (document_id, next_attempt) in retries {
+                                if let Err(err) = queue_tx
+                                    .send(Event::schedule_submission(
+                                        account_id,
+                                        document_id,
+                                        next_attempt,
+                                    ))
+                                    .await
+                                {
+                                    error!("Error sending event to queue: {}", err);
+                                }
                             }
 
-                            // Notify subscribers
-                            if let Err(err) = core
-                                .publish_state_change(StateChange {
-                                    account_id,
-                                    types: vec![(TypeState::EmailSubmission, changes.change_id)],
-                                })
-                                .await
-                            {
-                                error!("Failed to publish state change: {}", err);
+                            if let Some(changes) = changes {
+                                // Commit change
+                                if core.is_in_cluster() {
+                                    core.commit_index(changes.change_id).await;
+                                }
+
+                                // Notify subscribers
+                                let mut types =
+                                    vec![(TypeState::EmailSubmission, changes.change_id)];
+                                if has_bounces {
+                                    types.extend([
+                                        (TypeState::Email, changes.change_id),
+                                        (TypeState::Mailbox, changes.change_id),
+                                        (TypeState::Thread, changes.change_id),
+                                        (TypeState::Quota, changes.change_id),
+                                    ]);
+                                }
+                                if let Err(err) = core
+                                    .publish_state_change(StateChange { account_id, types })
+                                    .await
+                                {
+                                    error!("Failed to publish state change: {}", err);
+                                }
                             }
                         }
-                        Ok(None) => (),
                         Err(err) => {
                             error!("Failed to update email submissions: {}", err);
                         }
                     }
                 }
-                Event::VacationResponse { from, to, message } => {
-                    match if is_tls {
-                        client.clone().connect_tls().await
-                    } else {
-                        client.clone().connect().await
-                    } {
-                        Ok(mut client) => {
-                            if let Err(err) = client
-                                .send(Message::empty().from(from).to(to).body(&message))
-                                .await
-                            {
-                                debug!("Failed to send vacation response: {}", err);
-                            }
-                            client.quit().await.ok();
-                        }
-                        Err(err) => {
-                            error!("Failed to connect to relay server: {}", err);
+                Event::VacationResponse { from, to, message }
+                | Event::Redirect { from, to, message }
+                | Event::InvitationReply { from, to, message } => {
+                    let route = delivery_route(&to, resolver.is_some());
+                    let delivery = Delivery {
+                        pos: 0,
+                        mail_from: Address {
+                            email: from,
+                            parameters: None,
+                        },
+                        rcpt_to: vec![Address {
+                            email: to,
+                            parameters: None,
+                        }],
+                        raw_message: &message,
+                        headers: None,
+                    };
+                    for (rcpt, attempt) in
+                        deliver(&smtp_settings, resolver.as_ref(), route, &[delivery])
+                            .await
+                            .into_iter()
+                            .flatten()
+                    {
+                        if let DeliveryAttempt::Failed(reply) = attempt {
+                            debug!("Failed to send message to {}: {}", rcpt, reply);
                         }
                     }
                 }
@@ -558,32 +758,521 @@ where
     tx
 }
 
-struct SMTPRelay {
+enum DeliveryAttempt {
+    Accepted(String),
+    Failed(String),
+}
+
+// Returns the recipients that have not been attempted yet or are due for a retry.
+fn due_recipients<'x>(
+    email_submission: &'x TinyORM<EmailSubmission>,
+    envelope: &'x Envelope,
+    now: i64,
+) -> Vec<&'x Address> {
+    if let Some(Value::DeliveryQueue { value }) = email_submission.get(&Property::DeliveryQueue) {
+        envelope
+            .rcpt_to
+            .iter()
+            .filter(|rcpt| {
+                matches!(value.recipients.get(&rcpt.email),
+                    Some(queued) if queued.next_attempt <= now)
+            })
+            .collect()
+    } else {
+        envelope.rcpt_to.iter().collect()
+    }
+}
+
+// Updates the delivery status and queue of a submission, temporary failures are
+// retried with an exponential backoff until the maximum queue age is reached.
+fn apply_delivery_attempts(
+    current_email_submission: &TinyORM<EmailSubmission>,
+    attempts: Vec<(String, DeliveryAttempt)>,
+    retry_interval: i64,
+    max_age: i64,
+    now: i64,
+) -> (TinyORM<EmailSubmission>, Vec<DeliveryFailure>) {
+    let mut email_submission = TinyORM::track_changes(current_email_submission);
+    let mut delivery_status = if let Some(Value::DeliveryStatus { value }) =
+        current_email_submission.get(&Property::DeliveryStatus)
+    {
+        value.clone()
+    } else {
+        AHashMap::with_capacity(attempts.len())
+    };
+    let mut queue = if let Some(Value::DeliveryQueue { value }) =
+        current_email_submission.get(&Property::DeliveryQueue)
+    {
+        value.clone()
+    } else {
+        DeliveryQueue::new(now)
+    };
+    let mut failures = Vec::new();
+    let mut is_final = matches!(
+        current_email_submission.get(&Property::UndoStatus),
+        Some(Value::UndoStatus {
+            value: UndoStatus::Final
+        })
+    );
+
+    for (rcpt, attempt) in attempts {
+        let (reply, delivered) = match attempt {
+            DeliveryAttempt::Accepted(reply) => {
+                queue.recipients.remove(&rcpt);
+                is_final = true;
+                (reply, Delivered::Queued)
+            }
+            DeliveryAttempt::Failed(reply)
+                if !reply.starts_with('5') && now - queue.created < max_age =>
+            {
+                let queued = queue
+                    .recipients
+                    .entry(rcpt.clone())
+                    .or_insert(QueuedRecipient {
+                        attempts: 0,
+                        next_attempt: now,
+                    });
+                queued.next_attempt =
+                    now + retry_interval.saturating_mul(1 << queued.attempts.min(10));
+                queued.attempts += 1;
+                (reply, Delivered::Queued)
+            }
+            DeliveryAttempt::Failed(reply) => {
+                queue.recipients.remove(&rcpt);
+                failures.push(DeliveryFailure::new(rcpt.clone(), reply.clone()));
+                (reply, Delivered::No)
+            }
+        };
+        delivery_status.insert(
+            rcpt,
+            DeliveryStatus::new(reply, delivered, Displayed::Unknown),
+        );
+    }
+
+    // Submissions can be canceled until a recipient accepts the message
+    email_submission.set(
+        Property::UndoStatus,
+        Value::UndoStatus {
+            value: if is_final {
+                UndoStatus::Final
+            } else if !queue.recipients.is_empty() {
+                UndoStatus::Pending
+            } else {
+                UndoStatus::Canceled
+            },
+        },
+    );
+    email_submission.set(
+        Property::DeliveryStatus,
+        Value::DeliveryStatus {
+            value: delivery_status,
+        },
+    );
+    email_submission.set(
+        Property::DeliveryQueue,
+        if !queue.recipients.is_empty() {
+            Value::DeliveryQueue { value: queue }
+        } else {
+            Value::Null
+        },
+    );
+
+    (email_submission, failures)
+}
+
+struct Delivery<'x> {
+    pos: usize,
+    mail_from: Address,
+    rcpt_to: Vec<Address>,
+    raw_message: &'x [u8],
+    headers: Option<String>,
+}
+
+// Returns the recipient domain when messages are delivered directly to its MX hosts.
+fn delivery_route(rcpt: &str, is_direct: bool) -> Option<String> {
+    if is_direct {
+        rcpt.rsplit_once('@')
+            .map(|(_, domain)| domain.to_lowercase())
+    } else {
+        None
+    }
+}
+
+// Delivers messages either to the configured relay or to the mail exchangers of
+// the route's domain, falling back across hosts in order of preference.
+async fn deliver(
+    smtp_settings: &SMTPSettings,
+    resolver: Option<&TokioAsyncResolver>,
+    route: Option<String>,
+    deliveries: &[Delivery<'_>],
+) -> Vec<Vec<(String, DeliveryAttempt)>> {
+    // Obtain hosts to connect to
+    let hosts = match (&smtp_settings.delivery, resolver, &route) {
+        (SMTPDelivery::Relay { hostname, .. }, _, _) => vec![(hostname.clone(), hostname.clone())],
+        (SMTPDelivery::Direct { .. }, Some(resolver), Some(domain)) => {
+            match resolve_mx(resolver, domain).await {
+                Ok(hosts) => hosts,
+                Err(reply) => return fail_deliveries(deliveries, &reply),
+            }
+        }
+        _ => return fail_deliveries(deliveries, "550 5.1.3 Invalid recipient address."),
+    };
+
+    let mut last_err = String::new();
+    for (server_name, address) in &hosts {
+        match deliver_to_host(smtp_settings, server_name, address, deliveries).await {
+            Ok(results) => return results,
+            Err(err) => {
+                error!("Failed to deliver to SMTP server {}: {}", server_name, err);
+                last_err = err.to_string();
+            }
+        }
+    }
+
+    fail_deliveries(deliveries, &last_err)
+}
+
+// Opens a session with a host. Relays are contacted as configured, while mail
+// exchangers are upgraded with STARTTLS whenever they advertise it.
+async fn deliver_to_host(
+    smtp_settings: &SMTPSettings,
+    server_name: &str,
+    address: &str,
+    deliveries: &[Delivery<'_>],
+) -> std::io::Result<Vec<Vec<(String, DeliveryAttempt)>>> {
+    let hostname = &smtp_settings.hostname;
+    let timeout = smtp_settings.timeout;
+    let tls_connector = &smtp_settings.tls_connector;
+
+    match &smtp_settings.delivery {
+        SMTPDelivery::Relay {
+            port,
+            credentials,
+            tls,
+            ..
+        } => {
+            let port = if *port > 0 {
+                *port
+            } else if *tls {
+                465
+            } else {
+                25
+            };
+            if *tls && port == 465 {
+                let mut client =
+                    SmtpClient::connect_tls(address, port, timeout, tls_connector).await?;
+                let capabilities = client.ehlo(hostname).await?;
+                send_deliveries(client, &capabilities, credentials.as_ref(), deliveries).await
+            } else {
+                let mut client = SmtpClient::connect(address, port, timeout).await?;
+                let capabilities = client.ehlo(hostname).await?;
+                if *tls {
+                    if !capabilities.has_capability("STARTTLS") {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::Other,
+                            "Relay host does not support STARTTLS.",
+                        ));
+                    }
+                    let mut client = client.start_tls(server_name, tls_connector).await?;
+                    let capabilities = client.ehlo(hostname).await?;
+                    send_deliveries(client, &capabilities, credentials.as_ref(), deliveries).await
+                } else {
+                    send_deliveries(client, &capabilities, credentials.as_ref(), deliveries).await
+                }
+            }
+        }
+        SMTPDelivery::Direct { port, .. } => {
+            let mut client = SmtpClient::connect(address, *port, timeout).await?;
+            let capabilities = client.ehlo(hostname).await?;
+            if capabilities.has_capability("STARTTLS") {
+                let mut client = client.start_tls(server_name, tls_connector).await?;
+                let capabilities = client.ehlo(hostname).await?;
+                send_deliveries(client, &capabilities, None, deliveries).await
+            } else {
+                debug!(
+                    "SMTP server {} does not support STARTTLS, delivering in plain text.",
+                    server_name
+                );
+                send_deliveries(client, &capabilities, None, deliveries).await
+            }
+        }
+    }
+}
+
+async fn send_deliveries<T: AsyncRead + AsyncWrite + Unpin>(
+    mut client: SmtpClient<T>,
+    capabilities: &Reply,
+    credentials: Option<&(String, String)>,
+    deliveries: &[Delivery<'_>],
+) -> std::io::Result<Vec<Vec<(String, DeliveryAttempt)>>> {
+    if let Some((username, secret)) = credentials {
+        client.authenticate(capabilities, username, secret).await?;
+    }
+
+    let mut results = Vec::with_capacity(deliveries.len());
+    for delivery in deliveries {
+        let mut attempts = Vec::with_capacity(delivery.rcpt_to.len());
+
+        // Send mail-from
+        let mail_from = match client
+            .cmd(format!("MAIL FROM:{}\r\n", &delivery.mail_from).as_bytes())
+            .await
+        {
+            Ok(reply) if reply.is_positive_completion() => Ok(()),
+            Ok(reply) => Err(reply.to_string()),
+            Err(err) => Err(err.to_string()),
+        };
+        if let Err(err) = mail_from {
+            for rcpt in &delivery.rcpt_to {
+                attempts.push((rcpt.email.to_string(), DeliveryAttempt::Failed(err.clone())));
+            }
+        } else {
+            // Send recipients
+            let mut accepted_rcpt = Vec::with_capacity(delivery.rcpt_to.len());
+            for rcpt in &delivery.rcpt_to {
+                match client
+                    .cmd(format!("RCPT TO:{}\r\n", &rcpt).as_bytes())
+                    .await
+                {
+                    Ok(reply) if reply.is_positive_completion() => {
+                        accepted_rcpt.push((rcpt.email.to_string(), reply.to_string()));
+                    }
+                    Ok(reply) => {
+                        attempts.push((
+                            rcpt.email.to_string(),
+                            DeliveryAttempt::Failed(reply.to_string()),
+                        ));
+                    }
+                    Err(err) => {
+                        attempts.push((
+                            rcpt.email.to_string(),
+                            DeliveryAttempt::Failed(err.to_string()),
+                        ));
+                    }
+                }
+            }
+
+            // Do not submit message if no recipients were accepted
+            if !accepted_rcpt.is_empty() {
+                let result = if let Some(headers) = &delivery.headers {
+                    client
+                        .data_with_headers(headers.as_bytes(), delivery.raw_message)
+                        .await
+                } else {
+                    client.data(delivery.raw_message).await
+                };
+
+                match result {
+                    Ok(_) => {
+                        for (rcpt, reply) in accepted_rcpt {
+                            attempts.push((rcpt, DeliveryAttempt::Accepted(reply)));
+                        }
+                    }
+                    Err(err) => {
+                        let err = err.to_string();
+                        for (rcpt, _) in accepted_rcpt {
+                            attempts.push((rcpt, DeliveryAttempt::Failed(err.clone())));
+                        }
+                    }
+                }
+            }
+        }
+
+        results.push(attempts);
+        client.rset().await.ok();
+    }
+
+    // Send QUIT
+    client.quit().await.ok();
+
+    Ok(results)
+}
+
+fn fail_deliveries(
+    deliveries: &[Delivery<'_>],
+    reply: &str,
+) -> Vec<Vec<(String, DeliveryAttempt)>> {
+    deliveries
+        .iter()
+        .map(|delivery| {
+            delivery
+                .rcpt_to
+                .iter()
+                .map(|rcpt| {
+                    (
+                        rcpt.email.to_string(),
+                        DeliveryAttempt::Failed(reply.to_string()),
+                    )
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// Returns the names and addresses of the mail exchangers of a domain, ordered by preference.
+async fn resolve_mx(
+    resolver: &TokioAsyncResolver,
+    domain: &str,
+) -> Result<Vec<(String, String)>, String> {
+    let exchangers = match resolver.mx_lookup(domain).await {
+        Ok(mx_lookup) => {
+            let mut exchangers = mx_lookup
+                .iter()
+                .map(|mx| (mx.preference(), mx.exchange().clone()))
+                .collect::<Vec<_>>();
+
+            // Null MX (RFC 7505)
+            if exchangers.iter().any(|(_, exchange)| exchange.is_root()) {
+                return Err(format!(
+                    "556 5.1.10 Domain {} does not accept mail.",
+                    domain
+                ));
+            }
+
+            exchangers.sort_unstable_by_key(|(preference, _)| *preference);
+            exchangers
+                .into_iter()
+                .map(|(_, exchange)| exchange.to_utf8())
+                .collect::<Vec<_>>()
+        }
+        Err(err) => match err.kind() {
+            ResolveErrorKind::NoRecordsFound { response_code, .. }
+                if *response_code == ResponseCode::NXDomain =>
+            {
+                return Err(format!("550 5.1.2 Domain {} does not exist.", domain));
+            }
+            ResolveErrorKind::NoRecordsFound { .. } => {
+                // Use the domain as an implicit MX (RFC 5321, section 5.1)
+                vec![domain.to_string()]
+            }
+            _ => {
+                return Err(format!(
+                    "451 4.4.3 Failed to obtain MX records for {}: {}",
+                    domain, err
+                ));
+            }
+        },
+    };
+
+    let mut hosts = Vec::new();
+    for exchange in exchangers {
+        match resolver.lookup_ip(exchange.as_str()).await {
+            Ok(addresses) => {
+                let server_name = exchange.trim_end_matches('.');
+                hosts.extend(
+                    addresses
+                        .iter()
+                        .map(|address| (server_name.to_string(), address.to_string())),
+                );
+            }
+            Err(err) => {
+                debug!("Failed to resolve MX host {}: {}", exchange, err);
+            }
+        }
+    }
+
+    if !hosts.is_empty() {
+        Ok(hosts)
+    } else {
+        Err(format!(
+            "451 4.4.3 Failed to resolve mail exchangers for {}.",
+            domain
+        ))
+    }
+}
+
+fn build_resolver(dns_server: Option<SocketAddr>) -> Result<TokioAsyncResolver, ResolveError> {
+    if let Some(dns_server) = dns_server {
+        TokioAsyncResolver::tokio(
+            ResolverConfig::from_parts(
+                None,
+                vec![],
+                NameServerConfigGroup::from_ips_clear(&[dns_server.ip()], dns_server.port(), true),
+            ),
+            ResolverOpts::default(),
+        )
+    } else {
+        TokioAsyncResolver::tokio_from_system_conf()
+    }
+}
+
+enum SMTPDelivery {
+    Relay {
+        hostname: String,
+        port: u16,
+        credentials: Option<(String, String)>,
+        tls: bool,
+    },
+    Direct {
+        port: u16,
+        dns_server: Option<SocketAddr>,
+    },
+}
+
+struct SMTPSettings {
+    delivery: SMTPDelivery,
     hostname: String,
-    port: u16,
-    credentials: Option<(String, String)>,
-    tls: bool,
+    tls_connector: TlsConnector,
     timeout: Duration,
+    retry_interval: i64,
+    max_age: i64,
 }
 
-fn parse_smtp_settings(settings: &EnvSettings) -> Option<SMTPRelay> {
-    Some(SMTPRelay {
-        hostname: settings.get("smtp-relay-host")?,
-        port: settings.parse("smtp-relay-port").unwrap_or(0),
-        credentials: if let (Some(auth), Some(pass)) = (
-            settings.get("smtp-relay-auth"),
-            settings.get("smtp-relay-secret"),
-        ) {
-            (auth, pass).into()
-        } else {
-            None
+fn parse_smtp_settings(settings: &EnvSettings) -> Option<SMTPSettings> {
+    let delivery = match settings.get("smtp-delivery-mode").as_deref() {
+        Some("mx") => SMTPDelivery::Direct {
+            port: settings.parse("smtp-mx-port").unwrap_or(25),
+            dns_server: if settings.contains_key("smtp-mx-dns-server") {
+                settings
+                    .parse_socketaddr("smtp-mx-dns-server", "127.0.0.1:53")
+                    .into()
+            } else {
+                None
+            },
+        },
+        Some("relay") | None => SMTPDelivery::Relay {
+            hostname: settings.get("smtp-relay-host")?,
+            port: settings.parse("smtp-relay-port").unwrap_or(0),
+            credentials: if let (Some(auth), Some(pass)) = (
+                settings.get("smtp-relay-auth"),
+                settings.get("smtp-relay-secret"),
+            ) {
+                (auth, pass).into()
+            } else {
+                None
+            },
+            tls: settings.parse("smtp-relay-tls").unwrap_or(false),
         },
-        tls: settings.parse("smtp-relay-tls").unwrap_or(false),
+        Some(mode) => {
+            error!("Invalid SMTP delivery mode '{}'.", mode);
+            return None;
+        }
+    };
+
+    // TLS with mail exchangers is opportunistic, so their certificates are not verified
+    let tls_connector = TlsConnector::from(Arc::new(load_tls_client_config(matches!(
+        delivery,
+        SMTPDelivery::Direct { .. }
+    ))));
+
+    Some(SMTPSettings {
+        delivery,
+        hostname: gethostname::gethostname()
+            .to_str()
+            .unwrap_or("localhost")
+            .to_string(),
+        tls_connector,
         timeout: Duration::from_millis(
             settings
                 .parse("smtp-relay-timeout")
                 .unwrap_or(DEFAULT_SMTP_TIMEOUT_MS),
         ),
+        retry_interval: settings
+            .parse("smtp-relay-retry-interval")
+            .unwrap_or(DEFAULT_SMTP_RETRY_INTERVAL),
+        max_age: settings
+            .parse("smtp-relay-max-age")
+            .unwrap_or(DEFAULT_SMTP_MAX_AGE),
     })
 }
 
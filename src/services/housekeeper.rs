@@ -24,10 +24,11 @@
 use std::time::{Duration, SystemTime};
 
 use actix_web::web;
-use jmap_sharing::principal::set::JMAPSetPrincipal;
+use jmap_sharing::principal::{ldap::JMAPLdap, set::JMAPSetPrincipal};
 use store::{
     chrono::{self, Datelike, TimeZone},
     config::env_settings::EnvSettings,
+    core::error::StoreError,
     tracing::{debug, error, info},
     ColumnFamily, Store,
 };
@@ -44,6 +45,7 @@ pub enum Event {
     PurgeBlobs,
     SnapshotLog,
     CompactDb,
+    SyncDirectory,
     Exit,
 }
 
@@ -56,6 +58,7 @@ const TASK_PURGE_ACCOUNTS: usize = 0;
 const TASK_PURGE_BLOBS: usize = 1;
 const TASK_SNAPSHOT_LOG: usize = 2;
 const TASK_COMPACT_DB: usize = 3;
+const TASK_SYNC_DIRECTORY: usize = 4;
 
 pub fn spawn_housekeeper<T>(
     core: web::Data<JMAPServer<T>>,
@@ -84,6 +87,11 @@ pub fn spawn_housekeeper<T>(
             .get("schedule-compact-db")
             .unwrap_or_else(|| "0 4 *".to_string()),
     );
+    let sync_directory_at = SimpleCron::parse(
+        &settings
+            .get("schedule-sync-directory")
+            .unwrap_or_else(|| "0 2 *".to_string()),
+    );
     let max_log_entries: u64 = settings.parse("max-changelog-entries").unwrap_or(10000);
 
     tokio::spawn(async move {
@@ -94,8 +102,9 @@ pub fn spawn_housekeeper<T>(
                 purge_blobs_at.time_to_next(),
                 snapshot_log_at.time_to_next(),
                 compact_db_at.time_to_next(),
+                sync_directory_at.time_to_next(),
             ];
-            let mut tasks_to_run = [false, false, false, false];
+            let mut tasks_to_run = [false, false, false, false, false];
             let start_time = SystemTime::now()
                 .duration_since(SystemTime::UNIX_EPOCH)
                 .map(|d| d.as_secs())
@@ -108,6 +117,7 @@ pub fn spawn_housekeeper<T>(
                     Event::PurgeBlobs => tasks_to_run[TASK_PURGE_BLOBS] = true,
                     Event::SnapshotLog => tasks_to_run[TASK_SNAPSHOT_LOG] = true,
                     Event::CompactDb => tasks_to_run[TASK_COMPACT_DB] = true,
+                    Event::SyncDirectory => tasks_to_run[TASK_SYNC_DIRECTORY] = true,
                     Event::Exit => {
                         debug!("Housekeeper task exiting.");
                         return;
@@ -160,6 +170,21 @@ pub fn spawn_housekeeper<T>(
                             core.spawn_worker(move || store.db.compact(ColumnFamily::Bitmaps))
                                 .await
                         }
+                        TASK_SYNC_DIRECTORY if store.config.ldap.is_some() && core.is_leader() => {
+                            info!("Synchronizing principals with the LDAP directory.");
+                            match core.spawn_worker(move || store.ldap_sync()).await {
+                                Ok(Some(change_id))
+                                    if core.is_in_cluster()
+                                        && !core.commit_index(change_id).await =>
+                                {
+                                    Err(StoreError::InternalError(
+                                        "Failed to commit directory changes.".to_string(),
+                                    ))
+                                }
+                                result => result.map(|_| ()),
+                            }
+                        }
+                        TASK_SYNC_DIRECTORY => Ok(()),
                         _ => unreachable!(),
                     };
 
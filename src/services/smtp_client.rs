@@ -0,0 +1,311 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{fmt::Display, io, time::Duration};
+
+use jmap::base64;
+use rustls::ServerName;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+use tokio_rustls::{client::TlsStream, TlsConnector};
+
+// Minimal SMTP client used to deliver outbound messages. Connections are
+// opened in clear text and upgraded with STARTTLS when the server offers it.
+pub struct SmtpClient<T: AsyncRead + AsyncWrite + Unpin> {
+    stream: BufReader<T>,
+    timeout: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct Reply {
+    pub code: u16,
+    pub lines: Vec<String>,
+}
+
+impl SmtpClient<TcpStream> {
+    // Connects and waits for the server greeting.
+    pub async fn connect(host: &str, port: u16, timeout: Duration) -> io::Result<Self> {
+        let stream = tokio::time::timeout(timeout, TcpStream::connect((host, port)))
+            .await
+            .map_err(|_| timeout_error())??;
+        let mut client = SmtpClient {
+            stream: BufReader::new(stream),
+            timeout,
+        };
+        client.read_reply().await?.assert_positive_completion()?;
+        Ok(client)
+    }
+
+    // Connects over implicit TLS and waits for the server greeting.
+    pub async fn connect_tls(
+        host: &str,
+        port: u16,
+        timeout: Duration,
+        tls_connector: &TlsConnector,
+    ) -> io::Result<SmtpClient<TlsStream<TcpStream>>> {
+        let stream = tokio::time::timeout(timeout, TcpStream::connect((host, port)))
+            .await
+            .map_err(|_| timeout_error())??;
+        let mut client = SmtpClient {
+            stream: BufReader::new(tls_handshake(stream, host, timeout, tls_connector).await?),
+            timeout,
+        };
+        client.read_reply().await?.assert_positive_completion()?;
+        Ok(client)
+    }
+
+    // Upgrades the connection to TLS after the server accepts STARTTLS.
+    pub async fn start_tls(
+        mut self,
+        server_name: &str,
+        tls_connector: &TlsConnector,
+    ) -> io::Result<SmtpClient<TlsStream<TcpStream>>> {
+        self.cmd(b"STARTTLS\r\n")
+            .await?
+            .assert_positive_completion()?;
+
+        // Responses pipelined before the handshake must not be trusted
+        if !self.stream.buffer().is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Unexpected data received before the TLS handshake.",
+            ));
+        }
+
+        Ok(SmtpClient {
+            stream: BufReader::new(
+                tls_handshake(
+                    self.stream.into_inner(),
+                    server_name,
+                    self.timeout,
+                    tls_connector,
+                )
+                .await?,
+            ),
+            timeout: self.timeout,
+        })
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> SmtpClient<T> {
+    pub async fn ehlo(&mut self, hostname: &str) -> io::Result<Reply> {
+        let reply = self
+            .cmd(format!("EHLO {}\r\n", hostname).as_bytes())
+            .await?;
+        reply.assert_positive_completion()?;
+        Ok(reply)
+    }
+
+    // Authenticates using PLAIN, or LOGIN when PLAIN is not offered.
+    pub async fn authenticate(
+        &mut self,
+        capabilities: &Reply,
+        username: &str,
+        secret: &str,
+    ) -> io::Result<()> {
+        if capabilities.has_auth_mechanism("PLAIN") || !capabilities.has_auth_mechanism("LOGIN") {
+            self.cmd(
+                format!(
+                    "AUTH PLAIN {}\r\n",
+                    base64::encode(format!("\u{0}{}\u{0}{}", username, secret))
+                )
+                .as_bytes(),
+            )
+            .await?
+            .assert_positive_completion()
+        } else {
+            self.cmd(b"AUTH LOGIN\r\n").await?.assert_code(334)?;
+            self.cmd(format!("{}\r\n", base64::encode(username)).as_bytes())
+                .await?
+                .assert_code(334)?;
+            self.cmd(format!("{}\r\n", base64::encode(secret)).as_bytes())
+                .await?
+                .assert_positive_completion()
+        }
+    }
+
+    pub async fn cmd(&mut self, cmd: &[u8]) -> io::Result<Reply> {
+        self.write(cmd).await?;
+        self.read_reply().await
+    }
+
+    pub async fn data(&mut self, message: &[u8]) -> io::Result<Reply> {
+        self.data_with_headers(b"", message).await
+    }
+
+    pub async fn data_with_headers(&mut self, headers: &[u8], message: &[u8]) -> io::Result<Reply> {
+        self.cmd(b"DATA\r\n").await?.assert_code(354)?;
+
+        // Dot-stuff the message and terminate it with a single dot
+        let mut buf = Vec::with_capacity(headers.len() + message.len() + 16);
+        let mut last_ch = b'\n';
+        for &ch in headers.iter().chain(message.iter()) {
+            if ch == b'.' && last_ch == b'\n' {
+                buf.push(b'.');
+            }
+            buf.push(ch);
+            last_ch = ch;
+        }
+        if !buf.ends_with(b"\r\n") {
+            buf.extend_from_slice(b"\r\n");
+        }
+        buf.extend_from_slice(b".\r\n");
+        self.write(&buf).await?;
+
+        let reply = self.read_reply().await?;
+        reply.assert_positive_completion()?;
+        Ok(reply)
+    }
+
+    pub async fn rset(&mut self) -> io::Result<Reply> {
+        self.cmd(b"RSET\r\n").await
+    }
+
+    pub async fn quit(&mut self) -> io::Result<Reply> {
+        self.cmd(b"QUIT\r\n").await
+    }
+
+    async fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        tokio::time::timeout(self.timeout, async {
+            self.stream.get_mut().write_all(bytes).await?;
+            self.stream.get_mut().flush().await
+        })
+        .await
+        .map_err(|_| timeout_error())?
+    }
+
+    async fn read_reply(&mut self) -> io::Result<Reply> {
+        tokio::time::timeout(self.timeout, async {
+            let mut code = 0;
+            let mut lines = Vec::new();
+            let mut line = String::new();
+
+            loop {
+                line.clear();
+                if self.stream.read_line(&mut line).await? == 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "Connection closed by SMTP server.",
+                    ));
+                }
+                let line = line.trim_end();
+                let (line_code, is_last) = match (
+                    line.get(0..3).and_then(|code| code.parse::<u16>().ok()),
+                    line.as_bytes().get(3),
+                ) {
+                    (Some(line_code), Some(b'-')) => (line_code, false),
+                    (Some(line_code), Some(b' ') | None) => (line_code, true),
+                    _ => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("Invalid SMTP reply: {:?}", line),
+                        ))
+                    }
+                };
+                if code != 0 && code != line_code {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Inconsistent SMTP reply codes.",
+                    ));
+                }
+                code = line_code;
+                lines.push(line.get(4..).unwrap_or_default().to_string());
+
+                if is_last {
+                    return Ok(Reply { code, lines });
+                }
+            }
+        })
+        .await
+        .map_err(|_| timeout_error())?
+    }
+}
+
+impl Reply {
+    pub fn is_positive_completion(&self) -> bool {
+        (200..300).contains(&self.code)
+    }
+
+    pub fn has_capability(&self, capability: &str) -> bool {
+        self.lines.iter().skip(1).any(|line| {
+            line.split_ascii_whitespace()
+                .next()
+                .map_or(false, |name| name.eq_ignore_ascii_case(capability))
+        })
+    }
+
+    fn has_auth_mechanism(&self, mechanism: &str) -> bool {
+        self.lines.iter().skip(1).any(|line| {
+            let mut words = line.split_ascii_whitespace();
+            words
+                .next()
+                .map_or(false, |name| name.eq_ignore_ascii_case("AUTH"))
+                && words.any(|name| name.eq_ignore_ascii_case(mechanism))
+        })
+    }
+
+    fn assert_positive_completion(&self) -> io::Result<()> {
+        if self.is_positive_completion() {
+            Ok(())
+        } else {
+            Err(self.to_error())
+        }
+    }
+
+    fn assert_code(&self, code: u16) -> io::Result<()> {
+        if self.code == code {
+            Ok(())
+        } else {
+            Err(self.to_error())
+        }
+    }
+
+    fn to_error(&self) -> io::Error {
+        io::Error::new(io::ErrorKind::Other, self.to_string())
+    }
+}
+
+impl Display for Reply {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.code, self.lines.join(" "))
+    }
+}
+
+async fn tls_handshake(
+    stream: TcpStream,
+    server_name: &str,
+    timeout: Duration,
+    tls_connector: &TlsConnector,
+) -> io::Result<TlsStream<TcpStream>> {
+    let domain = ServerName::try_from(server_name)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Failed to parse TLS domain."))?;
+    tokio::time::timeout(timeout, tls_connector.connect(domain, stream))
+        .await
+        .map_err(|_| timeout_error())?
+}
+
+fn timeout_error() -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, "SMTP connection timed out.")
+}
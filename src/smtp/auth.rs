@@ -0,0 +1,267 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap::{base64, principal::schema::AppPasswordScope};
+use jmap_sharing::principal::account::JMAPAccountStore;
+use store::{
+    tracing::{debug, error},
+    AccountId, Store,
+};
+
+use crate::authorization::auth::RemoteAddress;
+
+use super::session::{Account, PendingAuth, Session};
+
+impl<T> Session<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    pub async fn handle_auth(
+        &mut self,
+        mechanism: String,
+        initial_response: Option<String>,
+    ) -> Result<(), ()> {
+        if self.account.is_some() {
+            return self
+                .write_bytes(b"503 5.5.1 Already authenticated.\r\n")
+                .await;
+        } else if self.is_tls_required() {
+            return self
+                .write_bytes(b"538 5.7.11 Encryption required.\r\n")
+                .await;
+        } else if self.mail_from.is_some() {
+            return self
+                .write_bytes(b"503 5.5.1 AUTH not allowed during a mail transaction.\r\n")
+                .await;
+        } else if !matches!(
+            mechanism.as_str(),
+            "PLAIN" | "LOGIN" | "XOAUTH2" | "OAUTHBEARER"
+        ) {
+            return self
+                .write_bytes(b"504 5.5.4 Authentication mechanism not supported.\r\n")
+                .await;
+        }
+
+        let mut pending_auth = PendingAuth {
+            mechanism,
+            username: None,
+        };
+        match initial_response {
+            Some(response) if pending_auth.mechanism == "LOGIN" => {
+                // RFC 4954 allows sending the username as initial response
+                pending_auth.username = match decode_response(response.as_bytes()) {
+                    Some(username) => String::from_utf8_lossy(&username).into_owned().into(),
+                    None => return self.write_invalid_response().await,
+                };
+                self.pending_auth = pending_auth.into();
+                self.parser.expect_auth_response();
+                self.write_bytes(b"334 UGFzc3dvcmQ6\r\n").await
+            }
+            Some(response) => {
+                self.authenticate_sasl(pending_auth, response.into_bytes())
+                    .await
+            }
+            None => {
+                let challenge: &[u8] = if pending_auth.mechanism == "LOGIN" {
+                    b"334 VXNlcm5hbWU6\r\n"
+                } else {
+                    b"334 \r\n"
+                };
+                self.pending_auth = pending_auth.into();
+                self.parser.expect_auth_response();
+                self.write_bytes(challenge).await
+            }
+        }
+    }
+
+    pub async fn handle_auth_response(&mut self, response: Vec<u8>) -> Result<(), ()> {
+        let mut pending_auth = if let Some(pending_auth) = self.pending_auth.take() {
+            pending_auth
+        } else {
+            return self
+                .write_bytes(b"503 5.5.1 No AUTH in progress.\r\n")
+                .await;
+        };
+        if response == b"*" {
+            return self
+                .write_bytes(b"501 5.0.0 Authentication cancelled.\r\n")
+                .await;
+        }
+
+        if pending_auth.mechanism == "LOGIN" && pending_auth.username.is_none() {
+            pending_auth.username = match decode_response(&response) {
+                Some(username) => String::from_utf8_lossy(&username).into_owned().into(),
+                None => return self.write_invalid_response().await,
+            };
+            self.pending_auth = pending_auth.into();
+            self.parser.expect_auth_response();
+            self.write_bytes(b"334 UGFzc3dvcmQ6\r\n").await
+        } else {
+            self.authenticate_sasl(pending_auth, response).await
+        }
+    }
+
+    async fn authenticate_sasl(
+        &mut self,
+        pending_auth: PendingAuth,
+        response: Vec<u8>,
+    ) -> Result<(), ()> {
+        let response = match decode_response(&response) {
+            Some(response) => response,
+            None => return self.write_invalid_response().await,
+        };
+
+        match pending_auth.mechanism.as_str() {
+            "PLAIN" => {
+                // authzid NUL authcid NUL passwd
+                let mut parts = response.split(|&ch| ch == 0);
+                match (parts.next(), parts.next(), parts.next()) {
+                    (Some(_), Some(login), Some(secret)) => {
+                        self.authenticate(
+                            String::from_utf8_lossy(login).into_owned(),
+                            String::from_utf8_lossy(secret).into_owned(),
+                        )
+                        .await
+                    }
+                    _ => self.write_invalid_response().await,
+                }
+            }
+            "LOGIN" => {
+                self.authenticate(
+                    pending_auth.username.unwrap_or_default(),
+                    String::from_utf8_lossy(&response).into_owned(),
+                )
+                .await
+            }
+            _ => {
+                // XOAUTH2: user=<user> ^A auth=Bearer <token> ^A ^A
+                // OAUTHBEARER: gs2-header ^A auth=Bearer <token> ^A ^A
+                let response = String::from_utf8_lossy(&response);
+                if let Some(token) = response.split('\x01').find_map(|part| {
+                    part.strip_prefix("auth=")
+                        .and_then(|auth| auth.split_once(' '))
+                        .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+                        .map(|(_, token)| token.trim().to_string())
+                }) {
+                    if !self.is_auth_allowed().await? {
+                        return Ok(());
+                    }
+                    match self
+                        .core
+                        .validate_access_token("access_token", &token)
+                        .await
+                    {
+                        Ok((account_id, _, _, _)) => self.set_account(account_id).await,
+                        Err(err) => {
+                            debug!("Failed to validate access token: {}", err);
+                            self.write_bytes(b"535 5.7.8 Authentication credentials invalid.\r\n")
+                                .await
+                        }
+                    }
+                } else {
+                    self.write_invalid_response().await
+                }
+            }
+        }
+    }
+
+    async fn authenticate(&mut self, login: String, secret: String) -> Result<(), ()> {
+        if !self.is_auth_allowed().await? {
+            return Ok(());
+        }
+
+        match self
+            .core
+            .authenticate_client(
+                login.trim().to_lowercase(),
+                secret,
+                AppPasswordScope::Smtp.into(),
+            )
+            .await
+        {
+            Ok(Some((account_id, _))) => self.set_account(account_id).await,
+            Ok(None) => {
+                self.write_bytes(b"535 5.7.8 Authentication credentials invalid.\r\n")
+                    .await
+            }
+            Err(err) => {
+                error!("Failed to authenticate: {}", err);
+                self.write_bytes(b"454 4.7.0 Temporary authentication failure.\r\n")
+                    .await
+            }
+        }
+    }
+
+    async fn set_account(&mut self, account_id: AccountId) -> Result<(), ()> {
+        let store = self.core.store.clone();
+        match self
+            .core
+            .spawn_worker(move || store.get_account_addresses(account_id))
+            .await
+        {
+            Ok(addresses) => {
+                self.account = Account {
+                    account_id,
+                    addresses,
+                }
+                .into();
+                self.write_bytes(b"235 2.7.0 Authentication succeeded.\r\n")
+                    .await
+            }
+            Err(err) => {
+                error!("Failed to obtain account details: {}", err);
+                self.write_bytes(b"454 4.7.0 Temporary authentication failure.\r\n")
+                    .await
+            }
+        }
+    }
+
+    // Enforces the rate limit for authentication requests
+    async fn is_auth_allowed(&mut self) -> Result<bool, ()> {
+        if self
+            .core
+            .is_auth_allowed(RemoteAddress::IpAddress(self.peer_addr.ip()))
+            .await
+            .is_ok()
+        {
+            Ok(true)
+        } else {
+            self.write_bytes(b"454 4.7.0 Too many authentication attempts.\r\n")
+                .await?;
+            Ok(false)
+        }
+    }
+
+    async fn write_invalid_response(&mut self) -> Result<(), ()> {
+        self.write_bytes(b"501 5.5.2 Invalid SASL response.\r\n")
+            .await
+    }
+}
+
+fn decode_response(response: &[u8]) -> Option<Vec<u8>> {
+    if response != b"=" {
+        base64::decode(response).ok()
+    } else {
+        Some(Vec::new())
+    }
+}
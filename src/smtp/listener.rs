@@ -0,0 +1,256 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use actix_web::web;
+use store::{
+    config::env_settings::EnvSettings,
+    tracing::{debug, error, info, warn},
+    Store,
+};
+use tokio::{io::AsyncWriteExt, net::TcpListener, sync::watch};
+use tokio_rustls::TlsAcceptor;
+
+use crate::{cluster::rpc::tls::load_tls_server_config, smtp::session::Session, JMAPServer};
+
+const TIMEOUT: Duration = Duration::from_secs(5 * 60); // 5 minutes
+const DEFAULT_SUBMISSION_PORT: u16 = 1587;
+
+pub fn init_smtp() -> (watch::Sender<bool>, watch::Receiver<bool>) {
+    watch::channel::<bool>(true)
+}
+
+pub fn spawn_smtp<T>(
+    core: web::Data<JMAPServer<T>>,
+    settings: &EnvSettings,
+    shutdown_rx: watch::Receiver<bool>,
+) where
+    T: for<'x> Store<'x> + 'static,
+{
+    // Parse bind addresses
+    let bind_ip = settings.parse_ipaddr("submission-bind-addr", "127.0.0.1");
+    let bind_addr = SocketAddr::from((
+        bind_ip,
+        settings
+            .parse("submission-port")
+            .unwrap_or(DEFAULT_SUBMISSION_PORT),
+    ));
+    let tls_bind_addr = settings
+        .parse::<u16>("submission-tls-port")
+        .map(|port| SocketAddr::from((bind_ip, port)));
+
+    // Build TLS acceptor
+    let tls_acceptor = if let (Some(cert_path), Some(key_path)) = (
+        settings.get("submission-cert-path"),
+        settings.get("submission-key-path"),
+    ) {
+        Some(Arc::new(TlsAcceptor::from(Arc::new(
+            load_tls_server_config(&cert_path, &key_path),
+        ))))
+    } else {
+        None
+    };
+    let mut tls_only = settings.parse("submission-tls-only").unwrap_or(false);
+    if tls_only && tls_acceptor.is_none() {
+        warn!("SMTP server is configured to only accept TLS connections, but no TLS certificate was provided.");
+        tls_only = false;
+    }
+    let allow_plain_auth = settings
+        .parse("submission-allow-plain-auth")
+        .unwrap_or(false);
+
+    let hostname = Arc::new(
+        gethostname::gethostname()
+            .to_str()
+            .unwrap_or("localhost")
+            .to_string(),
+    );
+
+    // Submission with STARTTLS (port 587)
+    info!("Starting SMTP submission service at {}...", bind_addr);
+    spawn_listener(
+        core.clone(),
+        bind_addr,
+        tls_acceptor.clone(),
+        tls_only,
+        allow_plain_auth,
+        hostname.clone(),
+        shutdown_rx.clone(),
+    );
+
+    // Submission over implicit TLS (port 465)
+    if let Some(tls_bind_addr) = tls_bind_addr {
+        if tls_acceptor.is_some() {
+            info!(
+                "Starting SMTP submission service over TLS at {}...",
+                tls_bind_addr
+            );
+            spawn_listener(
+                core,
+                tls_bind_addr,
+                tls_acceptor,
+                true,
+                allow_plain_auth,
+                hostname,
+                shutdown_rx,
+            );
+        } else {
+            warn!("SMTP submission TLS port was configured, but no TLS certificate was provided.");
+        }
+    }
+}
+
+fn spawn_listener<T>(
+    core: web::Data<JMAPServer<T>>,
+    bind_addr: SocketAddr,
+    tls_acceptor: Option<Arc<TlsAcceptor>>,
+    tls_only: bool,
+    allow_plain_auth: bool,
+    hostname: Arc<String>,
+    mut shutdown_rx: watch::Receiver<bool>,
+) where
+    T: for<'x> Store<'x> + 'static,
+{
+    tokio::spawn(async move {
+        // Start listening for SMTP connections.
+        let listener = match TcpListener::bind(bind_addr).await {
+            Ok(listener) => listener,
+            Err(err) => {
+                error!("Failed to bind SMTP service to {}: {}", bind_addr, err);
+                return;
+            }
+        };
+
+        let greeting = Arc::new(
+            format!(
+                concat!(
+                    "220 {} Stalwart ESMTP v",
+                    env!("CARGO_PKG_VERSION"),
+                    " at your service.\r\n"
+                ),
+                &hostname
+            )
+            .into_bytes(),
+        );
+
+        loop {
+            tokio::select! {
+                stream = listener.accept() => {
+                    match stream {
+                        Ok((mut stream, peer_addr)) => {
+                            let shutdown_rx = shutdown_rx.clone();
+                            let core = core.clone();
+                            let greeting = greeting.clone();
+                            let tls_acceptor = tls_acceptor.clone();
+                            let hostname = hostname.clone();
+
+                            tokio::spawn(async move {
+                                if tls_only {
+                                    let mut stream = match tls_acceptor.as_ref().unwrap().accept(stream).await {
+                                        Ok(stream) => stream,
+                                        Err(e) => {
+                                            debug!("Failed to accept TLS connection: {}", e);
+                                            return;
+                                        }
+                                    };
+
+                                    // Send greeting
+                                    if let Err(err) = stream.write_all(&greeting).await {
+                                        debug!("Failed to send greeting to {}: {}", peer_addr, err);
+                                        return;
+                                    }
+
+                                    handle_conn(
+                                        Session::new(core, peer_addr, stream.into(), None, allow_plain_auth, hostname),
+                                        shutdown_rx
+                                    ).await;
+                                } else {
+                                    // Send greeting
+                                    if let Err(err) = stream.write_all(&greeting).await {
+                                        debug!("Failed to send greeting to {}: {}", peer_addr, err);
+                                        return;
+                                    }
+
+                                    handle_conn(
+                                        Session::new(core, peer_addr, stream.into(), tls_acceptor, allow_plain_auth, hostname),
+                                        shutdown_rx
+                                    ).await;
+                                }
+                            });
+                        }
+                        Err(err) => {
+                            error!("Failed to accept TCP connection: {}", err);
+                        }
+                    }
+                },
+                _ = shutdown_rx.changed() => {
+                    debug!("SMTP listener shutting down.");
+                    break;
+                }
+            };
+        }
+    });
+}
+
+pub async fn handle_conn<T>(mut session: Session<T>, mut shutdown_rx: watch::Receiver<bool>)
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    let mut buf = vec![0; 4096];
+
+    loop {
+        tokio::select! {
+            result = tokio::time::timeout(
+                TIMEOUT,
+                session.read_bytes(&mut buf)) => {
+                match result {
+                    Ok(Ok(bytes_read)) => {
+                        if bytes_read > 0 {
+                            if session.ingest(&buf[..bytes_read]).await.is_err() {
+                                debug!("Disconnecting client.");
+                                return;
+                            }
+                        } else {
+                            debug!("SMTP connection closed by {}", session.peer_addr);
+                            break;
+                        }
+                    },
+                    Ok(Err(_)) => {
+                        break;
+                    },
+                    Err(_) => {
+                        session.write_bytes(b"221 2.0.0 Disconnecting inactive client.\r\n").await.ok();
+                        debug!("SMTP connection timed out with {}.", session.peer_addr);
+                        break;
+                    }
+                }
+            },
+            _ = shutdown_rx.changed() => {
+                session.write_bytes(b"421 4.3.0 Server shutting down.\r\n").await.ok();
+                debug!("SMTP connection with peer {} shutting down.", session.peer_addr);
+                return;
+            }
+        };
+    }
+}
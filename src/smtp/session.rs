@@ -0,0 +1,426 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{net::SocketAddr, sync::Arc};
+
+use actix_web::web;
+use store::{chrono::Local, tracing::debug, AccountId, Store};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_rustls::TlsAcceptor;
+
+use crate::{
+    lmtp::{
+        request::{Event, Param, Request, RequestParser, State},
+        response::{Extension, Response},
+        session::Stream,
+    },
+    JMAPServer,
+};
+
+const MAX_COMMAND_LENGTH: usize = 4096;
+const MAX_RECIPIENTS: usize = 100;
+pub const AUTH_MECHANISMS: &str = "PLAIN LOGIN XOAUTH2 OAUTHBEARER";
+
+pub struct Session<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    pub core: web::Data<JMAPServer<T>>,
+    pub tls_acceptor: Option<Arc<TlsAcceptor>>,
+    pub allow_plain_auth: bool,
+    pub hostname: Arc<String>,
+    pub parser: RequestParser,
+    pub peer_addr: SocketAddr,
+    pub stream: Stream,
+
+    // State
+    pub remote_hostname: Option<String>,
+    pub account: Option<Account>,
+    pub pending_auth: Option<PendingAuth>,
+    pub mail_from: Option<String>,
+    pub mail_size: Option<usize>,
+    pub rcpt_to: Vec<String>,
+    pub message: Vec<u8>,
+}
+
+pub struct Account {
+    pub account_id: AccountId,
+    pub addresses: Vec<String>,
+}
+
+pub struct PendingAuth {
+    pub mechanism: String,
+    pub username: Option<String>,
+}
+
+impl<T> Session<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    pub fn new(
+        core: web::Data<JMAPServer<T>>,
+        peer_addr: SocketAddr,
+        stream: Stream,
+        tls_acceptor: Option<Arc<TlsAcceptor>>,
+        allow_plain_auth: bool,
+        hostname: Arc<String>,
+    ) -> Self {
+        Self {
+            parser: RequestParser::new(MAX_COMMAND_LENGTH, core.store.config.mail_max_size),
+            tls_acceptor,
+            allow_plain_auth,
+            peer_addr,
+            stream,
+            core,
+            remote_hostname: None,
+            account: None,
+            pending_auth: None,
+            mail_from: None,
+            mail_size: None,
+            rcpt_to: Vec::new(),
+            message: Vec::new(),
+            hostname,
+        }
+    }
+
+    pub async fn ingest(&mut self, bytes: &[u8]) -> Result<(), ()> {
+        let mut bytes = bytes.iter();
+
+        loop {
+            match self.parser.parse(&mut bytes) {
+                Ok(request) => match request {
+                    Request::Ehlo { domain } => {
+                        let mut extensions = vec![
+                            Extension::EnhancedStatusCodes,
+                            Extension::Pipelining,
+                            Extension::Chunking,
+                            Extension::EightBitMime,
+                            Extension::BinaryMime,
+                            Extension::SmtpUtf8,
+                            Extension::Help,
+                            Extension::Size(self.core.store.config.mail_max_size as u32),
+                        ];
+                        if !self.stream.is_tls() && self.tls_acceptor.is_some() {
+                            extensions.push(Extension::StartTls);
+                        }
+                        if self.account.is_none() && !self.is_tls_required() {
+                            extensions.push(Extension::Auth(AUTH_MECHANISMS));
+                        }
+                        self.write_bytes(
+                            &Response::Lhlo {
+                                local_host: self.hostname.as_ref().into(),
+                                remote_host: domain.as_str().into(),
+                                extensions,
+                            }
+                            .into_bytes(),
+                        )
+                        .await?;
+                        self.remote_hostname = domain.into();
+                        self.reset();
+                    }
+                    Request::Helo { domain } => {
+                        self.write_bytes(format!("250 {}\r\n", self.hostname).as_bytes())
+                            .await?;
+                        self.remote_hostname = domain.into();
+                        self.reset();
+                    }
+                    Request::Lhlo { .. } => {
+                        self.write_bytes(b"500 5.5.1 Use EHLO for SMTP submission.\r\n")
+                            .await?;
+                    }
+                    Request::Xforward { .. } => {
+                        self.write_bytes(b"502 5.5.1 Command not implemented.\r\n")
+                            .await?;
+                    }
+                    Request::Auth {
+                        mechanism,
+                        initial_response,
+                    } => {
+                        self.handle_auth(mechanism, initial_response).await?;
+                    }
+                    Request::AuthResponse { response } => {
+                        self.handle_auth_response(response).await?;
+                    }
+                    Request::Mail { sender, params } => {
+                        let addresses = if let Some(account) = &self.account {
+                            &account.addresses
+                        } else {
+                            self.write_bytes(b"530 5.7.0 Authentication required.\r\n")
+                                .await?;
+                            continue;
+                        };
+                        let mail_size = params.iter().find_map(|p| {
+                            if let Param::Size(size) = p {
+                                Some(*size as usize)
+                            } else {
+                                None
+                            }
+                        });
+                        if self.mail_from.is_some() {
+                            self.write_bytes(b"503 5.5.1 Sender already specified.\r\n")
+                                .await?;
+                        } else if matches!(mail_size, Some(size) if size > self.core.store.config.mail_max_size)
+                        {
+                            self.write_bytes(
+                                format!(
+                                    "552 5.3.4 Message exceeds maximum size of {} bytes.\r\n",
+                                    self.core.store.config.mail_max_size
+                                )
+                                .as_bytes(),
+                            )
+                            .await?;
+                        } else if addresses
+                            .iter()
+                            .any(|address| address.eq_ignore_ascii_case(&sender))
+                        {
+                            self.write_bytes(
+                                format!("250 2.1.0 Sender <{}> accepted.\r\n", sender).as_bytes(),
+                            )
+                            .await?;
+                            self.mail_from = sender.to_lowercase().into();
+                            self.mail_size = mail_size;
+                        } else {
+                            self.write_bytes(
+                                format!(
+                                    "550 5.7.1 You are not allowed to send as <{}>.\r\n",
+                                    sender
+                                )
+                                .as_bytes(),
+                            )
+                            .await?;
+                        }
+                    }
+                    Request::Rcpt { recipient, .. } => {
+                        if self.mail_from.is_none() {
+                            self.write_bytes(b"503 5.5.1 Missing MAIL FROM.\r\n")
+                                .await?;
+                        } else if self.rcpt_to.len() >= MAX_RECIPIENTS {
+                            self.write_bytes(b"452 4.5.3 Too many recipients.\r\n")
+                                .await?;
+                        } else if !recipient.contains('@') {
+                            self.write_bytes(b"501 5.1.3 Invalid recipient address.\r\n")
+                                .await?;
+                        } else {
+                            self.write_bytes(
+                                format!("250 2.1.5 Recipient <{}> accepted.\r\n", recipient)
+                                    .as_bytes(),
+                            )
+                            .await?;
+                            if !self.rcpt_to.contains(&recipient) {
+                                self.rcpt_to.push(recipient);
+                            }
+                        }
+                    }
+                    Request::Data { data } => {
+                        self.message = data;
+                        self.submit_message().await?;
+                    }
+                    Request::Bdat { data, is_last } => {
+                        if self.rcpt_to.is_empty() {
+                            self.write_bytes(b"503 5.5.1 Missing RCPT TO.\r\n").await?;
+                        } else if self.message.len() + data.len()
+                            < self.core.store.config.mail_max_size
+                        {
+                            if self.message.is_empty() {
+                                let received = self.build_received();
+                                self.message = Vec::with_capacity(
+                                    self.buffer_capacity(std::cmp::max(1024, data.len()))
+                                        + received.len(),
+                                );
+                                self.message.extend_from_slice(received.as_bytes());
+                            }
+                            self.message.extend_from_slice(&data);
+                            if is_last {
+                                self.submit_message().await?;
+                            } else {
+                                self.write_bytes(b"250 2.1.0 Message chunk accepted.\r\n")
+                                    .await?;
+                            }
+                        } else {
+                            self.write_bytes(
+                                format!(
+                                    "552 5.3.4 Message exceeds maximum size of {} bytes.\r\n",
+                                    self.core.store.config.mail_max_size
+                                )
+                                .as_bytes(),
+                            )
+                            .await?;
+                            self.reset();
+                        }
+                    }
+                    Request::Vrfy { .. } => {
+                        self.write_bytes(b"252 2.5.0 Cannot verify address.\r\n")
+                            .await?;
+                    }
+                    Request::Expn { .. } => {
+                        self.write_bytes(b"502 5.5.1 Command not implemented.\r\n")
+                            .await?;
+                    }
+                    Request::Help { .. } => {
+                        self.write_bytes(
+                            b"250 2.0.0 Help can be found at https://stalw.art/jmap/\r\n",
+                        )
+                        .await?;
+                    }
+                    Request::StartTls => match (&self.stream, &self.tls_acceptor) {
+                        (Stream::Clear(_), Some(_)) => {
+                            self.write_bytes(b"220 2.0.0 Ready to start TLS\r\n")
+                                .await?;
+                            match self
+                                .tls_acceptor
+                                .as_ref()
+                                .unwrap()
+                                .accept(std::mem::take(&mut self.stream).unwrap_clear())
+                                .await
+                            {
+                                Ok(stream) => {
+                                    // Discard any state obtained before the TLS negotiation
+                                    self.stream = stream.into();
+                                    self.remote_hostname = None;
+                                    self.account = None;
+                                    self.pending_auth = None;
+                                    self.reset();
+                                    return Ok(());
+                                }
+                                Err(e) => {
+                                    debug!("Failed to accept TLS connection: {}", e);
+                                    return Err(());
+                                }
+                            };
+                        }
+                        (Stream::Clear(_), None) => {
+                            self.write_bytes(b"501 5.7.4 TLS not configured on this server.\r\n")
+                                .await?;
+                        }
+                        (Stream::Tls(_), _) => {
+                            self.write_bytes(b"501 5.7.0 Already in TLS mode.\r\n")
+                                .await?;
+                        }
+                        (_, _) => {
+                            unreachable!()
+                        }
+                    },
+                    Request::Rset => {
+                        self.reset();
+                        self.write_bytes(b"250 2.0.0 OK\r\n").await?;
+                    }
+                    Request::Noop => {
+                        self.write_bytes(b"250 2.0.0 OK\r\n").await?;
+                    }
+                    Request::Quit => {
+                        self.write_bytes(b"221 2.0.0 Bye\r\n").await?;
+                        return Err(());
+                    }
+                },
+                Err(Event::NeedsMoreBytes) => {
+                    break;
+                }
+                Err(Event::Data) => {
+                    if !self.rcpt_to.is_empty() {
+                        let received = self.build_received();
+                        self.parser.buf =
+                            Vec::with_capacity(self.buffer_capacity(1024) + received.len());
+                        self.parser.buf.extend_from_slice(received.as_bytes());
+                        self.write_bytes(
+                            b"354 3.0.0 Start mail input; end with <CRLF>.<CRLF>.\r\n",
+                        )
+                        .await?;
+                    } else {
+                        self.parser.state = State::Start;
+                        self.write_bytes(b"503 5.5.1 Missing RCPT TO.\r\n").await?;
+                    }
+                }
+                Err(Event::Message { response }) => {
+                    self.write_bytes(&response.into_bytes()).await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Credentials are not accepted in cleartext when STARTTLS is available,
+    /// unless plaintext authentication was explicitly allowed.
+    pub fn is_tls_required(&self) -> bool {
+        !self.stream.is_tls() && self.tls_acceptor.is_some() && !self.allow_plain_auth
+    }
+
+    pub fn reset(&mut self) {
+        self.mail_from = None;
+        self.mail_size = None;
+        self.rcpt_to.clear();
+        self.message = Vec::new();
+    }
+
+    /// Returns the number of bytes to preallocate for the message, the size
+    /// declared by the client is only a hint.
+    fn buffer_capacity(&self, default: usize) -> usize {
+        std::cmp::min(
+            self.mail_size.unwrap_or(default),
+            self.core.store.config.mail_max_size,
+        )
+    }
+
+    pub async fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), ()> {
+        match &mut self.stream {
+            Stream::Clear(stream) => stream.write_all(bytes).await.map_err(|err| {
+                debug!("Failed to write to stream: {}", err);
+            }),
+            Stream::Tls(stream) => stream.write_all(bytes).await.map_err(|err| {
+                debug!("Failed to write to TLS stream: {}", err);
+            }),
+            _ => unreachable!(),
+        }
+    }
+
+    pub async fn read_bytes(&mut self, bytes: &mut [u8]) -> Result<usize, ()> {
+        match &mut self.stream {
+            Stream::Clear(stream) => stream.read(bytes).await.map_err(|err| {
+                debug!("Failed to read from stream: {}", err);
+            }),
+            Stream::Tls(stream) => stream.read(bytes).await.map_err(|err| {
+                debug!("Failed to read from TLS stream: {}", err);
+            }),
+            _ => unreachable!(),
+        }
+    }
+
+    fn build_received(&self) -> String {
+        format!(
+            concat!(
+                "Received: from {} ([{}])\r\n",
+                "\tby {} (Stalwart JMAP) with {};\r\n",
+                "\t{}\r\n"
+            ),
+            self.remote_hostname.as_deref().unwrap_or("unknown"),
+            self.peer_addr.ip(),
+            self.hostname.as_ref(),
+            if self.stream.is_tls() {
+                "ESMTPSA"
+            } else {
+                "ESMTPA"
+            },
+            Local::now().to_rfc2822()
+        )
+    }
+}
@@ -0,0 +1,235 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap::types::{blob::JMAPBlob, jmap::JMAPId};
+use serde_json::{json, Value};
+use store::{blob::BlobId, tracing::error, Store};
+
+use crate::api::request::{execute_internal_calls, InternalCallError};
+
+use super::session::Session;
+
+impl<T> Session<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    pub async fn submit_message(&mut self) -> Result<(), ()> {
+        let message = std::mem::take(&mut self.message);
+        let mail_from = self.mail_from.take().unwrap_or_default();
+        let rcpt_to = std::mem::take(&mut self.rcpt_to);
+        self.mail_size = None;
+
+        match self.submit(mail_from, rcpt_to, message).await {
+            Ok(_) => {
+                self.write_bytes(b"250 2.0.0 Message queued for delivery.\r\n")
+                    .await
+            }
+            Err(InternalCallError::Method(reason)) => {
+                self.write_bytes(format!("554 5.6.0 Message rejected: {}\r\n", reason).as_bytes())
+                    .await
+            }
+            Err(InternalCallError::Unavailable(err)) => {
+                error!("Failed to submit message: {}", err);
+                self.write_bytes(b"451 4.3.0 Temporary server failure.\r\n")
+                    .await
+            }
+        }
+    }
+
+    /// Saves a copy of the message in the Sent mailbox and submits it for
+    /// delivery using the identity that matches the envelope sender.
+    async fn submit(
+        &self,
+        mail_from: String,
+        rcpt_to: Vec<String>,
+        message: Vec<u8>,
+    ) -> Result<String, InternalCallError> {
+        let account_id = JMAPId::from(self.account.as_ref().unwrap().account_id);
+
+        // Obtain the identity and the Sent mailbox, creating them if missing
+        let response = self
+            .jmap(
+                vec![
+                    (
+                        "Identity/get",
+                        json!({
+                            "accountId": account_id,
+                            "properties": ["email"],
+                        }),
+                    ),
+                    (
+                        "Mailbox/query",
+                        json!({
+                            "accountId": account_id,
+                            "filter": { "role": "sent" },
+                        }),
+                    ),
+                ],
+                Vec::new(),
+            )
+            .await?;
+        let mut identity_id = response
+            .get(0)
+            .and_then(|r| r.get("list"))
+            .and_then(|list| list.as_array())
+            .and_then(|list| {
+                list.iter().find(|identity| {
+                    identity
+                        .get("email")
+                        .and_then(|email| email.as_str())
+                        .map_or(false, |email| email.eq_ignore_ascii_case(&mail_from))
+                })
+            })
+            .and_then(|identity| identity.get("id"))
+            .and_then(|id| id.as_str())
+            .map(|id| id.to_string());
+        let mut sent_id = response
+            .get(1)
+            .and_then(|r| r.get("ids"))
+            .and_then(|ids| ids.get(0))
+            .and_then(|id| id.as_str())
+            .map(|id| id.to_string());
+
+        if identity_id.is_none() || sent_id.is_none() {
+            let mut calls = Vec::new();
+            if identity_id.is_none() {
+                calls.push((
+                    "Identity/set",
+                    json!({
+                        "accountId": account_id,
+                        "create": { "i": { "name": "", "email": mail_from } },
+                    }),
+                ));
+            }
+            if sent_id.is_none() {
+                calls.push((
+                    "Mailbox/set",
+                    json!({
+                        "accountId": account_id,
+                        "create": { "s": { "name": "Sent Items", "role": "sent" } },
+                    }),
+                ));
+            }
+            for response in self.jmap(calls, Vec::new()).await? {
+                if let Some(id) = created_id(&response, "i") {
+                    identity_id = id.into();
+                } else if let Some(id) = created_id(&response, "s") {
+                    sent_id = id.into();
+                } else {
+                    return Err(not_created(&response));
+                }
+            }
+        }
+
+        // Save a copy of the message in the Sent mailbox
+        let blob_id = JMAPBlob::new(BlobId::new_external(&message)).to_string();
+        let response = self
+            .jmap(
+                vec![(
+                    "Email/import",
+                    json!({
+                        "accountId": account_id,
+                        "emails": {
+                            "m": {
+                                "blobId": blob_id,
+                                "mailboxIds": { sent_id.unwrap(): true },
+                                "keywords": { "$seen": true },
+                            }
+                        },
+                    }),
+                )],
+                vec![message],
+            )
+            .await?;
+        let email_id = response
+            .first()
+            .and_then(|r| created_id(r, "m"))
+            .ok_or_else(|| not_created(response.first().unwrap_or(&Value::Null)))?;
+
+        // Submit the message for delivery
+        let response = self
+            .jmap(
+                vec![(
+                    "EmailSubmission/set",
+                    json!({
+                        "accountId": account_id,
+                        "create": {
+                            "s": {
+                                "identityId": identity_id.unwrap(),
+                                "emailId": email_id,
+                                "envelope": {
+                                    "mailFrom": { "email": mail_from },
+                                    "rcptTo": rcpt_to
+                                        .iter()
+                                        .map(|rcpt| json!({ "email": rcpt }))
+                                        .collect::<Vec<_>>(),
+                                },
+                            }
+                        },
+                    }),
+                )],
+                Vec::new(),
+            )
+            .await?;
+        response
+            .first()
+            .and_then(|r| created_id(r, "s"))
+            .ok_or_else(|| not_created(response.first().unwrap_or(&Value::Null)))
+    }
+
+    async fn jmap(
+        &self,
+        calls: Vec<(&str, Value)>,
+        blobs: Vec<Vec<u8>>,
+    ) -> Result<Vec<Value>, InternalCallError> {
+        execute_internal_calls(
+            &self.core,
+            self.account.as_ref().unwrap().account_id,
+            calls,
+            blobs,
+        )
+        .await
+    }
+}
+
+fn created_id(response: &Value, create_id: &str) -> Option<String> {
+    response
+        .get("created")?
+        .get(create_id)?
+        .get("id")?
+        .as_str()
+        .map(|id| id.to_string())
+}
+
+fn not_created(response: &Value) -> InternalCallError {
+    InternalCallError::Method(
+        response
+            .get("notCreated")
+            .and_then(|errors| errors.as_object())
+            .and_then(|errors| errors.values().next())
+            .and_then(|error| error.get("description").or_else(|| error.get("type")))
+            .and_then(|description| description.as_str())
+            .unwrap_or("Request failed.")
+            .to_string(),
+    )
+}
@@ -0,0 +1,322 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use actix_web::web;
+use jmap::{
+    base64,
+    types::{blob::JMAPBlob, jmap::JMAPId},
+};
+use jmap_client::{client::Client, mailbox::Role};
+use reqwest::{header, StatusCode};
+use serde_json::json;
+use store::{
+    blob::BlobId,
+    sha2::{Digest, Sha256},
+    Store,
+};
+
+use crate::{
+    tests::jmap::{bypass_authentication, jmap_request},
+    JMAPServer,
+};
+
+const USING: &[&str] = &["urn:ietf:params:jmap:core", "urn:ietf:params:jmap:blob"];
+
+pub async fn test<T>(server: web::Data<JMAPServer<T>>, client: &mut Client)
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    println!("Running blob upload/download tests...");
+
+    // Bypass authentication
+    bypass_authentication(&server).await;
+
+    // Upload a blob larger than a single download chunk
+    let blob = (0..(3 * 1024 * 1024 + 123))
+        .map(|n| (n % 251) as u8)
+        .collect::<Vec<_>>();
+    let upload = client.upload(None, blob.clone(), None).await.unwrap();
+    assert_eq!(upload.size(), blob.len());
+
+    // Uploading the same contents again returns the same blobId
+    assert_eq!(
+        client
+            .upload(None, blob.clone(), None)
+            .await
+            .unwrap()
+            .blob_id(),
+        upload.blob_id()
+    );
+
+    let url = format!(
+        "{}/jmap/download/{}/{}/test.bin",
+        server.base_session.base_url(),
+        JMAPId::new(1),
+        upload.blob_id()
+    );
+
+    // Full download
+    let response = download(&url, &[]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers().get(header::ACCEPT_RANGES).unwrap(),
+        "bytes"
+    );
+    let etag = response
+        .headers()
+        .get(header::ETAG)
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_string();
+    assert_eq!(etag, format!("\"{}\"", upload.blob_id()));
+    assert_eq!(response.bytes().await.unwrap().to_vec(), blob);
+
+    // Conditional requests
+    let response = download(&url, &[(header::IF_NONE_MATCH, &etag)]).await;
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    let response = download(&url, &[(header::IF_NONE_MATCH, "\"other\"")]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Range requests
+    let size = blob.len();
+    for (range, expected) in [
+        ("bytes=0-99", 0..100),
+        ("bytes=1048570-2097160", 1048570..2097161),
+        ("bytes=3145000-", 3145000..size),
+        ("bytes=-50", size - 50..size),
+        ("bytes=100-99999999", 100..size),
+    ] {
+        let response = download(&url, &[(header::RANGE, range)]).await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT, "{}", range);
+        assert_eq!(
+            response.headers().get(header::CONTENT_RANGE).unwrap(),
+            &format!("bytes {}-{}/{}", expected.start, expected.end - 1, size)
+        );
+        assert_eq!(
+            response.bytes().await.unwrap(),
+            &blob[expected],
+            "{}",
+            range
+        );
+    }
+
+    // Unsatisfiable ranges
+    let response = download(&url, &[(header::RANGE, "bytes=99999999-")]).await;
+    assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(
+        response.headers().get(header::CONTENT_RANGE).unwrap(),
+        &format!("bytes */{}", size)
+    );
+
+    // Multiple ranges and mismatched If-Range return the full blob
+    for headers in [
+        vec![(header::RANGE, "bytes=0-1,5-6")],
+        vec![
+            (header::RANGE, "bytes=0-1"),
+            (header::IF_RANGE, "\"other\""),
+        ],
+    ] {
+        let response = download(&url, &headers).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.bytes().await.unwrap().len(), size);
+    }
+
+    // Blob/upload concatenates text, base64 and existing blob data sources
+    let response = jmap_request(
+        &server,
+        None,
+        USING,
+        "Blob/upload",
+        json!({
+            "accountId": JMAPId::new(1).to_string(),
+            "create": {
+                "b1": {
+                    "data": [
+                        {"data:asText": "Hello "},
+                        {"data:asBase64": "d29ybGQ="},
+                        {"blobId": upload.blob_id(), "offset": 65, "length": 3}
+                    ],
+                    "type": "text/plain"
+                },
+                "b2": {
+                    "data": [{"data:asBase64": "not base64!"}]
+                },
+                "b3": {
+                    "data": [{"blobId": upload.blob_id(), "offset": size, "length": 1}]
+                }
+            }
+        }),
+    )
+    .await;
+    let expected = b"Hello worldABC";
+    assert_eq!(response["created"]["b1"]["size"], expected.len());
+    assert_eq!(response["created"]["b1"]["type"], "text/plain");
+    assert_eq!(response["notCreated"]["b2"]["type"], "invalidProperties");
+    assert_eq!(response["notCreated"]["b3"]["type"], "invalidProperties");
+    let blob_id = response["created"]["b1"]["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    // Blob/get returns data, size and digests of the requested range
+    let response = jmap_request(
+        &server,
+        None,
+        USING,
+        "Blob/get",
+        json!({
+            "accountId": JMAPId::new(1).to_string(),
+            "ids": [blob_id],
+            "properties": ["data:asBase64", "data", "size", "digest:sha-256"],
+            "offset": 0,
+            "length": 11
+        }),
+    )
+    .await;
+    let text_blob = &response["list"][0];
+    assert_eq!(text_blob["data:asText"], "Hello world");
+    assert_eq!(text_blob["data:asBase64"], base64::encode("Hello world"));
+    assert_eq!(text_blob["size"], expected.len());
+    assert_eq!(
+        text_blob["digest:sha-256"],
+        base64::encode(Sha256::digest(b"Hello world"))
+    );
+    assert!(text_blob.get("isTruncated").is_none());
+
+    let response = jmap_request(
+        &server,
+        None,
+        USING,
+        "Blob/get",
+        json!({
+            "accountId": JMAPId::new(1).to_string(),
+            "ids": [upload.blob_id()],
+            "properties": ["data:asText", "size"],
+            "offset": 245,
+            "length": 11
+        }),
+    )
+    .await;
+    let binary_blob = &response["list"][0];
+    assert_eq!(binary_blob["data:asText"], serde_json::Value::Null);
+    assert_eq!(binary_blob["isEncodingProblem"], true);
+    assert_eq!(binary_blob["size"], size);
+
+    let response = jmap_request(
+        &server,
+        None,
+        USING,
+        "Blob/get",
+        json!({
+            "accountId": JMAPId::new(1).to_string(),
+            "ids": [blob_id],
+            "properties": ["data:asText"],
+            "offset": 6,
+            "length": 1000
+        }),
+    )
+    .await;
+    assert_eq!(response["list"][0]["isTruncated"], true);
+    assert_eq!(response["list"][0]["data:asText"], "worldABC");
+
+    // Blob/lookup finds the emails, mailboxes and threads referencing a blob
+    let mailbox_id = client
+        .set_default_account_id(JMAPId::new(1).to_string())
+        .mailbox_create("Blob Test", None::<String>, Role::None)
+        .await
+        .unwrap()
+        .take_id();
+    let email = client
+        .email_import(
+            concat!(
+                "From: bill@example.com\r\n",
+                "To: jdoe@example.com\r\n",
+                "Subject: Blob lookup\r\n",
+                "\r\n",
+                "Blob lookup test."
+            )
+            .as_bytes()
+            .to_vec(),
+            [&mailbox_id],
+            None::<Vec<&str>>,
+            None,
+        )
+        .await
+        .unwrap();
+    let missing_blob_id = JMAPBlob::new(BlobId::new_external(b"missing")).to_string();
+    let response = jmap_request(
+        &server,
+        None,
+        USING,
+        "Blob/lookup",
+        json!({
+            "accountId": JMAPId::new(1).to_string(),
+            "typeNames": ["Email", "Mailbox", "Thread"],
+            "ids": [email.blob_id().unwrap(), blob_id, missing_blob_id]
+        }),
+    )
+    .await;
+    assert_eq!(
+        response["list"][0]["matchedIds"],
+        json!({
+            "Email": [email.id().unwrap()],
+            "Mailbox": [mailbox_id],
+            "Thread": [email.thread_id().unwrap()]
+        })
+    );
+    assert_eq!(
+        response["list"][1]["matchedIds"],
+        json!({"Email": [], "Mailbox": [], "Thread": []})
+    );
+    assert_eq!(response["notFound"], json!([missing_blob_id]));
+
+    let response = jmap_request(
+        &server,
+        None,
+        USING,
+        "Blob/lookup",
+        json!({
+            "accountId": JMAPId::new(1).to_string(),
+            "typeNames": ["ContactCard"],
+            "ids": [blob_id]
+        }),
+    )
+    .await;
+    assert_eq!(response["type"], "unknownDataType");
+
+    client.mailbox_destroy(&mailbox_id, true).await.unwrap();
+}
+
+async fn download(url: &str, headers: &[(header::HeaderName, &str)]) -> reqwest::Response {
+    let mut request = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap_or_default()
+        .get(url)
+        .bearer_auth("DO_NOT_ATTEMPT_THIS_AT_HOME");
+    for (name, value) in headers {
+        request = request.header(name.clone(), *value);
+    }
+    request.send().await.unwrap()
+}
@@ -0,0 +1,405 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use actix_web::web;
+use jmap::{types::jmap::JMAPId, SUPERUSER_ID};
+use jmap_client::client::{Client, Credentials};
+use jmap_sharing::principal::{
+    credentials::{base32_decode, hotp},
+    set::JMAPSetPrincipal,
+};
+use reqwest::{Method, StatusCode};
+use store::{
+    ahash::AHashMap,
+    sha2::{Digest, Sha256},
+    Store,
+};
+
+use crate::{
+    authorization::{
+        credentials::{AppPasswordResponse, TOTPEnrollResponse},
+        oauth::OAuthMetadata,
+        oidc::base64url,
+    },
+    tests::{
+        jmap::oauth::{
+            get, get_bytes, parse_code_input, parse_code_redirect, post, post_expect_redirect,
+            post_status, unwrap_token_response,
+        },
+        store::utils::StoreCompareWith,
+    },
+    JMAPServer,
+};
+
+pub async fn test<T>(server: web::Data<JMAPServer<T>>, admin_client: &mut Client)
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    println!("Running app password and two-factor authentication tests...");
+
+    // Create test account
+    let domain_id = admin_client
+        .set_default_account_id(JMAPId::from(SUPERUSER_ID).to_string())
+        .domain_create("example.com")
+        .await
+        .unwrap()
+        .take_id();
+    let jane_id = admin_client
+        .individual_create("jane@example.com", "abcde", "Jane Smith")
+        .await
+        .unwrap()
+        .take_id();
+    let base_url = server.base_session.base_url().to_string();
+    let main_login = ("jane@example.com", "abcde");
+
+    // ------------------------
+    // App passwords
+    // ------------------------
+
+    // Labels are required
+    assert_eq!(
+        request(
+            Method::POST,
+            &format!("{}/auth/app-passwords", base_url),
+            main_login,
+            &[("label", "")],
+        )
+        .await
+        .0,
+        StatusCode::BAD_REQUEST
+    );
+
+    // Create one app password per scope
+    let jmap_password = create_app_password(&base_url, main_login, "Phone", "jmap").await;
+    let smtp_password = create_app_password(&base_url, main_login, "Printer", "smtp").await;
+    assert_eq!(jmap_password.label, "Phone");
+    assert_eq!(smtp_password.label, "Printer");
+    assert!(jmap_password.created_at > 0);
+    let jmap_secret = jmap_password.password.clone().unwrap();
+    let smtp_secret = smtp_password.password.clone().unwrap();
+
+    // Listing app passwords never displays them
+    let (status, bytes) = request(
+        Method::GET,
+        &format!("{}/auth/app-passwords", base_url),
+        ("jane@example.com", &jmap_secret),
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let passwords: Vec<AppPasswordResponse> = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(passwords.len(), 2);
+    for password in passwords {
+        assert!(password.password.is_none());
+        assert_eq!(password.current, password.id == jmap_password.id);
+    }
+
+    // App passwords are restricted to their scope
+    let jane_client = Client::new()
+        .credentials(Credentials::basic("jane@example.com", &jmap_secret))
+        .connect(&base_url)
+        .await
+        .unwrap();
+    assert_eq!(jane_client.default_account_id(), jane_id);
+    assert_unauthorized(&base_url, "jane@example.com", &smtp_secret).await;
+    assert_unauthorized(&base_url, "jane@example.com", "wrong_pass").await;
+
+    // App passwords cannot manage credentials
+    assert_eq!(
+        request(
+            Method::POST,
+            &format!("{}/auth/app-passwords", base_url),
+            ("jane@example.com", &jmap_secret),
+            &[("label", "Laptop")],
+        )
+        .await
+        .0,
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        request(
+            Method::POST,
+            &format!("{}/auth/totp", base_url),
+            ("jane@example.com", &jmap_secret),
+            &[],
+        )
+        .await
+        .0,
+        StatusCode::FORBIDDEN
+    );
+
+    // ------------------------
+    // Two-factor authentication
+    // ------------------------
+
+    // Enroll TOTP
+    let (status, bytes) = request(
+        Method::POST,
+        &format!("{}/auth/totp", base_url),
+        main_login,
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let enrollment: TOTPEnrollResponse = serde_json::from_slice(&bytes).unwrap();
+    assert!(enrollment.uri.starts_with("otpauth://totp/"));
+    assert!(enrollment.uri.contains(&enrollment.secret));
+    let secret = base32_decode(&enrollment.secret).unwrap();
+    let step = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        / 30;
+
+    // Enrollment is completed once a valid code is verified
+    assert_eq!(
+        request(
+            Method::POST,
+            &format!("{}/auth/totp/verify", base_url),
+            main_login,
+            &[("code", "000000")],
+        )
+        .await
+        .0,
+        StatusCode::BAD_REQUEST
+    );
+    assert_eq!(
+        request(
+            Method::POST,
+            &format!("{}/auth/totp/verify", base_url),
+            main_login,
+            &[("code", &totp_code(&secret, step - 1))],
+        )
+        .await
+        .0,
+        StatusCode::NO_CONTENT
+    );
+
+    // Basic auth with the main password is now refused, app passwords still work
+    assert_unauthorized(&base_url, main_login.0, main_login.1).await;
+    Client::new()
+        .credentials(Credentials::basic("jane@example.com", &jmap_secret))
+        .connect(&base_url)
+        .await
+        .unwrap();
+
+    // OAuth logins require a valid authentication code
+    let metadata: OAuthMetadata = get(&format!(
+        "{}/.well-known/oauth-authorization-server",
+        base_url
+    ))
+    .await;
+    let code_verifier = "dBjftJeZ4CVP-mJ0APNTEDh6LcQ0S0dP-Jq0Tq5uWbE";
+    let auth_endpoint = format!(
+        concat!(
+            "{}?response_type=code&client_id=OAuthyMcOAuthFace&state=xyz",
+            "&redirect_uri=https://localhost&code_challenge={}&code_challenge_method=S256"
+        ),
+        metadata.authorization_endpoint,
+        base64url(&Sha256::digest(code_verifier.as_bytes()))
+    );
+    let mut auth_request = AHashMap::from_iter([
+        ("email".to_string(), main_login.0.to_string()),
+        ("password".to_string(), main_login.1.to_string()),
+        (
+            "code".to_string(),
+            parse_code_input(get_bytes(&auth_endpoint).await),
+        ),
+    ]);
+    assert_eq!(
+        post_status(&metadata.authorization_endpoint, &auth_request).await,
+        StatusCode::OK
+    );
+
+    // Codes that were already used are rejected
+    auth_request.insert("otp".to_string(), totp_code(&secret, step - 1));
+    auth_request.insert(
+        "code".to_string(),
+        parse_code_input(get_bytes(&auth_endpoint).await),
+    );
+    assert_eq!(
+        post_status(&metadata.authorization_endpoint, &auth_request).await,
+        StatusCode::OK
+    );
+
+    // A fresh code completes the login
+    auth_request.insert("otp".to_string(), totp_code(&secret, step));
+    auth_request.insert(
+        "code".to_string(),
+        parse_code_input(get_bytes(&auth_endpoint).await),
+    );
+    let code = parse_code_redirect(
+        post_expect_redirect(&metadata.authorization_endpoint, &auth_request).await,
+        "xyz",
+    );
+    let (token, _, _) = unwrap_token_response(
+        post(
+            &metadata.token_endpoint,
+            &AHashMap::from_iter([
+                ("client_id".to_string(), "OAuthyMcOAuthFace".to_string()),
+                ("redirect_uri".to_string(), "https://localhost".to_string()),
+                ("grant_type".to_string(), "authorization_code".to_string()),
+                ("code_verifier".to_string(), code_verifier.to_string()),
+                ("code".to_string(), code),
+            ]),
+        )
+        .await,
+    );
+
+    // Disable two-factor authentication
+    assert_eq!(
+        request(
+            Method::DELETE,
+            &format!("{}/auth/totp", base_url),
+            ("jane@example.com", &jmap_secret),
+            &[("code", "000000")],
+        )
+        .await
+        .0,
+        StatusCode::FORBIDDEN
+    );
+    // A bearer token alone is not enough to disable the second factor
+    for (code, expected_status) in [
+        ("000000".to_string(), StatusCode::FORBIDDEN),
+        (totp_code(&secret, step + 1), StatusCode::NO_CONTENT),
+    ] {
+        assert_eq!(
+            reqwest::Client::builder()
+                .timeout(Duration::from_millis(500))
+                .danger_accept_invalid_certs(true)
+                .build()
+                .unwrap_or_default()
+                .delete(&format!("{}/auth/totp", base_url))
+                .bearer_auth(&token)
+                .form(&[("code", code)])
+                .send()
+                .await
+                .unwrap()
+                .status(),
+            expected_status
+        );
+    }
+    Client::new()
+        .credentials(Credentials::basic(main_login.0, main_login.1))
+        .connect(&base_url)
+        .await
+        .unwrap();
+
+    // ------------------------
+    // Revocation
+    // ------------------------
+
+    // Revoked app passwords can no longer be used
+    for password in [&jmap_password, &smtp_password] {
+        assert_eq!(
+            request(
+                Method::DELETE,
+                &format!("{}/auth/app-passwords/{}", base_url, password.id),
+                main_login,
+                &[],
+            )
+            .await
+            .0,
+            StatusCode::NO_CONTENT
+        );
+    }
+    assert_eq!(
+        request(
+            Method::DELETE,
+            &format!("{}/auth/app-passwords/{}", base_url, jmap_password.id),
+            main_login,
+            &[],
+        )
+        .await
+        .0,
+        StatusCode::NOT_FOUND
+    );
+    assert_unauthorized(&base_url, "jane@example.com", &jmap_secret).await;
+
+    // Destroy test accounts
+    for principal_id in [jane_id, domain_id] {
+        admin_client.principal_destroy(&principal_id).await.unwrap();
+    }
+    server.store.principal_purge().unwrap();
+    server.store.assert_is_empty();
+}
+
+async fn create_app_password(
+    base_url: &str,
+    login: (&str, &str),
+    label: &str,
+    scope: &str,
+) -> AppPasswordResponse {
+    let (status, bytes) = request(
+        Method::POST,
+        &format!("{}/auth/app-passwords", base_url),
+        login,
+        &[("label", label), ("scope", scope)],
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    serde_json::from_slice(&bytes).unwrap()
+}
+
+async fn request(
+    method: Method,
+    url: &str,
+    (user, pass): (&str, &str),
+    params: &[(&str, &str)],
+) -> (StatusCode, Vec<u8>) {
+    let response = reqwest::Client::builder()
+        .timeout(Duration::from_millis(500))
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap_or_default()
+        .request(method, url)
+        .basic_auth(user, Some(pass))
+        .form(params)
+        .send()
+        .await
+        .unwrap();
+    (
+        response.status(),
+        response.bytes().await.unwrap_or_default().to_vec(),
+    )
+}
+
+async fn assert_unauthorized(base_url: &str, user: &str, pass: &str) {
+    match Client::new()
+        .credentials(Credentials::basic(user, pass))
+        .connect(base_url)
+        .await
+    {
+        Ok(_) => panic!("Expected unauthorized access."),
+        Err(err) => {
+            let err = err.to_string();
+            assert!(err.contains("Unauthorized"), "{}", err);
+        }
+    }
+}
+
+fn totp_code(secret: &[u8], step: u64) -> String {
+    format!("{:06}", hotp(secret, step).unwrap())
+}
@@ -0,0 +1,500 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+};
+
+use actix_web::web;
+use jmap::{principal::schema::Type, types::jmap::JMAPId, SUPERUSER_ID};
+use jmap_client::client::{Client, Credentials};
+use jmap_sharing::principal::{account::JMAPAccountStore, ldap::JMAPLdap, set::JMAPSetPrincipal};
+use store::{config::env_settings::EnvSettings, RecipientType, Store};
+
+use crate::{tests::store::utils::StoreCompareWith, JMAPServer};
+
+const LDAP_PORT: u16 = 9012;
+const BASE_DN: &str = "dc=example,dc=com";
+const BIND_DN: &str = "cn=admin,dc=example,dc=com";
+const BIND_SECRET: &str = "adminpass";
+
+struct MockEntry {
+    dn: String,
+    password: Option<String>,
+    attributes: Vec<(String, Vec<String>)>,
+}
+
+enum MockFilter {
+    And(Vec<MockFilter>),
+    Or(Vec<MockFilter>),
+    Not(Box<MockFilter>),
+    Equal(String, String),
+    Present(String),
+}
+
+pub fn add_ldap_settings(settings: &mut EnvSettings) {
+    for (key, value) in [
+        ("ldap-url", format!("ldap://127.0.0.1:{}", LDAP_PORT)),
+        ("ldap-base-dn", BASE_DN.to_string()),
+        ("ldap-bind-dn", BIND_DN.to_string()),
+        ("ldap-bind-secret", BIND_SECRET.to_string()),
+    ] {
+        settings.set_value(key.to_string(), value);
+    }
+}
+
+pub async fn test<T>(server: web::Data<JMAPServer<T>>, admin_client: &mut Client)
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    println!("Running LDAP directory tests...");
+    let directory = spawn_mock_ldap_server();
+    let base_url = server.base_session.base_url();
+
+    // Create test domain and a local account
+    let domain_id = admin_client
+        .set_default_account_id(JMAPId::from(SUPERUSER_ID).to_string())
+        .domain_create("example.com")
+        .await
+        .unwrap()
+        .take_id();
+    let john_id = admin_client
+        .individual_create("jdoe@example.com", "12345", "John Doe")
+        .await
+        .unwrap()
+        .take_id();
+
+    // Directory users are provisioned on their first login
+    let jane_client = Client::new()
+        .credentials(Credentials::basic("jane@example.com", "janepass"))
+        .connect(base_url)
+        .await
+        .unwrap();
+    let jane_id = JMAPId::parse(jane_client.default_account_id())
+        .unwrap()
+        .get_document_id();
+    assert_eq!(
+        server.store.get_account_details(jane_id).unwrap().unwrap(),
+        (
+            "jane@example.com".to_string(),
+            "Jane Smith".to_string(),
+            Type::Individual
+        )
+    );
+    assert_eq!(
+        server.store.get_account_addresses(jane_id).unwrap(),
+        vec![
+            "jane@example.com".to_string(),
+            "jane.smith@example.com".to_string()
+        ]
+    );
+
+    // Invalid directory passwords are rejected
+    assert!(Client::new()
+        .credentials(Credentials::basic("jane@example.com", "wrongpass"))
+        .connect(base_url)
+        .await
+        .is_err());
+    assert!(Client::new()
+        .credentials(Credentials::basic("jane@example.com", ""))
+        .connect(base_url)
+        .await
+        .is_err());
+
+    // Local accounts are used as a fallback
+    Client::new()
+        .credentials(Credentials::basic("jdoe@example.com", "12345"))
+        .connect(base_url)
+        .await
+        .unwrap();
+    assert!(Client::new()
+        .credentials(Credentials::basic("jdoe@example.com", "wrongpass"))
+        .connect(base_url)
+        .await
+        .is_err());
+
+    // Synchronize users and groups
+    let store = server.store.clone();
+    assert!(server
+        .spawn_worker(move || store.ldap_sync())
+        .await
+        .unwrap()
+        .is_some());
+    let bob_id = server
+        .store
+        .find_individual("bob@example.com")
+        .unwrap()
+        .unwrap();
+    let acl_token = server.store.get_acl_token(jane_id).unwrap();
+    assert_eq!(acl_token.member_of.len(), 2);
+    let sales_id = acl_token.member_of[1];
+    assert_eq!(
+        server.store.get_account_details(sales_id).unwrap().unwrap(),
+        (
+            "sales@example.com".to_string(),
+            "Sales".to_string(),
+            Type::Group
+        )
+    );
+    assert!(server
+        .store
+        .get_acl_token(bob_id)
+        .unwrap()
+        .member_of
+        .contains(&sales_id));
+
+    // Unchanged directories do not produce changes
+    let store = server.store.clone();
+    assert_eq!(
+        server
+            .spawn_worker(move || store.ldap_sync())
+            .await
+            .unwrap(),
+        None
+    );
+
+    // Group recipients are expanded from the directory
+    let store = server.store.clone();
+    match server
+        .spawn_worker(move || store.expand_rcpt("sales@example.com".to_string()))
+        .await
+        .unwrap()
+        .as_ref()
+    {
+        RecipientType::List(list) => {
+            assert_eq!(
+                list,
+                &vec![
+                    (jane_id, "jane@example.com".to_string()),
+                    (bob_id, "bob@example.com".to_string())
+                ]
+            );
+        }
+        other => panic!("Unexpected recipient type: {:?}", other),
+    }
+
+    // Directory changes are synchronized
+    directory
+        .lock()
+        .unwrap()
+        .iter_mut()
+        .find(|entry| entry.dn.starts_with("uid=jane,"))
+        .unwrap()
+        .attributes
+        .retain(|(name, _)| name != "mailAlternateAddress");
+    let store = server.store.clone();
+    assert!(server
+        .spawn_worker(move || store.ldap_sync())
+        .await
+        .unwrap()
+        .is_some());
+    assert_eq!(
+        server.store.get_account_addresses(jane_id).unwrap(),
+        vec!["jane@example.com".to_string()]
+    );
+
+    // Destroy test accounts
+    for principal_id in [
+        JMAPId::from(sales_id).to_string(),
+        JMAPId::from(jane_id).to_string(),
+        JMAPId::from(bob_id).to_string(),
+        john_id,
+        domain_id,
+    ] {
+        admin_client.principal_destroy(&principal_id).await.unwrap();
+    }
+    server.store.principal_purge().unwrap();
+    server.store.assert_is_empty();
+}
+
+fn spawn_mock_ldap_server() -> Arc<Mutex<Vec<MockEntry>>> {
+    let directory = Arc::new(Mutex::new(vec![
+        MockEntry::new(
+            "uid=jane,ou=people,dc=example,dc=com",
+            Some("janepass"),
+            &[
+                ("objectClass", "inetOrgPerson"),
+                ("mail", "jane@example.com"),
+                ("cn", "Jane Smith"),
+                ("mailAlternateAddress", "jane.smith@example.com"),
+            ],
+        ),
+        MockEntry::new(
+            "uid=bob,ou=people,dc=example,dc=com",
+            Some("bobpass"),
+            &[
+                ("objectClass", "inetOrgPerson"),
+                ("mail", "bob@example.com"),
+                ("cn", "Bob Jones"),
+            ],
+        ),
+        MockEntry::new(
+            "cn=sales,ou=groups,dc=example,dc=com",
+            None,
+            &[
+                ("objectClass", "groupOfNames"),
+                ("mail", "sales@example.com"),
+                ("cn", "Sales"),
+                ("member", "uid=jane,ou=people,dc=example,dc=com"),
+                ("member", "uid=bob,ou=people,dc=example,dc=com"),
+            ],
+        ),
+        MockEntry::new(BIND_DN, Some(BIND_SECRET), &[("cn", "admin")]),
+    ]));
+    let listener = TcpListener::bind(("127.0.0.1", LDAP_PORT)).unwrap();
+    let directory_ = directory.clone();
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let directory = directory_.clone();
+            std::thread::spawn(move || handle_connection(stream, directory));
+        }
+    });
+
+    directory
+}
+
+fn handle_connection(mut stream: TcpStream, directory: Arc<Mutex<Vec<MockEntry>>>) {
+    while let Some(message) = read_message(&mut stream) {
+        let (_, message_id, rest) = parse_element(&message).unwrap();
+        let (op, request, _) = parse_element(rest).unwrap();
+        let mut response = Vec::new();
+
+        match op {
+            // BindRequest
+            0x60 => {
+                let (_, _version, rest) = parse_element(request).unwrap();
+                let (_, dn, rest) = parse_element(rest).unwrap();
+                let (_, password, _) = parse_element(rest).unwrap();
+                let dn = String::from_utf8_lossy(dn).to_lowercase();
+                let password = String::from_utf8_lossy(password).into_owned();
+                let is_valid = directory.lock().unwrap().iter().any(|entry| {
+                    entry.dn.to_lowercase() == dn && entry.password.as_ref() == Some(&password)
+                });
+                response.push(ldap_result(0x61, if is_valid { 0 } else { 49 }));
+            }
+            // SearchRequest
+            0x63 => {
+                let (_, base_dn, rest) = parse_element(request).unwrap();
+                let (_, scope, rest) = parse_element(rest).unwrap();
+                let (_, _deref, rest) = parse_element(rest).unwrap();
+                let (_, _size_limit, rest) = parse_element(rest).unwrap();
+                let (_, _time_limit, rest) = parse_element(rest).unwrap();
+                let (_, _types_only, rest) = parse_element(rest).unwrap();
+                let (filter_tag, filter, rest) = parse_element(rest).unwrap();
+                let (_, mut attributes, _) = parse_element(rest).unwrap();
+                let filter = MockFilter::parse(filter_tag, filter);
+                let base_dn = String::from_utf8_lossy(base_dn).to_lowercase();
+                let mut requested = Vec::new();
+                while let Some((_, name, rest)) = parse_element(attributes) {
+                    requested.push(String::from_utf8_lossy(name).to_lowercase());
+                    attributes = rest;
+                }
+
+                for entry in directory.lock().unwrap().iter() {
+                    let dn = entry.dn.to_lowercase();
+                    let in_scope = if scope == [0] {
+                        dn == base_dn
+                    } else {
+                        dn == base_dn || dn.ends_with(&format!(",{}", base_dn))
+                    };
+                    if in_scope && filter.matches(entry) {
+                        let mut attributes = Vec::new();
+                        for (name, values) in &entry.attributes {
+                            if requested.is_empty() || requested.contains(&name.to_lowercase()) {
+                                let values = values
+                                    .iter()
+                                    .flat_map(|value| ber(0x04, value.as_bytes()))
+                                    .collect::<Vec<_>>();
+                                attributes.extend(ber(
+                                    0x30,
+                                    &[ber(0x04, name.as_bytes()), ber(0x31, &values)].concat(),
+                                ));
+                            }
+                        }
+                        response.push(ber(
+                            0x64,
+                            &[ber(0x04, entry.dn.as_bytes()), ber(0x30, &attributes)].concat(),
+                        ));
+                    }
+                }
+                response.push(ldap_result(0x65, 0));
+            }
+            // UnbindRequest
+            0x42 => {
+                return;
+            }
+            _ => {
+                panic!("Unsupported LDAP operation {:02x}", op);
+            }
+        }
+
+        for op in response {
+            stream
+                .write_all(&ber(0x30, &[ber(0x02, message_id), op].concat()))
+                .unwrap();
+        }
+        stream.flush().unwrap();
+    }
+}
+
+impl MockEntry {
+    fn new(dn: &str, password: Option<&str>, attributes: &[(&str, &str)]) -> Self {
+        let mut entry = MockEntry {
+            dn: dn.to_string(),
+            password: password.map(|p| p.to_string()),
+            attributes: Vec::new(),
+        };
+        for (name, value) in attributes {
+            if let Some((_, values)) = entry
+                .attributes
+                .iter_mut()
+                .find(|(attribute, _)| attribute == name)
+            {
+                values.push(value.to_string());
+            } else {
+                entry
+                    .attributes
+                    .push((name.to_string(), vec![value.to_string()]));
+            }
+        }
+        entry
+    }
+
+    fn values(&self, name: &str) -> Option<&Vec<String>> {
+        self.attributes
+            .iter()
+            .find(|(attribute, _)| attribute.eq_ignore_ascii_case(name))
+            .map(|(_, values)| values)
+    }
+}
+
+impl MockFilter {
+    fn parse(tag: u8, mut content: &[u8]) -> Self {
+        match tag {
+            0xa0 | 0xa1 => {
+                let mut filters = Vec::new();
+                while let Some((tag, filter, rest)) = parse_element(content) {
+                    filters.push(MockFilter::parse(tag, filter));
+                    content = rest;
+                }
+                if tag == 0xa0 {
+                    MockFilter::And(filters)
+                } else {
+                    MockFilter::Or(filters)
+                }
+            }
+            0xa2 => {
+                let (tag, filter, _) = parse_element(content).unwrap();
+                MockFilter::Not(Box::new(MockFilter::parse(tag, filter)))
+            }
+            0xa3 => {
+                let (_, name, rest) = parse_element(content).unwrap();
+                let (_, value, _) = parse_element(rest).unwrap();
+                MockFilter::Equal(
+                    String::from_utf8_lossy(name).into_owned(),
+                    String::from_utf8_lossy(value).into_owned(),
+                )
+            }
+            0x87 => MockFilter::Present(String::from_utf8_lossy(content).into_owned()),
+            _ => panic!("Unsupported LDAP filter {:02x}", tag),
+        }
+    }
+
+    fn matches(&self, entry: &MockEntry) -> bool {
+        match self {
+            MockFilter::And(filters) => filters.iter().all(|f| f.matches(entry)),
+            MockFilter::Or(filters) => filters.iter().any(|f| f.matches(entry)),
+            MockFilter::Not(filter) => !filter.matches(entry),
+            MockFilter::Equal(name, value) if name.eq_ignore_ascii_case("entryDN") => {
+                entry.dn.eq_ignore_ascii_case(value)
+            }
+            MockFilter::Equal(name, value) => entry.values(name).map_or(false, |values| {
+                values.iter().any(|v| v.eq_ignore_ascii_case(value))
+            }),
+            MockFilter::Present(name) => {
+                name.eq_ignore_ascii_case("objectClass") || entry.values(name).is_some()
+            }
+        }
+    }
+}
+
+fn read_message(stream: &mut TcpStream) -> Option<Vec<u8>> {
+    let mut header = [0u8; 2];
+    stream.read_exact(&mut header).ok()?;
+    let mut message = header.to_vec();
+    let len = if header[1] & 0x80 != 0 {
+        let mut len_bytes = vec![0u8; (header[1] & 0x7f) as usize];
+        stream.read_exact(&mut len_bytes).ok()?;
+        message.extend_from_slice(&len_bytes);
+        len_bytes
+            .into_iter()
+            .fold(0usize, |len, byte| (len << 8) | byte as usize)
+    } else {
+        header[1] as usize
+    };
+    let mut content = vec![0u8; len];
+    stream.read_exact(&mut content).ok()?;
+    message.extend(content);
+    Some(message)
+}
+
+fn parse_element(bytes: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let tag = *bytes.first()?;
+    let len_byte = *bytes.get(1)?;
+    let (len, offset) = if len_byte & 0x80 != 0 {
+        let num_bytes = (len_byte & 0x7f) as usize;
+        (
+            bytes
+                .get(2..2 + num_bytes)?
+                .iter()
+                .fold(0usize, |len, byte| (len << 8) | *byte as usize),
+            2 + num_bytes,
+        )
+    } else {
+        (len_byte as usize, 2)
+    };
+    let content = bytes.get(offset..offset + len)?;
+    Some((tag, content, &bytes[offset + len..]))
+}
+
+fn ber(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![tag];
+    if content.len() < 0x80 {
+        bytes.push(content.len() as u8);
+    } else {
+        bytes.push(0x84);
+        bytes.extend_from_slice(&(content.len() as u32).to_be_bytes());
+    }
+    bytes.extend_from_slice(content);
+    bytes
+}
+
+fn ldap_result(op: u8, result_code: u8) -> Vec<u8> {
+    ber(
+        op,
+        &[ber(0x0a, &[result_code]), ber(0x04, b""), ber(0x04, b"")].concat(),
+    )
+}
@@ -26,7 +26,9 @@ use std::{path::PathBuf, sync::Arc, time::Duration};
 use actix_web::{dev::ServerHandle, web};
 use jmap::{types::jmap::JMAPId, SUPERUSER_ID};
 use jmap_client::client::{Client, Credentials};
-use store::{core::acl::ACLToken, Store};
+use reqwest::header;
+use serde_json::json;
+use store::{config::env_settings::EnvSettings, core::acl::ACLToken, Store};
 use store_rocksdb::RocksDB;
 use tokio::sync::oneshot;
 
@@ -40,8 +42,12 @@ use super::store::utils::{destroy_temp_dir, init_settings};
 
 pub mod acl;
 pub mod authorization;
+pub mod blob;
+pub mod credentials;
 pub mod event_source;
+pub mod ldap;
 pub mod oauth;
+pub mod oidc_login;
 pub mod push_subscription;
 pub mod references;
 pub mod stress_test;
@@ -57,6 +63,17 @@ where
     T: for<'x> Store<'x> + 'static,
 {
     let (settings, temp_dir) = init_settings(test_name, peer_num, total_peers, delete_if_exists);
+    let (server, client, handle) = start_jmap_server(settings).await;
+
+    (server, client, temp_dir, handle)
+}
+
+pub async fn start_jmap_server<T>(
+    settings: EnvSettings,
+) -> (web::Data<JMAPServer<T>>, Client, ServerHandle)
+where
+    T: for<'x> Store<'x> + 'static,
+{
     let server = init_jmap_server::<T>(&settings, None);
 
     // Start web server
@@ -83,7 +100,7 @@ where
         .unwrap();
     client.set_default_account_id(JMAPId::new(1));
 
-    (server, client, temp_dir, handle)
+    (server, client, handle)
 }
 
 pub async fn init_jmap_tests<T>(test_name: &str) -> (web::Data<JMAPServer<T>>, Client, PathBuf)
@@ -131,6 +148,50 @@ where
         .await;
 }
 
+// Sends a single method call using the provided capabilities, authenticating
+// either with a login and password or with the bypass token.
+pub async fn jmap_request<T>(
+    server: &JMAPServer<T>,
+    login: Option<(&str, &str)>,
+    using: &[&str],
+    method: &str,
+    arguments: serde_json::Value,
+) -> serde_json::Value
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    let request = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap_or_default()
+        .post(server.base_session.api_url());
+    let request = if let Some((login, secret)) = login {
+        request.basic_auth(login, Some(secret))
+    } else {
+        request.bearer_auth("DO_NOT_ATTEMPT_THIS_AT_HOME")
+    };
+
+    let mut response: serde_json::Value = serde_json::from_slice(
+        &request
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(
+                json!({
+                    "using": using,
+                    "methodCalls": [[method, arguments, "c0"]],
+                })
+                .to_string(),
+            )
+            .send()
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap(),
+    )
+    .unwrap();
+    response["methodResponses"][0][1].take()
+}
+
 #[actix_web::test]
 #[ignore]
 async fn jmap_core_tests() {
@@ -138,11 +199,37 @@ async fn jmap_core_tests() {
 
     // Run tests
     oauth::test(server.clone(), &mut client).await;
+    credentials::test(server.clone(), &mut client).await;
     acl::test(server.clone(), &mut client).await;
     authorization::test(server.clone(), &mut client).await;
+    blob::test(server.clone(), &mut client).await;
     event_source::test(server.clone(), &mut client).await;
     push_subscription::test(server.clone(), &mut client).await;
     websocket::test(server.clone(), &mut client).await;
 
     destroy_temp_dir(&temp_dir);
 }
+
+#[actix_web::test]
+#[ignore]
+async fn jmap_oidc_login_tests() {
+    let (mut settings, temp_dir) = init_settings("jmap_oidc_login_tests", 1, 1, true);
+    oidc_login::add_oidc_settings(&mut settings);
+    let (server, mut client, _) = start_jmap_server::<RocksDB>(settings).await;
+
+    oidc_login::test(server, &mut client).await;
+
+    destroy_temp_dir(&temp_dir);
+}
+
+#[actix_web::test]
+#[ignore]
+async fn jmap_ldap_tests() {
+    let (mut settings, temp_dir) = init_settings("jmap_ldap_tests", 1, 1, true);
+    ldap::add_ldap_settings(&mut settings);
+    let (server, mut client, _) = start_jmap_server::<RocksDB>(settings).await;
+
+    ldap::test(server, &mut client).await;
+
+    destroy_temp_dir(&temp_dir);
+}
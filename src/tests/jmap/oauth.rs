@@ -24,18 +24,29 @@
 use std::time::Duration;
 
 use actix_web::web::{self, Bytes};
-use jmap::{types::jmap::JMAPId, SUPERUSER_ID};
+use ed25519_dalek::{PublicKey, Signature, Verifier};
+use jmap::{base64, types::jmap::JMAPId, SUPERUSER_ID};
 use jmap_client::{
     client::{Client, Credentials},
     mailbox::query::Filter,
 };
 use jmap_sharing::principal::set::JMAPSetPrincipal;
-use reqwest::{header, redirect::Policy};
+use reqwest::{header, redirect::Policy, StatusCode};
 use serde::de::DeserializeOwned;
-use store::{ahash::AHashMap, Store};
+use store::{
+    ahash::AHashMap,
+    sha2::{Digest, Sha256},
+    Store,
+};
 
 use crate::{
-    authorization::oauth::{DeviceAuthResponse, ErrorType, OAuthMetadata, TokenResponse},
+    authorization::{
+        oauth::{
+            DeviceAuthResponse, ErrorType, GrantResponse, OAuthMetadata, TokenIntrospectResponse,
+            TokenResponse,
+        },
+        oidc::{base64url, IdTokenClaims, JsonWebKeySet, OpenIdMetadata, UserInfo},
+    },
     tests::store::utils::StoreCompareWith,
     JMAPServer,
 };
@@ -71,10 +82,25 @@ where
     // Authorization code flow
     // ------------------------
 
+    // Authorization requests without a PKCE code challenge are rejected
+    assert_eq!(
+        get_status(&format!(
+            "{}?response_type=code&client_id=OAuthyMcOAuthFace&state=xyz&redirect_uri=https://localhost",
+            metadata.authorization_endpoint
+        ))
+        .await,
+        StatusCode::BAD_REQUEST
+    );
+
     // Build authorization request
+    let code_verifier = "dBjftJeZ4CVP-mJ0APNTEDh6LcQ0S0dP-Jq0Tq5uWbE";
     let auth_endpoint = format!(
-        "{}?response_type=token&client_id=OAuthyMcOAuthFace&state=xyz&redirect_uri=https://localhost",
-        metadata.authorization_endpoint
+        concat!(
+            "{}?response_type=code&client_id=OAuthyMcOAuthFace&state=xyz",
+            "&redirect_uri=https://localhost&code_challenge={}&code_challenge_method=S256"
+        ),
+        metadata.authorization_endpoint,
+        base64url(&Sha256::digest(code_verifier.as_bytes()))
     );
     let mut auth_request = AHashMap::from_iter([
         ("email".to_string(), "jdoe@example.com".to_string()),
@@ -127,8 +153,24 @@ where
         }
     );
 
-    // Obtain token
+    // The PKCE code verifier has to match the code challenge
     token_params.insert("redirect_uri".to_string(), "https://localhost".to_string());
+    assert_eq!(
+        post::<TokenResponse>(&metadata.token_endpoint, &token_params).await,
+        TokenResponse::Error {
+            error: ErrorType::InvalidGrant
+        }
+    );
+    token_params.insert("code_verifier".to_string(), "wrong_verifier".to_string());
+    assert_eq!(
+        post::<TokenResponse>(&metadata.token_endpoint, &token_params).await,
+        TokenResponse::Error {
+            error: ErrorType::InvalidGrant
+        }
+    );
+
+    // Obtain token
+    token_params.insert("code_verifier".to_string(), code_verifier.to_string());
     let (token, _, _) = unwrap_token_response(post(&metadata.token_endpoint, &token_params).await);
 
     // Connect to account using token and attempt to search
@@ -145,6 +187,79 @@ where
         .ids()
         .is_empty());
 
+    // ------------------------
+    // OpenID Connect
+    // ------------------------
+
+    // Obtain OpenID Connect metadata and signing keys
+    let oidc_metadata: OpenIdMetadata = get(&format!(
+        "{}/.well-known/openid-configuration",
+        server.base_session.base_url()
+    ))
+    .await;
+    assert_eq!(oidc_metadata.oauth.issuer, metadata.issuer);
+    assert_eq!(
+        oidc_metadata.oauth.code_challenge_methods_supported,
+        vec!["S256".to_string()]
+    );
+    let jwks: JsonWebKeySet = get(oidc_metadata.oauth.jwks_uri.as_ref().unwrap()).await;
+    assert_eq!(jwks.keys.len(), 1);
+    assert_eq!(
+        oidc_metadata.id_token_signing_alg_values_supported,
+        vec![jwks.keys[0].alg.to_string()]
+    );
+
+    // Request an ID token using the authorization code flow
+    auth_request.insert(
+        "code".to_string(),
+        parse_code_input(
+            get_bytes(&format!(
+                "{}&scope=openid%20email&nonce=n-0S6_WzA2Mj",
+                auth_endpoint
+            ))
+            .await,
+        ),
+    );
+    token_params.insert(
+        "code".to_string(),
+        parse_code_redirect(
+            post_expect_redirect(&metadata.authorization_endpoint, &auth_request).await,
+            "xyz",
+        ),
+    );
+    let (token, id_token) = match post(&metadata.token_endpoint, &token_params).await {
+        TokenResponse::Granted {
+            access_token,
+            id_token: Some(id_token),
+            scope,
+            ..
+        } => {
+            assert_eq!(scope.unwrap(), "openid email");
+            (access_token, id_token)
+        }
+        response => panic!("Expected an ID token, got {:?}", response),
+    };
+
+    // Verify the ID token signature and claims
+    let claims = verify_id_token(&id_token, &jwks);
+    assert_eq!(claims.iss, server.base_session.base_url());
+    assert_eq!(claims.aud, "OAuthyMcOAuthFace");
+    assert_eq!(claims.nonce.unwrap(), "n-0S6_WzA2Mj");
+    assert!(claims.exp > claims.iat);
+    let user_info = UserInfo {
+        sub: john_id.to_string(),
+        name: "John Doe".to_string().into(),
+        email: "jdoe@example.com".to_string().into(),
+        picture: None,
+    };
+    assert_eq!(claims.user, user_info);
+
+    // The same claims are returned by the userinfo endpoint
+    assert_eq!(
+        get_with_token::<UserInfo>(&oidc_metadata.userinfo_endpoint, &token).await,
+        user_info
+    );
+
     // ------------------------
     // Device code flow
     // ------------------------
@@ -306,6 +421,106 @@ where
         }
     );
 
+    // ------------------------
+    // Grant management
+    // ------------------------
+
+    // Obtain tokens for two different clients
+    let (token, refresh_token) = device_token(&metadata, "app-1").await;
+    let (other_token, other_refresh_token) = device_token(&metadata, "app-2").await;
+    let other_client = Client::new()
+        .credentials(Credentials::bearer(&other_token))
+        .connect(server.base_session.base_url())
+        .await
+        .unwrap();
+    assert_eq!(other_client.default_account_id(), john_id);
+
+    // Introspect a refresh token
+    let introspect_params = AHashMap::from_iter([
+        ("token".to_string(), other_refresh_token.to_string()),
+        ("token_type_hint".to_string(), "refresh_token".to_string()),
+    ]);
+    let response: TokenIntrospectResponse =
+        post_with_token(&metadata.introspection_endpoint, &token, &introspect_params).await;
+    assert!(response.active);
+    assert_eq!(response.client_id.unwrap(), "app-2");
+    assert_eq!(response.username.unwrap(), "jdoe@example.com");
+    assert_eq!(response.sub.unwrap(), john_id);
+
+    // Invalid tokens are reported as inactive
+    assert_eq!(
+        post_with_token::<TokenIntrospectResponse>(
+            &metadata.introspection_endpoint,
+            &token,
+            &AHashMap::from_iter([("token".to_string(), "invalid_token".to_string())]),
+        )
+        .await,
+        TokenIntrospectResponse::default()
+    );
+
+    // List grants
+    let grants_url = format!("{}/auth/grants", server.base_session.base_url());
+    let grants: Vec<GrantResponse> = get_with_token(&grants_url, &token).await;
+    let grant = grants
+        .iter()
+        .find(|grant| grant.client_id == "app-1")
+        .unwrap();
+    assert!(grant.current);
+    assert!(grant.ip.is_some());
+    let other_grant = grants
+        .iter()
+        .find(|grant| grant.client_id == "app-2")
+        .unwrap();
+    assert!(!other_grant.current);
+
+    // Revoking a grant should evict its cached sessions
+    let other_grant_url = format!("{}/{}", grants_url, other_grant.id);
+    assert_eq!(
+        delete_with_token(&other_grant_url, &token).await,
+        StatusCode::NO_CONTENT
+    );
+    assert_eq!(
+        delete_with_token(&other_grant_url, &token).await,
+        StatusCode::NOT_FOUND
+    );
+    assert_unauthorized(server.base_session.base_url(), &other_token).await;
+    assert_eq!(
+        post::<TokenResponse>(
+            &metadata.token_endpoint,
+            &AHashMap::from_iter([
+                ("client_id".to_string(), "app-2".to_string()),
+                ("grant_type".to_string(), "refresh_token".to_string()),
+                ("refresh_token".to_string(), other_refresh_token),
+            ]),
+        )
+        .await,
+        TokenResponse::Error {
+            error: ErrorType::InvalidGrant
+        }
+    );
+
+    // Revoke a refresh token, which also revokes its access tokens
+    let mut revoke_params = AHashMap::from_iter([
+        ("token".to_string(), refresh_token.to_string()),
+        ("client_id".to_string(), "app-2".to_string()),
+    ]);
+    assert_eq!(
+        post::<TokenResponse>(&metadata.revocation_endpoint, &revoke_params).await,
+        TokenResponse::Error {
+            error: ErrorType::InvalidClient
+        }
+    );
+    revoke_params.insert("client_id".to_string(), "app-1".to_string());
+    assert_eq!(
+        post_status(&metadata.revocation_endpoint, &revoke_params).await,
+        StatusCode::OK
+    );
+    assert_unauthorized(server.base_session.base_url(), &token).await;
+    assert_eq!(
+        post_status(&metadata.revocation_endpoint, &revoke_params).await,
+        StatusCode::OK
+    );
+
     // Destroy test accounts
     for principal_id in [john_id, domain_id] {
         admin_client.principal_destroy(&principal_id).await.unwrap();
@@ -330,11 +545,106 @@ async fn post_bytes(url: &str, params: &AHashMap<String, String>) -> Bytes {
         .unwrap()
 }
 
-async fn post<T: DeserializeOwned>(url: &str, params: &AHashMap<String, String>) -> T {
+pub async fn post<T: DeserializeOwned>(url: &str, params: &AHashMap<String, String>) -> T {
     serde_json::from_slice(&post_bytes(url, params).await).unwrap()
 }
 
-async fn post_expect_redirect(url: &str, params: &AHashMap<String, String>) -> String {
+pub async fn post_status(url: &str, params: &AHashMap<String, String>) -> StatusCode {
+    reqwest::Client::builder()
+        .timeout(Duration::from_millis(500))
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap_or_default()
+        .post(url)
+        .form(params)
+        .send()
+        .await
+        .unwrap()
+        .status()
+}
+
+async fn post_with_token<T: DeserializeOwned>(
+    url: &str,
+    token: &str,
+    params: &AHashMap<String, String>,
+) -> T {
+    serde_json::from_slice(
+        &reqwest::Client::builder()
+            .timeout(Duration::from_millis(500))
+            .danger_accept_invalid_certs(true)
+            .build()
+            .unwrap_or_default()
+            .post(url)
+            .bearer_auth(token)
+            .form(params)
+            .send()
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap(),
+    )
+    .unwrap()
+}
+
+async fn get_with_token<T: DeserializeOwned>(url: &str, token: &str) -> T {
+    serde_json::from_slice(
+        &reqwest::Client::builder()
+            .timeout(Duration::from_millis(500))
+            .danger_accept_invalid_certs(true)
+            .build()
+            .unwrap_or_default()
+            .get(url)
+            .bearer_auth(token)
+            .send()
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap(),
+    )
+    .unwrap()
+}
+
+async fn delete_with_token(url: &str, token: &str) -> StatusCode {
+    reqwest::Client::builder()
+        .timeout(Duration::from_millis(500))
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap_or_default()
+        .delete(url)
+        .bearer_auth(token)
+        .send()
+        .await
+        .unwrap()
+        .status()
+}
+
+async fn device_token(metadata: &OAuthMetadata, client_id: &str) -> (String, String) {
+    let device_response: DeviceAuthResponse = post(
+        &metadata.device_authorization_endpoint,
+        &AHashMap::from_iter([("client_id".to_string(), client_id.to_string())]),
+    )
+    .await;
+    assert_client_auth("jdoe@example.com", "abcde", &device_response, "successful").await;
+    let (token, refresh_token, _) = unwrap_token_response(
+        post(
+            &metadata.token_endpoint,
+            &AHashMap::from_iter([
+                ("client_id".to_string(), client_id.to_string()),
+                (
+                    "grant_type".to_string(),
+                    "urn:ietf:params:oauth:grant-type:device_code".to_string(),
+                ),
+                ("device_code".to_string(), device_response.device_code),
+            ]),
+        )
+        .await,
+    );
+    (token, refresh_token.unwrap())
+}
+
+pub async fn post_expect_redirect(url: &str, params: &AHashMap<String, String>) -> String {
     let response = reqwest::Client::builder()
         .timeout(Duration::from_millis(500))
         .danger_accept_invalid_certs(true)
@@ -355,7 +665,7 @@ async fn post_expect_redirect(url: &str, params: &AHashMap<String, String>) -> S
         .to_string()
 }
 
-async fn get_bytes(url: &str) -> Bytes {
+pub async fn get_bytes(url: &str) -> Bytes {
     reqwest::Client::builder()
         .timeout(Duration::from_millis(500))
         .danger_accept_invalid_certs(true)
@@ -370,7 +680,20 @@ async fn get_bytes(url: &str) -> Bytes {
         .unwrap()
 }
 
-async fn get<T: DeserializeOwned>(url: &str) -> T {
+async fn get_status(url: &str) -> StatusCode {
+    reqwest::Client::builder()
+        .timeout(Duration::from_millis(500))
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap_or_default()
+        .get(url)
+        .send()
+        .await
+        .unwrap()
+        .status()
+}
+
+pub async fn get<T: DeserializeOwned>(url: &str) -> T {
     serde_json::from_slice(&get_bytes(url).await).unwrap()
 }
 
@@ -409,7 +732,7 @@ async fn assert_unauthorized(base_url: &str, token: &str) {
     }
 }
 
-fn parse_code_input(bytes: Bytes) -> String {
+pub fn parse_code_input(bytes: Bytes) -> String {
     let html = String::from_utf8_lossy(&bytes).into_owned();
     if let Some((_, code)) = html.split_once("name=\"code\" value=\"") {
         if let Some((code, _)) = code.split_once('\"') {
@@ -419,7 +742,7 @@ fn parse_code_input(bytes: Bytes) -> String {
     panic!("Could not parse code input: {}", html);
 }
 
-fn parse_code_redirect(uri: String, state: &str) -> String {
+pub fn parse_code_redirect(uri: String, state: &str) -> String {
     if let Some(code) = uri.strip_prefix("https://localhost?code=") {
         if let Some(code) = code.strip_suffix(&format!("&state={}", state)) {
             return code.to_string();
@@ -428,7 +751,40 @@ fn parse_code_redirect(uri: String, state: &str) -> String {
     panic!("Invalid redirect URI: {}", uri);
 }
 
-fn unwrap_token_response(response: TokenResponse) -> (String, Option<String>, u64) {
+fn verify_id_token(id_token: &str, jwks: &JsonWebKeySet) -> IdTokenClaims {
+    let (message, signature) = id_token.rsplit_once('.').unwrap();
+    let (header, claims) = message.split_once('.').unwrap();
+    let header: AHashMap<String, String> =
+        serde_json::from_slice(&base64::decode_config(header, base64::URL_SAFE_NO_PAD).unwrap())
+            .unwrap();
+    let jwk = jwks
+        .keys
+        .iter()
+        .find(|jwk| Some(&jwk.kid) == header.get("kid"))
+        .unwrap();
+    assert_eq!(header.get("alg"), Some(&jwk.alg));
+    assert_eq!(jwk.alg, "EdDSA");
+
+    PublicKey::from_bytes(
+        &base64::decode_config(jwk.x.as_ref().unwrap(), base64::URL_SAFE_NO_PAD).unwrap(),
+    )
+    .unwrap()
+    .verify(
+        message.as_bytes(),
+        &Signature::try_from(
+            base64::decode_config(signature, base64::URL_SAFE_NO_PAD)
+                .unwrap()
+                .as_slice(),
+        )
+        .unwrap(),
+    )
+    .unwrap();
+
+    serde_json::from_slice(&base64::decode_config(claims, base64::URL_SAFE_NO_PAD).unwrap())
+        .unwrap()
+}
+
+pub fn unwrap_token_response(response: TokenResponse) -> (String, Option<String>, u64) {
     match response {
         TokenResponse::Granted {
             access_token,
@@ -0,0 +1,519 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use actix_web::web;
+use jmap::{types::jmap::JMAPId, SUPERUSER_ID};
+use jmap_client::client::{Client, Credentials};
+use jmap_sharing::principal::{account::JMAPAccountStore, set::JMAPSetPrincipal};
+use reqwest::{header, redirect::Policy, StatusCode, Url};
+use serde_json::json;
+use store::{
+    ahash::AHashMap,
+    config::env_settings::EnvSettings,
+    sha2::{Digest, Sha256},
+    Store,
+};
+
+use crate::{
+    authorization::oidc::{base64url, JsonWebKeySet, SigningKey},
+    tests::store::utils::StoreCompareWith,
+    JMAPServer,
+};
+
+use super::oauth::{parse_code_redirect, post, post_expect_redirect, unwrap_token_response};
+
+const IDP_PORT: u16 = 9011;
+const CLIENT_ID: &str = "stalwart";
+const CLIENT_SECRET: &str = "s3cr3t";
+
+struct MockIdP {
+    key: SigningKey,
+    logins: AHashMap<String, MockLogin>,
+}
+
+struct MockLogin {
+    nonce: String,
+    code_challenge: String,
+    email: String,
+    name: String,
+}
+
+pub fn add_oidc_settings(settings: &mut EnvSettings) {
+    for (key, value) in [
+        (
+            "oidc-upstream-issuer",
+            format!("http://127.0.0.1:{}", IDP_PORT),
+        ),
+        ("oidc-upstream-client-id", CLIENT_ID.to_string()),
+        ("oidc-upstream-client-secret", CLIENT_SECRET.to_string()),
+        ("oidc-upstream-auto-provision", "true".to_string()),
+    ] {
+        settings.set_value(key.to_string(), value);
+    }
+}
+
+pub async fn test<T>(server: web::Data<JMAPServer<T>>, admin_client: &mut Client)
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    println!("Running OpenID Connect login tests...");
+    let idp = spawn_mock_idp();
+
+    // Create test account
+    let domain_id = admin_client
+        .set_default_account_id(JMAPId::from(SUPERUSER_ID).to_string())
+        .domain_create("example.com")
+        .await
+        .unwrap()
+        .take_id();
+    let john_id = admin_client
+        .individual_create("jdoe@example.com", "abcde", "John Doe")
+        .await
+        .unwrap()
+        .take_id();
+
+    // Password authentication is disabled
+    let base_url = server.base_session.base_url();
+    let code_verifier = "dBjftJeZ4CVP-mJ0APNTEDh6LcQ0S0dP-Jq0Tq5uWbE";
+    let auth_endpoint = format!(
+        concat!(
+            "{}/auth/code?response_type=code&client_id=OAuthyMcOAuthFace&state=xyz",
+            "&redirect_uri=https://localhost&code_challenge={}&code_challenge_method=S256"
+        ),
+        base_url,
+        base64url(&Sha256::digest(code_verifier.as_bytes()))
+    );
+    assert_eq!(
+        http_client()
+            .post(format!("{}/auth/code", base_url))
+            .form(&AHashMap::from_iter([
+                ("email".to_string(), "jdoe@example.com".to_string()),
+                ("password".to_string(), "abcde".to_string()),
+                ("code".to_string(), "".to_string()),
+            ]))
+            .send()
+            .await
+            .unwrap()
+            .status(),
+        StatusCode::BAD_REQUEST
+    );
+
+    // Existing accounts are matched by e-mail address
+    let token = sso_login(
+        &server,
+        &idp,
+        &auth_endpoint,
+        code_verifier,
+        "jdoe@example.com",
+        None,
+    )
+    .await
+    .unwrap();
+    let john_client = Client::new()
+        .credentials(Credentials::bearer(&token))
+        .connect(base_url)
+        .await
+        .unwrap();
+    assert_eq!(john_client.default_account_id(), john_id);
+
+    // Unknown accounts are provisioned
+    let token = sso_login(
+        &server,
+        &idp,
+        &auth_endpoint,
+        code_verifier,
+        "jane@example.com",
+        None,
+    )
+    .await
+    .unwrap();
+    let jane_id = JMAPId::from(
+        server
+            .store
+            .find_individual("jane@example.com")
+            .unwrap()
+            .unwrap(),
+    )
+    .to_string();
+    let jane_client = Client::new()
+        .credentials(Credentials::bearer(&token))
+        .connect(base_url)
+        .await
+        .unwrap();
+    assert_eq!(jane_client.default_account_id(), jane_id);
+    assert_eq!(
+        server
+            .store
+            .get_account_details(JMAPId::parse(&jane_id).unwrap().get_document_id())
+            .unwrap()
+            .unwrap()
+            .1,
+        "Jane Doe"
+    );
+
+    // Unverified e-mail addresses are not trusted
+    assert_eq!(
+        sso_login(
+            &server,
+            &idp,
+            &auth_endpoint,
+            code_verifier,
+            "unverified@example.com",
+            None,
+        )
+        .await,
+        None
+    );
+    assert!(server
+        .store
+        .find_individual("unverified@example.com")
+        .unwrap()
+        .is_none());
+
+    // ID tokens with a different nonce are rejected
+    assert_eq!(
+        sso_login(
+            &server,
+            &idp,
+            &auth_endpoint,
+            code_verifier,
+            "jdoe@example.com",
+            Some("invalid_nonce"),
+        )
+        .await,
+        None
+    );
+
+    // Errors returned by the identity provider deny access
+    let (callback_uri, state, binding) = upstream_redirect(&auth_endpoint).await;
+    assert_eq!(
+        get_redirect(
+            &format!("{}?error=access_denied&state={}", callback_uri, state),
+            &binding
+        )
+        .await,
+        "https://localhost?error=access_denied&state=xyz"
+    );
+
+    // States are bound to the browser that started the login
+    let (callback_uri, state, _) = upstream_redirect(&auth_endpoint).await;
+    for binding in ["", "upstream_binding=invalid"] {
+        assert_eq!(
+            http_client()
+                .get(format!("{}?code=abc&state={}", callback_uri, state))
+                .header(header::COOKIE, binding)
+                .send()
+                .await
+                .unwrap()
+                .status(),
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    // Invalid states are rejected
+    assert_eq!(
+        http_client()
+            .get(format!("{}?code=abc&state=invalid", callback_uri))
+            .send()
+            .await
+            .unwrap()
+            .status(),
+        StatusCode::BAD_REQUEST
+    );
+
+    // Destroy test accounts
+    for principal_id in [john_id, jane_id, domain_id] {
+        admin_client.principal_destroy(&principal_id).await.unwrap();
+    }
+    server.store.principal_purge().unwrap();
+    server.store.assert_is_empty();
+}
+
+async fn sso_login<T>(
+    server: &JMAPServer<T>,
+    idp: &Arc<Mutex<MockIdP>>,
+    auth_endpoint: &str,
+    code_verifier: &str,
+    email: &str,
+    nonce: Option<&str>,
+) -> Option<String>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    // Sign in at the identity provider
+    let response = http_client().get(auth_endpoint).send().await.unwrap();
+    let binding = binding_cookie(&response);
+    let params = Url::parse(
+        response
+            .headers()
+            .get(header::LOCATION)
+            .unwrap()
+            .to_str()
+            .unwrap(),
+    )
+    .unwrap()
+    .query_pairs()
+    .into_owned()
+    .collect::<AHashMap<_, _>>();
+    assert_eq!(params["client_id"], CLIENT_ID);
+    assert_eq!(params["code_challenge_method"], "S256");
+    idp.lock().unwrap().logins.insert(
+        "idp-code".to_string(),
+        MockLogin {
+            nonce: nonce.unwrap_or(params["nonce"].as_str()).to_string(),
+            code_challenge: params["code_challenge"].to_string(),
+            email: email.to_string(),
+            name: if email.starts_with("jane") {
+                "Jane Doe"
+            } else {
+                "John Doe"
+            }
+            .to_string(),
+        },
+    );
+
+    // Return to the JMAP server
+    let redirect = get_redirect(
+        &format!(
+            "{}?code=idp-code&state={}",
+            params["redirect_uri"], params["state"]
+        ),
+        &binding,
+    )
+    .await;
+    if redirect.contains("error=access_denied") {
+        return None;
+    }
+
+    // Obtain token
+    let (token, _, _) = unwrap_token_response(
+        post(
+            &format!("{}/auth/token", server.base_session.base_url()),
+            &AHashMap::from_iter([
+                ("client_id".to_string(), "OAuthyMcOAuthFace".to_string()),
+                ("redirect_uri".to_string(), "https://localhost".to_string()),
+                ("grant_type".to_string(), "authorization_code".to_string()),
+                ("code".to_string(), parse_code_redirect(redirect, "xyz")),
+                ("code_verifier".to_string(), code_verifier.to_string()),
+            ]),
+        )
+        .await,
+    );
+    Some(token)
+}
+
+async fn upstream_redirect(auth_endpoint: &str) -> (String, String, String) {
+    let response = http_client().get(auth_endpoint).send().await.unwrap();
+    let params = Url::parse(
+        response
+            .headers()
+            .get(header::LOCATION)
+            .unwrap()
+            .to_str()
+            .unwrap(),
+    )
+    .unwrap()
+    .query_pairs()
+    .into_owned()
+    .collect::<AHashMap<_, _>>();
+    (
+        params["redirect_uri"].to_string(),
+        params["state"].to_string(),
+        binding_cookie(&response),
+    )
+}
+
+fn binding_cookie(response: &reqwest::Response) -> String {
+    response
+        .headers()
+        .get(header::SET_COOKIE)
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .split(';')
+        .next()
+        .unwrap()
+        .to_string()
+}
+
+async fn get_redirect(url: &str, cookie: &str) -> String {
+    http_client()
+        .get(url)
+        .header(header::COOKIE, cookie)
+        .send()
+        .await
+        .unwrap()
+        .headers()
+        .get(header::LOCATION)
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
+fn http_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(Duration::from_millis(500))
+        .redirect(Policy::none())
+        .build()
+        .unwrap_or_default()
+}
+
+fn spawn_mock_idp() -> Arc<Mutex<MockIdP>> {
+    let idp = Arc::new(Mutex::new(MockIdP {
+        key: SigningKey::from_pem(include_str!("../resources/key.pem")).unwrap(),
+        logins: AHashMap::new(),
+    }));
+    let listener = TcpListener::bind(("127.0.0.1", IDP_PORT)).unwrap();
+    let idp_ = idp.clone();
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let idp = idp_.clone();
+            std::thread::spawn(move || handle_request(stream, idp));
+        }
+    });
+
+    idp
+}
+
+fn handle_request(stream: TcpStream, idp: Arc<Mutex<MockIdP>>) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line).unwrap_or(0) == 0 {
+        return;
+    }
+    let mut request_line = request_line.split_ascii_whitespace();
+    let method = request_line.next().unwrap().to_string();
+    let path = request_line.next().unwrap().to_string();
+
+    // Read headers and body
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap();
+            }
+        }
+    }
+    let mut body = vec![0u8; content_length];
+    reader.read_exact(&mut body).unwrap();
+    let params = Url::parse(&format!(
+        "http://localhost/?{}",
+        String::from_utf8(body).unwrap()
+    ))
+    .unwrap()
+    .query_pairs()
+    .into_owned()
+    .collect::<AHashMap<_, _>>();
+
+    let issuer = format!("http://127.0.0.1:{}", IDP_PORT);
+    let mut idp = idp.lock().unwrap();
+    let (status, body) = match (method.as_str(), path.as_str()) {
+        ("GET", "/.well-known/openid-configuration") => (
+            "200 OK",
+            json!({
+                "issuer": issuer,
+                "authorization_endpoint": format!("{}/auth", issuer),
+                "token_endpoint": format!("{}/token", issuer),
+                "jwks_uri": format!("{}/jwks", issuer),
+            }),
+        ),
+        ("GET", "/jwks") => (
+            "200 OK",
+            serde_json::to_value(JsonWebKeySet {
+                keys: vec![idp.key.jwk()],
+            })
+            .unwrap(),
+        ),
+        ("POST", "/token") => match idp.logins.remove(params["code"].as_str()) {
+            Some(login)
+                if params["client_id"] == CLIENT_ID
+                    && params["client_secret"] == CLIENT_SECRET
+                    && base64url(&Sha256::digest(params["code_verifier"].as_bytes()))
+                        == login.code_challenge =>
+            {
+                let now = SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs();
+                (
+                    "200 OK",
+                    json!({
+                        "access_token": "idp-access-token",
+                        "token_type": "Bearer",
+                        "id_token": idp.key.sign_jwt(&json!({
+                            "iss": issuer,
+                            "aud": CLIENT_ID,
+                            "sub": login.email,
+                            "exp": now + 60,
+                            "iat": now,
+                            "nonce": login.nonce,
+                            "email": login.email,
+                            "email_verified": if !login.email.starts_with("unverified") {
+                                json!(true)
+                            } else {
+                                json!(null)
+                            },
+                            "name": login.name,
+                        })).unwrap(),
+                    }),
+                )
+            }
+            _ => ("400 Bad Request", json!({"error": "invalid_grant"})),
+        },
+        _ => ("404 Not Found", json!({})),
+    };
+    drop(idp);
+
+    let body = body.to_string();
+    let mut stream = stream;
+    stream
+        .write_all(
+            format!(
+                concat!(
+                    "HTTP/1.1 {}\r\nContent-Type: application/json\r\n",
+                    "Content-Length: {}\r\nConnection: close\r\n\r\n{}"
+                ),
+                status,
+                body.len(),
+                body
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+    stream.flush().unwrap();
+}
@@ -0,0 +1,361 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use actix_web::web;
+use jmap::{types::jmap::JMAPId, SUPERUSER_ID};
+use jmap_client::client::Client;
+use serde_json::json;
+use store::Store;
+
+use crate::{
+    tests::{jmap::jmap_request, store::utils::StoreCompareWith},
+    JMAPServer,
+};
+
+const USING: &[&str] = &[
+    "urn:ietf:params:jmap:core",
+    "urn:ietf:params:jmap:calendars",
+];
+
+pub async fn test<T>(server: web::Data<JMAPServer<T>>, client: &mut Client)
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    println!("Running Calendars tests...");
+
+    // Create a test account
+    let domain_id = client
+        .set_default_account_id(JMAPId::new(SUPERUSER_ID as u64))
+        .domain_create("example.com")
+        .await
+        .unwrap()
+        .take_id();
+    let account_id = client
+        .individual_create("jdoe@example.com", "12345", "John Doe")
+        .await
+        .unwrap()
+        .take_id();
+
+    // Create a calendar, time zones are validated
+    let response = jmap_request(
+        &server,
+        Some(("jdoe@example.com", "12345")),
+        USING,
+        "Calendar/set",
+        json!({
+            "accountId": account_id,
+            "create": {
+                "c1": {"name": "Personal", "timeZone": "Europe/Madrid"},
+                "c2": {"name": "Work", "timeZone": "Mars/Olympus_Mons"},
+            },
+        }),
+    )
+    .await;
+    assert_eq!(
+        response["notCreated"]["c2"]["type"],
+        json!("invalidProperties")
+    );
+    let calendar_id = response["created"]["c1"]["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let response = jmap_request(
+        &server,
+        Some(("jdoe@example.com", "12345")),
+        USING,
+        "Calendar/get",
+        json!({
+            "accountId": account_id,
+            "ids": [calendar_id],
+            "properties": ["name", "isDefault", "isVisible", "myRights"],
+        }),
+    )
+    .await;
+    assert_eq!(response["list"][0]["isDefault"], json!(true));
+    assert_eq!(response["list"][0]["isVisible"], json!(true));
+    assert_eq!(response["list"][0]["myRights"]["mayAdmin"], json!(true));
+
+    // Create events
+    let response = jmap_request(
+        &server,
+        Some(("jdoe@example.com", "12345")),
+        USING,
+        "CalendarEvent/set",
+        json!({
+            "accountId": account_id,
+            "create": {
+                "e1": {
+                    "calendarIds": {&calendar_id: true},
+                    "title": "Dentist appointment",
+                    "start": "2022-06-01T10:00:00",
+                    "duration": "PT1H",
+                    "timeZone": "Etc/UTC",
+                },
+                "e2": {
+                    "calendarIds": {&calendar_id: true},
+                    "title": "Team meeting",
+                    "start": "2022-01-03T09:00:00",
+                    "duration": "PT30M",
+                    "timeZone": "Europe/Madrid",
+                    "recurrenceRules": [{
+                        "@type": "RecurrenceRule",
+                        "frequency": "weekly",
+                        "byDay": [{"@type": "NDay", "day": "mo"}],
+                    }],
+                    "recurrenceOverrides": {
+                        "2022-01-10T09:00:00": {"excluded": true},
+                    },
+                    "participants": {
+                        "p1": {
+                            "@type": "Participant",
+                            "email": "jane@example.org",
+                            "roles": {"attendee": true},
+                        },
+                    },
+                },
+                "e3": {
+                    "title": "Orphan event",
+                    "start": "2022-01-01T00:00:00",
+                },
+                "e4": {
+                    "calendarIds": {&calendar_id: true},
+                    "title": "Invalid recurrence",
+                    "start": "2022-01-01T00:00:00",
+                    "recurrenceRules": [{"frequency": "fortnightly"}],
+                },
+            },
+        }),
+    )
+    .await;
+    for id in ["e3", "e4"] {
+        assert_eq!(
+            response["notCreated"][id]["type"],
+            json!("invalidProperties"),
+            "{}",
+            id
+        );
+    }
+    let dentist_id = response["created"]["e1"]["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let meeting_id = response["created"]["e2"]["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    assert!(response["created"]["e2"]["uid"].is_string());
+
+    // Query events, time ranges are matched against every occurrence
+    for (filter, expected) in [
+        (
+            json!({"after": "2022-01-10T00:00:00Z", "before": "2022-01-11T00:00:00Z"}),
+            json!([]),
+        ),
+        (
+            json!({"after": "2022-01-17T00:00:00Z", "before": "2022-01-18T00:00:00Z"}),
+            json!([meeting_id]),
+        ),
+        (
+            json!({"after": "2022-06-01T00:00:00Z", "before": "2022-06-02T00:00:00Z"}),
+            json!([dentist_id]),
+        ),
+        (
+            json!({"after": "2030-01-01T00:00:00Z"}),
+            json!([meeting_id]),
+        ),
+        (json!({"before": "2022-01-01T00:00:00Z"}), json!([])),
+        (json!({"title": "dentist"}), json!([dentist_id])),
+        (json!({"attendee": "jane@example.org"}), json!([meeting_id])),
+        (json!({"text": "meeting"}), json!([meeting_id])),
+        (
+            json!({"inCalendars": [calendar_id]}),
+            json!([meeting_id, dentist_id]),
+        ),
+    ] {
+        assert_eq!(
+            jmap_request(
+                &server,
+                Some(("jdoe@example.com", "12345")),
+                USING,
+                "CalendarEvent/query",
+                json!({
+                    "accountId": account_id,
+                    "filter": filter,
+                    "sort": [{"property": "start"}],
+                }),
+            )
+            .await["ids"],
+            expected,
+            "{}",
+            filter
+        );
+    }
+
+    // Time ranges are not supported within operators
+    for filter in [
+        json!({"operator": "NOT", "conditions": [{"after": "2022-01-17T00:00:00Z"}]}),
+        json!({"operator": "AND", "conditions": [
+            {"title": "dentist"},
+            {"operator": "OR", "conditions": [
+                {"before": "2022-01-01T00:00:00Z"},
+                {"title": "meeting"},
+            ]},
+        ]}),
+    ] {
+        assert_eq!(
+            jmap_request(
+                &server,
+                Some(("jdoe@example.com", "12345")),
+                USING,
+                "CalendarEvent/query",
+                json!({
+                    "accountId": account_id,
+                    "filter": filter,
+                }),
+            )
+            .await["type"],
+            json!("unsupportedFilter"),
+            "{}",
+            filter
+        );
+    }
+    assert_eq!(
+        jmap_request(
+            &server,
+            Some(("jdoe@example.com", "12345")),
+            USING,
+            "CalendarEvent/query",
+            json!({
+                "accountId": account_id,
+                "filter": {"operator": "AND", "conditions": [
+                    {"after": "2022-01-17T00:00:00Z"},
+                    {"before": "2022-01-18T00:00:00Z"},
+                ]},
+            }),
+        )
+        .await["ids"],
+        json!([meeting_id])
+    );
+
+    // Moving an event updates its time span
+    let response = jmap_request(
+        &server,
+        Some(("jdoe@example.com", "12345")),
+        USING,
+        "CalendarEvent/set",
+        json!({
+            "accountId": account_id,
+            "update": {&dentist_id: {"start": "2022-06-02T10:00:00"}},
+        }),
+    )
+    .await;
+    assert!(response["updated"][&dentist_id].is_object());
+    assert_eq!(
+        jmap_request(
+            &server,
+            Some(("jdoe@example.com", "12345")),
+            USING,
+            "CalendarEvent/query",
+            json!({
+                "accountId": account_id,
+                "filter": {"after": "2022-06-01T00:00:00Z", "before": "2022-06-02T00:00:00Z"},
+            }),
+        )
+        .await["ids"],
+        json!([])
+    );
+    let response = jmap_request(
+        &server,
+        Some(("jdoe@example.com", "12345")),
+        USING,
+        "CalendarEvent/get",
+        json!({
+            "accountId": account_id,
+            "ids": [meeting_id],
+            "properties": ["@type", "calendarIds", "recurrenceRules", "start"],
+        }),
+    )
+    .await;
+    assert_eq!(response["list"][0]["@type"], json!("Event"));
+    assert_eq!(response["list"][0]["start"], json!("2022-01-03T09:00:00"));
+    assert_eq!(
+        response["list"][0]["recurrenceRules"][0]["frequency"],
+        json!("weekly")
+    );
+    assert_eq!(
+        response["list"][0]["calendarIds"],
+        json!({&calendar_id: true})
+    );
+
+    // Non-empty calendars can only be destroyed with onDestroyRemoveEvents
+    let response = jmap_request(
+        &server,
+        Some(("jdoe@example.com", "12345")),
+        USING,
+        "Calendar/set",
+        json!({
+            "accountId": account_id,
+            "destroy": [calendar_id],
+        }),
+    )
+    .await;
+    assert_eq!(
+        response["notDestroyed"][&calendar_id]["type"],
+        json!("calendarHasEvent")
+    );
+    let response = jmap_request(
+        &server,
+        Some(("jdoe@example.com", "12345")),
+        USING,
+        "Calendar/set",
+        json!({
+            "accountId": account_id,
+            "destroy": [calendar_id],
+            "onDestroyRemoveEvents": true,
+        }),
+    )
+    .await;
+    assert_eq!(response["destroyed"], json!([calendar_id]));
+    let response = jmap_request(
+        &server,
+        Some(("jdoe@example.com", "12345")),
+        USING,
+        "CalendarEvent/get",
+        json!({
+            "accountId": account_id,
+            "ids": [dentist_id, meeting_id],
+        }),
+    )
+    .await;
+    assert_eq!(response["notFound"], json!([dentist_id, meeting_id]));
+
+    // Remove test data
+    client
+        .set_default_account_id(JMAPId::new(SUPERUSER_ID as u64))
+        .principal_destroy(&account_id)
+        .await
+        .unwrap();
+    client.principal_destroy(&domain_id).await.unwrap();
+    server.store.principal_purge().unwrap();
+    server.store.assert_is_empty();
+}
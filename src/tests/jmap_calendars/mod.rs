@@ -0,0 +1,39 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use store_rocksdb::RocksDB;
+
+use super::{jmap::init_jmap_tests, store::utils::destroy_temp_dir};
+
+pub mod calendars;
+
+#[actix_web::test]
+#[ignore]
+async fn jmap_calendars_tests() {
+    let (server, mut client, temp_dir) = init_jmap_tests::<RocksDB>("jmap_calendars_tests").await;
+
+    // Run tests
+    calendars::test(server.clone(), &mut client).await;
+
+    destroy_temp_dir(&temp_dir);
+}
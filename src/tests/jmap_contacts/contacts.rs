@@ -0,0 +1,297 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use actix_web::web;
+use jmap::{types::jmap::JMAPId, SUPERUSER_ID};
+use jmap_client::client::Client;
+use serde_json::json;
+use store::Store;
+
+use crate::{
+    tests::{jmap::jmap_request, store::utils::StoreCompareWith},
+    JMAPServer,
+};
+
+const USING: &[&str] = &["urn:ietf:params:jmap:core", "urn:ietf:params:jmap:contacts"];
+
+pub async fn test<T>(server: web::Data<JMAPServer<T>>, client: &mut Client)
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    println!("Running Contacts tests...");
+
+    // Create a test account
+    let domain_id = client
+        .set_default_account_id(JMAPId::new(SUPERUSER_ID as u64))
+        .domain_create("example.com")
+        .await
+        .unwrap()
+        .take_id();
+    let account_id = client
+        .individual_create("jdoe@example.com", "12345", "John Doe")
+        .await
+        .unwrap()
+        .take_id();
+
+    // The first address book becomes the default one
+    let response = jmap_request(
+        &server,
+        Some(("jdoe@example.com", "12345")),
+        USING,
+        "AddressBook/set",
+        json!({
+            "accountId": account_id,
+            "create": {"b1": {"name": "Personal"}},
+        }),
+    )
+    .await;
+    let personal_id = response["created"]["b1"]["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let response = jmap_request(
+        &server,
+        Some(("jdoe@example.com", "12345")),
+        USING,
+        "AddressBook/set",
+        json!({
+            "accountId": account_id,
+            "create": {
+                "b2": {"name": "Work", "isDefault": true},
+                "b3": {"name": "Personal"},
+            },
+        }),
+    )
+    .await;
+    assert_eq!(
+        response["notCreated"]["b3"]["type"],
+        json!("invalidProperties")
+    );
+    let work_id = response["created"]["b2"]["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let response = jmap_request(
+        &server,
+        Some(("jdoe@example.com", "12345")),
+        USING,
+        "AddressBook/get",
+        json!({
+            "accountId": account_id,
+            "ids": [personal_id, work_id],
+            "properties": ["name", "isDefault", "myRights"],
+        }),
+    )
+    .await;
+    assert_eq!(response["list"][0]["isDefault"], json!(false));
+    assert_eq!(response["list"][1]["isDefault"], json!(true));
+    assert_eq!(response["list"][1]["myRights"]["mayShare"], json!(true));
+
+    // Contact cards have to belong to an address book
+    let response = jmap_request(
+        &server,
+        Some(("jdoe@example.com", "12345")),
+        USING,
+        "ContactCard/set",
+        json!({
+            "accountId": account_id,
+            "create": {
+                "c1": {
+                    "addressBookIds": {&personal_id: true},
+                    "kind": "individual",
+                    "name": {
+                        "components": [
+                            {"kind": "given", "value": "John"},
+                            {"kind": "surname", "value": "Appleseed"}
+                        ]
+                    },
+                    "emails": {"e1": {"address": "john@example.org"}},
+                },
+                "c2": {
+                    "name": {"full": "Jane Appleseed"},
+                },
+            },
+        }),
+    )
+    .await;
+    assert_eq!(
+        response["notCreated"]["c2"]["type"],
+        json!("invalidProperties")
+    );
+    let card_id = response["created"]["c1"]["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    assert!(response["created"]["c1"]["uid"]
+        .as_str()
+        .unwrap()
+        .starts_with("urn:uuid:"));
+
+    // Query contact cards
+    for (filter, expected) in [
+        (json!({"text": "appleseed"}), json!([card_id])),
+        (json!({"email": "john@example.org"}), json!([card_id])),
+        (json!({"inAddressBook": personal_id}), json!([card_id])),
+        (json!({"inAddressBook": work_id}), json!([])),
+        (json!({"kind": "individual"}), json!([card_id])),
+        (json!({"name": "Jane"}), json!([])),
+    ] {
+        assert_eq!(
+            jmap_request(
+                &server,
+                Some(("jdoe@example.com", "12345")),
+                USING,
+                "ContactCard/query",
+                json!({
+                    "accountId": account_id,
+                    "filter": filter,
+                }),
+            )
+            .await["ids"],
+            expected,
+            "{}",
+            filter
+        );
+    }
+
+    // Add the card to a second address book
+    let response = jmap_request(
+        &server,
+        Some(("jdoe@example.com", "12345")),
+        USING,
+        "ContactCard/set",
+        json!({
+            "accountId": account_id,
+            "update": {&card_id: {format!("addressBookIds/{}", work_id): true}},
+        }),
+    )
+    .await;
+    assert!(response["updated"][&card_id].is_object());
+    let response = jmap_request(
+        &server,
+        Some(("jdoe@example.com", "12345")),
+        USING,
+        "ContactCard/get",
+        json!({
+            "accountId": account_id,
+            "ids": [card_id],
+            "properties": ["@type", "addressBookIds", "name"],
+        }),
+    )
+    .await;
+    assert_eq!(response["list"][0]["@type"], json!("Card"));
+    assert_eq!(
+        response["list"][0]["addressBookIds"][&personal_id],
+        json!(true)
+    );
+    assert_eq!(response["list"][0]["addressBookIds"][&work_id], json!(true));
+    assert_eq!(
+        response["list"][0]["name"]["components"][1]["value"],
+        json!("Appleseed")
+    );
+
+    // Non-empty address books can only be destroyed with onDestroyRemoveContents
+    let response = jmap_request(
+        &server,
+        Some(("jdoe@example.com", "12345")),
+        USING,
+        "AddressBook/set",
+        json!({
+            "accountId": account_id,
+            "destroy": [personal_id],
+        }),
+    )
+    .await;
+    assert_eq!(
+        response["notDestroyed"][&personal_id]["type"],
+        json!("addressBookHasContents")
+    );
+    let response = jmap_request(
+        &server,
+        Some(("jdoe@example.com", "12345")),
+        USING,
+        "AddressBook/set",
+        json!({
+            "accountId": account_id,
+            "destroy": [personal_id],
+            "onDestroyRemoveContents": true,
+        }),
+    )
+    .await;
+    assert_eq!(response["destroyed"], json!([personal_id]));
+
+    // The card should still be in the second address book
+    let response = jmap_request(
+        &server,
+        Some(("jdoe@example.com", "12345")),
+        USING,
+        "ContactCard/get",
+        json!({
+            "accountId": account_id,
+            "ids": [card_id],
+            "properties": ["addressBookIds"],
+        }),
+    )
+    .await;
+    assert_eq!(
+        response["list"][0]["addressBookIds"],
+        json!({&work_id: true})
+    );
+
+    // Destroying the last address book removes the card
+    let response = jmap_request(
+        &server,
+        Some(("jdoe@example.com", "12345")),
+        USING,
+        "AddressBook/set",
+        json!({
+            "accountId": account_id,
+            "destroy": [work_id],
+            "onDestroyRemoveContents": true,
+        }),
+    )
+    .await;
+    assert_eq!(response["destroyed"], json!([work_id]));
+    let response = jmap_request(
+        &server,
+        Some(("jdoe@example.com", "12345")),
+        USING,
+        "ContactCard/get",
+        json!({
+            "accountId": account_id,
+            "ids": [card_id],
+        }),
+    )
+    .await;
+    assert_eq!(response["notFound"], json!([card_id]));
+
+    // Remove test data
+    client
+        .set_default_account_id(JMAPId::new(SUPERUSER_ID as u64))
+        .principal_destroy(&account_id)
+        .await
+        .unwrap();
+    client.principal_destroy(&domain_id).await.unwrap();
+    server.store.principal_purge().unwrap();
+    server.store.assert_is_empty();
+}
@@ -0,0 +1,225 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use actix_web::web;
+use jmap::{types::jmap::JMAPId, SUPERUSER_ID};
+use jmap_client::{client::Client, email};
+use serde_json::json;
+use store::Store;
+
+use crate::{
+    tests::{
+        jmap::jmap_request,
+        jmap_mail::{
+            email_submission::{assert_message_delivery, spawn_mock_smtp_server, MockMessage},
+            lmtp::SmtpConnection,
+        },
+        store::utils::StoreCompareWith,
+    },
+    JMAPServer,
+};
+
+const USING: &[&str] = &["urn:ietf:params:jmap:core", "urn:ietf:params:jmap:mail"];
+
+pub async fn test<T>(server: web::Data<JMAPServer<T>>, client: &mut Client)
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    println!("Running Calendar invitation tests...");
+
+    // Create a test account
+    let domain_id = client
+        .set_default_account_id(JMAPId::new(SUPERUSER_ID as u64))
+        .domain_create("example.com")
+        .await
+        .unwrap()
+        .take_id();
+    let account_id = client
+        .individual_create("jdoe@example.com", "12345", "John Doe")
+        .await
+        .unwrap()
+        .take_id();
+    client.set_default_account_id(&account_id);
+
+    // Start mock SMTP server
+    let (mut smtp_rx, _smtp_settings) = spawn_mock_smtp_server();
+
+    // Invitations are only accepted when sent by the organizer
+    let mut lmtp = SmtpConnection::connect().await;
+    lmtp.ingest(
+        "bill@example.com",
+        &["jdoe@example.com"],
+        &build_invitation("Quarterly planning"),
+    )
+    .await;
+    lmtp.ingest(
+        "mallory@example.com",
+        &["jdoe@example.com"],
+        &build_invitation("Forged planning"),
+    )
+    .await;
+
+    let email_id = query_email(client, "Quarterly").await;
+    let response = jmap_request(
+        &server,
+        Some(("jdoe@example.com", "12345")),
+        USING,
+        "Email/get",
+        json!({
+            "accountId": account_id,
+            "ids": [email_id, query_email(client, "Forged").await],
+            "properties": ["calendarInvitation"],
+        }),
+    )
+    .await;
+    let invitation = &response["list"][0]["calendarInvitation"];
+    assert_eq!(invitation["method"], json!("request"));
+    assert_eq!(invitation["uid"], json!("e2c1a4f0-planning@example.com"));
+    assert_eq!(invitation["sequence"], json!(1));
+    assert_eq!(invitation["summary"], json!("Quarterly planning"));
+    assert_eq!(invitation["start"], json!("2022-10-10T10:00:00"));
+    assert_eq!(invitation["end"], json!("2022-10-10T11:00:00"));
+    assert_eq!(invitation["timeZone"], json!("Europe/Berlin"));
+    assert_eq!(invitation["organizer"]["email"], json!("bill@example.com"));
+    assert_eq!(
+        invitation["attendees"][0],
+        json!({
+            "email": "jdoe@example.com",
+            "name": "John Doe",
+            "participationStatus": "needs-action",
+        })
+    );
+    assert_eq!(response["list"][1]["calendarInvitation"], json!(null));
+
+    // Accept the invitation, an iTIP reply should be sent to the organizer
+    let response = jmap_request(
+        &server,
+        Some(("jdoe@example.com", "12345")),
+        USING,
+        "Email/invitationReply",
+        json!({
+            "accountId": account_id,
+            "emailId": email_id,
+            "participationStatus": "accepted",
+        }),
+    )
+    .await;
+    assert_eq!(response["participationStatus"], json!("accepted"));
+    assert_message_delivery(
+        &mut smtp_rx,
+        MockMessage::new(
+            "<jdoe@example.com>",
+            ["<bill@example.com>"],
+            "@Accepted: Quarterly planning",
+        ),
+        false,
+    )
+    .await;
+
+    // Messages without an invitation cannot be replied to
+    lmtp.ingest(
+        "bill@example.com",
+        &["jdoe@example.com"],
+        concat!(
+            "From: bill@example.com\r\n",
+            "To: jdoe@example.com\r\n",
+            "Subject: Regular message\r\n",
+            "\r\n",
+            "No invitation here."
+        ),
+    )
+    .await;
+    let response = jmap_request(
+        &server,
+        Some(("jdoe@example.com", "12345")),
+        USING,
+        "Email/invitationReply",
+        json!({
+            "accountId": account_id,
+            "emailId": query_email(client, "Regular").await,
+            "participationStatus": "declined",
+        }),
+    )
+    .await;
+    assert_eq!(response["type"], json!("invalidArguments"));
+
+    // Remove test data
+    client
+        .set_default_account_id(JMAPId::new(SUPERUSER_ID as u64))
+        .principal_destroy(&account_id)
+        .await
+        .unwrap();
+    client.principal_destroy(&domain_id).await.unwrap();
+    server.store.principal_purge().unwrap();
+    server.store.assert_is_empty();
+}
+
+async fn query_email(client: &mut Client, subject: &str) -> String {
+    client
+        .email_query(
+            email::query::Filter::subject(subject).into(),
+            None::<Vec<_>>,
+        )
+        .await
+        .unwrap()
+        .take_ids()
+        .pop()
+        .unwrap()
+}
+
+fn build_invitation(summary: &str) -> String {
+    format!(
+        concat!(
+            "From: Bill <bill@example.com>\r\n",
+            "To: jdoe@example.com\r\n",
+            "Subject: Invitation: {}\r\n",
+            "MIME-Version: 1.0\r\n",
+            "Content-Type: multipart/alternative; boundary=\"invitation\"\r\n",
+            "\r\n",
+            "--invitation\r\n",
+            "Content-Type: text/plain\r\n",
+            "\r\n",
+            "You have been invited to {}.\r\n",
+            "--invitation\r\n",
+            "Content-Type: text/calendar; charset=utf-8; method=REQUEST\r\n",
+            "\r\n",
+            "BEGIN:VCALENDAR\r\n",
+            "VERSION:2.0\r\n",
+            "PRODID:-//Example//Calendar//EN\r\n",
+            "METHOD:REQUEST\r\n",
+            "BEGIN:VEVENT\r\n",
+            "UID:e2c1a4f0-planning@example.com\r\n",
+            "SEQUENCE:1\r\n",
+            "DTSTAMP:20221001T080000Z\r\n",
+            "DTSTART;TZID=Europe/Berlin:20221010T100000\r\n",
+            "DTEND;TZID=Europe/Berlin:20221010T110000\r\n",
+            "SUMMARY:{}\r\n",
+            "ORGANIZER;CN=Bill:mailto:bill@example.com\r\n",
+            "ATTENDEE;CN=\"John Doe\";PARTSTAT=NEEDS-ACTION;RSVP=TRUE:mailto:jdoe@example.com\r\n",
+            "END:VEVENT\r\n",
+            "END:VCALENDAR\r\n",
+            "--invitation--\r\n"
+        ),
+        summary, summary, summary
+    )
+}
@@ -0,0 +1,367 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use actix_web::web;
+use jmap::{
+    orm::{serialize::JMAPOrm, TinyORM},
+    principal::schema::{Principal, Property, Value},
+    types::jmap::JMAPId,
+    SUPERUSER_ID,
+};
+use jmap_client::client::Client;
+use jmap_mail::INBOX_ID;
+use jmap_sharing::principal::set::JMAPSetPrincipal;
+use serde_json::json;
+use store::{
+    core::{collection::Collection, document::Document},
+    write::batch::WriteBatch,
+    AccountId, Store,
+};
+
+use crate::{
+    tests::{
+        jmap::jmap_request,
+        jmap_mail::lmtp::{AssertResult, SmtpConnection},
+        store::utils::StoreCompareWith,
+    },
+    JMAPServer,
+};
+
+const USING: &[&str] = &["urn:ietf:params:jmap:core", "urn:ietf:params:jmap:quota"];
+
+pub async fn test<T>(server: web::Data<JMAPServer<T>>, client: &mut Client)
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    println!("Running Email Quota tests...");
+
+    // Create a test account with a quota
+    let domain_id = client
+        .set_default_account_id(JMAPId::new(SUPERUSER_ID as u64))
+        .domain_create("example.com")
+        .await
+        .unwrap()
+        .take_id();
+    let account_id = client
+        .individual_create("jdoe@example.com", "12345", "John Doe")
+        .await
+        .unwrap()
+        .take_id();
+    let document_id = JMAPId::parse(&account_id).unwrap().get_document_id();
+    let inbox_id = JMAPId::new(INBOX_ID as u64).to_string();
+    set_quota(&server, document_id, 1024);
+    client.set_default_account_id(&account_id);
+
+    // Import messages until the quota is exceeded
+    let mut email_ids = Vec::new();
+    let mut used_quota = 0;
+    for num in 0..2 {
+        let message = build_message(num);
+        used_quota += message.len() as i64;
+        email_ids.push(
+            client
+                .email_import(message.into_bytes(), [&inbox_id], None::<Vec<String>>, None)
+                .await
+                .unwrap()
+                .take_id(),
+        );
+    }
+    assert_eq!(
+        server.store.get_used_quota(document_id).unwrap(),
+        used_quota
+    );
+
+    // Quota/get should report the current usage
+    let quota = jmap_request(
+        &server,
+        Some(("jdoe@example.com", "12345")),
+        USING,
+        "Quota/get",
+        json!({
+            "accountId": account_id,
+            "ids": null,
+        }),
+    )
+    .await;
+    let quota_state = quota["state"].as_str().unwrap().to_string();
+    let quota = &quota["list"][0];
+    let quota_id = quota["id"].as_str().unwrap().to_string();
+    assert_eq!(quota["used"], json!(used_quota));
+    assert_eq!(quota["hardLimit"], json!(1024));
+    assert_eq!(quota["resourceType"], json!("octets"));
+    assert_eq!(quota["scope"], json!("account"));
+    assert_eq!(quota["name"], json!("jdoe@example.com"));
+    assert_eq!(quota["types"], json!(["Mail"]));
+
+    // Unknown properties are rejected
+    assert_eq!(
+        jmap_request(
+            &server,
+            Some(("jdoe@example.com", "12345")),
+            USING,
+            "Quota/get",
+            json!({
+                "accountId": account_id,
+                "ids": null,
+                "properties": ["bogus"],
+            }),
+        )
+        .await["type"],
+        json!("invalidArguments")
+    );
+
+    // Quota/query should filter by scope and resource type
+    assert_eq!(
+        jmap_request(
+            &server,
+            Some(("jdoe@example.com", "12345")),
+            USING,
+            "Quota/query",
+            json!({
+                "accountId": account_id,
+                "filter": {"resourceType": "octets", "scope": "account"},
+            }),
+        )
+        .await["ids"],
+        json!([quota_id])
+    );
+    assert_eq!(
+        jmap_request(
+            &server,
+            Some(("jdoe@example.com", "12345")),
+            USING,
+            "Quota/query",
+            json!({
+                "accountId": account_id,
+                "filter": {"scope": "domain"},
+            }),
+        )
+        .await["ids"],
+        json!([])
+    );
+
+    // Importing a message over quota should fail, either at upload or import time
+    assert!(client
+        .email_import(
+            build_message(2).into_bytes(),
+            [&inbox_id],
+            None::<Vec<String>>,
+            None,
+        )
+        .await
+        .is_err());
+    assert_eq!(
+        server.store.get_used_quota(document_id).unwrap(),
+        used_quota
+    );
+
+    // Uploads over quota should fail
+    assert!(client.upload(None, vec![b'a'; 512], None).await.is_err());
+
+    // LMTP delivery over quota should fail
+    let mut lmtp = SmtpConnection::connect().await;
+    lmtp.mail_from("bill@example.com", 2).await;
+    lmtp.rcpt_to("jdoe@example.com", 2).await;
+    lmtp.data(3).await;
+    lmtp.data_bytes(&build_message(3), 1, 5)
+        .await
+        .assert_contains("552 5.2.2");
+
+    // Deleting a message should free up space
+    client
+        .email_destroy(&email_ids.pop().unwrap())
+        .await
+        .unwrap();
+    used_quota -= build_message(1).len() as i64;
+    assert_eq!(
+        server.store.get_used_quota(document_id).unwrap(),
+        used_quota
+    );
+
+    // Pending uploads should count towards the quota until imported
+    let message = build_message(5);
+    let blob_id = client
+        .upload(None, message.as_bytes().to_vec(), None)
+        .await
+        .unwrap()
+        .take_blob_id();
+    assert_eq!(
+        server.store.get_pending_quota(document_id).unwrap(),
+        message.len() as i64
+    );
+    assert!(client.upload(None, vec![b'a'; 512], None).await.is_err());
+    let email_id = jmap_request(
+        &server,
+        Some(("jdoe@example.com", "12345")),
+        &["urn:ietf:params:jmap:core", "urn:ietf:params:jmap:mail"],
+        "Email/import",
+        json!({
+            "accountId": account_id,
+            "emails": {
+                "i0": {
+                    "blobId": blob_id,
+                    "mailboxIds": {&inbox_id: true},
+                },
+            },
+        }),
+    )
+    .await["created"]["i0"]["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    email_ids.push(email_id);
+    used_quota += message.len() as i64;
+    assert_eq!(server.store.get_pending_quota(document_id).unwrap(), 0);
+    assert_eq!(
+        server.store.get_used_quota(document_id).unwrap(),
+        used_quota
+    );
+
+    // Quota/changes should report the updated usage
+    assert_eq!(
+        jmap_request(
+            &server,
+            Some(("jdoe@example.com", "12345")),
+            USING,
+            "Quota/changes",
+            json!({
+                "accountId": account_id,
+                "sinceState": quota_state,
+            }),
+        )
+        .await["updated"],
+        json!([quota_id])
+    );
+
+    // Changing the limit through Principal/set should be reported by Quota/changes
+    let quota_state = jmap_request(
+        &server,
+        Some(("jdoe@example.com", "12345")),
+        USING,
+        "Quota/get",
+        json!({
+            "accountId": account_id,
+            "ids": null,
+        }),
+    )
+    .await["state"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    assert_eq!(
+        jmap_request(
+            &server,
+            None,
+            USING,
+            "Principal/set",
+            json!({
+                "accountId": JMAPId::new(SUPERUSER_ID as u64).to_string(),
+                "update": {
+                    &account_id: {"quota": 2048},
+                },
+            }),
+        )
+        .await["updated"],
+        json!({ &account_id: null })
+    );
+    assert_eq!(
+        jmap_request(
+            &server,
+            Some(("jdoe@example.com", "12345")),
+            USING,
+            "Quota/changes",
+            json!({
+                "accountId": account_id,
+                "sinceState": quota_state,
+            }),
+        )
+        .await["updated"],
+        json!([quota_id])
+    );
+    assert_eq!(
+        jmap_request(
+            &server,
+            Some(("jdoe@example.com", "12345")),
+            USING,
+            "Quota/get",
+            json!({
+                "accountId": account_id,
+                "ids": null,
+            }),
+        )
+        .await["list"][0]["hardLimit"],
+        json!(2048)
+    );
+
+    lmtp.ingest("bill@example.com", &["jdoe@example.com"], &build_message(4))
+        .await;
+    used_quota += build_message(4).len() as i64;
+    assert_eq!(
+        server.store.get_used_quota(document_id).unwrap(),
+        used_quota
+    );
+
+    // Remove test data
+    client
+        .set_default_account_id(JMAPId::new(SUPERUSER_ID as u64))
+        .principal_destroy(&account_id)
+        .await
+        .unwrap();
+    client.principal_destroy(&domain_id).await.unwrap();
+    server.store.principal_purge().unwrap();
+    server.store.assert_is_empty();
+}
+
+fn set_quota<T>(server: &JMAPServer<T>, account_id: AccountId, quota: i64)
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    let current_fields = server
+        .store
+        .get_orm::<Principal>(SUPERUSER_ID, account_id)
+        .unwrap()
+        .unwrap();
+    let mut fields = TinyORM::track_changes(&current_fields);
+    fields.set(Property::Quota, Value::Number { value: quota });
+
+    let mut document = Document::new(Collection::Principal, account_id);
+    let mut batch = WriteBatch::new(SUPERUSER_ID);
+    current_fields.merge(&mut document, fields).unwrap();
+    batch.update_document(document);
+    batch.log_update(Collection::Principal, account_id as u64);
+    server.store.write(batch).unwrap();
+}
+
+fn build_message(num: usize) -> String {
+    format!(
+        concat!(
+            "From: bill@example.com\r\n",
+            "To: jdoe@example.com\r\n",
+            "Subject: TPS Report #{}\r\n",
+            "\r\n",
+            "{}"
+        ),
+        num,
+        "I'm going to need those TPS reports ASAP. ".repeat(10)
+    )
+}
@@ -28,6 +28,7 @@ use jmap::{types::jmap::JMAPId, SUPERUSER_ID};
 use jmap_client::{
     client::Client,
     core::set::{SetError, SetErrorType, SetObject},
+    email,
     email_submission::{Address, Delivered, DeliveryStatus, Displayed, UndoStatus},
     mailbox::Role,
     Error,
@@ -70,6 +71,7 @@ impl MockMessage {
 pub struct MockSMTPSettings {
     pub fail_mail_from: bool,
     pub fail_rcpt_to: bool,
+    pub defer_rcpt_to: bool,
     pub fail_message: bool,
     pub do_stop: bool,
 }
@@ -400,6 +402,79 @@ where
     );
     smtp_settings.lock().fail_message = false;
 
+    // Permanent failures should be reported to the sender's Inbox
+    assert_eq!(
+        client
+            .email_query(
+                email::query::Filter::subject("Undelivered Mail Returned to Sender").into(),
+                None::<Vec<_>>,
+            )
+            .await
+            .unwrap()
+            .ids()
+            .len(),
+        2
+    );
+
+    // Temporary failures should be retried
+    smtp_settings.lock().defer_rcpt_to = true;
+    let email_submission_id = client
+        .email_submission_create_envelope(
+            &email_id,
+            &identity_id,
+            "jdoe@example.com",
+            ["jane_smith@example.com"],
+        )
+        .await
+        .unwrap()
+        .take_id();
+    expect_nothing(&mut smtp_rx).await;
+    let email_submission = client
+        .email_submission_get(&email_submission_id, None)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        email_submission.undo_status().unwrap(),
+        &UndoStatus::Pending
+    );
+    assert_eq!(
+        email_submission.delivery_status().unwrap(),
+        &AHashMap::from_iter([(
+            "jane_smith@example.com".to_string(),
+            DeliveryStatus::new(
+                "451 4.3.0 Try again later.",
+                Delivered::Queued,
+                Displayed::Unknown
+            )
+        )])
+    );
+    smtp_settings.lock().defer_rcpt_to = false;
+    assert_message_delivery(
+        &mut smtp_rx,
+        MockMessage::new(
+            "<jdoe@example.com>",
+            ["<jane_smith@example.com>"],
+            email_body,
+        ),
+        false,
+    )
+    .await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let email_submission = client
+        .email_submission_get(&email_submission_id, None)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(email_submission.undo_status().unwrap(), &UndoStatus::Final);
+    assert_eq!(
+        email_submission.delivery_status().unwrap(),
+        &AHashMap::from_iter([(
+            "jane_smith@example.com".to_string(),
+            DeliveryStatus::new("250 OK", Delivered::Queued, Displayed::Unknown)
+        )])
+    );
+
     // Enable DKIM for the domain
     client
         .set_default_account_id(JMAPId::from(SUPERUSER_ID))
@@ -408,7 +483,7 @@ where
         .unwrap();
     client.set_default_account_id(&account_id);
 
-    // Confirm that the sendAt property is updated when using FUTURERELEASE
+    // Submissions using FUTURERELEASE should be held until they are due
     let email_submission_id = client
         .email_submission_create_envelope(
             &email_id,
@@ -419,10 +494,64 @@ where
         .await
         .unwrap()
         .take_id();
+    expect_nothing(&mut smtp_rx).await;
+    let email_submission = client
+        .email_submission_get(&email_submission_id, None)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        email_submission.send_at().unwrap(),
+        DateTime::parse_from_rfc3339("2079-11-20T05:00:00Z")
+            .unwrap()
+            .timestamp()
+    );
+    assert_eq!(
+        email_submission.undo_status().unwrap(),
+        &UndoStatus::Pending
+    );
+
+    // Pending submissions can be canceled, but only once
+    client
+        .email_submission_change_status(&email_submission_id, UndoStatus::Canceled)
+        .await
+        .unwrap();
+    assert_eq!(
+        client
+            .email_submission_get(&email_submission_id, None)
+            .await
+            .unwrap()
+            .unwrap()
+            .undo_status()
+            .unwrap(),
+        &UndoStatus::Canceled
+    );
+    assert!(matches!(
+        client
+            .email_submission_change_status(&email_submission_id, UndoStatus::Canceled)
+            .await,
+        Err(Error::Set(SetError {
+            type_: SetErrorType::CannotUnsend,
+            ..
+        }))
+    ));
+
+    // Held submissions are sent once they are due
+    let email_submission_id = client
+        .email_submission_create_envelope(
+            &email_id,
+            &identity_id,
+            Address::new("jdoe@example.com").parameter("HOLDFOR", Some("2")),
+            ["jane_smith@example.com"],
+        )
+        .await
+        .unwrap()
+        .take_id();
+    expect_nothing(&mut smtp_rx).await;
     assert_message_delivery(
         &mut smtp_rx,
         MockMessage::new(
-            "<jdoe@example.com> HOLDUNTIL=2079-11-20T05:00:00Z",
+            "<jdoe@example.com>",
             ["<jane_smith@example.com>"],
             email_body,
         ),
@@ -430,16 +559,15 @@ where
     )
     .await;
     tokio::time::sleep(Duration::from_millis(100)).await;
-    let email_submission = client
-        .email_submission_get(&email_submission_id, None)
-        .await
-        .unwrap()
-        .unwrap();
     assert_eq!(
-        email_submission.send_at().unwrap(),
-        DateTime::parse_from_rfc3339("2079-11-20T05:00:00Z")
+        client
+            .email_submission_get(&email_submission_id, None)
+            .await
             .unwrap()
-            .timestamp()
+            .unwrap()
+            .undo_status()
+            .unwrap(),
+        &UndoStatus::Final
     );
 
     // Verify onSuccessUpdateEmail action
@@ -515,7 +643,7 @@ pub fn spawn_mock_smtp_server() -> (mpsc::Receiver<MockMessage>, Arc<Mutex<MockS
                 .await
                 .unwrap();
 
-            while rx.read_line(&mut buf).await.is_ok() {
+            while matches!(rx.read_line(&mut buf).await, Ok(bytes) if bytes > 0) {
                 print!("-> {}", buf);
                 if buf.starts_with("EHLO") {
                     tx.write_all(b"250 Hi there, but I have no extensions to offer :-(\r\n")
@@ -537,6 +665,10 @@ pub fn spawn_mock_smtp_server() -> (mpsc::Receiver<MockMessage>, Arc<Mutex<MockS
                         )
                         .await
                         .unwrap();
+                    } else if settings.lock().defer_rcpt_to {
+                        tx.write_all("451 4.3.0 Try again later.\r\n".as_bytes())
+                            .await
+                            .unwrap();
                     } else {
                         message
                             .rcpt_to
@@ -574,6 +706,10 @@ pub fn spawn_mock_smtp_server() -> (mpsc::Receiver<MockMessage>, Arc<Mutex<MockS
                             .await
                             .unwrap();
                     }
+                } else if buf.starts_with("STARTTLS") {
+                    tx.write_all(b"502 5.5.1 I do not speak TLS.\r\n")
+                        .await
+                        .unwrap();
                 } else if buf.starts_with("QUIT") {
                     tx.write_all("250 Arrivederci!\r\n".as_bytes())
                         .await
@@ -0,0 +1,240 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::Duration;
+
+use jmap::types::jmap::JMAPId;
+use jmap_client::{
+    email,
+    email_submission::{Delivered, DeliveryStatus, Displayed, UndoStatus},
+    mailbox::Role,
+};
+use store::{ahash::AHashMap, Store};
+use tokio::net::UdpSocket;
+
+use crate::tests::{
+    jmap::start_jmap_server,
+    jmap_mail::email_submission::{assert_message_delivery, spawn_mock_smtp_server, MockMessage},
+    store::utils::{destroy_temp_dir, init_settings},
+};
+
+pub async fn test<T>()
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    println!("Running E-mail MX delivery tests...");
+
+    // Start a server that delivers messages directly to the recipient's MX hosts
+    let (mut settings, temp_dir) = init_settings("jmap_mail_mx_tests", 2, 1, true);
+    for (key, value) in [
+        ("smtp-delivery-mode", "mx"),
+        ("smtp-mx-port", "9999"),
+        ("smtp-mx-dns-server", "127.0.0.1:9953"),
+    ] {
+        settings.set_value(key.to_string(), value.to_string());
+    }
+    let (_server, mut client, handle) = start_jmap_server::<T>(settings).await;
+
+    // Start mock DNS and SMTP servers
+    spawn_mock_dns_server();
+    let (mut smtp_rx, _) = spawn_mock_smtp_server();
+
+    // Create a domain, a test account and an identity
+    client
+        .set_default_account_id(JMAPId::new(0))
+        .domain_create("example.com")
+        .await
+        .unwrap();
+    let account_id = client
+        .individual_create("jdoe@example.com", "12345", "John Doe")
+        .await
+        .unwrap()
+        .take_id();
+    let identity_id = client
+        .set_default_account_id(&account_id)
+        .identity_create("John Doe", "jdoe@example.com")
+        .await
+        .unwrap()
+        .take_id();
+    let mailbox_id = client
+        .mailbox_create("JMAP EmailSubmission", None::<String>, Role::None)
+        .await
+        .unwrap()
+        .take_id();
+
+    // The first MX host of example.org is down and example.net does not accept mail
+    let email_body = "From: jdoe@example.com\r\nTo: jane@example.org\r\nSubject: hey\r\n\r\ntest";
+    let email_id = client
+        .email_import(
+            email_body.as_bytes().to_vec(),
+            [&mailbox_id],
+            None::<Vec<&str>>,
+            None,
+        )
+        .await
+        .unwrap()
+        .take_id();
+    let email_submission_id = client
+        .email_submission_create_envelope(
+            &email_id,
+            &identity_id,
+            "jdoe@example.com",
+            ["jane@example.org", "john@Example.org", "bill@example.net"],
+        )
+        .await
+        .unwrap()
+        .take_id();
+
+    // Recipients on the same domain should be delivered in a single transaction
+    assert_message_delivery(
+        &mut smtp_rx,
+        MockMessage::new(
+            "<jdoe@example.com>",
+            ["<jane@example.org>", "<john@Example.org>"],
+            email_body,
+        ),
+        false,
+    )
+    .await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let email_submission = client
+        .email_submission_get(&email_submission_id, None)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(email_submission.undo_status().unwrap(), &UndoStatus::Final);
+    assert_eq!(
+        email_submission.delivery_status().unwrap(),
+        &AHashMap::from_iter([
+            (
+                "jane@example.org".to_string(),
+                DeliveryStatus::new("250 OK", Delivered::Queued, Displayed::Unknown)
+            ),
+            (
+                "john@Example.org".to_string(),
+                DeliveryStatus::new("250 OK", Delivered::Queued, Displayed::Unknown)
+            ),
+            (
+                "bill@example.net".to_string(),
+                DeliveryStatus::new(
+                    "556 5.1.10 Domain example.net does not accept mail.",
+                    Delivered::No,
+                    Displayed::Unknown
+                )
+            ),
+        ])
+    );
+
+    // The sender should have received a bounce for example.net
+    assert_eq!(
+        client
+            .email_query(
+                email::query::Filter::subject("Undelivered Mail Returned to Sender").into(),
+                None::<Vec<_>>,
+            )
+            .await
+            .unwrap()
+            .ids()
+            .len(),
+        1
+    );
+
+    handle.stop(true).await;
+    destroy_temp_dir(&temp_dir);
+}
+
+// Answers MX and A queries for a few test domains.
+pub fn spawn_mock_dns_server() {
+    tokio::spawn(async move {
+        let socket = UdpSocket::bind("127.0.0.1:9953").await.unwrap_or_else(|e| {
+            panic!("Failed to bind mock DNS server to 127.0.0.1:9953: {}", e);
+        });
+        let mut buf = vec![0u8; 512];
+
+        while let Ok((size, addr)) = socket.recv_from(&mut buf).await {
+            if let Some(response) = build_dns_response(&buf[..size]) {
+                socket.send_to(&response, addr).await.unwrap();
+            }
+        }
+    });
+}
+
+fn build_dns_response(query: &[u8]) -> Option<Vec<u8>> {
+    // Parse question
+    let mut pos = 12;
+    let mut labels = Vec::new();
+    loop {
+        let len = *query.get(pos)? as usize;
+        pos += 1;
+        if len == 0 {
+            break;
+        }
+        labels.push(
+            std::str::from_utf8(query.get(pos..pos + len)?)
+                .ok()?
+                .to_lowercase(),
+        );
+        pos += len;
+    }
+    let name = labels.join(".");
+    let qtype = u16::from_be_bytes([*query.get(pos)?, *query.get(pos + 1)?]);
+    let question = query.get(12..pos + 4)?;
+
+    let answers: Vec<(u16, Vec<u8>)> = match (qtype, name.as_str()) {
+        (15, "example.org") => vec![
+            (15, mx_record(10, "mx1.example.org")),
+            (15, mx_record(20, "mx2.example.org")),
+        ],
+        (15, "example.net") => vec![(15, mx_record(0, ""))],
+        (1, "mx1.example.org") => vec![(1, vec![127, 0, 0, 2])],
+        (1, "mx2.example.org") => vec![(1, vec![127, 0, 0, 1])],
+        _ => vec![],
+    };
+
+    let mut response = Vec::with_capacity(512);
+    response.extend_from_slice(&query[..2]);
+    response.extend_from_slice(&[0x81, 0x80, 0x00, 0x01]);
+    response.extend_from_slice(&(answers.len() as u16).to_be_bytes());
+    response.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+    response.extend_from_slice(question);
+    for (rtype, rdata) in answers {
+        response.extend_from_slice(&[0xc0, 0x0c]);
+        response.extend_from_slice(&rtype.to_be_bytes());
+        response.extend_from_slice(&[0x00, 0x01]);
+        response.extend_from_slice(&60u32.to_be_bytes());
+        response.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        response.extend_from_slice(&rdata);
+    }
+
+    Some(response)
+}
+
+fn mx_record(preference: u16, exchange: &str) -> Vec<u8> {
+    let mut rdata = preference.to_be_bytes().to_vec();
+    for label in exchange.split('.').filter(|label| !label.is_empty()) {
+        rdata.push(label.len() as u8);
+        rdata.extend_from_slice(label.as_bytes());
+    }
+    rdata.push(0);
+    rdata
+}
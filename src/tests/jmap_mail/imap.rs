@@ -0,0 +1,221 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use actix_web::web;
+use jmap::{types::jmap::JMAPId, SUPERUSER_ID};
+use jmap_client::{client::Client, mailbox::Role};
+use store::Store;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines, ReadHalf, WriteHalf},
+    net::TcpStream,
+};
+
+use crate::{tests::store::utils::StoreCompareWith, JMAPServer};
+
+pub async fn test<T>(server: web::Data<JMAPServer<T>>, client: &mut Client)
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    println!("Running IMAP tests...");
+
+    // Create a test account with an Inbox
+    let domain_id = client
+        .set_default_account_id(JMAPId::new(SUPERUSER_ID as u64))
+        .domain_create("example.com")
+        .await
+        .unwrap()
+        .take_id();
+    let account_id = client
+        .individual_create("jdoe@example.com", "12345", "John Doe")
+        .await
+        .unwrap()
+        .take_id();
+    client
+        .set_default_account_id(&account_id)
+        .mailbox_create("Inbox", None::<String>, Role::Inbox)
+        .await
+        .unwrap();
+
+    // Greeting and authentication
+    let mut imap = ImapConnection::connect().await;
+    assert!(imap.greeting.starts_with("* OK [CAPABILITY IMAP4rev2"));
+    imap.assert_ok("A1", "LOGIN jdoe@example.com wrongpass", false)
+        .await;
+    imap.assert_ok("A2", "LOGIN jdoe@example.com 12345", true)
+        .await;
+
+    // Mailbox management
+    imap.assert_ok("A3", "CREATE \"Archive/2022\"", true).await;
+    let lines = imap.assert_ok("A4", "LIST \"\" \"*\"", true).await;
+    for name in ["\"INBOX\"", "\"Archive\"", "\"Archive/2022\""] {
+        assert!(
+            lines
+                .iter()
+                .any(|line| line.starts_with("* LIST") && line.ends_with(name)),
+            "{:?}",
+            lines
+        );
+    }
+
+    // Append messages to the selected mailbox
+    let lines = imap.assert_ok("A5", "SELECT INBOX", true).await;
+    assert!(lines.iter().any(|line| line == "* 0 EXISTS"), "{:?}", lines);
+    let lines = imap
+        .append("A6", "INBOX (\\Seen)", &build_message("First message"))
+        .await;
+    assert!(lines.iter().any(|line| line == "* 1 EXISTS"), "{:?}", lines);
+    assert!(lines.last().unwrap().contains("[APPENDUID "));
+    imap.append("A7", "INBOX", &build_message("Second message"))
+        .await;
+
+    // Fetch
+    let lines = imap
+        .assert_ok("A8", "UID FETCH 1:* (FLAGS ENVELOPE)", true)
+        .await;
+    assert!(
+        lines[0].starts_with("* 1 FETCH") && lines[0].contains("\\Seen"),
+        "{:?}",
+        lines
+    );
+    assert!(lines.iter().any(|line| line.contains("\"Second message\"")));
+    let lines = imap
+        .assert_ok("A9", "FETCH 2 (BODY.PEEK[HEADER.FIELDS (SUBJECT)])", true)
+        .await;
+    assert!(lines.iter().any(|line| line == "Subject: Second message"));
+
+    // Store and search
+    let lines = imap
+        .assert_ok("B1", "STORE 2 +FLAGS (\\Flagged)", true)
+        .await;
+    assert!(lines[0].contains("\\Flagged"), "{:?}", lines);
+    let lines = imap.assert_ok("B2", "SEARCH FLAGGED", true).await;
+    assert_eq!(lines[0], "* SEARCH 2");
+    let lines = imap.assert_ok("B3", "SEARCH UNSEEN", true).await;
+    assert_eq!(lines[0], "* SEARCH 2");
+
+    // Copy and expunge
+    let lines = imap.assert_ok("B4", "COPY 1 \"Archive/2022\"", true).await;
+    assert!(lines.last().unwrap().contains("[COPYUID "));
+    imap.assert_ok("B5", "STORE 1 +FLAGS.SILENT (\\Deleted)", true)
+        .await;
+    let lines = imap.assert_ok("B6", "EXPUNGE", true).await;
+    assert_eq!(lines[0], "* 1 EXPUNGE");
+    let lines = imap
+        .assert_ok("B7", "STATUS \"Archive/2022\" (MESSAGES UNSEEN)", true)
+        .await;
+    assert_eq!(lines[0], "* STATUS \"Archive/2022\" (MESSAGES 1 UNSEEN 0)");
+
+    // Logout
+    let lines = imap.assert_ok("B8", "LOGOUT", true).await;
+    assert!(lines[0].starts_with("* BYE"));
+
+    // Remove test data
+    client
+        .set_default_account_id(JMAPId::new(SUPERUSER_ID as u64))
+        .principal_destroy(&account_id)
+        .await
+        .unwrap();
+    client.principal_destroy(&domain_id).await.unwrap();
+    server.store.principal_purge().unwrap();
+    server.store.assert_is_empty();
+}
+
+fn build_message(subject: &str) -> String {
+    format!(
+        concat!(
+            "From: Bill Foobar <bill@example.com>\r\n",
+            "To: John Doe <jdoe@example.com>\r\n",
+            "Subject: {}\r\n",
+            "\r\n",
+            "This is a test message.\r\n"
+        ),
+        subject
+    )
+}
+
+pub struct ImapConnection {
+    greeting: String,
+    reader: Lines<BufReader<ReadHalf<TcpStream>>>,
+    writer: WriteHalf<TcpStream>,
+}
+
+impl ImapConnection {
+    pub async fn connect() -> Self {
+        let (reader, writer) =
+            tokio::io::split(TcpStream::connect("127.0.0.1:11431").await.unwrap());
+        let mut reader = BufReader::new(reader).lines();
+        ImapConnection {
+            greeting: reader.next_line().await.unwrap().unwrap(),
+            reader,
+            writer,
+        }
+    }
+
+    pub async fn assert_ok(&mut self, tag: &str, command: &str, is_ok: bool) -> Vec<String> {
+        self.send(&format!("{} {}\r\n", tag, command)).await;
+        self.read(tag, is_ok).await
+    }
+
+    pub async fn append(&mut self, tag: &str, args: &str, message: &str) -> Vec<String> {
+        self.send(&format!(
+            "{} APPEND {} {{{}+}}\r\n{}\r\n",
+            tag,
+            args,
+            message.len(),
+            message
+        ))
+        .await;
+        self.read(tag, true).await
+    }
+
+    async fn send(&mut self, text: &str) {
+        self.writer.write_all(text.as_bytes()).await.unwrap();
+    }
+
+    async fn read(&mut self, tag: &str, is_ok: bool) -> Vec<String> {
+        let mut lines = Vec::new();
+        loop {
+            let line = tokio::time::timeout(
+                std::time::Duration::from_millis(1500),
+                self.reader.next_line(),
+            )
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+            let is_tagged = line.starts_with(&format!("{} ", tag));
+            lines.push(line);
+            if is_tagged {
+                break;
+            }
+        }
+        let status = lines.last().unwrap()[tag.len() + 1..].to_string();
+        assert_eq!(
+            status.starts_with("OK"),
+            is_ok,
+            "Unexpected response: {:?}",
+            lines
+        );
+        lines
+    }
+}
@@ -25,19 +25,27 @@ use store_rocksdb::RocksDB;
 
 use super::{jmap::init_jmap_tests, store::utils::destroy_temp_dir};
 
+pub mod calendar_invitation;
 pub mod email_changes;
 pub mod email_copy;
 pub mod email_get;
 pub mod email_parse;
 pub mod email_query;
 pub mod email_query_changes;
+pub mod email_quota;
 pub mod email_set;
 pub mod email_submission;
+pub mod email_submission_mx;
 pub mod email_thread;
 pub mod email_thread_merge;
+pub mod imap;
 pub mod lmtp;
 pub mod mailbox;
+pub mod pop3;
 pub mod search_snippet;
+pub mod sieve_script;
+pub mod smtp;
+pub mod spam_filter;
 pub mod vacation_response;
 
 #[actix_web::test]
@@ -57,9 +65,17 @@ async fn jmap_mail_tests() {
     email_copy::test(server.clone(), &mut client).await;
     email_submission::test(server.clone(), &mut client).await;
     lmtp::test(server.clone(), &mut client).await;
+    email_quota::test(server.clone(), &mut client).await;
     vacation_response::test(server.clone(), &mut client).await;
+    smtp::test(server.clone(), &mut client).await;
+    sieve_script::test(server.clone(), &mut client).await;
+    spam_filter::test(server.clone(), &mut client).await;
+    calendar_invitation::test(server.clone(), &mut client).await;
     mailbox::test(server.clone(), &mut client).await;
+    imap::test(server.clone(), &mut client).await;
+    pop3::test(server.clone(), &mut client).await;
     search_snippet::test(server.clone(), &mut client).await;
+    email_submission_mx::test::<RocksDB>().await;
 
     destroy_temp_dir(&temp_dir);
 }
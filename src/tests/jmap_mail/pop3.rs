@@ -0,0 +1,254 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use actix_web::web;
+use jmap::{base64, types::jmap::JMAPId, SUPERUSER_ID};
+use jmap_client::{client::Client, email, mailbox::Role};
+use store::Store;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines, ReadHalf, WriteHalf},
+    net::TcpStream,
+};
+
+use crate::{tests::store::utils::StoreCompareWith, JMAPServer};
+
+pub async fn test<T>(server: web::Data<JMAPServer<T>>, client: &mut Client)
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    println!("Running POP3 tests...");
+
+    // Create a test account with an Inbox and an Archive folder
+    let domain_id = client
+        .set_default_account_id(JMAPId::new(SUPERUSER_ID as u64))
+        .domain_create("example.com")
+        .await
+        .unwrap()
+        .take_id();
+    let account_id = client
+        .individual_create("jdoe@example.com", "12345", "John Doe")
+        .await
+        .unwrap()
+        .take_id();
+    client.set_default_account_id(&account_id);
+    let inbox_id = client
+        .mailbox_create("Inbox", None::<String>, Role::Inbox)
+        .await
+        .unwrap()
+        .take_id();
+    let archive_id = client
+        .mailbox_create("Archive", None::<String>, Role::Archive)
+        .await
+        .unwrap()
+        .take_id();
+
+    // Import test messages, the last one is flagged as deleted and is not listed
+    let mut message_ids = Vec::new();
+    for (num, (mailbox_ids, keywords)) in [
+        (vec![&inbox_id], vec![]),
+        (vec![&inbox_id], vec!["$seen"]),
+        (vec![&inbox_id, &archive_id], vec![]),
+        (vec![&inbox_id], vec!["$deleted"]),
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        message_ids.push(
+            client
+                .email_import(
+                    build_message(num + 1).into_bytes(),
+                    mailbox_ids,
+                    keywords.into(),
+                    Some(1000 * (num as i64 + 1)),
+                )
+                .await
+                .unwrap()
+                .take_id(),
+        );
+    }
+
+    // Authenticate
+    let mut pop3 = Pop3Connection::connect().await;
+    assert!(pop3.greeting.starts_with("+OK"));
+    let capabilities = pop3.multi_line("CAPA", true).await;
+    for capability in ["USER", "UIDL", "TOP", "SASL PLAIN"] {
+        assert!(capabilities.iter().any(|c| c == capability));
+    }
+    pop3.single_line("RETR 1", false).await;
+    pop3.single_line("USER jdoe@example.com", true).await;
+    assert_eq!(
+        pop3.single_line("PASS wrong", false).await,
+        "-ERR [AUTH] Authentication failed."
+    );
+    assert_eq!(
+        pop3.single_line(
+            &format!("AUTH PLAIN {}", base64::encode("\0jdoe@example.com\012345")),
+            true
+        )
+        .await,
+        "+OK Maildrop has 3 messages (312 octets)."
+    );
+
+    // Listings
+    assert_eq!(pop3.single_line("STAT", true).await, "+OK 3 312");
+    assert_eq!(
+        pop3.multi_line("LIST", true).await,
+        vec!["1 104", "2 104", "3 104"]
+    );
+    let uids = pop3.multi_line("UIDL", true).await;
+    assert_eq!(uids.len(), 3);
+    assert_eq!(
+        pop3.single_line("UIDL 2", true).await,
+        format!("+OK {}", uids[1])
+    );
+    pop3.single_line("LIST 4", false).await;
+
+    // Retrieve messages
+    let message = pop3.multi_line("RETR 1", true).await;
+    assert!(message.iter().any(|line| line == "Subject: POP3 test 1"));
+    assert!(message.iter().any(|line| line == "..dot-stuffed line"));
+    let message = pop3.multi_line("TOP 2 0", true).await;
+    assert_eq!(message.last().unwrap(), "");
+    assert!(message.iter().any(|line| line == "Subject: POP3 test 2"));
+
+    // Deletions are undone by RSET
+    pop3.single_line("DELE 1", true).await;
+    pop3.single_line("DELE 1", false).await;
+    pop3.single_line("RETR 1", false).await;
+    assert_eq!(pop3.single_line("STAT", true).await, "+OK 2 208");
+    pop3.single_line("RSET", true).await;
+    assert_eq!(pop3.single_line("STAT", true).await, "+OK 3 312");
+
+    // Deletions are applied on QUIT
+    pop3.single_line("DELE 1", true).await;
+    pop3.single_line("DELE 3", true).await;
+    assert_eq!(
+        pop3.single_line("QUIT", true).await,
+        "+OK Stalwart POP3 bids you farewell (2 messages removed)."
+    );
+    assert!(client
+        .email_get(&message_ids[0], None::<Vec<email::Property>>)
+        .await
+        .unwrap()
+        .is_none());
+    assert_eq!(
+        client
+            .email_get(&message_ids[2], [email::Property::MailboxIds].into())
+            .await
+            .unwrap()
+            .unwrap()
+            .mailbox_ids(),
+        [archive_id.as_str()]
+    );
+
+    // The remaining message is listed on the next session
+    let mut pop3 = Pop3Connection::connect().await;
+    pop3.single_line("USER jdoe@example.com", true).await;
+    pop3.single_line("PASS 12345", true).await;
+    assert_eq!(
+        pop3.single_line("UIDL 1", true).await,
+        format!("+OK 1 {}", &uids[1][2..])
+    );
+    pop3.single_line("QUIT", true).await;
+
+    // Remove test data
+    client
+        .set_default_account_id(JMAPId::new(SUPERUSER_ID as u64))
+        .principal_destroy(&account_id)
+        .await
+        .unwrap();
+    client.principal_destroy(&domain_id).await.unwrap();
+    server.store.principal_purge().unwrap();
+    server.store.assert_is_empty();
+}
+
+fn build_message(num: usize) -> String {
+    format!(
+        concat!(
+            "From: bill@example.com\r\n",
+            "To: jdoe@example.com\r\n",
+            "Subject: POP3 test {}\r\n",
+            "\r\n",
+            "Message body.\r\n",
+            ".dot-stuffed line\r\n"
+        ),
+        num
+    )
+}
+
+pub struct Pop3Connection {
+    greeting: String,
+    reader: Lines<BufReader<ReadHalf<TcpStream>>>,
+    writer: WriteHalf<TcpStream>,
+}
+
+impl Pop3Connection {
+    pub async fn connect() -> Self {
+        let (reader, writer) =
+            tokio::io::split(TcpStream::connect("127.0.0.1:11101").await.unwrap());
+        let mut reader = BufReader::new(reader).lines();
+        Pop3Connection {
+            greeting: reader.next_line().await.unwrap().unwrap(),
+            reader,
+            writer,
+        }
+    }
+
+    pub async fn single_line(&mut self, command: &str, is_ok: bool) -> String {
+        self.send(command).await;
+        let line = self.read_line().await;
+        assert_eq!(line.starts_with("+OK"), is_ok, "{}: {}", command, line);
+        line
+    }
+
+    pub async fn multi_line(&mut self, command: &str, is_ok: bool) -> Vec<String> {
+        self.single_line(command, is_ok).await;
+        let mut lines = Vec::new();
+        loop {
+            let line = self.read_line().await;
+            if line == "." {
+                break;
+            }
+            lines.push(line);
+        }
+        lines
+    }
+
+    async fn send(&mut self, command: &str) {
+        self.writer
+            .write_all(format!("{}\r\n", command).as_bytes())
+            .await
+            .unwrap();
+    }
+
+    async fn read_line(&mut self) -> String {
+        tokio::time::timeout(
+            std::time::Duration::from_millis(1500),
+            self.reader.next_line(),
+        )
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap()
+    }
+}
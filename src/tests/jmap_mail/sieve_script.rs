@@ -0,0 +1,386 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use actix_web::web;
+use jmap::{types::jmap::JMAPId, SUPERUSER_ID};
+use jmap_client::{client::Client, email, mailbox::Role};
+use jmap_mail::INBOX_ID;
+use serde_json::json;
+use store::{core::collection::Collection, Store};
+
+use crate::{
+    tests::{
+        jmap::jmap_request,
+        jmap_mail::lmtp::{AssertResult, SmtpConnection},
+        store::utils::StoreCompareWith,
+    },
+    JMAPServer,
+};
+
+const USING: &[&str] = &["urn:ietf:params:jmap:core", "urn:ietf:params:jmap:sieve"];
+
+pub async fn test<T>(server: web::Data<JMAPServer<T>>, client: &mut Client)
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    println!("Running Sieve Script tests...");
+
+    // Create a test account
+    let domain_id = client
+        .set_default_account_id(JMAPId::new(SUPERUSER_ID as u64))
+        .domain_create("example.com")
+        .await
+        .unwrap()
+        .take_id();
+    let account_id = client
+        .individual_create("jdoe@example.com", "12345", "John Doe")
+        .await
+        .unwrap()
+        .take_id();
+    client
+        .principal_set_aliases(&account_id, ["john.doe@example.com"].into())
+        .await
+        .unwrap();
+    let document_id = JMAPId::parse(&account_id).unwrap().get_document_id();
+    let inbox_id = JMAPId::new(INBOX_ID as u64).to_string();
+    client.set_default_account_id(&account_id);
+    let reports_id = client
+        .mailbox_create("Reports", None::<String>, Role::None)
+        .await
+        .unwrap()
+        .take_id();
+
+    // Scripts with errors should be rejected
+    let invalid_blob_id = client
+        .upload(None, b"if true { fileinto \"Reports\"; }".to_vec(), None)
+        .await
+        .unwrap()
+        .take_blob_id();
+    let response = jmap_request(
+        &server,
+        Some(("jdoe@example.com", "12345")),
+        USING,
+        "SieveScript/set",
+        json!({
+            "accountId": account_id,
+            "create": {"s0": {"name": "invalid", "blobId": invalid_blob_id}},
+        }),
+    )
+    .await;
+    assert_eq!(response["notCreated"]["s0"]["type"], json!("invalidSieve"));
+
+    // Create and activate a script
+    let blob_id = client
+        .upload(None, SCRIPT.as_bytes().to_vec(), None)
+        .await
+        .unwrap()
+        .take_blob_id();
+    let response = jmap_request(
+        &server,
+        Some(("jdoe@example.com", "12345")),
+        USING,
+        "SieveScript/set",
+        json!({
+            "accountId": account_id,
+            "create": {"s1": {"name": "filters", "blobId": blob_id}},
+            "onSuccessActivateScript": "#s1",
+        }),
+    )
+    .await;
+    let script_id = response["created"]["s1"]["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    // Script names must be unique
+    let response = jmap_request(
+        &server,
+        Some(("jdoe@example.com", "12345")),
+        USING,
+        "SieveScript/set",
+        json!({
+            "accountId": account_id,
+            "create": {"s2": {"name": "filters", "blobId": blob_id}},
+        }),
+    )
+    .await;
+    assert_eq!(response["notCreated"]["s2"]["type"], json!("alreadyExists"));
+
+    // SieveScript/get and SieveScript/query should return the active script
+    let response = jmap_request(
+        &server,
+        Some(("jdoe@example.com", "12345")),
+        USING,
+        "SieveScript/get",
+        json!({
+            "accountId": account_id,
+            "ids": [script_id],
+        }),
+    )
+    .await;
+    assert_eq!(response["list"][0]["name"], json!("filters"));
+    assert_eq!(response["list"][0]["isActive"], json!(true));
+    assert_eq!(
+        jmap_request(
+            &server,
+            Some(("jdoe@example.com", "12345")),
+            USING,
+            "SieveScript/query",
+            json!({
+                "accountId": account_id,
+                "filter": {"isActive": true},
+            }),
+        )
+        .await["ids"],
+        json!([script_id])
+    );
+
+    // Messages matching the script should be filed into the Reports mailbox
+    let mut lmtp = SmtpConnection::connect().await;
+    lmtp.ingest(
+        "bill@example.com",
+        &["jdoe@example.com"],
+        &build_message("bill@example.com", "TPS Report"),
+    )
+    .await;
+    let email_ids = client
+        .email_query(
+            email::query::Filter::in_mailbox(&reports_id).into(),
+            None::<Vec<_>>,
+        )
+        .await
+        .unwrap();
+    assert_eq!(email_ids.ids().len(), 1);
+    let email = client
+        .email_get(&email_ids.ids()[0], None::<Vec<_>>)
+        .await
+        .unwrap()
+        .unwrap();
+    let mut keywords = email.keywords().to_vec();
+    keywords.sort_unstable();
+    assert_eq!(keywords, ["$important", "$seen"]);
+
+    // Messages not matching the script should be delivered to the Inbox
+    lmtp.ingest(
+        "bill@example.com",
+        &["jdoe@example.com"],
+        &build_message("bill@example.com", "Lunch"),
+    )
+    .await;
+    assert_eq!(
+        client
+            .email_query(
+                email::query::Filter::in_mailbox(&inbox_id).into(),
+                None::<Vec<_>>,
+            )
+            .await
+            .unwrap()
+            .ids()
+            .len(),
+        1
+    );
+
+    // Discarded messages should not be stored
+    lmtp.ingest(
+        "spam@example.com",
+        &["jdoe@example.com"],
+        &build_message("spam@example.com", "Buy now"),
+    )
+    .await;
+    assert_eq!(
+        server
+            .store
+            .get_document_ids(document_id, Collection::Mail)
+            .unwrap()
+            .unwrap()
+            .len(),
+        2
+    );
+
+    // Rejected messages should return a permanent failure on a single line
+    lmtp.mail_from("bill@example.com", 2).await;
+    lmtp.rcpt_to("jdoe@example.com", 2).await;
+    lmtp.data(3).await;
+    lmtp.data_bytes(&build_message("bill@example.com", "Reject me"), 1, 5)
+        .await
+        .assert_contains("550 5.7.1")
+        .assert_contains("Not interested 250 Injected");
+
+    // The Sieve envelope contains the recipient the message was addressed to
+    lmtp.ingest(
+        "bill@example.com",
+        &["john.doe@example.com"],
+        &build_message("bill@example.com", "Hello"),
+    )
+    .await;
+    assert_eq!(
+        client
+            .email_query(
+                email::query::Filter::in_mailbox(&reports_id).into(),
+                None::<Vec<_>>,
+            )
+            .await
+            .unwrap()
+            .ids()
+            .len(),
+        2
+    );
+
+    // Redirected messages are not stored, unless they were already
+    // delivered to this recipient, which indicates a mail loop
+    lmtp.ingest(
+        "bill@example.com",
+        &["jdoe@example.com"],
+        &build_message("bill@example.com", "Forward me"),
+    )
+    .await;
+    assert_eq!(
+        server
+            .store
+            .get_document_ids(document_id, Collection::Mail)
+            .unwrap()
+            .unwrap()
+            .len(),
+        3
+    );
+    lmtp.ingest(
+        "bill@example.com",
+        &["jdoe@example.com"],
+        &format!(
+            "Delivered-To: jdoe@example.com\r\n{}",
+            build_message("bill@example.com", "Forward me")
+        ),
+    )
+    .await;
+    assert_eq!(
+        client
+            .email_query(
+                email::query::Filter::in_mailbox(&inbox_id).into(),
+                None::<Vec<_>>,
+            )
+            .await
+            .unwrap()
+            .ids()
+            .len(),
+        2
+    );
+
+    // Active scripts cannot be destroyed
+    let response = jmap_request(
+        &server,
+        Some(("jdoe@example.com", "12345")),
+        USING,
+        "SieveScript/set",
+        json!({
+            "accountId": account_id,
+            "destroy": [script_id],
+        }),
+    )
+    .await;
+    assert_eq!(
+        response["notDestroyed"][&script_id]["type"],
+        json!("sieveIsActive")
+    );
+
+    // Deactivate and destroy the script
+    let response = jmap_request(
+        &server,
+        Some(("jdoe@example.com", "12345")),
+        USING,
+        "SieveScript/set",
+        json!({
+            "accountId": account_id,
+            "onSuccessDeactivateScript": true,
+        }),
+    )
+    .await;
+    assert_eq!(response["updated"][&script_id], json!({"isActive": false}));
+    let response = jmap_request(
+        &server,
+        Some(("jdoe@example.com", "12345")),
+        USING,
+        "SieveScript/set",
+        json!({
+            "accountId": account_id,
+            "destroy": [script_id],
+        }),
+    )
+    .await;
+    assert_eq!(response["destroyed"], json!([script_id]));
+
+    // Remove test data
+    client
+        .set_default_account_id(JMAPId::new(SUPERUSER_ID as u64))
+        .principal_destroy(&account_id)
+        .await
+        .unwrap();
+    client.principal_destroy(&domain_id).await.unwrap();
+    server.store.principal_purge().unwrap();
+    server.store.assert_is_empty();
+}
+
+const SCRIPT: &str = r#"require ["fileinto", "imap4flags", "reject", "envelope"];
+
+if header :contains "subject" "TPS" {
+    fileinto :flags ["\\Seen", "$important"] "Reports";
+    stop;
+}
+
+if envelope :is "from" "spam@example.com" {
+    discard;
+    stop;
+}
+
+if envelope :is "to" "john.doe@example.com" {
+    fileinto "Reports";
+    stop;
+}
+
+if header :contains "subject" "Forward me" {
+    redirect "jane@example.org";
+    stop;
+}
+
+if header :contains "subject" "Reject me" {
+    redirect "jane@example.org";
+    reject text:
+Not
+interested
+250 Injected
+.
+;
+}
+"#;
+
+fn build_message(from: &str, subject: &str) -> String {
+    format!(
+        concat!(
+            "From: {}\r\n",
+            "To: jdoe@example.com\r\n",
+            "Subject: {}\r\n",
+            "\r\n",
+            "I'm going to need those TPS reports ASAP."
+        ),
+        from, subject
+    )
+}
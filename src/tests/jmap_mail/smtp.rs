@@ -0,0 +1,279 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::Duration;
+
+use actix_web::web;
+use jmap::{base64, types::jmap::JMAPId, SUPERUSER_ID};
+use jmap_client::client::Client;
+use serde_json::json;
+use store::Store;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines, ReadHalf, WriteHalf},
+    net::TcpStream,
+};
+
+use crate::{
+    tests::{
+        jmap::jmap_request,
+        jmap_mail::email_submission::{
+            assert_message_delivery, expect_nothing, spawn_mock_smtp_server, MockMessage,
+        },
+        store::utils::StoreCompareWith,
+    },
+    JMAPServer,
+};
+
+const USING: &[&str] = &[
+    "urn:ietf:params:jmap:core",
+    "urn:ietf:params:jmap:mail",
+    "urn:ietf:params:jmap:submission",
+];
+
+pub async fn test<T>(server: web::Data<JMAPServer<T>>, client: &mut Client)
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    println!("Running SMTP submission tests...");
+
+    // Create a test account with an alias
+    let domain_id = client
+        .set_default_account_id(JMAPId::new(SUPERUSER_ID as u64))
+        .domain_create("example.com")
+        .await
+        .unwrap()
+        .take_id();
+    let account_id = client
+        .individual_create("jdoe@example.com", "12345", "John Doe")
+        .await
+        .unwrap()
+        .take_id();
+    client
+        .principal_set_aliases(&account_id, ["john.doe@example.com"].into())
+        .await
+        .unwrap();
+    client.set_default_account_id(&account_id);
+
+    // Start mock SMTP server
+    let (mut smtp_rx, smtp_settings) = spawn_mock_smtp_server();
+
+    // Authentication is required before submitting messages
+    let mut smtp = SubmissionConnection::connect().await;
+    assert!(smtp.greeting.starts_with("220 "));
+    let capabilities = smtp.send_ok("EHLO client.example.com", 2).await;
+    assert!(capabilities
+        .iter()
+        .any(|c| c == "250-AUTH PLAIN LOGIN XOAUTH2 OAUTHBEARER"));
+    smtp.send_ok("MAIL FROM:<jdoe@example.com>", 5).await;
+    smtp.send_ok(
+        &format!("AUTH PLAIN {}", base64::encode("\0jdoe@example.com\0wrong")),
+        5,
+    )
+    .await;
+
+    // AUTH LOGIN with challenges
+    smtp.send_ok("AUTH LOGIN", 3).await;
+    smtp.send_ok(&base64::encode("jdoe@example.com"), 3).await;
+    assert_eq!(
+        smtp.send_ok(&base64::encode("12345"), 2).await,
+        vec!["235 2.7.0 Authentication succeeded."]
+    );
+    smtp.send_ok(
+        &format!("AUTH PLAIN {}", base64::encode("\0jdoe@example.com\012345")),
+        5,
+    )
+    .await;
+
+    // Declared sizes over the limit are rejected
+    assert!(smtp
+        .send_ok("MAIL FROM:<jdoe@example.com> SIZE=1000000000", 5)
+        .await[0]
+        .starts_with("552 5.3.4 "));
+
+    // Senders have to match the account's address or one of its aliases
+    smtp.send_ok("MAIL FROM:<bill@example.com>", 5).await;
+    smtp.send_ok("MAIL FROM:<john.doe@example.com>", 2).await;
+    smtp.send_ok("RCPT TO:<bill@foobar.com>", 2).await;
+    smtp.send_ok("DATA", 3).await;
+    assert_eq!(
+        smtp.send_ok(
+            &format!("{}.", build_message("john.doe@example.com", "first")),
+            2
+        )
+        .await,
+        vec!["250 2.0.0 Message queued for delivery."]
+    );
+    assert_message_delivery(
+        &mut smtp_rx,
+        MockMessage::new(
+            "<john.doe@example.com>",
+            ["<bill@foobar.com>"],
+            "@Subject: SMTP submission first",
+        ),
+        false,
+    )
+    .await;
+
+    // Submit a second message using BDAT
+    let message = build_message("jdoe@example.com", "second");
+    smtp.send_ok("MAIL FROM:<jdoe@example.com>", 2).await;
+    smtp.send_ok("RCPT TO:<bill@foobar.com>", 2).await;
+    smtp.send_ok("RCPT TO:<jane@foobar.com>", 2).await;
+    smtp_settings.lock().do_stop = true;
+    smtp.send_ok(
+        &format!("BDAT {} LAST\r\n{}", message.len(), message.trim_end()),
+        2,
+    )
+    .await;
+    assert_message_delivery(
+        &mut smtp_rx,
+        MockMessage::new(
+            "<jdoe@example.com>",
+            ["<bill@foobar.com>", "<jane@foobar.com>"],
+            "@Subject: SMTP submission second",
+        ),
+        false,
+    )
+    .await;
+    smtp.send_ok("QUIT", 2).await;
+    expect_nothing(&mut smtp_rx).await;
+
+    // Copies are stored in the Sent mailbox using an identity for each sender
+    let sent_id = jmap_request(
+        &server,
+        Some(("jdoe@example.com", "12345")),
+        USING,
+        "Mailbox/query",
+        json!({
+            "accountId": account_id,
+            "filter": { "role": "sent" },
+        }),
+    )
+    .await["ids"][0]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let response = jmap_request(
+        &server,
+        Some(("jdoe@example.com", "12345")),
+        USING,
+        "Email/query",
+        json!({
+            "accountId": account_id,
+            "filter": { "inMailbox": sent_id, "hasKeyword": "$seen" },
+        }),
+    )
+    .await;
+    assert_eq!(response["ids"].as_array().unwrap().len(), 2);
+    let response = jmap_request(
+        &server,
+        Some(("jdoe@example.com", "12345")),
+        USING,
+        "Identity/get",
+        json!({
+            "accountId": account_id,
+            "properties": ["email"],
+        }),
+    )
+    .await;
+    let mut identities = response["list"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|identity| identity["email"].as_str().unwrap().to_string())
+        .collect::<Vec<_>>();
+    identities.sort_unstable();
+    assert_eq!(identities, ["jdoe@example.com", "john.doe@example.com"]);
+
+    // Remove test data
+    client
+        .set_default_account_id(JMAPId::new(SUPERUSER_ID as u64))
+        .principal_destroy(&account_id)
+        .await
+        .unwrap();
+    client.principal_destroy(&domain_id).await.unwrap();
+    server.store.principal_purge().unwrap();
+    server.store.assert_is_empty();
+}
+
+fn build_message(from: &str, subject: &str) -> String {
+    format!(
+        concat!(
+            "From: {}\r\n",
+            "To: bill@foobar.com\r\n",
+            "Subject: SMTP submission {}\r\n",
+            "\r\n",
+            "Message body.\r\n"
+        ),
+        from, subject
+    )
+}
+
+pub struct SubmissionConnection {
+    greeting: String,
+    reader: Lines<BufReader<ReadHalf<TcpStream>>>,
+    writer: WriteHalf<TcpStream>,
+}
+
+impl SubmissionConnection {
+    pub async fn connect() -> Self {
+        let (reader, writer) =
+            tokio::io::split(TcpStream::connect("127.0.0.1:11581").await.unwrap());
+        let mut reader = BufReader::new(reader).lines();
+        SubmissionConnection {
+            greeting: reader.next_line().await.unwrap().unwrap(),
+            reader,
+            writer,
+        }
+    }
+
+    /// Sends a command and reads the full reply, asserting that its first
+    /// digit matches the expected reply class.
+    pub async fn send_ok(&mut self, command: &str, class: u8) -> Vec<String> {
+        self.writer
+            .write_all(format!("{}\r\n", command).as_bytes())
+            .await
+            .unwrap();
+        let mut lines = Vec::new();
+        loop {
+            let line = tokio::time::timeout(Duration::from_millis(1500), self.reader.next_line())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            assert_eq!(
+                line.as_bytes()[0],
+                b'0' + class,
+                "{}: {}",
+                command.split_once('\r').map_or(command, |(c, _)| c),
+                line
+            );
+            let is_last = line.as_bytes().get(3) != Some(&b'-');
+            lines.push(line);
+            if is_last {
+                break;
+            }
+        }
+        lines
+    }
+}
@@ -0,0 +1,211 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use actix_web::web;
+use jmap::{types::jmap::JMAPId, SUPERUSER_ID};
+use jmap_client::{
+    client::Client,
+    email,
+    mailbox::{self, Role},
+};
+use jmap_mail::INBOX_ID;
+use store::Store;
+
+use crate::{
+    tests::{jmap_mail::lmtp::SmtpConnection, store::utils::StoreCompareWith},
+    JMAPServer,
+};
+
+pub async fn test<T>(server: web::Data<JMAPServer<T>>, client: &mut Client)
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    println!("Running Spam filter tests...");
+
+    // Create a test account
+    let domain_id = client
+        .set_default_account_id(JMAPId::new(SUPERUSER_ID as u64))
+        .domain_create("example.com")
+        .await
+        .unwrap()
+        .take_id();
+    let account_id = client
+        .individual_create("jdoe@example.com", "12345", "John Doe")
+        .await
+        .unwrap()
+        .take_id();
+    client.set_default_account_id(&account_id);
+    let inbox_id = JMAPId::new(INBOX_ID as u64).to_string();
+    let junk_id = client
+        .mailbox_query(
+            mailbox::query::Filter::role(Role::Junk).into(),
+            None::<Vec<_>>,
+        )
+        .await
+        .unwrap()
+        .take_ids()
+        .pop()
+        .unwrap();
+
+    // Messages flagged as spam by the upstream MTA should be filed into Junk
+    let mut lmtp = SmtpConnection::connect().await;
+    lmtp.ingest(
+        "bill@example.com",
+        &["jdoe@example.com"],
+        &build_message("Flagged message", "X-Spam-Flag: YES\r\n", SPAM_BODY),
+    )
+    .await;
+    let email_ids = client
+        .email_query(
+            email::query::Filter::in_mailbox(&junk_id).into(),
+            None::<Vec<_>>,
+        )
+        .await
+        .unwrap()
+        .take_ids();
+    assert_eq!(email_ids.len(), 1);
+    assert_eq!(
+        client
+            .email_get(&email_ids[0], None::<Vec<_>>)
+            .await
+            .unwrap()
+            .unwrap()
+            .keywords(),
+        &["$junk"]
+    );
+
+    // Untrained messages should be delivered to the Inbox
+    for _ in 0..10 {
+        lmtp.ingest(
+            "bill@example.com",
+            &["jdoe@example.com"],
+            &build_message("Special offer", "", SPAM_BODY),
+        )
+        .await;
+        lmtp.ingest(
+            "jane@example.com",
+            &["jdoe@example.com"],
+            &build_message("Budget review", "", HAM_BODY),
+        )
+        .await;
+    }
+    assert_eq!(count_emails(client, &inbox_id).await, 20);
+
+    // Train the classifier by marking messages as junk and not junk
+    for (subject, keyword) in [("offer", "$junk"), ("budget", "$notjunk")] {
+        let email_ids = client
+            .email_query(
+                email::query::Filter::subject(subject).into(),
+                None::<Vec<_>>,
+            )
+            .await
+            .unwrap()
+            .take_ids();
+        assert_eq!(email_ids.len(), 10);
+        for email_id in email_ids {
+            client
+                .email_set_keyword(&email_id, keyword, true)
+                .await
+                .unwrap();
+        }
+    }
+
+    // Flipping the keywords unlearns the previous class rather than
+    // counting the same messages twice
+    let email_ids = client
+        .email_query(
+            email::query::Filter::subject("offer").into(),
+            None::<Vec<_>>,
+        )
+        .await
+        .unwrap()
+        .take_ids();
+    for (keyword, set) in [
+        ("$junk", false),
+        ("$notjunk", true),
+        ("$notjunk", false),
+        ("$junk", true),
+    ] {
+        for email_id in &email_ids {
+            client
+                .email_set_keyword(email_id, keyword, set)
+                .await
+                .unwrap();
+        }
+    }
+
+    // Spam should now be filed into Junk while ham stays in the Inbox
+    lmtp.ingest(
+        "bill@example.com",
+        &["jdoe@example.com"],
+        &build_message("Another special offer", "", SPAM_BODY),
+    )
+    .await;
+    lmtp.ingest(
+        "jane@example.com",
+        &["jdoe@example.com"],
+        &build_message("Budget review follow-up", "", HAM_BODY),
+    )
+    .await;
+    assert_eq!(count_emails(client, &junk_id).await, 2);
+    assert_eq!(count_emails(client, &inbox_id).await, 21);
+
+    // Remove test data
+    client
+        .set_default_account_id(JMAPId::new(SUPERUSER_ID as u64))
+        .principal_destroy(&account_id)
+        .await
+        .unwrap();
+    client.principal_destroy(&domain_id).await.unwrap();
+    server.store.principal_purge().unwrap();
+    server.store.assert_is_empty();
+}
+
+const SPAM_BODY: &str = "Buy cheap pills with a huge discount, limited offer!";
+const HAM_BODY: &str = "Please review the quarterly budget before our meeting.";
+
+async fn count_emails(client: &mut Client, mailbox_id: &str) -> usize {
+    client
+        .email_query(
+            email::query::Filter::in_mailbox(mailbox_id).into(),
+            None::<Vec<_>>,
+        )
+        .await
+        .unwrap()
+        .ids()
+        .len()
+}
+
+fn build_message(subject: &str, headers: &str, body: &str) -> String {
+    format!(
+        concat!(
+            "From: bill@example.com\r\n",
+            "To: jdoe@example.com\r\n",
+            "Subject: {}\r\n",
+            "{}",
+            "\r\n",
+            "{}"
+        ),
+        subject, headers, body
+    )
+}
@@ -23,5 +23,7 @@
 
 pub mod cluster;
 pub mod jmap;
+pub mod jmap_calendars;
+pub mod jmap_contacts;
 pub mod jmap_mail;
 pub mod store;
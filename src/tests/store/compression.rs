@@ -0,0 +1,130 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::sync::Arc;
+
+use store::{
+    blob::{
+        compress::{Codec, FrameHeader, COMPRESSED_MAGIC},
+        BlobId, BlobStore,
+    },
+    serialize::key::BlobKey,
+    ColumnFamily, JMAPStore, Store,
+};
+
+pub fn test<T>(db: Arc<JMAPStore<T>>)
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    assert_eq!(db.blob_store.compression.codec, Codec::Lz4);
+
+    let blob = (0..50_000)
+        .map(|n| format!("Message line number {}.\r\n", n % 500))
+        .collect::<String>()
+        .into_bytes();
+
+    for blob_id in [BlobId::new_local(&blob), BlobId::new_external(&blob)] {
+        // Blobs are compressed on write
+        db.blob_store(&blob_id, blob.clone()).unwrap();
+        db.blob_store(&blob_id, blob.clone()).unwrap();
+        let compressed = if blob_id.is_local() {
+            db.db
+                .get::<Vec<u8>>(ColumnFamily::Blobs, &BlobKey::serialize(&blob_id))
+                .unwrap()
+        } else {
+            db.blob_store.get(&blob_id).unwrap()
+        }
+        .unwrap();
+        let header = FrameHeader::parse(&compressed).unwrap();
+        assert_eq!(header.blob_size as usize, blob.len());
+        assert!(compressed.len() < blob.len() / 2);
+
+        // And decompressed on read
+        assert_eq!(db.blob_get(&blob_id).unwrap(), Some(blob.clone()));
+        assert_eq!(db.blob_size(&blob_id).unwrap(), Some(blob.len() as u32));
+        for range in [
+            0..10,
+            65_530..65_540,
+            500_000..500_100,
+            blob.len() as u32 - 10..blob.len() as u32,
+        ] {
+            assert_eq!(
+                db.blob_get_range(&blob_id, range.clone()).unwrap(),
+                Some(blob[range.start as usize..range.end as usize].to_vec()),
+                "{:?}",
+                range
+            );
+        }
+        assert_eq!(
+            db.blob_get_range(&blob_id, blob.len() as u32 - 10..u32::MAX)
+                .unwrap(),
+            Some(blob[blob.len() - 10..].to_vec())
+        );
+    }
+
+    // Small blobs, or blobs starting with the frame magic, are framed as well
+    let blob = [&COMPRESSED_MAGIC[..], b"\x01 looks like a frame header"].concat();
+    for blob_id in [BlobId::new_local(&blob), BlobId::new_external(&blob)] {
+        db.blob_store(&blob_id, blob.clone()).unwrap();
+        assert_eq!(db.blob_get(&blob_id).unwrap(), Some(blob.clone()));
+        assert_eq!(db.blob_size(&blob_id).unwrap(), Some(blob.len() as u32));
+        assert_eq!(
+            db.blob_get_range(&blob_id, 0..5).unwrap(),
+            Some(blob[0..5].to_vec())
+        );
+    }
+
+    // Files are compressed one chunk at a time
+    let blob = (0..100_000)
+        .map(|n| format!("Uploaded line number {}.\r\n", n % 700))
+        .collect::<String>()
+        .into_bytes();
+    let blob_id = BlobId::new_external(&blob);
+    let path = std::env::temp_dir().join(format!("stalwart_upload_{}", std::process::id()));
+    std::fs::write(&path, &blob).unwrap();
+    db.blob_store_file(&blob_id, &path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(!path.with_extension("chunks").exists());
+    assert_eq!(
+        db.blob_store.get(&blob_id).unwrap(),
+        Some(db.blob_store.compression.compress(blob.clone()).unwrap())
+    );
+    assert_eq!(db.blob_get(&blob_id).unwrap(), Some(blob.clone()));
+    assert_eq!(
+        db.blob_get_range(&blob_id, 1_000_000..1_000_050).unwrap(),
+        Some(blob[1_000_000..1_000_050].to_vec())
+    );
+
+    // Blobs that were stored uncompressed are still readable
+    let blob = b"Uncompressed blob".repeat(1000);
+    let blob_id = BlobId::new_external(&blob);
+    assert!(db.blob_store.put(&blob_id, &blob).unwrap());
+    assert_eq!(db.blob_get(&blob_id).unwrap(), Some(blob.clone()));
+    assert_eq!(db.blob_size(&blob_id).unwrap(), Some(blob.len() as u32));
+    assert_eq!(
+        db.blob_get_range(&blob_id, 2..12).unwrap(),
+        Some(b"compressed".to_vec())
+    );
+    assert!(db.blob_store.delete(&blob_id).unwrap());
+    assert_eq!(db.blob_size(&blob_id).unwrap(), None);
+}
@@ -0,0 +1,283 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::sync::Arc;
+
+use store::{
+    blob::{
+        compress::{decompress, FrameHeader},
+        BlobId, BlobStore,
+    },
+    config::env_settings::EnvSettings,
+    core::{collection::Collection, document::Document},
+    crypto::{rotate_master_key, Encryption, SymmetricEncrypt},
+    serialize::key::{BlobKey, ValueKey},
+    write::{
+        batch::WriteBatch,
+        options::{IndexOptions, Options},
+    },
+    ColumnFamily, JMAPStore, Store,
+};
+
+pub const MASTER_KEY: &str = "der_wille_als_ding_an_sich_selbst";
+const NEW_MASTER_KEY: &str = "die_welt_als_wille_und_vorstellung";
+
+pub fn add_encryption_settings(settings: &mut EnvSettings) {
+    settings.set_value("encryption-master-key".to_string(), MASTER_KEY.to_string());
+}
+
+pub fn test<T>(db: Arc<JMAPStore<T>>, settings: &EnvSettings)
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    assert!(db.encryption.is_some());
+
+    // Values flagged for encryption are encrypted with the account's data key
+    // and stored under their own key
+    let value = b"Kritik der reinen Vernunft".to_vec();
+    for (account_id, options) in [
+        (1, IndexOptions::new().store().encrypt()),
+        (2, IndexOptions::new().store().encrypt()),
+        (3, IndexOptions::new().store()),
+    ] {
+        let mut document = Document::new(Collection::Mail, 0);
+        document.binary(0, value.clone(), options);
+        db.write(WriteBatch::insert(account_id, document)).unwrap();
+    }
+    let get_value = |key: Vec<u8>| db.db.get::<Vec<u8>>(ColumnFamily::Values, &key).unwrap();
+    let encrypted_1 = get_value(ValueKey::serialize_encrypted_value(
+        1,
+        Collection::Mail,
+        0,
+        0,
+    ))
+    .unwrap();
+    let encrypted_2 = get_value(ValueKey::serialize_encrypted_value(
+        2,
+        Collection::Mail,
+        0,
+        0,
+    ))
+    .unwrap();
+    assert_ne!(encrypted_1, value);
+    assert_ne!(
+        encrypted_1[SymmetricEncrypt::NONCE_LEN..],
+        encrypted_2[SymmetricEncrypt::NONCE_LEN..]
+    );
+    for account_id in [1, 2] {
+        assert_eq!(
+            get_value(ValueKey::serialize_value(
+                account_id,
+                Collection::Mail,
+                0,
+                0
+            )),
+            None
+        );
+    }
+    assert_eq!(
+        get_value(ValueKey::serialize_value(3, Collection::Mail, 0, 0)),
+        Some(value.clone())
+    );
+    assert_eq!(
+        get_value(ValueKey::serialize_encrypted_value(
+            3,
+            Collection::Mail,
+            0,
+            0
+        )),
+        None
+    );
+    for account_id in [1, 2, 3] {
+        assert_eq!(
+            db.get_document_value::<Vec<u8>>(account_id, Collection::Mail, 0, 0)
+                .unwrap(),
+            Some(value.clone())
+        );
+        assert_eq!(
+            db.get_multi_document_value::<Vec<u8>>(account_id, Collection::Mail, 0..2, 0)
+                .unwrap(),
+            vec![Some(value.clone()), None]
+        );
+    }
+
+    // Values can't be decrypted with another account's data key
+    db.db
+        .set(
+            ColumnFamily::Values,
+            &ValueKey::serialize_encrypted_value(2, Collection::Mail, 0, 0),
+            &encrypted_1,
+        )
+        .unwrap();
+    assert!(db
+        .get_document_value::<Vec<u8>>(2, Collection::Mail, 0, 0)
+        .is_err());
+    db.db
+        .set(
+            ColumnFamily::Values,
+            &ValueKey::serialize_encrypted_value(2, Collection::Mail, 0, 0),
+            &encrypted_2,
+        )
+        .unwrap();
+
+    // Plaintext values that look encrypted are not mistaken for ciphertext
+    let mut document = Document::new(Collection::Mail, 1);
+    document.binary(0, encrypted_1.clone(), IndexOptions::new().store());
+    db.write(WriteBatch::insert(3, document)).unwrap();
+    assert_eq!(
+        db.get_document_value::<Vec<u8>>(3, Collection::Mail, 1, 0)
+            .unwrap(),
+        Some(encrypted_1.clone())
+    );
+
+    // Data keys are derived from the master key, other nodes decrypt the same values
+    let other_node = Encryption::new(settings).unwrap().unwrap();
+    assert_eq!(
+        Encryption::decrypt_value(&other_node.value_cipher(1), &encrypted_1).unwrap(),
+        value
+    );
+    assert_eq!(
+        other_node.blob_key,
+        db.encryption.as_ref().unwrap().blob_key
+    );
+
+    // Blobs are encrypted but still deduplicated by their hash
+    let blob = b"Die Welt als Wille und Vorstellung".repeat(100);
+    let mut stored_blobs = Vec::new();
+    for blob_id in [BlobId::new_local(&blob), BlobId::new_external(&blob)] {
+        db.blob_store(&blob_id, blob.clone()).unwrap();
+        let encrypted = get_blob(&db, &blob_id);
+        assert!(FrameHeader::parse(&encrypted).unwrap().encrypted);
+        assert!(!encrypted
+            .windows(b"Vorstellung".len())
+            .any(|w| w == b"Vorstellung"));
+
+        db.blob_store(&blob_id, blob.clone()).unwrap();
+        assert_eq!(db.blob_get(&blob_id).unwrap(), Some(blob.clone()));
+        assert_eq!(
+            db.blob_get_range(&blob_id, 4..8).unwrap(),
+            Some(b"Welt".to_vec())
+        );
+        stored_blobs.push((blob_id, encrypted));
+    }
+
+    // Blobs stored before enabling encryption are still readable, including
+    // the ones that start like an encrypted blob
+    for blob in [
+        b"Parerga und Paralipomena".to_vec(),
+        [&stored_blobs[1].1[..], b"Parerga"].concat(),
+    ] {
+        let blob_id = BlobId::new_external(&blob);
+        assert!(db.blob_store.put(&blob_id, &blob).unwrap());
+        assert_eq!(db.blob_get(&blob_id).unwrap(), Some(blob));
+        assert!(db.blob_store.delete(&blob_id).unwrap());
+    }
+
+    // Rotate the master key, values and blobs are re-encrypted once
+    let rotate_settings = |master_key: &str, new_master_key: &str| {
+        let mut settings = EnvSettings {
+            args: settings.args.clone(),
+        };
+        settings.set_value("encryption-master-key".to_string(), master_key.to_string());
+        settings.set_value(
+            "encryption-new-master-key".to_string(),
+            new_master_key.to_string(),
+        );
+        settings
+    };
+    let new_settings = rotate_settings(MASTER_KEY, NEW_MASTER_KEY);
+    assert_eq!(rotate_master_key(&db.db, &new_settings).unwrap(), 4);
+    assert_eq!(rotate_master_key(&db.db, &new_settings).unwrap(), 0);
+    let new_key = Encryption::with_master_key(NEW_MASTER_KEY.as_bytes(), 10);
+    for (account_id, encrypted) in [(1, &encrypted_1), (2, &encrypted_2)] {
+        let rotated = get_value(ValueKey::serialize_encrypted_value(
+            account_id,
+            Collection::Mail,
+            0,
+            0,
+        ))
+        .unwrap();
+        assert_ne!(&rotated, encrypted);
+        assert_eq!(
+            Encryption::decrypt_value(&new_key.value_cipher(account_id), &rotated).unwrap(),
+            value
+        );
+    }
+    for (blob_id, encrypted) in &stored_blobs {
+        let rotated = get_blob(&db, blob_id);
+        assert_ne!(&rotated, encrypted);
+        assert_eq!(
+            decompress(new_key.decrypt_blob(blob_id, &rotated).unwrap()).unwrap(),
+            blob
+        );
+    }
+
+    // Rotating with the wrong master key fails
+    assert!(rotate_master_key(
+        &db.db,
+        &rotate_settings("ein_ganz_falscher_schluessel_fuer_alles", MASTER_KEY)
+    )
+    .is_err());
+
+    // Rotate back to the original master key
+    assert_eq!(
+        rotate_master_key(&db.db, &rotate_settings(NEW_MASTER_KEY, MASTER_KEY)).unwrap(),
+        4
+    );
+    for (blob_id, encrypted) in &stored_blobs {
+        assert_eq!(&get_blob(&db, blob_id), encrypted);
+        assert_eq!(db.blob_get(blob_id).unwrap(), Some(blob.clone()));
+    }
+    for account_id in [1, 2] {
+        assert_eq!(
+            db.get_document_value::<Vec<u8>>(account_id, Collection::Mail, 0, 0)
+                .unwrap(),
+            Some(value.clone())
+        );
+    }
+
+    // Remove the unlinked blobs
+    for (blob_id, _) in &stored_blobs {
+        db.db
+            .delete(ColumnFamily::Blobs, &BlobKey::serialize_prefix(blob_id, 0))
+            .unwrap();
+    }
+    db.purge_blobs().unwrap();
+    for (blob_id, _) in &stored_blobs {
+        assert_eq!(db.blob_get(blob_id).unwrap(), None);
+    }
+}
+
+fn get_blob<T>(db: &JMAPStore<T>, blob_id: &BlobId) -> Vec<u8>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    if blob_id.is_local() {
+        db.db
+            .get::<Vec<u8>>(ColumnFamily::Blobs, &BlobKey::serialize(blob_id))
+            .unwrap()
+    } else {
+        db.blob_store.get(blob_id).unwrap()
+    }
+    .unwrap()
+}
@@ -22,8 +22,11 @@
 */
 
 pub mod blobs;
+pub mod compression;
+pub mod encryption;
 pub mod log;
 pub mod query;
+pub mod s3;
 pub mod utils;
 
 use std::{path::PathBuf, sync::Arc};
@@ -68,8 +71,42 @@ fn store_tests() {
     let db = Arc::new(db);
 
     blobs::test(db.clone());
+    compression::test(db.clone());
     log::test(db.clone());
     query::test(db, true);
 
     destroy_temp_dir(&temp_dir);
 }
+
+#[test]
+#[ignore]
+fn s3_blob_tests() {
+    let (mut settings, temp_dir) = init_settings("strdb_s3", 1, 1, true);
+    s3::add_s3_settings(&mut settings);
+    let db = Arc::new(JMAPStore::<RocksDB>::new(
+        RocksDB::open(&settings).unwrap(),
+        JMAPConfig::from(&settings),
+        &settings,
+    ));
+
+    s3::test(db);
+
+    destroy_temp_dir(&temp_dir);
+}
+
+#[test]
+#[ignore]
+fn encryption_tests() {
+    let (mut settings, temp_dir) = init_settings("strdb_encryption", 1, 1, true);
+    encryption::add_encryption_settings(&mut settings);
+    let db = Arc::new(JMAPStore::<RocksDB>::new(
+        RocksDB::open(&settings).unwrap(),
+        JMAPConfig::from(&settings),
+        &settings,
+    ));
+
+    encryption::test(db.clone(), &settings);
+    blobs::test(db);
+
+    destroy_temp_dir(&temp_dir);
+}
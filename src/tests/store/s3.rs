@@ -0,0 +1,311 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+};
+
+use store::{
+    ahash::AHashMap,
+    blob::{BlobBackend, BlobId, BlobStore},
+    config::env_settings::EnvSettings,
+    JMAPStore, Store,
+};
+
+const S3_PORT: u16 = 9010;
+
+#[derive(Default)]
+struct MockS3 {
+    objects: AHashMap<String, Vec<u8>>,
+    uploads: AHashMap<String, AHashMap<u32, Vec<u8>>>,
+    next_upload_id: u32,
+    multipart_uploads: u32,
+}
+
+pub fn add_s3_settings(settings: &mut EnvSettings) {
+    for (key, value) in [
+        ("blob-store", "s3".to_string()),
+        ("blob-s3-endpoint", format!("http://127.0.0.1:{}", S3_PORT)),
+        ("blob-s3-bucket", "stalwart".to_string()),
+        ("blob-s3-access-key", "minioadmin".to_string()),
+        ("blob-s3-secret-key", "minioadmin".to_string()),
+        ("blob-s3-prefix", "blobs/".to_string()),
+    ] {
+        settings.set_value(key.to_string(), value);
+    }
+}
+
+pub fn test<T>(db: Arc<JMAPStore<T>>)
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    let s3 = spawn_mock_s3_server();
+    assert!(matches!(db.blob_store.backend, BlobBackend::S3(_)));
+
+    // Small blobs are uploaded in a single request
+    let blob = b"Hello world!".to_vec();
+    let blob_id = BlobId::new_external(&blob);
+    assert!(db.blob_store.put(&blob_id, &blob).unwrap());
+    assert_eq!(db.blob_store.get(&blob_id).unwrap(), Some(blob.clone()));
+    assert_eq!(
+        db.blob_store.get_range(&blob_id, 6..11).unwrap(),
+        Some(b"world".to_vec())
+    );
+    assert_eq!(
+        db.blob_store.get_range(&blob_id, 4..5).unwrap(),
+        Some(b"o".to_vec())
+    );
+    assert_eq!(
+        db.blob_store.get_range(&blob_id, 6..u32::MAX).unwrap(),
+        Some(b"world!".to_vec())
+    );
+    assert!(s3
+        .lock()
+        .unwrap()
+        .objects
+        .contains_key(&format!("blobs/{}", blob_id)));
+    assert_eq!(
+        db.blob_store.size(&blob_id).unwrap(),
+        Some(blob.len() as u64)
+    );
+
+    // Large blobs are uploaded in parts
+    let large_blob = (0..(12 * 1024 * 1024))
+        .map(|n| (n % 251) as u8)
+        .collect::<Vec<_>>();
+    let large_blob_id = BlobId::new_external(&large_blob);
+    assert!(db.blob_store.put(&large_blob_id, &large_blob).unwrap());
+    assert_eq!(s3.lock().unwrap().multipart_uploads, 1);
+    assert!(s3.lock().unwrap().uploads.is_empty());
+    assert_eq!(
+        db.blob_store.get(&large_blob_id).unwrap(),
+        Some(large_blob.clone())
+    );
+    assert_eq!(
+        db.blob_store
+            .get_range(&large_blob_id, 9_000_000..9_000_100)
+            .unwrap(),
+        Some(large_blob[9_000_000..9_000_100].to_vec())
+    );
+
+    // Delete blobs
+    assert!(db.blob_store.delete(&blob_id).unwrap());
+    assert!(!db.blob_store.delete(&blob_id).unwrap());
+    assert_eq!(db.blob_store.get(&blob_id).unwrap(), None);
+    assert_eq!(db.blob_store.size(&blob_id).unwrap(), None);
+    assert!(db.blob_store.delete(&large_blob_id).unwrap());
+    assert!(s3.lock().unwrap().objects.is_empty());
+
+    // Purging blobs deletes them from the bucket
+    super::blobs::test(db);
+    assert!(s3.lock().unwrap().objects.is_empty());
+}
+
+fn spawn_mock_s3_server() -> Arc<Mutex<MockS3>> {
+    let s3 = Arc::new(Mutex::new(MockS3::default()));
+    let listener = TcpListener::bind(("127.0.0.1", S3_PORT)).unwrap();
+    let s3_ = s3.clone();
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let s3 = s3_.clone();
+            std::thread::spawn(move || handle_request(stream, s3));
+        }
+    });
+
+    s3
+}
+
+fn handle_request(stream: TcpStream, s3: Arc<Mutex<MockS3>>) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line).unwrap_or(0) == 0 {
+        return;
+    }
+    let mut request_line = request_line.split_ascii_whitespace();
+    let method = request_line.next().unwrap().to_string();
+    let target = request_line.next().unwrap();
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    // Read headers and body
+    let mut content_length = 0;
+    let mut range = None;
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            match name.to_ascii_lowercase().as_str() {
+                "content-length" => content_length = value.trim().parse().unwrap(),
+                "range" => range = value.trim().strip_prefix("bytes=").map(|r| r.to_string()),
+                _ => (),
+            }
+        }
+    }
+    let mut body = vec![0u8; content_length];
+    reader.read_exact(&mut body).unwrap();
+
+    // Remove the bucket name from the path
+    let key = path
+        .trim_start_matches('/')
+        .split_once('/')
+        .map(|(_, key)| key.to_string())
+        .unwrap_or_default();
+    let params = query
+        .split('&')
+        .filter_map(|param| {
+            param
+                .split_once('=')
+                .or_else(|| (param, "").into())
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+        })
+        .collect::<AHashMap<_, _>>();
+
+    let mut s3 = s3.lock().unwrap();
+    let (status, headers, body) = match (method.as_str(), params.get("uploadId")) {
+        ("PUT", Some(upload_id)) => {
+            let part_number: u32 = params.get("partNumber").unwrap().parse().unwrap();
+            s3.uploads
+                .get_mut(upload_id)
+                .unwrap()
+                .insert(part_number, body);
+            (
+                "200 OK",
+                format!("ETag: \"part-{}\"\r\n", part_number),
+                Vec::new(),
+            )
+        }
+        ("PUT", None) => {
+            s3.objects.insert(key, body);
+            ("200 OK", "ETag: \"object\"\r\n".to_string(), Vec::new())
+        }
+        ("POST", None) if params.contains_key("uploads") => {
+            s3.next_upload_id += 1;
+            let upload_id = format!("upload-{}", s3.next_upload_id);
+            s3.uploads.insert(upload_id.clone(), AHashMap::new());
+            (
+                "200 OK",
+                String::new(),
+                format!(
+                    concat!(
+                        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>",
+                        "<InitiateMultipartUploadResult>",
+                        "<Bucket>stalwart</Bucket><Key>{}</Key><UploadId>{}</UploadId>",
+                        "</InitiateMultipartUploadResult>"
+                    ),
+                    key, upload_id
+                )
+                .into_bytes(),
+            )
+        }
+        ("POST", Some(upload_id)) => {
+            let mut parts = s3
+                .uploads
+                .remove(upload_id)
+                .unwrap()
+                .into_iter()
+                .collect::<Vec<_>>();
+            parts.sort_unstable_by_key(|(part_number, _)| *part_number);
+            s3.objects.insert(
+                key.clone(),
+                parts.into_iter().flat_map(|(_, part)| part).collect(),
+            );
+            s3.multipart_uploads += 1;
+            (
+                "200 OK",
+                String::new(),
+                format!(
+                    concat!(
+                        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>",
+                        "<CompleteMultipartUploadResult>",
+                        "<Bucket>stalwart</Bucket><Key>{}</Key><ETag>\"object\"</ETag>",
+                        "</CompleteMultipartUploadResult>"
+                    ),
+                    key
+                )
+                .into_bytes(),
+            )
+        }
+        ("DELETE", Some(upload_id)) => {
+            s3.uploads.remove(upload_id);
+            ("204 No Content", String::new(), Vec::new())
+        }
+        ("DELETE", None) => {
+            if s3.objects.remove(&key).is_some() {
+                ("204 No Content", String::new(), Vec::new())
+            } else {
+                ("404 Not Found", String::new(), Vec::new())
+            }
+        }
+        ("GET", None) => match (s3.objects.get(&key), range) {
+            (Some(object), Some(range)) => {
+                let (from, to) = range.split_once('-').unwrap();
+                let from: usize = from.parse().unwrap();
+                let to: usize = to
+                    .parse::<usize>()
+                    .map(|to| std::cmp::min(to + 1, object.len()))
+                    .unwrap_or(object.len());
+                if from < object.len() {
+                    (
+                        "206 Partial Content",
+                        String::new(),
+                        object[from..to].to_vec(),
+                    )
+                } else {
+                    ("416 Range Not Satisfiable", String::new(), Vec::new())
+                }
+            }
+            (Some(object), None) => ("200 OK", String::new(), object.clone()),
+            (None, _) => ("404 Not Found", String::new(), Vec::new()),
+        },
+        // HEAD responses include the object length but no body
+        ("HEAD", None) => match s3.objects.get(&key) {
+            Some(object) => ("200 OK", String::new(), object.clone()),
+            None => ("404 Not Found", String::new(), Vec::new()),
+        },
+        _ => ("400 Bad Request", String::new(), Vec::new()),
+    };
+    drop(s3);
+
+    let mut stream = stream;
+    stream
+        .write_all(
+            format!(
+                "HTTP/1.1 {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n",
+                status,
+                headers,
+                body.len()
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+    if method != "HEAD" {
+        stream.write_all(&body).unwrap();
+    }
+    stream.flush().unwrap();
+}
@@ -28,10 +28,15 @@ use flate2::bufread::GzDecoder;
 use jmap::orm::TinyORM;
 use jmap::principal::schema::Principal;
 use jmap::push_subscription::schema::PushSubscription;
+use jmap_calendars::calendar::schema::Calendar;
+use jmap_calendars::calendar_event::schema::CalendarEvent;
+use jmap_contacts::address_book::schema::AddressBook;
+use jmap_contacts::contact_card::schema::ContactCard;
 use jmap_mail::email_submission::schema::EmailSubmission;
 use jmap_mail::identity::schema::Identity;
 use jmap_mail::mail::schema::Email;
 use jmap_mail::mailbox::schema::Mailbox;
+use jmap_mail::sieve_script::schema::SieveScript;
 use jmap_mail::vacation_response::schema::VacationResponse;
 use store::ahash::AHashSet;
 use store::serialize::key::ValueKey;
@@ -42,7 +47,10 @@ use store::{
     core::collection::Collection,
     roaring::RoaringBitmap,
     serialize::{
-        key::{FOLLOWER_COMMIT_INDEX_KEY, LEADER_COMMIT_INDEX_KEY},
+        key::{
+            BAYES_KEY, FOLLOWER_COMMIT_INDEX_KEY, LEADER_COMMIT_INDEX_KEY, PENDING_QUOTA_KEY,
+            QUOTA_KEY,
+        },
         StoreDeserialize,
     },
     AccountId, ColumnFamily, JMAPStore, Store,
@@ -176,12 +184,20 @@ pub fn init_settings(
                 format!("http://127.0.0.1:{}", 8000 + peer_num),
             ),
             ("lmtp-port".to_string(), (11200 + peer_num).to_string()),
+            ("imap-port".to_string(), (11430 + peer_num).to_string()),
+            ("pop3-port".to_string(), (11100 + peer_num).to_string()),
+            (
+                "submission-port".to_string(),
+                (11580 + peer_num).to_string(),
+            ),
             ("max-objects-in-set".to_string(), "100000".to_string()),
             ("query-max-results".to_string(), "100000".to_string()),
             ("jmap-port".to_string(), (8000 + peer_num).to_string()),
+            ("spam-bayes-min-learns".to_string(), "10".to_string()),
             ("smtp-relay-host".to_string(), "127.0.0.1".to_string()),
             ("smtp-relay-port".to_string(), "9999".to_string()),
             ("smtp-relay-tls".to_string(), "false".to_string()),
+            ("smtp-relay-retry-interval".to_string(), "1".to_string()),
             ("max-concurrent-uploads".to_string(), "4".to_string()),
             ("max-concurrent-requests".to_string(), "8".to_string()),
             ("push-attempt-interval".to_string(), "500".to_string()),
@@ -207,6 +223,16 @@ pub fn init_settings(
         ]
         .into_iter(),
     );
+    let mut key_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    key_path.push("src");
+    key_path.push("tests");
+    key_path.push("resources");
+    key_path.push("key.pem");
+    args.insert(
+        "oidc-signing-key-path".to_string(),
+        key_path.to_str().unwrap().to_string(),
+    );
+
     if total_peers > 1 {
         let mut pem_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
         pem_dir.push("src");
@@ -343,7 +369,42 @@ where
                             && &key[..] != FOLLOWER_COMMIT_INDEX_KEY
                             && &key[..] != LEADER_COMMIT_INDEX_KEY
                         {
-                            let (account_id, pos) = key.read_leb128().unwrap();
+                            let (account_id, pos) = key.read_leb128::<AccountId>().unwrap();
+                            if key[pos] == QUOTA_KEY {
+                                *total_keys.get_mut(&cf).unwrap() += 1;
+                                let other_value =
+                                    other.db.get::<i64>(cf, &key).unwrap().unwrap_or(0);
+                                let value = i64::deserialize(&value).unwrap();
+                                if ASSERT {
+                                    assert_eq!(value, other_value, "Quota for {}", account_id);
+                                } else if value != other_value {
+                                    println!(
+                                        "Quota mismatch for {}: {} != {}",
+                                        account_id, value, other_value
+                                    );
+                                }
+                                continue;
+                            }
+                            if key[pos] == PENDING_QUOTA_KEY {
+                                // Uploads are only tracked by the node that received them
+                                continue;
+                            }
+                            if key[pos] == BAYES_KEY {
+                                // Spam classifier weights are replicated by replaying training
+                                *total_keys.get_mut(&cf).unwrap() += 1;
+                                let other_value =
+                                    other.db.get::<i64>(cf, &key).unwrap().unwrap_or(0);
+                                let value = i64::deserialize(&value).unwrap();
+                                if ASSERT {
+                                    assert_eq!(value, other_value, "Bayes token {:?}", key);
+                                } else if value != other_value {
+                                    println!(
+                                        "Bayes token mismatch for {:?}: {} != {}",
+                                        key, value, other_value
+                                    );
+                                }
+                                continue;
+                            }
                             let collection = key[pos].into();
                             let (document_id, _) = (&key[pos + 1..]).read_leb128().unwrap();
 
@@ -431,7 +492,40 @@ where
                                                 )
                                                 .unwrap()
                                             ),
-                                            Collection::Thread | Collection::None => unreachable!(),
+                                            Collection::SieveScript => assert_eq!(
+                                                TinyORM::<SieveScript>::deserialize(&value)
+                                                    .unwrap(),
+                                                TinyORM::<SieveScript>::deserialize(&other_value)
+                                                    .unwrap()
+                                            ),
+                                            Collection::AddressBook => assert_eq!(
+                                                TinyORM::<AddressBook>::deserialize(&value)
+                                                    .unwrap(),
+                                                TinyORM::<AddressBook>::deserialize(&other_value)
+                                                    .unwrap()
+                                            ),
+                                            Collection::ContactCard => assert_eq!(
+                                                TinyORM::<ContactCard>::deserialize(&value)
+                                                    .unwrap(),
+                                                TinyORM::<ContactCard>::deserialize(&other_value)
+                                                    .unwrap()
+                                            ),
+                                            Collection::Calendar => assert_eq!(
+                                                TinyORM::<Calendar>::deserialize(&value).unwrap(),
+                                                TinyORM::<Calendar>::deserialize(&other_value)
+                                                    .unwrap()
+                                            ),
+                                            Collection::CalendarEvent => assert_eq!(
+                                                TinyORM::<CalendarEvent>::deserialize(&value)
+                                                    .unwrap(),
+                                                TinyORM::<CalendarEvent>::deserialize(&other_value)
+                                                    .unwrap()
+                                            ),
+                                            Collection::Thread
+                                            | Collection::Quota
+                                            | Collection::None => {
+                                                unreachable!()
+                                            }
                                         }
                                     } else if ASSERT {
                                         panic!(
@@ -636,7 +730,13 @@ where
                         );
                     }
                     ColumnFamily::Values if (0..=9).contains(&key[0]) => {
-                        panic!("{:?} {:?}={:?}", cf, key, value);
+                        if [QUOTA_KEY, PENDING_QUOTA_KEY].contains(&key[key.len() - 1])
+                            && key.read_leb128::<AccountId>().unwrap().1 == key.len() - 1
+                        {
+                            assert_eq!(i64::deserialize(&value).unwrap(), 0, "{:?}", key);
+                        } else {
+                            panic!("{:?} {:?}={:?}", cf, key, value);
+                        }
                     }
                     ColumnFamily::Indexes => {
                         panic!(